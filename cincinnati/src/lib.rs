@@ -17,6 +17,7 @@ extern crate serde_derive;
 
 #[macro_use]
 pub mod plugins;
+pub mod versions;
 
 use commons::prelude_errors::*;
 use daggy::petgraph::visit::{IntoNodeReferences, NodeRef};
@@ -28,6 +29,26 @@ use std::{collections, fmt};
 pub use daggy::{self, WouldCycle};
 
 pub const CONTENT_TYPE: &str = "application/json";
+
+/// Default user agent of upstream requests.
+pub const DEFAULT_USER_AGENT: &str = concat!("cincinnati/", env!("CARGO_PKG_VERSION"));
+
+/// Metadata key listing the channels of a release.
+pub static CHANNELS_KEY: &str = "io.openshift.upgrades.graph.release.channels";
+
+/// Parse the values of a comma-separated metadata list, skipping empty ones.
+pub fn parse_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Parse the channel names listed in the metadata value at `CHANNELS_KEY`.
+pub fn parse_channels(value: &str) -> impl Iterator<Item = &str> {
+    parse_list(value)
+}
+
 const EXPECT_NODE_WEIGHT: &str = "all exisitng nodes to have a weight (release)";
 
 #[cfg(not(any(test, feature = "test")))]
@@ -43,8 +64,7 @@ pub use std::collections::BTreeMap as MapImpl;
 pub use std::collections::BTreeSet as SetImpl;
 
 /// Graph type which stores `Release` as node-weights and `Empty` as edge-weights.
-#[derive(Debug, Default)]
-#[cfg_attr(any(test, feature = "test"), derive(Clone))]
+#[derive(Clone, Debug, Default)]
 pub struct Graph {
     dag: Dag<Release, Empty>,
 }
@@ -378,11 +398,231 @@ impl Graph {
         }
     }
 
+    /// Return the sorted versions of the direct children of the given release.
+    pub fn next_versions(&self, source: &ReleaseId) -> Vec<String> {
+        let mut versions: Vec<String> = self
+            .next_releases(source)
+            .map(|(_, _, release)| release.version().to_string())
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    /// Return the sorted versions of the direct parents of the given release.
+    pub fn previous_versions(&self, source: &ReleaseId) -> Vec<String> {
+        let mut versions: Vec<String> = self
+            .previous_releases(source)
+            .map(|(_, _, release)| release.version().to_string())
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    /// Returns tuples of ReleaseId and its version String for all releases,
+    /// sorted by version as defined in the `versions` module.
+    ///
+    /// Releases with an invalid version are left out.
+    pub fn releases_sorted_by_version(&self) -> Vec<(ReleaseId, String)> {
+        self.sorted_by_version(|_| true)
+    }
+
+    /// Returns the ReleaseId and version String of the newest release for
+    /// which filter_fn returns true, as defined in the `versions` module.
+    ///
+    /// Releases with an invalid version are left out.
+    pub fn max_version_in<F>(&self, filter_fn: F) -> Option<(ReleaseId, String)>
+    where
+        F: Fn(&Release) -> bool,
+    {
+        self.sorted_by_version(filter_fn).pop()
+    }
+
+    fn sorted_by_version<F>(&self, filter_fn: F) -> Vec<(ReleaseId, String)>
+    where
+        F: Fn(&Release) -> bool,
+    {
+        let mut releases: Vec<(ReleaseId, &str, semver::Version)> = self
+            .dag
+            .node_references()
+            .filter(|nr| filter_fn(nr.weight()))
+            .filter_map(|nr| {
+                let version = nr.weight().version();
+                match versions::parse_release_version(version) {
+                    Ok(parsed) => Some((ReleaseId(nr.id()), version, parsed)),
+                    Err(e) => {
+                        log::debug!("ignoring release for version ordering: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        releases.sort_by(|(_, _, a), (_, _, b)| versions::cmp_release_versions(a, b));
+
+        releases
+            .into_iter()
+            .map(|(release_id, version, _)| (release_id, version.to_string()))
+            .collect()
+    }
+
     /// Return the number of releases (nodes) in the graph.
     pub fn releases_count(&self) -> u64 {
         self.dag.node_count() as u64
     }
 
+    /// Return the number of nodes in the graph, same as `releases_count`.
+    pub fn node_count(&self) -> usize {
+        self.dag.node_count()
+    }
+
+    /// Return the number of edges in the graph.
+    pub fn edge_count(&self) -> usize {
+        self.dag.edge_count()
+    }
+
+    /// Return the subgraph of the releases whose metadata value at `key`
+    /// satisfies `predicate`.
+    ///
+    /// Only edges with both of their releases in the subgraph are kept.
+    /// Abstract releases and releases without `key` are never part of it.
+    pub fn subset_by_metadata<F>(&self, key: &str, predicate: F) -> Graph
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut subset = Graph::default();
+        let mut kept: MapImpl<daggy::NodeIndex, daggy::NodeIndex> = MapImpl::new();
+
+        for (index, release) in self.dag.node_references() {
+            let matches = match release {
+                Release::Concrete(release) => release
+                    .metadata
+                    .get(key)
+                    .map_or(false, |value| predicate(value)),
+                Release::Abstract(_) => false,
+            };
+            if matches {
+                kept.insert(index, subset.dag.add_node(release.clone()));
+            }
+        }
+
+        for edge in self.dag.raw_edges() {
+            if let (Some(source), Some(target)) =
+                (kept.get(&edge.source()), kept.get(&edge.target()))
+            {
+                subset
+                    .dag
+                    .add_edge(*source, *target, Empty {})
+                    .expect("a subgraph of an acyclic graph to be acyclic");
+            }
+        }
+
+        subset
+    }
+
+    /// Rebuild the graph with its releases and edges in a canonical order.
+    ///
+    /// Releases are ordered by version, as defined in the `versions` module,
+    /// followed by the releases with an invalid version in lexical order.
+    /// Edges are ordered by their source, then by their target release.
+    ///
+    /// Fails if several releases share the same version, as their order
+    /// would not be reproducible.
+    pub fn canonicalize(&mut self) -> Result<(), Error> {
+        let mut releases: Vec<(daggy::NodeIndex, &str, Option<semver::Version>)> = self
+            .dag
+            .node_references()
+            .map(|nr| {
+                let version = nr.weight().version();
+                (
+                    nr.id(),
+                    version,
+                    versions::parse_release_version(version).ok(),
+                )
+            })
+            .collect();
+        releases.sort_by(|(_, a, parsed_a), (_, b, parsed_b)| {
+            use std::cmp::Ordering::*;
+
+            match (parsed_a, parsed_b) {
+                (Some(parsed_a), Some(parsed_b)) => {
+                    versions::cmp_release_versions(parsed_a, parsed_b).then_with(|| a.cmp(b))
+                }
+                (Some(_), None) => Less,
+                (None, Some(_)) => Greater,
+                (None, None) => a.cmp(b),
+            }
+        });
+        if let Some(pair) = releases.windows(2).find(|pair| pair[0].1 == pair[1].1) {
+            bail!("duplicate release version '{}'", pair[0].1);
+        }
+
+        let mut canonical = Graph::default();
+        let mut positions: MapImpl<daggy::NodeIndex, daggy::NodeIndex> = MapImpl::new();
+        for (index, _, _) in &releases {
+            let release = self.dag.node_weight(*index).expect(EXPECT_NODE_WEIGHT);
+            positions.insert(*index, canonical.dag.add_node(release.clone()));
+        }
+
+        let mut edges: Vec<(daggy::NodeIndex, daggy::NodeIndex)> = self
+            .dag
+            .raw_edges()
+            .iter()
+            .map(|edge| (positions[&edge.source()], positions[&edge.target()]))
+            .collect();
+        edges.sort();
+        for (source, target) in edges {
+            canonical
+                .dag
+                .add_edge(source, target, Empty {})
+                .expect("a reordering of an acyclic graph to be acyclic");
+        }
+
+        *self = canonical;
+        Ok(())
+    }
+
+    /// Return a serializable view of the graph, with extra fields on each release.
+    ///
+    /// The fields returned by `fields` for a release, which must serialize to
+    /// a map or a struct, are emitted along with its own fields.
+    pub fn with_release_fields<F, T>(&self, fields: F) -> GraphWithReleaseFields<'_, F>
+    where
+        F: Fn(&Release) -> T,
+        T: Serialize,
+    {
+        GraphWithReleaseFields {
+            graph: self,
+            fields,
+        }
+    }
+
+    /// Render the graph in GraphViz DOT format.
+    ///
+    /// Every release is a node labeled by its version, and every edge is a
+    /// directed arrow from the source to the target release.
+    pub fn to_dot(&self) -> String {
+        fn quote(input: &str) -> String {
+            format!("\"{}\"", input.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+
+        let mut dot = String::from("digraph cincinnati {\n");
+        for (index, release) in self.dag.node_references() {
+            dot.push_str(&format!(
+                "    {} [label={}];\n",
+                index.index(),
+                quote(release.version())
+            ));
+        }
+        for edge in self.dag.raw_edges() {
+            dot.push_str(&format!(
+                "    {} -> {};\n",
+                edge.source().index(),
+                edge.target().index()
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
     /// Removes the nodes with the given ReleaseIds and returns the number of
     /// removed releases.
     ///
@@ -580,30 +820,82 @@ impl Serialize for Graph {
     where
         S: Serializer,
     {
-        struct Edges<'a>(&'a [daggy::petgraph::graph::Edge<Empty>]);
         struct Nodes<'a>(&'a [daggy::petgraph::graph::Node<Release>]);
 
-        impl<'a> Serialize for Edges<'a> {
+        impl<'a> Serialize for Nodes<'a> {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
             {
-                serializer.collect_seq(self.0.iter().map(|edge| (edge.source(), edge.target())))
+                serializer.collect_seq(self.0.iter().map(|node| &node.weight))
             }
         }
 
-        impl<'a> Serialize for Nodes<'a> {
+        let mut state = serializer.serialize_struct("Graph", 2)?;
+        state.serialize_field("nodes", &Nodes(&self.dag.raw_nodes()))?;
+        state.serialize_field("edges", &RawEdges(&self.dag.raw_edges()))?;
+        state.end()
+    }
+}
+
+/// Edges of a graph, serialized as pairs of release indices.
+struct RawEdges<'a>(&'a [daggy::petgraph::graph::Edge<Empty>]);
+
+impl<'a> Serialize for RawEdges<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|edge| (edge.source(), edge.target())))
+    }
+}
+
+/// Serializable view of a graph with extra fields on each release.
+///
+/// See `Graph::with_release_fields`.
+pub struct GraphWithReleaseFields<'a, F> {
+    graph: &'a Graph,
+    fields: F,
+}
+
+impl<'a, F, T> Serialize for GraphWithReleaseFields<'a, F>
+where
+    F: Fn(&Release) -> T,
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        #[derive(Serialize)]
+        struct Node<'a, T> {
+            #[serde(flatten)]
+            release: &'a Release,
+            #[serde(flatten)]
+            fields: T,
+        }
+
+        struct Nodes<'a, F>(&'a [daggy::petgraph::graph::Node<Release>], &'a F);
+
+        impl<'a, F, T> Serialize for Nodes<'a, F>
+        where
+            F: Fn(&Release) -> T,
+            T: Serialize,
+        {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
             {
-                serializer.collect_seq(self.0.iter().map(|node| &node.weight))
+                serializer.collect_seq(self.0.iter().map(|node| Node {
+                    release: &node.weight,
+                    fields: (self.1)(&node.weight),
+                }))
             }
         }
 
         let mut state = serializer.serialize_struct("Graph", 2)?;
-        state.serialize_field("nodes", &Nodes(&self.dag.raw_nodes()))?;
-        state.serialize_field("edges", &Edges(&self.dag.raw_edges()))?;
+        state.serialize_field("nodes", &Nodes(&self.graph.dag.raw_nodes(), &self.fields))?;
+        state.serialize_field("edges", &RawEdges(&self.graph.dag.raw_edges()))?;
         state.end()
     }
 }
@@ -769,6 +1061,7 @@ pub mod testing {
         metadata: TestMetadata,
         edges: Option<TestEdges>,
         version_template: String,
+        versions: Option<Vec<String>>,
         enable_payload_suffix: bool,
     }
 
@@ -779,6 +1072,7 @@ pub mod testing {
                 metadata: Default::default(),
                 edges: None,
                 version_template: "{{i}}.0.0".to_string(),
+                versions: None,
                 enable_payload_suffix: false,
             }
         }
@@ -809,6 +1103,12 @@ pub mod testing {
             self
         }
 
+        /// Use the given versions by index, instead of the version template.
+        pub fn with_versions(mut self, versions: &[&str]) -> Self {
+            self.versions = Some(versions.iter().map(ToString::to_string).collect());
+            self
+        }
+
         pub fn enable_payload_suffix(mut self, enable: bool) -> Self {
             self.enable_payload_suffix = enable;
             self
@@ -822,7 +1122,10 @@ pub mod testing {
                 .metadata
                 .into_iter()
                 .map(|(i, mut metadata)| {
-                    let version_unsuffixed = self.version_template.replace("{{i}}", &i.to_string());
+                    let version_unsuffixed = match &self.versions {
+                        Some(versions) => versions[i].clone(),
+                        None => self.version_template.replace("{{i}}", &i.to_string()),
+                    };
                     let version_suffix = metadata.remove("version_suffix").unwrap_or_default();
 
                     let version = format!("{}{}", version_unsuffixed, version_suffix);
@@ -878,6 +1181,64 @@ pub mod testing {
             .build()
     }
 
+    /// Generate a graph with the given releases, by version and metadata, and edges by version.
+    pub fn generate_versioned_graph(
+        image: &str,
+        releases: Vec<(&str, MapImpl<String, String>)>,
+        edges: &[(&str, &str)],
+    ) -> Graph {
+        let versions: Vec<&str> = releases.iter().map(|(version, _)| *version).collect();
+        let index = |version: &str| {
+            versions
+                .iter()
+                .position(|v| *v == version)
+                .unwrap_or_else(|| panic!("unknown version '{}'", version))
+        };
+        let edges = edges
+            .iter()
+            .map(|(from, to)| (index(from), index(to)))
+            .collect();
+        let metadata = releases
+            .into_iter()
+            .enumerate()
+            .map(|(i, (_, metadata))| (i, metadata))
+            .collect();
+
+        TestGraphBuilder::new()
+            .with_image(image)
+            .with_versions(&versions)
+            .with_metadata(metadata)
+            .with_edges(Some(edges))
+            .build()
+    }
+
+    /// Run an internal plugin on the given graph and parameters.
+    pub fn run_internal_plugin<P>(
+        plugin: &P,
+        graph: Graph,
+        parameters: plugins::Parameters,
+    ) -> Fallible<plugins::InternalIO>
+    where
+        P: plugins::InternalPlugin,
+    {
+        let mut runtime = commons::testing::init_runtime()?;
+
+        runtime.block_on(plugin.run_internal(plugins::InternalIO {
+            graph,
+            parameters,
+            warnings: Default::default(),
+        }))
+    }
+
+    /// Versions of the releases in the graph, sorted as defined in the `versions` module.
+    pub fn sorted_versions(graph: &Graph) -> Vec<String> {
+        graph
+            .releases_sorted_by_version()
+            .into_iter()
+            .map(|(_, version)| version)
+            .collect()
+    }
+
     impl std::cmp::PartialOrd for Release {
         fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
             Some(self.version().cmp(other.version()))
@@ -1055,6 +1416,15 @@ mod tests {
 
     type TestResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+    #[test]
+    fn parse_channel_lists() {
+        assert_eq!(
+            parse_channels("stable-4.6, fast-4.6,").collect::<Vec<_>>(),
+            vec!["stable-4.6", "fast-4.6"]
+        );
+        assert_eq!(parse_channels(" , ").count(), 0);
+    }
+
     #[test]
     fn serialize_graph() {
         let graph = generate_graph();
@@ -1064,6 +1434,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn serialize_graph_with_release_fields() {
+        let graph = generate_graph();
+        let view = graph
+            .with_release_fields(|release| serde_json::json!({ "major": &release.version()[..1] }));
+        assert_eq!(
+            serde_json::to_string(&view).unwrap(),
+            r#"{"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{},"major":"1"},{"version":"2.0.0","payload":"image/2.0.0","metadata":{},"major":"2"},{"version":"3.0.0","payload":"image/3.0.0","metadata":{},"major":"3"}],"edges":[[0,1],[1,2],[0,2]]}"#
+        );
+    }
+
     #[test]
     fn deserialize_graph() {
         let json = r#"{"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}},{"version":"2.0.0","payload":"image/2.0.0","metadata":{}},{"version":"3.0.0","payload":"image/3.0.0","metadata":{}}],"edges":[[0,1],[1,2],[0,2]]}"#;
@@ -1075,6 +1456,154 @@ mod tests {
         assert_eq!(ser, json);
     }
 
+    #[test]
+    fn node_and_edge_count() -> TestResult<()> {
+        let graph = generate_graph();
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 3);
+        assert_eq!(graph.node_count() as u64, graph.releases_count());
+
+        let mut graph = Graph::default();
+        assert_eq!((graph.node_count(), graph.edge_count()), (0, 0));
+
+        let v1 = graph.add_release(Release::Abstract(AbstractRelease {
+            version: String::from("1.0.0"),
+        }))?;
+        let v2 = graph.add_release(Release::Abstract(AbstractRelease {
+            version: String::from("2.0.0"),
+        }))?;
+        assert_eq!((graph.node_count(), graph.edge_count()), (2, 0));
+
+        graph.add_edge(&v1, &v2)?;
+        assert_eq!((graph.node_count(), graph.edge_count()), (2, 1));
+
+        graph.remove_releases(vec![v2]);
+        assert_eq!((graph.node_count(), graph.edge_count()), (1, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn subset_by_metadata() -> TestResult<()> {
+        let channels_key = "io.openshift.upgrades.graph.release.channels";
+        let mut graph = Graph::default();
+        let mut ids = vec![];
+        for (version, channels) in &[
+            ("1.0.0", Some("stable,fast")),
+            ("1.1.0", Some("fast")),
+            ("1.2.0", Some("stable, fast")),
+            ("1.3.0", None),
+        ] {
+            let mut metadata = MapImpl::new();
+            if let Some(channels) = channels {
+                metadata.insert(channels_key.to_string(), channels.to_string());
+            }
+            ids.push(graph.add_release(Release::Concrete(ConcreteRelease {
+                version: version.to_string(),
+                payload: format!("image/{}", version),
+                metadata,
+            }))?);
+        }
+        let abstract_id = graph.add_release(Release::Abstract(AbstractRelease {
+            version: String::from("0.9.0"),
+        }))?;
+        graph.add_edge(&abstract_id, &ids[0])?;
+        graph.add_edge(&ids[0], &ids[1])?;
+        graph.add_edge(&ids[1], &ids[2])?;
+        graph.add_edge(&ids[0], &ids[2])?;
+        graph.add_edge(&ids[2], &ids[3])?;
+
+        fn in_channel(channel: &'static str) -> impl Fn(&str) -> bool {
+            move |channels| channels.split(',').map(str::trim).any(|c| c == channel)
+        }
+
+        let stable = graph.subset_by_metadata(channels_key, in_channel("stable"));
+        assert_eq!(stable.releases_count(), 2);
+        let expected_edges: MapImpl<String, SetImpl<String>> = vec![(
+            "1.0.0".to_string(),
+            vec!["1.2.0".to_string()].into_iter().collect(),
+        )]
+        .into_iter()
+        .collect();
+        assert_eq!(stable.get_edges(true)?, expected_edges);
+        match stable.find_by_releaseid(&stable.find_by_version("1.2.0").unwrap())? {
+            Release::Concrete(release) => assert_eq!(release.payload, "image/1.2.0"),
+            Release::Abstract(_) => panic!("expected a concrete release"),
+        }
+
+        let fast = graph.subset_by_metadata(channels_key, in_channel("fast"));
+        assert_eq!(fast.releases_count(), 3);
+        assert_eq!(
+            fast.get_edges(true)?
+                .values()
+                .map(SetImpl::len)
+                .sum::<usize>(),
+            3
+        );
+
+        let candidate = graph.subset_by_metadata(channels_key, in_channel("candidate"));
+        assert_eq!(candidate, Graph::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sorted_and_max_versions() -> TestResult<()> {
+        let mut graph = Graph::default();
+        for version in &[
+            "4.10.0",
+            "4.9.0+s390x",
+            "invalid",
+            "4.9.0-rc.1",
+            "4.9.0+amd64",
+        ] {
+            graph.add_release(Release::Abstract(AbstractRelease {
+                version: version.to_string(),
+            }))?;
+        }
+
+        let versions = |releases: Vec<(ReleaseId, String)>| -> Vec<String> {
+            releases.into_iter().map(|(_, version)| version).collect()
+        };
+        assert_eq!(
+            versions(graph.releases_sorted_by_version()),
+            vec!["4.9.0-rc.1", "4.9.0+amd64", "4.9.0+s390x", "4.10.0"]
+        );
+
+        let max = graph.max_version_in(|release| release.version().starts_with("4.9."));
+        assert_eq!(
+            max.map(|(_, version)| version),
+            Some("4.9.0+s390x".to_string())
+        );
+        assert_eq!(graph.max_version_in(|_| false), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn graph_to_dot() {
+        let mut graph = generate_graph();
+        graph
+            .add_release(Release::Abstract(AbstractRelease {
+                version: String::from("4.0.0-\"quoted\""),
+            }))
+            .unwrap();
+
+        assert_eq!(
+            graph.to_dot(),
+            r#"digraph cincinnati {
+    0 [label="1.0.0"];
+    1 [label="2.0.0"];
+    2 [label="3.0.0"];
+    3 [label="4.0.0-\"quoted\""];
+    0 -> 1;
+    1 -> 2;
+    0 -> 2;
+}
+"#
+        );
+    }
+
     #[test]
     fn test_graph_eq_false_for_unequal_graphs() {
         let graph1 = {
@@ -1388,4 +1917,44 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn next_and_previous_versions() -> TestResult<()> {
+        let graph = generate_custom_graph(
+            "image",
+            (0..4).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 2), (1, 2), (2, 3), (0, 3)]),
+        );
+
+        let v2 = graph
+            .find_by_version("2.0.0")
+            .ok_or_else(|| "couldn't find version 2.0.0".to_string())?;
+        assert_eq!(graph.previous_versions(&v2), vec!["0.0.0", "1.0.0"]);
+        assert_eq!(graph.next_versions(&v2), vec!["3.0.0"]);
+
+        let v0 = graph
+            .find_by_version("0.0.0")
+            .ok_or_else(|| "couldn't find version 0.0.0".to_string())?;
+        assert!(graph.previous_versions(&v0).is_empty());
+        assert_eq!(graph.next_versions(&v0), vec!["2.0.0", "3.0.0"]);
+
+        assert_eq!(graph.find_by_version("4.0.0"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn canonicalize_duplicate_versions() {
+        let mut graph = Graph::default();
+        for version in &["4.6.1", "4.6.2", "4.6.1"] {
+            graph.dag.add_node(Release::Concrete(ConcreteRelease {
+                version: version.to_string(),
+                payload: format!("image/{}", version),
+                metadata: MapImpl::new(),
+            }));
+        }
+
+        let err = graph.canonicalize().unwrap_err();
+        assert_eq!(err.to_string(), "duplicate release version '4.6.1'");
+    }
 }
@@ -5,36 +5,70 @@
 
 use crate as cincinnati;
 
-use self::cincinnati::plugins::BoxedPlugin;
+use self::cincinnati::plugins::metrics::PluginMetrics;
+use self::cincinnati::plugins::migrations::SettingsMigrations;
+use self::cincinnati::plugins::{BoxedPlugin, PLUGIN_ABI_VERSION};
 
 use super::internal::arch_filter::ArchFilterPlugin;
+use super::internal::arch_normalize::ArchNormalizePlugin;
+use super::internal::canonicalize::CanonicalizePlugin;
+use super::internal::channel_deprecation::ChannelDeprecationPlugin;
 use super::internal::channel_filter::ChannelFilterPlugin;
+use super::internal::channel_heads_check::ChannelHeadsCheckPlugin;
 use super::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
+use super::internal::client_version_filter::ClientVersionFilterPlugin;
+use super::internal::coalesce_patches::CoalescePatchesPlugin;
+use super::internal::date_cutoff_filter::DateCutoffFilterPlugin;
+use super::internal::digest_allowlist::DigestAllowlistPlugin;
 use super::internal::dkrv2_openshift_secondary_metadata_scraper::{
     DkrV2OpenshiftSecondaryMetadataScraperPlugin, DkrV2OpenshiftSecondaryMetadataScraperSettings,
 };
 use super::internal::edge_add_remove::EdgeAddRemovePlugin;
+use super::internal::edge_sanitize::EdgeSanitizePlugin;
+use super::internal::edges_overlay::EdgesOverlayPlugin;
+use super::internal::entitlement_filter::EntitlementFilterPlugin;
+use super::internal::git_metadata::{GitMetadataPlugin, GitMetadataSettings};
 use super::internal::github_openshift_secondary_metadata_scraper::{
     GithubOpenshiftSecondaryMetadataScraperPlugin, GithubOpenshiftSecondaryMetadataScraperSettings,
 };
+use super::internal::lifecycle_tag::LifecycleTagPlugin;
+use super::internal::manifest_list_arch::{ManifestListArchPlugin, ManifestListArchSettings};
 use super::internal::metadata_fetch_quay::QuayMetadataFetchPlugin;
+use super::internal::metadata_namespace_filter::MetadataNamespaceFilterPlugin;
+use super::internal::metadata_projection::MetadataProjectionPlugin;
+use super::internal::min_updates_check::MinUpdatesCheckPlugin;
 use super::internal::node_remove::NodeRemovePlugin;
 use super::internal::openshift_secondary_metadata_parser::{
     OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
 };
+use super::internal::platform_filter::PlatformFilterPlugin;
+use super::internal::release_notes::ReleaseNotesPlugin;
 use super::internal::release_scrape_dockerv2::{
     ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings,
 };
+use super::internal::security_gate::SecurityGatePlugin;
+use super::internal::stream_position::StreamPositionPlugin;
+use super::internal::upgrade_estimate::UpgradeEstimatePlugin;
 use commons::prelude_errors::*;
+use smart_default::SmartDefault;
+use std::collections::HashSet;
 use std::fmt::Debug;
+use std::str::FromStr;
 
 /// Key used to look up plugin-type in a configuration entry.
 static CONFIG_PLUGIN_NAME_KEY: &str = "name";
 
 /// Settings for a plugin.
 pub trait PluginSettings: Debug + Send {
+    /// Name of the plugin built from this configuration.
+    ///
+    /// This is checked against plugin allowlists before building the plugin.
+    fn plugin_name(&self) -> &'static str;
+
     /// Build the corresponding plugin for this configuration.
-    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin>;
+    ///
+    /// Metrics of the plugin are to be created via the given `metrics` handle.
+    fn build_plugin(&self, metrics: &PluginMetrics) -> Fallible<BoxedPlugin>;
 }
 
 /// Validate configuration for a plugin and fill in defaults.
@@ -45,16 +79,41 @@ pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>>
         .as_str()
         .ok_or_else(|| format_err!("invalid plugin name value"))?
         .to_string();
+    let cfg = settings_migrations(&name).migrate(cfg)?;
 
     match name.as_str() {
         ChannelFilterPlugin::PLUGIN_NAME => ChannelFilterPlugin::deserialize_config(cfg),
+        ChannelDeprecationPlugin::PLUGIN_NAME => ChannelDeprecationPlugin::deserialize_config(cfg),
         EdgeAddRemovePlugin::PLUGIN_NAME => EdgeAddRemovePlugin::deserialize_config(cfg),
         NodeRemovePlugin::PLUGIN_NAME => NodeRemovePlugin::deserialize_config(cfg),
         QuayMetadataFetchPlugin::PLUGIN_NAME => QuayMetadataFetchPlugin::deserialize_config(cfg),
+        MetadataProjectionPlugin::PLUGIN_NAME => MetadataProjectionPlugin::deserialize_config(cfg),
+        MetadataNamespaceFilterPlugin::PLUGIN_NAME => {
+            MetadataNamespaceFilterPlugin::deserialize_config(cfg)
+        }
         CincinnatiGraphFetchPlugin::PLUGIN_NAME => {
             CincinnatiGraphFetchPlugin::deserialize_config(cfg)
         }
         ArchFilterPlugin::PLUGIN_NAME => ArchFilterPlugin::deserialize_config(cfg),
+        ArchNormalizePlugin::PLUGIN_NAME => ArchNormalizePlugin::deserialize_config(cfg),
+        ChannelHeadsCheckPlugin::PLUGIN_NAME => ChannelHeadsCheckPlugin::deserialize_config(cfg),
+        MinUpdatesCheckPlugin::PLUGIN_NAME => MinUpdatesCheckPlugin::deserialize_config(cfg),
+        CoalescePatchesPlugin::PLUGIN_NAME => CoalescePatchesPlugin::deserialize_config(cfg),
+        LifecycleTagPlugin::PLUGIN_NAME => LifecycleTagPlugin::deserialize_config(cfg),
+        DateCutoffFilterPlugin::PLUGIN_NAME => DateCutoffFilterPlugin::deserialize_config(cfg),
+        PlatformFilterPlugin::PLUGIN_NAME => PlatformFilterPlugin::deserialize_config(cfg),
+        ClientVersionFilterPlugin::PLUGIN_NAME => {
+            ClientVersionFilterPlugin::deserialize_config(cfg)
+        }
+        DigestAllowlistPlugin::PLUGIN_NAME => DigestAllowlistPlugin::deserialize_config(cfg),
+        EdgesOverlayPlugin::PLUGIN_NAME => EdgesOverlayPlugin::deserialize_config(cfg),
+        EdgeSanitizePlugin::PLUGIN_NAME => EdgeSanitizePlugin::deserialize_config(cfg),
+        CanonicalizePlugin::PLUGIN_NAME => CanonicalizePlugin::deserialize_config(cfg),
+        EntitlementFilterPlugin::PLUGIN_NAME => EntitlementFilterPlugin::deserialize_config(cfg),
+        ReleaseNotesPlugin::PLUGIN_NAME => ReleaseNotesPlugin::deserialize_config(cfg),
+        SecurityGatePlugin::PLUGIN_NAME => SecurityGatePlugin::deserialize_config(cfg),
+        StreamPositionPlugin::PLUGIN_NAME => StreamPositionPlugin::deserialize_config(cfg),
+        UpgradeEstimatePlugin::PLUGIN_NAME => UpgradeEstimatePlugin::deserialize_config(cfg),
         ReleaseScrapeDockerv2Plugin::PLUGIN_NAME => {
             ReleaseScrapeDockerv2Settings::deserialize_config(cfg)
         }
@@ -67,27 +126,238 @@ pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>>
         DkrV2OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME => {
             DkrV2OpenshiftSecondaryMetadataScraperSettings::deserialize_config(cfg)
         }
+        GitMetadataPlugin::PLUGIN_NAME => GitMetadataSettings::deserialize_config(cfg),
+        ManifestListArchPlugin::PLUGIN_NAME => ManifestListArchSettings::deserialize_config(cfg),
         x => bail!("unknown plugin '{}'", x),
     }
 }
 
+/// Return the settings migrations of the named plugin.
+///
+/// Plugins without migrations only accept version 1 of their settings.
+pub fn settings_migrations(name: &str) -> SettingsMigrations {
+    match name {
+        QuayMetadataFetchPlugin::PLUGIN_NAME => QuayMetadataFetchPlugin::settings_migrations(),
+        x => SettingsMigrations::new(x),
+    }
+}
+
+/// Comma-separated plugin names allowed by this build, if restricted at compile time.
+static BUILTIN_PLUGIN_ALLOWLIST: Option<&str> = option_env!("CINCINNATI_PLUGIN_ALLOWLIST");
+
+/// Set of plugins which may be built, by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PluginAllowlist(Option<HashSet<String>>);
+
+impl PluginAllowlist {
+    /// Allow all plugins.
+    pub fn all() -> Self {
+        Self(None)
+    }
+
+    /// Allow only the given plugins.
+    pub fn from_names<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self(Some(names.into_iter().map(Into::into).collect()))
+    }
+
+    /// Plugins allowed by this build.
+    ///
+    /// All plugins are allowed, unless the build restricts them by setting
+    /// `CINCINNATI_PLUGIN_ALLOWLIST` to a comma-separated list of names at
+    /// compile time. An invalid list fails every plugin build.
+    pub fn builtin() -> Fallible<Self> {
+        match BUILTIN_PLUGIN_ALLOWLIST {
+            Some(names) => names.parse().context(format!(
+                "invalid builtin plugin allowlist '{}', set by CINCINNATI_PLUGIN_ALLOWLIST",
+                names
+            )),
+            None => Ok(Self::all()),
+        }
+    }
+
+    /// Restrict this allowlist to the plugins also allowed by `other`.
+    pub fn restrict(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (None, names) | (names, None) => Self(names.clone()),
+            (Some(names), Some(other_names)) => {
+                Self(Some(names.intersection(other_names).cloned().collect()))
+            }
+        }
+    }
+
+    /// Whether the given plugin is allowed.
+    pub fn is_allowed(&self, name: &str) -> bool {
+        match &self.0 {
+            None => true,
+            Some(names) => names.contains(name),
+        }
+    }
+
+    /// Check that the given plugin is allowed.
+    pub fn check(&self, name: &str) -> Fallible<()> {
+        ensure!(
+            self.is_allowed(name),
+            "plugin '{}' is not allowed by the plugin allowlist",
+            name
+        );
+        Ok(())
+    }
+}
+
+impl FromStr for PluginAllowlist {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        let names: HashSet<&str> = input
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        ensure!(!names.is_empty(), "empty plugin allowlist");
+        Ok(Self::from_names(names))
+    }
+}
+
 /// Bulid a vector of plugins from PluginSettings
+///
+/// Plugins are restricted to those allowed by this build.
 pub fn build_plugins(
     settings: &[Box<dyn PluginSettings>],
     registry: Option<&prometheus::Registry>,
 ) -> Fallible<Vec<BoxedPlugin>> {
+    build_allowed_plugins(settings, registry, &PluginAllowlist::all())
+}
+
+/// Build a vector of plugins from PluginSettings, failing on any plugin not in `allowlist`.
+///
+/// The allowlist is further restricted to the plugins allowed by this build.
+/// Plugins built against another version of the plugin interface are refused.
+pub fn build_allowed_plugins(
+    settings: &[Box<dyn PluginSettings>],
+    registry: Option<&prometheus::Registry>,
+    allowlist: &PluginAllowlist,
+) -> Fallible<Vec<BoxedPlugin>> {
+    let allowlist = PluginAllowlist::builtin()?.restrict(allowlist);
+    let metrics = PluginMetrics::new(registry);
+    metrics.register_skipped_runs()?;
     let mut plugins = Vec::with_capacity(settings.len());
     for setting in settings {
-        let plugin = setting.build_plugin(registry)?;
+        // Forbidden plugins are refused before they are built, as building may have side effects.
+        allowlist.check(setting.plugin_name())?;
+        let plugin = setting.build_plugin(&metrics)?;
+        check_compatibility(&plugin)?;
         plugins.push(plugin);
     }
 
     Ok(plugins)
 }
 
+/// Check that a plugin was built against the plugin interface version of this build.
+fn check_compatibility(plugin: &BoxedPlugin) -> Fallible<()> {
+    let version = plugin.compatibility();
+    ensure!(
+        version == PLUGIN_ABI_VERSION,
+        "plugin '{}' was built for plugin ABI version {}, but this build requires version {}",
+        plugin.get_name(),
+        version,
+        PLUGIN_ABI_VERSION
+    );
+
+    Ok(())
+}
+
+/// Handling of an empty plugin chain, which is likely a misconfiguration.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyChain {
+    /// Log a warning and fail graph requests as unavailable.
+    #[default]
+    Warn,
+    /// Fail with an error.
+    Fail,
+    /// Log a warning and serve empty graphs.
+    Serve,
+}
+
+impl EmptyChain {
+    /// Whether graph requests are answered with an empty graph on an empty chain.
+    pub fn serves_empty_graph(self) -> bool {
+        self == EmptyChain::Serve
+    }
+}
+
+impl FromStr for EmptyChain {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        match input {
+            "warn" => Ok(EmptyChain::Warn),
+            "fail" => Ok(EmptyChain::Fail),
+            "serve" => Ok(EmptyChain::Serve),
+            _ => bail!(
+                "unknown empty plugin chain handling '{}', expected 'warn', 'fail' or 'serve'",
+                input
+            ),
+        }
+    }
+}
+
+/// Check that the effective plugin chain is not empty.
+///
+/// An empty chain is likely a misconfiguration, which would otherwise go
+/// unnoticed: clients take an empty graph for the absence of upgrades.
+pub fn check_chain(plugins: &[BoxedPlugin], on_empty: EmptyChain) -> Fallible<()> {
+    if !plugins.is_empty() {
+        return Ok(());
+    }
+
+    match on_empty {
+        EmptyChain::Warn => {
+            log::warn!("empty plugin chain, graph requests will fail as unavailable");
+            Ok(())
+        }
+        EmptyChain::Serve => {
+            log::warn!("empty plugin chain, an empty graph will be served");
+            Ok(())
+        }
+        EmptyChain::Fail => bail!("empty plugin chain"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::plugins::prelude_plugin_impl::{
+        async_trait, InternalIO, InternalPlugin, InternalPluginWrapper,
+    };
+
+    /// Plugin built against a previous version of the plugin interface.
+    #[derive(Debug)]
+    struct OutdatedPlugin;
+
+    #[async_trait]
+    impl InternalPlugin for OutdatedPlugin {
+        const PLUGIN_NAME: &'static str = "outdated";
+        const ABI_VERSION: u32 = 3;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            Ok(io)
+        }
+    }
+
+    impl PluginSettings for OutdatedPlugin {
+        fn plugin_name(&self) -> &'static str {
+            <Self as InternalPlugin>::PLUGIN_NAME
+        }
+
+        fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+            Ok(new_plugin!(InternalPluginWrapper(OutdatedPlugin)))
+        }
+    }
 
     #[test]
     fn deserialize_basic() {
@@ -99,7 +369,7 @@ mod tests {
 
         let node_remove_default: toml::Value = toml::from_str("name = 'node-remove'").unwrap();
         let nr_settings = deserialize_config(node_remove_default).unwrap();
-        nr_settings.build_plugin(None).unwrap();
+        nr_settings.build_plugin(&PluginMetrics::default()).unwrap();
 
         let cfg = r#"
             name = "quay-metadata"
@@ -107,6 +377,152 @@ mod tests {
         "#;
         let quay_metadata_repo: toml::Value = toml::from_str(cfg).unwrap();
         let qm_settings = deserialize_config(quay_metadata_repo).unwrap();
-        qm_settings.build_plugin(None).unwrap();
+        qm_settings.build_plugin(&PluginMetrics::default()).unwrap();
+    }
+
+    #[test]
+    fn deserialize_errors_name_plugin_and_field() {
+        let cfg = r#"
+            name = "quay-metadata"
+            repository = "mytest"
+            api_base = 1
+        "#;
+        let err = deserialize_config(toml::from_str(cfg).unwrap()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("plugin 'quay-metadata': field 'api_base': invalid type: integer"),
+            "unexpected error: {}",
+            err
+        );
+
+        let cfg = r#"
+            name = "channel-deprecation"
+            key_prefix = 2
+            [channels]
+            "stable-4.5" = 1
+        "#;
+        let err = deserialize_config(toml::from_str(cfg).unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("plugin 'channel-deprecation': "),
+            "unexpected error: {}",
+            err
+        );
+        for field in &["field 'key_prefix'", "field 'channels.stable-4.5'"] {
+            assert!(err.contains(field), "missing {} in error: {}", field, err);
+        }
+    }
+
+    #[test]
+    fn settings_version_without_migrations() {
+        for cfg in &[
+            "name = 'node-remove'",
+            "name = 'node-remove'\nsettings_version = 1",
+        ] {
+            deserialize_config(toml::from_str(cfg).unwrap()).unwrap();
+        }
+
+        let err = deserialize_config(
+            toml::from_str("name = 'node-remove'\nsettings_version = 2").unwrap(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("unsupported settings_version 2"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn empty_chain() {
+        check_chain(&[], EmptyChain::Warn).unwrap();
+        check_chain(&[], EmptyChain::Fail).unwrap_err();
+        check_chain(&[], EmptyChain::Serve).unwrap();
+        assert_eq!(EmptyChain::default(), EmptyChain::Warn);
+
+        let settings = deserialize_config(toml::from_str("name = 'node-remove'").unwrap()).unwrap();
+        let plugins = build_plugins(&[settings], None).unwrap();
+        check_chain(&plugins, EmptyChain::Fail).unwrap();
+
+        assert_eq!("fail".parse::<EmptyChain>().unwrap(), EmptyChain::Fail);
+        assert_eq!("serve".parse::<EmptyChain>().unwrap(), EmptyChain::Serve);
+        assert!(EmptyChain::Serve.serves_empty_graph());
+        assert!(!EmptyChain::Warn.serves_empty_graph());
+        "error".parse::<EmptyChain>().unwrap_err();
+    }
+
+    #[test]
+    fn allowed_plugin_builds() {
+        let allowlist: PluginAllowlist = "node-remove, channel-filter".parse().unwrap();
+        let settings = deserialize_config(toml::from_str("name = 'node-remove'").unwrap()).unwrap();
+        let plugins = build_allowed_plugins(&[settings], None, &allowlist).unwrap();
+        assert_eq!(plugins.len(), 1);
+
+        // All plugins are allowed by default.
+        assert!(PluginAllowlist::default().is_allowed("quay-metadata"));
+        "".parse::<PluginAllowlist>().unwrap_err();
+    }
+
+    #[test]
+    fn builtin_allowlist_is_valid() {
+        // Catches an invalid CINCINNATI_PLUGIN_ALLOWLIST in the builds setting it.
+        PluginAllowlist::builtin().unwrap();
+    }
+
+    #[test]
+    fn forbidden_plugin_fails() {
+        let allowlist = PluginAllowlist::from_names(vec!["node-remove"]);
+        let cfg = r#"
+            name = "quay-metadata"
+            repository = "mytest"
+        "#;
+        let settings = deserialize_config(toml::from_str(cfg).unwrap()).unwrap();
+        let err = build_allowed_plugins(&[settings], None, &allowlist).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "plugin 'quay-metadata' is not allowed by the plugin allowlist"
+        );
+
+        // Forbidden plugins are not built, e.g. don't create their directories.
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let cache_directory = tmp_dir.path().join("git-metadata");
+        let cfg = format!(
+            r#"
+            name = "git-metadata"
+            url = "https://example.com/metadata.git"
+            cache_directory = {:?}
+            "#,
+            cache_directory
+        );
+        let settings = deserialize_config(toml::from_str(&cfg).unwrap()).unwrap();
+        let err = build_allowed_plugins(&[settings], None, &allowlist).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "plugin 'git-metadata' is not allowed by the plugin allowlist"
+        );
+        assert!(!cache_directory.exists());
+
+        let restricted = allowlist.restrict(&PluginAllowlist::from_names(vec!["channel-filter"]));
+        assert!(!restricted.is_allowed("node-remove"));
+        assert!(!restricted.is_allowed("channel-filter"));
+    }
+
+    #[test]
+    fn incompatible_plugin_fails() {
+        let settings = deserialize_config(toml::from_str("name = 'node-remove'").unwrap()).unwrap();
+        let plugins = build_plugins(&[settings], None).unwrap();
+        assert_eq!(plugins[0].compatibility(), PLUGIN_ABI_VERSION);
+
+        let settings: Box<dyn PluginSettings> = Box::new(OutdatedPlugin);
+        let err = build_plugins(&[settings], None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "plugin 'outdated' was built for plugin ABI version {}, but this build requires version {}",
+                PLUGIN_ABI_VERSION - 1,
+                PLUGIN_ABI_VERSION
+            )
+        );
     }
 }
@@ -38,6 +38,7 @@ mod tests {
     #[async_trait]
     impl ExternalPlugin for DummyWebClient {
         const PLUGIN_NAME: &'static str = "dummy-web-client";
+        const ABI_VERSION: u32 = 4;
 
         async fn run_external(self: &Self, io: ExternalIO) -> Fallible<ExternalIO> {
             let input: interface::PluginExchange = io.try_into()?;
@@ -78,6 +79,7 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect(),
+            warnings: Default::default(),
         };
 
         let input: ExternalIO = input_internal.clone().try_into().unwrap();
@@ -112,6 +114,7 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect(),
+            warnings: Default::default(),
         };
 
         let input: ExternalIO = input_internal.clone().try_into().unwrap();
@@ -0,0 +1,185 @@
+//! Health checks of plugin chains.
+//!
+//! Each plugin reports its own notion of health, e.g. whether its upstream is
+//! reachable or its credentials are loaded. Plugins are healthy unless they
+//! override the health check. A chain is healthy only if all its plugins are.
+
+use super::BoxedPlugin;
+use actix_web::HttpResponse;
+use futures::future::join_all;
+use log::warn;
+
+/// Health of a single plugin.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum PluginHealth {
+    /// The plugin is able to process graphs.
+    Healthy,
+    /// The plugin is unable to process graphs, for the given reason.
+    Unhealthy { reason: String },
+}
+
+impl PluginHealth {
+    /// Whether the plugin is healthy.
+    pub fn is_healthy(&self) -> bool {
+        *self == PluginHealth::Healthy
+    }
+}
+
+/// Health of a plugin of a chain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PluginHealthReport {
+    /// Plugin name.
+    pub name: &'static str,
+    /// Plugin health.
+    #[serde(flatten)]
+    pub health: PluginHealth,
+}
+
+/// Aggregated health of a plugin chain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    /// Whether all plugins are healthy.
+    pub healthy: bool,
+    /// Health of each plugin, in chain order.
+    pub plugins: Vec<PluginHealthReport>,
+}
+
+impl HealthReport {
+    /// Serve the report as JSON, with a 503 status if unhealthy.
+    pub fn into_response(self) -> HttpResponse {
+        if self.healthy {
+            HttpResponse::Ok().json(self)
+        } else {
+            HttpResponse::ServiceUnavailable().json(self)
+        }
+    }
+}
+
+/// Check the health of all plugins concurrently.
+pub async fn check<'a, T>(plugins: T) -> HealthReport
+where
+    T: Iterator<Item = &'a BoxedPlugin>,
+{
+    let plugins: Vec<PluginHealthReport> = join_all(plugins.map(|plugin| async move {
+        PluginHealthReport {
+            name: plugin.get_name(),
+            health: plugin.health().await,
+        }
+    }))
+    .await;
+
+    let healthy = plugins.iter().all(|plugin| plugin.health.is_healthy());
+    for plugin in plugins.iter().filter(|plugin| !plugin.health.is_healthy()) {
+        warn!("plugin '{}' is unhealthy: {:?}", plugin.name, plugin.health);
+    }
+
+    HealthReport { healthy, plugins }
+}
+
+/// Serve the aggregated health of the plugins registered as application data.
+pub async fn serve(plugins: actix_web::web::Data<&'static [BoxedPlugin]>) -> HttpResponse {
+    check(plugins.iter()).await.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::prelude_plugin_impl::*;
+    use commons::testing::init_runtime;
+
+    #[derive(Debug)]
+    struct HealthyPlugin;
+
+    #[async_trait]
+    impl InternalPlugin for HealthyPlugin {
+        const PLUGIN_NAME: &'static str = "healthy";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            Ok(io)
+        }
+    }
+
+    #[derive(Debug)]
+    struct UnhealthyPlugin;
+
+    #[async_trait]
+    impl InternalPlugin for UnhealthyPlugin {
+        const PLUGIN_NAME: &'static str = "unhealthy";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            Ok(io)
+        }
+
+        async fn health(self: &Self) -> PluginHealth {
+            PluginHealth::Unhealthy {
+                reason: "upstream unreachable".to_string(),
+            }
+        }
+    }
+
+    #[test]
+    fn all_healthy() {
+        let mut runtime = init_runtime().unwrap();
+        let plugins: Vec<BoxedPlugin> = new_plugins!(
+            InternalPluginWrapper(HealthyPlugin),
+            InternalPluginWrapper(HealthyPlugin)
+        );
+
+        let report = runtime.block_on(check(plugins.iter()));
+        assert!(report.healthy);
+        assert_eq!(report.plugins.len(), 2);
+
+        assert_eq!(report.into_response().status(), 200);
+        assert!(runtime.block_on(check(std::iter::empty())).healthy);
+    }
+
+    #[test]
+    fn single_unhealthy_plugin() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let plugins: Vec<BoxedPlugin> = new_plugins!(
+            InternalPluginWrapper(HealthyPlugin),
+            InternalPluginWrapper(UnhealthyPlugin),
+            InternalPluginWrapper(HealthyPlugin)
+        );
+
+        let report = runtime.block_on(check(plugins.iter()));
+        assert!(!report.healthy);
+        assert_eq!(
+            report.plugins,
+            vec![
+                PluginHealthReport {
+                    name: "healthy",
+                    health: PluginHealth::Healthy,
+                },
+                PluginHealthReport {
+                    name: "unhealthy",
+                    health: PluginHealth::Unhealthy {
+                        reason: "upstream unreachable".to_string(),
+                    },
+                },
+                PluginHealthReport {
+                    name: "healthy",
+                    health: PluginHealth::Healthy,
+                },
+            ]
+        );
+
+        assert_eq!(
+            serde_json::to_value(&report)?,
+            serde_json::json!({
+                "healthy": false,
+                "plugins": [
+                    {"name": "healthy", "status": "healthy"},
+                    {"name": "unhealthy", "status": "unhealthy", "reason": "upstream unreachable"},
+                    {"name": "healthy", "status": "healthy"},
+                ],
+            })
+        );
+        assert_eq!(report.into_response().status(), 503);
+
+        Ok(())
+    }
+}
@@ -33,7 +33,11 @@ pub struct ArchFilterPlugin {
 }
 
 impl PluginSettings for ArchFilterPlugin {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
 }
@@ -44,7 +48,7 @@ impl ArchFilterPlugin {
 
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let plugin: Self = cfg.try_into()?;
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
 
         ensure!(!plugin.key_prefix.is_empty(), "empty arch-key prefix");
         ensure!(!plugin.key_suffix.is_empty(), "empty arch-key suffix");
@@ -87,10 +91,26 @@ lazy_static! {
 #[async_trait]
 impl InternalPlugin for ArchFilterPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![ParameterDeclaration::new(
+            "arch",
+            format!(
+                "Architecture to filter the graph by, '{}' by default",
+                self.default_arch
+            ),
+        )
+        .with_pattern(ARCH_VALIDATION_REGEX_STR)]
+    }
 
     async fn run_internal(self: &Self, internal_io: InternalIO) -> Fallible<InternalIO> {
         let arch = infer_arch(
-            internal_io.parameters.get("arch").map(|s| s.to_string()),
+            internal_io.parameters.client("arch").map(|s| s.to_string()),
             self.default_arch.clone(),
         )?;
 
@@ -153,6 +173,7 @@ impl InternalPlugin for ArchFilterPlugin {
         Ok(InternalIO {
             graph,
             parameters: internal_io.parameters,
+            warnings: internal_io.warnings,
         })
     }
 }
@@ -235,6 +256,7 @@ mod tests {
                 .iter()
                 .map(|(k, v)| (k.to_string(), v.to_string()))
                 .collect(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime.block_on(future_processed_graph)?.graph;
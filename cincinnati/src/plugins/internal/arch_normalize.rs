@@ -0,0 +1,205 @@
+//! This plugin rewrites the architecture metadata of each release to a
+//! canonical name.
+//!
+//! Upstream metadata may name the same architecture differently, e.g.
+//! `x86_64` and `amd64`. Each comma-separated value at the arch key is
+//! replaced according to the `aliases` map. Values which are neither an alias
+//! nor a canonical architecture are kept as-is, with a warning.
+//! The plugin is meant to run before any architecture-aware plugin, such as
+//! `arch-filter`.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::arch_filter::{DEFAULT_ARCH_KEY, DEFAULT_KEY_FILTER};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Default aliases, mapped to their canonical architecture.
+pub static DEFAULT_ALIASES: &[(&str, &str)] = &[("x86_64", "amd64"), ("aarch64", "arm64")];
+
+/// Default canonical architectures.
+pub static DEFAULT_CANONICAL_ARCHS: &[&str] = &["amd64", "arm64", "ppc64le", "s390x"];
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ArchNormalizePlugin {
+    #[default(DEFAULT_KEY_FILTER.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_ARCH_KEY.to_string())]
+    pub key_suffix: String,
+
+    /// Architecture aliases, mapped to their canonical architecture.
+    #[default(DEFAULT_ALIASES.iter().map(|(alias, arch)| (alias.to_string(), arch.to_string())).collect())]
+    pub aliases: BTreeMap<String, String>,
+
+    /// Canonical architectures, kept as-is without a warning.
+    #[default(DEFAULT_CANONICAL_ARCHS.iter().map(|arch| arch.to_string()).collect())]
+    pub canonical: BTreeSet<String>,
+}
+
+impl PluginSettings for ArchNormalizePlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl ArchNormalizePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "arch-normalize";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let mut plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty arch-key prefix");
+        ensure!(!plugin.key_suffix.is_empty(), "empty arch-key suffix");
+        for (alias, arch) in &plugin.aliases {
+            ensure!(
+                !alias.is_empty() && !arch.is_empty(),
+                "empty architecture alias"
+            );
+        }
+
+        // Alias targets are canonical by definition.
+        let targets: Vec<String> = plugin.aliases.values().cloned().collect();
+        plugin.canonical.extend(targets);
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Return the canonical name of an architecture.
+    pub fn normalize<'a>(&'a self, arch: &'a str) -> &'a str {
+        if let Some(canonical) = self.aliases.get(arch) {
+            return canonical;
+        }
+        if !self.canonical.contains(arch) {
+            warn!("keeping unknown architecture '{}' as-is", arch);
+        }
+        arch
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ArchNormalizePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let arch_key = format!("{}.{}", self.key_prefix, self.key_suffix);
+
+        graph.find_by_fn_mut(|release| {
+            let metadata = match release.get_metadata_mut() {
+                Some(metadata) => metadata,
+                None => return false,
+            };
+
+            if let Some(values) = metadata.get_mut(&arch_key) {
+                let normalized = cincinnati::parse_list(values)
+                    .map(|value| self.normalize(value))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                if *values != normalized {
+                    trace!("normalizing architectures '{}' to '{}'", values, normalized);
+                    *values = normalized;
+                }
+            }
+
+            false
+        });
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{generate_custom_graph, TestMetadata};
+    use commons::testing::init_runtime;
+
+    fn arch_key() -> String {
+        format!("{}.{}", DEFAULT_KEY_FILTER, DEFAULT_ARCH_KEY)
+    }
+
+    fn metadata(archs: &[&str]) -> TestMetadata {
+        archs
+            .iter()
+            .enumerate()
+            .map(|(i, arch)| {
+                (
+                    i,
+                    vec![(arch_key(), arch.to_string())].into_iter().collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn normalize(plugin: ArchNormalizePlugin, archs: &[&str]) -> Fallible<cincinnati::Graph> {
+        let mut runtime = init_runtime()?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: generate_custom_graph("image", metadata(archs), None),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+        Ok(io.graph)
+    }
+
+    #[test]
+    fn default_aliases() -> Fallible<()> {
+        let graph = normalize(
+            ArchNormalizePlugin::default(),
+            &["x86_64", "aarch64", "amd64", "arm64", "x86_64,aarch64"],
+        )?;
+
+        let expected = generate_custom_graph(
+            "image",
+            metadata(&["amd64", "arm64", "amd64", "arm64", "amd64,arm64"]),
+            None,
+        );
+        assert_eq!(graph, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_arch_kept() -> Fallible<()> {
+        let plugin = ArchNormalizePlugin::default();
+        assert_eq!(plugin.normalize("riscv64"), "riscv64");
+
+        let graph = normalize(plugin, &["riscv64", "x86_64,riscv64"])?;
+
+        let expected =
+            generate_custom_graph("image", metadata(&["riscv64", "amd64,riscv64"]), None);
+        assert_eq!(graph, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_empty_aliases() -> Fallible<()> {
+        let cfg = toml::from_str(r#"aliases = { x86_64 = "" }"#)?;
+        assert!(ArchNormalizePlugin::deserialize_config(cfg).is_err());
+
+        let cfg = toml::from_str(r#"aliases = { x86_64 = "x86-64" }"#)?;
+        assert!(ArchNormalizePlugin::deserialize_config(cfg).is_ok());
+
+        Ok(())
+    }
+}
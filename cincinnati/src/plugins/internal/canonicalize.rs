@@ -0,0 +1,134 @@
+//! This plugin puts the releases and edges of the graph in a canonical order.
+//!
+//! The graph is serialized in the order its releases and edges were added,
+//! which depends on the order of the scraped registry, of the fetched
+//! metadata and of the previous plugins. Reordering them makes the served
+//! graph reproducible, e.g. to compare or cache it across runs: releases are
+//! ordered by version, and edges by their source, then by their target.
+//!
+//! The run fails if several releases share the same version.
+//!
+//! This plugin is meant to run last in the graph-builder.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct CanonicalizePlugin {}
+
+impl PluginSettings for CanonicalizePlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl CanonicalizePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "canonicalize";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        Ok(Box::new(plugin))
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for CanonicalizePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        graph.canonicalize()?;
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::Graph;
+    use commons::testing::init_runtime;
+
+    fn release(version: &str) -> serde_json::Value {
+        serde_json::json!({
+            "version": version,
+            "payload": format!("image:{}", version),
+            "metadata": {},
+        })
+    }
+
+    /// Build a graph of the given releases, with edges by release position.
+    fn build_graph(versions: &[&str], edges: &[(usize, usize)]) -> Graph {
+        serde_json::from_value(serde_json::json!({
+            "nodes": versions.iter().map(|version| release(version)).collect::<Vec<_>>(),
+            "edges": edges,
+        }))
+        .unwrap()
+    }
+
+    fn canonicalize(graph: Graph) -> Fallible<serde_json::Value> {
+        let mut runtime = init_runtime()?;
+        let io = runtime.block_on(CanonicalizePlugin::default().run_internal(InternalIO {
+            graph,
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+        Ok(serde_json::to_value(&io.graph)?)
+    }
+
+    #[test]
+    fn stable_order() -> Fallible<()> {
+        let expected = serde_json::json!({
+            "nodes": [
+                release("4.6.1"),
+                release("4.6.2"),
+                release("4.10.0-rc.1"),
+                release("4.10.0"),
+                release("latest"),
+            ],
+            "edges": [[0, 1], [0, 2], [0, 3], [1, 3], [2, 3], [3, 4]],
+        });
+
+        let shuffled = [
+            build_graph(
+                &["4.10.0", "latest", "4.6.2", "4.6.1", "4.10.0-rc.1"],
+                &[(0, 1), (2, 0), (3, 4), (3, 2), (4, 0), (3, 0)],
+            ),
+            build_graph(
+                &["latest", "4.10.0-rc.1", "4.6.1", "4.10.0", "4.6.2"],
+                &[(1, 3), (2, 3), (4, 3), (3, 0), (2, 1), (2, 4)],
+            ),
+        ];
+        for graph in shuffled.iter() {
+            let canonical = canonicalize(graph.clone())?;
+            assert_eq!(canonical, expected);
+
+            // Canonicalizing is idempotent.
+            let graph: Graph = serde_json::from_value(canonical)?;
+            assert_eq!(canonicalize(graph)?, expected);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+//! This plugin flags releases in channels which are being retired.
+//!
+//! The channels of each release are read from the release metadata at
+//! `<key_prefix>.<key_suffix>` as a comma-separated list. Releases in any of
+//! the configured `channels` are annotated with:
+//! * `<key_prefix>.channel.deprecated`: `true`.
+//! * `<key_prefix>.channel.deprecation_message`: the deprecation message of
+//!   each deprecated channel of the release, as `<channel>: <message>` joined
+//!   by `; `.
+//! * `<key_prefix>.channel.sunset`: the earliest sunset date of these
+//!   channels, as `YYYY-MM-DD`, if any is configured in `sunsets`.
+//!
+//! Releases in no deprecated channel are left untouched.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_CHANNEL_KEY: &str = "release.channels";
+static DEPRECATED_KEY: &str = "channel.deprecated";
+static MESSAGE_KEY: &str = "channel.deprecation_message";
+static SUNSET_KEY: &str = "channel.sunset";
+
+/// Format of the sunset dates.
+static SUNSET_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ChannelDeprecationPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_CHANNEL_KEY.to_string())]
+    pub key_suffix: String,
+
+    /// Deprecation messages of the deprecated channels, by channel.
+    pub channels: BTreeMap<String, String>,
+
+    /// Sunset dates of deprecated channels, as `YYYY-MM-DD`, by channel.
+    pub sunsets: BTreeMap<String, String>,
+}
+
+impl PluginSettings for ChannelDeprecationPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl ChannelDeprecationPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "channel-deprecation";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty channel-key prefix");
+        ensure!(!plugin.key_suffix.is_empty(), "empty channel-key suffix");
+        ensure!(
+            !plugin.channels.is_empty(),
+            "no deprecated channels configured"
+        );
+        for (channel, message) in &plugin.channels {
+            ensure!(
+                !message.trim().is_empty(),
+                "empty deprecation message for channel '{}'",
+                channel
+            );
+        }
+        for (channel, sunset) in &plugin.sunsets {
+            ensure!(
+                plugin.channels.contains_key(channel),
+                "sunset date for channel '{}', which is not deprecated",
+                channel
+            );
+            NaiveDate::parse_from_str(sunset, SUNSET_FORMAT).context(format!(
+                "invalid sunset date '{}' for channel '{}', expected YYYY-MM-DD",
+                sunset, channel
+            ))?;
+        }
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Deprecated channels among the given comma-separated list of channels.
+    fn deprecated_channels<'a>(&'a self, channels: &str) -> Vec<&'a str> {
+        // Iterating the configured channels keeps the result sorted and deduplicated.
+        self.channels
+            .keys()
+            .filter(|deprecated| {
+                cincinnati::parse_channels(channels).any(|channel| channel == deprecated.as_str())
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ChannelDeprecationPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let channel_key = format!("{}.{}", self.key_prefix, self.key_suffix);
+        let deprecated_key = format!("{}.{}", self.key_prefix, DEPRECATED_KEY);
+        let message_key = format!("{}.{}", self.key_prefix, MESSAGE_KEY);
+        let sunset_key = format!("{}.{}", self.key_prefix, SUNSET_KEY);
+
+        let mut flagged = 0;
+        graph.find_by_fn_mut(|release| {
+            let concrete_release = match release {
+                cincinnati::Release::Concrete(concrete_release) => concrete_release,
+                cincinnati::Release::Abstract(_) => return false,
+            };
+
+            let deprecated = match concrete_release.metadata.get(&channel_key) {
+                Some(channels) => self.deprecated_channels(channels),
+                None => return false,
+            };
+            if deprecated.is_empty() {
+                return false;
+            }
+
+            let message = deprecated
+                .iter()
+                .map(|channel| format!("{}: {}", channel, self.channels[*channel]))
+                .collect::<Vec<_>>()
+                .join("; ");
+            // Dates in this format sort chronologically.
+            let sunset = deprecated
+                .iter()
+                .filter_map(|channel| self.sunsets.get(*channel))
+                .min();
+
+            trace!(
+                "flagging '{}' as deprecated through {}",
+                concrete_release.version,
+                deprecated.join(", ")
+            );
+            let metadata = &mut concrete_release.metadata;
+            metadata.insert(deprecated_key.clone(), "true".to_string());
+            metadata.insert(message_key.clone(), message);
+            if let Some(sunset) = sunset {
+                metadata.insert(sunset_key.clone(), sunset.clone());
+            }
+            flagged += 1;
+
+            false
+        });
+        trace!("flagged {} releases as deprecated", flagged);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_versioned_graph, run_internal_plugin};
+    use cincinnati::Graph;
+
+    /// Graph with one release per given version and comma-separated channels.
+    fn build_graph(releases: &[(&str, &str)]) -> Graph {
+        let key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_CHANNEL_KEY);
+
+        generate_versioned_graph(
+            "image",
+            releases
+                .iter()
+                .map(|(version, channels)| {
+                    let metadata = vec![(key.clone(), channels.to_string())]
+                        .into_iter()
+                        .collect();
+                    (*version, metadata)
+                })
+                .collect(),
+            &[],
+        )
+    }
+
+    fn plugin(config: &str) -> ChannelDeprecationPlugin {
+        toml::from_str(config).unwrap()
+    }
+
+    fn run(plugin: ChannelDeprecationPlugin, graph: Graph) -> Fallible<Graph> {
+        Ok(run_internal_plugin(&plugin, graph, Default::default())
+            .context("plugin run failed")?
+            .graph)
+    }
+
+    /// Deprecation metadata of the given release, by key suffix.
+    fn deprecation(graph: &Graph, version: &str) -> BTreeMap<&'static str, String> {
+        let release_id = graph.find_by_version(version).unwrap();
+        let metadata = match graph.find_by_releaseid(&release_id).unwrap() {
+            cincinnati::Release::Concrete(release) => &release.metadata,
+            cincinnati::Release::Abstract(_) => panic!("'{}' is abstract", version),
+        };
+
+        [DEPRECATED_KEY, MESSAGE_KEY, SUNSET_KEY]
+            .iter()
+            .filter_map(|key| {
+                metadata
+                    .get(&format!("{}.{}", DEFAULT_KEY_PREFIX, key))
+                    .map(|value| (*key, value.clone()))
+            })
+            .collect()
+    }
+
+    static CONFIG: &str = r#"
+        [channels]
+        "stable-4.5" = "stable-4.5 is retired, switch to stable-4.6"
+        "fast-4.5" = "fast-4.5 is retired, switch to fast-4.6"
+
+        [sunsets]
+        "stable-4.5" = "2021-06-30"
+        "fast-4.5" = "2021-05-31"
+    "#;
+
+    #[test]
+    fn flags_deprecated_channels_only() -> Fallible<()> {
+        let graph = run(
+            plugin(CONFIG),
+            build_graph(&[
+                ("4.5.1", "stable-4.5"),
+                ("4.5.2", "candidate-4.5, stable-4.5, fast-4.5"),
+                ("4.6.0", "candidate-4.6,stable-4.6"),
+                ("4.6.1", "stable-4.5x"),
+            ]),
+        )?;
+
+        let expected: BTreeMap<&str, String> = vec![
+            (DEPRECATED_KEY, "true".to_string()),
+            (
+                MESSAGE_KEY,
+                "stable-4.5: stable-4.5 is retired, switch to stable-4.6".to_string(),
+            ),
+            (SUNSET_KEY, "2021-06-30".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(deprecation(&graph, "4.5.1"), expected);
+
+        // Messages of all deprecated channels are attached, along with the earliest sunset.
+        let expected: BTreeMap<&str, String> = vec![
+            (DEPRECATED_KEY, "true".to_string()),
+            (
+                MESSAGE_KEY,
+                "fast-4.5: fast-4.5 is retired, switch to fast-4.6; \
+                 stable-4.5: stable-4.5 is retired, switch to stable-4.6"
+                    .to_string(),
+            ),
+            (SUNSET_KEY, "2021-05-31".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(deprecation(&graph, "4.5.2"), expected);
+
+        assert!(deprecation(&graph, "4.6.0").is_empty());
+        assert!(deprecation(&graph, "4.6.1").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sunset_is_optional() -> Fallible<()> {
+        let plugin = plugin(
+            r#"
+            [channels]
+            "stable-4.5" = "stable-4.5 is retired"
+            "#,
+        );
+
+        let graph = run(
+            plugin,
+            build_graph(&[("4.5.1", "stable-4.5"), ("4.6.0", "stable-4.6")]),
+        )?;
+
+        let deprecation_451 = deprecation(&graph, "4.5.1");
+        assert_eq!(deprecation_451[DEPRECATED_KEY], "true");
+        assert_eq!(
+            deprecation_451[MESSAGE_KEY],
+            "stable-4.5: stable-4.5 is retired"
+        );
+        assert!(!deprecation_451.contains_key(SUNSET_KEY));
+        assert!(deprecation(&graph, "4.6.0").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() {
+        ChannelDeprecationPlugin::deserialize_config(toml::from_str(CONFIG).unwrap()).unwrap();
+
+        for input in &[
+            "",
+            "key_prefix = ''\n[channels]\n'stable-4.5' = 'retired'",
+            "[channels]\n'stable-4.5' = ' '",
+            "[channels]\n'stable-4.5' = 'retired'\n[sunsets]\n'stable-4.6' = '2021-06-30'",
+            "[channels]\n'stable-4.5' = 'retired'\n[sunsets]\n'stable-4.5' = '30/06/2021'",
+        ] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                ChannelDeprecationPlugin::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+    }
+}
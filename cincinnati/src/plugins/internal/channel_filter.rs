@@ -24,7 +24,11 @@ pub struct ChannelFilterPlugin {
 }
 
 impl PluginSettings for ChannelFilterPlugin {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
 }
@@ -34,7 +38,7 @@ impl ChannelFilterPlugin {
 
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let plugin: Self = cfg.try_into()?;
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
 
         ensure!(!plugin.key_prefix.is_empty(), "empty channel-key prefix");
         ensure!(!plugin.key_suffix.is_empty(), "empty channel-key suffix");
@@ -54,10 +58,24 @@ lazy_static! {
 #[async_trait]
 impl InternalPlugin for ChannelFilterPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![
+            ParameterDeclaration::new("channel", "Channel to filter the graph by")
+                .with_pattern(CHANNEL_VALIDATION_REGEX_STR),
+        ]
+    }
 
     async fn run_internal(self: &Self, internal_io: InternalIO) -> Fallible<InternalIO> {
-        let channel = get_multiple_values!(internal_io.parameters, "channel")
-            .map_err(|e| GraphError::MissingParams(vec![e.to_string()]))?
+        let channel = internal_io
+            .parameters
+            .client("channel")
+            .ok_or_else(|| GraphError::MissingParams(vec!["channel".to_string()]))?
             .clone();
 
         if !CHANNEL_VALIDATION_REGEX_RE.is_match(&channel) {
@@ -77,7 +95,7 @@ impl InternalPlugin for ChannelFilterPlugin {
                             .metadata
                             .get_mut(&format!("{}.{}", self.key_prefix, self.key_suffix))
                             .map_or(true, |values| {
-                                !values.split(',').any(|value| value.trim() == channel)
+                                !cincinnati::parse_channels(values).any(|value| value == channel)
                             }),
                         // remove if it's not a ConcreteRelease
                         _ => true,
@@ -99,6 +117,7 @@ impl InternalPlugin for ChannelFilterPlugin {
         Ok(InternalIO {
             graph,
             parameters: internal_io.parameters,
+            warnings: internal_io.warnings,
         })
     }
 }
@@ -147,6 +166,7 @@ mod tests {
                         .iter()
                         .map(|(a, b)| (a.to_string(), b.to_string()))
                         .collect(),
+                    warnings: Default::default(),
                 });
                 let result = runtime.block_on(future_result);
                 (datum.assert_fn)(&result);
@@ -376,7 +396,8 @@ mod tests {
             let plugin = plugin.clone();
             let future_processed_graph = plugin.run_internal(InternalIO {
                 graph: datum.input_graph,
-                parameters: datum.parameters,
+                parameters: datum.parameters.into(),
+                warnings: Default::default(),
             });
 
             let processed_graph = runtime
@@ -0,0 +1,323 @@
+//! This plugin validates that every channel has exactly one head per architecture.
+//!
+//! Releases are grouped by channel and architecture, as listed in their
+//! metadata. Within such a group, a head is a release without an edge to
+//! another release of the same group. A group without a head leaves clients
+//! on that architecture without a target, and a group with several heads
+//! strands the clients of all but one of them.
+//!
+//! The expected architectures of each channel are taken from `arches`. If it
+//! is empty, only the architectures present in a channel are validated.
+//! Depending on `mode`, violations either fail the plugin or are logged.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_CHANNEL_KEY: &str = "release.channels";
+static DEFAULT_ARCH_KEY: &str = "release.arch";
+
+/// Reaction to releases which violate the validation.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    /// Fail the plugin execution.
+    #[default]
+    Fail,
+    /// Log a warning and pass the graph through unchanged.
+    Warn,
+}
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ChannelHeadsCheckPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_CHANNEL_KEY.to_string())]
+    pub channel_key_suffix: String,
+
+    #[default(DEFAULT_ARCH_KEY.to_string())]
+    pub arch_key_suffix: String,
+
+    /// Architectures which every channel is expected to provide.
+    pub arches: Vec<String>,
+
+    pub mode: ValidationMode,
+}
+
+impl PluginSettings for ChannelHeadsCheckPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl ChannelHeadsCheckPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "channel-heads-check";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty key prefix");
+        ensure!(
+            !plugin.channel_key_suffix.is_empty(),
+            "empty channel-key suffix"
+        );
+        ensure!(!plugin.arch_key_suffix.is_empty(), "empty arch-key suffix");
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Find the (channel, arch) pairs without exactly one head.
+    ///
+    /// Returns the pairs along with the versions of their heads.
+    fn violations(&self, graph: &cincinnati::Graph) -> BTreeMap<(String, String), Vec<String>> {
+        let channel_key = format!("{}.{}", self.key_prefix, self.channel_key_suffix);
+        let arch_key = format!("{}.{}", self.key_prefix, self.arch_key_suffix);
+
+        let split = |values: &str| -> Vec<String> {
+            cincinnati::parse_list(values)
+                .map(ToString::to_string)
+                .collect()
+        };
+
+        // Group the releases by channel and architecture.
+        let mut groups: BTreeMap<String, BTreeMap<String, Vec<(ReleaseId, String)>>> =
+            BTreeMap::new();
+        for (release_id, version, channels) in graph.find_by_metadata_key(&channel_key) {
+            let arches = match graph.find_by_releaseid(&release_id) {
+                Ok(cincinnati::Release::Concrete(release)) => release
+                    .metadata
+                    .get(&arch_key)
+                    .map(|arches| split(arches))
+                    .unwrap_or_default(),
+                _ => vec![],
+            };
+            if arches.is_empty() {
+                trace!("ignoring release '{}' without architecture", version);
+                continue;
+            }
+
+            for channel in split(&channels) {
+                let channel_groups = groups.entry(channel).or_default();
+                for arch in &arches {
+                    channel_groups
+                        .entry(arch.clone())
+                        .or_default()
+                        .push((release_id.clone(), version.clone()));
+                }
+            }
+        }
+
+        let mut violations = BTreeMap::new();
+        for (channel, mut channel_groups) in groups {
+            for arch in &self.arches {
+                channel_groups.entry(arch.clone()).or_default();
+            }
+
+            for (arch, members) in channel_groups {
+                let versions: BTreeSet<&str> = members
+                    .iter()
+                    .map(|(_, version)| version.as_str())
+                    .collect();
+
+                let heads: Vec<String> = members
+                    .iter()
+                    .filter(|(release_id, _)| {
+                        !graph
+                            .next_releases(release_id)
+                            .any(|(_, _, next)| versions.contains(next.version()))
+                    })
+                    .map(|(_, version)| version.clone())
+                    .collect();
+
+                if heads.len() != 1 {
+                    violations.insert((channel.clone(), arch), heads);
+                }
+            }
+        }
+
+        violations
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ChannelHeadsCheckPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let violations = self.violations(&io.graph);
+
+        if !violations.is_empty() {
+            let details = violations
+                .iter()
+                .map(|((channel, arch), heads)| {
+                    if heads.is_empty() {
+                        format!("channel '{}' has no head for arch '{}'", channel, arch)
+                    } else {
+                        format!(
+                            "channel '{}' has {} heads for arch '{}': {}",
+                            channel,
+                            heads.len(),
+                            arch,
+                            heads.join(", ")
+                        )
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+
+            match self.mode {
+                ValidationMode::Fail => bail!("channel head validation failed: {}", details),
+                ValidationMode::Warn => warn!("channel head validation failed: {}", details),
+            }
+        }
+
+        Ok(io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_versioned_graph, run_internal_plugin};
+    use cincinnati::Graph;
+
+    fn build_graph(releases: &[(&str, &str, &str)], edges: &[(&str, &str)]) -> Graph {
+        let channel_key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_CHANNEL_KEY);
+        let arch_key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_ARCH_KEY);
+
+        generate_versioned_graph(
+            "image",
+            releases
+                .iter()
+                .map(|(version, channels, arch)| {
+                    let metadata = vec![
+                        (channel_key.clone(), channels.to_string()),
+                        (arch_key.clone(), arch.to_string()),
+                    ]
+                    .into_iter()
+                    .collect();
+                    (*version, metadata)
+                })
+                .collect(),
+            edges,
+        )
+    }
+
+    /// A multi-arch channel, where `s390x` has no release at all.
+    fn missing_arch_graph() -> Graph {
+        build_graph(
+            &[
+                ("4.5.1+amd64", "stable-4.5", "amd64"),
+                ("4.5.2+amd64", "stable-4.5", "amd64"),
+                ("4.5.1+ppc64le", "stable-4.5", "ppc64le"),
+                ("4.5.2+ppc64le", "stable-4.5", "ppc64le"),
+            ],
+            &[
+                ("4.5.1+amd64", "4.5.2+amd64"),
+                ("4.5.1+ppc64le", "4.5.2+ppc64le"),
+            ],
+        )
+    }
+
+    fn plugin(mode: ValidationMode) -> ChannelHeadsCheckPlugin {
+        ChannelHeadsCheckPlugin {
+            arches: vec![
+                "amd64".to_string(),
+                "ppc64le".to_string(),
+                "s390x".to_string(),
+            ],
+            mode,
+            ..Default::default()
+        }
+    }
+
+    fn run(plugin: ChannelHeadsCheckPlugin, graph: Graph) -> Fallible<Graph> {
+        Ok(run_internal_plugin(&plugin, graph, Default::default())?.graph)
+    }
+
+    #[test]
+    fn missing_arch_head_fails() {
+        let err = run(plugin(ValidationMode::Fail), missing_arch_graph()).unwrap_err();
+        let msg = err.to_string();
+
+        assert!(
+            msg.contains("channel 'stable-4.5' has no head for arch 's390x'"),
+            "unexpected error: {}",
+            msg
+        );
+        assert!(!msg.contains("amd64"), "unexpected error: {}", msg);
+        assert!(!msg.contains("ppc64le"), "unexpected error: {}", msg);
+    }
+
+    #[test]
+    fn missing_arch_head_warns() -> Fallible<()> {
+        let graph = run(plugin(ValidationMode::Warn), missing_arch_graph())?;
+
+        assert_eq!(graph, missing_arch_graph());
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_heads() {
+        let graph = build_graph(
+            &[
+                ("4.5.1+amd64", "stable-4.5", "amd64"),
+                ("4.5.2+amd64", "stable-4.5", "amd64"),
+                ("4.5.3+amd64", "stable-4.5", "amd64"),
+            ],
+            &[("4.5.1+amd64", "4.5.2+amd64")],
+        );
+
+        let violations = plugin(ValidationMode::Fail).violations(&graph);
+        assert_eq!(
+            violations.get(&("stable-4.5".to_string(), "amd64".to_string())),
+            Some(&vec!["4.5.2+amd64".to_string(), "4.5.3+amd64".to_string()])
+        );
+    }
+
+    #[test]
+    fn valid_multi_arch_channel() -> Fallible<()> {
+        let graph = build_graph(
+            &[
+                ("4.5.1+amd64", "stable-4.5, fast-4.5", "amd64"),
+                ("4.5.2+amd64", "fast-4.5", "amd64"),
+                ("4.5.1+s390x", "stable-4.5,fast-4.5", "s390x"),
+                ("4.5.1+multi", "stable-4.5", "ppc64le, s390x, amd64"),
+            ],
+            &[
+                ("4.5.1+amd64", "4.5.2+amd64"),
+                ("4.5.1+amd64", "4.5.1+multi"),
+                ("4.5.1+s390x", "4.5.1+multi"),
+            ],
+        );
+
+        let plugin = ChannelHeadsCheckPlugin {
+            arches: vec!["amd64".to_string(), "s390x".to_string()],
+            ..Default::default()
+        };
+        assert!(plugin.violations(&graph).is_empty());
+        run(plugin, graph)?;
+
+        Ok(())
+    }
+}
@@ -2,22 +2,51 @@
 //!
 //! Instead of processing the input graph, this plugin fetches a graph from a
 //! remote endpoint, which makes it effectively discard any given input graph.
+//!
+//! If `serve_stale_on_error` is enabled, the last successfully fetched graph
+//! is returned when fetching fails. Its age is then reported in the
+//! `STALE_AGE_PARAM` parameter.
+//!
+//! If `auth_token_path` is set, the token read from this file is attached to
+//! every upstream request as the `auth_header` header, as a bearer token for
+//! the default `Authorization` header and verbatim otherwise. The file is read
+//! again whenever it changes, so that rotated tokens are picked up without a
+//! restart.
+//!
+//! Upstream requests carry the `user_agent` setting as `User-Agent` header,
+//! `cincinnati/<version>` by default.
+//!
+//! If the upstream tags its graph with an `ETag`, the graph is revalidated
+//! with `If-None-Match` on the next fetch, and reused if the upstream reports
+//! it unchanged. The ETag of the fetched graph is reported in the
+//! `UPSTREAM_ETAG_PARAM` parameter.
+//!
+//! If `ignore_trailing_data` is enabled, only the first JSON value of the
+//! upstream response is parsed, and any data following it is logged and
+//! ignored instead of failing the fetch. This works around proxies appending
+//! garbage to responses.
 
 use crate as cincinnati;
 
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
-use self::cincinnati::CONTENT_TYPE;
+use self::cincinnati::{CONTENT_TYPE, DEFAULT_USER_AGENT};
 
 use commons::prelude_errors::*;
-use commons::tracing::{get_tracer, set_context};
+use commons::tracing::{get_tracer, set_context, DEBUG_ID_HEADER, DEBUG_ID_PARAM};
 use opentelemetry::api::{Span, Tracer};
 
+use commons::watched_file::WatchedFile;
 use commons::GraphError;
 use prometheus::Counter;
 use reqwest;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
-use std::time::Duration;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, ETAG, IF_NONE_MATCH, USER_AGENT,
+};
+use reqwest::StatusCode;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 /// Default URL to upstream graph provider.
 pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/v1/graph";
@@ -25,6 +54,15 @@ pub static DEFAULT_UPSTREAM_URL: &str = "http://localhost:8080/v1/graph";
 /// Default graph-builder connection timeout in seconds.
 pub static DEFAULT_TIMEOUT_SECS: u64 = 30;
 
+/// Parameter set when a stale graph is served, holding its age in seconds.
+pub static STALE_AGE_PARAM: &str = "__graph.stale_age_secs";
+
+/// Parameter holding the ETag of the fetched upstream graph, if it has one.
+pub static UPSTREAM_ETAG_PARAM: &str = "__graph.upstream_etag";
+
+/// Default header carrying the upstream authentication token.
+pub static DEFAULT_AUTH_HEADER: &str = "Authorization";
+
 /// Plugin settings.
 #[derive(Clone, CustomDebug, Deserialize, SmartDefault)]
 #[serde(default)]
@@ -34,6 +72,69 @@ struct CincinnatiGraphFetchSettings {
 
     #[default(DEFAULT_TIMEOUT_SECS)]
     timeout: u64,
+
+    serve_stale_on_error: bool,
+
+    auth_token_path: Option<PathBuf>,
+
+    #[default(DEFAULT_AUTH_HEADER.to_string())]
+    auth_header: String,
+
+    #[default(DEFAULT_USER_AGENT.to_string())]
+    user_agent: String,
+
+    ignore_trailing_data: bool,
+}
+
+/// Authentication token attached to upstream requests.
+#[derive(Debug)]
+pub struct UpstreamAuth {
+    /// Header carrying the token.
+    header: HeaderName,
+    /// File holding the token.
+    token: WatchedFile,
+}
+
+impl UpstreamAuth {
+    /// Read the token at `path`, to be sent as the `header` header.
+    pub fn try_new(header: &str, path: PathBuf) -> Fallible<Self> {
+        let header = HeaderName::from_bytes(header.as_bytes())
+            .context(format!("invalid authentication header '{}'", header))?;
+        let auth = Self {
+            header,
+            token: WatchedFile::try_new(path)?,
+        };
+        auth.header_value()?;
+        Ok(auth)
+    }
+
+    /// Build the header value from the current token.
+    ///
+    /// Errors never include the token itself.
+    fn header_value(&self) -> Fallible<HeaderValue> {
+        let token = self.token.read()?;
+        let token = token.trim();
+        ensure!(
+            !token.is_empty(),
+            "empty authentication token in '{}'",
+            self.token.path().display()
+        );
+
+        let value = if self.header == AUTHORIZATION {
+            format!("Bearer {}", token)
+        } else {
+            token.to_string()
+        };
+        let mut value = HeaderValue::from_str(&value).map_err(|_| {
+            format_err!(
+                "invalid authentication token in '{}'",
+                self.token.path().display()
+            )
+        })?;
+        value.set_sensitive(true);
+
+        Ok(value)
+    }
 }
 
 /// Graph fetcher for Cincinnati `/v1/graph` endpoints.
@@ -50,14 +151,49 @@ pub struct CincinnatiGraphFetchPlugin {
     #[debug(skip)]
     pub http_upstream_errors_total: Counter,
 
+    /// Whether to serve the last fetched graph if fetching fails
+    pub serve_stale_on_error: bool,
+
+    /// Authentication token attached to upstream requests, if any
+    pub auth: Option<UpstreamAuth>,
+
+    /// User agent of upstream requests
+    pub user_agent: HeaderValue,
+
+    /// Whether to ignore data following the graph in upstream responses
+    pub ignore_trailing_data: bool,
+
     // graph-builder connection client
     client: reqwest::Client,
+
+    // last successfully fetched graph, along with the time it was fetched
+    #[debug(skip)]
+    last_fetched: Mutex<Option<(bytes::Bytes, Instant)>>,
+
+    // time of the last successful fetch, tracked even if stale graphs are not served
+    #[debug(skip)]
+    last_success: Mutex<Option<Instant>>,
+
+    // last fetched graph tagged with an ETag, along with its ETag, for revalidation
+    #[debug(skip)]
+    last_tagged: Mutex<Option<(String, bytes::Bytes)>>,
 }
 
 impl PluginSettings for CincinnatiGraphFetchSettings {
-    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        CincinnatiGraphFetchPlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, metrics: &PluginMetrics) -> Fallible<BoxedPlugin> {
         let cfg = self.clone();
-        let plugin = CincinnatiGraphFetchPlugin::try_new(cfg.upstream, cfg.timeout, registry)?;
+        let mut plugin =
+            CincinnatiGraphFetchPlugin::try_new(cfg.upstream, cfg.timeout, metrics.registry())?;
+        plugin.serve_stale_on_error = cfg.serve_stale_on_error;
+        plugin.user_agent = HeaderValue::from_str(&cfg.user_agent)?;
+        plugin.ignore_trailing_data = cfg.ignore_trailing_data;
+        if let Some(path) = cfg.auth_token_path {
+            plugin.auth = Some(UpstreamAuth::try_new(&cfg.auth_header, path)?);
+        }
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
 }
@@ -68,9 +204,23 @@ impl CincinnatiGraphFetchPlugin {
 
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let settings: CincinnatiGraphFetchSettings = cfg.try_into()?;
+        let settings: CincinnatiGraphFetchSettings =
+            commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
 
         ensure!(!settings.upstream.is_empty(), "empty upstream");
+        if let Some(path) = &settings.auth_token_path {
+            ensure!(
+                !path.as_os_str().is_empty(),
+                "empty authentication token path"
+            );
+        }
+        HeaderName::from_bytes(settings.auth_header.as_bytes()).context(format!(
+            "invalid authentication header '{}'",
+            settings.auth_header
+        ))?;
+        ensure!(!settings.user_agent.is_empty(), "empty user agent");
+        HeaderValue::from_str(&settings.user_agent)
+            .context(format!("invalid user agent '{}'", settings.user_agent))?;
 
         Ok(Box::new(settings))
     }
@@ -105,22 +255,52 @@ impl CincinnatiGraphFetchPlugin {
             upstream,
             http_upstream_reqs,
             http_upstream_errors_total,
+            serve_stale_on_error: false,
+            auth: None,
+            user_agent: HeaderValue::from_static(DEFAULT_USER_AGENT),
+            ignore_trailing_data: false,
             client,
+            last_fetched: Mutex::new(None),
+            last_success: Mutex::new(None),
+            last_tagged: Mutex::new(None),
         })
     }
 }
 
 impl CincinnatiGraphFetchPlugin {
-    async fn do_run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+    /// Fetch the upstream graph, along with its ETag if it has one.
+    async fn do_run_internal(
+        self: &Self,
+        debug_id: Option<&str>,
+    ) -> Fallible<(cincinnati::Graph, Option<String>)> {
         // extract current trace ID from headers
         // this is required to make graph-builder trace a child of police-engine request
         let mut headers = HeaderMap::new();
         headers.insert(ACCEPT, HeaderValue::from_static(CONTENT_TYPE));
+        headers.insert(USER_AGENT, self.user_agent.clone());
         {
             let span = get_tracer().get_active_span();
             set_context(span.get_context(), &mut headers)
                 .context("failed to set the tracing context")?;
         }
+        // forward the debug id, so that upstream force-samples the request too
+        if let Some(debug_id) = debug_id {
+            headers.insert(
+                DEBUG_ID_HEADER,
+                HeaderValue::from_str(debug_id).context("invalid debug id")?,
+            );
+        }
+
+        if let Some(auth) = &self.auth {
+            headers.insert(auth.header.clone(), auth.header_value()?);
+        }
+
+        let last_tagged = self.last_tagged.lock().ok().and_then(|last| last.clone());
+        if let Some((etag, _)) = &last_tagged {
+            if let Ok(value) = HeaderValue::from_str(etag) {
+                headers.insert(IF_NONE_MATCH, value);
+            }
+        }
 
         trace!("getting graph from upstream at {}", self.upstream);
         self.http_upstream_reqs.inc();
@@ -133,38 +313,141 @@ impl CincinnatiGraphFetchPlugin {
             .map_err(|e| GraphError::FailedUpstreamFetch(e.to_string()))
             .await?;
 
-        if !res.status().is_success() {
-            return Err(GraphError::FailedUpstreamFetch(res.status().to_string()).into());
+        if res.status() == StatusCode::UNAUTHORIZED {
+            let reason = match &self.auth {
+                Some(auth) => format!(
+                    "authentication token from '{}' rejected by upstream ({})",
+                    auth.token.path().display(),
+                    res.status()
+                ),
+                None => format!(
+                    "upstream requires authentication, set 'auth_token_path' ({})",
+                    res.status()
+                ),
+            };
+            return Err(GraphError::FailedUpstreamFetch(reason).into());
         }
 
-        let body = res
-            // TODO(steveeJ): find a way to make this fail in a test
-            .bytes()
-            .map_err(move |e| GraphError::FailedUpstreamFetch(e.to_string()))
-            .await?;
+        let (etag, body) = match (res.status(), last_tagged) {
+            (StatusCode::NOT_MODIFIED, Some((etag, body))) => {
+                trace!("upstream graph unchanged, reusing it");
+                (Some(etag), body)
+            }
+            (status, _) if !status.is_success() => {
+                return Err(GraphError::FailedUpstreamFetch(status.to_string()).into());
+            }
+            _ => {
+                let etag = res
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string);
+                let body = res
+                    // TODO(steveeJ): find a way to make this fail in a test
+                    .bytes()
+                    .map_err(move |e| GraphError::FailedUpstreamFetch(e.to_string()))
+                    .await?;
+                (etag, body)
+            }
+        };
 
-        let graph =
-            serde_json::from_slice(&body).map_err(|e| GraphError::FailedJsonIn(e.to_string()))?;
+        let graph = self.parse_graph(&body)?;
 
-        Ok(InternalIO {
-            graph,
-            parameters: io.parameters,
-        })
+        let fetched = Instant::now();
+        if let Ok(mut last_success) = self.last_success.lock() {
+            *last_success = Some(fetched);
+        }
+        if let Ok(mut last_tagged) = self.last_tagged.lock() {
+            *last_tagged = etag.clone().map(|etag| (etag, body.clone()));
+        }
+        if self.serve_stale_on_error {
+            if let Ok(mut last_fetched) = self.last_fetched.lock() {
+                *last_fetched = Some((body, fetched));
+            }
+        }
+
+        Ok((graph, etag))
+    }
+
+    /// Parse an upstream graph, ignoring any data following it if so configured.
+    fn parse_graph(&self, body: &[u8]) -> Result<cincinnati::Graph, GraphError> {
+        if !self.ignore_trailing_data {
+            return serde_json::from_slice(body)
+                .map_err(|e| GraphError::FailedJsonIn(e.to_string()));
+        }
+
+        let mut values = serde_json::Deserializer::from_slice(body).into_iter();
+        let graph = match values.next() {
+            Some(graph) => graph.map_err(|e| GraphError::FailedJsonIn(e.to_string()))?,
+            None => return Err(GraphError::FailedJsonIn("empty upstream graph".to_string())),
+        };
+
+        let trailing = &body[values.byte_offset()..];
+        if !trailing.iter().all(u8::is_ascii_whitespace) {
+            warn!(
+                "ignoring {} bytes of trailing data after the graph from {}",
+                trailing.len(),
+                self.upstream
+            );
+        }
+
+        Ok(graph)
+    }
+
+    /// Return the last fetched graph along with its age, if serving stale graphs is enabled.
+    fn stale_graph(&self) -> Option<(cincinnati::Graph, Duration)> {
+        if !self.serve_stale_on_error {
+            return None;
+        }
+
+        let (body, fetched) = self.last_fetched.lock().ok()?.clone()?;
+        let graph = self.parse_graph(&body).ok()?;
+
+        Some((graph, fetched.elapsed()))
     }
 }
 
 #[async_trait]
 impl InternalPlugin for CincinnatiGraphFetchPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn graph_age(self: &Self) -> Option<Duration> {
+        let fetched = (*self.last_success.lock().ok()?)?;
+        Some(fetched.elapsed())
+    }
 
     async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
-        self.do_run_internal(io)
-            .map_err(move |e| {
+        let (mut parameters, mut warnings) = (io.parameters, io.warnings);
+
+        let debug_id = parameters.internal(DEBUG_ID_PARAM).map(String::as_str);
+        let graph = match self.do_run_internal(debug_id).await {
+            Ok((graph, etag)) => {
+                if let Some(etag) = etag {
+                    parameters.insert_internal(UPSTREAM_ETAG_PARAM, etag);
+                }
+                graph
+            }
+            Err(e) => {
                 error!("error fetching graph: {}", e);
                 self.http_upstream_errors_total.inc();
-                e
-            })
-            .await
+
+                let (graph, age) = self.stale_graph().ok_or(e)?;
+                warn!("serving stale graph, fetched {}s ago", age.as_secs());
+                parameters.insert_internal(STALE_AGE_PARAM, age.as_secs().to_string());
+                warnings.push(Warning::new(
+                    Self::PLUGIN_NAME,
+                    format!("serving a stale graph, fetched {}s ago", age.as_secs()),
+                ));
+                graph
+            }
+        };
+
+        Ok(InternalIO {
+            graph,
+            parameters,
+            warnings,
+        })
     }
 }
 
@@ -206,6 +489,7 @@ mod tests {
                 let future_processed_graph = plugin.run_internal(InternalIO {
                     graph: Default::default(),
                     parameters: Default::default(),
+                    warnings: Default::default(),
                 });
 
                 let processed_graph = runtime
@@ -271,6 +555,7 @@ mod tests {
                 let future_result = plugin.run_internal(InternalIO {
                     graph: Default::default(),
                     parameters: Default::default(),
+                    warnings: Default::default(),
                 });
 
                 assert!(runtime.block_on(future_result).is_err());
@@ -315,6 +600,393 @@ mod tests {
         mock_body: "{not a valid graph}",
     );
 
+    #[test]
+    fn trailing_data() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let path = "/trailing-data";
+        let graph = generate_custom_graph(
+            "image",
+            (0..3).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+        let json = serde_json::to_string(&graph)?;
+
+        let mut plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}{}", mockito::server_url(), path),
+            30,
+            None,
+        )?;
+        let mut fetch = |plugin: &CincinnatiGraphFetchPlugin, body: String| {
+            let _m = mockito::mock("GET", path)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(body)
+                .create();
+            runtime.block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+                warnings: Default::default(),
+            }))
+        };
+
+        // Strict parsing, by default.
+        assert!(!plugin.ignore_trailing_data);
+        assert_eq!(fetch(&plugin, json.clone())?.graph, graph);
+        assert_eq!(fetch(&plugin, format!("{}\n \t\n", json))?.graph, graph);
+        let err = fetch(&plugin, format!("{}\r\n0\r\n\r\n", json)).unwrap_err();
+        assert!(
+            err.to_string().contains("trailing characters"),
+            "unexpected error: {}",
+            err
+        );
+
+        // Tolerant parsing.
+        plugin.ignore_trailing_data = true;
+        assert_eq!(fetch(&plugin, json.clone())?.graph, graph);
+        assert_eq!(fetch(&plugin, format!("{}\n \t\n", json))?.graph, graph);
+        assert_eq!(
+            fetch(&plugin, format!("{}\r\n0\r\n\r\n", json))?.graph,
+            graph
+        );
+        assert_eq!(fetch(&plugin, format!("{}{}", json, json))?.graph, graph);
+        fetch(&plugin, "{not a valid graph}".to_string()).unwrap_err();
+        fetch(&plugin, " \n".to_string()).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_stale_on_error() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let path = "/serve-stale-on-error";
+        let graph = || {
+            generate_custom_graph(
+                "image",
+                (0..3).map(|i| (i, Default::default())).collect(),
+                Some(vec![(0, 1), (1, 2)]),
+            )
+        };
+        let input = || InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        };
+
+        let mut plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}{}", mockito::server_url(), path),
+            30,
+            None,
+        )?;
+        plugin.serve_stale_on_error = true;
+
+        // Nothing to fall back to yet.
+        {
+            let _m = mockito::mock("GET", path).with_status(500).create();
+            assert!(runtime.block_on(plugin.run_internal(input())).is_err());
+            assert_eq!(plugin.graph_age(), None);
+        }
+
+        // Fresh fetch.
+        {
+            let _m = mockito::mock("GET", path)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(serde_json::to_string(&graph())?)
+                .create();
+            let io = runtime.block_on(plugin.run_internal(input()))?;
+            assert_eq!(io.graph, graph());
+            assert_eq!(io.parameters.internal(STALE_AGE_PARAM), None);
+            assert!(io.warnings.is_empty());
+        }
+        let fresh_age = plugin.graph_age().expect("graph age after a fresh fetch");
+
+        // Failed fetch, falling back to the stale graph.
+        {
+            let _m = mockito::mock("GET", path).with_status(500).create();
+            let io = runtime.block_on(plugin.run_internal(input()))?;
+            assert_eq!(io.graph, graph());
+            assert_eq!(
+                io.parameters.internal(STALE_AGE_PARAM).map(String::as_str),
+                Some("0")
+            );
+            assert_eq!(
+                io.warnings,
+                vec![Warning::new(
+                    CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                    "serving a stale graph, fetched 0s ago"
+                )]
+            );
+        }
+
+        // Failed fetches don't refresh the graph age.
+        assert!(plugin.graph_age().expect("graph age after a failed fetch") >= fresh_age);
+
+        assert_eq!(3, plugin.http_upstream_reqs.get() as u64);
+        assert_eq!(2, plugin.http_upstream_errors_total.get() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn forward_debug_id() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let path = "/forward-debug-id";
+
+        let _m = mockito::mock("GET", path)
+            .match_header(DEBUG_ID_HEADER, "abc")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"nodes":[],"edges":[]}"#)
+            .create();
+
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}{}", mockito::server_url(), path),
+            30,
+            None,
+        )?;
+        let io = InternalIO {
+            graph: Default::default(),
+            parameters: vec![(DEBUG_ID_PARAM.to_string(), "abc".to_string())]
+                .into_iter()
+                .collect(),
+            warnings: Default::default(),
+        };
+
+        runtime.block_on(plugin.run_internal(io))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn send_user_agent() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let path = "/send-user-agent";
+
+        let mut plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}{}", mockito::server_url(), path),
+            30,
+            None,
+        )?;
+        let io = || InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        };
+
+        for user_agent in &[DEFAULT_USER_AGENT, "custom-agent/1.0"] {
+            plugin.user_agent = HeaderValue::from_static(*user_agent);
+            let _m = mockito::mock("GET", path)
+                .match_header("user-agent", *user_agent)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"nodes":[],"edges":[]}"#)
+                .create();
+
+            runtime.block_on(plugin.run_internal(io()))?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn revalidate_with_etag() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let path = "/revalidate-with-etag";
+        let graph = generate_custom_graph(
+            "image",
+            (0..3)
+                .into_iter()
+                .map(|i| (i, Default::default()))
+                .collect(),
+            Some(vec![(0, 1), (1, 2)]),
+        );
+
+        let plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}{}", mockito::server_url(), path),
+            30,
+            None,
+        )?;
+        let io = || InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        };
+
+        let tagged = mockito::mock("GET", path)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", r#""v1""#)
+            .with_body(serde_json::to_string(&graph)?)
+            .expect(1)
+            .create();
+        let fetched = runtime.block_on(plugin.run_internal(io()))?;
+        tagged.assert();
+        drop(tagged);
+        assert_eq!(fetched.graph, graph);
+        assert_eq!(
+            fetched.parameters.internal(UPSTREAM_ETAG_PARAM),
+            Some(&r#""v1""#.to_string())
+        );
+
+        // The unchanged graph is reused.
+        let unchanged = mockito::mock("GET", path)
+            .match_header("if-none-match", r#""v1""#)
+            .with_status(304)
+            .expect(1)
+            .create();
+        let revalidated = runtime.block_on(plugin.run_internal(io()))?;
+        unchanged.assert();
+        assert_eq!(revalidated.graph, graph);
+        assert_eq!(
+            revalidated.parameters.internal(UPSTREAM_ETAG_PARAM),
+            Some(&r#""v1""#.to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn send_auth_token() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let path = "/send-auth-token";
+        let dir = tempfile::tempdir()?;
+        let token_path = dir.path().join("token");
+        std::fs::write(&token_path, "s3cr3t\n")?;
+
+        let mut plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}{}", mockito::server_url(), path),
+            30,
+            None,
+        )?;
+        plugin.auth = Some(UpstreamAuth::try_new(
+            DEFAULT_AUTH_HEADER,
+            token_path.clone(),
+        )?);
+        assert!(!format!("{:?}", plugin).contains("s3cr3t"));
+
+        {
+            let _m = mockito::mock("GET", path)
+                .match_header("authorization", "Bearer s3cr3t")
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"nodes":[],"edges":[]}"#)
+                .create();
+            runtime.block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+                warnings: Default::default(),
+            }))?;
+        }
+
+        // A rotated token is picked up by the next request.
+        std::fs::write(&token_path, "r0tated-s3cr3t\n")?;
+        {
+            let _m = mockito::mock("GET", path)
+                .match_header("authorization", "Bearer r0tated-s3cr3t")
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"nodes":[],"edges":[]}"#)
+                .create();
+            runtime.block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+                warnings: Default::default(),
+            }))?;
+        }
+
+        // Custom headers carry the token verbatim.
+        plugin.auth = Some(UpstreamAuth::try_new("x-auth-token", token_path)?);
+        {
+            let _m = mockito::mock("GET", path)
+                .match_header("x-auth-token", "r0tated-s3cr3t")
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"nodes":[],"edges":[]}"#)
+                .create();
+            runtime.block_on(plugin.run_internal(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+                warnings: Default::default(),
+            }))?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_auth_token() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let path = "/rejected-auth-token";
+        let dir = tempfile::tempdir()?;
+        let token_path = dir.path().join("token");
+        std::fs::write(&token_path, "s3cr3t")?;
+
+        let _m = mockito::mock("GET", path)
+            .with_status(401)
+            .with_body("Unauthorized")
+            .create();
+
+        let mut plugin = CincinnatiGraphFetchPlugin::try_new(
+            format!("{}{}", mockito::server_url(), path),
+            30,
+            None,
+        )?;
+        let input = || InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        };
+
+        let err = runtime.block_on(plugin.run_internal(input())).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("upstream requires authentication, set 'auth_token_path'"));
+
+        plugin.auth = Some(UpstreamAuth::try_new(
+            DEFAULT_AUTH_HEADER,
+            token_path.clone(),
+        )?);
+        let err = runtime
+            .block_on(plugin.run_internal(input()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&format!(
+            "authentication token from '{}' rejected by upstream",
+            token_path.display()
+        )));
+        assert!(!err.contains("s3cr3t"));
+        assert_eq!(2, plugin.http_upstream_errors_total.get() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_auth_settings() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let token_path = dir.path().join("token");
+
+        // Missing token file.
+        assert!(UpstreamAuth::try_new(DEFAULT_AUTH_HEADER, token_path.clone()).is_err());
+
+        std::fs::write(&token_path, " \n")?;
+        let err = UpstreamAuth::try_new(DEFAULT_AUTH_HEADER, token_path.clone()).unwrap_err();
+        assert!(err.to_string().contains("empty authentication token"));
+
+        std::fs::write(&token_path, "s3cr3t\u{7f}")?;
+        let err = UpstreamAuth::try_new(DEFAULT_AUTH_HEADER, token_path).unwrap_err();
+        assert!(!err.to_string().contains("s3cr3t"));
+
+        let cfg: toml::Value = toml::from_str("auth_header = 'not a header'")?;
+        assert!(CincinnatiGraphFetchPlugin::deserialize_config(cfg).is_err());
+
+        for user_agent in &["''", r#""bad\u0001agent""#] {
+            let cfg: toml::Value = toml::from_str(&format!("user_agent = {}", user_agent))?;
+            assert!(CincinnatiGraphFetchPlugin::deserialize_config(cfg).is_err());
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn register_metrics() -> Fallible<()> {
         let mut rt = testing::init_runtime()?;
@@ -0,0 +1,281 @@
+//! This plugin removes releases requiring a newer client than the requesting one.
+//!
+//! The minimum client version of each release, e.g. of `oc` or of the cluster
+//! version operator, is read from the release metadata at
+//! `<key_prefix>.<key_suffix>`. The client version is read from the
+//! parameters value at key "client_version", optionally prefixed with `v`.
+//! Releases requiring a newer client are removed, releases without a
+//! requirement are kept.
+//!
+//! Without a client version, all releases are kept, or the releases with a
+//! requirement are removed, according to `missing_client_version`.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::versions::parse_release_version;
+
+use commons::GraphError;
+use semver::Version;
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_MIN_CLIENT_VERSION_KEY: &str = "release.min_client_version";
+
+/// Name of the parameter holding the client version.
+pub const CLIENT_VERSION_PARAM: &str = "client_version";
+
+/// Handling of requests without a client version.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingClientVersion {
+    /// Keep all releases.
+    #[default]
+    Keep,
+    /// Remove the releases with a minimum client version.
+    Restrict,
+}
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ClientVersionFilterPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_MIN_CLIENT_VERSION_KEY.to_string())]
+    pub key_suffix: String,
+
+    pub missing_client_version: MissingClientVersion,
+}
+
+impl PluginSettings for ClientVersionFilterPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl ClientVersionFilterPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "client-version-filter";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(
+            !plugin.key_prefix.is_empty(),
+            "empty min-client-version-key prefix"
+        );
+        ensure!(
+            !plugin.key_suffix.is_empty(),
+            "empty min-client-version-key suffix"
+        );
+
+        Ok(Box::new(plugin))
+    }
+}
+
+/// Parse a client version, optionally prefixed with `v`.
+fn parse_client_version(version: &str) -> Fallible<Version> {
+    let version = version.trim();
+    let version = version.strip_prefix('v').unwrap_or(version);
+    parse_release_version(version)
+}
+
+#[async_trait]
+impl InternalPlugin for ClientVersionFilterPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    fn relevant_parameters(self: &Self) -> Option<&'static [&'static str]> {
+        match self.missing_client_version {
+            MissingClientVersion::Keep => Some(&[CLIENT_VERSION_PARAM]),
+            MissingClientVersion::Restrict => None,
+        }
+    }
+
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![ParameterDeclaration::new(
+            CLIENT_VERSION_PARAM,
+            "Client version, optionally prefixed with 'v', removing the releases requiring a newer client",
+        )]
+    }
+
+    async fn run_internal(self: &Self, internal_io: InternalIO) -> Fallible<InternalIO> {
+        let client_version = match internal_io.parameters.client(CLIENT_VERSION_PARAM) {
+            Some(version) => Some(parse_client_version(version).map_err(|e| {
+                GraphError::InvalidParams(format!(
+                    "'{}' is not a valid version: {}",
+                    CLIENT_VERSION_PARAM, e
+                ))
+            })?),
+            None if self.missing_client_version == MissingClientVersion::Keep => {
+                return Ok(internal_io)
+            }
+            None => None,
+        };
+
+        let key = format!("{}.{}", self.key_prefix, self.key_suffix);
+        let mut graph = internal_io.graph;
+
+        let to_remove = graph
+            .find_by_fn_mut(|release| {
+                let concrete_release = match release {
+                    cincinnati::Release::Concrete(concrete_release) => concrete_release,
+                    cincinnati::Release::Abstract(_) => return false,
+                };
+
+                let min_version = match concrete_release.metadata.get(&key) {
+                    Some(min_version) => min_version,
+                    None => return false,
+                };
+                let client_version = match &client_version {
+                    Some(client_version) => client_version,
+                    None => return true,
+                };
+
+                match parse_client_version(min_version) {
+                    Ok(min_version) => *client_version < min_version,
+                    Err(e) => {
+                        warn!(
+                            "invalid minimum client version '{}' for release '{}': {}",
+                            min_version, concrete_release.version, e
+                        );
+                        false
+                    }
+                }
+            })
+            .into_iter()
+            .map(|(release_id, version)| {
+                trace!("removing '{}', requiring a newer client", version);
+                release_id
+            })
+            .collect();
+
+        let removed = graph.remove_releases(to_remove);
+        trace!("removed {} releases", removed);
+
+        Ok(InternalIO {
+            graph,
+            parameters: internal_io.parameters,
+            warnings: internal_io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_versioned_graph, run_internal_plugin, sorted_versions};
+
+    fn run(
+        plugin: ClientVersionFilterPlugin,
+        client_version: Option<&str>,
+    ) -> Fallible<Vec<String>> {
+        let key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_MIN_CLIENT_VERSION_KEY);
+        let releases = vec![
+            ("4.6.1", None),
+            ("4.7.0", Some("4.6.0")),
+            ("4.8.0", Some("4.7.3")),
+            ("4.9.0", Some("not a version")),
+        ];
+        let graph = generate_versioned_graph(
+            "image",
+            releases
+                .into_iter()
+                .map(|(version, min_client_version)| {
+                    let metadata = min_client_version
+                        .map(|min_client_version| (key.clone(), min_client_version.to_string()))
+                        .into_iter()
+                        .collect();
+                    (version, metadata)
+                })
+                .collect(),
+            &[],
+        );
+        let parameters = client_version
+            .map(|version| (CLIENT_VERSION_PARAM.to_string(), version.to_string()))
+            .into_iter()
+            .collect();
+
+        Ok(sorted_versions(
+            &run_internal_plugin(&plugin, graph, parameters)?.graph,
+        ))
+    }
+
+    #[test]
+    fn compatible_client() -> Fallible<()> {
+        // A client exactly at the minimum version is compatible.
+        for client_version in &["4.7.3", "v4.7.3", "4.8.0"] {
+            assert_eq!(
+                run(ClientVersionFilterPlugin::default(), Some(client_version))?,
+                vec!["4.6.1", "4.7.0", "4.8.0", "4.9.0"],
+                "client version: {}",
+                client_version
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn incompatible_client() -> Fallible<()> {
+        let versions = run(ClientVersionFilterPlugin::default(), Some("4.7.2"))?;
+
+        assert_eq!(versions, vec!["4.6.1", "4.7.0", "4.9.0"]);
+
+        let err = run(ClientVersionFilterPlugin::default(), Some("latest")).unwrap_err();
+        match err.downcast_ref::<GraphError>() {
+            Some(GraphError::InvalidParams(_)) => {}
+            _ => panic!("unexpected error: {}", err),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_client_version() -> Fallible<()> {
+        let versions = run(ClientVersionFilterPlugin::default(), None)?;
+        assert_eq!(versions.len(), 4);
+
+        let plugin = ClientVersionFilterPlugin {
+            missing_client_version: MissingClientVersion::Restrict,
+            ..Default::default()
+        };
+        assert_eq!(plugin.relevant_parameters(), None);
+        assert_eq!(run(plugin, None)?, vec!["4.6.1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() -> Fallible<()> {
+        for input in &["key_suffix = ''", "missing_client_version = 'remove'"] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                ClientVersionFilterPlugin::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+
+        let plugin: ClientVersionFilterPlugin =
+            toml::from_str("missing_client_version = 'restrict'")?;
+        assert_eq!(
+            plugin.missing_client_version,
+            MissingClientVersion::Restrict
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+//! This plugin coalesces the patch releases of each minor version.
+//!
+//! For every `major.minor` stream only the release with the highest patch
+//! version is kept. Edges from and to the removed intermediate releases are
+//! redirected to the retained release, so that every retained release stays
+//! reachable from wherever it was reachable before.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::versions::{cmp_release_versions, parse_release_version};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CoalescePatchesPlugin {}
+
+impl PluginSettings for CoalescePatchesPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl CoalescePatchesPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "coalesce-patches";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        Ok(Box::new(plugin))
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for CoalescePatchesPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        // Group the concrete releases by minor version, keeping the build
+        // metadata in the key so that releases for different architectures
+        // are never coalesced with each other. The fourth component of a
+        // release version is parsed into the build metadata, so the latter is
+        // taken from the version string.
+        let mut streams: BTreeMap<(u64, u64, String), Vec<(ReleaseId, String, semver::Version)>> =
+            BTreeMap::new();
+        for (release_id, version) in graph.find_by_fn_mut(|release| match release {
+            cincinnati::Release::Concrete(_) => true,
+            cincinnati::Release::Abstract(_) => false,
+        }) {
+            let parsed = match parse_release_version(&version) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    warn!("skipping release: {}", e);
+                    continue;
+                }
+            };
+            let build = version
+                .splitn(2, '+')
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+
+            streams
+                .entry((parsed.major, parsed.minor, build))
+                .or_default()
+                .push((release_id, version, parsed));
+        }
+
+        // Map the version of every release which will be removed to the
+        // version of the release which replaces it.
+        let mut to_remove: Vec<ReleaseId> = vec![];
+        let mut replacements: HashMap<String, String> = HashMap::new();
+        for (_, mut releases) in streams {
+            releases.sort_by(|(_, _, a), (_, _, b)| cmp_release_versions(a, b));
+
+            let (_, highest, _) = match releases.pop() {
+                Some(release) => release,
+                None => continue,
+            };
+
+            for (release_id, version, _) in releases {
+                trace!("coalescing '{}' into '{}'", version, highest);
+                replacements.insert(version, highest.clone());
+                to_remove.push(release_id);
+            }
+        }
+
+        if to_remove.is_empty() {
+            return Ok(InternalIO {
+                graph,
+                parameters: io.parameters,
+                warnings: io.warnings,
+            });
+        }
+
+        let replace = |version: &str| -> String {
+            replacements
+                .get(version)
+                .cloned()
+                .unwrap_or_else(|| version.to_string())
+        };
+
+        // Compute the redirected edges before any node is removed, as the
+        // removal invalidates the `ReleaseId`s.
+        let mut redirected: BTreeSet<(String, String)> = BTreeSet::new();
+        for release_id in &to_remove {
+            let version = graph.find_by_releaseid(release_id)?.version().to_string();
+
+            for (_, _, previous) in graph.previous_releases(release_id) {
+                redirected.insert((replace(previous.version()), replace(&version)));
+            }
+            for (_, _, next) in graph.next_releases(release_id) {
+                redirected.insert((replace(&version), replace(next.version())));
+            }
+        }
+
+        let removed = graph.remove_releases(to_remove);
+        trace!("removed {} releases", removed);
+
+        for (from, to) in redirected {
+            if from == to {
+                continue;
+            }
+
+            let (from_id, to_id) = match (graph.find_by_version(&from), graph.find_by_version(&to))
+            {
+                (Some(from_id), Some(to_id)) => (from_id, to_id),
+                _ => bail!("could not find releases for edge {} -> {}", from, to),
+            };
+
+            if let Err(e) = graph.add_edge(&from_id, &to_id) {
+                if e.downcast_ref::<cincinnati::errors::EdgeAlreadyExists>()
+                    .is_none()
+                {
+                    warn!("not redirecting edge {} -> {}: {}", from, to, e);
+                }
+            }
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_versioned_graph, run_internal_plugin};
+    use cincinnati::{Graph, MapImpl};
+
+    fn build_graph(versions: &[&str], edges: &[(&str, &str)]) -> Graph {
+        generate_versioned_graph(
+            "image",
+            versions
+                .iter()
+                .map(|version| (*version, MapImpl::new()))
+                .collect(),
+            edges,
+        )
+    }
+
+    fn run(graph: Graph) -> Fallible<Graph> {
+        Ok(
+            run_internal_plugin(&CoalescePatchesPlugin::default(), graph, Default::default())
+                .context("plugin run failed")?
+                .graph,
+        )
+    }
+
+    #[test]
+    fn keeps_highest_patch_per_minor() -> Fallible<()> {
+        let input_graph = build_graph(
+            &[
+                "4.5.1", "4.5.2", "4.5.3", "4.6.0", "4.6.1", "4.6.2", "4.7.0",
+            ],
+            &[
+                ("4.5.1", "4.5.2"),
+                ("4.5.1", "4.5.3"),
+                ("4.5.2", "4.5.3"),
+                ("4.5.2", "4.6.0"),
+                ("4.5.3", "4.6.1"),
+                ("4.6.0", "4.6.1"),
+                ("4.6.1", "4.6.2"),
+                ("4.6.1", "4.7.0"),
+            ],
+        );
+
+        let expected_graph = build_graph(
+            &["4.5.3", "4.6.2", "4.7.0"],
+            &[("4.5.3", "4.6.2"), ("4.6.2", "4.7.0")],
+        );
+
+        assert_eq!(expected_graph, run(input_graph)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_incoming_edges_from_other_minors() -> Fallible<()> {
+        let input_graph = build_graph(
+            &["4.4.9", "4.5.1", "4.5.2", "4.5.3"],
+            &[("4.4.9", "4.5.1"), ("4.5.1", "4.5.2"), ("4.5.2", "4.5.3")],
+        );
+
+        let expected_graph = build_graph(&["4.4.9", "4.5.3"], &[("4.4.9", "4.5.3")]);
+
+        assert_eq!(expected_graph, run(input_graph)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn does_not_coalesce_across_architectures() -> Fallible<()> {
+        let input_graph = build_graph(
+            &["4.5.1+amd64", "4.5.2+amd64", "4.5.1+s390x"],
+            &[("4.5.1+amd64", "4.5.2+amd64")],
+        );
+
+        let expected_graph = build_graph(&["4.5.2+amd64", "4.5.1+s390x"], &[]);
+
+        assert_eq!(expected_graph, run(input_graph)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn coalesces_release_versions() -> Fallible<()> {
+        let input_graph = build_graph(
+            &["4.5.1", "4.5.1.1", "4.5.9", "4.6.0"],
+            &[
+                ("4.5.1", "4.5.1.1"),
+                ("4.5.1.1", "4.5.9"),
+                ("4.5.1.1", "4.6.0"),
+            ],
+        );
+
+        let expected_graph = build_graph(&["4.5.9", "4.6.0"], &[("4.5.9", "4.6.0")]);
+
+        assert_eq!(expected_graph, run(input_graph)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn untouched_without_patches() -> Fallible<()> {
+        let input_graph = build_graph(&["4.5.0", "4.6.0"], &[("4.5.0", "4.6.0")]);
+        let expected_graph = build_graph(&["4.5.0", "4.6.0"], &[("4.5.0", "4.6.0")]);
+
+        assert_eq!(expected_graph, run(input_graph)?);
+
+        Ok(())
+    }
+}
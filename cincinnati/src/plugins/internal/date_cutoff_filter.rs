@@ -0,0 +1,277 @@
+//! This plugin removes releases built after a client-supplied cutoff date.
+//!
+//! The cutoff is read as an RFC 3339 timestamp from the parameters value at key
+//! "before", which allows clients to query the graph as it looked at a given
+//! time. Without this parameter the graph is passed through unchanged, and the
+//! plugin is skipped altogether by the plugin pipeline.
+//!
+//! The build time of each release is read as an RFC 3339 timestamp from the
+//! release metadata at `<key_prefix>.<key_suffix>`. Releases without a valid
+//! build time are kept or removed according to `missing_timestamp`.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use chrono::{DateTime, FixedOffset};
+use commons::GraphError;
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_CREATED_KEY: &str = "release.created";
+
+/// Name of the parameter holding the cutoff date.
+pub const BEFORE_PARAM: &str = "before";
+
+/// Handling of releases without a valid build time.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingTimestamp {
+    /// Keep the release in the graph.
+    #[default]
+    Keep,
+    /// Remove the release from the graph.
+    Remove,
+}
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct DateCutoffFilterPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_CREATED_KEY.to_string())]
+    pub key_suffix: String,
+
+    pub missing_timestamp: MissingTimestamp,
+}
+
+impl PluginSettings for DateCutoffFilterPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl DateCutoffFilterPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "date-cutoff-filter";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty created-key prefix");
+        ensure!(!plugin.key_suffix.is_empty(), "empty created-key suffix");
+
+        Ok(Box::new(plugin))
+    }
+}
+
+/// Parse the cutoff date from the given "before" parameter.
+fn parse_cutoff(before: &str) -> Result<DateTime<FixedOffset>, GraphError> {
+    DateTime::parse_from_rfc3339(before).map_err(|e| {
+        GraphError::InvalidParams(format!(
+            "'{}' is not a valid RFC 3339 date: {}",
+            BEFORE_PARAM, e
+        ))
+    })
+}
+
+#[async_trait]
+impl InternalPlugin for DateCutoffFilterPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    fn relevant_parameters(self: &Self) -> Option<&'static [&'static str]> {
+        Some(&[BEFORE_PARAM])
+    }
+
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![ParameterDeclaration::new(
+            BEFORE_PARAM,
+            "RFC 3339 timestamp, removing the releases built after it",
+        )]
+    }
+
+    async fn run_internal(self: &Self, internal_io: InternalIO) -> Fallible<InternalIO> {
+        let cutoff = match internal_io.parameters.client(BEFORE_PARAM) {
+            Some(before) => parse_cutoff(before)?,
+            None => return Ok(internal_io),
+        };
+
+        let key = format!("{}.{}", self.key_prefix, self.key_suffix);
+        let mut graph = internal_io.graph;
+
+        let to_remove = graph
+            .find_by_fn_mut(|release| {
+                let concrete_release = match release {
+                    cincinnati::Release::Concrete(concrete_release) => concrete_release,
+                    cincinnati::Release::Abstract(_) => return false,
+                };
+
+                let created = concrete_release.metadata.get(&key).and_then(|created| {
+                    DateTime::parse_from_rfc3339(created)
+                        .map_err(|e| {
+                            warn!(
+                                "invalid build time '{}' for release '{}': {}",
+                                created, concrete_release.version, e
+                            )
+                        })
+                        .ok()
+                });
+
+                match created {
+                    Some(created) => created > cutoff,
+                    None => self.missing_timestamp == MissingTimestamp::Remove,
+                }
+            })
+            .into_iter()
+            .map(|(release_id, version)| {
+                trace!("queuing '{}' for removal", version);
+                release_id
+            })
+            .collect();
+
+        let removed = graph.remove_releases(to_remove);
+        trace!("removed {} releases", removed);
+
+        Ok(InternalIO {
+            graph,
+            parameters: internal_io.parameters,
+            warnings: internal_io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_versioned_graph, run_internal_plugin, sorted_versions};
+    use cincinnati::Graph;
+
+    fn run(
+        plugin: DateCutoffFilterPlugin,
+        graph: Graph,
+        before: Option<&str>,
+    ) -> Fallible<Vec<String>> {
+        let parameters = before
+            .map(|before| (BEFORE_PARAM.to_string(), before.to_string()))
+            .into_iter()
+            .collect();
+
+        Ok(sorted_versions(
+            &run_internal_plugin(&plugin, graph, parameters)?.graph,
+        ))
+    }
+
+    fn straddling_graph() -> Graph {
+        let key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_CREATED_KEY);
+        let releases = vec![
+            ("4.6.1", Some("2020-10-01T12:00:00Z")),
+            ("4.6.2", Some("2020-10-15T12:00:00Z")),
+            ("4.6.3", Some("2020-10-15T14:00:00+02:00")),
+            ("4.6.4", Some("2020-11-01T12:00:00Z")),
+            ("4.6.5", None),
+            ("4.6.6", Some("last tuesday")),
+        ];
+
+        generate_versioned_graph(
+            "image",
+            releases
+                .into_iter()
+                .map(|(version, created)| {
+                    let metadata = created
+                        .map(|created| (key.clone(), created.to_string()))
+                        .into_iter()
+                        .collect();
+                    (version, metadata)
+                })
+                .collect(),
+            &[],
+        )
+    }
+
+    #[test]
+    fn removes_releases_after_cutoff() -> Fallible<()> {
+        let versions = run(
+            DateCutoffFilterPlugin::default(),
+            straddling_graph(),
+            Some("2020-10-15T12:00:00Z"),
+        )?;
+
+        // A release built exactly at the cutoff is kept.
+        assert_eq!(versions, vec!["4.6.1", "4.6.2", "4.6.3", "4.6.5", "4.6.6"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn removes_releases_without_timestamp() -> Fallible<()> {
+        let plugin = DateCutoffFilterPlugin {
+            missing_timestamp: MissingTimestamp::Remove,
+            ..Default::default()
+        };
+
+        let versions = run(plugin, straddling_graph(), Some("2020-10-15T11:59:59Z"))?;
+
+        assert_eq!(versions, vec!["4.6.1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn passes_through_without_cutoff() -> Fallible<()> {
+        let plugin = DateCutoffFilterPlugin {
+            missing_timestamp: MissingTimestamp::Remove,
+            ..Default::default()
+        };
+
+        let versions = run(plugin, straddling_graph(), None)?;
+
+        assert_eq!(versions.len(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_cutoff() {
+        let err = run(
+            DateCutoffFilterPlugin::default(),
+            straddling_graph(),
+            Some("2020-10-15"),
+        )
+        .unwrap_err();
+
+        match err.downcast_ref::<GraphError>() {
+            Some(GraphError::InvalidParams(_)) => {}
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn deserialize_config_validation() -> Fallible<()> {
+        for input in &["key_prefix = ''", "missing_timestamp = 'drop'"] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                DateCutoffFilterPlugin::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+
+        let plugin: DateCutoffFilterPlugin = toml::from_str("missing_timestamp = 'remove'")?;
+        assert_eq!(plugin.missing_timestamp, MissingTimestamp::Remove);
+
+        Ok(())
+    }
+}
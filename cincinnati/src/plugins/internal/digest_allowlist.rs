@@ -0,0 +1,248 @@
+//! This plugin removes releases whose image digest is not on an allowlist.
+//!
+//! The allowlist is read from a file holding one digest per line, such as
+//! `sha256:0123...`. Empty lines and lines starting with `#` are ignored.
+//! The digest of a release is the part of its payload reference following the
+//! `@` separator; releases referenced by tag have no digest and are removed.
+//!
+//! The file is read on every run, so that changes are picked up by the next
+//! scrape. Without an allowlist file, or with an empty one, all releases are
+//! allowed.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct DigestAllowlistPlugin {
+    /// Path to the allowlist file, all releases are allowed if unset.
+    pub allowlist: Option<PathBuf>,
+}
+
+impl PluginSettings for DigestAllowlistPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl DigestAllowlistPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "digest-allowlist";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        if let Some(allowlist) = &plugin.allowlist {
+            ensure!(
+                !allowlist.as_os_str().is_empty(),
+                "empty allowlist file path"
+            );
+        }
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Read and parse the allowlist file, if any.
+    async fn read_allowlist(&self) -> Fallible<HashSet<String>> {
+        let path = match &self.allowlist {
+            Some(path) => path,
+            None => return Ok(HashSet::new()),
+        };
+
+        let content = tokio::fs::read_to_string(path).await.context(format!(
+            "failed to read digest allowlist '{}'",
+            path.display()
+        ))?;
+
+        Ok(parse_allowlist(&content))
+    }
+}
+
+/// Parse the digests of an allowlist.
+pub fn parse_allowlist(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Return the digest of a payload reference, if it is referenced by digest.
+pub fn payload_digest(payload: &str) -> Option<&str> {
+    payload
+        .rsplitn(2, '@')
+        .next()
+        .filter(|digest| digest.len() < payload.len())
+}
+
+#[async_trait]
+impl InternalPlugin for DigestAllowlistPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    async fn run_internal(self: &Self, internal_io: InternalIO) -> Fallible<InternalIO> {
+        let allowlist = self.read_allowlist().await?;
+        if allowlist.is_empty() {
+            return Ok(internal_io);
+        }
+
+        let mut graph = internal_io.graph;
+
+        let to_remove = graph
+            .find_by_fn_mut(|release| {
+                let concrete_release = match release {
+                    cincinnati::Release::Concrete(concrete_release) => concrete_release,
+                    cincinnati::Release::Abstract(_) => return false,
+                };
+
+                match payload_digest(&concrete_release.payload) {
+                    Some(digest) => !allowlist.contains(digest),
+                    None => true,
+                }
+            })
+            .into_iter()
+            .map(|(release_id, version)| {
+                info!("removing release '{}', its digest is not allowed", version);
+                release_id
+            })
+            .collect();
+
+        let removed = graph.remove_releases(to_remove);
+        trace!("removed {} releases", removed);
+
+        Ok(InternalIO {
+            graph,
+            parameters: internal_io.parameters,
+            warnings: internal_io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::{ConcreteRelease, Graph};
+    use commons::testing::init_runtime;
+    use std::io::Write;
+
+    static ALLOWED_DIGEST: &str =
+        "sha256:1111111111111111111111111111111111111111111111111111111111111111";
+    static OTHER_DIGEST: &str =
+        "sha256:2222222222222222222222222222222222222222222222222222222222222222";
+
+    fn build_graph(releases: &[(&str, &str)]) -> Graph {
+        let mut graph = Graph::default();
+
+        for (version, payload) in releases {
+            graph
+                .add_release(cincinnati::Release::Concrete(ConcreteRelease {
+                    version: version.to_string(),
+                    payload: payload.to_string(),
+                    metadata: Default::default(),
+                }))
+                .unwrap();
+        }
+
+        graph
+    }
+
+    fn mixed_graph() -> Graph {
+        let allowed = format!("quay.io/openshift/release@{}", ALLOWED_DIGEST);
+        let other = format!("quay.io/openshift/release@{}", OTHER_DIGEST);
+        let mirrored = format!("mirror.example.com:5000/release@{}", ALLOWED_DIGEST);
+
+        build_graph(&[
+            ("4.6.1", allowed.as_str()),
+            ("4.6.2", other.as_str()),
+            ("4.6.3", "quay.io/openshift/release:4.6.3"),
+            ("4.6.4", mirrored.as_str()),
+        ])
+    }
+
+    fn run(plugin: DigestAllowlistPlugin, graph: Graph) -> Fallible<Vec<String>> {
+        let mut runtime = init_runtime()?;
+
+        let future_processed_graph = plugin.run_internal(InternalIO {
+            graph,
+            parameters: Default::default(),
+            warnings: Default::default(),
+        });
+
+        let mut versions: Vec<String> = runtime
+            .block_on(future_processed_graph)?
+            .graph
+            .find_by_fn_mut(|_| true)
+            .into_iter()
+            .map(|(_, version)| version)
+            .collect();
+        versions.sort();
+        Ok(versions)
+    }
+
+    #[test]
+    fn removes_disallowed_releases() -> Fallible<()> {
+        let mut allowlist = tempfile::NamedTempFile::new()?;
+        writeln!(allowlist, "# attested releases")?;
+        writeln!(allowlist, "  {}  ", ALLOWED_DIGEST)?;
+        writeln!(allowlist)?;
+
+        let plugin = DigestAllowlistPlugin {
+            allowlist: Some(allowlist.path().to_path_buf()),
+        };
+        let versions = run(plugin, mixed_graph())?;
+
+        assert_eq!(versions, vec!["4.6.1", "4.6.4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn allows_all_without_allowlist() -> Fallible<()> {
+        let versions = run(DigestAllowlistPlugin::default(), mixed_graph())?;
+        assert_eq!(versions.len(), 4);
+
+        let mut allowlist = tempfile::NamedTempFile::new()?;
+        writeln!(allowlist, "# nothing attested yet")?;
+
+        let plugin = DigestAllowlistPlugin {
+            allowlist: Some(allowlist.path().to_path_buf()),
+        };
+        let versions = run(plugin, mixed_graph())?;
+        assert_eq!(versions.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_missing_allowlist() {
+        let plugin = DigestAllowlistPlugin {
+            allowlist: Some("/nonexistent/digest-allowlist".into()),
+        };
+        run(plugin, mixed_graph()).unwrap_err();
+
+        let cfg: toml::Value = toml::from_str("allowlist = ''").unwrap();
+        assert!(DigestAllowlistPlugin::deserialize_config(cfg).is_err());
+    }
+
+    #[test]
+    fn digest_of_payload() {
+        assert_eq!(
+            payload_digest("quay.io/openshift/release@sha256:abc"),
+            Some("sha256:abc")
+        );
+        assert_eq!(payload_digest("localhost:5000/release:4.6.3"), None);
+    }
+}
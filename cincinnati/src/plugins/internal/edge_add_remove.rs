@@ -25,6 +25,11 @@ pub struct EdgeAddRemovePlugin {
 #[async_trait]
 impl InternalPlugin for EdgeAddRemovePlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
 
     async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
         let mut graph = io.graph;
@@ -34,12 +39,17 @@ impl InternalPlugin for EdgeAddRemovePlugin {
         Ok(InternalIO {
             graph,
             parameters: io.parameters,
+            warnings: io.warnings,
         })
     }
 }
 
 impl PluginSettings for EdgeAddRemovePlugin {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
         Ok(new_plugin!(InternalPluginWrapper(self.clone())))
     }
 }
@@ -70,7 +80,7 @@ impl EdgeAddRemovePlugin {
 
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let plugin: Self = cfg.try_into()?;
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
 
         ensure!(!plugin.key_prefix.is_empty(), "empty prefix");
         ensure!(
@@ -381,6 +391,7 @@ mod tests {
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph.clone(),
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -436,6 +447,7 @@ mod tests {
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph,
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -500,6 +512,7 @@ mod tests {
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph,
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -552,6 +565,7 @@ mod tests {
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph,
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -608,6 +622,7 @@ mod tests {
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph,
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -659,6 +674,7 @@ mod tests {
                 let future_processed_graph = plugin.run_internal(InternalIO {
                     graph: input_graph.clone(),
                     parameters: Default::default(),
+                    warnings: Default::default(),
                 });
 
                 let processed_graph = runtime.block_on(future_processed_graph)?.graph;
@@ -831,6 +847,7 @@ mod tests {
             cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
                 graph: input_graph.clone(),
                 parameters: Default::default(),
+                warnings: Default::default(),
             }),
         );
 
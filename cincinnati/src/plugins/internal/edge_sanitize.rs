@@ -0,0 +1,288 @@
+//! This plugin removes duplicate edges and self-edges from the graph.
+//!
+//! Merging several metadata sources, e.g. image labels and git metadata, may
+//! declare the same edge more than once, which some clients don't handle. The
+//! first of duplicate edges is kept. Self-edges are rejected when building a
+//! graph, and are only checked for defensively; in `strict` mode they fail
+//! the run, and thus the scrape, instead of being removed.
+//!
+//! Each removal is logged and recorded as a warning. Edges carry no metadata
+//! in the graph, so the sources which declared a removed edge are unknown.
+//!
+//! This plugin is meant to run late in the graph-builder, after all metadata
+//! sources have been merged.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use daggy::EdgeIndex;
+use std::collections::HashSet;
+
+/// Defect of a removed edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeDefect {
+    /// Edge between the same releases as a previous one.
+    Duplicate,
+    /// Edge from a release to itself.
+    SelfEdge,
+}
+
+/// Defective edge, by index and versions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DefectiveEdge {
+    pub index: EdgeIndex,
+    pub from: String,
+    pub to: String,
+    pub defect: EdgeDefect,
+}
+
+#[derive(Clone, CustomDebug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct EdgeSanitizePlugin {
+    /// Whether to fail on self-edges instead of removing them.
+    pub strict: bool,
+
+    /// The optional metric for the number of removed duplicate edges
+    #[serde(skip)]
+    #[debug(skip)]
+    duplicate_edges: Option<prometheus::Counter>,
+
+    /// The optional metric for the number of removed self-edges
+    #[serde(skip)]
+    #[debug(skip)]
+    self_edges: Option<prometheus::Counter>,
+}
+
+impl PluginSettings for EdgeSanitizePlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, metrics: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(
+            self.with_metrics(metrics)?
+        )))
+    }
+}
+
+impl EdgeSanitizePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "edge-sanitize";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Return a copy of the plugin counting removed edges through `metrics`.
+    fn with_metrics(&self, metrics: &PluginMetrics) -> Fallible<Self> {
+        let metrics = metrics.scoped(Self::PLUGIN_NAME);
+        Ok(Self {
+            duplicate_edges: Some(metrics.counter(
+                "duplicate_edges_total",
+                "Total number of removed duplicate edges",
+            )?),
+            self_edges: Some(
+                metrics.counter("self_edges_total", "Total number of removed self-edges")?,
+            ),
+            ..self.clone()
+        })
+    }
+}
+
+/// Find the defective edges among the given `(index, from, to)` edges.
+///
+/// The first of duplicate edges is kept.
+pub fn find_defects<I>(edges: I) -> Vec<DefectiveEdge>
+where
+    I: IntoIterator<Item = (EdgeIndex, String, String)>,
+{
+    let mut seen = HashSet::new();
+    edges
+        .into_iter()
+        .filter_map(|(index, from, to)| {
+            let defect = if from == to {
+                EdgeDefect::SelfEdge
+            } else if !seen.insert((from.clone(), to.clone())) {
+                EdgeDefect::Duplicate
+            } else {
+                return None;
+            };
+            Some(DefectiveEdge {
+                index,
+                from,
+                to,
+                defect,
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl InternalPlugin for EdgeSanitizePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let (mut graph, mut warnings) = (io.graph, io.warnings);
+
+        let mut edges = vec![];
+        for (release_id, version) in graph.find_by_fn_mut(|_| true) {
+            for (index, _, next) in graph.next_releases(&release_id) {
+                edges.push((index, version.clone(), next.version().to_string()));
+            }
+        }
+        let defects = find_defects(edges);
+
+        if self.strict {
+            if let Some(edge) = defects
+                .iter()
+                .find(|edge| edge.defect == EdgeDefect::SelfEdge)
+            {
+                bail!("self-edge on release '{}'", edge.from);
+            }
+        }
+
+        for edge in &defects {
+            let (kind, counter) = match edge.defect {
+                EdgeDefect::Duplicate => ("duplicate edge", &self.duplicate_edges),
+                EdgeDefect::SelfEdge => ("self-edge", &self.self_edges),
+            };
+            let message = format!("removed {} '{}' -> '{}'", kind, edge.from, edge.to);
+            warn!("{}", message);
+            warnings.push(Warning::new(Self::PLUGIN_NAME, message));
+            if let Some(counter) = counter {
+                counter.inc();
+            }
+        }
+
+        let indices: Vec<EdgeIndex> = defects.iter().map(|edge| edge.index).collect();
+        graph.remove_edges_by_index(&indices)?;
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::TestGraphBuilder;
+    use commons::testing::init_runtime;
+
+    #[test]
+    fn removes_duplicate_edges() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let registry = prometheus::Registry::new();
+        let plugin =
+            EdgeSanitizePlugin::default().with_metrics(&PluginMetrics::new(Some(&registry)))?;
+
+        let graph = TestGraphBuilder::new()
+            .with_metadata(vec![
+                (0, Default::default()),
+                (1, Default::default()),
+                (2, Default::default()),
+            ])
+            .with_edges(Some(vec![(0, 1), (0, 1), (1, 2), (0, 2), (1, 2), (1, 2)]))
+            .build();
+        assert_eq!(graph.edge_count(), 6);
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph,
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+
+        let mut edges: Vec<(String, String)> = vec![];
+        let mut graph = io.graph;
+        for (release_id, version) in graph.find_by_fn_mut(|_| true) {
+            for next in graph.next_versions(&release_id) {
+                edges.push((version.clone(), next));
+            }
+        }
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![
+                ("0.0.0".to_string(), "1.0.0".to_string()),
+                ("0.0.0".to_string(), "2.0.0".to_string()),
+                ("1.0.0".to_string(), "2.0.0".to_string()),
+            ]
+        );
+
+        assert_eq!(io.warnings.len(), 3);
+        assert!(io.warnings.contains(&Warning::new(
+            EdgeSanitizePlugin::PLUGIN_NAME,
+            "removed duplicate edge '0.0.0' -> '1.0.0'"
+        )));
+        assert!(io.warnings.contains(&Warning::new(
+            EdgeSanitizePlugin::PLUGIN_NAME,
+            "removed duplicate edge '1.0.0' -> '2.0.0'"
+        )));
+
+        let counters: Vec<(String, u64)> = registry
+            .gather()
+            .iter()
+            .map(|family| {
+                (
+                    family.get_name().to_string(),
+                    family.get_metric()[0].get_counter().get_value() as u64,
+                )
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            vec![
+                ("edge_sanitize_duplicate_edges_total".to_string(), 3),
+                ("edge_sanitize_self_edges_total".to_string(), 0),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn finds_self_edges() {
+        // Graphs reject self-edges, so they are only checked on raw edges.
+        let edge = |index: usize, from: &str, to: &str| {
+            (EdgeIndex::new(index), from.to_string(), to.to_string())
+        };
+        let defects = find_defects(vec![
+            edge(0, "4.6.1", "4.6.2"),
+            edge(1, "4.6.2", "4.6.2"),
+            edge(2, "4.6.1", "4.6.2"),
+            edge(3, "4.6.2", "4.6.2"),
+        ]);
+
+        assert_eq!(
+            defects
+                .iter()
+                .map(|edge| (edge.index.index(), edge.defect))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, EdgeDefect::SelfEdge),
+                (2, EdgeDefect::Duplicate),
+                (3, EdgeDefect::SelfEdge),
+            ]
+        );
+    }
+
+    #[test]
+    fn deserialize_config_strict() -> Fallible<()> {
+        let plugin: EdgeSanitizePlugin = toml::from_str("strict = true")?;
+        assert!(plugin.strict);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! This plugin adds edges listed in an external file to the graph.
+//!
+//! Some upgrade paths are governed by a policy file rather than by release
+//! metadata. The file maps source versions to the target versions they can be
+//! updated to, in TOML format:
+//!
+//! ```toml
+//! "4.5.1" = ["4.5.2", "4.5.3"]
+//! "4.5.2" = ["4.5.3"]
+//! ```
+//!
+//! The file is read on every run, so that changes are picked up by the next
+//! scrape. Entries referencing versions which are not in the graph are skipped.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Edges read from an overlay file, as source version to target versions.
+pub type OverlayEdges = BTreeMap<String, Vec<String>>;
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct EdgesOverlayPlugin {
+    /// Path to the overlay file.
+    pub path: PathBuf,
+}
+
+impl PluginSettings for EdgesOverlayPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl EdgesOverlayPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "edges-overlay";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(
+            !plugin.path.as_os_str().is_empty(),
+            "empty overlay file path"
+        );
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Read and parse the overlay file.
+    async fn read_edges(&self) -> Fallible<OverlayEdges> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .context(format!(
+                "failed to read edges overlay '{}'",
+                self.path.display()
+            ))?;
+
+        toml::from_str(&content).context(format!(
+            "failed to parse edges overlay '{}'",
+            self.path.display()
+        ))
+    }
+}
+
+/// Add the given edges to the graph, returning the number of added edges.
+///
+/// Edges with an endpoint which is not in the graph are skipped with a
+/// warning, edges which already exist are left untouched.
+pub fn apply_overlay(graph: &mut cincinnati::Graph, edges: &OverlayEdges) -> Fallible<usize> {
+    let mut added = 0;
+
+    for (from_version, to_versions) in edges {
+        let from = match graph.find_by_version(from_version) {
+            Some(from) => from,
+            None => {
+                warn!(
+                    "skipping overlay edges from '{}', which is not in the graph",
+                    from_version
+                );
+                continue;
+            }
+        };
+
+        for to_version in to_versions {
+            let to = match graph.find_by_version(to_version) {
+                Some(to) => to,
+                None => {
+                    warn!(
+                        "skipping overlay edge from '{}' to '{}', which is not in the graph",
+                        from_version, to_version
+                    );
+                    continue;
+                }
+            };
+
+            if let Err(e) = graph.add_edge(&from, &to) {
+                if let Some(eae) = e.downcast_ref::<cincinnati::errors::EdgeAlreadyExists>() {
+                    debug!("{}", eae);
+                    continue;
+                }
+                return Err(e.context(format!(
+                    "failed to add overlay edge from '{}' to '{}'",
+                    from_version, to_version
+                )));
+            }
+
+            trace!(
+                "added overlay edge from '{}' to '{}'",
+                from_version,
+                to_version
+            );
+            added += 1;
+        }
+    }
+
+    Ok(added)
+}
+
+#[async_trait]
+impl InternalPlugin for EdgesOverlayPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let edges = self.read_edges().await?;
+
+        let mut graph = io.graph;
+        let added = apply_overlay(&mut graph, &edges)?;
+        debug!("added {} overlay edges", added);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate_custom_graph;
+    use commons::testing::init_runtime;
+    use std::io::Write;
+
+    /// Graph with versions "0.0.0" to "3.0.0" and a single edge from "0.0.0" to "1.0.0".
+    fn input_graph() -> cincinnati::Graph {
+        generate_custom_graph(
+            "image",
+            (0..4).map(|i| (i, Default::default())).collect(),
+            Some(vec![(0, 1)]),
+        )
+    }
+
+    fn run(plugin: &EdgesOverlayPlugin) -> Fallible<cincinnati::Graph> {
+        let mut runtime = init_runtime()?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: input_graph(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+
+        Ok(io.graph)
+    }
+
+    fn edges(graph: &cincinnati::Graph) -> Vec<(String, String)> {
+        graph
+            .get_edges(true)
+            .unwrap()
+            .into_iter()
+            .flat_map(|(from, tos)| tos.into_iter().map(move |to| (from.clone(), to)))
+            .collect()
+    }
+
+    #[test]
+    fn applies_overlay_edges() -> Fallible<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        writeln!(file, r#""0.0.0" = ["1.0.0", "2.0.0"]"#)?;
+        writeln!(file, r#""2.0.0" = ["3.0.0"]"#)?;
+
+        let plugin = EdgesOverlayPlugin {
+            path: file.path().to_path_buf(),
+        };
+        let graph = run(&plugin)?;
+
+        assert_eq!(
+            edges(&graph),
+            vec![
+                ("0.0.0".to_string(), "1.0.0".to_string()),
+                ("0.0.0".to_string(), "2.0.0".to_string()),
+                ("2.0.0".to_string(), "3.0.0".to_string()),
+            ]
+        );
+
+        // The file is re-read on every run.
+        let mut file = std::fs::File::create(file.path())?;
+        writeln!(file, r#""1.0.0" = ["3.0.0"]"#)?;
+        let graph = run(&plugin)?;
+
+        assert_eq!(
+            edges(&graph),
+            vec![
+                ("0.0.0".to_string(), "1.0.0".to_string()),
+                ("1.0.0".to_string(), "3.0.0".to_string()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn skips_absent_nodes() -> Fallible<()> {
+        let mut graph = input_graph();
+        let overlay: OverlayEdges = toml::from_str(
+            r#"
+                "0.0.0" = ["9.9.9", "3.0.0"]
+                "8.8.8" = ["1.0.0"]
+            "#,
+        )?;
+
+        let added = apply_overlay(&mut graph, &overlay)?;
+
+        assert_eq!(added, 1);
+        assert_eq!(
+            edges(&graph),
+            vec![
+                ("0.0.0".to_string(), "1.0.0".to_string()),
+                ("0.0.0".to_string(), "3.0.0".to_string()),
+            ]
+        );
+        assert_eq!(graph.releases_count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_cycles_and_missing_file() {
+        let mut graph = input_graph();
+        let overlay: OverlayEdges = toml::from_str(r#""1.0.0" = ["0.0.0"]"#).unwrap();
+        apply_overlay(&mut graph, &overlay).unwrap_err();
+
+        let plugin = EdgesOverlayPlugin {
+            path: "/nonexistent/edges-overlay.toml".into(),
+        };
+        run(&plugin).unwrap_err();
+
+        let cfg: toml::Value = toml::from_str("path = ''").unwrap();
+        assert!(EdgesOverlayPlugin::deserialize_config(cfg).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! This plugin removes releases in channels the client is not entitled to.
+//!
+//! Entitlement tiers are ordered from the lowest to the highest in `tiers`,
+//! and a tier grants access to all channels requiring it or a lower one. The
+//! client tier is read from the `tier_param` parameter, or else from the
+//! `tier_header` request header, as forwarded by the policy-engine. Clients
+//! without a tier, or with an unknown one, get the lowest tier.
+//!
+//! The tier required by each channel is configured in `channel_tiers`, by
+//! channel name with or without its version suffix, e.g. `eus-4.6` or `eus`.
+//! Channels without a required tier are open to all clients. Gated channels
+//! are removed from the channels of each release, and releases left in none
+//! of their channels are removed. Releases without channels are kept.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use commons::http::HEADER_PARAM_PREFIX;
+use std::collections::{BTreeMap, HashSet};
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_CHANNELS_KEY: &str = "release.channels";
+static DEFAULT_TIER_PARAM: &str = "entitlement";
+static DEFAULT_TIER_HEADER: &str = "x-cincinnati-entitlement";
+
+/// Default tiers, from the lowest to the highest.
+pub static DEFAULT_TIERS: &[&str] = &["free", "standard", "premium"];
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct EntitlementFilterPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_CHANNELS_KEY.to_string())]
+    pub key_suffix: String,
+
+    /// Client parameter carrying the tier.
+    #[default(DEFAULT_TIER_PARAM.to_string())]
+    pub tier_param: String,
+
+    /// Request header carrying the tier, if the parameter is missing.
+    #[default(DEFAULT_TIER_HEADER.to_string())]
+    pub tier_header: String,
+
+    /// Tiers, from the lowest to the highest.
+    #[default(DEFAULT_TIERS.iter().map(ToString::to_string).collect())]
+    pub tiers: Vec<String>,
+
+    /// Tier required by each channel.
+    pub channel_tiers: BTreeMap<String, String>,
+}
+
+impl PluginSettings for EntitlementFilterPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl EntitlementFilterPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "entitlement-filter";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let mut plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty channel-key prefix");
+        ensure!(!plugin.key_suffix.is_empty(), "empty channel-key suffix");
+        ensure!(!plugin.tier_param.is_empty(), "empty tier parameter");
+        ensure!(!plugin.tier_header.is_empty(), "empty tier header");
+        ensure!(!plugin.tiers.is_empty(), "no entitlement tiers");
+
+        let mut tiers = HashSet::new();
+        for tier in &plugin.tiers {
+            ensure!(tiers.insert(tier), "duplicate entitlement tier '{}'", tier);
+        }
+        for (channel, tier) in &plugin.channel_tiers {
+            ensure!(
+                tiers.contains(tier),
+                "unknown entitlement tier '{}' for channel '{}'",
+                tier,
+                channel
+            );
+        }
+
+        // Headers are forwarded by lowercase name.
+        plugin.tier_header = plugin.tier_header.to_lowercase();
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Return the rank of the client tier, the lowest for unknown tiers.
+    fn client_rank(&self, parameters: &Parameters) -> usize {
+        let header_param = format!("{}{}", HEADER_PARAM_PREFIX, self.tier_header);
+        let tier = match parameters
+            .client(&self.tier_param)
+            .or_else(|| parameters.internal(&header_param))
+        {
+            Some(tier) => tier.trim(),
+            None => return 0,
+        };
+
+        self.rank(tier).unwrap_or_else(|| {
+            debug!("unknown entitlement tier '{}', using the lowest", tier);
+            0
+        })
+    }
+
+    /// Return the rank of a tier.
+    fn rank(&self, tier: &str) -> Option<usize> {
+        self.tiers.iter().position(|known| known == tier)
+    }
+
+    /// Return the rank of the tier required by a channel.
+    fn required_rank(&self, channel: &str) -> usize {
+        let name = match channel.rfind('-') {
+            Some(pos) => &channel[..pos],
+            None => channel,
+        };
+        self.channel_tiers
+            .get(channel)
+            .or_else(|| self.channel_tiers.get(name))
+            .and_then(|tier| self.rank(tier))
+            .unwrap_or(0)
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for EntitlementFilterPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![ParameterDeclaration::new(
+            self.tier_param.clone(),
+            format!("Entitlement tier, one of: {}", self.tiers.join(", ")),
+        )]
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let client_rank = self.client_rank(&io.parameters);
+        let channels_key = format!("{}.{}", self.key_prefix, self.key_suffix);
+
+        let to_remove = graph
+            .find_by_fn_mut(|release| {
+                let metadata = match release.get_metadata_mut() {
+                    Some(metadata) => metadata,
+                    None => return false,
+                };
+                let channels = match metadata.get_mut(&channels_key) {
+                    Some(channels) => channels,
+                    None => return false,
+                };
+
+                let entitled: Vec<&str> = cincinnati::parse_channels(channels)
+                    .filter(|channel| self.required_rank(channel) <= client_rank)
+                    .collect();
+                if entitled.is_empty() {
+                    return true;
+                }
+
+                let entitled = entitled.join(",");
+                if *channels != entitled {
+                    *channels = entitled;
+                }
+                false
+            })
+            .into_iter()
+            .map(|(release_id, version)| {
+                trace!("removing '{}', in no entitled channel", version);
+                release_id
+            })
+            .collect();
+
+        let removed = graph.remove_releases(to_remove);
+        trace!("removed {} releases", removed);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::{generate_custom_graph, TestMetadata};
+    use commons::testing::init_runtime;
+
+    fn channels_key() -> String {
+        format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_CHANNELS_KEY)
+    }
+
+    fn metadata(channels: &[&str]) -> TestMetadata {
+        channels
+            .iter()
+            .enumerate()
+            .map(|(i, channels)| {
+                (
+                    i,
+                    vec![(channels_key(), channels.to_string())]
+                        .into_iter()
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn plugin() -> EntitlementFilterPlugin {
+        EntitlementFilterPlugin {
+            channel_tiers: vec![
+                ("eus".to_string(), "premium".to_string()),
+                ("candidate-4.7".to_string(), "standard".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Return the sorted channels of the releases kept for a client with the given parameters.
+    fn kept_channels(channels: &[&str], parameters: &[(&str, &str)]) -> Fallible<Vec<String>> {
+        let mut runtime = init_runtime()?;
+
+        let mut io = runtime.block_on(
+            plugin().run_internal(InternalIO {
+                graph: generate_custom_graph("image", metadata(channels), None),
+                parameters: parameters
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                warnings: Default::default(),
+            }),
+        )?;
+
+        let mut kept = vec![];
+        io.graph.find_by_fn_mut(|release| {
+            if let Some(channels) = release
+                .get_metadata_mut()
+                .and_then(|metadata| metadata.get(&channels_key()))
+            {
+                kept.push(channels.clone());
+            }
+            false
+        });
+        kept.sort();
+        Ok(kept)
+    }
+
+    static CHANNELS: &[&str] = &[
+        "stable-4.6",
+        "stable-4.6,eus-4.6",
+        "eus-4.6",
+        "candidate-4.7",
+        "candidate-4.6",
+    ];
+
+    #[test]
+    fn non_entitled_client() -> Fallible<()> {
+        // Missing and unknown tiers get the lowest one.
+        for parameters in &[vec![], vec![("entitlement", "gold")]] {
+            assert_eq!(
+                kept_channels(CHANNELS, parameters)?,
+                vec!["candidate-4.6", "stable-4.6", "stable-4.6"],
+                "parameters: {:?}",
+                parameters
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn entitled_client() -> Fallible<()> {
+        // The highest tier is entitled to all channels.
+        let mut all: Vec<String> = CHANNELS.iter().map(ToString::to_string).collect();
+        all.sort();
+        assert_eq!(kept_channels(CHANNELS, &[("entitlement", "premium")])?, all);
+
+        // Tiers are read from the forwarded header if the parameter is missing.
+        let header_param = format!("{}{}", HEADER_PARAM_PREFIX, DEFAULT_TIER_HEADER);
+        assert_eq!(
+            kept_channels(CHANNELS, &[(&header_param, "standard")])?,
+            vec!["candidate-4.6", "candidate-4.7", "stable-4.6", "stable-4.6"]
+        );
+
+        // The parameter takes precedence over the header.
+        assert_eq!(
+            kept_channels(
+                CHANNELS,
+                &[(&header_param, "premium"), ("entitlement", "free")]
+            )?,
+            vec!["candidate-4.6", "stable-4.6", "stable-4.6"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() -> Fallible<()> {
+        for input in &[
+            "tiers = []",
+            "tiers = ['free', 'free']",
+            "channel_tiers = { eus = 'gold' }",
+            "tier_param = ''",
+        ] {
+            let cfg: toml::Value = toml::from_str(input)?;
+            assert!(
+                EntitlementFilterPlugin::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+
+        let cfg: toml::Value = toml::from_str("channel_tiers = { eus = 'premium' }")?;
+        assert!(EntitlementFilterPlugin::deserialize_config(cfg).is_ok());
+
+        Ok(())
+    }
+}
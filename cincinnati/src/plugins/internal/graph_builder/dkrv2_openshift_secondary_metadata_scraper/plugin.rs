@@ -1,5 +1,6 @@
 use crate as cincinnati;
 use crate::plugins::internal::dkrv2_openshift_secondary_metadata_scraper::gpg;
+use crate::plugins::internal::github_openshift_secondary_metadata_scraper::plugin::publish_data_dir;
 use crate::plugins::internal::release_scrape_dockerv2::registry;
 use reqwest::{Client, ClientBuilder};
 use std::path::{Path, PathBuf};
@@ -27,7 +28,7 @@ pub static DEFAULT_SIGNATURE_BASEURL: &str =
 pub static DEFAULT_SIGNATURE_FETCH_TIMEOUT_SECS: u64 = 30;
 
 // Defines the key for placing the data directory path in the IO parameters
-pub static GRAPH_DATA_DIR_PARAM_KEY: &str = "io.openshift.upgrades.secondary_metadata.directory";
+pub static GRAPH_DATA_DIR_PARAM_KEY: &str = "__secondary_metadata.directory";
 
 /// Plugin settings.
 #[derive(Debug, SmartDefault, Clone, Deserialize)]
@@ -82,10 +83,10 @@ pub struct DkrV2OpenshiftSecondaryMetadataScraperSettings {
 impl DkrV2OpenshiftSecondaryMetadataScraperSettings {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let mut settings: Self = cfg
-            .clone()
-            .try_into()
-            .context(format!("Deserializing {:#?}", &cfg))?;
+        let mut settings: Self = commons::de::from_toml_named(
+            cfg,
+            DkrV2OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME,
+        )?;
 
         ensure!(
             !settings
@@ -208,7 +209,11 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
 }
 
 impl PluginSettings for DkrV2OpenshiftSecondaryMetadataScraperSettings {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        DkrV2OpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
         let plugin = DkrV2OpenshiftSecondaryMetadataScraperPlugin::try_new(self.clone())?;
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
@@ -217,6 +222,7 @@ impl PluginSettings for DkrV2OpenshiftSecondaryMetadataScraperSettings {
 #[async_trait]
 impl InternalPlugin for DkrV2OpenshiftSecondaryMetadataScraperPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
 
     async fn run_internal(self: &Self, mut io: InternalIO) -> Fallible<InternalIO> {
         let registry_client = registry::new_registry_client(
@@ -288,14 +294,7 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
         match (&state.cached_layers, &state.cached_data_dir) {
             (Some(cached_layers), Some(cached_data_dir)) if cached_layers.as_slice() == layers => {
                 trace!("Using cached data directory for tag {}", self.settings.tag);
-                io.parameters.insert(
-                    GRAPH_DATA_DIR_PARAM_KEY.to_string(),
-                    cached_data_dir
-                        .path()
-                        .to_str()
-                        .ok_or_else(|| format_err!("data_dir cannot be converted to str"))?
-                        .to_string(),
-                );
+                publish_data_dir(io, cached_data_dir.path())?;
                 Ok(true)
             }
 
@@ -306,14 +305,7 @@ impl DkrV2OpenshiftSecondaryMetadataScraperPlugin {
     fn create_data_dir(&self, io: &mut InternalIO) -> Fallible<TempDir> {
         let data_dir = tempfile::tempdir_in(self.data_dir.path())?;
 
-        io.parameters.insert(
-            GRAPH_DATA_DIR_PARAM_KEY.to_string(),
-            data_dir
-                .path()
-                .to_str()
-                .ok_or_else(|| format_err!("data_dir cannot be converted to str"))?
-                .to_string(),
-        );
+        publish_data_dir(io, data_dir.path())?;
 
         trace!(
             "Using data directory {:?} for tag {}",
@@ -444,7 +436,7 @@ mod network_tests {
 
         debug!("Settings: {:#?}", &settings);
 
-        let plugin = Box::leak(Box::new(settings.build_plugin(None)?));
+        let plugin = Box::leak(Box::new(settings.build_plugin(&PluginMetrics::default())?));
 
         let mut data_dirs_counter: std::collections::HashMap<PathBuf, usize> = Default::default();
 
@@ -453,6 +445,7 @@ mod network_tests {
                 plugin.run(cincinnati::plugins::PluginIO::InternalIO(InternalIO {
                     graph: Default::default(),
                     parameters: Default::default(),
+                    warnings: Default::default(),
                 }))
             })
             .await??;
@@ -465,7 +458,7 @@ mod network_tests {
 
             let data_dir = if let cincinnati::plugins::PluginIO::InternalIO(iio) = io {
                 iio.parameters
-                    .get(GRAPH_DATA_DIR_PARAM_KEY)
+                    .internal(GRAPH_DATA_DIR_PARAM_KEY)
                     .map(PathBuf::from)
                     .unwrap()
             } else {
@@ -0,0 +1,20 @@
+//! This plugin merges release metadata declared in a git repository into the graph.
+//!
+//! The repository holds one YAML declaration per release in a directory,
+//! assigning channels and arbitrary metadata to a release version:
+//!
+//! ```yaml
+//! version: 4.6.1
+//! channels: [stable-4.6, fast-4.6]
+//! metadata:
+//!   url: https://access.redhat.com/errata/RHBA-2020:4196
+//! ```
+//!
+//! The repository is cloned with a depth of one into a cache directory, which
+//! is kept across runs and restarts. It is only fetched again once the remote
+//! reference advances, and the cached checkout is used if the remote cannot be
+//! reached. Fetching is done with the `git` executable, which must be in `PATH`.
+
+pub mod plugin;
+
+pub use plugin::{GitMetadataPlugin, GitMetadataSettings};
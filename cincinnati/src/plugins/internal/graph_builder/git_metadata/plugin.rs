@@ -0,0 +1,584 @@
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::graph_builder::openshift_secondary_metadata_parser::plugin::{
+    deserialize_directory_files, DeserializeDirectoryFilesErrorDiscriminants,
+};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+use tokio::sync::Mutex as FuturesMutex;
+
+pub static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_RELEASES_DIRECTORY: &str = "releases";
+static CHANNELS_KEY_SUFFIX: &str = "release.channels";
+
+/// Reference checked out if none is configured.
+static REMOTE_HEAD: &str = "HEAD";
+
+/// Precedence between metadata declared in git and metadata from image labels.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Precedence {
+    /// Declared metadata replaces label metadata.
+    #[default]
+    Git,
+    /// Declared metadata only fills in keys missing from the labels.
+    Labels,
+}
+
+/// Release declaration, as found in the metadata repository.
+#[derive(Debug, Deserialize)]
+pub struct ReleaseDeclaration {
+    pub version: String,
+
+    /// Channels of the release, left untouched if unset.
+    #[serde(default)]
+    pub channels: Option<Vec<String>>,
+
+    /// Metadata of the release, by full key.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct GitMetadataSettings {
+    /// URL of the metadata repository.
+    url: String,
+
+    /// Branch or tag to check out, the remote HEAD if unset.
+    reference: Option<String>,
+
+    /// Path to a file holding a token, sent as bearer authorization over HTTP.
+    auth_token_path: Option<PathBuf>,
+
+    /// Directory caching the checkout across runs and restarts.
+    cache_directory: PathBuf,
+
+    /// Directory of the release declarations, relative to the repository root.
+    #[default(DEFAULT_RELEASES_DIRECTORY.to_string())]
+    releases_directory: String,
+
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    key_prefix: String,
+
+    precedence: Precedence,
+}
+
+impl GitMetadataSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let settings: Self = commons::de::from_toml_named(cfg, GitMetadataPlugin::PLUGIN_NAME)?;
+
+        ensure!(!settings.url.is_empty(), "empty url");
+        ensure!(
+            settings
+                .reference
+                .as_ref()
+                .map_or(true, |reference| !reference.is_empty()),
+            "empty reference"
+        );
+        ensure!(
+            !settings.cache_directory.as_os_str().is_empty(),
+            "empty cache_directory"
+        );
+        ensure!(
+            !settings.releases_directory.is_empty(),
+            "empty releases_directory"
+        );
+        ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
+
+        Ok(Box::new(settings))
+    }
+}
+
+impl PluginSettings for GitMetadataSettings {
+    fn plugin_name(&self) -> &'static str {
+        GitMetadataPlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        let plugin = GitMetadataPlugin::try_new(self.clone())?;
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+/// Plugin.
+#[derive(CustomDebug)]
+pub struct GitMetadataPlugin {
+    settings: GitMetadataSettings,
+
+    #[debug(skip)]
+    auth_token: Option<String>,
+
+    /// Serializes runs, as they share the checkout.
+    #[debug(skip)]
+    checkout_lock: FuturesMutex<()>,
+}
+
+impl GitMetadataPlugin {
+    pub(crate) const PLUGIN_NAME: &'static str = "git-metadata";
+
+    /// Instantiate a new instance of `Self`.
+    pub fn try_new(settings: GitMetadataSettings) -> Fallible<Self> {
+        let auth_token = settings
+            .auth_token_path
+            .as_ref()
+            .map(|path| {
+                std::fs::read_to_string(path).context(format!("Reading auth token from {:?}", path))
+            })
+            .transpose()?
+            .and_then(|token| token.lines().next().map(|line| line.trim().to_owned()));
+
+        std::fs::create_dir_all(&settings.cache_directory).context(format!(
+            "Creating directory {:?}",
+            &settings.cache_directory
+        ))?;
+
+        Ok(Self {
+            settings,
+            auth_token,
+            checkout_lock: FuturesMutex::new(()),
+        })
+    }
+}
+
+/// Run git on the given checkout, returning its trimmed standard output.
+fn git(checkout: &Path, auth_token: Option<&str>, args: &[&str]) -> Fallible<String> {
+    let mut command = Command::new("git");
+
+    // Pin the repository, so that git never picks up an enclosing one.
+    command
+        .arg(format!("--git-dir={}", checkout.join(".git").display()))
+        .arg(format!("--work-tree={}", checkout.display()))
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0");
+
+    // Passed via the environment rather than the arguments, to keep the token
+    // out of the process list.
+    if let Some(token) = auth_token {
+        command
+            .env("GIT_CONFIG_COUNT", "1")
+            .env("GIT_CONFIG_KEY_0", "http.extraHeader")
+            .env(
+                "GIT_CONFIG_VALUE_0",
+                format!("Authorization: Bearer {}", token),
+            );
+    }
+
+    let output = command
+        .output()
+        .context(format!("Running git {}", args.join(" ")))?;
+    ensure!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Bring the cached checkout up to date, returning the checked out commit.
+///
+/// The remote is only fetched once its reference advances past the cached
+/// commit. Failures fall back to the cached checkout, if there is one.
+fn refresh_checkout(settings: &GitMetadataSettings, auth_token: Option<&str>) -> Fallible<String> {
+    let checkout = settings.cache_directory.as_path();
+    let reference = settings.reference.as_deref().unwrap_or(REMOTE_HEAD);
+
+    git(checkout, None, &["init", "--quiet"])?;
+    let cached = git(
+        checkout,
+        None,
+        &["rev-parse", "--verify", "--quiet", "HEAD^{commit}"],
+    )
+    .ok()
+    .filter(|commit| !commit.is_empty());
+
+    let update = git(
+        checkout,
+        auth_token,
+        &["ls-remote", &settings.url, reference],
+    )
+    .and_then(|refs| {
+        refs.split_whitespace()
+            .next()
+            .map(str::to_string)
+            .ok_or_else(|| format_err!("{} has no reference {}", settings.url, reference))
+    })
+    .and_then(|remote| {
+        if cached.as_ref() == Some(&remote) {
+            trace!("{} is up to date at {}", settings.url, remote);
+            return Ok(remote);
+        }
+
+        debug!("Fetching {} at {}", settings.url, remote);
+        git(
+            checkout,
+            auth_token,
+            &["fetch", "--quiet", "--depth", "1", &settings.url, reference],
+        )?;
+        git(
+            checkout,
+            None,
+            &["checkout", "--quiet", "--force", "--detach", "FETCH_HEAD"],
+        )?;
+        git(checkout, None, &["rev-parse", "HEAD"])
+    });
+
+    match (update, cached) {
+        (Ok(commit), _) => Ok(commit),
+        (Err(e), Some(cached)) => {
+            warn!(
+                "Could not update {}, using cached commit {}: {:#}",
+                settings.url, cached, e
+            );
+            Ok(cached)
+        }
+        (Err(e), None) => Err(e.context(format!(
+            "Checking out {} without a cached checkout",
+            settings.url
+        ))),
+    }
+}
+
+/// Merge release declarations into the metadata of the releases in the graph.
+///
+/// Declarations of releases which are not in the graph are skipped. Returns
+/// the number of merged declarations.
+pub fn merge_declarations(
+    graph: &mut cincinnati::Graph,
+    declarations: &[ReleaseDeclaration],
+    key_prefix: &str,
+    precedence: Precedence,
+) -> Fallible<usize> {
+    let channels_key = format!("{}.{}", key_prefix, CHANNELS_KEY_SUFFIX);
+    let mut merged = 0;
+
+    for declaration in declarations {
+        let release_id = match graph.find_by_version(&declaration.version) {
+            Some(release_id) => release_id,
+            None => {
+                debug!(
+                    "Skipping declaration of {}, which is not in the graph",
+                    declaration.version
+                );
+                continue;
+            }
+        };
+        let metadata = graph.get_metadata_as_ref_mut(&release_id)?;
+
+        let channels = declaration
+            .channels
+            .as_ref()
+            .map(|channels| (channels_key.clone(), channels.join(",")));
+        for (key, value) in declaration
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .chain(channels)
+        {
+            match precedence {
+                Precedence::Git => {
+                    metadata.insert(key, value);
+                }
+                Precedence::Labels => {
+                    metadata.entry(key).or_insert(value);
+                }
+            }
+        }
+
+        merged += 1;
+    }
+
+    Ok(merged)
+}
+
+#[async_trait]
+impl InternalPlugin for GitMetadataPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let _checkout_guard = self.checkout_lock.lock().await;
+
+        let commit = {
+            let settings = self.settings.clone();
+            let auth_token = self.auth_token.clone();
+            tokio::task::spawn_blocking(move || refresh_checkout(&settings, auth_token.as_deref()))
+                .await??
+        };
+        debug!("Using release declarations of commit {}", commit);
+
+        // Declarations are authoritative, so any malformed file is an error.
+        let disallowed_errors: HashSet<_> = vec![
+            DeserializeDirectoryFilesErrorDiscriminants::File,
+            DeserializeDirectoryFilesErrorDiscriminants::InvalidExtension,
+            DeserializeDirectoryFilesErrorDiscriminants::MissingExtension,
+            DeserializeDirectoryFilesErrorDiscriminants::Deserialize,
+        ]
+        .into_iter()
+        .collect();
+
+        let releases_dir = self
+            .settings
+            .cache_directory
+            .join(&self.settings.releases_directory);
+        let declarations: Vec<ReleaseDeclaration> = deserialize_directory_files(
+            &releases_dir,
+            regex::Regex::new("ya+ml")?,
+            &disallowed_errors,
+        )
+        .await
+        .context(format!(
+            "Reading release declarations from {:?}",
+            releases_dir
+        ))?;
+
+        let mut graph = io.graph;
+        let merged = merge_declarations(
+            &mut graph,
+            &declarations,
+            &self.settings.key_prefix,
+            self.settings.precedence,
+        )?;
+        debug!("Merged {} release declarations", merged);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::{ConcreteRelease, Graph, MapImpl};
+    use commons::testing::init_runtime;
+
+    fn channels_key() -> String {
+        format!("{}.{}", DEFAULT_KEY_PREFIX, CHANNELS_KEY_SUFFIX)
+    }
+
+    /// Graph with releases 4.6.1 and 4.6.2, labelled with the "candidate-4.6" channel.
+    fn labelled_graph() -> Graph {
+        let mut graph = Graph::default();
+
+        for version in &["4.6.1", "4.6.2"] {
+            let mut metadata = MapImpl::new();
+            metadata.insert(channels_key(), "candidate-4.6".to_string());
+            metadata.insert("url".to_string(), format!("https://label/{}", version));
+            graph
+                .add_release(cincinnati::Release::Concrete(ConcreteRelease {
+                    version: version.to_string(),
+                    payload: format!("image:{}", version),
+                    metadata,
+                }))
+                .unwrap();
+        }
+
+        graph
+    }
+
+    fn metadata(graph: &mut Graph, version: &str) -> MapImpl<String, String> {
+        let release_id = graph.find_by_version(version).unwrap();
+        graph.get_metadata_as_ref_mut(&release_id).unwrap().clone()
+    }
+
+    /// Run git in the given fixture repository.
+    fn fixture_git(repo: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .current_dir(repo)
+            .args(&["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// Commit the given release declarations to the fixture repository.
+    fn commit_declarations(repo: &Path, declarations: &[(&str, &str)]) {
+        let releases_dir = repo.join(DEFAULT_RELEASES_DIRECTORY);
+        std::fs::create_dir_all(&releases_dir).unwrap();
+        for (version, content) in declarations {
+            std::fs::write(releases_dir.join(format!("{}.yaml", version)), content).unwrap();
+        }
+
+        fixture_git(repo, &["add", "--all"]);
+        fixture_git(repo, &["commit", "--quiet", "--message", "update releases"]);
+    }
+
+    /// Create a fixture repository on the "main" branch.
+    fn fixture_repo() -> tempfile::TempDir {
+        let repo = tempfile::tempdir().unwrap();
+        fixture_git(repo.path(), &["init", "--quiet"]);
+        fixture_git(repo.path(), &["symbolic-ref", "HEAD", "refs/heads/main"]);
+        commit_declarations(
+            repo.path(),
+            &[(
+                "4.6.1",
+                "version: 4.6.1\nchannels: [stable-4.6, fast-4.6]\nmetadata:\n  url: https://git/4.6.1\n",
+            )],
+        );
+        repo
+    }
+
+    fn plugin(repo: &Path, cache_directory: &Path) -> GitMetadataPlugin {
+        let settings: GitMetadataSettings = toml::from_str(&format!(
+            r#"
+                url = "file://{}"
+                reference = "main"
+                cache_directory = {:?}
+            "#,
+            repo.display(),
+            cache_directory,
+        ))
+        .unwrap();
+
+        GitMetadataPlugin::try_new(settings).unwrap()
+    }
+
+    fn run(plugin: &GitMetadataPlugin) -> Fallible<Graph> {
+        let mut runtime = init_runtime()?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: labelled_graph(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+
+        Ok(io.graph)
+    }
+
+    #[test]
+    fn merge_precedence() -> Fallible<()> {
+        let declarations: Vec<ReleaseDeclaration> = vec![
+            serde_yaml::from_str(
+                "version: 4.6.1\nchannels: [stable-4.6]\nmetadata: {url: https://git, errata: RHBA-1}",
+            )?,
+            serde_yaml::from_str("version: 4.6.2\nmetadata: {errata: RHBA-2}")?,
+            serde_yaml::from_str("version: 9.9.9\nchannels: [stable-9.9]")?,
+        ];
+
+        let mut graph = labelled_graph();
+        let merged = merge_declarations(
+            &mut graph,
+            &declarations,
+            DEFAULT_KEY_PREFIX,
+            Precedence::Git,
+        )?;
+        assert_eq!(merged, 2);
+
+        let release = metadata(&mut graph, "4.6.1");
+        assert_eq!(release[&channels_key()], "stable-4.6");
+        assert_eq!(release["url"], "https://git");
+        assert_eq!(release["errata"], "RHBA-1");
+
+        // Releases declared without channels keep their labelled ones.
+        let release = metadata(&mut graph, "4.6.2");
+        assert_eq!(release[&channels_key()], "candidate-4.6");
+        assert_eq!(release["errata"], "RHBA-2");
+
+        let mut graph = labelled_graph();
+        merge_declarations(
+            &mut graph,
+            &declarations,
+            DEFAULT_KEY_PREFIX,
+            Precedence::Labels,
+        )?;
+
+        let release = metadata(&mut graph, "4.6.1");
+        assert_eq!(release[&channels_key()], "candidate-4.6");
+        assert_eq!(release["url"], "https://label/4.6.1");
+        assert_eq!(release["errata"], "RHBA-1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_when_remote_advances() -> Fallible<()> {
+        let repo = fixture_repo();
+        let cache = tempfile::tempdir()?;
+        let plugin = plugin(repo.path(), cache.path());
+
+        let mut graph = run(&plugin)?;
+        assert_eq!(
+            metadata(&mut graph, "4.6.1")[&channels_key()],
+            "stable-4.6,fast-4.6"
+        );
+        assert_eq!(
+            metadata(&mut graph, "4.6.2")[&channels_key()],
+            "candidate-4.6"
+        );
+
+        // Without a new remote commit, the cached checkout is used as is,
+        // which this local modification makes observable.
+        let cached_declaration = cache
+            .path()
+            .join(DEFAULT_RELEASES_DIRECTORY)
+            .join("4.6.1.yaml");
+        std::fs::write(&cached_declaration, "version: 4.6.1\nchannels: [cached]\n")?;
+        let mut graph = run(&plugin)?;
+        assert_eq!(metadata(&mut graph, "4.6.1")[&channels_key()], "cached");
+
+        commit_declarations(
+            repo.path(),
+            &[("4.6.2", "version: 4.6.2\nchannels: [fast-4.6]\n")],
+        );
+        let mut graph = run(&plugin)?;
+        assert_eq!(
+            metadata(&mut graph, "4.6.1")[&channels_key()],
+            "stable-4.6,fast-4.6"
+        );
+        assert_eq!(metadata(&mut graph, "4.6.2")[&channels_key()], "fast-4.6");
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_failure_falls_back_to_cache() -> Fallible<()> {
+        let repo = fixture_repo();
+        let repo_path = repo.path().to_path_buf();
+        let cache = tempfile::tempdir()?;
+        let plugin = plugin(&repo_path, cache.path());
+
+        run(&plugin)?;
+        repo.close()?;
+
+        let mut graph = run(&plugin)?;
+        assert_eq!(
+            metadata(&mut graph, "4.6.1")[&channels_key()],
+            "stable-4.6,fast-4.6"
+        );
+
+        // There is nothing to fall back to without a cached checkout.
+        let empty_cache = tempfile::tempdir()?;
+        run(&self::plugin(&repo_path, empty_cache.path())).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() {
+        for input in &[
+            "cache_directory = '/tmp/cache'",
+            "url = 'file:///repo'",
+            "url = 'file:///repo'\ncache_directory = '/tmp/cache'\nreference = ''",
+            "url = 'file:///repo'\ncache_directory = '/tmp/cache'\nprecedence = 'image'",
+        ] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                GitMetadataSettings::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use super::github_v3;
 use std::convert::{TryFrom, TryInto};
+use std::path::Path;
 
 use crate as cincinnati;
 
@@ -16,7 +17,26 @@ pub static DEFAULT_OUTPUT_WHITELIST: &[&str] = &[
 ];
 
 // Defines the key for placing the data directory path in the IO parameters
-pub static GRAPH_DATA_DIR_PARAM_KEY: &str = "io.openshift.upgrades.secondary_metadata.directory";
+pub static GRAPH_DATA_DIR_PARAM_KEY: &str = "__secondary_metadata.directory";
+
+// Defines the former key of the data directory path, still published for external plugins
+pub static LEGACY_GRAPH_DATA_DIR_PARAM_KEY: &str =
+    "io.openshift.upgrades.secondary_metadata.directory";
+
+/// Place the data directory path in the IO parameters.
+///
+/// The path is published under its former key too, which isn't reserved and
+/// thus lives among the client parameters.
+pub fn publish_data_dir(io: &mut InternalIO, data_dir: &Path) -> Fallible<()> {
+    let data_dir = data_dir
+        .to_str()
+        .ok_or_else(|| format_err!("data_dir cannot be converted to str"))?;
+    io.parameters
+        .insert_internal(GRAPH_DATA_DIR_PARAM_KEY, data_dir);
+    io.parameters
+        .insert_client(LEGACY_GRAPH_DATA_DIR_PARAM_KEY, data_dir);
+    Ok(())
+}
 
 lazy_static::lazy_static! {
     pub static ref DEFAULT_REFERENCE_BRANCH: Option<String> = Some(String::from("master"));
@@ -96,10 +116,10 @@ pub struct GithubOpenshiftSecondaryMetadataScraperSettings {
 impl GithubOpenshiftSecondaryMetadataScraperSettings {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let mut settings: Self = cfg
-            .clone()
-            .try_into()
-            .context(format!("Deserializing {:#?}", &cfg))?;
+        let mut settings: Self = commons::de::from_toml_named(
+            cfg,
+            GithubOpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME,
+        )?;
 
         ensure!(!settings.github_org.is_empty(), "empty github_org");
         ensure!(!settings.github_repo.is_empty(), "empty github_repo");
@@ -437,7 +457,11 @@ impl GithubOpenshiftSecondaryMetadataScraperPlugin {
 }
 
 impl PluginSettings for GithubOpenshiftSecondaryMetadataScraperSettings {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        GithubOpenshiftSecondaryMetadataScraperPlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
         let plugin = GithubOpenshiftSecondaryMetadataScraperPlugin::try_new(self.clone())?;
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
@@ -446,16 +470,10 @@ impl PluginSettings for GithubOpenshiftSecondaryMetadataScraperSettings {
 #[async_trait]
 impl InternalPlugin for GithubOpenshiftSecondaryMetadataScraperPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
 
     async fn run_internal(self: &Self, mut io: InternalIO) -> Fallible<InternalIO> {
-        io.parameters.insert(
-            GRAPH_DATA_DIR_PARAM_KEY.to_string(),
-            self.data_dir
-                .path()
-                .to_str()
-                .ok_or_else(|| format_err!("data_dir cannot be converted to str"))?
-                .to_string(),
-        );
+        publish_data_dir(&mut io, self.data_dir.path())?;
 
         let should_update = self
             .refresh_commit_wanted()
@@ -511,13 +529,14 @@ mod network_tests {
 
         debug!("Settings: {:#?}", &settings);
 
-        let plugin = settings.build_plugin(None)?;
+        let plugin = settings.build_plugin(&PluginMetrics::default())?;
 
         for _ in 0..2 {
             let _ = runtime.block_on(plugin.run(cincinnati::plugins::PluginIO::InternalIO(
                 InternalIO {
                     graph: Default::default(),
                     parameters: Default::default(),
+                    warnings: Default::default(),
                 },
             )))?;
 
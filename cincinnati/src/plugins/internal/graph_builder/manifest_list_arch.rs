@@ -0,0 +1,524 @@
+//! This plugin detects release payloads which are manifest lists.
+//!
+//! The payload of each release is expected as a registry pull spec by digest,
+//! `<registry>/<repository>@<digest>`, as recorded by the registry scraper.
+//! If the manifest at that digest is a manifest list, the pull spec of each
+//! platform's image is written to the release metadata at
+//! `<key_prefix>.<payloads_key_suffix>.<arch>`, and the architecture at
+//! `<key_prefix>.<arch_key_suffix>` is set to `multi`. Releases with a
+//! single-architecture payload are left unchanged.
+//!
+//! Registries are accessed with the same credentials settings as the registry
+//! scraper. Payloads whose manifest can't be fetched either fail the plugin or
+//! are skipped, according to `unreachable_manifest`.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::release_scrape_dockerv2::registry;
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use dkregistry::v2::manifest::Manifest;
+use futures::lock::Mutex as FuturesMutex;
+use futures::{StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_ARCH_KEY: &str = "release.arch";
+static DEFAULT_PAYLOADS_KEY: &str = "release.payloads";
+
+/// Architecture of releases with a manifest list payload.
+pub static MULTI_ARCH: &str = "multi";
+
+/// Default fetch concurrency.
+pub static DEFAULT_FETCH_CONCURRENCY: usize = 16;
+
+/// Handling of payloads whose manifest can't be fetched.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnreachableManifest {
+    /// Fail the plugin.
+    #[default]
+    Fail,
+    /// Leave the release unchanged.
+    Skip,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ManifestListArchSettings {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_ARCH_KEY.to_string())]
+    pub arch_key_suffix: String,
+
+    #[default(DEFAULT_PAYLOADS_KEY.to_string())]
+    pub payloads_key_suffix: String,
+
+    pub unreachable_manifest: UnreachableManifest,
+
+    #[default(DEFAULT_FETCH_CONCURRENCY)]
+    pub fetch_concurrency: usize,
+
+    /// Username for authenticating with the registries
+    #[default(Option::None)]
+    pub username: Option<String>,
+
+    /// Password for authenticating with the registries
+    #[default(Option::None)]
+    pub password: Option<String>,
+
+    /// File containing the credentials for authenticating with the registries.
+    /// Takes precedence over username and password
+    #[default(Option::None)]
+    pub credentials_path: Option<PathBuf>,
+}
+
+impl PluginSettings for ManifestListArchSettings {
+    fn plugin_name(&self) -> &'static str {
+        ManifestListArchPlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(ManifestListArchPlugin {
+            settings: self.clone(),
+            cache: Default::default(),
+        })))
+    }
+}
+
+impl ManifestListArchSettings {
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let mut settings: Self =
+            commons::de::from_toml_named(cfg, ManifestListArchPlugin::PLUGIN_NAME)?;
+
+        ensure!(!settings.key_prefix.is_empty(), "empty key prefix");
+        ensure!(
+            !settings.arch_key_suffix.is_empty(),
+            "empty arch-key suffix"
+        );
+        ensure!(
+            !settings.payloads_key_suffix.is_empty(),
+            "empty payloads-key suffix"
+        );
+        ensure!(settings.fetch_concurrency > 0, "zero fetch concurrency");
+        if let Some(credentials_path) = &settings.credentials_path {
+            if credentials_path == &PathBuf::from("") {
+                warn!("Settings contain an empty credentials path, setting to None");
+                settings.credentials_path = None;
+            }
+        }
+
+        Ok(Box::new(settings))
+    }
+}
+
+/// Platform digests of a manifest list, by architecture.
+type PlatformDigests = BTreeMap<String, String>;
+
+/// Payload pull spec, split into its parts.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PayloadRef {
+    registry: String,
+    repository: String,
+    digest: String,
+}
+
+impl PayloadRef {
+    /// Parse a `<registry>/<repository>@<digest>` pull spec.
+    ///
+    /// The registry may be prefixed with its scheme, as recorded for insecure
+    /// registries.
+    fn try_from_str(payload: &str) -> Fallible<Self> {
+        let (image, digest) = match payload.rfind('@') {
+            Some(pos) => (&payload[..pos], &payload[pos + 1..]),
+            None => bail!("payload '{}' is not referenced by digest", payload),
+        };
+        let host_start = image.find("://").map_or(0, |pos| pos + 3);
+        let repository_start = match image[host_start..].find('/') {
+            Some(pos) => host_start + pos,
+            None => bail!("payload '{}' has no repository", payload),
+        };
+
+        Ok(Self {
+            registry: image[..repository_start].to_string(),
+            repository: image[repository_start + 1..].to_string(),
+            digest: digest.to_string(),
+        })
+    }
+
+    /// Pull spec of the image with the given digest in the same repository.
+    fn with_digest(&self, digest: &str) -> String {
+        format!("{}/{}@{}", self.registry, self.repository, digest)
+    }
+}
+
+/// Manifest list detector.
+#[derive(CustomDebug)]
+pub struct ManifestListArchPlugin {
+    settings: ManifestListArchSettings,
+
+    /// Platform digests of already inspected payloads, `None` for single-arch ones.
+    ///
+    /// Manifests are immutable for a given digest, so entries never expire.
+    #[debug(skip)]
+    cache: FuturesMutex<HashMap<PayloadRef, Option<PlatformDigests>>>,
+}
+
+impl ManifestListArchPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "manifest-list-arch";
+
+    /// Fetch the platform digests of a payload, if it is a manifest list.
+    async fn platform_digests(&self, payload: &PayloadRef) -> Fallible<Option<PlatformDigests>> {
+        if let Some(cached) = self.cache.lock().await.get(payload) {
+            return Ok(cached.clone());
+        }
+
+        let registry = registry::Registry::try_from_str(&payload.registry)
+            .context(format!("Parsing {} as Registry", &payload.registry))?;
+
+        let (username, password) = match &self.settings.credentials_path {
+            Some(credentials_path) => {
+                registry::read_credentials(Some(credentials_path), &registry.host_port_string())
+                    .context(format!(
+                        "Reading registry credentials from {:?}",
+                        credentials_path
+                    ))?
+            }
+            None => (
+                self.settings.username.clone(),
+                self.settings.password.clone(),
+            ),
+        };
+
+        let registry_client = registry::new_registry_client(
+            &registry,
+            &payload.repository,
+            username.as_deref(),
+            password.as_deref(),
+        )
+        .await?;
+
+        let (manifest, _) = registry_client
+            .get_manifest_and_ref(&payload.repository, &payload.digest)
+            .await
+            .map_err(|e| format_err!("{}", e))?;
+
+        let digests = match manifest {
+            Manifest::ML(list) => Some(
+                list.manifests
+                    .into_iter()
+                    .map(|entry| (entry.platform.architecture, entry.digest))
+                    .collect(),
+            ),
+            _ => None,
+        };
+
+        self.cache
+            .lock()
+            .await
+            .insert(payload.clone(), digests.clone());
+
+        Ok(digests)
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for ManifestListArchPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        let payloads: Vec<(ReleaseId, String)> = graph
+            .find_by_fn_mut(|_| true)
+            .into_iter()
+            .filter_map(
+                |(release_id, _)| match graph.find_by_releaseid(&release_id) {
+                    Ok(cincinnati::Release::Concrete(release)) => {
+                        Some((release_id, release.payload.clone()))
+                    }
+                    _ => None,
+                },
+            )
+            .collect();
+
+        let detected: Vec<(ReleaseId, PayloadRef, PlatformDigests)> =
+            futures::stream::iter(payloads)
+                .map(|(release_id, payload)| async move {
+                    let result = match PayloadRef::try_from_str(&payload) {
+                        Ok(payload_ref) => self
+                            .platform_digests(&payload_ref)
+                            .await
+                            .map(|digests| digests.map(|digests| (payload_ref, digests))),
+                        Err(e) => Err(e),
+                    };
+
+                    match (result, self.settings.unreachable_manifest) {
+                        (Ok(detected), _) => Ok(detected
+                            .map(|(payload_ref, digests)| (release_id, payload_ref, digests))),
+                        (Err(e), UnreachableManifest::Skip) => {
+                            warn!("skipping payload '{}': {:#}", payload, e);
+                            Ok(None)
+                        }
+                        (Err(e), UnreachableManifest::Fail) => {
+                            Err(e.context(format!("failed to inspect payload '{}'", payload)))
+                        }
+                    }
+                })
+                .buffer_unordered(self.settings.fetch_concurrency)
+                .try_filter_map(|detected| async move { Ok(detected) })
+                .try_collect()
+                .await?;
+
+        let arch_key = format!(
+            "{}.{}",
+            self.settings.key_prefix, self.settings.arch_key_suffix
+        );
+        let payloads_key = format!(
+            "{}.{}",
+            self.settings.key_prefix, self.settings.payloads_key_suffix
+        );
+        for (release_id, payload_ref, digests) in detected {
+            let metadata = graph.get_metadata_as_ref_mut(&release_id)?;
+            for (arch, digest) in digests {
+                metadata.insert(
+                    format!("{}.{}", payloads_key, arch),
+                    payload_ref.with_digest(&digest),
+                );
+            }
+            metadata.insert(arch_key.clone(), MULTI_ARCH.to_string());
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::{ConcreteRelease, Graph, MapImpl};
+    use commons::testing::init_runtime;
+
+    static LIST_DIGEST: &str =
+        "sha256:1111111111111111111111111111111111111111111111111111111111111111";
+    static SINGLE_DIGEST: &str =
+        "sha256:2222222222222222222222222222222222222222222222222222222222222222";
+    static AMD64_DIGEST: &str =
+        "sha256:3333333333333333333333333333333333333333333333333333333333333333";
+    static ARM64_DIGEST: &str =
+        "sha256:4444444444444444444444444444444444444444444444444444444444444444";
+
+    /// Image configuration of the single-arch payload, at `CONFIG_DIGEST`.
+    static CONFIG: &str = r#"{"architecture":"amd64","os":"linux","config":{},"rootfs":{"type":"layers","diff_ids":[]}}"#;
+    static CONFIG_DIGEST: &str =
+        "sha256:dc570f145a7f2862c9ef3c30b8d6ae2feaceb0d364e4b2e08e67ae18815427d9";
+
+    fn mock_registry(repository: &str) -> Vec<mockito::Mock> {
+        let manifests = format!("/v2/{}/manifests", repository);
+        vec![
+            mockito::mock("GET", "/v2/")
+                .with_status(200)
+                .with_header("Docker-Distribution-API-Version", "registry/2.0")
+                .create(),
+            mockito::mock("GET", format!("{}/{}", manifests, LIST_DIGEST).as_str())
+                .with_status(200)
+                .with_header(
+                    "content-type",
+                    "application/vnd.docker.distribution.manifest.list.v2+json",
+                )
+                .with_header("Docker-Content-Digest", LIST_DIGEST)
+                .with_body(
+                    serde_json::json!({
+                        "schemaVersion": 2,
+                        "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+                        "manifests": [
+                            {
+                                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                                "size": 1,
+                                "digest": AMD64_DIGEST,
+                                "platform": { "architecture": "amd64", "os": "linux" }
+                            },
+                            {
+                                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                                "size": 1,
+                                "digest": ARM64_DIGEST,
+                                "platform": { "architecture": "arm64", "os": "linux" }
+                            }
+                        ]
+                    })
+                    .to_string(),
+                )
+                .create(),
+            mockito::mock("GET", format!("{}/{}", manifests, SINGLE_DIGEST).as_str())
+                .with_status(200)
+                .with_header(
+                    "content-type",
+                    "application/vnd.docker.distribution.manifest.v2+json",
+                )
+                .with_header("Docker-Content-Digest", SINGLE_DIGEST)
+                .with_body(
+                    serde_json::json!({
+                        "schemaVersion": 2,
+                        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                        "config": {
+                            "mediaType": "application/vnd.docker.container.image.v1+json",
+                            "size": CONFIG.len(),
+                            "digest": CONFIG_DIGEST
+                        },
+                        "layers": []
+                    })
+                    .to_string(),
+                )
+                .create(),
+            mockito::mock(
+                "GET",
+                format!("/v2/{}/blobs/{}", repository, CONFIG_DIGEST).as_str(),
+            )
+            .with_status(200)
+            .with_body(CONFIG)
+            .create(),
+        ]
+    }
+
+    fn build_graph(repository: &str, releases: &[(&str, &str)]) -> Graph {
+        let mut graph = Graph::default();
+        for (version, digest) in releases {
+            graph
+                .add_release(cincinnati::Release::Concrete(ConcreteRelease {
+                    version: version.to_string(),
+                    payload: format!("{}/{}@{}", mockito::server_url(), repository, digest),
+                    metadata: MapImpl::new(),
+                }))
+                .unwrap();
+        }
+        graph
+    }
+
+    fn run(plugin: ManifestListArchSettings, graph: Graph) -> Fallible<Graph> {
+        let mut runtime = init_runtime()?;
+        let plugin = ManifestListArchPlugin {
+            settings: plugin,
+            cache: Default::default(),
+        };
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph,
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+        Ok(io.graph)
+    }
+
+    fn metadata(graph: &Graph, version: &str) -> MapImpl<String, String> {
+        match graph.find_by_releaseid(&graph.find_by_version(version).unwrap()) {
+            Ok(cincinnati::Release::Concrete(release)) => release.metadata.clone(),
+            _ => panic!("release '{}' not found", version),
+        }
+    }
+
+    #[test]
+    fn detects_manifest_lists() -> Fallible<()> {
+        let repository = "test/detect";
+        let _mocks = mock_registry(repository);
+
+        let graph = run(
+            ManifestListArchSettings::default(),
+            build_graph(
+                repository,
+                &[("4.6.0", LIST_DIGEST), ("4.6.1", SINGLE_DIGEST)],
+            ),
+        )?;
+
+        let payload = |digest: &str| format!("{}/{}@{}", mockito::server_url(), repository, digest);
+        let expected: MapImpl<String, String> = vec![
+            (
+                format!("{}.{}.amd64", DEFAULT_KEY_PREFIX, DEFAULT_PAYLOADS_KEY),
+                payload(AMD64_DIGEST),
+            ),
+            (
+                format!("{}.{}.arm64", DEFAULT_KEY_PREFIX, DEFAULT_PAYLOADS_KEY),
+                payload(ARM64_DIGEST),
+            ),
+            (
+                format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_ARCH_KEY),
+                MULTI_ARCH.to_string(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(metadata(&graph, "4.6.0"), expected);
+
+        // Single-arch payloads are left unchanged.
+        assert!(metadata(&graph, "4.6.1").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unreachable_manifest() -> Fallible<()> {
+        let repository = "test/unreachable";
+        let _mocks = mock_registry(repository);
+        let missing = "sha256:5555555555555555555555555555555555555555555555555555555555555555";
+        let _missing = mockito::mock(
+            "GET",
+            format!("/v2/{}/manifests/{}", repository, missing).as_str(),
+        )
+        .with_status(404)
+        .create();
+        let releases = &[("4.6.0", LIST_DIGEST), ("4.6.2", missing)];
+
+        assert!(run(
+            ManifestListArchSettings::default(),
+            build_graph(repository, releases)
+        )
+        .is_err());
+
+        let graph = run(
+            ManifestListArchSettings {
+                unreachable_manifest: UnreachableManifest::Skip,
+                ..Default::default()
+            },
+            build_graph(repository, releases),
+        )?;
+        assert_eq!(
+            metadata(&graph, "4.6.0")[&format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_ARCH_KEY)],
+            MULTI_ARCH
+        );
+        assert!(metadata(&graph, "4.6.2").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_payload_ref() -> Fallible<()> {
+        let payload = PayloadRef::try_from_str(
+            "http://127.0.0.1:5000/openshift-release-dev/ocp-release@sha256:abc",
+        )?;
+        assert_eq!(payload.registry, "http://127.0.0.1:5000");
+        assert_eq!(payload.repository, "openshift-release-dev/ocp-release");
+        assert_eq!(payload.digest, "sha256:abc");
+        assert_eq!(
+            payload.with_digest("sha256:def"),
+            "http://127.0.0.1:5000/openshift-release-dev/ocp-release@sha256:def"
+        );
+
+        for invalid in &["quay.io/ocp-release:4.6.0", "quay.io@sha256:abc"] {
+            assert!(PayloadRef::try_from_str(invalid).is_err(), "{}", invalid);
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 //! Plugins specific to the graph-builder
 
 pub mod dkrv2_openshift_secondary_metadata_scraper;
+pub mod git_metadata;
 pub mod github_openshift_secondary_metadata_scraper;
+pub mod manifest_list_arch;
 pub mod openshift_secondary_metadata_parser;
 pub mod release_scrape_dockerv2;
 
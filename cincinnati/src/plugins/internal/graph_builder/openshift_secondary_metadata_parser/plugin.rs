@@ -1,6 +1,8 @@
 use crate as cincinnati;
 
-use self::cincinnati::plugins::internal::graph_builder::github_openshift_secondary_metadata_scraper::plugin::GRAPH_DATA_DIR_PARAM_KEY;
+use self::cincinnati::plugins::internal::graph_builder::github_openshift_secondary_metadata_scraper::plugin::{
+    GRAPH_DATA_DIR_PARAM_KEY, LEGACY_GRAPH_DATA_DIR_PARAM_KEY,
+};
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
@@ -121,7 +123,8 @@ pub struct OpenshiftSecondaryMetadataParserSettings {
 impl OpenshiftSecondaryMetadataParserSettings {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let settings: Self = cfg.try_into()?;
+        let settings: Self =
+            commons::de::from_toml_named(cfg, OpenshiftSecondaryMetadataParserPlugin::PLUGIN_NAME)?;
 
         ensure!(!settings.key_prefix.is_empty(), "empty key_prefix");
         ensure!(!settings.default_arch.is_empty(), "empty default_arch");
@@ -149,7 +152,11 @@ impl OpenshiftSecondaryMetadataParserPlugin {
 }
 
 impl PluginSettings for OpenshiftSecondaryMetadataParserSettings {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        OpenshiftSecondaryMetadataParserPlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
         let plugin = OpenshiftSecondaryMetadataParserPlugin::new(self.clone());
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
@@ -289,7 +296,12 @@ impl OpenshiftSecondaryMetadataParserPlugin {
     pub(crate) const PLUGIN_NAME: &'static str = "openshift-secondary-metadata-parse";
 
     fn get_data_directory(&self, io: &InternalIO) -> PathBuf {
-        if let Some(data_dir) = io.parameters.get(GRAPH_DATA_DIR_PARAM_KEY) {
+        // External scrapers may still publish the path under its former key.
+        if let Some(data_dir) = io
+            .parameters
+            .internal(GRAPH_DATA_DIR_PARAM_KEY)
+            .or_else(|| io.parameters.client(LEGACY_GRAPH_DATA_DIR_PARAM_KEY))
+        {
             PathBuf::from(data_dir)
         } else {
             self.settings.data_directory.clone()
@@ -578,6 +590,7 @@ impl OpenshiftSecondaryMetadataParserPlugin {
 #[async_trait]
 impl InternalPlugin for OpenshiftSecondaryMetadataParserPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
 
     async fn run_internal(self: &Self, mut io: InternalIO) -> Fallible<InternalIO> {
         let data_dir = self.get_data_directory(&io);
@@ -594,6 +607,7 @@ impl InternalPlugin for OpenshiftSecondaryMetadataParserPlugin {
 #[cfg(test)]
 mod tests {
     use super::OpenshiftSecondaryMetadataParserPlugin;
+    use super::{GRAPH_DATA_DIR_PARAM_KEY, LEGACY_GRAPH_DATA_DIR_PARAM_KEY};
 
     use crate as cincinnati;
 
@@ -661,6 +675,7 @@ mod tests {
                 .block_on(plugin.run_internal(InternalIO {
                     graph: graph_raw,
                     parameters: Default::default(),
+                    warnings: Default::default(),
                 }))
                 .context("Running plugin")
                 .unwrap();
@@ -682,6 +697,7 @@ mod tests {
                 .block_on(edge_add_remove_plugin.run_internal(InternalIO {
                     graph: graph_with_quay_metadata,
                     parameters: Default::default(),
+                    warnings: Default::default(),
                 }))
                 .context(
                     "Running fixture graph with quay metadata through the EdgeEAddRemovePlugin",
@@ -736,8 +752,39 @@ mod tests {
             .block_on(plugin.run_internal(InternalIO {
                 graph: Default::default(),
                 parameters: Default::default(),
+                warnings: Default::default(),
             }))
             .context("Running plugin")
             .unwrap_err();
     }
+
+    #[test]
+    fn data_directory_parameter_keys() {
+        let plugin = OpenshiftSecondaryMetadataParserPlugin::new(
+            toml::from_str(r#"data_directory = "/settings""#).unwrap(),
+        );
+        let data_directory = |params: &[(&str, &str)]| {
+            plugin.get_data_directory(&InternalIO {
+                graph: Default::default(),
+                parameters: params
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                warnings: Default::default(),
+            })
+        };
+
+        assert_eq!(data_directory(&[]), PathBuf::from("/settings"));
+        assert_eq!(
+            data_directory(&[(LEGACY_GRAPH_DATA_DIR_PARAM_KEY, "/legacy")]),
+            PathBuf::from("/legacy")
+        );
+        assert_eq!(
+            data_directory(&[
+                (GRAPH_DATA_DIR_PARAM_KEY, "/internal"),
+                (LEGACY_GRAPH_DATA_DIR_PARAM_KEY, "/legacy"),
+            ]),
+            PathBuf::from("/internal")
+        );
+    }
 }
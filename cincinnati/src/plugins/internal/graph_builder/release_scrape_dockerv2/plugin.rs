@@ -5,6 +5,7 @@ use crate as cincinnati;
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
+use regex::Regex;
 use std::convert::TryInto;
 
 /// Default registry to scrape.
@@ -26,9 +27,28 @@ pub struct ReleaseScrapeDockerv2Settings {
     #[default(DEFAULT_SCRAPE_REGISTRY.to_string())]
     pub registry: String,
 
+    /// Repository to scrape, unless discovering repositories.
     #[default(DEFAULT_SCRAPE_REPOSITORY.to_string())]
     pub repository: String,
 
+    /// Namespace in which to discover the repositories to scrape, through the quay API.
+    #[default(Option::None)]
+    pub repository_namespace: Option<String>,
+
+    /// Patterns of the discovered repository names to scrape; all if empty.
+    pub repository_include: Vec<String>,
+
+    /// Patterns of the discovered repository names not to scrape, even if included.
+    pub repository_exclude: Vec<String>,
+
+    /// Base URL of the quay API used to discover repositories.
+    #[default(quay::v1::DEFAULT_API_BASE.to_string())]
+    pub quay_api_base: String,
+
+    /// File containing the quay API token used to discover repositories.
+    #[default(Option::None)]
+    pub quay_api_credentials_path: Option<PathBuf>,
+
     /// Metadata key where to record the manifest-reference.
     #[default(DEFAULT_MANIFESTREF_KEY.to_string())]
     pub manifestref_key: String,
@@ -51,8 +71,12 @@ pub struct ReleaseScrapeDockerv2Settings {
 }
 
 impl PluginSettings for ReleaseScrapeDockerv2Settings {
-    fn build_plugin(&self, registry: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
-        let plugin = ReleaseScrapeDockerv2Plugin::try_new(self.clone(), None, registry)?;
+    fn plugin_name(&self) -> &'static str {
+        ReleaseScrapeDockerv2Plugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, metrics: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        let plugin = ReleaseScrapeDockerv2Plugin::try_new(self.clone(), None, metrics.registry())?;
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
 }
@@ -60,7 +84,8 @@ impl PluginSettings for ReleaseScrapeDockerv2Settings {
 impl ReleaseScrapeDockerv2Settings {
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let mut settings: Self = cfg.try_into()?;
+        let mut settings: Self =
+            commons::de::from_toml_named(cfg, ReleaseScrapeDockerv2Plugin::PLUGIN_NAME)?;
 
         ensure!(!settings.repository.is_empty(), "empty repository");
         ensure!(!settings.registry.is_empty(), "empty registry");
@@ -74,17 +99,75 @@ impl ReleaseScrapeDockerv2Settings {
                 settings.credentials_path = None;
             }
         }
+        match &settings.repository_namespace {
+            Some(namespace) => {
+                ensure!(!namespace.is_empty(), "empty repository_namespace");
+                RepositoryFilter::try_new(
+                    &settings.repository_include,
+                    &settings.repository_exclude,
+                )?;
+            }
+            None => ensure!(
+                settings.repository_include.is_empty() && settings.repository_exclude.is_empty(),
+                "repository filters without repository_namespace"
+            ),
+        }
 
         Ok(Box::new(settings))
     }
 }
 
+/// Filter of discovered repositories, by name.
+///
+/// Patterns must match whole repository names. Exclusions take precedence
+/// over inclusions, and all repositories are included without inclusions.
+#[derive(Clone, Debug)]
+pub struct RepositoryFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl RepositoryFilter {
+    /// Compile the given inclusion and exclusion patterns.
+    pub fn try_new(include: &[String], exclude: &[String]) -> Fallible<Self> {
+        fn compile(patterns: &[String]) -> Fallible<Vec<Regex>> {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Regex::new(&format!("^(?:{})$", pattern))
+                        .context(format!("invalid repository pattern '{}'", pattern))
+                })
+                .collect()
+        }
+
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Whether to scrape the repository with the given name.
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(name)))
+            && !self.exclude.iter().any(|re| re.is_match(name))
+    }
+}
+
+/// Discovery of the repositories to scrape in a quay namespace.
+#[derive(Debug)]
+struct RepositoryDiscovery {
+    client: quay::v1::Client,
+    namespace: String,
+    filter: RepositoryFilter,
+}
+
 /// Metadata fetcher for quay.io API.
 #[derive(CustomDebug)]
 pub struct ReleaseScrapeDockerv2Plugin {
     settings: ReleaseScrapeDockerv2Settings,
     registry: registry::Registry,
     cache: registry::cache::Cache,
+    discovery: Option<RepositoryDiscovery>,
 
     #[debug(skip)]
     graph_upstream_raw_releases: prometheus::IntGauge,
@@ -124,39 +207,99 @@ impl ReleaseScrapeDockerv2Plugin {
             settings.password = password;
         }
 
+        let discovery = match &settings.repository_namespace {
+            Some(namespace) => {
+                let token = match &settings.quay_api_credentials_path {
+                    Some(path) => Some(quay::read_credentials(path)?),
+                    None => None,
+                };
+                let client = quay::v1::Client::builder()
+                    .api_base(Some(settings.quay_api_base.clone()))
+                    .access_token(token)
+                    .build()?;
+                Some(RepositoryDiscovery {
+                    client,
+                    namespace: namespace.clone(),
+                    filter: RepositoryFilter::try_new(
+                        &settings.repository_include,
+                        &settings.repository_exclude,
+                    )?,
+                })
+            }
+            None => None,
+        };
+
         Ok(Self {
             settings,
             registry,
             cache: cache.unwrap_or_else(registry::cache::new),
+            discovery,
             graph_upstream_raw_releases,
         })
     }
+
+    /// Return the repositories to scrape.
+    ///
+    /// Without discovery, this is the configured repository only.
+    async fn repositories(&self) -> Fallible<Vec<String>> {
+        let discovery = match &self.discovery {
+            Some(discovery) => discovery,
+            None => return Ok(vec![self.settings.repository.clone()]),
+        };
+
+        let repositories = discovery
+            .client
+            .list_repositories(&discovery.namespace, |repo| {
+                discovery.filter.matches(&repo.name)
+            })
+            .await
+            .context(format!(
+                "failed to discover repositories in namespace '{}'",
+                discovery.namespace
+            ))?;
+        if repositories.is_empty() {
+            warn!(
+                "could not find any repository to scrape in namespace '{}'",
+                discovery.namespace
+            );
+        }
+
+        Ok(repositories
+            .iter()
+            .map(quay::v1::Repository::full_name)
+            .collect())
+    }
 }
 
 #[async_trait]
 impl InternalPlugin for ReleaseScrapeDockerv2Plugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
 
     async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
-        let releases = registry::fetch_releases(
-            &self.registry,
-            &self.settings.repository,
-            self.settings.username.as_ref().map(String::as_ref),
-            self.settings.password.as_ref().map(String::as_ref),
-            self.cache.clone(),
-            &self.settings.manifestref_key,
-            self.settings.fetch_concurrency,
-        )
-        .await
-        .context("failed to fetch all release metadata")?;
-
-        if releases.is_empty() {
-            warn!(
-                "could not find any releases in {}/{}",
-                &self.registry.host_port_string(),
-                &self.settings.repository
-            );
-        };
+        let mut releases = vec![];
+        for repository in self.repositories().await? {
+            let repository_releases = registry::fetch_releases(
+                &self.registry,
+                &repository,
+                self.settings.username.as_ref().map(String::as_ref),
+                self.settings.password.as_ref().map(String::as_ref),
+                self.cache.clone(),
+                &self.settings.manifestref_key,
+                self.settings.fetch_concurrency,
+            )
+            .await
+            .context("failed to fetch all release metadata")?;
+
+            if repository_releases.is_empty() {
+                warn!(
+                    "could not find any releases in {}/{}",
+                    &self.registry.host_port_string(),
+                    &repository
+                );
+            };
+            releases.extend(repository_releases);
+        }
 
         self.graph_upstream_raw_releases
             .set(releases.len().try_into()?);
@@ -166,10 +309,171 @@ impl InternalPlugin for ReleaseScrapeDockerv2Plugin {
         Ok(InternalIO {
             graph,
             parameters: io.parameters,
+            warnings: io.warnings,
         })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    /// Mock a page of the repository listing of a namespace.
+    fn mock_page(
+        namespace: &str,
+        page: Option<&str>,
+        names: &[&str],
+        next_page: Option<&str>,
+    ) -> mockito::Mock {
+        let query = match page {
+            Some(page) => format!("namespace={}&next_page={}", namespace, page),
+            None => format!("namespace={}", namespace),
+        };
+        let body = serde_json::json!({
+            "repositories": names
+                .iter()
+                .map(|name| serde_json::json!({ "namespace": namespace, "name": name }))
+                .collect::<Vec<_>>(),
+            "next_page": next_page,
+        });
+        mockito::mock("GET", "/api/v1/repository")
+            .match_query(mockito::Matcher::Exact(query))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(body.to_string())
+            .create()
+    }
+
+    fn discovery_plugin(
+        namespace: Option<&str>,
+        include: &[&str],
+        exclude: &[&str],
+    ) -> Fallible<ReleaseScrapeDockerv2Plugin> {
+        let to_strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let settings = ReleaseScrapeDockerv2Settings {
+            repository_namespace: namespace.map(str::to_string),
+            repository_include: to_strings(include),
+            repository_exclude: to_strings(exclude),
+            quay_api_base: format!("{}/api/v1/", mockito::server_url()),
+            ..Default::default()
+        };
+        ReleaseScrapeDockerv2Plugin::try_new(settings, None, None)
+    }
+
+    #[test]
+    fn discover_paginated_repositories() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let _mocks = vec![
+            mock_page("paginated", None, &["ocp-release", "origin"], Some("p2")),
+            mock_page(
+                "paginated",
+                Some("p2"),
+                &["ocp-release-nightly"],
+                Some("p3"),
+            ),
+            mock_page("paginated", Some("p3"), &["ocp-release-art"], None),
+        ];
+
+        let plugin = discovery_plugin(Some("paginated"), &["ocp-release.*"], &[])?;
+        assert_eq!(
+            runtime.block_on(plugin.repositories())?,
+            vec![
+                "paginated/ocp-release",
+                "paginated/ocp-release-nightly",
+                "paginated/ocp-release-art",
+            ]
+        );
+
+        // Without inclusions, all repositories are discovered.
+        let plugin = discovery_plugin(Some("paginated"), &[], &[])?;
+        assert_eq!(runtime.block_on(plugin.repositories())?.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn exclusions_take_precedence() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let _mock = mock_page(
+            "filtered",
+            None,
+            &[
+                "ocp-release",
+                "ocp-release-nightly",
+                "ocp-release-art",
+                "origin",
+            ],
+            None,
+        );
+
+        let plugin = discovery_plugin(
+            Some("filtered"),
+            &["ocp-release.*", "origin"],
+            &["ocp-release-nightly", "origin"],
+        )?;
+        assert_eq!(
+            runtime.block_on(plugin.repositories())?,
+            vec!["filtered/ocp-release", "filtered/ocp-release-art"]
+        );
+
+        // Patterns match whole names.
+        let filter = RepositoryFilter::try_new(&["release".to_string()], &[])?;
+        assert!(filter.matches("release"));
+        assert!(!filter.matches("ocp-release"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn discovery_failure() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+        let _mock = mockito::mock("GET", "/api/v1/repository")
+            .match_query(mockito::Matcher::Exact("namespace=broken".to_string()))
+            .with_status(500)
+            .create();
+
+        let plugin = discovery_plugin(Some("broken"), &[], &[])?;
+        let err = runtime.block_on(plugin.repositories()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to discover repositories in namespace 'broken'"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fallback_without_discovery() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+
+        let plugin = discovery_plugin(None, &[], &[])?;
+        assert_eq!(
+            runtime.block_on(plugin.repositories())?,
+            vec![DEFAULT_SCRAPE_REPOSITORY.to_string()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() {
+        for input in &[
+            "repository_include = ['ocp-release.*']",
+            "repository_namespace = ''",
+            "repository_namespace = 'openshift-release-dev'\nrepository_exclude = ['(']",
+        ] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                ReleaseScrapeDockerv2Settings::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "test-net")]
 mod network_tests;
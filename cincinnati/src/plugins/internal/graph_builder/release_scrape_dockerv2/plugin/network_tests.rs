@@ -49,6 +49,7 @@ fn scrape_private_with_credentials_must_succeed() -> Fallible<()> {
         .block_on(plugin.run_internal(InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         }))?
         .graph;
 
@@ -131,6 +132,7 @@ fn scrape_private_without_credentials_must_fail() -> Fallible<()> {
         .block_on(plugin.run_internal(InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         }))
         .unwrap_err();
 
@@ -166,6 +168,7 @@ fn scrape_public_with_no_release_metadata_must_not_error() -> Fallible<()> {
         .block_on(plugin.run_internal(InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         }))
         .context("should not error on emtpy repo")?
         .graph;
@@ -206,6 +209,7 @@ fn scrape_public_with_first_empty_tag_must_succeed() -> Fallible<()> {
         .block_on(plugin.run_internal(InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         }))?
         .graph;
 
@@ -294,6 +298,7 @@ fn scrape_public_must_succeed_with_various_registry_urls(registry: &str) {
         .block_on(plugin.run_internal(InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         }))
         .unwrap()
         .graph;
@@ -378,6 +383,7 @@ fn scrape_public_with_cyclic_metadata_fails() -> Fallible<()> {
         .block_on(plugin.run_internal(InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         }))
         .expect_err("create_graph succeeded despite cyclic metadata");
 
@@ -420,6 +426,7 @@ fn scrape_public_multiarch_manual_succeeds() -> Fallible<()> {
         .block_on(plugin.run_internal(InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         }))?
         .graph;
 
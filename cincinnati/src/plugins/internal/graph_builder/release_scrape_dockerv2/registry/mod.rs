@@ -17,6 +17,7 @@ use crate as cincinnati;
 use self::cincinnati::plugins::internal::graph_builder::release::Metadata;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
 
+use commons::metrics::{record_cache_lookup, CacheOutcome};
 use flate2::read::GzDecoder;
 use futures::lock::Mutex as FuturesMutex;
 use futures::prelude::*;
@@ -39,6 +40,9 @@ pub mod cache {
     use std::sync::Arc;
     use tokio::sync::RwLock as FuturesRwLock;
 
+    /// The name of the cache in lookup metrics
+    pub static CACHE_NAME: &str = "release_metadata";
+
     /// The key type of the cache
     type Key = String;
 
@@ -367,6 +371,12 @@ async fn lookup_or_fetch(
         cache.read().await.get(&manifestref).map(Clone::clone)
     };
 
+    let outcome = match cached_metadata {
+        Some(_) => CacheOutcome::Hit,
+        None => CacheOutcome::Miss,
+    };
+    record_cache_lookup(cache::CACHE_NAME, outcome);
+
     let metadata = match cached_metadata {
         Some(cached_metadata) => {
             trace!(
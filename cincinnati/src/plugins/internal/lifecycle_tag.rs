@@ -0,0 +1,462 @@
+//! This plugin tags releases with their support lifecycle state.
+//!
+//! The state of each release is derived from a list of rules, mapping semver
+//! ranges to one of `supported`, `maintenance` or `eol`, and written to the
+//! release metadata at `<key_prefix>.<key_suffix>`. Releases which match no
+//! rule are left untagged. Releases in the `eol` state may optionally be
+//! removed from the graph.
+//!
+//! If a release matches more than one rule, the rule is chosen according to
+//! the configured `resolution`:
+//! * `first-match`: the first matching rule in configuration order wins.
+//! * `most-specific`: the matching rule with the tightest bounds around the
+//!   release wins. Ranges bounded on both sides beat half-open ones, then the
+//!   higher lower bound and finally the lower upper bound decide. Remaining
+//!   ties are resolved by configuration order.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::versions::parse_release_version;
+
+use semver::{Version, VersionReq};
+use std::cmp::Ordering;
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_LIFECYCLE_KEY: &str = "release.lifecycle";
+
+/// Support lifecycle state of a release.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LifecycleState {
+    Supported,
+    Maintenance,
+    Eol,
+}
+
+impl LifecycleState {
+    /// Metadata value for this state.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LifecycleState::Supported => "supported",
+            LifecycleState::Maintenance => "maintenance",
+            LifecycleState::Eol => "eol",
+        }
+    }
+}
+
+/// Lifecycle state assigned to a range of releases.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LifecycleRule {
+    /// Releases this rule applies to.
+    pub versions: VersionReq,
+    /// Lifecycle state of the matching releases.
+    pub state: LifecycleState,
+}
+
+/// Resolution strategy for releases matching more than one rule.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleResolution {
+    #[default]
+    FirstMatch,
+    MostSpecific,
+}
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct LifecycleTagPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_LIFECYCLE_KEY.to_string())]
+    pub key_suffix: String,
+
+    pub rules: Vec<LifecycleRule>,
+
+    pub resolution: RuleResolution,
+
+    /// Whether to remove releases in the `eol` state.
+    pub remove_eol: bool,
+}
+
+impl PluginSettings for LifecycleTagPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl LifecycleTagPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "lifecycle-tag";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty lifecycle-key prefix");
+        ensure!(!plugin.key_suffix.is_empty(), "empty lifecycle-key suffix");
+        ensure!(!plugin.rules.is_empty(), "no lifecycle rules configured");
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Determine the lifecycle state of the given release version.
+    fn state_for(&self, version: &Version) -> Option<LifecycleState> {
+        let mut matching = self
+            .rules
+            .iter()
+            .filter(|rule| rule.versions.matches(version));
+
+        let rule = match self.resolution {
+            RuleResolution::FirstMatch => matching.next(),
+            RuleResolution::MostSpecific => matching
+                .fold(None, |best: Option<(&LifecycleRule, Bounds)>, rule| {
+                    let bounds = range_bounds(&rule.versions, version);
+                    let more_specific = best.as_ref().map_or(true, |(_, best_bounds)| {
+                        compare_specificity(&bounds, best_bounds) == Ordering::Greater
+                    });
+                    if more_specific {
+                        Some((rule, bounds))
+                    } else {
+                        best
+                    }
+                })
+                .map(|(rule, _)| rule),
+        };
+
+        rule.map(|rule| rule.state)
+    }
+}
+
+/// Lower and upper bound of a version range; `None` means unbounded.
+type Bounds = (Option<Version>, Option<Version>);
+
+/// Compute the bounds of the range in `req` which matches `version`.
+///
+/// `VersionReq` does not expose its comparators, so they are recovered from
+/// its normalized string representation, e.g. `>=4.6.0, <4.7.0 || =4.8.1`.
+fn range_bounds(req: &VersionReq, version: &Version) -> Bounds {
+    let req = req.to_string();
+    let range = req
+        .split(" || ")
+        .find(|range| {
+            VersionReq::parse(range)
+                .map(|req| req.matches(version))
+                .unwrap_or(false)
+        })
+        .unwrap_or_default();
+
+    let mut lower: Option<Version> = None;
+    let mut upper: Option<Version> = None;
+    for predicate in range.split(", ") {
+        let split = match predicate.find(|c: char| c.is_ascii_digit()) {
+            Some(split) => split,
+            None => continue,
+        };
+        let (op, bound) = predicate.split_at(split);
+        let bound = match parse_release_version(bound) {
+            Ok(bound) => bound,
+            Err(_) => continue,
+        };
+
+        if op.starts_with('>') || op == "=" {
+            lower = std::cmp::max(lower, Some(bound.clone()));
+        }
+        if op.starts_with('<') || op == "=" {
+            upper = Some(match upper {
+                Some(upper) => std::cmp::min(upper, bound),
+                None => bound,
+            });
+        }
+    }
+
+    (lower, upper)
+}
+
+/// Order bounds by specificity, `Ordering::Greater` meaning `a` is more specific than `b`.
+fn compare_specificity(a: &Bounds, b: &Bounds) -> Ordering {
+    let bounded = |(lower, upper): &Bounds| lower.is_some() && upper.is_some();
+
+    bounded(a)
+        .cmp(&bounded(b))
+        .then_with(|| a.0.cmp(&b.0))
+        .then_with(|| match (&a.1, &b.1) {
+            (Some(a), Some(b)) => b.cmp(a),
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (None, None) => Ordering::Equal,
+        })
+}
+
+#[async_trait]
+impl InternalPlugin for LifecycleTagPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let key = format!("{}.{}", self.key_prefix, self.key_suffix);
+
+        let to_remove = graph
+            .find_by_fn_mut(|release| {
+                let concrete_release = match release {
+                    cincinnati::Release::Concrete(concrete_release) => concrete_release,
+                    cincinnati::Release::Abstract(_) => return false,
+                };
+
+                let version = match parse_release_version(&concrete_release.version) {
+                    Ok(version) => version,
+                    Err(e) => {
+                        warn!("not tagging release: {}", e);
+                        return false;
+                    }
+                };
+
+                let state = match self.state_for(&version) {
+                    Some(state) => state,
+                    None => return false,
+                };
+
+                trace!(
+                    "tagging '{}' as '{}'",
+                    concrete_release.version,
+                    state.as_str()
+                );
+                concrete_release
+                    .metadata
+                    .insert(key.clone(), state.as_str().to_string());
+
+                self.remove_eol && state == LifecycleState::Eol
+            })
+            .into_iter()
+            .map(|(release_id, version)| {
+                trace!("queuing '{}' for removal", version);
+                release_id
+            })
+            .collect();
+
+        let removed = graph.remove_releases(to_remove);
+        trace!("removed {} releases", removed);
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_versioned_graph, run_internal_plugin};
+    use cincinnati::{Graph, MapImpl};
+
+    fn build_graph(versions: &[&str]) -> Graph {
+        generate_versioned_graph(
+            "image",
+            versions
+                .iter()
+                .map(|version| (*version, MapImpl::new()))
+                .collect(),
+            &[],
+        )
+    }
+
+    fn plugin(config: &str) -> LifecycleTagPlugin {
+        toml::from_str(config).unwrap()
+    }
+
+    fn run(plugin: LifecycleTagPlugin, graph: Graph) -> Fallible<Graph> {
+        Ok(run_internal_plugin(&plugin, graph, Default::default())
+            .context("plugin run failed")?
+            .graph)
+    }
+
+    fn tagged(graph: &Graph, state: &str) -> Vec<String> {
+        let mut versions: Vec<String> = graph
+            .find_by_metadata_pair(
+                &format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_LIFECYCLE_KEY),
+                state,
+            )
+            .into_iter()
+            .map(|(_, version)| version)
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    static RULES: &str = r#"
+        [[rules]]
+        versions = ">=4.6.0"
+        state = "supported"
+
+        [[rules]]
+        versions = ">=4.4.0, <4.6.0"
+        state = "maintenance"
+
+        [[rules]]
+        versions = "<4.4.0"
+        state = "eol"
+    "#;
+
+    #[test]
+    fn tags_each_state() -> Fallible<()> {
+        let graph = run(
+            plugin(RULES),
+            build_graph(&["4.3.9", "4.3.9.1", "4.4.0", "4.5.3", "4.6.0", "4.7.1+amd64"]),
+        )?;
+
+        assert_eq!(tagged(&graph, "supported"), vec!["4.6.0", "4.7.1+amd64"]);
+        assert_eq!(tagged(&graph, "maintenance"), vec!["4.4.0", "4.5.3"]);
+        assert_eq!(tagged(&graph, "eol"), vec!["4.3.9", "4.3.9.1"]);
+        assert_eq!(graph.releases_count(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn removes_eol() -> Fallible<()> {
+        let mut plugin = plugin(RULES);
+        plugin.remove_eol = true;
+
+        let graph = run(plugin, build_graph(&["4.3.9", "4.5.3", "4.6.0"]))?;
+
+        assert_eq!(graph.releases_count(), 2);
+        assert!(graph.find_by_version("4.3.9").is_none());
+        assert!(tagged(&graph, "eol").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_unmatched_untagged() -> Fallible<()> {
+        let plugin = plugin(
+            r#"
+            [[rules]]
+            versions = ">=4.6.0"
+            state = "supported"
+            "#,
+        );
+
+        let graph = run(plugin, build_graph(&["4.5.0", "4.6.0"]))?;
+
+        assert_eq!(tagged(&graph, "supported"), vec!["4.6.0"]);
+        assert!(graph
+            .find_by_metadata_key(&format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_LIFECYCLE_KEY))
+            .iter()
+            .all(|(_, version, _)| version != "4.5.0"));
+
+        Ok(())
+    }
+
+    static OVERLAPPING_RULES: &str = r#"
+        [[rules]]
+        versions = ">=4.0.0"
+        state = "supported"
+
+        [[rules]]
+        versions = ">=4.5.0"
+        state = "maintenance"
+
+        [[rules]]
+        versions = "~4.5.2"
+        state = "eol"
+
+        [[rules]]
+        versions = "=4.5.3"
+        state = "supported"
+    "#;
+
+    #[test]
+    fn overlapping_first_match() -> Fallible<()> {
+        let plugin = plugin(OVERLAPPING_RULES);
+        assert_eq!(plugin.resolution, RuleResolution::FirstMatch);
+
+        let graph = run(plugin, build_graph(&["4.4.0", "4.5.2", "4.5.3", "4.6.0"]))?;
+
+        assert_eq!(
+            tagged(&graph, "supported"),
+            vec!["4.4.0", "4.5.2", "4.5.3", "4.6.0"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_most_specific() -> Fallible<()> {
+        let plugin = plugin(&format!(
+            "resolution = \"most-specific\"\n{}",
+            OVERLAPPING_RULES
+        ));
+
+        let graph = run(
+            plugin,
+            build_graph(&["4.4.0", "4.5.2", "4.5.3", "4.5.4", "4.6.0"]),
+        )?;
+
+        assert_eq!(tagged(&graph, "supported"), vec!["4.4.0", "4.5.3"]);
+        assert_eq!(tagged(&graph, "maintenance"), vec!["4.6.0"]);
+        assert_eq!(tagged(&graph, "eol"), vec!["4.5.2", "4.5.4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn specificity_order() {
+        let bounds = |req: &str, version: &str| {
+            range_bounds(
+                &VersionReq::parse(req).unwrap(),
+                &Version::parse(version).unwrap(),
+            )
+        };
+
+        let wide = bounds(">=4.0.0", "4.5.3");
+        let narrow = bounds(">=4.5.0", "4.5.3");
+        let closed = bounds(">=4.0.0, <5.0.0", "4.5.3");
+        let exact = bounds("=4.5.3", "4.5.3");
+        let alternatives = bounds("=4.4.0 || >=4.5.0, <4.6.0", "4.5.3");
+
+        assert_eq!(compare_specificity(&narrow, &wide), Ordering::Greater);
+        assert_eq!(compare_specificity(&closed, &narrow), Ordering::Greater);
+        assert_eq!(compare_specificity(&exact, &closed), Ordering::Greater);
+        assert_eq!(
+            compare_specificity(&alternatives, &closed),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_specificity(&exact, &alternatives),
+            Ordering::Greater
+        );
+        assert_eq!(compare_specificity(&wide, &wide), Ordering::Equal);
+    }
+
+    #[test]
+    fn deserialize_config_validation() {
+        for input in &[
+            "",
+            "key_prefix = ''\n[[rules]]\nversions = '*'\nstate = 'eol'",
+            "[[rules]]\nversions = '*'\nstate = 'unknown'",
+            "resolution = 'last-match'\n[[rules]]\nversions = '*'\nstate = 'eol'",
+        ] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                LifecycleTagPlugin::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+    }
+}
@@ -3,15 +3,51 @@
 //! The fetch process is all or nothing, i.e. it fails in these cases:
 //! * a Release doesn't contain the manifestref in its metadata
 //! * the dynamic metadata can't be fetched for a single manifestref
+//!
+//! By default the labels of all releases are fetched one after the other,
+//! and applied to the graph once all of them are. For repositories with
+//! thousands of tags, the `streamed` apply mode fetches labels concurrently
+//! and applies each set as soon as it is fetched, without buffering them.
+//!
+//! The age of the API credentials, from the modification time of their file,
+//! is exported before each scrape. Past the optional warning threshold and
+//! maximum age, a warning then an error is logged, so that expiring tokens
+//! are rotated before scrapes start failing. With `reload_credentials`, the
+//! file is read again before each scrape, keeping the previous token if it
+//! can't be read.
+//!
+//! API requests carry the `user_agent` setting as `User-Agent` header,
+//! `cincinnati/<version>` by default.
 
 use crate as cincinnati;
 
 use self::cincinnati::plugins::prelude::*;
 use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::DEFAULT_USER_AGENT;
+use futures::stream::{self, Stream, StreamExt};
+use prometheus::Gauge;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
 pub static DEFAULT_QUAY_LABEL_FILTER: &str = "io.openshift.upgrades.graph";
 pub static DEFAULT_QUAY_MANIFESTREF_KEY: &str = "io.openshift.upgrades.graph.release.manifestref";
 pub static DEFAULT_QUAY_REPOSITORY: &str = "openshift";
+pub const DEFAULT_FETCH_CONCURRENCY: usize = 16;
+
+/// Labels fetched for a release, along with its ID and version.
+type FetchedLabels = (Vec<(String, String)>, (ReleaseId, String));
+
+/// How fetched labels are applied to the graph.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplyMode {
+    /// Fetch the labels of all releases sequentially, then apply them.
+    #[default]
+    Buffered,
+    /// Fetch labels concurrently, and apply each set as soon as it is fetched.
+    Streamed,
+}
 
 /// Plugin settings.
 #[derive(Clone, Debug, Deserialize, SmartDefault)]
@@ -26,11 +62,163 @@ struct QuayMetadataSettings {
     #[default(DEFAULT_QUAY_REPOSITORY.to_string())]
     repository: String,
 
-    #[default(DEFAULT_QUAY_LABEL_FILTER.to_string())]
-    label_filter: String,
+    #[default(vec![DEFAULT_QUAY_LABEL_FILTER.to_string()])]
+    label_filters: Vec<String>,
 
     #[default(DEFAULT_QUAY_MANIFESTREF_KEY.to_string())]
     manifestref_key: String,
+
+    apply_mode: ApplyMode,
+
+    #[default(DEFAULT_FETCH_CONCURRENCY)]
+    fetch_concurrency: usize,
+
+    #[default(DEFAULT_USER_AGENT.to_string())]
+    user_agent: String,
+
+    /// Maximum age of the credentials, in seconds.
+    credentials_max_age: Option<u64>,
+
+    /// Age of the credentials past which a warning is logged, in seconds.
+    ///
+    /// Defaults to three quarters of `credentials_max_age`.
+    credentials_warning_age: Option<u64>,
+
+    reload_credentials: bool,
+}
+
+impl QuayMetadataSettings {
+    /// Age of the credentials past which a warning is logged.
+    fn warning_age(&self) -> Option<Duration> {
+        self.credentials_warning_age
+            .or_else(|| self.credentials_max_age.map(|max_age| max_age / 4 * 3))
+            .map(Duration::from_secs)
+    }
+}
+
+/// Age of the credentials, relative to the configured thresholds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CredentialsAge {
+    /// Below the warning threshold.
+    Fresh,
+    /// Past the warning threshold.
+    Expiring,
+    /// Past the maximum age.
+    Expired,
+}
+
+/// Token loaded from the credentials file.
+struct LoadedCredentials {
+    token: String,
+    /// Modification time of the file when it was loaded.
+    modified: SystemTime,
+}
+
+impl LoadedCredentials {
+    fn load(path: &Path) -> Fallible<Self> {
+        let token = quay::read_credentials(path)?;
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .context(format!("could not inspect '{}'", path.display()))?;
+        Ok(Self { token, modified })
+    }
+}
+
+/// Quay API credentials, along with their age policy.
+#[derive(CustomDebug)]
+struct QuayCredentials {
+    path: PathBuf,
+    reload: bool,
+    max_age: Option<Duration>,
+    warning_age: Option<Duration>,
+
+    #[debug(skip)]
+    loaded: Mutex<LoadedCredentials>,
+
+    /// The optional metric for the age of the credentials
+    #[debug(skip)]
+    age_seconds: Option<Gauge>,
+}
+
+impl QuayCredentials {
+    fn try_new(path: PathBuf) -> Fallible<Self> {
+        let loaded = LoadedCredentials::load(&path)?;
+        Ok(Self {
+            path,
+            reload: false,
+            max_age: None,
+            warning_age: None,
+            loaded: Mutex::new(loaded),
+            age_seconds: None,
+        })
+    }
+
+    /// Return the token, reading the file again first if `reload` is set.
+    ///
+    /// The previous token is kept if the file can't be read.
+    fn token(&self) -> Fallible<String> {
+        let mut loaded = self
+            .loaded
+            .lock()
+            .map_err(|_| format_err!("poisoned lock on quay API credentials"))?;
+        if self.reload {
+            match LoadedCredentials::load(&self.path) {
+                Ok(reloaded) => {
+                    if reloaded.token != loaded.token {
+                        info!(
+                            "reloaded quay API credentials from '{}'",
+                            self.path.display()
+                        );
+                    }
+                    *loaded = reloaded;
+                }
+                Err(e) => warn!(
+                    "could not reload quay API credentials, keeping the previous ones: {:#}",
+                    e
+                ),
+            }
+        }
+        Ok(loaded.token.clone())
+    }
+
+    /// Return the age of the loaded credentials at `now`.
+    fn age(&self, now: SystemTime) -> Fallible<Duration> {
+        let loaded = self
+            .loaded
+            .lock()
+            .map_err(|_| format_err!("poisoned lock on quay API credentials"))?;
+        // A modification time in the future counts as fresh.
+        Ok(now.duration_since(loaded.modified).unwrap_or_default())
+    }
+
+    /// Export the age of the credentials at `now`, and warn if they are about to expire.
+    fn check_age(&self, now: SystemTime) -> Fallible<CredentialsAge> {
+        let age = self.age(now)?;
+        if let Some(gauge) = &self.age_seconds {
+            gauge.set(age.as_secs_f64());
+        }
+
+        match (self.max_age, self.warning_age) {
+            (Some(max_age), _) if age > max_age => {
+                error!(
+                    "quay API credentials from '{}' are {}s old, past the maximum age of {}s",
+                    self.path.display(),
+                    age.as_secs(),
+                    max_age.as_secs()
+                );
+                Ok(CredentialsAge::Expired)
+            }
+            (_, Some(warning_age)) if age > warning_age => {
+                warn!(
+                    "quay API credentials from '{}' are {}s old, rotate them before they expire",
+                    self.path.display(),
+                    age.as_secs()
+                );
+                Ok(CredentialsAge::Expiring)
+            }
+            _ => Ok(CredentialsAge::Fresh),
+        }
+    }
 }
 
 /// Metadata fetcher for quay.io API.
@@ -38,20 +226,39 @@ struct QuayMetadataSettings {
 pub struct QuayMetadataFetchPlugin {
     client: quay::v1::Client,
     repo: String,
-    label_filter: String,
+    label_filters: Vec<String>,
     manifestref_key: String,
+    apply_mode: ApplyMode,
+    fetch_concurrency: usize,
+    credentials: Option<QuayCredentials>,
 }
 
 impl PluginSettings for QuayMetadataSettings {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
+    fn plugin_name(&self) -> &'static str {
+        QuayMetadataFetchPlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, metrics: &PluginMetrics) -> Fallible<BoxedPlugin> {
         let cfg = self.clone();
-        let plugin = QuayMetadataFetchPlugin::try_new(
+        let warning_age = cfg.warning_age();
+        let mut plugin = QuayMetadataFetchPlugin::try_new(
             cfg.repository,
-            cfg.label_filter,
+            cfg.label_filters,
             cfg.manifestref_key,
             cfg.api_credentials_path,
             cfg.api_base,
-        )?;
+        )?
+        .with_apply_mode(cfg.apply_mode, cfg.fetch_concurrency)
+        .with_user_agent(cfg.user_agent);
+        if let Some(credentials) = plugin.credentials.as_mut() {
+            credentials.reload = cfg.reload_credentials;
+            credentials.max_age = cfg.credentials_max_age.map(Duration::from_secs);
+            credentials.warning_age = warning_age;
+            credentials.age_seconds = Some(metrics.gauge(
+                "quay_credentials_age_seconds",
+                "Age of the quay API credentials, in seconds",
+            )?);
+        }
         Ok(new_plugin!(InternalPluginWrapper(plugin)))
     }
 }
@@ -60,51 +267,211 @@ impl QuayMetadataFetchPlugin {
     /// Plugin name, for configuration.
     pub const PLUGIN_NAME: &'static str = "quay-metadata";
 
+    /// Settings migrations.
+    ///
+    /// * version 2: the single `label_filter` became the `label_filters` list.
+    pub fn settings_migrations() -> SettingsMigrations {
+        SettingsMigrations::new(Self::PLUGIN_NAME).then(|mut table| {
+            if let Some(label_filter) = table.remove("label_filter") {
+                ensure!(
+                    !table.contains_key("label_filters"),
+                    "both label_filter and label_filters are set"
+                );
+                table.insert(
+                    "label_filters".to_string(),
+                    toml::Value::Array(vec![label_filter]),
+                );
+            }
+            Ok(table)
+        })
+    }
+
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let settings: QuayMetadataSettings = cfg.try_into()?;
+        Ok(Box::new(Self::parse_settings(cfg)?))
+    }
+
+    fn parse_settings(cfg: toml::Value) -> Fallible<QuayMetadataSettings> {
+        let settings: QuayMetadataSettings = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
 
         ensure!(!settings.repository.is_empty(), "empty repository");
-        ensure!(!settings.label_filter.is_empty(), "empty label_filter");
+        ensure!(!settings.label_filters.is_empty(), "empty label_filters");
+        ensure!(
+            settings
+                .label_filters
+                .iter()
+                .all(|filter| !filter.is_empty()),
+            "empty label filter"
+        );
+        ensure!(settings.fetch_concurrency > 0, "zero fetch_concurrency");
+        ensure!(!settings.user_agent.is_empty(), "empty user agent");
+        reqwest::header::HeaderValue::from_str(&settings.user_agent)
+            .context(format!("invalid user agent '{}'", settings.user_agent))?;
+        ensure!(
+            settings.api_credentials_path.is_some()
+                || (settings.credentials_max_age.is_none()
+                    && settings.credentials_warning_age.is_none()
+                    && !settings.reload_credentials),
+            "credentials settings without api_credentials_path"
+        );
+        if let (Some(warning_age), Some(max_age)) = (
+            settings.credentials_warning_age,
+            settings.credentials_max_age,
+        ) {
+            ensure!(
+                warning_age < max_age,
+                "credentials_warning_age not below credentials_max_age"
+            );
+        }
 
-        Ok(Box::new(settings))
+        Ok(settings)
     }
 
     pub fn try_new(
         repo: String,
-        label_filter: String,
+        label_filters: Vec<String>,
         manifestref_key: String,
         api_token_path: Option<PathBuf>,
         api_base: String,
     ) -> Fallible<Self> {
-        let api_token = api_token_path
-            .map(quay::read_credentials)
+        let credentials = api_token_path
+            .map(QuayCredentials::try_new)
             .transpose()
             .context("could not read quay API credentials")?;
 
         let client: quay::v1::Client = quay::v1::Client::builder()
-            .access_token(api_token)
             .api_base(Some(api_base.to_string()))
+            .user_agent(Some(DEFAULT_USER_AGENT.to_string()))
             .build()?;
 
         Ok(Self {
             client,
             repo,
-            label_filter,
+            label_filters,
             manifestref_key,
+            apply_mode: ApplyMode::default(),
+            fetch_concurrency: DEFAULT_FETCH_CONCURRENCY,
+            credentials,
         })
     }
+
+    /// Set how labels are applied, and how many are fetched concurrently in streamed mode.
+    pub fn with_apply_mode(self, apply_mode: ApplyMode, fetch_concurrency: usize) -> Self {
+        Self {
+            apply_mode,
+            fetch_concurrency,
+            ..self
+        }
+    }
+
+    /// Set the user agent of API requests.
+    pub fn with_user_agent(self, user_agent: String) -> Self {
+        Self {
+            client: self.client.with_user_agent(Some(user_agent)),
+            ..self
+        }
+    }
+
+    /// Return the client for a scrape, authenticated with the current credentials.
+    fn scrape_client(&self) -> Fallible<quay::v1::Client> {
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return Ok(self.client.clone()),
+        };
+
+        let token = credentials.token()?;
+        credentials.check_age(SystemTime::now())?;
+        Ok(self.client.with_access_token(Some(token)))
+    }
+
+    /// Fetch the labels of a release, for all label filters.
+    async fn fetch_labels(
+        &self,
+        client: &quay::v1::Client,
+        release_id: ReleaseId,
+        release_version: String,
+        manifestref: String,
+    ) -> Fallible<FetchedLabels> {
+        let mut quay_labels: Vec<(String, String)> = vec![];
+        for label_filter in &self.label_filters {
+            let labels = client
+                .get_labels(
+                    self.repo.clone(),
+                    manifestref.clone(),
+                    Some(label_filter.clone()),
+                )
+                .await?;
+            quay_labels.extend(labels.into_iter().map(Into::<(String, String)>::into));
+        }
+
+        Ok((quay_labels, (release_id, release_version)))
+    }
+}
+
+/// Insert fetched labels into the metadata of their release.
+fn apply_labels(graph: &mut cincinnati::Graph, fetched: FetchedLabels) -> Fallible<()> {
+    let (labels, (release_id, release_version)) = fetched;
+
+    let metadata = graph
+        .get_metadata_as_ref_mut(&release_id)
+        .context("trying to find metadata for release")?;
+    for (key, value) in labels {
+        let warn_msg = if metadata.contains_key(&key) {
+            Some(format!(
+                "[{}] key '{}' already exists. overwriting with value '{}'. ",
+                &release_version, &key, &value
+            ))
+        } else {
+            None
+        };
+
+        trace!(
+            "[{}] inserting ('{}', '{}')",
+            &release_version,
+            &key,
+            &value
+        );
+
+        if let Some(previous_value) = metadata.insert(key, value) {
+            warn!(
+                "{}previous value: '{}'",
+                warn_msg.unwrap_or_default(),
+                previous_value
+            );
+        };
+    }
+
+    Ok(())
+}
+
+/// Apply fetched labels to the graph as they come, stopping at the first failure.
+///
+/// Returns the number of releases the labels were applied to.
+async fn apply_streamed<S>(graph: &mut cincinnati::Graph, mut fetches: S) -> Fallible<usize>
+where
+    S: Stream<Item = Fallible<FetchedLabels>> + Unpin,
+{
+    let mut applied = 0;
+    while let Some(fetched) = fetches.next().await {
+        apply_labels(graph, fetched?)?;
+        applied += 1;
+    }
+
+    Ok(applied)
 }
 
 #[async_trait]
 impl InternalPlugin for QuayMetadataFetchPlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
 
     async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
-        let (mut graph, parameters) = (io.graph, io.parameters);
+        let (mut graph, parameters, warnings) = (io.graph, io.parameters, io.warnings);
 
         trace!("fetching metadata from quay labels...");
 
+        let client = self.scrape_client()?;
+
         let release_manifestrefs: Vec<(ReleaseId, String, String)> =
             graph.find_by_metadata_key(&self.manifestref_key);
 
@@ -115,60 +482,426 @@ impl InternalPlugin for QuayMetadataFetchPlugin {
             );
         }
 
-        let mut labels_with_releaseinfo = Vec::with_capacity(release_manifestrefs.len());
-        for (release_id, release_version, manifestref) in release_manifestrefs {
-            let (client, repo, label_filter) = (
-                self.client.clone(),
-                self.repo.clone(),
-                self.label_filter.clone(),
-            );
+        match self.apply_mode {
+            ApplyMode::Buffered => {
+                let mut labels_with_releaseinfo = Vec::with_capacity(release_manifestrefs.len());
+                for (release_id, release_version, manifestref) in release_manifestrefs {
+                    labels_with_releaseinfo.push(
+                        self.fetch_labels(&client, release_id, release_version, manifestref)
+                            .await?,
+                    );
+                }
 
-            let quay_labels = client
-                .get_labels(
-                    repo.clone(),
-                    manifestref.clone(),
-                    Some(label_filter.clone()),
-                )
-                .await?
+                for fetched in labels_with_releaseinfo {
+                    apply_labels(&mut graph, fetched)?;
+                }
+            }
+            ApplyMode::Streamed => {
+                let fetches = stream::iter(release_manifestrefs)
+                    .map(|(release_id, release_version, manifestref)| {
+                        self.fetch_labels(&client, release_id, release_version, manifestref)
+                    })
+                    .buffer_unordered(self.fetch_concurrency);
+
+                let applied = apply_streamed(&mut graph, fetches).await?;
+                trace!("applied quay labels to {} releases", applied);
+            }
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters,
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(cfg: &str) -> Fallible<QuayMetadataSettings> {
+        QuayMetadataFetchPlugin::parse_settings(
+            QuayMetadataFetchPlugin::settings_migrations().migrate(toml::from_str(cfg)?)?,
+        )
+    }
+
+    #[test]
+    fn settings_default() -> Fallible<()> {
+        let settings = parse(r#"name = "quay-metadata""#)?;
+        assert_eq!(settings.label_filters, vec![DEFAULT_QUAY_LABEL_FILTER]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn settings_v1() -> Fallible<()> {
+        for cfg in &[
+            r#"
+                name = "quay-metadata"
+                label_filter = "com.example"
+            "#,
+            r#"
+                name = "quay-metadata"
+                settings_version = 1
+                label_filter = "com.example"
+            "#,
+        ] {
+            let settings = parse(cfg)?;
+            assert_eq!(settings.label_filters, vec!["com.example"]);
+        }
+
+        let conflicting = r#"
+            name = "quay-metadata"
+            label_filter = "com.example"
+            label_filters = ["org.example"]
+        "#;
+        parse(conflicting).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn settings_v2() -> Fallible<()> {
+        let cfg = r#"
+            name = "quay-metadata"
+            settings_version = 2
+            label_filters = ["com.example", "org.example"]
+        "#;
+        let settings = parse(cfg)?;
+        assert_eq!(settings.label_filters, vec!["com.example", "org.example"]);
+
+        let empty = r#"
+            name = "quay-metadata"
+            settings_version = 2
+            label_filters = []
+        "#;
+        parse(empty).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn settings_future_version() {
+        let cfg = r#"
+            name = "quay-metadata"
+            settings_version = 3
+            label_filters = ["com.example"]
+        "#;
+        let err = parse(cfg).unwrap_err();
+        assert!(
+            err.to_string().contains("unsupported settings_version 3"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn settings_apply_mode() -> Fallible<()> {
+        let settings = parse(r#"name = "quay-metadata""#)?;
+        assert_eq!(settings.apply_mode, ApplyMode::Buffered);
+
+        let cfg = r#"
+            name = "quay-metadata"
+            apply_mode = "streamed"
+            fetch_concurrency = 4
+        "#;
+        let settings = parse(cfg)?;
+        assert_eq!(settings.apply_mode, ApplyMode::Streamed);
+        assert_eq!(settings.fetch_concurrency, 4);
+
+        let zero = r#"
+            name = "quay-metadata"
+            apply_mode = "streamed"
+            fetch_concurrency = 0
+        "#;
+        parse(zero).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn settings_credentials() -> Fallible<()> {
+        let cfg = r#"
+            name = "quay-metadata"
+            api_credentials_path = "/var/run/secrets/quay/token"
+            credentials_max_age = 2000
+            reload_credentials = true
+        "#;
+        let settings = parse(cfg)?;
+        assert_eq!(settings.warning_age(), Some(Duration::from_secs(1500)));
+        assert!(settings.reload_credentials);
+
+        for invalid in &[
+            r#"
+                name = "quay-metadata"
+                reload_credentials = true
+            "#,
+            r#"
+                name = "quay-metadata"
+                api_credentials_path = "/var/run/secrets/quay/token"
+                credentials_max_age = 2000
+                credentials_warning_age = 2000
+            "#,
+        ] {
+            assert!(parse(invalid).is_err(), "input: {}", invalid);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn credentials_age() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("token");
+        std::fs::write(&path, "token\n")?;
+
+        let registry = prometheus::Registry::new();
+        let mut credentials = QuayCredentials::try_new(path)?;
+        credentials.max_age = Some(Duration::from_secs(100));
+        credentials.warning_age = Some(Duration::from_secs(75));
+        credentials.age_seconds =
+            Some(PluginMetrics::new(Some(&registry)).gauge("quay_credentials_age_seconds", "age")?);
+
+        let loaded_at = credentials.loaded.lock().unwrap().modified;
+        let at = |secs: u64| loaded_at + Duration::from_secs(secs);
+        let gauge = credentials.age_seconds.clone().unwrap();
+
+        assert_eq!(credentials.check_age(at(10))?, CredentialsAge::Fresh);
+        assert_eq!(gauge.get(), 10.0);
+        assert_eq!(credentials.check_age(at(75))?, CredentialsAge::Fresh);
+        assert_eq!(credentials.check_age(at(76))?, CredentialsAge::Expiring);
+        assert_eq!(credentials.check_age(at(101))?, CredentialsAge::Expired);
+        assert_eq!(gauge.get(), 101.0);
+
+        // A modification time in the future counts as fresh.
+        let before = loaded_at - Duration::from_secs(5);
+        assert_eq!(credentials.age(before)?, Duration::from_secs(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn credentials_reload_fallback() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("token");
+        std::fs::write(&path, "first\n")?;
+
+        let mut credentials = QuayCredentials::try_new(path.clone())?;
+        std::fs::write(&path, "second\n")?;
+        assert_eq!(credentials.token()?, "first");
+
+        credentials.reload = true;
+        assert_eq!(credentials.token()?, "second");
+
+        // An unreadable file keeps the previous token.
+        std::fs::write(&path, "")?;
+        assert_eq!(credentials.token()?, "second");
+        std::fs::remove_file(&path)?;
+        assert_eq!(credentials.token()?, "second");
+
+        Ok(())
+    }
+
+    #[test]
+    fn sends_user_agent() -> Fallible<()> {
+        use cincinnati::testing::{generate_custom_graph, TestMetadata};
+
+        let mut runtime = commons::testing::init_runtime()?;
+        let metadata: TestMetadata = vec![(
+            0,
+            [(
+                DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+                "sha256:0".to_string(),
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+        )];
+
+        for user_agent in &[DEFAULT_USER_AGENT, "custom-agent/1.0"] {
+            let _m = mockito::mock(
+                "GET",
+                "/api/v1/repository/test/user-agent/manifest/sha256:0/labels",
+            )
+            .match_query(mockito::Matcher::Any)
+            .match_header("user-agent", *user_agent)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"labels":[]}"#)
+            .create();
+
+            let mut plugin = QuayMetadataFetchPlugin::try_new(
+                "test/user-agent".to_string(),
+                vec![DEFAULT_QUAY_LABEL_FILTER.to_string()],
+                DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+                None,
+                format!("{}/api/v1/", mockito::server_url()),
+            )?;
+            if *user_agent != DEFAULT_USER_AGENT {
+                plugin = plugin.with_user_agent(user_agent.to_string());
+            }
+
+            runtime.block_on(plugin.run_internal(InternalIO {
+                graph: generate_custom_graph("image", metadata.clone(), None),
+                parameters: Default::default(),
+                warnings: Default::default(),
+            }))?;
+        }
+
+        parse(
+            r#"
+                name = "quay-metadata"
+                user_agent = ""
+            "#,
+        )
+        .unwrap_err();
+
+        Ok(())
+    }
+
+    mod streamed {
+        use super::*;
+        use cincinnati::testing::{generate_custom_graph, TestMetadata};
+        use commons::testing::init_runtime;
+
+        static LABEL_KEY: &str = "io.openshift.upgrades.graph.release.remove";
+
+        /// Graph with versions "0.0.0" to "2.0.0", each with a manifestref.
+        fn input_graph() -> cincinnati::Graph {
+            let metadata: TestMetadata = (0..3)
+                .map(|i| {
+                    (
+                        i,
+                        [(
+                            DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+                            format!("sha256:{}", i),
+                        )]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    )
+                })
+                .collect();
+            generate_custom_graph("image", metadata, None)
+        }
+
+        /// Metadata value at `LABEL_KEY` for each release, by version.
+        fn labels(graph: &cincinnati::Graph) -> Vec<(String, Option<String>)> {
+            let values: std::collections::HashMap<String, String> = graph
+                .find_by_metadata_key(LABEL_KEY)
                 .into_iter()
-                .map(Into::into)
-                .collect::<Vec<(String, String)>>();
+                .map(|(_, version, value)| (version, value))
+                .collect();
 
-            labels_with_releaseinfo.push((quay_labels, (release_id, release_version)));
+            let mut labels: Vec<(String, Option<String>)> = graph
+                .find_by_metadata_key(DEFAULT_QUAY_MANIFESTREF_KEY)
+                .into_iter()
+                .map(|(_, version, _)| {
+                    let value = values.get(&version).cloned();
+                    (version, value)
+                })
+                .collect();
+            labels.sort();
+            labels
         }
 
-        for (labels, (release_id, release_version)) in labels_with_releaseinfo {
-            let metadata = graph
-                .get_metadata_as_ref_mut(&release_id)
-                .context("trying to find metadata for release")?;
-            for (key, value) in labels {
-                let warn_msg = if metadata.contains_key(&key) {
-                    Some(format!(
-                        "[{}] key '{}' already exists. overwriting with value '{}'. ",
-                        &release_version, &key, &value
-                    ))
-                } else {
-                    None
-                };
-
-                trace!(
-                    "[{}] inserting ('{}', '{}')",
-                    &release_version,
-                    &key,
-                    &value
-                );
+        #[test]
+        fn applies_labels_incrementally() -> Fallible<()> {
+            let mut runtime = init_runtime()?;
+            let mut graph = input_graph();
+
+            let mut releases = graph.find_by_metadata_key(DEFAULT_QUAY_MANIFESTREF_KEY);
+            releases.sort_by(|a, b| a.1.cmp(&b.1));
+            let fetched = |i: usize| -> Fallible<FetchedLabels> {
+                let (release_id, version, _) = releases[i].clone();
+                Ok((
+                    vec![(LABEL_KEY.to_string(), "true".to_string())],
+                    (release_id, version),
+                ))
+            };
 
-                if let Some(previous_value) = metadata.insert(key, value) {
-                    warn!(
-                        "{}previous value: '{}'",
-                        warn_msg.unwrap_or_default(),
-                        previous_value
+            // The fetch for the second release fails, after the first one was applied.
+            let fetches = stream::iter(vec![
+                fetched(0),
+                Err(format_err!("fetch failed")),
+                fetched(2),
+            ]);
+            let err = runtime
+                .block_on(apply_streamed(&mut graph, fetches))
+                .unwrap_err();
+            assert_eq!(err.to_string(), "fetch failed");
+
+            assert_eq!(
+                labels(&graph),
+                vec![
+                    ("0.0.0".to_string(), Some("true".to_string())),
+                    ("1.0.0".to_string(), None),
+                    ("2.0.0".to_string(), None),
+                ]
+            );
+
+            Ok(())
+        }
+
+        #[test]
+        fn streamed_graph_matches_buffered() -> Fallible<()> {
+            let mut runtime = init_runtime()?;
+
+            let _mocks: Vec<mockito::Mock> = (0..3)
+                .map(|i| {
+                    let body = format!(
+                        r#"{{"labels":[{{"key":"{}","value":"{}","media_type":"text/plain","id":"{}","source_type":"api"}}]}}"#,
+                        LABEL_KEY,
+                        i % 2 == 0,
+                        i
                     );
-                };
+                    mockito::mock(
+                        "GET",
+                        format!("/api/v1/repository/test/repo/manifest/sha256:{}/labels", i)
+                            .as_str(),
+                    )
+                    .match_query(mockito::Matcher::Any)
+                    .with_status(200)
+                    .with_header("content-type", "application/json")
+                    .with_body(body)
+                    .create()
+                })
+                .collect();
+
+            let mut graphs = vec![];
+            for apply_mode in &[ApplyMode::Buffered, ApplyMode::Streamed] {
+                let plugin = QuayMetadataFetchPlugin::try_new(
+                    "test/repo".to_string(),
+                    vec![DEFAULT_QUAY_LABEL_FILTER.to_string()],
+                    DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+                    None,
+                    format!("{}/api/v1/", mockito::server_url()),
+                )?
+                .with_apply_mode(*apply_mode, 2);
+
+                let graph = runtime
+                    .block_on(plugin.run_internal(InternalIO {
+                        graph: input_graph(),
+                        parameters: Default::default(),
+                        warnings: Default::default(),
+                    }))?
+                    .graph;
+                graphs.push(graph);
             }
-        }
 
-        Ok(InternalIO { graph, parameters })
+            assert_eq!(
+                labels(&graphs[1]),
+                vec![
+                    ("0.0.0".to_string(), Some("true".to_string())),
+                    ("1.0.0".to_string(), Some("false".to_string())),
+                    ("2.0.0".to_string(), Some("true".to_string())),
+                ]
+            );
+            assert_eq!(graphs[0], graphs[1]);
+
+            Ok(())
+        }
     }
 }
 
@@ -323,7 +1056,7 @@ mod tests_net {
         let plugin = Box::new(
             QuayMetadataFetchPlugin::try_new(
                 "redhat/openshift-cincinnati-test-labels-public-manual".to_string(),
-                DEFAULT_QUAY_LABEL_FILTER.to_string(),
+                vec![DEFAULT_QUAY_LABEL_FILTER.to_string()],
                 DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
                 None,
                 quay::v1::DEFAULT_API_BASE.to_string(),
@@ -333,6 +1066,7 @@ mod tests_net {
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph,
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -383,7 +1117,7 @@ mod tests_net {
         let plugin = Box::new(
             QuayMetadataFetchPlugin::try_new(
                 "redhat/openshift-cincinnati-test-labels-private-manual".to_string(),
-                DEFAULT_QUAY_LABEL_FILTER.to_string(),
+                vec![DEFAULT_QUAY_LABEL_FILTER.to_string()],
                 DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
                 Some(token_file.into()),
                 quay::v1::DEFAULT_API_BASE.to_string(),
@@ -393,6 +1127,7 @@ mod tests_net {
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph,
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -0,0 +1,177 @@
+//! This plugin restricts release metadata to a set of allowed namespaces.
+//!
+//! Metadata keys not starting with any of the `allowed_namespaces` prefixes
+//! are dropped, so that vendor-specific metadata never leaks to clients. All
+//! keys are kept if no namespace is configured.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetadataNamespaceFilterPlugin {
+    /// Prefixes of the metadata keys to keep, all keys are kept if empty.
+    pub allowed_namespaces: Vec<String>,
+}
+
+impl PluginSettings for MetadataNamespaceFilterPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl MetadataNamespaceFilterPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "metadata-namespace-filter";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(
+            plugin
+                .allowed_namespaces
+                .iter()
+                .all(|namespace| !namespace.is_empty()),
+            "empty metadata namespace"
+        );
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Whether the given metadata key is in an allowed namespace.
+    pub fn is_allowed(&self, key: &str) -> bool {
+        self.allowed_namespaces.is_empty()
+            || self
+                .allowed_namespaces
+                .iter()
+                .any(|namespace| key.starts_with(namespace.as_str()))
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for MetadataNamespaceFilterPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        if !self.allowed_namespaces.is_empty() {
+            graph.find_by_fn_mut(|release| {
+                if let Some(metadata) = release.get_metadata_mut() {
+                    let disallowed: Vec<String> = metadata
+                        .keys()
+                        .filter(|key| !self.is_allowed(key))
+                        .cloned()
+                        .collect();
+                    for key in disallowed {
+                        trace!("dropping metadata key '{}' outside allowed namespaces", key);
+                        metadata.remove(&key);
+                    }
+                }
+                false
+            });
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{generate_custom_graph, TestMetadata};
+    use commons::testing::init_runtime;
+
+    static PAIRS: &[(&str, &str)] = &[
+        ("io.openshift.upgrades.graph.release.channels", "stable-4.6"),
+        (
+            "io.openshift.upgrades.graph.release.manifestref",
+            "sha256:0",
+        ),
+        ("url", "https://example.com/errata"),
+        ("com.example.vendor.build-host", "builder-3"),
+        ("com.example.vendor.ticket", "REL-42"),
+    ];
+
+    fn metadata(pairs: &[(&str, &str)]) -> TestMetadata {
+        vec![(
+            0,
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )]
+    }
+
+    fn filter(namespaces: &[&str]) -> Fallible<cincinnati::Graph> {
+        let mut runtime = init_runtime()?;
+        let plugin = MetadataNamespaceFilterPlugin {
+            allowed_namespaces: namespaces.iter().map(ToString::to_string).collect(),
+        };
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: generate_custom_graph("image", metadata(PAIRS), None),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+        Ok(io.graph)
+    }
+
+    #[test]
+    fn drops_keys_outside_allowed_namespaces() -> Fallible<()> {
+        let graph = filter(&["io.openshift.", "url"])?;
+
+        let expected = generate_custom_graph(
+            "image",
+            metadata(&[
+                ("io.openshift.upgrades.graph.release.channels", "stable-4.6"),
+                (
+                    "io.openshift.upgrades.graph.release.manifestref",
+                    "sha256:0",
+                ),
+                ("url", "https://example.com/errata"),
+            ]),
+            None,
+        );
+        assert_eq!(graph, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_all_keys_without_namespaces() -> Fallible<()> {
+        assert_eq!(
+            filter(&[])?,
+            generate_custom_graph("image", metadata(PAIRS), None)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_empty_namespaces() -> Fallible<()> {
+        let cfg: toml::Value = toml::from_str("allowed_namespaces = ['io.openshift.', '']")?;
+        assert!(MetadataNamespaceFilterPlugin::deserialize_config(cfg).is_err());
+
+        let cfg: toml::Value = toml::from_str("allowed_namespaces = []")?;
+        assert!(MetadataNamespaceFilterPlugin::deserialize_config(cfg).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+//! This plugin keeps only the client-visible metadata of each release.
+//!
+//! Metadata keys not matching any entry of the `client_visible_keys`
+//! allowlist are dropped. An entry matches a key exactly, unless it ends with
+//! `*`, in which case it matches all keys starting with the rest of the entry.
+//! It is meant to be the last plugin of a policy pipeline, so that internal
+//! metadata used by previous plugins never reaches clients.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+/// Suffix of the allowlist entries matching key prefixes.
+static PREFIX_WILDCARD: char = '*';
+
+/// Metadata keys clients rely on, kept by default.
+pub static DEFAULT_CLIENT_VISIBLE_KEYS: &[&str] =
+    &["url", "io.openshift.upgrades.graph.release.channels"];
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct MetadataProjectionPlugin {
+    /// Metadata keys, or key prefixes ending with `*`, visible to clients.
+    #[default(DEFAULT_CLIENT_VISIBLE_KEYS.iter().map(|key| key.to_string()).collect())]
+    pub client_visible_keys: Vec<String>,
+}
+
+impl PluginSettings for MetadataProjectionPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl MetadataProjectionPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "metadata-projection";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(
+            plugin
+                .client_visible_keys
+                .iter()
+                .all(|key| !key.trim_end_matches(PREFIX_WILDCARD).is_empty()),
+            "empty client-visible key"
+        );
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Whether the given metadata key is visible to clients.
+    pub fn is_visible(&self, key: &str) -> bool {
+        self.client_visible_keys.iter().any(|entry| {
+            if entry.ends_with(PREFIX_WILDCARD) {
+                key.starts_with(entry.trim_end_matches(PREFIX_WILDCARD))
+            } else {
+                key == entry
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for MetadataProjectionPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+
+        graph.find_by_fn_mut(|release| {
+            if let Some(metadata) = release.get_metadata_mut() {
+                let hidden: Vec<String> = metadata
+                    .keys()
+                    .filter(|key| !self.is_visible(key))
+                    .cloned()
+                    .collect();
+                for key in hidden {
+                    trace!("dropping metadata key '{}'", key);
+                    metadata.remove(&key);
+                }
+            }
+            false
+        });
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{generate_custom_graph, TestMetadata};
+    use commons::testing::init_runtime;
+
+    fn metadata(pairs: &[(&str, &str)]) -> TestMetadata {
+        vec![(
+            0,
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )]
+    }
+
+    fn project(
+        plugin: MetadataProjectionPlugin,
+        pairs: &[(&str, &str)],
+    ) -> Fallible<cincinnati::Graph> {
+        let mut runtime = init_runtime()?;
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: generate_custom_graph("image", metadata(pairs), None),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+        Ok(io.graph)
+    }
+
+    #[test]
+    fn keeps_allowlisted_keys() -> Fallible<()> {
+        let plugin = MetadataProjectionPlugin {
+            client_visible_keys: vec!["url".to_string(), "com.example.support.*".to_string()],
+        };
+
+        let graph = project(
+            plugin,
+            &[
+                ("url", "https://example.com/errata"),
+                ("com.example.support.level", "full"),
+                ("com.example.support.stream", "4.6"),
+                ("com.example.supported", "true"),
+                (
+                    "io.openshift.upgrades.graph.release.manifestref",
+                    "sha256:0",
+                ),
+                ("urls", "https://example.com"),
+            ],
+        )?;
+
+        let expected = generate_custom_graph(
+            "image",
+            metadata(&[
+                ("url", "https://example.com/errata"),
+                ("com.example.support.level", "full"),
+                ("com.example.support.stream", "4.6"),
+            ]),
+            None,
+        );
+        assert_eq!(graph, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn default_keys_match_client_expectations() -> Fallible<()> {
+        // Documented in docs/user/running-cincinnati.md.
+        assert_eq!(
+            MetadataProjectionPlugin::default().client_visible_keys,
+            vec!["url", "io.openshift.upgrades.graph.release.channels"]
+        );
+
+        let graph = project(
+            MetadataProjectionPlugin::default(),
+            &[
+                ("url", "https://example.com/errata"),
+                ("io.openshift.upgrades.graph.release.channels", "stable-4.6"),
+                (
+                    "io.openshift.upgrades.graph.release.manifestref",
+                    "sha256:0",
+                ),
+                ("io.openshift.upgrades.graph.previous.remove", "4.5.0"),
+            ],
+        )?;
+
+        let expected = generate_custom_graph(
+            "image",
+            metadata(&[
+                ("url", "https://example.com/errata"),
+                ("io.openshift.upgrades.graph.release.channels", "stable-4.6"),
+            ]),
+            None,
+        );
+        assert_eq!(graph, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_empty_keys() {
+        for keys in &[vec![""], vec!["url", "*"]] {
+            let cfg = toml::Value::try_from(
+                vec![("client_visible_keys", keys.clone())]
+                    .into_iter()
+                    .collect::<std::collections::BTreeMap<_, _>>(),
+            )
+            .unwrap();
+            assert!(MetadataProjectionPlugin::deserialize_config(cfg).is_err());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! This plugin validates that releases have a minimum number of available updates.
+//!
+//! A release without outgoing edges leaves its clients stuck without any
+//! notice, which usually means that edges were filtered too aggressively.
+//! Every release is expected to have at least `min_updates` outgoing edges,
+//! except for the terminal releases listed in `terminal_versions` and, if
+//! `exclude_latest` is set, the newest release of the graph.
+//! Depending on `mode`, violations either fail the plugin or are logged and
+//! returned as graph warnings.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::channel_heads_check::ValidationMode;
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use std::collections::BTreeSet;
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct MinUpdatesCheckPlugin {
+    /// Minimum number of outgoing edges of a non-terminal release.
+    #[default(1)]
+    pub min_updates: usize,
+
+    /// Versions of the releases which aren't expected to have updates.
+    pub terminal_versions: BTreeSet<String>,
+
+    /// Whether the newest release of the graph is terminal.
+    #[default(true)]
+    pub exclude_latest: bool,
+
+    pub mode: ValidationMode,
+}
+
+impl PluginSettings for MinUpdatesCheckPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl MinUpdatesCheckPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "min-updates-check";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(plugin.min_updates > 0, "zero min_updates");
+        ensure!(
+            plugin
+                .terminal_versions
+                .iter()
+                .all(|version| !version.is_empty()),
+            "empty terminal version"
+        );
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Find the non-terminal releases with too few updates.
+    ///
+    /// Returns their versions along with their number of updates, sorted by version.
+    fn violations(&self, graph: &mut cincinnati::Graph) -> Vec<(String, usize)> {
+        let latest = if self.exclude_latest {
+            graph
+                .max_version_in(|release| matches!(release, cincinnati::Release::Concrete(_)))
+                .map(|(_, version)| version)
+        } else {
+            None
+        };
+
+        let releases =
+            graph.find_by_fn_mut(|release| matches!(release, cincinnati::Release::Concrete(_)));
+
+        let mut violations: Vec<(String, usize)> = releases
+            .into_iter()
+            .filter(|(_, version)| {
+                !self.terminal_versions.contains(version) && latest.as_ref() != Some(version)
+            })
+            .map(|(release_id, version)| (version, graph.next_releases(&release_id).count()))
+            .filter(|(_, updates)| *updates < self.min_updates)
+            .collect();
+        violations.sort();
+
+        violations
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for MinUpdatesCheckPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let (mut graph, parameters, mut warnings) = (io.graph, io.parameters, io.warnings);
+
+        let violations = self.violations(&mut graph);
+
+        if !violations.is_empty() {
+            let details = format!(
+                "{} release(s) with fewer than {} update(s): {}",
+                violations.len(),
+                self.min_updates,
+                violations
+                    .iter()
+                    .map(|(version, updates)| format!("{} ({})", version, updates))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+
+            match self.mode {
+                ValidationMode::Fail => bail!("update availability validation failed: {}", details),
+                ValidationMode::Warn => {
+                    warn!("update availability validation failed: {}", details);
+                    warnings.push(Warning::new(Self::PLUGIN_NAME, details));
+                }
+            }
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters,
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::generate_custom_graph;
+    use commons::testing::init_runtime;
+
+    /// Graph with versions "0.0.0" to "3.0.0", and the given edges by index.
+    fn build_graph(edges: Vec<(usize, usize)>) -> cincinnati::Graph {
+        let metadata = (0..4).map(|i| (i, Default::default())).collect();
+        generate_custom_graph("image", metadata, Some(edges))
+    }
+
+    /// A healthy graph, where "2.0.0" was then stranded by filtering its edge to "3.0.0".
+    fn stranded_graph() -> cincinnati::Graph {
+        build_graph(vec![(0, 1), (0, 2), (1, 3)])
+    }
+
+    fn run(plugin: MinUpdatesCheckPlugin, graph: cincinnati::Graph) -> Fallible<InternalIO> {
+        let mut runtime = init_runtime()?;
+
+        runtime.block_on(plugin.run_internal(InternalIO {
+            graph,
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))
+    }
+
+    #[test]
+    fn healthy_graph() -> Fallible<()> {
+        let graph = build_graph(vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+
+        for mode in &[ValidationMode::Fail, ValidationMode::Warn] {
+            let plugin = MinUpdatesCheckPlugin {
+                mode: *mode,
+                ..Default::default()
+            };
+            let io = run(plugin, graph.clone())?;
+            assert_eq!(io.graph, graph);
+            assert!(io.warnings.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn stranded_release_fails() {
+        let err = run(Default::default(), stranded_graph()).unwrap_err();
+        let msg = err.to_string();
+
+        assert!(
+            msg.contains("1 release(s) with fewer than 1 update(s): 2.0.0 (0)"),
+            "unexpected error: {}",
+            msg
+        );
+    }
+
+    #[test]
+    fn stranded_release_warns() -> Fallible<()> {
+        let plugin = MinUpdatesCheckPlugin {
+            mode: ValidationMode::Warn,
+            ..Default::default()
+        };
+        let io = run(plugin, stranded_graph())?;
+
+        assert_eq!(io.graph, stranded_graph());
+        assert_eq!(io.warnings.len(), 1);
+        assert!(
+            io.warnings[0].message.ends_with(": 2.0.0 (0)"),
+            "unexpected warning: {}",
+            io.warnings[0].message
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal_releases() -> Fallible<()> {
+        let mut plugin = MinUpdatesCheckPlugin {
+            terminal_versions: vec!["2.0.0".to_string()].into_iter().collect(),
+            ..Default::default()
+        };
+        run(plugin.clone(), stranded_graph())?;
+
+        // Without excluding it, the newest release counts as stranded too.
+        plugin.exclude_latest = false;
+        let err = run(plugin.clone(), stranded_graph()).unwrap_err();
+        assert!(err.to_string().ends_with(": 3.0.0 (0)"), "{}", err);
+
+        // A higher minimum catches releases with a single update.
+        plugin.exclude_latest = true;
+        plugin.min_updates = 2;
+        let err = run(plugin, stranded_graph()).unwrap_err();
+        assert!(err.to_string().ends_with(": 1.0.0 (1)"), "{}", err);
+
+        Ok(())
+    }
+}
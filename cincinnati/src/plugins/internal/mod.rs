@@ -1,15 +1,36 @@
 //! This module implements the internal plugins
 
 pub mod arch_filter;
+pub mod arch_normalize;
+pub mod canonicalize;
+pub mod channel_deprecation;
 pub mod channel_filter;
+pub mod channel_heads_check;
 pub mod cincinnati_graph_fetch;
+pub mod client_version_filter;
+pub mod coalesce_patches;
+pub mod date_cutoff_filter;
+pub mod digest_allowlist;
 pub mod edge_add_remove;
+pub mod edge_sanitize;
+pub mod edges_overlay;
+pub mod entitlement_filter;
+pub mod lifecycle_tag;
 pub mod metadata_fetch_quay;
+pub mod metadata_namespace_filter;
+pub mod metadata_projection;
+pub mod min_updates_check;
 pub mod node_remove;
+pub mod platform_filter;
+pub mod release_notes;
+pub mod security_gate;
+pub mod stream_position;
+pub mod upgrade_estimate;
 
 mod graph_builder;
 
 pub use graph_builder::{
-    dkrv2_openshift_secondary_metadata_scraper, github_openshift_secondary_metadata_scraper,
+    dkrv2_openshift_secondary_metadata_scraper, git_metadata,
+    github_openshift_secondary_metadata_scraper, manifest_list_arch,
     openshift_secondary_metadata_parser, release_scrape_dockerv2,
 };
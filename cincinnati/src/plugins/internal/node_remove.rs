@@ -8,16 +8,37 @@ use self::cincinnati::plugins::prelude_plugin_impl::*;
 /// Prefix for the metadata key operations.
 pub static DEFAULT_KEY_FILTER: &str = "io.openshift.upgrades.graph";
 
-#[derive(Clone, Debug, Deserialize, SmartDefault)]
+/// Buckets for the number of releases removed per run.
+static REMOVED_RELEASES_BUCKETS: &[f64] = &[0.0, 1.0, 5.0, 10.0, 50.0, 100.0, 500.0];
+
+#[derive(Clone, CustomDebug, Deserialize, SmartDefault)]
 #[serde(default)]
 pub struct NodeRemovePlugin {
     #[default(DEFAULT_KEY_FILTER.to_string())]
     pub key_prefix: String,
+
+    /// The optional metric for the number of releases removed per run
+    #[serde(skip)]
+    #[debug(skip)]
+    removed_releases: Option<prometheus::Histogram>,
 }
 
 impl PluginSettings for NodeRemovePlugin {
-    fn build_plugin(&self, _: Option<&prometheus::Registry>) -> Fallible<BoxedPlugin> {
-        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, metrics: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        let removed_releases = metrics.scoped(Self::PLUGIN_NAME).histogram(
+            "removed_releases",
+            "Number of releases removed per run",
+            REMOVED_RELEASES_BUCKETS.to_vec(),
+        )?;
+
+        Ok(new_plugin!(InternalPluginWrapper(Self {
+            removed_releases: Some(removed_releases),
+            ..self.clone()
+        })))
     }
 }
 
@@ -27,7 +48,7 @@ impl NodeRemovePlugin {
 
     /// Validate plugin configuration and fill in defaults.
     pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
-        let plugin: Self = cfg.try_into()?;
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
 
         ensure!(!plugin.key_prefix.is_empty(), "empty prefix");
 
@@ -38,6 +59,11 @@ impl NodeRemovePlugin {
 #[async_trait]
 impl InternalPlugin for NodeRemovePlugin {
     const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
 
     async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
         let mut graph = io.graph;
@@ -58,10 +84,14 @@ impl InternalPlugin for NodeRemovePlugin {
         let removed = graph.remove_releases(to_remove);
 
         trace!("removed {} releases", removed);
+        if let Some(removed_releases) = &self.removed_releases {
+            removed_releases.observe(removed as f64);
+        }
 
         Ok(InternalIO {
             graph,
             parameters: io.parameters,
+            warnings: io.warnings,
         })
     }
 }
@@ -114,10 +144,14 @@ mod tests {
             generate_custom_graph("image", metadata, None)
         };
 
-        let plugin = Box::new(NodeRemovePlugin { key_prefix });
+        let plugin = Box::new(NodeRemovePlugin {
+            key_prefix,
+            ..Default::default()
+        });
         let future_processed_graph = plugin.run_internal(InternalIO {
             graph: input_graph,
             parameters: Default::default(),
+            warnings: Default::default(),
         });
 
         let processed_graph = runtime
@@ -129,4 +163,41 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn build_with_metrics() -> Fallible<()> {
+        let registry = prometheus::Registry::new();
+        let settings = vec![
+            NodeRemovePlugin::deserialize_config(toml::from_str("name = 'node-remove'")?)?,
+            NodeRemovePlugin::deserialize_config(toml::from_str("key_prefix = 'other'")?)?,
+        ];
+
+        let plugins: &'static [BoxedPlugin] = Box::leak(
+            cincinnati::plugins::catalog::build_plugins(&settings, Some(&registry))?
+                .into_boxed_slice(),
+        );
+        assert_eq!(plugins.len(), 2);
+
+        let mut runtime = init_runtime()?;
+        runtime.block_on(cincinnati::plugins::process(
+            plugins.iter(),
+            cincinnati::plugins::PluginIO::InternalIO(InternalIO {
+                graph: Default::default(),
+                parameters: Default::default(),
+                warnings: Default::default(),
+            }),
+        ))?;
+
+        let families = registry.gather();
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].get_name(), "node_remove_removed_releases");
+        assert_eq!(
+            families[0].get_metric()[0]
+                .get_histogram()
+                .get_sample_count(),
+            2
+        );
+
+        Ok(())
+    }
 }
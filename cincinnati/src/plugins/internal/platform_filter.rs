@@ -0,0 +1,208 @@
+//! This plugin removes releases which are not available on the client platform.
+//!
+//! The platform is read from the parameters value at key "platform", e.g.
+//! "aws" or "baremetal". Without this parameter the graph is passed through
+//! unchanged, and the plugin is skipped altogether by the plugin pipeline.
+//!
+//! The platforms a release is published for are read as a comma-separated
+//! list from the release metadata at `<key_prefix>.<key_suffix>`, and are
+//! matched case-insensitively. Releases without this metadata are available
+//! on all platforms.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+
+use commons::GraphError;
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_PLATFORMS_KEY: &str = "release.platforms";
+
+/// Name of the parameter holding the client platform.
+pub const PLATFORM_PARAM: &str = "platform";
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct PlatformFilterPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_PLATFORMS_KEY.to_string())]
+    pub key_suffix: String,
+}
+
+impl PluginSettings for PlatformFilterPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl PlatformFilterPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "platform-filter";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty platforms-key prefix");
+        ensure!(!plugin.key_suffix.is_empty(), "empty platforms-key suffix");
+
+        Ok(Box::new(plugin))
+    }
+}
+
+/// Return whether a comma-separated list of platforms contains `platform`.
+fn has_platform(platforms: &str, platform: &str) -> bool {
+    platforms
+        .split(',')
+        .any(|candidate| candidate.trim().eq_ignore_ascii_case(platform))
+}
+
+#[async_trait]
+impl InternalPlugin for PlatformFilterPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    fn relevant_parameters(self: &Self) -> Option<&'static [&'static str]> {
+        Some(&[PLATFORM_PARAM])
+    }
+
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![ParameterDeclaration::new(
+            PLATFORM_PARAM,
+            "Client platform, e.g. 'aws', removing the releases not available on it",
+        )]
+    }
+
+    async fn run_internal(self: &Self, internal_io: InternalIO) -> Fallible<InternalIO> {
+        let platform = match internal_io.parameters.client(PLATFORM_PARAM) {
+            Some(platform) => platform.trim().to_string(),
+            None => return Ok(internal_io),
+        };
+        if platform.is_empty() {
+            return Err(GraphError::InvalidParams(format!("empty '{}'", PLATFORM_PARAM)).into());
+        }
+
+        let key = format!("{}.{}", self.key_prefix, self.key_suffix);
+        let mut graph = internal_io.graph;
+
+        let to_remove = graph
+            .find_by_fn_mut(|release| match release {
+                cincinnati::Release::Concrete(concrete_release) => concrete_release
+                    .metadata
+                    .get(&key)
+                    .map_or(false, |platforms| !has_platform(platforms, &platform)),
+                cincinnati::Release::Abstract(_) => false,
+            })
+            .into_iter()
+            .map(|(release_id, version)| {
+                trace!("queuing '{}' for removal", version);
+                release_id
+            })
+            .collect();
+
+        let removed = graph.remove_releases(to_remove);
+        trace!("removed {} releases", removed);
+
+        Ok(InternalIO {
+            graph,
+            parameters: internal_io.parameters,
+            warnings: internal_io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_versioned_graph, run_internal_plugin, sorted_versions};
+
+    fn run(platform: Option<&str>) -> Fallible<Vec<String>> {
+        let key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_PLATFORMS_KEY);
+        let releases = vec![
+            ("4.6.1", Some("aws,azure,baremetal")),
+            ("4.6.2", Some("aws")),
+            ("4.6.3", Some(" Azure , gcp")),
+            ("4.6.4", None),
+        ];
+        let graph = generate_versioned_graph(
+            "image",
+            releases
+                .into_iter()
+                .map(|(version, platforms)| {
+                    let metadata = platforms
+                        .map(|platforms| (key.clone(), platforms.to_string()))
+                        .into_iter()
+                        .collect();
+                    (version, metadata)
+                })
+                .collect(),
+            &[],
+        );
+        let parameters = platform
+            .map(|platform| (PLATFORM_PARAM.to_string(), platform.to_string()))
+            .into_iter()
+            .collect();
+
+        Ok(sorted_versions(
+            &run_internal_plugin(&PlatformFilterPlugin::default(), graph, parameters)?.graph,
+        ))
+    }
+
+    #[test]
+    fn keeps_available_releases() -> Fallible<()> {
+        // Releases without platforms are available everywhere.
+        assert_eq!(run(Some("aws"))?, vec!["4.6.1", "4.6.2", "4.6.4"]);
+        assert_eq!(run(Some("azure"))?, vec!["4.6.1", "4.6.3", "4.6.4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn removes_unavailable_releases() -> Fallible<()> {
+        assert_eq!(run(Some("vsphere"))?, vec!["4.6.4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn passes_through_without_platform() -> Fallible<()> {
+        assert_eq!(run(None)?, vec!["4.6.1", "4.6.2", "4.6.3", "4.6.4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_empty_platform() {
+        let err = run(Some(" ")).unwrap_err();
+
+        match err.downcast_ref::<GraphError>() {
+            Some(GraphError::InvalidParams(_)) => {}
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn deserialize_config_validation() {
+        for input in &["key_prefix = ''", "key_suffix = ''"] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                PlatformFilterPlugin::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,543 @@
+//! This plugin adds a release notes URL to the metadata of each release.
+//!
+//! The URL is built from a template, in which variables are written between
+//! braces:
+//! * `{version}` expands to the release version.
+//! * `{metadata:<key>}` expands to the release metadata value at `<key>`.
+//!
+//! For example `https://access.redhat.com/errata/{metadata:io.openshift.upgrades.graph.release.errata}`.
+//! Values are inserted verbatim. Releases missing a referenced metadata key
+//! are left untouched, and counted.
+//!
+//! The URL can instead be read from a release metadata key, `source_key`, in
+//! which case the template is optional and only used for releases without
+//! this metadata. With `validate_urls`, URLs which are not absolute HTTP(S)
+//! URLs are skipped with a warning, and counted.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use url::Url;
+
+/// Default metadata key for the release notes URL.
+pub static DEFAULT_URL_KEY: &str = "url";
+
+/// Prefix of the template variables looking up release metadata.
+static METADATA_VARIABLE_PREFIX: &str = "metadata:";
+
+/// Segment of a parsed URL template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateSegment {
+    /// Literal text.
+    Literal(String),
+    /// Release version.
+    Version,
+    /// Release metadata value at the given key.
+    Metadata(String),
+}
+
+#[derive(Clone, CustomDebug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct ReleaseNotesPlugin {
+    /// URL template, used for releases without a URL at `source_key`.
+    pub template: Option<String>,
+
+    /// Metadata key to read the URL from.
+    pub source_key: Option<String>,
+
+    /// Metadata key the URL is written to.
+    #[default(DEFAULT_URL_KEY.to_string())]
+    pub key: String,
+
+    /// Whether to skip URLs which are not absolute HTTP(S) URLs.
+    pub validate_urls: bool,
+
+    /// Parsed URL template.
+    #[serde(skip)]
+    segments: Option<Vec<TemplateSegment>>,
+
+    /// The optional metric for the number of releases skipped for missing metadata or invalid URLs
+    #[serde(skip)]
+    #[debug(skip)]
+    skipped_releases: Option<prometheus::Counter>,
+}
+
+impl PluginSettings for ReleaseNotesPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, metrics: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        let skipped_releases = metrics.scoped(Self::PLUGIN_NAME).counter(
+            "skipped_releases",
+            "Number of releases without release notes URL for missing metadata or invalid URLs",
+        )?;
+
+        Ok(new_plugin!(InternalPluginWrapper(Self {
+            skipped_releases: Some(skipped_releases),
+            ..Self::try_new(
+                self.template.clone(),
+                self.source_key.clone(),
+                self.key.clone(),
+                self.validate_urls,
+            )?
+        })))
+    }
+}
+
+impl ReleaseNotesPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "release-notes";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        Ok(Box::new(Self::try_new(
+            plugin.template,
+            plugin.source_key,
+            plugin.key,
+            plugin.validate_urls,
+        )?))
+    }
+
+    /// Create the plugin, parsing its URL template.
+    pub fn try_new(
+        template: Option<String>,
+        source_key: Option<String>,
+        key: String,
+        validate_urls: bool,
+    ) -> Fallible<Self> {
+        ensure!(
+            template.is_some() || source_key.is_some(),
+            "either a URL template or a source metadata key is required"
+        );
+        if let Some(source_key) = &source_key {
+            ensure!(!source_key.is_empty(), "empty source metadata key");
+        }
+        ensure!(!key.is_empty(), "empty URL key");
+
+        let segments = match &template {
+            Some(template) => {
+                ensure!(!template.is_empty(), "empty URL template");
+                Some(
+                    parse_template(template)
+                        .context(format!("invalid URL template '{}'", template))?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            template,
+            source_key,
+            key,
+            validate_urls,
+            segments,
+            skipped_releases: None,
+        })
+    }
+
+    /// Find the release notes URL of a release.
+    ///
+    /// Returns the reason for which there is none as error.
+    fn notes_url(&self, release: &cincinnati::ConcreteRelease) -> Result<String, String> {
+        if let Some(url) = self
+            .source_key
+            .as_ref()
+            .and_then(|key| release.metadata.get(key))
+        {
+            return Ok(url.clone());
+        }
+
+        match &self.segments {
+            Some(segments) => expand_template(segments, release)
+                .map_err(|key| format!("missing metadata '{}'", key)),
+            None => Err(format!(
+                "missing metadata '{}'",
+                self.source_key.as_deref().unwrap_or_default()
+            )),
+        }
+    }
+}
+
+/// Ensure `url` is an absolute HTTP(S) URL.
+pub fn validate_url(url: &str) -> Fallible<Url> {
+    let parsed = Url::parse(url).context(format!("invalid URL '{}'", url))?;
+    ensure!(
+        parsed.scheme() == "http" || parsed.scheme() == "https",
+        "unsupported scheme '{}' in URL '{}'",
+        parsed.scheme(),
+        url
+    );
+    Ok(parsed)
+}
+
+/// Parse a URL template into its segments.
+pub fn parse_template(template: &str) -> Fallible<Vec<TemplateSegment>> {
+    let mut segments = vec![];
+    let mut rest = template;
+
+    while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+        let (literal, tail) = rest.split_at(start);
+        if !literal.is_empty() {
+            segments.push(TemplateSegment::Literal(literal.to_string()));
+        }
+
+        ensure!(tail.starts_with('{'), "unmatched '{}'", '}');
+        let end = tail
+            .find('}')
+            .ok_or_else(|| format_err!("unclosed variable '{}'", tail))?;
+        segments.push(parse_variable(&tail[1..end])?);
+
+        rest = &tail[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        segments.push(TemplateSegment::Literal(rest.to_string()));
+    }
+
+    Ok(segments)
+}
+
+/// Parse the name of a template variable.
+fn parse_variable(name: &str) -> Fallible<TemplateSegment> {
+    if name == "version" {
+        return Ok(TemplateSegment::Version);
+    }
+
+    if name.starts_with(METADATA_VARIABLE_PREFIX) {
+        let key = &name[METADATA_VARIABLE_PREFIX.len()..];
+        ensure!(
+            !key.is_empty() && !key.contains('{'),
+            "invalid metadata key '{}'",
+            key
+        );
+        return Ok(TemplateSegment::Metadata(key.to_string()));
+    }
+
+    bail!(
+        "unknown variable '{}', expected 'version' or 'metadata:<key>'",
+        name
+    )
+}
+
+/// Expand a parsed template for a release.
+///
+/// Returns the first missing metadata key as error.
+pub fn expand_template<'a>(
+    segments: &'a [TemplateSegment],
+    release: &cincinnati::ConcreteRelease,
+) -> Result<String, &'a str> {
+    let mut url = String::new();
+
+    for segment in segments {
+        match segment {
+            TemplateSegment::Literal(literal) => url.push_str(literal),
+            TemplateSegment::Version => url.push_str(&release.version),
+            TemplateSegment::Metadata(key) => match release.metadata.get(key) {
+                Some(value) => url.push_str(value),
+                None => return Err(key.as_str()),
+            },
+        }
+    }
+
+    Ok(url)
+}
+
+#[async_trait]
+impl InternalPlugin for ReleaseNotesPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let mut skipped = 0;
+
+        graph.find_by_fn_mut(|release| {
+            let concrete_release = match release {
+                cincinnati::Release::Concrete(concrete_release) => concrete_release,
+                cincinnati::Release::Abstract(_) => return false,
+            };
+
+            let url = match self.notes_url(concrete_release) {
+                Ok(url) => url,
+                Err(reason) => {
+                    debug!(
+                        "not adding URL to '{}', {}",
+                        concrete_release.version, reason
+                    );
+                    skipped += 1;
+                    return false;
+                }
+            };
+
+            if self.validate_urls {
+                if let Err(e) = validate_url(&url) {
+                    warn!("not adding URL to '{}': {}", concrete_release.version, e);
+                    skipped += 1;
+                    return false;
+                }
+            }
+
+            trace!("adding URL '{}' to '{}'", url, concrete_release.version);
+            concrete_release.metadata.insert(self.key.clone(), url);
+
+            false
+        });
+
+        if let Some(skipped_releases) = &self.skipped_releases {
+            skipped_releases.inc_by(skipped as f64);
+        }
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as cincinnati;
+
+    use super::*;
+    use cincinnati::testing::{generate_custom_graph, TestMetadata};
+    use commons::testing::init_runtime;
+
+    static ERRATA_KEY: &str = "io.openshift.upgrades.graph.release.errata";
+
+    /// Graph with versions "0.0.0" to "2.0.0", the last one without errata.
+    fn input_graph() -> cincinnati::Graph {
+        let metadata: TestMetadata = vec![
+            (
+                0,
+                [(ERRATA_KEY.to_string(), "RHBA-2020:0001".to_string())]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            ),
+            (
+                1,
+                [(ERRATA_KEY.to_string(), "RHBA-2020:0002".to_string())]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            ),
+            (2, Default::default()),
+        ];
+        generate_custom_graph("image", metadata, None)
+    }
+
+    fn run(plugin: ReleaseNotesPlugin) -> Fallible<Vec<(String, Option<String>)>> {
+        let mut runtime = init_runtime()?;
+        let key = plugin.key.clone();
+
+        let mut graph = runtime
+            .block_on(plugin.run_internal(InternalIO {
+                graph: input_graph(),
+                parameters: Default::default(),
+                warnings: Default::default(),
+            }))?
+            .graph;
+
+        let mut urls: Vec<(String, Option<String>)> = vec![];
+        graph.find_by_fn_mut(|release| {
+            if let cincinnati::Release::Concrete(concrete_release) = release {
+                urls.push((
+                    concrete_release.version.clone(),
+                    concrete_release.metadata.get(&key).cloned(),
+                ));
+            }
+            false
+        });
+        urls.sort();
+        Ok(urls)
+    }
+
+    #[test]
+    fn expands_template() -> Fallible<()> {
+        let plugin = ReleaseNotesPlugin::try_new(
+            Some(format!(
+                "https://example.com/errata/{{metadata:{}}}",
+                ERRATA_KEY
+            )),
+            None,
+            DEFAULT_URL_KEY.to_string(),
+            false,
+        )?;
+        let urls = run(plugin)?;
+        assert_eq!(
+            urls[..2],
+            [
+                (
+                    "0.0.0".to_string(),
+                    Some("https://example.com/errata/RHBA-2020:0001".to_string())
+                ),
+                (
+                    "1.0.0".to_string(),
+                    Some("https://example.com/errata/RHBA-2020:0002".to_string())
+                ),
+            ]
+        );
+
+        let plugin = ReleaseNotesPlugin::try_new(
+            Some("https://example.com/notes/{version}.html".to_string()),
+            None,
+            "notes".to_string(),
+            false,
+        )?;
+        let urls = run(plugin)?;
+        assert_eq!(
+            urls[2],
+            (
+                "2.0.0".to_string(),
+                Some("https://example.com/notes/2.0.0.html".to_string())
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn skips_missing_variables() -> Fallible<()> {
+        let skipped_releases = prometheus::Counter::new("skipped", "skipped releases")?;
+        let plugin = ReleaseNotesPlugin {
+            skipped_releases: Some(skipped_releases.clone()),
+            ..ReleaseNotesPlugin::try_new(
+                Some(format!("{{version}}/{{metadata:{}}}", ERRATA_KEY)),
+                None,
+                DEFAULT_URL_KEY.to_string(),
+                false,
+            )?
+        };
+
+        let urls = run(plugin)?;
+        assert_eq!(
+            urls,
+            vec![
+                (
+                    "0.0.0".to_string(),
+                    Some("0.0.0/RHBA-2020:0001".to_string())
+                ),
+                (
+                    "1.0.0".to_string(),
+                    Some("1.0.0/RHBA-2020:0002".to_string())
+                ),
+                ("2.0.0".to_string(), None),
+            ]
+        );
+        assert_eq!(skipped_releases.get() as u64, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_sourced_url() -> Fallible<()> {
+        let skipped_releases = prometheus::Counter::new("skipped", "skipped releases")?;
+        let plugin = ReleaseNotesPlugin {
+            skipped_releases: Some(skipped_releases.clone()),
+            ..ReleaseNotesPlugin::try_new(
+                Some("https://example.com/notes/{version}".to_string()),
+                Some(ERRATA_KEY.to_string()),
+                DEFAULT_URL_KEY.to_string(),
+                true,
+            )?
+        };
+
+        // The template is only a fallback, and invalid URLs are skipped.
+        let urls = run(plugin)?;
+        assert_eq!(
+            urls,
+            vec![
+                ("0.0.0".to_string(), None),
+                ("1.0.0".to_string(), None),
+                (
+                    "2.0.0".to_string(),
+                    Some("https://example.com/notes/2.0.0".to_string())
+                ),
+            ]
+        );
+        assert_eq!(skipped_releases.get() as u64, 2);
+
+        let plugin = ReleaseNotesPlugin::try_new(
+            None,
+            Some(ERRATA_KEY.to_string()),
+            DEFAULT_URL_KEY.to_string(),
+            false,
+        )?;
+        let urls = run(plugin)?;
+        assert_eq!(
+            urls,
+            vec![
+                ("0.0.0".to_string(), Some("RHBA-2020:0001".to_string())),
+                ("1.0.0".to_string(), Some("RHBA-2020:0002".to_string())),
+                ("2.0.0".to_string(), None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in &[
+            "",
+            "https://example.com/{channel}",
+            "https://example.com/{metadata:}",
+            "https://example.com/{version",
+            "https://example.com/version}",
+            "https://example.com/{metadata:a{b}",
+        ] {
+            let cfg = toml::Value::try_from(
+                vec![("template", *template)]
+                    .into_iter()
+                    .collect::<std::collections::BTreeMap<_, _>>(),
+            )
+            .unwrap();
+            assert!(
+                ReleaseNotesPlugin::deserialize_config(cfg).is_err(),
+                "template '{}' accepted",
+                template
+            );
+
+            let settings = ReleaseNotesPlugin {
+                template: Some(template.to_string()),
+                ..Default::default()
+            };
+            assert!(
+                settings.build_plugin(&PluginMetrics::default()).is_err(),
+                "template '{}' built",
+                template
+            );
+        }
+
+        assert!(
+            ReleaseNotesPlugin::try_new(None, None, DEFAULT_URL_KEY.to_string(), false).is_err()
+        );
+        assert!(ReleaseNotesPlugin::try_new(
+            None,
+            Some("".to_string()),
+            DEFAULT_URL_KEY.to_string(),
+            false
+        )
+        .is_err());
+        assert!(validate_url("ftp://example.com/notes").is_err());
+
+        assert_eq!(
+            parse_template("a{version}b{metadata:c}").unwrap(),
+            vec![
+                TemplateSegment::Literal("a".to_string()),
+                TemplateSegment::Version,
+                TemplateSegment::Literal("b".to_string()),
+                TemplateSegment::Metadata("c".to_string()),
+            ]
+        );
+    }
+}
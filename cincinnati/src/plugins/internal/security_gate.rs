@@ -0,0 +1,501 @@
+//! This plugin gates releases on their quay.io security scan.
+//!
+//! The security scan summary of each release is fetched by its manifestref.
+//! In `annotate` mode, the number of distinct critical vulnerabilities is
+//! written to the release metadata at `<key_prefix>.<critical_key_suffix>`.
+//! In `remove` mode, releases with more critical vulnerabilities than
+//! `max_critical` are additionally removed from the graph.
+//!
+//! Releases whose scan is still queued or in progress are left untouched if
+//! `skip_pending` is set, and otherwise handled like failed fetches.
+//!
+//! Like the `quay-metadata` plugin, the plugin fails by default if a summary
+//! can't be fetched for a single manifestref. With `lenient`, such releases
+//! are left untouched instead, and a warning is returned along with the graph.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::internal::metadata_fetch_quay::{
+    DEFAULT_QUAY_MANIFESTREF_KEY, DEFAULT_QUAY_REPOSITORY,
+};
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::DEFAULT_USER_AGENT;
+use quay::v1::{ScanStatus, SecuritySummary};
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_CRITICAL_KEY: &str = "release.security.critical";
+static CRITICAL_SEVERITY: &str = "critical";
+
+/// What to do with the scanned releases.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GateAction {
+    /// Annotate releases with their number of critical vulnerabilities.
+    #[default]
+    Annotate,
+    /// Annotate releases, and remove those above `max_critical`.
+    Remove,
+}
+
+/// Plugin settings.
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+struct SecurityGateSettings {
+    #[default(quay::v1::DEFAULT_API_BASE.to_string())]
+    api_base: String,
+
+    #[default(Option::None)]
+    api_credentials_path: Option<PathBuf>,
+
+    #[default(DEFAULT_QUAY_REPOSITORY.to_string())]
+    repository: String,
+
+    #[default(DEFAULT_QUAY_MANIFESTREF_KEY.to_string())]
+    manifestref_key: String,
+
+    #[default(DEFAULT_USER_AGENT.to_string())]
+    user_agent: String,
+
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    key_prefix: String,
+
+    /// Metadata key of the critical vulnerability count, under `key_prefix`.
+    #[default(DEFAULT_CRITICAL_KEY.to_string())]
+    critical_key_suffix: String,
+
+    action: GateAction,
+
+    /// Maximum number of critical vulnerabilities of a kept release, in `remove` mode.
+    max_critical: usize,
+
+    #[default(true)]
+    skip_pending: bool,
+
+    lenient: bool,
+}
+
+/// Security gate on the quay.io security scans.
+#[derive(Debug)]
+pub struct SecurityGatePlugin {
+    client: quay::v1::Client,
+    repo: String,
+    manifestref_key: String,
+    critical_key: String,
+    action: GateAction,
+    max_critical: usize,
+    skip_pending: bool,
+    lenient: bool,
+}
+
+impl PluginSettings for SecurityGateSettings {
+    fn plugin_name(&self) -> &'static str {
+        SecurityGatePlugin::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        let cfg = self.clone();
+        let plugin = SecurityGatePlugin::try_new(
+            cfg.repository,
+            cfg.manifestref_key,
+            cfg.api_credentials_path,
+            cfg.api_base,
+        )?
+        .with_user_agent(cfg.user_agent)
+        .with_critical_key(format!("{}.{}", cfg.key_prefix, cfg.critical_key_suffix))
+        .with_action(cfg.action, cfg.max_critical)
+        .with_policy(cfg.skip_pending, cfg.lenient);
+        Ok(new_plugin!(InternalPluginWrapper(plugin)))
+    }
+}
+
+impl SecurityGatePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "security-gate";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        Ok(Box::new(Self::parse_settings(cfg)?))
+    }
+
+    fn parse_settings(cfg: toml::Value) -> Fallible<SecurityGateSettings> {
+        let settings: SecurityGateSettings = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!settings.repository.is_empty(), "empty repository");
+        ensure!(
+            !settings.manifestref_key.is_empty(),
+            "empty manifestref_key"
+        );
+        ensure!(!settings.key_prefix.is_empty(), "empty prefix");
+        ensure!(
+            !settings.critical_key_suffix.is_empty(),
+            "empty critical_key_suffix"
+        );
+        ensure!(!settings.user_agent.is_empty(), "empty user agent");
+        reqwest::header::HeaderValue::from_str(&settings.user_agent)
+            .context(format!("invalid user agent '{}'", settings.user_agent))?;
+
+        Ok(settings)
+    }
+
+    pub fn try_new(
+        repo: String,
+        manifestref_key: String,
+        api_token_path: Option<PathBuf>,
+        api_base: String,
+    ) -> Fallible<Self> {
+        let api_token = api_token_path
+            .map(quay::read_credentials)
+            .transpose()
+            .context("could not read quay API credentials")?;
+
+        let client: quay::v1::Client = quay::v1::Client::builder()
+            .access_token(api_token)
+            .api_base(Some(api_base))
+            .user_agent(Some(DEFAULT_USER_AGENT.to_string()))
+            .build()?;
+
+        Ok(Self {
+            client,
+            repo,
+            manifestref_key,
+            critical_key: format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_CRITICAL_KEY),
+            action: GateAction::default(),
+            max_critical: 0,
+            skip_pending: true,
+            lenient: false,
+        })
+    }
+
+    /// Set the user agent of API requests.
+    pub fn with_user_agent(self, user_agent: String) -> Self {
+        Self {
+            client: self.client.with_user_agent(Some(user_agent)),
+            ..self
+        }
+    }
+
+    /// Set the metadata key of the critical vulnerability count.
+    pub fn with_critical_key(self, critical_key: String) -> Self {
+        Self {
+            critical_key,
+            ..self
+        }
+    }
+
+    /// Set the action on scanned releases, and the removal threshold.
+    pub fn with_action(self, action: GateAction, max_critical: usize) -> Self {
+        Self {
+            action,
+            max_critical,
+            ..self
+        }
+    }
+
+    /// Set whether pending scans are skipped, and whether failures are fatal.
+    pub fn with_policy(self, skip_pending: bool, lenient: bool) -> Self {
+        Self {
+            skip_pending,
+            lenient,
+            ..self
+        }
+    }
+
+    /// Fetch the scan summary of a release, failing on pending scans unless they're skipped.
+    async fn fetch_summary(&self, manifestref: &str) -> Fallible<Option<SecuritySummary>> {
+        let summary = self
+            .client
+            .get_security_status(self.repo.as_str(), manifestref)
+            .await
+            .context(format!("fetching the security scan of '{}'", manifestref))?;
+
+        match summary.status {
+            ScanStatus::Scanned => Ok(Some(summary)),
+            ScanStatus::Pending if self.skip_pending => Ok(None),
+            status => bail!(
+                "security scan of '{}' is not available: {:?}",
+                manifestref,
+                status
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for SecurityGatePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let (mut graph, parameters, mut warnings) = (io.graph, io.parameters, io.warnings);
+
+        let release_manifestrefs: Vec<(ReleaseId, String, String)> =
+            graph.find_by_metadata_key(&self.manifestref_key);
+
+        if release_manifestrefs.is_empty() {
+            warn!(
+                "no release has a manifestref at metadata key '{}'",
+                &self.manifestref_key
+            );
+        }
+
+        let mut to_remove = vec![];
+        for (release_id, version, manifestref) in release_manifestrefs {
+            let summary = match self.fetch_summary(&manifestref).await {
+                Ok(Some(summary)) => summary,
+                Ok(None) => {
+                    trace!("[{}] security scan pending, skipping", version);
+                    continue;
+                }
+                Err(e) if self.lenient => {
+                    let msg = format!("[{}] skipping security gate: {:#}", version, e);
+                    warn!("{}", msg);
+                    warnings.push(Warning::new(Self::PLUGIN_NAME, msg));
+                    continue;
+                }
+                Err(e) => return Err(e.context(format!("[{}] security gate failed", version))),
+            };
+
+            let critical = summary.count(CRITICAL_SEVERITY);
+            graph
+                .get_metadata_as_ref_mut(&release_id)
+                .context("trying to find metadata for release")?
+                .insert(self.critical_key.clone(), critical.to_string());
+
+            if self.action == GateAction::Remove && critical > self.max_critical {
+                info!(
+                    "[{}] removing release with {} critical vulnerabilities",
+                    version, critical
+                );
+                to_remove.push(release_id);
+            }
+        }
+
+        graph.remove_releases(to_remove);
+
+        Ok(InternalIO {
+            graph,
+            parameters,
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::testing::{generate_custom_graph, TestMetadata};
+    use commons::testing::init_runtime;
+
+    fn parse(cfg: &str) -> Fallible<SecurityGateSettings> {
+        SecurityGatePlugin::parse_settings(toml::from_str(cfg)?)
+    }
+
+    /// Graph with versions "0.0.0" to "2.0.0", each with a manifestref.
+    fn input_graph() -> cincinnati::Graph {
+        let metadata: TestMetadata = (0..3)
+            .map(|i| {
+                (
+                    i,
+                    [(
+                        DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+                        format!("sha256:{}", i),
+                    )]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                )
+            })
+            .collect();
+        generate_custom_graph("image", metadata, None)
+    }
+
+    /// Scan response with the given status and critical vulnerabilities.
+    fn scan_body(status: &str, critical: &[&str]) -> String {
+        let vulnerabilities: Vec<String> = critical
+            .iter()
+            .map(|name| format!(r#"{{"Name":"{}","Severity":"Critical"}}"#, name))
+            .collect();
+        format!(
+            r#"{{"status":"{}","data":{{"Layer":{{"Features":[{{"Name":"openssl","Vulnerabilities":[{}]}}]}}}}}}"#,
+            status,
+            vulnerabilities.join(",")
+        )
+    }
+
+    /// Mock the scan response of each release, by index.
+    fn mock_scans(repo: &str, bodies: &[String]) -> Vec<mockito::Mock> {
+        bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                mockito::mock(
+                    "GET",
+                    format!("/api/v1/repository/{}/manifest/sha256:{}/security", repo, i).as_str(),
+                )
+                .match_query(mockito::Matcher::Any)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(body)
+                .create()
+            })
+            .collect()
+    }
+
+    fn plugin(repo: &str) -> Fallible<SecurityGatePlugin> {
+        SecurityGatePlugin::try_new(
+            repo.to_string(),
+            DEFAULT_QUAY_MANIFESTREF_KEY.to_string(),
+            None,
+            format!("{}/api/v1/", mockito::server_url()),
+        )
+    }
+
+    /// Critical count of each remaining release, by version.
+    fn critical_counts(graph: &cincinnati::Graph) -> Vec<(String, Option<String>)> {
+        let key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_CRITICAL_KEY);
+        let counts: std::collections::HashMap<String, String> = graph
+            .find_by_metadata_key(&key)
+            .into_iter()
+            .map(|(_, version, count)| (version, count))
+            .collect();
+
+        let mut releases: Vec<(String, Option<String>)> = graph
+            .find_by_metadata_key(DEFAULT_QUAY_MANIFESTREF_KEY)
+            .into_iter()
+            .map(|(_, version, _)| {
+                let count = counts.get(&version).cloned();
+                (version, count)
+            })
+            .collect();
+        releases.sort();
+        releases
+    }
+
+    fn run(plugin: &SecurityGatePlugin) -> Fallible<InternalIO> {
+        init_runtime()?.block_on(plugin.run_internal(InternalIO {
+            graph: input_graph(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))
+    }
+
+    #[test]
+    fn settings() -> Fallible<()> {
+        let settings = parse(r#"name = "security-gate""#)?;
+        assert_eq!(settings.action, GateAction::Annotate);
+        assert_eq!(settings.max_critical, 0);
+        assert!(settings.skip_pending);
+        assert!(!settings.lenient);
+
+        let settings = parse(
+            r#"
+                name = "security-gate"
+                action = "remove"
+                max_critical = 2
+            "#,
+        )?;
+        assert_eq!(settings.action, GateAction::Remove);
+        assert_eq!(settings.max_critical, 2);
+
+        parse(
+            r#"
+                name = "security-gate"
+                action = "block"
+            "#,
+        )
+        .unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotate_mode() -> Fallible<()> {
+        let repo = "test/security-gate-annotate";
+        // The same vulnerability in several features counts once.
+        let _mocks = mock_scans(
+            repo,
+            &[
+                scan_body("scanned", &[]),
+                scan_body("scanned", &["CVE-1", "CVE-2", "CVE-2"]),
+                scan_body("scanned", &["CVE-1", "CVE-2", "CVE-3"]),
+            ],
+        );
+
+        let io = run(&plugin(repo)?)?;
+        assert_eq!(
+            critical_counts(&io.graph),
+            vec![
+                ("0.0.0".to_string(), Some("0".to_string())),
+                ("1.0.0".to_string(), Some("2".to_string())),
+                ("2.0.0".to_string(), Some("3".to_string())),
+            ]
+        );
+        assert!(io.warnings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn removal_mode() -> Fallible<()> {
+        let repo = "test/security-gate-remove";
+        let _mocks = mock_scans(
+            repo,
+            &[
+                scan_body("scanned", &[]),
+                scan_body("scanned", &["CVE-1", "CVE-2"]),
+                scan_body("scanned", &["CVE-1"]),
+            ],
+        );
+
+        let io = run(&plugin(repo)?.with_action(GateAction::Remove, 1))?;
+        assert_eq!(
+            critical_counts(&io.graph),
+            vec![
+                ("0.0.0".to_string(), Some("0".to_string())),
+                ("2.0.0".to_string(), Some("1".to_string())),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pending_scans() -> Fallible<()> {
+        let repo = "test/security-gate-pending";
+        let _mocks = mock_scans(
+            repo,
+            &[
+                scan_body("scanned", &["CVE-1"]),
+                scan_body("queued", &[]),
+                scan_body("scanning", &[]),
+            ],
+        );
+
+        // Skipped pending scans leave their release untouched.
+        let skipping = plugin(repo)?.with_action(GateAction::Remove, 0);
+        let io = run(&skipping)?;
+        assert_eq!(
+            critical_counts(&io.graph),
+            vec![("1.0.0".to_string(), None), ("2.0.0".to_string(), None),]
+        );
+
+        // Otherwise they fail the run, unless lenient.
+        let strict = plugin(repo)?.with_policy(false, false);
+        run(&strict).unwrap_err();
+
+        let lenient = plugin(repo)?.with_policy(false, true);
+        let io = run(&lenient)?;
+        assert_eq!(
+            critical_counts(&io.graph),
+            vec![
+                ("0.0.0".to_string(), Some("1".to_string())),
+                ("1.0.0".to_string(), None),
+                ("2.0.0".to_string(), None),
+            ]
+        );
+        assert_eq!(io.warnings.len(), 2);
+
+        Ok(())
+    }
+}
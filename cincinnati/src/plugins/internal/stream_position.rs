@@ -0,0 +1,236 @@
+//! This plugin tags each release with its position in the release streams of its channels.
+//!
+//! Releases are grouped by channel, as listed in their metadata, and ranked
+//! by version within each channel: the newest release is `latest`, the one
+//! before it `n-1`, and all others `older`. A release in several channels
+//! gets its highest position across them. The position is recorded in the
+//! release metadata under the `position_key_suffix` key.
+//!
+//! Releases without channels or with an invalid version are not ranked, and
+//! any previous position is removed from their metadata.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::versions::{cmp_release_versions, parse_release_version};
+
+use semver::Version;
+use std::collections::{BTreeMap, HashMap};
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_CHANNEL_KEY: &str = "release.channels";
+static DEFAULT_POSITION_KEY: &str = "release.stream_position";
+
+/// Position of a release in the release stream of a channel, from the lowest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StreamPosition {
+    /// Any release before the previous one.
+    Older,
+    /// Release right before the newest one.
+    Previous,
+    /// Newest release.
+    Latest,
+}
+
+impl StreamPosition {
+    /// Return the position for the release at `rank`, the newest being at 0.
+    pub fn from_rank(rank: usize) -> Self {
+        match rank {
+            0 => StreamPosition::Latest,
+            1 => StreamPosition::Previous,
+            _ => StreamPosition::Older,
+        }
+    }
+
+    /// Return the metadata value of the position.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StreamPosition::Latest => "latest",
+            StreamPosition::Previous => "n-1",
+            StreamPosition::Older => "older",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct StreamPositionPlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_CHANNEL_KEY.to_string())]
+    pub channel_key_suffix: String,
+
+    #[default(DEFAULT_POSITION_KEY.to_string())]
+    pub position_key_suffix: String,
+}
+
+impl PluginSettings for StreamPositionPlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl StreamPositionPlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "stream-position";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty key prefix");
+        ensure!(
+            !plugin.channel_key_suffix.is_empty(),
+            "empty channel-key suffix"
+        );
+        ensure!(
+            !plugin.position_key_suffix.is_empty(),
+            "empty position-key suffix"
+        );
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Compute the highest position of each ranked release, by version.
+    fn positions(&self, graph: &cincinnati::Graph) -> HashMap<String, StreamPosition> {
+        let channel_key = format!("{}.{}", self.key_prefix, self.channel_key_suffix);
+
+        // Group the releases by channel.
+        let mut channels: BTreeMap<String, Vec<(Version, String)>> = BTreeMap::new();
+        for (_, version, release_channels) in graph.find_by_metadata_key(&channel_key) {
+            let parsed = match parse_release_version(&version) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    trace!("ignoring release: {}", e);
+                    continue;
+                }
+            };
+
+            for channel in cincinnati::parse_channels(&release_channels) {
+                channels
+                    .entry(channel.to_string())
+                    .or_default()
+                    .push((parsed.clone(), version.clone()));
+            }
+        }
+
+        let mut positions: HashMap<String, StreamPosition> = HashMap::new();
+        for members in channels.values_mut() {
+            members.sort_by(|(a, _), (b, _)| cmp_release_versions(b, a));
+            for (rank, (_, version)) in members.iter().enumerate() {
+                let position = StreamPosition::from_rank(rank);
+                let highest = positions.entry(version.clone()).or_insert(position);
+                *highest = (*highest).max(position);
+            }
+        }
+
+        positions
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for StreamPositionPlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let positions = self.positions(&graph);
+        let position_key = format!("{}.{}", self.key_prefix, self.position_key_suffix);
+
+        graph.find_by_fn_mut(|release| {
+            let position = positions.get(release.version()).cloned();
+            if let Some(metadata) = release.get_metadata_mut() {
+                match position {
+                    Some(position) => {
+                        metadata.insert(position_key.clone(), position.as_str().to_string());
+                    }
+                    None => {
+                        metadata.remove(&position_key);
+                    }
+                }
+            }
+            false
+        });
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    /// Releases of the "stable-4.6" and "candidate-4.7" channels, in no particular order.
+    ///
+    /// 4.7.0 is in both channels, and 4.5.0 in none.
+    static GRAPH: &str = r#"{
+        "nodes": [
+            {"version": "4.6.2", "payload": "image/4.6.2", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.6"}},
+            {"version": "4.6.10", "payload": "image/4.6.10", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.6"}},
+            {"version": "4.6.0", "payload": "image/4.6.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.6"}},
+            {"version": "4.6.1", "payload": "image/4.6.1", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.6"}},
+            {"version": "4.7.0", "payload": "image/4.7.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.6, candidate-4.7"}},
+            {"version": "4.7.1", "payload": "image/4.7.1", "metadata": {"io.openshift.upgrades.graph.release.channels": "candidate-4.7"}},
+            {"version": "4.5.0", "payload": "image/4.5.0", "metadata": {"io.openshift.upgrades.graph.release.stream_position": "latest"}}
+        ],
+        "edges": []
+    }"#;
+
+    #[test]
+    fn ranks_releases_by_channel() -> Fallible<()> {
+        let mut runtime = init_runtime()?;
+
+        let io = runtime.block_on(StreamPositionPlugin::default().run_internal(InternalIO {
+            graph: serde_json::from_str(GRAPH)?,
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+
+        let key = format!("{}.{}", DEFAULT_KEY_PREFIX, DEFAULT_POSITION_KEY);
+        let positions: BTreeMap<String, String> = io
+            .graph
+            .find_by_metadata_key(&key)
+            .into_iter()
+            .map(|(_, version, position)| (version, position))
+            .collect();
+
+        // 4.7.0 is the latest of "stable-4.6", but n-1 in "candidate-4.7".
+        let expected: BTreeMap<String, String> = [
+            ("4.6.0", "older"),
+            ("4.6.1", "older"),
+            ("4.6.2", "older"),
+            ("4.6.10", "n-1"),
+            ("4.7.0", "latest"),
+            ("4.7.1", "latest"),
+        ]
+        .iter()
+        .map(|(version, position)| (version.to_string(), position.to_string()))
+        .collect();
+        assert_eq!(positions, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn position_order() {
+        assert!(StreamPosition::Latest > StreamPosition::Previous);
+        assert!(StreamPosition::Previous > StreamPosition::Older);
+        assert_eq!(StreamPosition::from_rank(5), StreamPosition::Older);
+    }
+}
@@ -0,0 +1,342 @@
+//! This plugin annotates each release with an estimated upgrade duration.
+//!
+//! Releases are grouped by channel, as listed in their metadata, and the
+//! estimate of a release is the duration of an upgrade from it to the head of
+//! its channel, the newest release of the channel. For a release in several
+//! channels, the longest estimate across them is kept. The estimate, in
+//! minutes, is the sum of:
+//! * a fixed `base` duration;
+//! * the duration of the version jump: the difference of the major versions,
+//!   or else of the minor versions, or else of the patch versions, times the
+//!   per-version duration of that component;
+//! * the number of components of the channel head, as recorded in its
+//!   metadata, times the per-component duration.
+//!
+//! The estimate is rounded up to a whole number of minutes and recorded in
+//! the release metadata under the `key_suffix` key. Channel heads, releases
+//! without channels or with an invalid version, and releases of channels
+//! whose head has no valid component count get no estimate, and any previous
+//! estimate is removed from their metadata.
+
+use crate as cincinnati;
+
+use self::cincinnati::plugins::prelude::*;
+use self::cincinnati::plugins::prelude_plugin_impl::*;
+use self::cincinnati::versions::{cmp_release_versions, parse_release_version};
+
+use semver::Version;
+use std::collections::{BTreeMap, HashMap};
+
+static DEFAULT_KEY_PREFIX: &str = "io.openshift.upgrades.graph";
+static DEFAULT_ESTIMATE_KEY: &str = "release.estimated_upgrade_minutes";
+static DEFAULT_CHANNEL_KEY: &str = "release.channels";
+static DEFAULT_COMPONENTS_KEY: &str = "release.components";
+
+/// Durations of the estimate terms, in minutes.
+#[derive(Clone, Debug, Deserialize, SmartDefault, PartialEq)]
+#[serde(default)]
+pub struct EstimateHeuristics {
+    /// Duration of any upgrade.
+    #[default(20.0)]
+    pub base: f64,
+    /// Duration of each major version jumped.
+    #[default(120.0)]
+    pub major: f64,
+    /// Duration of each minor version jumped.
+    #[default(30.0)]
+    pub minor: f64,
+    /// Duration of each patch version jumped.
+    #[default(1.0)]
+    pub patch: f64,
+    /// Duration of each component of the channel head.
+    #[default(0.5)]
+    pub component: f64,
+}
+
+impl EstimateHeuristics {
+    fn iter(&self) -> impl Iterator<Item = f64> {
+        vec![
+            self.base,
+            self.major,
+            self.minor,
+            self.patch,
+            self.component,
+        ]
+        .into_iter()
+    }
+
+    /// Estimate the duration of the upgrade from `from` to `to`, in minutes.
+    fn estimate(&self, from: &Version, to: &Version, components: u64) -> u64 {
+        let delta = |from: u64, to: u64| (to.max(from) - to.min(from)) as f64;
+        let jump = if from.major != to.major {
+            self.major * delta(from.major, to.major)
+        } else if from.minor != to.minor {
+            self.minor * delta(from.minor, to.minor)
+        } else {
+            self.patch * delta(from.patch, to.patch)
+        };
+
+        let minutes = self.base + jump + self.component * components as f64;
+        minutes.ceil() as u64
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct UpgradeEstimatePlugin {
+    #[default(DEFAULT_KEY_PREFIX.to_string())]
+    pub key_prefix: String,
+
+    #[default(DEFAULT_ESTIMATE_KEY.to_string())]
+    pub key_suffix: String,
+
+    /// Metadata key of the release channels, under `key_prefix`.
+    #[default(DEFAULT_CHANNEL_KEY.to_string())]
+    pub channel_key_suffix: String,
+
+    /// Metadata key of the component count, under `key_prefix`.
+    #[default(DEFAULT_COMPONENTS_KEY.to_string())]
+    pub components_key_suffix: String,
+
+    pub minutes: EstimateHeuristics,
+}
+
+impl PluginSettings for UpgradeEstimatePlugin {
+    fn plugin_name(&self) -> &'static str {
+        Self::PLUGIN_NAME
+    }
+
+    fn build_plugin(&self, _: &PluginMetrics) -> Fallible<BoxedPlugin> {
+        Ok(new_plugin!(InternalPluginWrapper(self.clone())))
+    }
+}
+
+impl UpgradeEstimatePlugin {
+    /// Plugin name, for configuration.
+    pub const PLUGIN_NAME: &'static str = "upgrade-estimate";
+
+    /// Validate plugin configuration and fill in defaults.
+    pub fn deserialize_config(cfg: toml::Value) -> Fallible<Box<dyn PluginSettings>> {
+        let plugin: Self = commons::de::from_toml_named(cfg, Self::PLUGIN_NAME)?;
+
+        ensure!(!plugin.key_prefix.is_empty(), "empty estimate-key prefix");
+        ensure!(!plugin.key_suffix.is_empty(), "empty estimate-key suffix");
+        ensure!(
+            !plugin.channel_key_suffix.is_empty(),
+            "empty channel-key suffix"
+        );
+        ensure!(
+            !plugin.components_key_suffix.is_empty(),
+            "empty components-key suffix"
+        );
+        for minutes in plugin.minutes.iter() {
+            ensure!(
+                minutes.is_finite() && minutes >= 0.0,
+                "invalid estimate duration {}",
+                minutes
+            );
+        }
+
+        Ok(Box::new(plugin))
+    }
+
+    /// Compute the longest estimate of each estimated release, by version.
+    fn estimates(&self, graph: &cincinnati::Graph) -> HashMap<String, u64> {
+        let channel_key = format!("{}.{}", self.key_prefix, self.channel_key_suffix);
+        let components_key = format!("{}.{}", self.key_prefix, self.components_key_suffix);
+
+        let components: HashMap<String, String> = graph
+            .find_by_metadata_key(&components_key)
+            .into_iter()
+            .map(|(_, version, components)| (version, components))
+            .collect();
+
+        // Group the releases by channel.
+        let mut channels: BTreeMap<String, Vec<(Version, String)>> = BTreeMap::new();
+        for (_, version, release_channels) in graph.find_by_metadata_key(&channel_key) {
+            let parsed = match parse_release_version(&version) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    trace!("ignoring release: {}", e);
+                    continue;
+                }
+            };
+
+            for channel in cincinnati::parse_channels(&release_channels) {
+                channels
+                    .entry(channel.to_string())
+                    .or_default()
+                    .push((parsed.clone(), version.clone()));
+            }
+        }
+
+        let mut estimates: HashMap<String, u64> = HashMap::new();
+        for (channel, members) in channels.iter_mut() {
+            members.sort_by(|(a, _), (b, _)| cmp_release_versions(b, a));
+            let (head, head_version) = match members.first() {
+                Some(head) => head,
+                None => continue,
+            };
+
+            let head_components = match components.get(head_version) {
+                Some(head_components) => head_components,
+                None => {
+                    trace!("no component count for the head of '{}'", channel);
+                    continue;
+                }
+            };
+            let head_components: u64 = match head_components.trim().parse() {
+                Ok(head_components) => head_components,
+                Err(e) => {
+                    warn!(
+                        "invalid component count '{}' of '{}': {}",
+                        head_components, head_version, e
+                    );
+                    continue;
+                }
+            };
+
+            for (parsed, version) in members.iter().skip(1) {
+                let minutes = self.minutes.estimate(parsed, head, head_components);
+                trace!(
+                    "estimate of '{}' -> '{}': {} minutes",
+                    version,
+                    head_version,
+                    minutes
+                );
+                let longest = estimates.entry(version.clone()).or_insert(minutes);
+                *longest = (*longest).max(minutes);
+            }
+        }
+
+        estimates
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for UpgradeEstimatePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    fn is_pure(self: &Self) -> bool {
+        true
+    }
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let mut graph = io.graph;
+        let estimates = self.estimates(&graph);
+        let estimate_key = format!("{}.{}", self.key_prefix, self.key_suffix);
+
+        graph.find_by_fn_mut(|release| {
+            let estimate = estimates.get(release.version()).cloned();
+            if let Some(metadata) = release.get_metadata_mut() {
+                match estimate {
+                    Some(minutes) => {
+                        metadata.insert(estimate_key.clone(), minutes.to_string());
+                    }
+                    None => {
+                        metadata.remove(&estimate_key);
+                    }
+                }
+            }
+            false
+        });
+
+        Ok(InternalIO {
+            graph,
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+
+    /// Releases of the "stable-4.8", "fast-4.8" and "candidate-4.9" channels.
+    ///
+    /// The head of "stable-4.8", 4.8.2, has 35 components, the head of
+    /// "fast-4.8", 4.8.3, has none, and the head of "candidate-4.9", 4.9.0,
+    /// has an invalid count. 4.5.0 is in no channel.
+    static GRAPH: &str = r#"{
+        "nodes": [
+            {"version": "4.6.1", "payload": "image/4.6.1", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.8"}},
+            {"version": "4.8.1", "payload": "image/4.8.1", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.8, fast-4.8"}},
+            {"version": "4.8.2", "payload": "image/4.8.2", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable-4.8, fast-4.8", "io.openshift.upgrades.graph.release.components": "35"}},
+            {"version": "4.8.3", "payload": "image/4.8.3", "metadata": {"io.openshift.upgrades.graph.release.channels": "fast-4.8"}},
+            {"version": "4.8.4", "payload": "image/4.8.4", "metadata": {"io.openshift.upgrades.graph.release.channels": "candidate-4.9"}},
+            {"version": "4.9.0", "payload": "image/4.9.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "candidate-4.9", "io.openshift.upgrades.graph.release.components": "many"}},
+            {"version": "4.5.0", "payload": "image/4.5.0", "metadata": {"io.openshift.upgrades.graph.release.estimated_upgrade_minutes": "10"}}
+        ],
+        "edges": []
+    }"#;
+
+    /// Run the plugin on the test graph and return the estimates, by version.
+    fn run(plugin: UpgradeEstimatePlugin) -> Fallible<BTreeMap<String, String>> {
+        let mut runtime = init_runtime()?;
+        let key = format!("{}.{}", plugin.key_prefix, plugin.key_suffix);
+
+        let io = runtime.block_on(plugin.run_internal(InternalIO {
+            graph: serde_json::from_str(GRAPH)?,
+            parameters: Default::default(),
+            warnings: Default::default(),
+        }))?;
+
+        Ok(io
+            .graph
+            .find_by_metadata_key(&key)
+            .into_iter()
+            .map(|(_, version, minutes)| (version, minutes))
+            .collect())
+    }
+
+    #[test]
+    fn small_and_large_jumps() -> Fallible<()> {
+        let estimates = run(UpgradeEstimatePlugin::default())?;
+
+        // One patch version to 4.8.2 and 35 components: 20 + 1 + 17.5, rounded up.
+        assert_eq!(estimates["4.8.1"], "39");
+        // Two minor versions to 4.8.2 and 35 components: 20 + 60 + 17.5, rounded up.
+        assert_eq!(estimates["4.6.1"], "98");
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_estimate_without_inputs() -> Fallible<()> {
+        let estimates = run(UpgradeEstimatePlugin::default())?;
+
+        // 4.8.2 is the head of "stable-4.8" and the head of "fast-4.8" has no
+        // component count, the head of "candidate-4.9" has an invalid one, and
+        // 4.5.0 is in no channel.
+        let expected: Vec<&str> = vec!["4.6.1", "4.8.1"];
+        assert_eq!(estimates.keys().collect::<Vec<_>>(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_config_validation() -> Fallible<()> {
+        for input in &[
+            "key_suffix = ''",
+            "channel_key_suffix = ''",
+            "components_key_suffix = ''",
+            "minutes = { minor = -1.0 }",
+        ] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            assert!(
+                UpgradeEstimatePlugin::deserialize_config(cfg).is_err(),
+                "input: '{}'",
+                input
+            );
+        }
+
+        let plugin: UpgradeEstimatePlugin = toml::from_str("minutes = { component = 2.0 }")?;
+        assert_eq!(plugin.minutes.component, 2.0);
+        assert_eq!(plugin.minutes.base, 20.0);
+
+        Ok(())
+    }
+}
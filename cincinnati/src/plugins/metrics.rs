@@ -0,0 +1,279 @@
+//! Metrics handle for plugins.
+//!
+//! Plugins receive a `PluginMetrics` handle when they are built. Metrics
+//! created through a handle scoped to a plugin are prefixed with the plugin
+//! name and registered to the optional registry of the pipeline. Requesting
+//! the same metric again, e.g. from a second instance of the same plugin,
+//! returns a handle to the already registered metric.
+//!
+//! Runs skipped by `process` are counted per plugin name, in a counter shared
+//! by all pipelines.
+
+use commons::prelude_errors::*;
+use lazy_static::lazy_static;
+use prometheus::{histogram_opts, Counter, Gauge, Histogram, IntCounterVec, Opts, Registry};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    /// Plugin runs skipped for lacking relevant parameters, by plugin name.
+    pub(crate) static ref SKIPPED_RUNS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "plugin_skipped_runs_total",
+            "Total number of plugin runs skipped for lacking relevant parameters"
+        ),
+        &["plugin"]
+    )
+    .unwrap();
+}
+
+/// Metric created through a `PluginMetrics` handle.
+#[derive(Clone)]
+enum PluginMetric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+impl PluginMetric {
+    fn kind(&self) -> &'static str {
+        match self {
+            PluginMetric::Counter(_) => "counter",
+            PluginMetric::Gauge(_) => "gauge",
+            PluginMetric::Histogram(_) => "histogram",
+        }
+    }
+
+    fn collector(&self) -> Box<dyn prometheus::core::Collector> {
+        match self {
+            PluginMetric::Counter(metric) => Box::new(metric.clone()),
+            PluginMetric::Gauge(metric) => Box::new(metric.clone()),
+            PluginMetric::Histogram(metric) => Box::new(metric.clone()),
+        }
+    }
+}
+
+/// Handle for plugins to create their metrics.
+#[derive(Clone, Default)]
+pub struct PluginMetrics<'a> {
+    registry: Option<&'a Registry>,
+    /// Prefix of the metric names, derived from the plugin name.
+    prefix: Option<String>,
+    /// Metrics created through this handle and its scoped copies, by full name.
+    metrics: Arc<Mutex<HashMap<String, PluginMetric>>>,
+}
+
+impl<'a> std::fmt::Debug for PluginMetrics<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PluginMetrics")
+            .field("registry", &self.registry.is_some())
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl<'a> PluginMetrics<'a> {
+    /// Create a handle registering metrics to `registry`, if any.
+    pub fn new(registry: Option<&'a Registry>) -> Self {
+        Self {
+            registry,
+            ..Default::default()
+        }
+    }
+
+    /// Return the underlying registry.
+    ///
+    /// This is meant for plugins which need to keep unprefixed metric names.
+    pub fn registry(&self) -> Option<&'a Registry> {
+        self.registry
+    }
+
+    /// Register the counter of skipped plugin runs, if not already registered.
+    pub(crate) fn register_skipped_runs(&self) -> Fallible<()> {
+        if let Some(registry) = self.registry {
+            match registry.register(Box::new(SKIPPED_RUNS.clone())) {
+                Ok(()) | Err(prometheus::Error::AlreadyReg) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Return a handle whose metrics are prefixed with the given plugin name.
+    pub fn scoped(&self, plugin_name: &str) -> Self {
+        Self {
+            registry: self.registry,
+            prefix: Some(plugin_name.replace('-', "_")),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Return a counter, creating and registering it if needed.
+    pub fn counter(&self, name: &str, help: &str) -> Fallible<Counter> {
+        let metric = self.get_or_register(name, || {
+            Ok(PluginMetric::Counter(Counter::with_opts(Opts::new(
+                self.full_name(name),
+                help,
+            ))?))
+        })?;
+
+        match metric {
+            PluginMetric::Counter(counter) => Ok(counter),
+            other => bail!(
+                "metric '{}' is a {}, not a counter",
+                self.full_name(name),
+                other.kind()
+            ),
+        }
+    }
+
+    /// Return a gauge, creating and registering it if needed.
+    pub fn gauge(&self, name: &str, help: &str) -> Fallible<Gauge> {
+        let metric = self.get_or_register(name, || {
+            Ok(PluginMetric::Gauge(Gauge::with_opts(Opts::new(
+                self.full_name(name),
+                help,
+            ))?))
+        })?;
+
+        match metric {
+            PluginMetric::Gauge(gauge) => Ok(gauge),
+            other => bail!(
+                "metric '{}' is a {}, not a gauge",
+                self.full_name(name),
+                other.kind()
+            ),
+        }
+    }
+
+    /// Return a histogram, creating and registering it if needed.
+    pub fn histogram(&self, name: &str, help: &str, buckets: Vec<f64>) -> Fallible<Histogram> {
+        let metric = self.get_or_register(name, || {
+            Ok(PluginMetric::Histogram(Histogram::with_opts(
+                histogram_opts!(self.full_name(name), help.to_string(), buckets),
+            )?))
+        })?;
+
+        match metric {
+            PluginMetric::Histogram(histogram) => Ok(histogram),
+            other => bail!(
+                "metric '{}' is a {}, not a histogram",
+                self.full_name(name),
+                other.kind()
+            ),
+        }
+    }
+
+    fn full_name(&self, name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}_{}", prefix, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn get_or_register<F>(&self, name: &str, new_metric: F) -> Fallible<PluginMetric>
+    where
+        F: FnOnce() -> Fallible<PluginMetric>,
+    {
+        let full_name = self.full_name(name);
+        let mut metrics = self
+            .metrics
+            .lock()
+            .map_err(|_| format_err!("poisoned plugin metrics lock"))?;
+
+        if let Some(metric) = metrics.get(&full_name) {
+            return Ok(metric.clone());
+        }
+
+        let metric = new_metric()?;
+        if let Some(registry) = self.registry {
+            // A metric with the same name may be left over from a previous
+            // pipeline built against this registry, replace it.
+            match registry.register(metric.collector()) {
+                Err(prometheus::Error::AlreadyReg) => {
+                    registry.unregister(metric.collector())?;
+                    registry.register(metric.collector())?;
+                }
+                result => result?,
+            }
+        }
+        metrics.insert(full_name, metric.clone());
+
+        Ok(metric)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixed_and_deduplicated() -> Fallible<()> {
+        let registry = Registry::new();
+        let metrics = PluginMetrics::new(Some(&registry));
+
+        let first = metrics.scoped("node-remove");
+        let second = metrics.scoped("node-remove");
+
+        let a = first.counter("runs_total", "Total number of runs")?;
+        let b = second.counter("runs_total", "Total number of runs")?;
+        a.inc();
+        b.inc();
+
+        first.gauge("last_removed", "Releases removed in the last run")?;
+        first.histogram("removed", "Removed releases", vec![1.0, 10.0])?;
+        metrics.gauge("unscoped", "Gauge without plugin prefix")?;
+
+        let names: Vec<String> = registry
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "node_remove_last_removed",
+                "node_remove_removed",
+                "node_remove_runs_total",
+                "unscoped",
+            ]
+        );
+        assert_eq!(a.get() as u64, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn kind_mismatch() -> Fallible<()> {
+        let metrics = PluginMetrics::default().scoped("test");
+
+        metrics.counter("metric", "help")?;
+        metrics.gauge("metric", "help").unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn replaces_stale_registration() -> Fallible<()> {
+        let registry = Registry::new();
+
+        let old = PluginMetrics::new(Some(&registry))
+            .scoped("test")
+            .counter("total", "help")?;
+        old.inc();
+
+        let new = PluginMetrics::new(Some(&registry))
+            .scoped("test")
+            .counter("total", "help")?;
+        new.inc_by(5.0);
+
+        let families = registry.gather();
+        assert_eq!(families.len(), 1);
+        assert_eq!(
+            families[0].get_metric()[0].get_counter().get_value() as u64,
+            5
+        );
+
+        Ok(())
+    }
+}
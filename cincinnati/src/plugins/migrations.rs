@@ -0,0 +1,156 @@
+//! Versioned plugin settings.
+//!
+//! Plugin configurations may carry an optional `settings_version` field. A
+//! plugin declares the latest version it understands and provides explicit
+//! migration functions between consecutive versions, so that older
+//! configurations keep deserializing the same way after the settings change.
+//! Configurations without a version are treated as version 1.
+//!
+//! Migrations are applied to every plugin configuration by
+//! `catalog::deserialize_config`, before the plugin deserializes it.
+
+use commons::prelude_errors::*;
+use toml::value::Table;
+
+/// Key used to look up the settings version in a configuration entry.
+pub static SETTINGS_VERSION_KEY: &str = "settings_version";
+
+/// Settings version assumed for configurations without an explicit version.
+pub const DEFAULT_SETTINGS_VERSION: u64 = 1;
+
+/// Migration of a settings table from one version to the next.
+pub type Migration = fn(Table) -> Fallible<Table>;
+
+/// Migrations for the settings of a single plugin.
+#[derive(Debug)]
+pub struct SettingsMigrations {
+    plugin_name: String,
+    migrations: Vec<Migration>,
+}
+
+impl SettingsMigrations {
+    /// Create migrations for a plugin which only accepts version 1.
+    pub fn new(plugin_name: &str) -> Self {
+        Self {
+            plugin_name: plugin_name.to_string(),
+            migrations: vec![],
+        }
+    }
+
+    /// Add the migration to the next version, after all previous ones.
+    ///
+    /// The n-th added migration converts version n to version n+1.
+    pub fn then(mut self, migration: Migration) -> Self {
+        self.migrations.push(migration);
+        self
+    }
+
+    /// Return the latest accepted settings version.
+    pub fn current_version(&self) -> u64 {
+        DEFAULT_SETTINGS_VERSION + self.migrations.len() as u64
+    }
+
+    /// Migrate the given configuration to the latest version.
+    ///
+    /// The `settings_version` field is stripped from the returned configuration.
+    pub fn migrate(&self, cfg: toml::Value) -> Fallible<toml::Value> {
+        let mut table = match cfg {
+            toml::Value::Table(table) => table,
+            other => bail!(
+                "plugin '{}': expected a table, got {}",
+                self.plugin_name,
+                other.type_str()
+            ),
+        };
+
+        let version = match table.remove(SETTINGS_VERSION_KEY) {
+            None => DEFAULT_SETTINGS_VERSION,
+            Some(toml::Value::Integer(version)) if version > 0 => version as u64,
+            Some(other) => bail!(
+                "plugin '{}': invalid {} '{}'",
+                self.plugin_name,
+                SETTINGS_VERSION_KEY,
+                other
+            ),
+        };
+
+        ensure!(
+            version <= self.current_version(),
+            "plugin '{}': unsupported {} {}, this build supports versions {} to {}",
+            self.plugin_name,
+            SETTINGS_VERSION_KEY,
+            version,
+            DEFAULT_SETTINGS_VERSION,
+            self.current_version()
+        );
+
+        let first = (version - DEFAULT_SETTINGS_VERSION) as usize;
+        for (i, migration) in self.migrations.iter().enumerate().skip(first) {
+            let from = DEFAULT_SETTINGS_VERSION + i as u64;
+            table = migration(table).context(format!(
+                "plugin '{}': migrating settings from version {} to {}",
+                self.plugin_name,
+                from,
+                from + 1
+            ))?;
+        }
+
+        Ok(toml::Value::Table(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_marker(mut table: Table) -> Fallible<Table> {
+        let count = table
+            .get("count")
+            .and_then(toml::Value::as_integer)
+            .unwrap_or_default();
+        table.insert("count".to_string(), toml::Value::Integer(count + 1));
+        Ok(table)
+    }
+
+    fn migrations() -> SettingsMigrations {
+        SettingsMigrations::new("test")
+            .then(add_marker)
+            .then(add_marker)
+    }
+
+    #[test]
+    fn migrate_versions() {
+        assert_eq!(migrations().current_version(), 3);
+
+        for (input, count) in &[
+            ("", 2),
+            ("settings_version = 1", 2),
+            ("settings_version = 2", 1),
+            ("settings_version = 3", 0),
+        ] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            let migrated = migrations().migrate(cfg).unwrap();
+
+            assert_eq!(
+                migrated.get("count").and_then(toml::Value::as_integer),
+                if *count == 0 { None } else { Some(*count) },
+                "input: '{}'",
+                input
+            );
+            assert!(migrated.get(SETTINGS_VERSION_KEY).is_none());
+        }
+    }
+
+    #[test]
+    fn reject_unknown_versions() {
+        for input in &[
+            "settings_version = 4",
+            "settings_version = 0",
+            "settings_version = -1",
+            "settings_version = 'two'",
+        ] {
+            let cfg: toml::Value = toml::from_str(input).unwrap();
+            migrations().migrate(cfg).unwrap_err();
+        }
+    }
+}
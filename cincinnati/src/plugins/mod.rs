@@ -6,19 +6,27 @@ pub mod macros;
 
 pub mod catalog;
 pub mod external;
+pub mod health;
 pub mod interface;
 pub mod internal;
+pub mod metrics;
+pub mod migrations;
+pub mod parameters;
 
 use crate as cincinnati;
 
+use self::cincinnati::plugins::health::PluginHealth;
 use self::cincinnati::plugins::interface::{PluginError, PluginExchange};
+pub use self::cincinnati::plugins::parameters::{
+    ParameterDeclaration, Parameters, RESERVED_PARAM_PREFIX,
+};
 
 use async_trait::async_trait;
 pub use commons::prelude_errors::*;
 use commons::tracing::get_tracer;
-use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Debug;
+use std::time::Duration;
 
 use opentelemetry::api::{trace::futures::Instrument, Tracer};
 
@@ -31,21 +39,45 @@ pub mod prelude {
 
     pub use plugins::catalog::PluginSettings;
     pub use plugins::internal::arch_filter::ArchFilterPlugin;
+    pub use plugins::internal::arch_normalize::ArchNormalizePlugin;
+    pub use plugins::internal::canonicalize::CanonicalizePlugin;
+    pub use plugins::internal::channel_deprecation::ChannelDeprecationPlugin;
     pub use plugins::internal::channel_filter::ChannelFilterPlugin;
+    pub use plugins::internal::channel_heads_check::ChannelHeadsCheckPlugin;
     pub use plugins::internal::cincinnati_graph_fetch::CincinnatiGraphFetchPlugin;
+    pub use plugins::internal::client_version_filter::ClientVersionFilterPlugin;
+    pub use plugins::internal::coalesce_patches::CoalescePatchesPlugin;
+    pub use plugins::internal::date_cutoff_filter::DateCutoffFilterPlugin;
+    pub use plugins::internal::digest_allowlist::DigestAllowlistPlugin;
     pub use plugins::internal::edge_add_remove::EdgeAddRemovePlugin;
+    pub use plugins::internal::edge_sanitize::EdgeSanitizePlugin;
+    pub use plugins::internal::edges_overlay::EdgesOverlayPlugin;
+    pub use plugins::internal::entitlement_filter::EntitlementFilterPlugin;
+    pub use plugins::internal::git_metadata::{GitMetadataPlugin, GitMetadataSettings};
     pub use plugins::internal::github_openshift_secondary_metadata_scraper::{
         GithubOpenshiftSecondaryMetadataScraperPlugin,
         GithubOpenshiftSecondaryMetadataScraperSettings,
     };
+    pub use plugins::internal::lifecycle_tag::LifecycleTagPlugin;
+    pub use plugins::internal::manifest_list_arch::{
+        ManifestListArchPlugin, ManifestListArchSettings,
+    };
     pub use plugins::internal::metadata_fetch_quay::QuayMetadataFetchPlugin;
+    pub use plugins::internal::metadata_namespace_filter::MetadataNamespaceFilterPlugin;
+    pub use plugins::internal::metadata_projection::MetadataProjectionPlugin;
+    pub use plugins::internal::min_updates_check::MinUpdatesCheckPlugin;
     pub use plugins::internal::node_remove::NodeRemovePlugin;
     pub use plugins::internal::openshift_secondary_metadata_parser::{
         OpenshiftSecondaryMetadataParserPlugin, OpenshiftSecondaryMetadataParserSettings,
     };
+    pub use plugins::internal::platform_filter::PlatformFilterPlugin;
+    pub use plugins::internal::release_notes::ReleaseNotesPlugin;
     pub use plugins::internal::release_scrape_dockerv2::{
         ReleaseScrapeDockerv2Plugin, ReleaseScrapeDockerv2Settings,
     };
+    pub use plugins::internal::security_gate::SecurityGatePlugin;
+    pub use plugins::internal::stream_position::StreamPositionPlugin;
+    pub use plugins::internal::upgrade_estimate::UpgradeEstimatePlugin;
 
     pub use std::iter::FromIterator;
 
@@ -58,7 +90,13 @@ pub mod prelude_plugin_impl {
 
     pub use self::cincinnati::{daggy, ReleaseId};
     pub use plugins::catalog::PluginSettings;
-    pub use plugins::{BoxedPlugin, InternalIO, InternalPlugin, InternalPluginWrapper};
+    pub use plugins::health::PluginHealth;
+    pub use plugins::metrics::PluginMetrics;
+    pub use plugins::migrations::SettingsMigrations;
+    pub use plugins::{
+        BoxedPlugin, InternalIO, InternalPlugin, InternalPluginWrapper, ParameterDeclaration,
+        Parameters, Warning, PLUGIN_ABI_VERSION,
+    };
 
     pub use async_trait::async_trait;
     pub use commons::prelude_errors::*;
@@ -71,6 +109,18 @@ pub mod prelude_plugin_impl {
     pub use std::str::FromStr;
 }
 
+/// Version of the plugin interface, i.e. the plugin traits and their IO types.
+///
+/// This must be bumped on every incompatible change of the interface, so that
+/// plugins built against another version are refused by `build_plugins`.
+/// Plugins declare the version they were written for as a literal
+/// `ABI_VERSION`, updated when they are ported to a new version.
+///
+/// Version 2 added `InternalIO::warnings`. Version 3 changed the plugin
+/// parameters to `Parameters`, and `PluginSettings::build_plugin` to take a
+/// `PluginMetrics` handle. Version 4 added `is_pure`.
+pub const PLUGIN_ABI_VERSION: u32 = 4;
+
 /// Convenience type for the thread-safe storage of plugins
 pub type BoxedPlugin = Box<dyn Plugin<PluginIO>>;
 
@@ -110,7 +160,41 @@ pub enum PluginResult {
 #[cfg_attr(test, derive(Clone, PartialEq))]
 pub struct InternalIO {
     pub graph: cincinnati::Graph,
-    pub parameters: HashMap<String, String>,
+    pub parameters: Parameters,
+    /// Caveats recorded by the plugins while processing the graph.
+    pub warnings: Vec<Warning>,
+}
+
+/// Recoverable issue recorded by a plugin, exposed to clients.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    /// Name of the plugin recording the warning.
+    pub plugin: String,
+    /// Human-readable description of the issue.
+    pub message: String,
+}
+
+impl Warning {
+    /// Create a warning recorded by the given plugin.
+    pub fn new<S: Into<String>>(plugin: &str, message: S) -> Self {
+        Self {
+            plugin: plugin.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Graph serialized along with the warnings recorded while processing it.
+///
+/// The warnings are emitted as a top-level `warnings` array, omitted when empty.
+/// The graph may also be a serializable view of it, e.g. with extra fields on
+/// its releases.
+#[derive(Debug, Serialize)]
+pub struct GraphWithWarnings<'a, G = cincinnati::Graph> {
+    #[serde(flatten)]
+    pub graph: &'a G,
+    #[serde(skip_serializing_if = "<[Warning]>::is_empty")]
+    pub warnings: &'a [Warning],
 }
 
 /// Struct used by the InternalPlugin trait impl's
@@ -131,6 +215,34 @@ where
     async fn run(self: &Self, t: T) -> Fallible<T>;
 
     fn get_name(self: &Self) -> &'static str;
+
+    /// Version of the plugin interface the plugin was built against.
+    fn compatibility(self: &Self) -> u32;
+
+    /// Parameters without which the plugin is a no-op, if any.
+    fn relevant_parameters(self: &Self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    /// Client parameters honored by the plugin, for documentation.
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![]
+    }
+
+    /// Age of the last graph successfully fetched by the plugin, if any.
+    fn graph_age(self: &Self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether the output of the plugin only depends on its input.
+    fn is_pure(self: &Self) -> bool {
+        false
+    }
+
+    /// Check whether the plugin is able to process graphs.
+    async fn health(self: &Self) -> PluginHealth {
+        PluginHealth::Healthy
+    }
 }
 
 /// Trait to be implemented by internal plugins with their native IO type
@@ -138,11 +250,55 @@ where
 pub trait InternalPlugin {
     const PLUGIN_NAME: &'static str;
 
+    /// Version of the plugin interface the plugin was written for.
+    ///
+    /// This must be the literal value of `PLUGIN_ABI_VERSION` at the time,
+    /// and is checked against it by `build_plugins`.
+    const ABI_VERSION: u32;
+
     async fn run_internal(self: &Self, input: InternalIO) -> Fallible<InternalIO>;
 
     fn get_name(self: &Self) -> &'static str {
         Self::PLUGIN_NAME
     }
+
+    /// Parameters without which the plugin is a no-op, if any.
+    ///
+    /// `process` skips the plugin for inputs carrying none of these parameters.
+    /// Plugins which must always run keep the default.
+    fn relevant_parameters(self: &Self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    /// Client parameters honored by the plugin, for documentation.
+    ///
+    /// These are documented in the OpenAPI document of the policy-engine.
+    fn declared_parameters(self: &Self) -> Vec<ParameterDeclaration> {
+        vec![]
+    }
+
+    /// Age of the last graph successfully fetched by the plugin, if any.
+    ///
+    /// Plugins reporting an age fetch a fresh graph on each run, regardless of
+    /// their input.
+    fn graph_age(self: &Self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether the output of the plugin only depends on its input.
+    ///
+    /// Pure plugins neither read files, query services nor depend on the
+    /// current time, so that their output can be reused for as long as their
+    /// input is unchanged. Plugins are assumed impure unless they declare
+    /// otherwise.
+    fn is_pure(self: &Self) -> bool {
+        false
+    }
+
+    /// Check whether the plugin is able to process graphs.
+    async fn health(self: &Self) -> PluginHealth {
+        PluginHealth::Healthy
+    }
 }
 
 /// Trait to be implemented by external plugins with its native IO type
@@ -156,11 +312,22 @@ where
 {
     const PLUGIN_NAME: &'static str;
 
+    /// Version of the plugin interface the plugin was written for.
+    ///
+    /// This must be the literal value of `PLUGIN_ABI_VERSION` at the time,
+    /// and is checked against it by `build_plugins`.
+    const ABI_VERSION: u32;
+
     async fn run_external(self: &Self, input: ExternalIO) -> Fallible<ExternalIO>;
 
     fn get_name(self: &Self) -> &'static str {
         Self::PLUGIN_NAME
     }
+
+    /// Check whether the plugin is able to process graphs.
+    async fn health(self: &Self) -> PluginHealth {
+        PluginHealth::Healthy
+    }
 }
 
 /// Convert from InternalIO to PluginIO
@@ -264,7 +431,8 @@ impl TryFrom<ExternalIO> for InternalIO {
 
         Ok(Self {
             graph: plugin_exchange.take_graph().into(),
-            parameters: plugin_exchange.take_parameters(),
+            parameters: plugin_exchange.take_parameters().into(),
+            warnings: Default::default(),
         })
     }
 }
@@ -277,7 +445,7 @@ impl From<InternalIO> for interface::PluginExchange {
         let mut plugin_exchange = Self::new();
 
         plugin_exchange.set_graph(internal_io.graph.into());
-        plugin_exchange.set_parameters(internal_io.parameters);
+        plugin_exchange.set_parameters(internal_io.parameters.into_map());
 
         plugin_exchange
     }
@@ -351,6 +519,30 @@ where
     fn get_name(&self) -> &'static str {
         <T as InternalPlugin>::PLUGIN_NAME
     }
+
+    fn compatibility(&self) -> u32 {
+        <T as InternalPlugin>::ABI_VERSION
+    }
+
+    fn relevant_parameters(&self) -> Option<&'static [&'static str]> {
+        self.0.relevant_parameters()
+    }
+
+    fn declared_parameters(&self) -> Vec<ParameterDeclaration> {
+        self.0.declared_parameters()
+    }
+
+    fn graph_age(&self) -> Option<Duration> {
+        self.0.graph_age()
+    }
+
+    fn is_pure(&self) -> bool {
+        self.0.is_pure()
+    }
+
+    async fn health(self: &Self) -> PluginHealth {
+        self.0.health().await
+    }
 }
 
 /// This implementation allows the process function to run ipmlementors of
@@ -370,31 +562,65 @@ where
     fn get_name(&self) -> &'static str {
         <T as ExternalPlugin>::PLUGIN_NAME
     }
+
+    fn compatibility(&self) -> u32 {
+        <T as ExternalPlugin>::ABI_VERSION
+    }
+
+    async fn health(self: &Self) -> PluginHealth {
+        self.0.health().await
+    }
 }
 
 /// Processes all given Plugins sequentially.
 ///
 /// This function automatically converts between the different IO representations
-/// if necessary.
-pub async fn process<T>(plugins: T, initial_io: PluginIO) -> Fallible<InternalIO>
+/// if necessary. Plugins declaring relevant parameters are skipped if the
+/// internal IO carries none of them. The warnings recorded by all plugins are
+/// collected in order, including across external plugins.
+pub async fn process<'a, T>(plugins: T, initial_io: PluginIO) -> Fallible<InternalIO>
 where
-    T: Iterator<Item = &'static BoxedPlugin>,
+    T: Iterator<Item = &'a BoxedPlugin>,
     T: Sync + Send,
-    T: 'static,
 {
     let mut io = initial_io;
+    let mut warnings = vec![];
 
     let _ = get_tracer().start("plugins", None);
 
     for next_plugin in plugins {
         let plugin_name = next_plugin.get_name();
+
+        if let (Some(relevant), PluginIO::InternalIO(internal_io)) =
+            (next_plugin.relevant_parameters(), &io)
+        {
+            if !relevant
+                .iter()
+                .any(|param| internal_io.parameters.contains_key(*param))
+            {
+                log::trace!("Skipping plugin '{}', no relevant parameters", plugin_name);
+                metrics::SKIPPED_RUNS
+                    .with_label_values(&[plugin_name])
+                    .inc();
+                continue;
+            }
+        }
+
         log::trace!("Running next plugin '{}'", plugin_name);
 
+        // Warnings are not part of the external representation.
+        if let PluginIO::InternalIO(internal_io) = &mut io {
+            warnings.append(&mut internal_io.warnings);
+        }
+
         let plugin_span = get_tracer().start(plugin_name, None);
         io = next_plugin.run(io).instrument(plugin_span).await?;
     }
 
-    io.try_into()
+    let mut io: InternalIO = io.try_into()?;
+    warnings.append(&mut io.warnings);
+    io.warnings = warnings;
+    Ok(io)
 }
 
 /// Wrapper around `process` with an optional timeout.
@@ -494,6 +720,7 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect(),
+            warnings: Default::default(),
         };
 
         let output_external: ExternalIO = input_internal.clone().try_into().unwrap();
@@ -514,6 +741,7 @@ mod tests {
     #[async_trait]
     impl InternalPlugin for TestInternalPlugin {
         const PLUGIN_NAME: &'static str = "test_internal_plugin";
+        const ABI_VERSION: u32 = 4;
 
         async fn run_internal(self: &Self, mut io: InternalIO) -> Fallible<InternalIO> {
             if let Some(inner_fn) = &self.inner_fn {
@@ -528,7 +756,7 @@ mod tests {
             (*dict_guard).insert(counter, true);
 
             io.parameters
-                .insert("COUNTER".to_string(), format!("{}", counter));
+                .insert_client("COUNTER", format!("{}", counter));
 
             Ok(io)
         }
@@ -539,12 +767,53 @@ mod tests {
     #[async_trait]
     impl ExternalPlugin for TestExternalPlugin {
         const PLUGIN_NAME: &'static str = "test_internal_plugin";
+        const ABI_VERSION: u32 = 4;
 
         async fn run_external(self: &Self, io: ExternalIO) -> Fallible<ExternalIO> {
             Ok(io)
         }
     }
 
+    /// Internal plugin counting the runs of the wrapped plugin.
+    #[derive(Debug)]
+    struct CountingPlugin<T> {
+        inner: T,
+        runs: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl<T> InternalPlugin for CountingPlugin<T>
+    where
+        T: InternalPlugin + Sync + Send + Debug,
+    {
+        const PLUGIN_NAME: &'static str = T::PLUGIN_NAME;
+        const ABI_VERSION: u32 = T::ABI_VERSION;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            self.inner.run_internal(io).await
+        }
+
+        fn relevant_parameters(self: &Self) -> Option<&'static [&'static str]> {
+            self.inner.relevant_parameters()
+        }
+    }
+
+    /// Internal plugin recording a warning with the given message.
+    #[derive(Debug)]
+    struct WarningPlugin(&'static str);
+
+    #[async_trait]
+    impl InternalPlugin for WarningPlugin {
+        const PLUGIN_NAME: &'static str = "test_warning_plugin";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, mut io: InternalIO) -> Fallible<InternalIO> {
+            io.warnings.push(Warning::new(Self::PLUGIN_NAME, self.0));
+            Ok(io)
+        }
+    }
+
     #[test]
     fn process_plugins_roundtrip_external_internal() -> Fallible<()> {
         let mut runtime = commons::testing::init_runtime()?;
@@ -567,6 +836,7 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect(),
+            warnings: Default::default(),
         };
 
         let expected_internalio = InternalIO {
@@ -578,6 +848,7 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
+            warnings: Default::default(),
         };
 
         let plugins_future = super::process(
@@ -614,6 +885,7 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect(),
+            warnings: Default::default(),
         };
 
         let runs: usize = 10;
@@ -628,6 +900,7 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect(),
+                warnings: Default::default(),
             };
 
             let plugins_future = process(
@@ -661,6 +934,7 @@ mod tests {
         let initial_internalio = InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         };
 
         let timeout = *PLUGIN_DELAY * 2;
@@ -706,6 +980,7 @@ mod tests {
         let initial_internalio = InternalIO {
             graph: Default::default(),
             parameters: Default::default(),
+            warnings: Default::default(),
         };
 
         // timeout hit
@@ -755,4 +1030,99 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn process_skips_plugins_without_relevant_parameters() -> Fallible<()> {
+        use internal::date_cutoff_filter::{DateCutoffFilterPlugin, BEFORE_PARAM};
+
+        let mut runtime = commons::testing::init_runtime()?;
+        let runs = Arc::new(AtomicUsize::new(0));
+        let plugins: Vec<BoxedPlugin> = new_plugins!(InternalPluginWrapper(CountingPlugin {
+            inner: DateCutoffFilterPlugin::default(),
+            runs: runs.clone(),
+        }));
+        let skipped_runs = || {
+            metrics::SKIPPED_RUNS
+                .with_label_values(&[DateCutoffFilterPlugin::PLUGIN_NAME])
+                .get()
+        };
+        let skipped_before = skipped_runs();
+
+        for (params, expected_runs) in &[
+            (vec![], 0),
+            (vec![("channel", "stable-4.6")], 0),
+            (vec![(BEFORE_PARAM, "2020-10-15T12:00:00Z")], 1),
+        ] {
+            runs.store(0, Ordering::SeqCst);
+            let io = InternalIO {
+                graph: generate_graph(),
+                parameters: params
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                warnings: Default::default(),
+            };
+
+            let processed =
+                runtime.block_on(process(plugins.iter(), PluginIO::InternalIO(io.clone())))?;
+            let unskipped = runtime.block_on(DateCutoffFilterPlugin::default().run_internal(io))?;
+
+            assert_eq!(runs.load(Ordering::SeqCst), *expected_runs, "{:?}", params);
+            assert_eq!(processed, unskipped, "{:?}", params);
+        }
+        assert_eq!(skipped_runs() - skipped_before, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn process_collects_warnings() -> Fallible<()> {
+        let mut runtime = commons::testing::init_runtime()?;
+        let plugins: Vec<BoxedPlugin> = new_plugins!(
+            InternalPluginWrapper(WarningPlugin("first")),
+            ExternalPluginWrapper(TestExternalPlugin {}),
+            InternalPluginWrapper(WarningPlugin("second"))
+        );
+        let io = InternalIO {
+            graph: generate_graph(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        };
+
+        let processed = runtime.block_on(process(plugins.iter(), PluginIO::InternalIO(io)))?;
+        assert_eq!(
+            processed.warnings,
+            vec![
+                Warning::new(WarningPlugin::PLUGIN_NAME, "first"),
+                Warning::new(WarningPlugin::PLUGIN_NAME, "second"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_graph_with_warnings() -> Fallible<()> {
+        let graph = generate_graph();
+        let graph_json = serde_json::to_value(&graph)?;
+
+        let json = serde_json::to_value(&GraphWithWarnings {
+            graph: &graph,
+            warnings: &[],
+        })?;
+        assert_eq!(json, graph_json);
+
+        let json = serde_json::to_value(&GraphWithWarnings {
+            graph: &graph,
+            warnings: &[Warning::new("test", "stale graph")],
+        })?;
+        assert_eq!(json["nodes"], graph_json["nodes"]);
+        assert_eq!(json["edges"], graph_json["edges"]);
+        assert_eq!(
+            json["warnings"],
+            serde_json::json!([{ "plugin": "test", "message": "stale graph" }])
+        );
+
+        Ok(())
+    }
 }
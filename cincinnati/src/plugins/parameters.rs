@@ -0,0 +1,231 @@
+//! Parameters passed along the plugins.
+//!
+//! Parameters come either from the client request, which is untrusted, or from
+//! the server annotating the request for the plugins, e.g. with the client
+//! capabilities or forwarded headers. Internal parameters are named with the
+//! reserved `__` prefix, which clients can't use: client parameters with this
+//! prefix are dropped when building the parameters of a request.
+//!
+//! The two namespaces are flattened into a single map when exchanged with
+//! external plugins, and told apart again by their prefix.
+
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+/// Prefix of the parameters which are reserved for the server.
+pub static RESERVED_PARAM_PREFIX: &str = "__";
+
+/// Whether a parameter name is in the reserved namespace.
+pub fn is_reserved(key: &str) -> bool {
+    key.starts_with(RESERVED_PARAM_PREFIX)
+}
+
+/// Client-provided and internally-injected parameters.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "HashMap<String, String>", into = "HashMap<String, String>")]
+pub struct Parameters {
+    client: HashMap<String, String>,
+    internal: HashMap<String, String>,
+}
+
+impl Parameters {
+    /// Build the parameters of a client request, dropping any reserved one.
+    pub fn from_client(params: HashMap<String, String>) -> Self {
+        let client = params
+            .into_iter()
+            .filter(|(key, _)| {
+                let reserved = is_reserved(key);
+                if reserved {
+                    log::debug!("dropping reserved client parameter '{}'", key);
+                }
+                !reserved
+            })
+            .collect();
+
+        Self {
+            client,
+            internal: HashMap::new(),
+        }
+    }
+
+    /// Return the value of a client parameter.
+    pub fn client(&self, key: &str) -> Option<&String> {
+        self.client.get(key)
+    }
+
+    /// Return the value of an internal parameter.
+    pub fn internal(&self, key: &str) -> Option<&String> {
+        self.internal.get(key)
+    }
+
+    /// Return the value of a parameter, in the namespace given by its name.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        if is_reserved(key) {
+            self.internal(key)
+        } else {
+            self.client(key)
+        }
+    }
+
+    /// Whether a parameter is present, in the namespace given by its name.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Set a client parameter, on behalf of the client.
+    ///
+    /// Reserved names are ignored.
+    pub fn insert_client<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let key = key.into();
+        if is_reserved(&key) {
+            log::debug!("ignoring reserved client parameter '{}'", key);
+            return;
+        }
+        self.client.insert(key, value.into());
+    }
+
+    /// Set an internal parameter.
+    ///
+    /// Its name is expected to start with `RESERVED_PARAM_PREFIX`, so that it
+    /// stays internal when exchanged with external plugins.
+    pub fn insert_internal<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.internal.insert(key.into(), value.into());
+    }
+
+    /// Return the client parameters.
+    pub fn client_params(&self) -> &HashMap<String, String> {
+        &self.client
+    }
+
+    /// Iterate over all parameters, client ones first.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.client.iter().chain(self.internal.iter())
+    }
+
+    /// Return the number of parameters.
+    pub fn len(&self) -> usize {
+        self.client.len() + self.internal.len()
+    }
+
+    /// Whether there is no parameter.
+    pub fn is_empty(&self) -> bool {
+        self.client.is_empty() && self.internal.is_empty()
+    }
+
+    /// Flatten the parameters into a single map.
+    pub fn into_map(self) -> HashMap<String, String> {
+        let mut map = self.client;
+        map.extend(self.internal);
+        map
+    }
+}
+
+/// Build parameters from a trusted source, e.g. the output of an external
+/// plugin, sorting them into namespaces by name.
+impl FromIterator<(String, String)> for Parameters {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let (internal, client) = iter.into_iter().partition(|(key, _)| is_reserved(key));
+        Self { client, internal }
+    }
+}
+
+impl From<HashMap<String, String>> for Parameters {
+    fn from(map: HashMap<String, String>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl From<Parameters> for HashMap<String, String> {
+    fn from(params: Parameters) -> Self {
+        params.into_map()
+    }
+}
+
+/// Client parameter honored by a plugin, as documented to clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParameterDeclaration {
+    /// Parameter name.
+    pub name: String,
+    /// Human-readable description.
+    pub description: String,
+    /// Regular expression matching the valid values, if restricted.
+    pub pattern: Option<String>,
+}
+
+impl ParameterDeclaration {
+    /// Declare a parameter accepting any value.
+    pub fn new<N, D>(name: N, description: D) -> Self
+    where
+        N: Into<String>,
+        D: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            pattern: None,
+        }
+    }
+
+    /// Restrict the valid values to those matching `pattern`.
+    pub fn with_pattern<P: Into<String>>(mut self, pattern: P) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn spoofed_reserved_keys_are_dropped() {
+        let mut params = Parameters::from_client(map(&[
+            ("channel", "stable-4.6"),
+            ("__capability.conditional_edges", "true"),
+        ]));
+        params.insert_client("__header.x-tier", "premium");
+
+        assert_eq!(params.client("channel"), Some(&"stable-4.6".to_string()));
+        assert_eq!(params.get("__capability.conditional_edges"), None);
+        assert_eq!(params.client("__capability.conditional_edges"), None);
+        assert_eq!(params.get("__header.x-tier"), None);
+        assert_eq!(params.len(), 1);
+    }
+
+    #[test]
+    fn internal_injection_survives() {
+        let mut params = Parameters::from_client(map(&[("channel", "stable-4.6")]));
+        params.insert_internal("__capability.conditional_edges", "true");
+
+        assert_eq!(
+            params.internal("__capability.conditional_edges"),
+            Some(&"true".to_string())
+        );
+        assert_eq!(params.client("__capability.conditional_edges"), None);
+
+        // Namespaces survive the exchange with external plugins.
+        let flattened = params.clone().into_map();
+        assert_eq!(flattened.len(), 2);
+        let exchanged: Parameters = flattened.into();
+        assert_eq!(exchanged, params);
+
+        let json = serde_json::to_string(&params).unwrap();
+        let deserialized: Parameters = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, params);
+    }
+}
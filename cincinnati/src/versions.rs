@@ -0,0 +1,127 @@
+//! Parsing and ordering of release versions.
+//!
+//! Release versions are semantic versions, with two additions for OpenShift:
+//! * build metadata, e.g. the architecture in `4.6.1+amd64`, is kept but
+//!   only breaks ties between versions of equal precedence;
+//! * four-component versions, e.g. `4.1.0.2`, are read as their first three
+//!   components with the fourth one prepended to the build metadata, so that
+//!   `4.1.0 < 4.1.0.1 < 4.1.0.2 < 4.1.1`.
+//!
+//! Components and numeric pre-release identifiers compare numerically, so
+//! `4.9.0 < 4.10.0`, and pre-releases such as nightlies and release
+//! candidates precede the release they lead to.
+
+use commons::prelude_errors::*;
+use semver::{Identifier, Version};
+use std::cmp::Ordering;
+
+/// Parse a release version, as documented at the module level.
+pub fn parse_release_version(version: &str) -> Fallible<Version> {
+    let (rest, build) = match version.find('+') {
+        Some(pos) => (&version[..pos], Some(&version[pos + 1..])),
+        None => (version, None),
+    };
+    let (core, pre) = match rest.find('-') {
+        Some(pos) => (&rest[..pos], Some(&rest[pos..])),
+        None => (rest, None),
+    };
+
+    let components: Vec<&str> = core.split('.').collect();
+    if components.len() != 4 {
+        return Version::parse(version)
+            .map_err(|e| format_err!("invalid release version '{}': {}", version, e));
+    }
+
+    let fourth: u64 = components[3]
+        .parse()
+        .map_err(|e| format_err!("invalid release version '{}': {}", version, e))?;
+    let mut parsed = Version::parse(&format!(
+        "{}{}{}",
+        components[..3].join("."),
+        pre.unwrap_or_default(),
+        build.map(|build| format!("+{}", build)).unwrap_or_default(),
+    ))
+    .map_err(|e| format_err!("invalid release version '{}': {}", version, e))?;
+    parsed.build.insert(0, Identifier::Numeric(fourth));
+
+    Ok(parsed)
+}
+
+/// Compare two parsed release versions by precedence, then by build metadata.
+///
+/// Unlike the `Ord` implementation of `Version`, this is a total order over
+/// distinct versions.
+pub fn cmp_release_versions(a: &Version, b: &Version) -> Ordering {
+    a.cmp(b).then_with(|| a.build.cmp(&b.build))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(versions: &[&str]) -> Vec<String> {
+        let mut parsed: Vec<(Version, &str)> = versions
+            .iter()
+            .map(|version| (parse_release_version(version).unwrap(), *version))
+            .collect();
+        parsed.sort_by(|(a, _), (b, _)| cmp_release_versions(a, b));
+        parsed
+            .into_iter()
+            .map(|(_, version)| version.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn ordering_edge_cases() {
+        // Expected order, shuffled before sorting.
+        let expected = vec![
+            "4.1.0",
+            "4.1.0.1",
+            "4.1.0.2",
+            "4.1.1",
+            "4.9.0-0.nightly-2021-01-02-000000",
+            "4.9.0-0.nightly-2021-01-10-000000",
+            "4.9.0-fc.0",
+            "4.9.0-rc.2",
+            "4.9.0-rc.10",
+            "4.9.0",
+            "4.9.0+amd64",
+            "4.9.0+s390x",
+            "4.9.10",
+            "4.10.0-rc.0",
+            "4.10.0",
+        ];
+        let mut shuffled = expected.clone();
+        shuffled.reverse();
+        shuffled.swap(0, 7);
+        shuffled.swap(3, 12);
+
+        assert_eq!(sorted(&shuffled), expected);
+    }
+
+    #[test]
+    fn four_component_versions() -> Fallible<()> {
+        let version = parse_release_version("4.1.0.2-rc.1+amd64")?;
+        assert_eq!(version, Version::parse("4.1.0-rc.1")?);
+        assert_eq!(
+            version.build,
+            vec![
+                Identifier::Numeric(2),
+                Identifier::AlphaNumeric("amd64".to_string())
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_versions() {
+        for version in &["", "4.1", "4.1.0.1.0", "4.1.0.x", "4.1.x.0"] {
+            assert!(
+                parse_release_version(version).is_err(),
+                "version: '{}'",
+                version
+            );
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Access logging of service requests.
+//!
+//! The `AccessLog` middleware emits one JSON line per request, with its
+//! method, path, response status, duration, client address and client
+//! parameters, at info level on the `ACCESS_LOG_TARGET` log target. Values of
+//! sensitive client parameters are redacted.
+
+use crate::http::{client_identity, IpNet};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use url::form_urlencoded;
+
+/// Log target of access log lines.
+pub static ACCESS_LOG_TARGET: &str = "access_log";
+
+/// Placeholder for the values of redacted client parameters.
+pub static REDACTED_VALUE: &str = "<redacted>";
+
+/// Access log line of a request.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AccessLogEntry {
+    /// Request method.
+    pub method: String,
+    /// Request path.
+    pub path: String,
+    /// Response status code.
+    pub status: u16,
+    /// Duration until the response head was ready, in milliseconds.
+    pub duration_ms: f64,
+    /// Client address, as resolved by `commons::http::client_identity`.
+    pub client: Option<IpAddr>,
+    /// Client parameters, with sensitive values redacted.
+    pub params: BTreeMap<String, String>,
+}
+
+/// Destination of access log lines.
+type Sink = Arc<dyn Fn(&AccessLogEntry) + Send + Sync>;
+
+/// Middleware logging each request of a service.
+#[derive(Clone)]
+pub struct AccessLog {
+    /// Client parameters whose values are redacted.
+    redacted_params: Arc<HashSet<String>>,
+    /// Proxies whose forwarding headers are honored to resolve the client address.
+    trusted_proxies: Arc<Vec<IpNet>>,
+    /// Destination of log lines.
+    sink: Sink,
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("redacted_params", &self.redacted_params)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .finish()
+    }
+}
+
+impl AccessLog {
+    /// Create the middleware, redacting the values of the given client parameters.
+    pub fn new(redacted_params: HashSet<String>) -> Self {
+        Self::with_sink(redacted_params, Arc::new(log_entry))
+    }
+
+    /// Create the middleware with a custom destination for log lines.
+    fn with_sink(redacted_params: HashSet<String>, sink: Sink) -> Self {
+        Self {
+            redacted_params: Arc::new(redacted_params),
+            trusted_proxies: Arc::new(vec![]),
+            sink,
+        }
+    }
+
+    /// Honor the forwarding headers set by the given proxies when logging the client address.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpNet>) -> Self {
+        self.trusted_proxies = Arc::new(trusted_proxies);
+        self
+    }
+
+    /// Extract the client parameters of a query string, redacting sensitive values.
+    fn params(&self, query: &str) -> BTreeMap<String, String> {
+        redact_params(query, &self.redacted_params)
+    }
+}
+
+/// Extract the client parameters of a query string, redacting the values of `redacted_params`.
+pub fn redact_params(query: &str, redacted_params: &HashSet<String>) -> BTreeMap<String, String> {
+    form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .map(|(key, value)| {
+            if redacted_params.contains(&key) {
+                (key, REDACTED_VALUE.to_string())
+            } else {
+                (key, value)
+            }
+        })
+        .collect()
+}
+
+/// Log an access log line as JSON.
+fn log_entry(entry: &AccessLogEntry) {
+    match serde_json::to_string(entry) {
+        Ok(line) => log::info!(target: ACCESS_LOG_TARGET, "{}", line),
+        Err(e) => log::warn!("failed to serialize access log line: {}", e),
+    }
+}
+
+impl<S, B> Transform<S> for AccessLog
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AccessLogMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessLogMiddleware {
+            service,
+            access_log: self.clone(),
+        })
+    }
+}
+
+/// Service wrapped by the `AccessLog` middleware.
+pub struct AccessLogMiddleware<S> {
+    service: S,
+    access_log: AccessLog,
+}
+
+impl<S, B> Service for AccessLogMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let params = self.access_log.params(req.query_string());
+        let client = client_identity(req.request(), &self.access_log.trusted_proxies);
+        let sink = self.access_log.sink.clone();
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(resp) => resp.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            sink(&AccessLogEntry {
+                method,
+                path,
+                status: status.as_u16(),
+                duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+                client,
+                params,
+            });
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use actix_web::{web, App, HttpResponse};
+    use std::sync::Mutex;
+
+    #[test]
+    fn logs_successful_request() {
+        let entries: Arc<Mutex<Vec<AccessLogEntry>>> = Arc::new(Mutex::new(vec![]));
+        let sink_entries = entries.clone();
+        let access_log = AccessLog::with_sink(
+            vec!["id".to_string()].into_iter().collect(),
+            Arc::new(move |entry: &AccessLogEntry| {
+                sink_entries.lock().unwrap().push(entry.clone())
+            }),
+        )
+        .with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+
+        let mut rt = crate::testing::init_runtime().unwrap();
+        let status = rt.block_on(async {
+            let mut svc = actix_web::test::init_service(
+                App::new()
+                    .wrap(access_log)
+                    .route("/v1/graph", web::get().to(|| HttpResponse::Ok().finish())),
+            )
+            .await;
+
+            let req = TestRequest::with_uri("/v1/graph?channel=stable-4.6&id=secret")
+                .peer_addr("10.0.0.1:8080".parse().unwrap())
+                .header("x-forwarded-for", "192.0.2.7")
+                .to_request();
+            actix_web::test::call_service(&mut svc, req).await.status()
+        });
+        assert_eq!(status, 200);
+
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.method, "GET");
+        assert_eq!(entry.path, "/v1/graph");
+        assert_eq!(entry.status, 200);
+        assert!(entry.duration_ms >= 0.0);
+        assert_eq!(entry.client, Some("192.0.2.7".parse().unwrap()));
+        assert_eq!(
+            entry.params,
+            vec![
+                ("channel".to_string(), "stable-4.6".to_string()),
+                ("id".to_string(), REDACTED_VALUE.to_string()),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        let line = serde_json::to_value(entry).unwrap();
+        for field in &[
+            "method",
+            "path",
+            "status",
+            "duration_ms",
+            "client",
+            "params",
+        ] {
+            assert!(line.get(field).is_some(), "missing field '{}'", field);
+        }
+    }
+}
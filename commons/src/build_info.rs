@@ -0,0 +1,137 @@
+//! Build information service.
+
+use actix_web::HttpResponse;
+
+/// Optional features which may be enabled at runtime.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OptionalFeatures {
+    /// Whether traces are reported to a tracing endpoint.
+    pub tracing: bool,
+    /// Whether the service terminates TLS itself.
+    pub tls: bool,
+}
+
+/// Machine-readable build information for a service.
+///
+/// The fields are expected to be populated from the `built_info` constants
+/// generated at build time for each service.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Git commit the service has been built from, if known.
+    pub git_commit: Option<String>,
+    /// Version of the compiler which built the service.
+    pub rustc_version: String,
+    /// UTC timestamp of the build.
+    pub build_time: String,
+    /// Cargo features enabled at build time.
+    pub cargo_features: Vec<String>,
+    /// Version of the service crate.
+    pub version: String,
+    /// Optional runtime features.
+    pub features: OptionalFeatures,
+}
+
+/// Assemble the `BuildInfo` of a service from its `built_info` module.
+///
+/// The module is expected to include the constants generated at build time
+/// by the `built` crate; `$tracing` tells whether traces are reported.
+#[macro_export]
+macro_rules! build_info {
+    ($built_info:ident, $tracing:expr) => {
+        $crate::build_info::BuildInfo {
+            git_commit: $built_info::GIT_VERSION.map(::std::string::ToString::to_string),
+            rustc_version: $built_info::RUSTC_VERSION.to_string(),
+            build_time: $built_info::BUILT_TIME_UTC.to_string(),
+            cargo_features: $built_info::FEATURES
+                .iter()
+                .map(::std::string::ToString::to_string)
+                .collect(),
+            version: $built_info::PKG_VERSION.to_string(),
+            features: $crate::build_info::OptionalFeatures {
+                tracing: $tracing,
+                tls: false,
+            },
+        }
+    };
+}
+
+/// Serve build information requests (JSON format).
+pub async fn serve(app_data: actix_web::web::Data<BuildInfo>) -> HttpResponse {
+    HttpResponse::Ok().json(app_data.get_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude_errors::*;
+    use crate::testing;
+
+    #[test]
+    fn serve_build_info_basic() -> Fallible<()> {
+        let mut rt = testing::init_runtime()?;
+
+        let build_info = BuildInfo {
+            git_commit: Some("0123456789abcdef".to_string()),
+            rustc_version: "rustc 1.50.0".to_string(),
+            build_time: "Thu, 01 Jan 1970 00:00:00 +0000".to_string(),
+            cargo_features: vec!["test-net".to_string()],
+            version: "0.1.0".to_string(),
+            features: OptionalFeatures {
+                tracing: true,
+                tls: false,
+            },
+        };
+
+        let resp = rt.block_on(serve(actix_web::web::Data::new(build_info.clone())));
+
+        assert_eq!(resp.status(), 200);
+        if let actix_web::body::ResponseBody::Body(body) = resp.body() {
+            if let actix_web::body::Body::Bytes(bytes) = body {
+                let served: BuildInfo = serde_json::from_slice(bytes.as_ref())?;
+                assert_eq!(served.git_commit, build_info.git_commit);
+                assert_eq!(served, build_info);
+            } else {
+                bail!("expected Body")
+            }
+        } else {
+            bail!("expected bytes in body")
+        };
+
+        Ok(())
+    }
+
+    mod built_info {
+        pub const GIT_VERSION: Option<&str> = Some("0123456789abcdef");
+        pub const RUSTC_VERSION: &str = "rustc 1.50.0";
+        pub const BUILT_TIME_UTC: &str = "Thu, 01 Jan 1970 00:00:00 +0000";
+        pub const FEATURES: &[&str] = &["TEST_NET"];
+        pub const PKG_VERSION: &str = "0.1.0";
+    }
+
+    #[test]
+    fn serve_build_info_commit() -> Fallible<()> {
+        let mut rt = testing::init_runtime()?;
+
+        let resp = rt.block_on(serve(actix_web::web::Data::new(crate::build_info!(
+            built_info, true
+        ))));
+
+        assert_eq!(resp.status(), 200);
+        if let actix_web::body::ResponseBody::Body(body) = resp.body() {
+            if let actix_web::body::Body::Bytes(bytes) = body {
+                let served: BuildInfo = serde_json::from_slice(bytes.as_ref())?;
+                assert_eq!(served.git_commit.as_deref(), built_info::GIT_VERSION);
+                assert_eq!(served.cargo_features, vec!["TEST_NET".to_string()]);
+                assert_eq!(served.version, built_info::PKG_VERSION);
+                assert!(served.features.tracing);
+                assert!(!served.features.tls);
+            } else {
+                bail!("expected Body")
+            }
+        } else {
+            bail!("expected bytes in body")
+        };
+
+        Ok(())
+    }
+}
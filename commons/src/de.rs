@@ -1,5 +1,8 @@
 //! Deserializers.
 
+use crate::prelude_errors::*;
+use serde_path_to_error::Segment;
+
 /// Deserialize a log-level from a numerical value.
 pub fn de_loglevel<'de, D>(deserializer: D) -> Result<Option<log::LevelFilter>, D::Error>
 where
@@ -16,3 +19,149 @@ where
     };
     Ok(Some(verbosity))
 }
+
+/// Deserialize the TOML configuration of the plugin `plugin_name`.
+///
+/// Errors name the plugin and the path of the offending field, e.g.
+/// `plugin 'quay-metadata': field 'api_base': invalid type: ...`.
+///
+/// Deserialization stops at the first error, so the offending top-level field
+/// is then dropped and deserialization retried, in order to report the errors
+/// of all top-level fields at once. Retrying stops once a dropped field is
+/// reported missing, as it is required.
+pub fn from_toml_named<T>(value: toml::Value, plugin_name: &str) -> Fallible<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let mut value = value;
+    let mut errors = vec![];
+    let mut dropped: Vec<String> = vec![];
+
+    loop {
+        let err = match serde_path_to_error::deserialize(value.clone()) {
+            Ok(parsed) if errors.is_empty() => return Ok(parsed),
+            Ok(_) => break,
+            Err(err) => err,
+        };
+
+        // A dropped required field is reported missing, which is no error of the input.
+        let message = err.inner().to_string();
+        if err.path().iter().next().is_none()
+            && dropped
+                .iter()
+                .any(|key| message == format!("missing field `{}`", key))
+        {
+            break;
+        }
+
+        let key = match err.path().iter().next() {
+            Some(Segment::Map { key }) => Some(key.clone()),
+            _ => None,
+        };
+        errors.push(match key {
+            Some(_) => format!("field '{}': {}", err.path(), message),
+            None => message,
+        });
+
+        let retry = match (key, value.as_table_mut()) {
+            (Some(key), Some(table)) => {
+                let removed = table.remove(&key).is_some();
+                dropped.push(key);
+                removed
+            }
+            _ => false,
+        };
+        if !retry {
+            break;
+        }
+    }
+
+    bail!("plugin '{}': {}", plugin_name, errors.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, Deserialize, PartialEq)]
+    #[serde(default)]
+    struct Nested {
+        url: String,
+        timeout: u64,
+    }
+
+    #[derive(Debug, Default, Deserialize, PartialEq)]
+    #[serde(default)]
+    struct Settings {
+        name: String,
+        repository: String,
+        upstream: Nested,
+    }
+
+    fn parse(input: &str) -> Fallible<Settings> {
+        from_toml_named(toml::from_str(input).unwrap(), "test-plugin")
+    }
+
+    #[test]
+    fn valid_settings() -> Fallible<()> {
+        let settings = parse("repository = 'repo'\n[upstream]\ntimeout = 30")?;
+        assert_eq!(settings.repository, "repo");
+        assert_eq!(settings.upstream.timeout, 30);
+
+        Ok(())
+    }
+
+    #[test]
+    fn error_names_plugin_and_field() {
+        let err = parse("repository = 1").unwrap_err().to_string();
+        assert!(
+            err.starts_with("plugin 'test-plugin': field 'repository': invalid type: integer"),
+            "unexpected error: {}",
+            err
+        );
+
+        let err = parse("[upstream]\ntimeout = 'soon'")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("plugin 'test-plugin': field 'upstream.timeout': invalid type: string"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn errors_of_all_fields() {
+        let err = parse("repository = 1\nname = 2\n[upstream]\nurl = 3")
+            .unwrap_err()
+            .to_string();
+        for field in &["'name'", "'repository'", "'upstream.url'"] {
+            assert!(err.contains(field), "missing {} in error: {}", field, err);
+        }
+        assert_eq!(err.matches("invalid type").count(), 3, "{}", err);
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct RequiredSettings {
+        name: String,
+        repository: String,
+    }
+
+    #[test]
+    fn errors_of_dropped_required_fields() {
+        let err = from_toml_named::<RequiredSettings>(
+            toml::from_str("name = 1\nrepository = 'repo'").unwrap(),
+            "test-plugin",
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(
+            err.starts_with("plugin 'test-plugin': field 'name': invalid type: integer"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(!err.contains("missing field"), "unexpected error: {}", err);
+    }
+}
@@ -1,6 +1,8 @@
+use crate::http::{ResponseHeaders, DEFAULT_ERROR_RESPONSE_HEADERS};
 use actix_web::http;
 use actix_web::HttpResponse;
 use prometheus::{IntCounterVec, Opts, Registry};
+use std::sync::RwLock;
 use thiserror::Error;
 
 pub mod prelude {
@@ -25,6 +27,10 @@ lazy_static! {
         &["code", "kind"]
     )
     .unwrap();
+
+    /// Headers set on error responses.
+    static ref ERROR_RESPONSE_HEADERS: RwLock<ResponseHeaders> =
+        RwLock::new(ResponseHeaders::from_static(DEFAULT_ERROR_RESPONSE_HEADERS));
 }
 
 /// Panic message for a request with a missing appstate.
@@ -38,6 +44,14 @@ pub fn register_metrics(registry: &Registry) -> Fallible<()> {
     Ok(())
 }
 
+/// Set the headers of all error responses, replacing the defaults.
+pub fn set_error_response_headers(headers: ResponseHeaders) {
+    match ERROR_RESPONSE_HEADERS.write() {
+        Ok(mut live) => *live = headers,
+        Err(poisoned) => *poisoned.into_inner() = headers,
+    }
+}
+
 #[derive(Debug, Error, Eq, PartialEq)]
 /// Error that can be returned by `/v1/graph` endpoint.
 pub enum GraphError {
@@ -76,6 +90,18 @@ pub enum GraphError {
     /// Failed to parse as Semantic Version
     #[error("failed to process version: {}", _0)]
     ArchVersionError(String),
+
+    /// Serialized graph exceeds the configured maximum size, in bytes.
+    #[error("serialized graph exceeds the maximum size of {} bytes", _0)]
+    GraphTooLarge(usize),
+
+    /// Requested release not found in the graph.
+    #[error("release '{}' not found", _0)]
+    ReleaseNotFound(String),
+
+    /// Service deliberately unavailable, with the seconds after which to retry, if known.
+    #[error("{}", _0)]
+    ServiceUnavailable(String, Option<u64>),
 }
 
 impl actix_web::error::ResponseError for GraphError {
@@ -85,19 +111,40 @@ impl actix_web::error::ResponseError for GraphError {
         V1_GRAPH_ERRORS
             .with_label_values(&[code.as_str(), &kind])
             .inc();
-        self.as_json_error()
+        self.localized_response(&self.value())
     }
 }
 
 impl GraphError {
     /// Return the HTTP JSON error response.
     pub fn as_json_error(&self) -> HttpResponse {
+        self.as_localized_json_error(&self.value())
+    }
+
+    /// Return the HTTP JSON error response, with the given user-facing message.
+    pub fn as_localized_json_error(&self, message: &str) -> HttpResponse {
         let code = self.status_code();
         let json_body = json!({
             "kind": self.kind(),
             "value": self.value(),
+            "message": message,
         });
-        HttpResponse::build(code).json(json_body)
+        let mut response = HttpResponse::build(code);
+        if let GraphError::ServiceUnavailable(_, Some(retry_after)) = self {
+            response.header(http::header::RETRY_AFTER, retry_after.to_string());
+        }
+        response.json(json_body)
+    }
+
+    /// Return the error response with the given user-facing message.
+    ///
+    /// Unlike `error_response`, this doesn't record the error in metrics.
+    pub fn localized_response(&self, message: &str) -> HttpResponse {
+        let mut response = self.as_localized_json_error(message);
+        if let Ok(headers) = ERROR_RESPONSE_HEADERS.read() {
+            headers.apply(response.headers_mut());
+        }
+        response
     }
 
     /// Return the HTTP status code for the error.
@@ -112,6 +159,9 @@ impl GraphError {
             GraphError::MissingParams(_) => http::StatusCode::BAD_REQUEST,
             GraphError::InvalidParams(_) => http::StatusCode::BAD_REQUEST,
             GraphError::ArchVersionError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            GraphError::GraphTooLarge(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            GraphError::ReleaseNotFound(_) => http::StatusCode::NOT_FOUND,
+            GraphError::ServiceUnavailable(_, _) => http::StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 
@@ -127,6 +177,9 @@ impl GraphError {
             GraphError::MissingParams(_) => "missing_params",
             GraphError::InvalidParams(_) => "invalid_params",
             GraphError::ArchVersionError(_) => "arch_version_error",
+            GraphError::GraphTooLarge(_) => "graph_too_large",
+            GraphError::ReleaseNotFound(_) => "release_not_found",
+            GraphError::ServiceUnavailable(_, _) => "service_unavailable",
         };
         kind.to_string()
     }
@@ -139,6 +192,24 @@ impl GraphError {
             _ => error_msg,
         }
     }
+
+    /// Return the detail of the error, for message templates.
+    pub fn detail(&self) -> String {
+        match self {
+            GraphError::FailedJsonIn(detail)
+            | GraphError::FailedJsonOut(detail)
+            | GraphError::FailedUpstreamFetch(detail)
+            | GraphError::FailedPluginExecution(detail)
+            | GraphError::InvalidParams(detail)
+            | GraphError::ArchVersionError(detail)
+            | GraphError::ReleaseNotFound(detail)
+            | GraphError::ServiceUnavailable(detail, _) => detail.clone(),
+            GraphError::GraphTooLarge(size) => size.to_string(),
+            GraphError::MissingParams(params) => params.join(", "),
+            // The upstream request failure isn't exposed to clients.
+            GraphError::FailedUpstreamRequest(_) | GraphError::InvalidContentType => String::new(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -157,4 +228,13 @@ mod tests {
         assert!(err_msg.contains("bar, foo"), "unexpected: {}", err_msg);
         assert!(!err_msg.contains("key"), "unexpected: {}", err_msg);
     }
+
+    #[test]
+    fn error_response_headers() {
+        use actix_web::error::ResponseError;
+        use actix_web::http::header::CACHE_CONTROL;
+
+        let response = super::GraphError::InvalidContentType.error_response();
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+    }
 }
@@ -0,0 +1,214 @@
+//! Request extractors performing the common validation of client requests.
+//!
+//! Extraction failures are reported as `GraphError`s, so that handlers using
+//! the extractors serve the same error bodies as the ones validating requests
+//! by hand.
+
+use crate::{ensure_content_type, ensure_query_params, GraphError};
+use actix_web::dev::Payload;
+use actix_web::web::Query;
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::ops::Deref;
+
+/// Media type of JSON responses.
+pub static JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Extractor ensuring that the client accepts JSON responses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AcceptsJson;
+
+impl FromRequest for AcceptsJson {
+    type Error = GraphError;
+    type Future = Ready<Result<Self, GraphError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(ensure_content_type(req.headers(), JSON_CONTENT_TYPE).map(|_| AcceptsJson))
+    }
+}
+
+/// Configuration of the `ValidatedQuery` extractor, registered as app data.
+///
+/// Without it, no query parameter is mandatory.
+#[derive(Clone, Debug, Default)]
+pub struct ValidatedQueryConfig {
+    /// Query parameters that must be present in all client requests.
+    pub mandatory_params: HashSet<String>,
+}
+
+impl ValidatedQueryConfig {
+    /// Create a configuration requiring the given query parameters.
+    pub fn new(mandatory_params: HashSet<String>) -> Self {
+        Self { mandatory_params }
+    }
+}
+
+/// Extractor checking the mandatory query parameters, and deserializing the query into `T`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T> ValidatedQuery<T> {
+    /// Unwrap the deserialized query.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedQuery<T>
+where
+    T: DeserializeOwned,
+{
+    type Error = GraphError;
+    type Future = Ready<Result<Self, GraphError>>;
+    type Config = ValidatedQueryConfig;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let validated = || -> Result<Self, GraphError> {
+            if let Some(config) = req.app_data::<Self::Config>() {
+                ensure_query_params(&config.mandatory_params, req.query_string())?;
+            }
+
+            Query::<T>::from_query(req.query_string())
+                .map(|query| ValidatedQuery(query.into_inner()))
+                .map_err(|e| GraphError::InvalidParams(e.to_string()))
+        };
+
+        ready(validated())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::TestRequest;
+    use actix_web::{web, App, HttpResponse};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Deserialize, PartialEq, Eq)]
+    struct ChannelQuery {
+        channel: String,
+        arch: Option<String>,
+    }
+
+    fn config(mandatory_params: &[&str]) -> ValidatedQueryConfig {
+        ValidatedQueryConfig::new(mandatory_params.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn accepts_json() {
+        let mut rt = crate::testing::init_runtime().unwrap();
+
+        let req = TestRequest::default()
+            .header(header::ACCEPT, JSON_CONTENT_TYPE)
+            .to_http_request();
+        assert_eq!(rt.block_on(AcceptsJson::extract(&req)), Ok(AcceptsJson));
+
+        for accept in &[None, Some("text/html")] {
+            let mut req = TestRequest::default();
+            if let Some(accept) = accept {
+                req = req.header(header::ACCEPT, *accept);
+            }
+            assert_eq!(
+                rt.block_on(AcceptsJson::extract(&req.to_http_request())),
+                Err(GraphError::InvalidContentType)
+            );
+        }
+    }
+
+    #[test]
+    fn validated_query() {
+        let mut rt = crate::testing::init_runtime().unwrap();
+
+        let req = TestRequest::with_uri("/?channel=stable&arch=amd64")
+            .app_data(config(&["channel"]))
+            .to_http_request();
+        let query = rt
+            .block_on(ValidatedQuery::<ChannelQuery>::extract(&req))
+            .unwrap();
+        assert_eq!(
+            query.into_inner(),
+            ChannelQuery {
+                channel: "stable".to_string(),
+                arch: Some("amd64".to_string()),
+            }
+        );
+
+        // Mandatory parameters are checked before deserialization.
+        let req = TestRequest::with_uri("/?arch=amd64")
+            .app_data(config(&["channel", "id"]))
+            .to_http_request();
+        assert_eq!(
+            rt.block_on(ValidatedQuery::<ChannelQuery>::extract(&req)),
+            Err(GraphError::MissingParams(vec![
+                "channel".to_string(),
+                "id".to_string()
+            ]))
+        );
+
+        // Without configuration, only deserialization can fail.
+        let req = TestRequest::with_uri("/?arch=amd64").to_http_request();
+        match rt.block_on(ValidatedQuery::<ChannelQuery>::extract(&req)) {
+            Err(GraphError::InvalidParams(_)) => {}
+            res => panic!("expected InvalidParams error, got: {:?}", res),
+        }
+        let query = rt
+            .block_on(ValidatedQuery::<HashMap<String, String>>::extract(&req))
+            .unwrap();
+        assert_eq!(query.get("arch").map(String::as_str), Some("amd64"));
+    }
+
+    #[test]
+    fn extractors_in_handler() {
+        async fn handler(
+            _: AcceptsJson,
+            query: ValidatedQuery<ChannelQuery>,
+        ) -> Result<HttpResponse, GraphError> {
+            Ok(HttpResponse::Ok().body(query.channel.clone()))
+        }
+
+        let mut rt = crate::testing::init_runtime().unwrap();
+        let statuses = rt.block_on(async {
+            let mut svc = actix_web::test::init_service(
+                App::new()
+                    .app_data(config(&["channel"]))
+                    .route("/graph", web::get().to(handler)),
+            )
+            .await;
+
+            let mut statuses = vec![];
+            for (uri, accept) in &[
+                ("/graph?channel=stable", JSON_CONTENT_TYPE),
+                ("/graph?channel=stable", "text/html"),
+                ("/graph", JSON_CONTENT_TYPE),
+            ] {
+                let req = TestRequest::with_uri(uri)
+                    .header(header::ACCEPT, *accept)
+                    .to_request();
+                let resp = actix_web::test::call_service(&mut svc, req).await;
+                statuses.push(resp.status());
+            }
+            statuses
+        });
+
+        assert_eq!(
+            statuses,
+            vec![
+                StatusCode::OK,
+                StatusCode::NOT_ACCEPTABLE,
+                StatusCode::BAD_REQUEST
+            ]
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! HTTP helpers shared by the Cincinnati services.
+
+use crate::prelude_errors::*;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::HeaderMap;
+use actix_web::HttpRequest;
+pub use ipnet::IpNet;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Non-standard header commonly set by reverse proxies.
+static X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Prefix of the plugin parameters carrying forwarded request headers, by
+/// lowercase header name.
+pub static HEADER_PARAM_PREFIX: &str = "__header.";
+
+/// Response headers set by the services themselves, which can't be configured.
+static RESERVED_RESPONSE_HEADERS: &[&str] = &["content-type", "content-length"];
+
+/// Default headers of error responses, by lowercase name.
+pub static DEFAULT_ERROR_RESPONSE_HEADERS: &[(&str, &str)] = &[("cache-control", "no-store")];
+
+/// Configured headers, set on responses.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResponseHeaders(Vec<(HeaderName, HeaderValue)>);
+
+impl ResponseHeaders {
+    /// Headers from static, lowercase names and values.
+    pub fn from_static(headers: &[(&'static str, &'static str)]) -> Self {
+        Self(
+            headers
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        HeaderName::from_static(name),
+                        HeaderValue::from_static(value),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Parse the configured headers, by name, rejecting reserved ones.
+    pub fn try_from_map(headers: &BTreeMap<String, String>) -> Fallible<Self> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .context(format!("invalid response header name '{}'", name))?;
+                ensure!(
+                    !RESERVED_RESPONSE_HEADERS.contains(&name.as_str()),
+                    "reserved response header '{}'",
+                    name
+                );
+                let value = HeaderValue::from_str(value)
+                    .context(format!("invalid value for response header '{}'", name))?;
+                Ok((name, value))
+            })
+            .collect::<Fallible<_>>()
+            .map(Self)
+    }
+
+    /// Add the given request headers to `Vary`, unless already listed.
+    pub fn vary(mut self, names: &[&str]) -> Self {
+        let position = self.0.iter().position(|(name, _)| *name == header::VARY);
+        let mut vary: Vec<String> = position
+            .and_then(|pos| self.0[pos].1.to_str().ok())
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(ToString::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        for name in names {
+            if !vary.iter().any(|listed| listed.eq_ignore_ascii_case(name)) {
+                vary.push(name.to_string());
+            }
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&vary.join(", ")) {
+            match position {
+                Some(pos) => self.0[pos].1 = value,
+                None => self.0.push((header::VARY, value)),
+            }
+        }
+        self
+    }
+
+    /// Set the headers on a response, replacing existing values.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.0 {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Determine the address of the client which originated `req`.
+///
+/// Forwarding headers are only honored if the socket peer is one of the
+/// `trusted_proxies`. In that case the hops listed in the standard `Forwarded`
+/// header (or, if absent, in `X-Forwarded-For`) are walked from the right,
+/// skipping trusted proxies, and the first untrusted hop is returned. If a hop
+/// can't be parsed, the last trusted hop which reported it is returned instead.
+///
+/// Returns `None` only if the socket peer address is not available.
+pub fn client_identity(req: &HttpRequest, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let is_trusted = |addr: &IpAddr| trusted_proxies.iter().any(|net| net.contains(addr));
+
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+
+    let hops = forwarded_hops(req);
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        match hop {
+            Some(addr) => {
+                client = *addr;
+                if !is_trusted(addr) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    Some(client)
+}
+
+/// Collect the forwarding hops in the order they were appended by proxies.
+///
+/// Unparsable entries (e.g. obfuscated identifiers) are kept as `None`.
+fn forwarded_hops(req: &HttpRequest) -> Vec<Option<IpAddr>> {
+    let headers = req.headers();
+    let values = |name: &HeaderName| -> Vec<String> {
+        headers
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .map(ToString::to_string)
+            .collect()
+    };
+
+    let forwarded = values(&header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let mut kv = pair.splitn(2, '=');
+                    match (kv.next(), kv.next()) {
+                        (Some(key), Some(node)) if key.trim().eq_ignore_ascii_case("for") => {
+                            Some(parse_node(node))
+                        }
+                        _ => None,
+                    }
+                })
+            })
+            .collect();
+    }
+
+    values(&HeaderName::from_static(X_FORWARDED_FOR))
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// Parse a node identifier, which may be quoted, bracketed and carry a port.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    if node.starts_with('[') {
+        if let Some(end) = node.find(']') {
+            return node[1..end].parse().ok();
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn trusted(nets: &[&str]) -> Vec<IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn no_forwarding_headers() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .to_http_request();
+
+        assert_eq!(client_identity(&req, &[]), ip("10.0.0.1"));
+        assert_eq!(
+            client_identity(&req, &trusted(&["10.0.0.0/8"])),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn no_peer_address() {
+        let req = TestRequest::default().to_http_request();
+
+        assert_eq!(client_identity(&req, &trusted(&["10.0.0.0/8"])), None);
+    }
+
+    #[test]
+    fn nested_proxies() {
+        let proxies = trusted(&["10.0.0.0/8", "192.168.0.0/16"]);
+
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .header("X-Forwarded-For", "203.0.113.7, 192.168.1.1")
+            .header("X-Forwarded-For", "10.1.2.3")
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("203.0.113.7"));
+
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .header(
+                header::FORWARDED,
+                "for=203.0.113.7;proto=https, for=\"192.168.1.1:8080\";by=10.0.0.1",
+            )
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn spoofed_headers() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+
+        // An untrusted peer can't claim to be someone else.
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.7:1234".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1")
+            .header(header::FORWARDED, "for=198.51.100.1")
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("203.0.113.7"));
+
+        // Entries prepended by the client are ignored once an untrusted hop is found.
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1, 203.0.113.7")
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("203.0.113.7"));
+
+        // Garbage stops the walk at the last trusted hop.
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1, garbage, 10.0.0.2")
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn ipv6_addresses() {
+        let proxies = trusted(&["fd00::/8"]);
+
+        let req = TestRequest::default()
+            .peer_addr("[fd00::1]:1234".parse().unwrap())
+            .header("X-Forwarded-For", "2001:db8::17, fd00::2")
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("2001:db8::17"));
+
+        let req = TestRequest::default()
+            .peer_addr("[fd00::1]:1234".parse().unwrap())
+            .header(
+                header::FORWARDED,
+                "for=\"[2001:db8:cafe::17]:4711\", for=\"[fd00::2]\"",
+            )
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("2001:db8:cafe::17"));
+
+        let req = TestRequest::default()
+            .peer_addr("[2001:db8::1]:1234".parse().unwrap())
+            .header("X-Forwarded-For", "2001:db8::17")
+            .to_http_request();
+        assert_eq!(client_identity(&req, &proxies), ip("2001:db8::1"));
+    }
+
+    fn headers_map(headers: &[(&str, &str)]) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn response_headers() -> Fallible<()> {
+        let headers = ResponseHeaders::try_from_map(&headers_map(&[
+            ("Cache-Control", "public, max-age=60"),
+            ("Vary", "Origin, accept"),
+        ]))?
+        .vary(&["Accept", "Accept-Encoding"]);
+
+        let mut map = HeaderMap::new();
+        map.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        headers.apply(&mut map);
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map.get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=60"
+        );
+        assert_eq!(
+            map.get(header::VARY).unwrap(),
+            "Origin, accept, Accept-Encoding"
+        );
+
+        let headers = ResponseHeaders::default().vary(&["Accept"]);
+        let mut map = HeaderMap::new();
+        headers.apply(&mut map);
+        assert_eq!(map.get(header::VARY).unwrap(), "Accept");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_response_headers() {
+        for name in &["Content-Type", "content-length"] {
+            let err = ResponseHeaders::try_from_map(&headers_map(&[(name, "x")])).unwrap_err();
+            assert!(err.to_string().contains("reserved"), "{}: {}", name, err);
+        }
+
+        ResponseHeaders::try_from_map(&headers_map(&[("not a name", "x")])).unwrap_err();
+        ResponseHeaders::try_from_map(&headers_map(&[("x-valid", "bad\nvalue")])).unwrap_err();
+    }
+}
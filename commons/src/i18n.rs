@@ -0,0 +1,269 @@
+//! Localization of the user-facing messages of error responses.
+//!
+//! Message catalogs are JSON files named after their language tag (e.g.
+//! `fr.json`, `pt-br.json`), mapping error kinds to message templates.
+//! Templates can refer to `{detail}`, the detail of the error (e.g. the
+//! invalid parameter or the missing release), and `{params}`, the
+//! comma-separated missing parameters of a `missing_params` error.
+//!
+//! Only the `message` field of error responses is localized: the `kind` and
+//! `value` fields, as well as logs, are always in English.
+
+use crate::errors::GraphError;
+use crate::prelude_errors::*;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{HeaderValue, ACCEPT_LANGUAGE, VARY};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// File extension of message catalogs.
+static CATALOG_EXTENSION: &str = "json";
+
+/// Message catalogs of error responses, by lowercase language tag.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorCatalogs {
+    catalogs: HashMap<String, HashMap<String, String>>,
+}
+
+impl ErrorCatalogs {
+    /// Read all message catalogs of a directory.
+    ///
+    /// Files without a `.json` extension are ignored.
+    pub fn read_dir<P>(dir: P) -> Fallible<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        let mut catalogs = HashMap::new();
+        let entries = std::fs::read_dir(dir).context(format!(
+            "failed to read message catalogs from '{}'",
+            dir.display()
+        ))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(CATALOG_EXTENSION) {
+                continue;
+            }
+            let language = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) if !stem.is_empty() => stem.to_lowercase(),
+                _ => continue,
+            };
+            let content = std::fs::read_to_string(&path)
+                .context(format!("failed to read '{}'", path.display()))?;
+            let messages: HashMap<String, String> = serde_json::from_str(&content)
+                .context(format!("invalid message catalog '{}'", path.display()))?;
+            catalogs.insert(language, messages);
+        }
+        Ok(Self { catalogs })
+    }
+
+    /// Add a message catalog, replacing any previous one for the language.
+    pub fn insert<S>(&mut self, language: S, messages: HashMap<String, String>)
+    where
+        S: AsRef<str>,
+    {
+        self.catalogs
+            .insert(language.as_ref().to_lowercase(), messages);
+    }
+
+    /// Return whether there are no catalogs.
+    pub fn is_empty(&self) -> bool {
+        self.catalogs.is_empty()
+    }
+
+    /// Choose the best available catalog for an `Accept-Language` header value.
+    ///
+    /// Language ranges are tried by decreasing quality, in header order for
+    /// equal qualities. A range matches a catalog of the same tag or, failing
+    /// that, of its primary subtag (e.g. `fr-CA` matches `fr`). Ranges with a
+    /// zero or invalid quality, and the `*` wildcard, are ignored.
+    pub fn negotiate(&self, accept_language: &str) -> Option<&str> {
+        let mut ranges: Vec<(String, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next()?.to_lowercase();
+                if tag.is_empty() || tag == "*" {
+                    return None;
+                }
+                let mut quality = 1.0;
+                for param in parts {
+                    let mut kv = param.splitn(2, '=').map(str::trim);
+                    if kv.next() == Some("q") {
+                        quality = kv.next()?.parse::<f32>().ok()?;
+                    }
+                }
+                if quality > 0.0 && quality <= 1.0 {
+                    Some((tag, quality))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        // The sort is stable, keeping header order among equal qualities.
+        ranges.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+        ranges.iter().find_map(|(tag, _)| {
+            let primary = tag.split('-').next().unwrap_or_default();
+            self.catalogs
+                .get_key_value(tag.as_str())
+                .or_else(|| self.catalogs.get_key_value(primary))
+                .map(|(language, _)| language.as_str())
+        })
+    }
+
+    /// Return the localized message of an error, if its catalog has a template for it.
+    pub fn message(&self, language: &str, error: &GraphError) -> Option<String> {
+        let template = self.catalogs.get(language)?.get(&error.kind())?;
+        let params = match error {
+            GraphError::MissingParams(params) => params.join(", "),
+            _ => String::new(),
+        };
+        Some(
+            template
+                .replace("{detail}", &error.detail())
+                .replace("{params}", &params),
+        )
+    }
+
+    /// Return the message of an error for an `Accept-Language` header value.
+    ///
+    /// This falls back to the English message if no catalog matches.
+    pub fn negotiated_message(&self, accept_language: Option<&str>, error: &GraphError) -> String {
+        accept_language
+            .and_then(|accept| self.negotiate(accept))
+            .and_then(|language| self.message(language, error))
+            .unwrap_or_else(|| error.value())
+    }
+
+    /// Localize the message of a graph error response, according to the request languages.
+    ///
+    /// Other responses, and all responses without catalogs, are returned unchanged.
+    pub fn localize_response(&self, response: ServiceResponse) -> ServiceResponse {
+        if self.is_empty() {
+            return response;
+        }
+
+        let localized = response
+            .response()
+            .error()
+            .and_then(|e| e.as_error::<GraphError>())
+            .map(|error| {
+                let accept_language = response
+                    .request()
+                    .headers()
+                    .get(ACCEPT_LANGUAGE)
+                    .and_then(|value| value.to_str().ok());
+                error.localized_response(&self.negotiated_message(accept_language, error))
+            });
+
+        match localized {
+            Some(localized) => {
+                let mut response = response.into_response(localized);
+                response
+                    .headers_mut()
+                    .append(VARY, HeaderValue::from_static("Accept-Language"));
+                response
+            }
+            None => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalogs() -> ErrorCatalogs {
+        let mut catalogs = ErrorCatalogs::default();
+        catalogs.insert(
+            "fr",
+            vec![(
+                "missing_params".to_string(),
+                "paramètres obligatoires manquants : {params}".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        catalogs.insert(
+            "de",
+            vec![(
+                "release_not_found".to_string(),
+                "Release '{detail}' nicht gefunden".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        catalogs
+    }
+
+    #[test]
+    fn negotiate_quality() {
+        let catalogs = catalogs();
+
+        assert_eq!(catalogs.negotiate("fr"), Some("fr"));
+        assert_eq!(catalogs.negotiate("de;q=0.5, fr;q=0.8"), Some("fr"));
+        assert_eq!(catalogs.negotiate("DE-at, fr;q=0.9"), Some("de"));
+        assert_eq!(catalogs.negotiate("fr;q=0.5, de;q=0.5"), Some("fr"));
+        assert_eq!(catalogs.negotiate("es, *;q=0.1, de;q=0.2"), Some("de"));
+        assert_eq!(catalogs.negotiate("fr;q=0, de;q=oops"), None);
+        assert_eq!(catalogs.negotiate("es, it"), None);
+        assert_eq!(catalogs.negotiate(""), None);
+    }
+
+    #[test]
+    fn missing_catalog_fallback() {
+        let catalogs = catalogs();
+        let error = GraphError::InvalidContentType;
+
+        // No catalog for the language.
+        assert_eq!(
+            catalogs.negotiated_message(Some("es"), &error),
+            error.value()
+        );
+        // No template for the error in the catalog.
+        assert_eq!(
+            catalogs.negotiated_message(Some("fr"), &error),
+            error.value()
+        );
+        // No header.
+        assert_eq!(catalogs.negotiated_message(None, &error), error.value());
+    }
+
+    #[test]
+    fn missing_params_message() {
+        let catalogs = catalogs();
+        let error = GraphError::MissingParams(vec!["arch".to_string(), "channel".to_string()]);
+
+        assert_eq!(
+            catalogs.negotiated_message(Some("fr-FR, en;q=0.5"), &error),
+            "paramètres obligatoires manquants : arch, channel"
+        );
+        assert_eq!(
+            catalogs.negotiated_message(Some("de"), &GraphError::ReleaseNotFound("4.1.0".into())),
+            "Release '4.1.0' nicht gefunden"
+        );
+    }
+
+    #[test]
+    fn read_catalogs_dir() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join("pt-BR.json"),
+            r#"{"invalid_params": "parâmetros inválidos: {detail}"}"#,
+        )?;
+        std::fs::write(dir.path().join("README.md"), "not a catalog")?;
+
+        let catalogs = ErrorCatalogs::read_dir(dir.path())?;
+        assert_eq!(catalogs.negotiate("pt-br"), Some("pt-br"));
+        assert_eq!(
+            catalogs.message("pt-br", &GraphError::InvalidParams("arch".into())),
+            Some("parâmetros inválidos: arch".to_string())
+        );
+
+        std::fs::write(dir.path().join("es.json"), "not json")?;
+        assert!(ErrorCatalogs::read_dir(dir.path()).is_err());
+
+        Ok(())
+    }
+}
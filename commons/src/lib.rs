@@ -6,18 +6,28 @@ extern crate actix_web;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
+extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 
 mod config;
 pub use crate::config::MergeOptions;
 
+pub mod access_log;
+pub mod build_info;
 pub mod de;
+pub mod extractors;
+pub mod http;
+pub mod i18n;
+pub mod log_throttle;
 pub mod metrics;
+pub mod signature;
 pub mod testing;
 pub mod tracing;
+pub mod watched_file;
 
 mod errors;
-pub use errors::{register_metrics, Fallible, GraphError, MISSING_APPSTATE_PANIC_MSG};
+pub use errors::{set_error_response_headers, Fallible, GraphError, MISSING_APPSTATE_PANIC_MSG};
 
 /// Commonly used imports for error handling.
 pub mod prelude_errors {
@@ -25,9 +35,19 @@ pub mod prelude_errors {
 }
 
 use actix_web::http::{header, HeaderMap};
+use prelude_errors::Context;
 use std::collections::HashSet;
+use std::path::Path;
 use url::form_urlencoded;
 
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    errors::register_metrics(registry)?;
+    metrics::register_metrics(registry)?;
+    tracing::register_metrics(registry)?;
+    Ok(())
+}
+
 /// Strip all but one leading slash and all trailing slashes
 pub fn parse_path_prefix<S>(path_prefix: S) -> String
 where
@@ -51,18 +71,63 @@ pub fn parse_params_set<S>(params: S) -> HashSet<String>
 where
     S: AsRef<str>,
 {
-    params
-        .as_ref()
-        .split(',')
-        .filter_map(|key| {
-            let trimmed = key.trim().to_string();
-            if trimmed.is_empty() {
-                None
-            } else {
-                Some(trimmed)
-            }
-        })
-        .collect()
+    parse_params_list(params).into_iter().collect()
+}
+
+/// Parse a comma-separated list of client parameters keys.
+///
+/// Keys keep their declaration order. Duplicated keys are only kept at their
+/// first occurrence.
+pub fn parse_params_list<S>(params: S) -> Vec<String>
+where
+    S: AsRef<str>,
+{
+    let mut list = Vec::new();
+    extend_params_list(
+        &mut list,
+        params.as_ref().split(',').map(|key| key.trim().to_string()),
+    );
+    list
+}
+
+/// Append client parameters keys to a list, skipping empty and already listed keys.
+pub fn extend_params_list<I>(list: &mut Vec<String>, params: I)
+where
+    I: IntoIterator<Item = String>,
+{
+    for key in params {
+        if !key.is_empty() && !list.contains(&key) {
+            list.push(key);
+        }
+    }
+}
+
+/// Read a set of client parameters keys from a file.
+///
+/// Keys in the file are separated by newlines and/or commas.
+pub fn read_params_set<P>(path: P) -> Fallible<HashSet<String>>
+where
+    P: AsRef<Path>,
+{
+    Ok(read_params_list(path)?.into_iter().collect())
+}
+
+/// Read a list of client parameters keys from a file, in declaration order.
+///
+/// Keys in the file are separated by newlines and/or commas.
+pub fn read_params_list<P>(path: P) -> Fallible<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).context(format!(
+        "failed to read client parameters from '{}'",
+        path.display()
+    ))?;
+
+    Ok(parse_params_list(
+        content.lines().collect::<Vec<_>>().join(","),
+    ))
 }
 
 /// Make sure `query` string contains all `params` keys.
@@ -138,6 +203,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_params_list() {
+        assert!(parse_params_list("").is_empty());
+        assert_eq!(parse_params_list("c,a,b"), vec!["c", "a", "b"]);
+        assert_eq!(parse_params_list("b, ,a,b , c,a"), vec!["b", "a", "c"]);
+
+        let mut list = parse_params_list("id");
+        extend_params_list(&mut list, vec!["arch".to_string(), "id".to_string()]);
+        assert_eq!(list, vec!["id", "arch"]);
+    }
+
+    #[test]
+    fn test_missing_params_sorted() {
+        // Configured order is kept, but missing parameters are always reported sorted.
+        let configured = parse_params_list("id,channel,arch");
+        assert_eq!(configured, vec!["id", "channel", "arch"]);
+
+        let required = configured.into_iter().collect();
+        assert_eq!(
+            ensure_query_params(&required, "channel=stable"),
+            Err(GraphError::MissingParams(vec![
+                "arch".to_string(),
+                "id".to_string()
+            ]))
+        );
+    }
+
     #[test]
     fn test_ensure_query_params() {
         let empty = HashSet::new();
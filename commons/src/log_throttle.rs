@@ -0,0 +1,269 @@
+//! Rate-limited logging.
+//!
+//! A `ThrottledLogger` admits at most a fixed number of messages per key in
+//! each time window. Messages beyond that are suppressed and counted, and the
+//! first message admitted in a later window reports how many were suppressed.
+//! Keys which fall silent can instead have their suppressed messages collected
+//! with `take_expired`, once their window is over.
+//! The `warn_throttled!` and `error_throttled!` macros log through a
+//! throttled logger, at the call site's log target.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of tracked keys above which expired keys are evicted.
+const EVICTION_THRESHOLD: usize = 1024;
+
+/// Message counts of a key within its current window.
+#[derive(Debug)]
+struct KeyWindow {
+    /// Start of the window.
+    start: Instant,
+    /// Number of messages admitted in the window.
+    admitted: u32,
+    /// Number of messages suppressed in the window.
+    suppressed: u64,
+}
+
+/// Admits at most `max_per_window` messages per key and time window.
+#[derive(Debug)]
+pub struct ThrottledLogger {
+    /// Maximum number of messages per key in a window.
+    max_per_window: u32,
+    /// Window length.
+    window: Duration,
+    /// Current window of each key.
+    keys: Mutex<HashMap<String, KeyWindow>>,
+}
+
+impl ThrottledLogger {
+    /// Create a logger admitting `max_per_window` messages per key in each `window`.
+    pub fn new(max_per_window: u32, window: Duration) -> Self {
+        Self {
+            max_per_window,
+            window,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Decide whether a message for `key` is to be logged now.
+    ///
+    /// Returns `None` if the message is to be suppressed, otherwise the
+    /// number of messages suppressed in the previous window of the key.
+    pub fn admit(&self, key: &str) -> Option<u64> {
+        self.admit_at(key, Instant::now())
+    }
+
+    /// Decide whether a message for `key` is to be logged at `now`.
+    fn admit_at(&self, key: &str, now: Instant) -> Option<u64> {
+        let mut keys = match self.keys.lock() {
+            Ok(keys) => keys,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if keys.len() >= EVICTION_THRESHOLD && !keys.contains_key(key) {
+            let window = self.window;
+            let expired: Vec<String> = keys
+                .iter()
+                .filter(|(_, entry)| now.duration_since(entry.start) >= window)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                keys.remove(&key);
+            }
+        }
+
+        let entry = keys.entry(key.to_string()).or_insert(KeyWindow {
+            start: now,
+            admitted: 0,
+            suppressed: 0,
+        });
+
+        let mut previously_suppressed = 0;
+        if now.duration_since(entry.start) >= self.window {
+            previously_suppressed = entry.suppressed;
+            *entry = KeyWindow {
+                start: now,
+                admitted: 0,
+                suppressed: 0,
+            };
+        }
+
+        if entry.admitted >= self.max_per_window {
+            entry.suppressed += 1;
+            return None;
+        }
+        entry.admitted += 1;
+
+        Some(previously_suppressed)
+    }
+
+    /// Collect the keys whose window is over with suppressed messages.
+    ///
+    /// Returns each such key with the number of messages suppressed in its
+    /// window, sorted by key. Collected keys start afresh, so that the next
+    /// admitted message doesn't report the same messages again.
+    pub fn take_expired(&self) -> Vec<(String, u64)> {
+        self.take_expired_at(Instant::now())
+    }
+
+    /// Collect the keys whose window is over at `now` with suppressed messages.
+    fn take_expired_at(&self, now: Instant) -> Vec<(String, u64)> {
+        let mut keys = match self.keys.lock() {
+            Ok(keys) => keys,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let window = self.window;
+        let mut expired: Vec<(String, u64)> = keys
+            .iter()
+            .filter(|(_, entry)| entry.suppressed > 0 && now.duration_since(entry.start) >= window)
+            .map(|(key, entry)| (key.clone(), entry.suppressed))
+            .collect();
+        for (key, _) in &expired {
+            keys.remove(key);
+        }
+
+        expired.sort();
+        expired
+    }
+}
+
+/// Log a message through a `ThrottledLogger`, under the given key.
+///
+/// The first message admitted after suppression is suffixed with the number
+/// of suppressed messages.
+#[macro_export]
+macro_rules! log_throttled {
+    ($logger:expr, $level:expr, $key:expr, $($arg:tt)+) => {
+        if let Some(suppressed) = $logger.admit($key) {
+            if suppressed > 0 {
+                ::log::log!(
+                    $level,
+                    "{} (suppressed {} similar messages)",
+                    format_args!($($arg)+),
+                    suppressed
+                );
+            } else {
+                ::log::log!($level, $($arg)+);
+            }
+        }
+    };
+}
+
+/// Log a warning through a `ThrottledLogger`, under the given key.
+#[macro_export]
+macro_rules! warn_throttled {
+    ($logger:expr, $key:expr, $($arg:tt)+) => {
+        $crate::log_throttled!($logger, ::log::Level::Warn, $key, $($arg)+)
+    };
+}
+
+/// Log an error through a `ThrottledLogger`, under the given key.
+#[macro_export]
+macro_rules! error_throttled {
+    ($logger:expr, $key:expr, $($arg:tt)+) => {
+        $crate::log_throttled!($logger, ::log::Level::Error, $key, $($arg)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppression_counts() {
+        let logger = ThrottledLogger::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(logger.admit_at("a", start), Some(0));
+        assert_eq!(
+            logger.admit_at("a", start + Duration::from_secs(1)),
+            Some(0)
+        );
+        for i in 2..5 {
+            assert_eq!(logger.admit_at("a", start + Duration::from_secs(i)), None);
+        }
+
+        // Keys are throttled independently.
+        assert_eq!(
+            logger.admit_at("b", start + Duration::from_secs(5)),
+            Some(0)
+        );
+
+        // The next window reports the suppressed messages once.
+        let next = start + Duration::from_secs(60);
+        assert_eq!(logger.admit_at("a", next), Some(3));
+        assert_eq!(logger.admit_at("a", next), Some(0));
+        assert_eq!(logger.admit_at("a", next), None);
+    }
+
+    #[test]
+    fn window_reset() {
+        let logger = ThrottledLogger::new(1, Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert_eq!(logger.admit_at("a", start), Some(0));
+        assert_eq!(logger.admit_at("a", start + Duration::from_secs(9)), None);
+        assert_eq!(
+            logger.admit_at("a", start + Duration::from_secs(10)),
+            Some(1)
+        );
+
+        // The window restarts at the first message after expiry.
+        assert_eq!(logger.admit_at("a", start + Duration::from_secs(19)), None);
+        assert_eq!(
+            logger.admit_at("a", start + Duration::from_secs(20)),
+            Some(1)
+        );
+
+        // Quiet windows report nothing.
+        assert_eq!(
+            logger.admit_at("a", start + Duration::from_secs(60)),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn expired_suppressions_taken() {
+        let logger = ThrottledLogger::new(1, Duration::from_secs(10));
+        let start = Instant::now();
+
+        for key in &["a", "b", "c"] {
+            assert_eq!(logger.admit_at(key, start), Some(0));
+        }
+        for _ in 0..2 {
+            assert_eq!(logger.admit_at("a", start + Duration::from_secs(1)), None);
+        }
+        assert_eq!(logger.admit_at("b", start + Duration::from_secs(1)), None);
+
+        // Windows in progress are left alone.
+        assert!(logger
+            .take_expired_at(start + Duration::from_secs(9))
+            .is_empty());
+
+        let next = start + Duration::from_secs(10);
+        assert_eq!(
+            logger.take_expired_at(next),
+            vec![("a".to_string(), 2), ("b".to_string(), 1)]
+        );
+        assert!(logger.take_expired_at(next).is_empty());
+
+        // Taken suppressions are not reported again.
+        assert_eq!(logger.admit_at("a", next), Some(0));
+    }
+
+    #[test]
+    fn expired_keys_evicted() {
+        let logger = ThrottledLogger::new(1, Duration::from_secs(10));
+        let start = Instant::now();
+
+        for i in 0..EVICTION_THRESHOLD {
+            logger.admit_at(&i.to_string(), start);
+        }
+        logger.admit_at("last", start + Duration::from_secs(10));
+
+        assert_eq!(logger.keys.lock().unwrap().len(), 1);
+    }
+}
@@ -1,8 +1,65 @@
 //! Metrics service.
+//!
+//! Caching layers also report their lookups here, in a single counter
+//! labeled by cache name and outcome, so that all caches can be watched
+//! on the same dashboard.
 
 use crate::prelude_errors::*;
 use actix_web::HttpResponse;
-use prometheus::{self, Registry};
+use prometheus::{self, IntCounterVec, Opts, Registry};
+
+lazy_static! {
+    static ref CACHE_LOOKUPS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "cache_lookups_total",
+            "Total number of cache lookups, by cache and outcome"
+        ),
+        &["cache", "outcome"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(CACHE_LOOKUPS.clone()))?;
+    Ok(())
+}
+
+/// Outcome of a cache lookup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheOutcome {
+    /// A fresh entry was found.
+    Hit,
+    /// No entry was found.
+    Miss,
+    /// An outdated entry was found, whether or not the cache serves it.
+    Stale,
+}
+
+impl CacheOutcome {
+    /// Label value of the outcome.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CacheOutcome::Hit => "hit",
+            CacheOutcome::Miss => "miss",
+            CacheOutcome::Stale => "stale",
+        }
+    }
+}
+
+/// Count a lookup in the named cache.
+pub fn record_cache_lookup(cache: &str, outcome: CacheOutcome) {
+    CACHE_LOOKUPS
+        .with_label_values(&[cache, outcome.as_str()])
+        .inc();
+}
+
+/// Return the number of lookups in the named cache with the given outcome.
+pub fn cache_lookups(cache: &str, outcome: CacheOutcome) -> u64 {
+    CACHE_LOOKUPS
+        .with_label_values(&[cache, outcome.as_str()])
+        .get()
+}
 
 /// For types that store a static Registry reference
 pub trait HasRegistry {
@@ -0,0 +1,261 @@
+//! Verification of detached OpenPGP signatures, e.g. of configuration files.
+//!
+//! Signatures and public keys may be either ASCII-armored or binary, as
+//! produced by `gpg --detach-sign [--armor]` and `gpg --export [--armor]`.
+
+use crate::prelude_errors::*;
+use pgp::composed::signed_key::SignedPublicKey;
+use pgp::{Deserializable, StandaloneSignature};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+/// Header of ASCII-armored data.
+static ARMOR_HEADER: &[u8] = b"-----BEGIN PGP";
+
+/// Parse ASCII-armored or binary OpenPGP data.
+fn parse<T>(data: &[u8]) -> Fallible<T>
+where
+    T: Deserializable,
+{
+    let start = data
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or_else(|| data.len());
+    let parsed = if data[start..].starts_with(ARMOR_HEADER) {
+        T::from_armor_single(data).map(|(parsed, _)| parsed)
+    } else {
+        T::from_bytes(data)
+    };
+    parsed.map_err(|e| format_err!("{}", e))
+}
+
+/// Verify the detached signature of `data` against a public key.
+///
+/// The signature may have been made by the primary key or any of its subkeys.
+pub fn verify_detached(data: &[u8], signature: &[u8], public_key: &[u8]) -> Fallible<()> {
+    let signature: StandaloneSignature = parse(signature).context("invalid signature")?;
+    let public_key: SignedPublicKey = parse(public_key).context("invalid public key")?;
+    public_key
+        .verify()
+        .map_err(|e| format_err!("invalid public key: {}", e))?;
+
+    let verified = signature.verify(&public_key, data).is_ok()
+        || public_key
+            .public_subkeys
+            .iter()
+            .any(|subkey| signature.verify(&subkey.key, data).is_ok());
+    ensure!(verified, "signature doesn't match the data and public key");
+
+    Ok(())
+}
+
+/// Detached signature of a configuration file, along with the public key verifying it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigSignature {
+    /// Path to the detached signature.
+    pub signature_path: PathBuf,
+    /// Path to the public key.
+    pub public_key_path: PathBuf,
+}
+
+impl ConfigSignature {
+    /// Return the signature settings, if configured.
+    ///
+    /// Both paths must be set for verification, and setting only one of them fails.
+    pub fn from_paths(
+        signature_path: Option<PathBuf>,
+        public_key_path: Option<PathBuf>,
+    ) -> Fallible<Option<Self>> {
+        match (signature_path, public_key_path) {
+            (Some(signature_path), Some(public_key_path)) => Ok(Some(Self {
+                signature_path,
+                public_key_path,
+            })),
+            (None, None) => Ok(None),
+            _ => bail!("the config signature and public key paths must be set together"),
+        }
+    }
+
+    /// Verify the signature of the content of the configuration file at `path`.
+    pub fn verify(&self, path: &Path, content: &[u8]) -> Fallible<()> {
+        let read = |path: &Path| {
+            std::fs::read(path).context(format!("failed to read '{}'", path.display()))
+        };
+
+        verify_detached(
+            content,
+            &read(&self.signature_path)?,
+            &read(&self.public_key_path)?,
+        )
+        .context(format!(
+            "failed to verify the signature '{}' of config file '{}'",
+            self.signature_path.display(),
+            path.display()
+        ))
+    }
+}
+
+/// Read a TOML configuration file, verifying its detached signature first if given.
+pub fn read_verified_filepath<T, P>(cfg_path: P, signature: Option<&ConfigSignature>) -> Fallible<T>
+where
+    T: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let content = std::fs::read(&cfg_path).context(format!(
+        "failed to open config path {:?}",
+        cfg_path.as_ref()
+    ))?;
+    // The verified content is the parsed one, even if the file changes meanwhile.
+    if let Some(signature) = signature {
+        signature.verify(cfg_path.as_ref(), &content)?;
+    }
+    let cfg = toml::from_slice(&content).context(format!(
+        "failed to parse config file {}:\n{}",
+        cfg_path.as_ref().display(),
+        std::str::from_utf8(&content).unwrap_or("file not decodable")
+    ))?;
+
+    Ok(cfg)
+}
+
+/// Read the configuration file, if any.
+///
+/// If a signature and public key are configured, the file is only read if its
+/// signature is valid.
+pub fn read_config_file<T>(
+    config_path: Option<&str>,
+    signature_path: Option<PathBuf>,
+    public_key_path: Option<PathBuf>,
+) -> Fallible<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    let signature = ConfigSignature::from_paths(signature_path, public_key_path)?;
+
+    match config_path {
+        Some(path) => Ok(Some(read_verified_filepath(path, signature.as_ref())?)),
+        None => {
+            ensure!(
+                signature.is_none(),
+                "config signature verification without a config file"
+            );
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FIXTURES: &str = "src/test_fixtures/signature";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(FIXTURES).join(name)
+    }
+
+    fn signature() -> ConfigSignature {
+        ConfigSignature {
+            signature_path: fixture("config.toml.asc"),
+            public_key_path: fixture("public_key.asc"),
+        }
+    }
+
+    #[test]
+    fn valid_signature() -> Fallible<()> {
+        let path = fixture("config.toml");
+        signature().verify(&path, &std::fs::read(&path)?)
+    }
+
+    #[test]
+    fn tampered_data() -> Fallible<()> {
+        let mut config = std::fs::read(fixture("config.toml"))?;
+        config.extend_from_slice(b"\n[policy]\nname = \"channel-filter\"\n");
+
+        let err = verify_detached(
+            &config,
+            &std::fs::read(fixture("config.toml.asc"))?,
+            &std::fs::read(fixture("public_key.asc"))?,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("doesn't match"),
+            "unexpected error: {}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_signature() -> Fallible<()> {
+        verify_detached(
+            &std::fs::read(fixture("config.toml"))?,
+            b"not a signature",
+            &std::fs::read(fixture("public_key.asc"))?,
+        )
+        .unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn signature_paths() -> Fallible<()> {
+        assert_eq!(ConfigSignature::from_paths(None, None)?, None);
+        assert_eq!(
+            ConfigSignature::from_paths(
+                Some(fixture("config.toml.asc")),
+                Some(fixture("public_key.asc"))
+            )?,
+            Some(signature())
+        );
+        ConfigSignature::from_paths(Some(fixture("config.toml.asc")), None).unwrap_err();
+        ConfigSignature::from_paths(None, Some(fixture("public_key.asc"))).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_signature() -> Fallible<()> {
+        let read = |config_path: &Path, public_key: bool| {
+            read_config_file::<toml::Value>(
+                Some(&config_path.to_string_lossy()),
+                Some(fixture("config.toml.asc")),
+                if public_key {
+                    Some(fixture("public_key.asc"))
+                } else {
+                    None
+                },
+            )
+        };
+
+        // A valid signature loads the config.
+        let cfg = read(&fixture("config.toml"), true)?.unwrap();
+        assert_eq!(
+            cfg["service"]["path_prefix"].as_str(),
+            Some("/api/upgrades_info")
+        );
+
+        // A tampered config fails.
+        let dir = tempfile::tempdir()?;
+        let tampered = dir.path().join("config.toml");
+        let mut content = std::fs::read_to_string(fixture("config.toml"))?;
+        content = content.replace("/api/upgrades_info", "/api/tampered");
+        std::fs::write(&tampered, content)?;
+        read(&tampered, true).unwrap_err();
+
+        // A signature without public key fails, even for a valid config.
+        read(&fixture("config.toml"), false).unwrap_err();
+
+        // A signature without config file fails.
+        read_config_file::<toml::Value>(
+            None,
+            Some(fixture("config.toml.asc")),
+            Some(fixture("public_key.asc")),
+        )
+        .unwrap_err();
+        assert_eq!(read_config_file::<toml::Value>(None, None, None)?, None);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,12 @@
 //! Test helpers.
 
 use crate::prelude_errors::*;
+use actix_web::dev::Server;
+use actix_web::rt::System;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 
 /// Initialize logging.
@@ -23,6 +29,110 @@ pub fn dummy_gauge(registry: &prometheus::Registry, value: f64) -> Fallible<()>
     Ok(())
 }
 
+/// Bind a listener on an ephemeral port of the loopback interface.
+pub fn ephemeral_listener() -> Fallible<TcpListener> {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).map_err(Error::from)
+}
+
+/// HTTP service running on its own actix system and thread, on an ephemeral port.
+///
+/// The service is stopped when dropped.
+pub struct TestService {
+    addr: SocketAddr,
+    server: Option<Server>,
+    system: Option<System>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl TestService {
+    /// Start the service run by `serve` on an ephemeral listener.
+    ///
+    /// The service accepts connections as soon as this returns.
+    pub fn start<F>(name: &str, serve: F) -> Fallible<Self>
+    where
+        F: FnOnce(TcpListener) -> std::io::Result<Server> + Send + 'static,
+    {
+        let listener = ephemeral_listener()?;
+        let addr = listener.local_addr()?;
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let system_name = name.to_string();
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let system = System::new(system_name);
+                let server = serve(listener);
+                let running = server.is_ok();
+                let _ = started_tx.send(server.map(|server| (server, System::current())));
+                if running {
+                    let _ = system.run();
+                }
+            })?;
+
+        let (server, system) = started_rx
+            .recv()?
+            .context(format!("failed to start test service '{}'", name))?;
+        log::debug!("test service '{}' listening on {}", name, addr);
+
+        Ok(Self {
+            addr,
+            server: Some(server),
+            system: Some(system),
+            thread: Some(thread),
+        })
+    }
+
+    /// Address of the service.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL of the given path on the service.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Stop the service gracefully, and wait for its thread to finish.
+    pub fn stop(&mut self) {
+        if let Some(server) = self.server.take() {
+            futures::executor::block_on(server.stop(true));
+        }
+        if let Some(system) = self.system.take() {
+            system.stop();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TestService {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Poll `url` until it answers with a success status, failing after `timeout`.
+pub async fn wait_ready(url: &str, timeout: Duration) -> Fallible<()> {
+    let client = reqwest::Client::new();
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        match client.get(url).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => log::trace!("'{}' not ready: {}", url, response.status()),
+            Err(e) => log::trace!("'{}' not ready: {}", url, e),
+        }
+        ensure!(
+            Instant::now() < deadline,
+            "'{}' not ready after {:?}",
+            url,
+            timeout
+        );
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+    }
+}
+
 /// Sort the JSON value represantion of a graph by version.
 pub fn sort_json_graph_by_version(v: &mut serde_json::Value) {
     if !v.is_object() {
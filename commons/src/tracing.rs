@@ -1,11 +1,20 @@
 //! Tracing service.
 
 use opentelemetry::api::{
-    Carrier, HttpTextFormat, Key, Provider, Span, SpanContext, TraceContextPropagator,
+    self, Carrier, HttpTextFormat, Key, KeyValue, Provider, Span, SpanContext,
+    TraceContextPropagator, Tracer,
 };
+use opentelemetry::exporter::trace::{ExportResult, SpanData, SpanExporter};
 use opentelemetry::{global, sdk};
 use opentelemetry_jaeger::{Exporter, Process};
-use std::collections::HashMap;
+use prometheus::IntCounter;
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use actix_web::dev::ServiceRequest;
 use actix_web::http;
@@ -13,6 +22,59 @@ use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 
 use crate::prelude_errors::*;
 
+/// Header carrying a debug id, which forces the request to be sampled.
+pub static DEBUG_ID_HEADER: &str = "jaeger-debug-id";
+
+/// Plugin parameter carrying the debug id of a request, for forwarding it upstream.
+pub static DEBUG_ID_PARAM: &str = "__tracing.debug_id";
+
+/// Maximum number of span batches queued for the reporter thread.
+const REPORTER_QUEUE_CAPACITY: usize = 1024;
+
+/// Retry policy of the reporter thread.
+const REPORTER_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    initial_backoff: Duration::from_millis(100),
+    max_backoff: Duration::from_secs(2),
+};
+
+lazy_static! {
+    static ref TRACING_SPANS_DROPPED: IntCounter = IntCounter::new(
+        "tracing_spans_dropped_total",
+        "Total number of spans dropped by the tracing reporter"
+    )
+    .unwrap();
+    static ref TRACING_REPORT_ERRORS: IntCounter = IntCounter::new(
+        "tracing_report_errors_total",
+        "Total number of failed attempts to report spans"
+    )
+    .unwrap();
+    static ref TRACING_FORCED_SAMPLES: IntCounter = IntCounter::new(
+        "tracing_forced_samples_total",
+        "Total number of requests force-sampled by a debug id"
+    )
+    .unwrap();
+    static ref TRACING_OVERSIZE_SPANS: IntCounter = IntCounter::new(
+        "tracing_oversize_spans_total",
+        "Total number of spans whose tags exceeded the configured size threshold"
+    )
+    .unwrap();
+}
+
+thread_local! {
+    /// Whether the span being started on this thread is force-sampled.
+    static FORCE_SAMPLING: Cell<bool> = Cell::new(false);
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(TRACING_SPANS_DROPPED.clone()))?;
+    registry.register(Box::new(TRACING_REPORT_ERRORS.clone()))?;
+    registry.register(Box::new(TRACING_FORCED_SAMPLES.clone()))?;
+    registry.register(Box::new(TRACING_OVERSIZE_SPANS.clone()))?;
+    Ok(())
+}
+
 /// init_tracer sets up Jaeger tracer
 pub fn init_tracer(name: &'static str, maybe_agent_endpoint: Option<String>) -> Fallible<()> {
     // Skip provider config if agent endpoint is not set
@@ -21,18 +83,25 @@ pub fn init_tracer(name: &'static str, maybe_agent_endpoint: Option<String>) ->
         Some(s) => s,
     };
 
-    let exporter = Exporter::builder()
-        .with_agent_endpoint(agent_endpoint)
-        .with_process(Process {
-            service_name: name.to_string(),
-            tags: vec![Key::new("exporter").string("jaeger")],
-        })
-        .init()?;
+    let new_exporter = move || -> Fallible<Exporter> {
+        let exporter = Exporter::builder()
+            .with_agent_endpoint(agent_endpoint.clone())
+            .with_process(Process {
+                service_name: name.to_string(),
+                tags: vec![Key::new("exporter").string("jaeger")],
+            })
+            .init()?;
+        Ok(exporter)
+    };
+
+    // Fail early on invalid settings, the reporter thread re-creates the
+    // exporter whenever it becomes unusable.
+    let exporter = new_exporter()?;
 
     let provider = sdk::Provider::builder()
-        .with_simple_exporter(exporter)
+        .with_simple_exporter(BackgroundExporter::spawn(exporter, new_exporter)?)
         .with_config(sdk::Config {
-            default_sampler: Box::new(sdk::Sampler::Always),
+            default_sampler: Box::new(DebugSampler(Box::new(sdk::Sampler::Always))),
             ..Default::default()
         })
         .build();
@@ -41,26 +110,331 @@ pub fn init_tracer(name: &'static str, maybe_agent_endpoint: Option<String>) ->
     Ok(())
 }
 
+/// Sampler deferring to the configured one, unless the span is force-sampled.
+#[derive(Debug)]
+struct DebugSampler(Box<dyn api::Sampler>);
+
+impl api::Sampler for DebugSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&SpanContext>,
+        trace_id: api::TraceId,
+        span_id: api::SpanId,
+        name: &str,
+        span_kind: &api::SpanKind,
+        attributes: &[KeyValue],
+        links: &[api::Link],
+    ) -> api::SamplingResult {
+        if FORCE_SAMPLING.with(Cell::get) {
+            return api::SamplingResult {
+                decision: api::SamplingDecision::RecordAndSampled,
+                attributes: Vec::new(),
+            };
+        }
+
+        self.0.should_sample(
+            parent_context,
+            trace_id,
+            span_id,
+            name,
+            span_kind,
+            attributes,
+            links,
+        )
+    }
+}
+
+/// Forced sampling of requests carrying a debug id, for on-demand debugging.
+///
+/// The debug id is read from the `jaeger-debug-id` header, or from an
+/// additional custom header. The number of forced samples is capped per
+/// minute, further requests are left to the configured sampler.
+#[derive(Debug)]
+pub struct DebugSampling {
+    /// Additional header carrying a debug id.
+    custom_header: Option<String>,
+    /// Maximum number of forced samples per minute.
+    max_per_minute: u32,
+    /// Start of the current minute, and number of forced samples in it.
+    window: Mutex<(Instant, u32)>,
+}
+
+impl DebugSampling {
+    /// Create the forced sampling with an optional custom header and a cap per minute.
+    pub fn new(custom_header: Option<String>, max_per_minute: u32) -> Self {
+        Self {
+            custom_header: custom_header.map(|header| header.to_lowercase()),
+            max_per_minute,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Return the debug id of a request, if any.
+    pub fn debug_id(&self, headers: &http::HeaderMap) -> Option<String> {
+        std::iter::once(DEBUG_ID_HEADER)
+            .chain(self.custom_header.as_deref())
+            .filter_map(|name| headers.get(name))
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_string())
+            .find(|id| !id.is_empty())
+    }
+
+    /// Return the debug id of a request if it is to be force-sampled at `now`.
+    fn check(&self, headers: &http::HeaderMap, now: Instant) -> Option<String> {
+        let debug_id = self.debug_id(headers)?;
+
+        let mut window = match self.window.lock() {
+            Ok(window) => window,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if now.duration_since(window.0) >= Duration::from_secs(60) {
+            *window = (now, 0);
+        }
+        if window.1 >= self.max_per_minute {
+            log::debug!(
+                "not force-sampling request with debug id '{}', cap reached",
+                debug_id
+            );
+            return None;
+        }
+        window.1 += 1;
+
+        TRACING_FORCED_SAMPLES.inc();
+        Some(debug_id)
+    }
+}
+
+/// Start a span for a request with the given headers.
+///
+/// If forced sampling is enabled and the request carries a debug id, the span
+/// is sampled regardless of the configured sampler, within the cap, and tagged
+/// with the debug id.
+pub fn create_span_from_headers(
+    name: &'static str,
+    parent: Option<SpanContext>,
+    headers: &http::HeaderMap,
+    debug_sampling: Option<&DebugSampling>,
+) -> global::BoxedSpan {
+    let debug_id = debug_sampling.and_then(|sampling| sampling.check(headers, Instant::now()));
+
+    FORCE_SAMPLING.with(|force| force.set(debug_id.is_some()));
+    let span = get_tracer().start(name, parent);
+    FORCE_SAMPLING.with(|force| force.set(false));
+
+    if let Some(tag) = debug_id.map(debug_id_tag) {
+        span.set_attribute(tag);
+    }
+    span
+}
+
+/// Span attribute for a debug id.
+fn debug_id_tag(debug_id: String) -> KeyValue {
+    Key::new(DEBUG_ID_HEADER).string(debug_id)
+}
+
+/// Retry policy for reporting a single batch.
+#[derive(Clone, Copy, Debug)]
+struct RetryPolicy {
+    /// Maximum number of attempts, after which the batch is dropped.
+    max_attempts: u32,
+    /// Pause after the first failed attempt, doubled after each further one.
+    initial_backoff: Duration,
+    /// Upper bound for the pause between attempts.
+    max_backoff: Duration,
+}
+
+/// Failure of a single report attempt.
+#[derive(Debug)]
+enum ReportError {
+    /// The report may succeed if retried with the same reporter.
+    Transient(Error),
+    /// The reporter is unusable and has to be re-created.
+    Broken(Error),
+}
+
+/// Destination of the batches drained by the reporter thread.
+trait Reporter<T> {
+    /// Report a batch of items.
+    fn report(&mut self, batch: &[T]) -> Result<(), ReportError>;
+}
+
+impl<E: SpanExporter> Reporter<Arc<SpanData>> for E {
+    fn report(&mut self, batch: &[Arc<SpanData>]) -> Result<(), ReportError> {
+        match self.export(batch.to_vec()) {
+            ExportResult::Success => Ok(()),
+            ExportResult::FailedRetryable => Err(ReportError::Transient(format_err!(
+                "retryable export failure"
+            ))),
+            ExportResult::FailedNotRetryable => {
+                Err(ReportError::Broken(format_err!("export failure")))
+            }
+        }
+    }
+}
+
+/// Span exporter handing spans over to a background reporter thread.
+///
+/// Spans are queued on a bounded channel. If the reporter can't keep up, new
+/// spans are dropped instead of being buffered without limit.
+#[derive(Debug)]
+struct BackgroundExporter {
+    sender: Mutex<SyncSender<Vec<Arc<SpanData>>>>,
+}
+
+impl BackgroundExporter {
+    /// Spawn the reporter thread, which exports the queued spans via
+    /// `reporter` and re-creates it via `new_reporter` when it breaks.
+    fn spawn<R, F>(reporter: R, new_reporter: F) -> Fallible<Self>
+    where
+        R: Reporter<Arc<SpanData>> + Send + 'static,
+        F: FnMut() -> Fallible<R> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(REPORTER_QUEUE_CAPACITY);
+
+        thread::Builder::new()
+            .name("tracing-reporter".to_string())
+            .spawn(move || {
+                run_reporter(
+                    receiver,
+                    Some(reporter),
+                    new_reporter,
+                    REPORTER_RETRY_POLICY,
+                )
+            })?;
+
+        Ok(Self {
+            sender: Mutex::new(sender),
+        })
+    }
+}
+
+impl SpanExporter for BackgroundExporter {
+    fn export(&self, batch: Vec<Arc<SpanData>>) -> ExportResult {
+        let sender = match self.sender.lock() {
+            Ok(sender) => sender,
+            Err(_) => return ExportResult::FailedNotRetryable,
+        };
+
+        enqueue(&sender, batch)
+    }
+
+    fn shutdown(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Queue a batch for the reporter thread without blocking.
+///
+/// The batch is dropped if the queue is full or the reporter is gone.
+fn enqueue<T>(sender: &SyncSender<Vec<T>>, batch: Vec<T>) -> ExportResult {
+    let len = batch.len();
+
+    match sender.try_send(batch) {
+        Ok(()) => ExportResult::Success,
+        Err(TrySendError::Full(_)) => {
+            log::debug!("tracing reporter queue is full, dropping {} spans", len);
+            TRACING_SPANS_DROPPED.inc_by(len as i64);
+            ExportResult::FailedRetryable
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            log::debug!("tracing reporter is gone, dropping {} spans", len);
+            TRACING_SPANS_DROPPED.inc_by(len as i64);
+            ExportResult::FailedNotRetryable
+        }
+    }
+}
+
+/// Forward queued batches to a reporter, until all senders are gone.
+///
+/// Failed reports are retried with an exponential backoff, as configured by
+/// `policy`. Batches which can't be reported within the allowed attempts are
+/// dropped. A broken reporter is dropped as well, and a new one is created via
+/// `new_reporter` for the next attempt.
+fn run_reporter<T, R, F>(
+    receiver: Receiver<Vec<T>>,
+    mut reporter: Option<R>,
+    mut new_reporter: F,
+    policy: RetryPolicy,
+) where
+    R: Reporter<T>,
+    F: FnMut() -> Fallible<R>,
+{
+    for batch in receiver {
+        let mut backoff = policy.initial_backoff;
+
+        for attempt in 1..=policy.max_attempts {
+            let result = match reporter.as_mut() {
+                Some(current) => current.report(&batch),
+                None => match new_reporter() {
+                    Ok(fresh) => reporter.get_or_insert(fresh).report(&batch),
+                    Err(e) => Err(ReportError::Broken(e.context("failed to create reporter"))),
+                },
+            };
+
+            let e = match result {
+                Ok(()) => break,
+                Err(ReportError::Transient(e)) => e,
+                Err(ReportError::Broken(e)) => {
+                    reporter = None;
+                    e
+                }
+            };
+            TRACING_REPORT_ERRORS.inc();
+
+            if attempt == policy.max_attempts {
+                log::warn!(
+                    "dropping {} spans after {} failed attempts: {}",
+                    batch.len(),
+                    attempt,
+                    e
+                );
+                TRACING_SPANS_DROPPED.inc_by(batch.len() as i64);
+            } else {
+                log::debug!("failed to report spans (attempt {}): {}", attempt, e);
+                thread::sleep(backoff);
+                backoff = std::cmp::min(backoff * 2, policy.max_backoff);
+            }
+        }
+    }
+
+    log::debug!("tracing reporter queue closed");
+}
+
 /// get_tracer returns an instance of global tracer
 pub fn get_tracer() -> global::BoxedTracer {
     global::trace_provider().get_tracer("")
 }
 
-struct HttpHeaderMapCarrier<'a>(&'a http::HeaderMap);
-impl<'a> Carrier for HttpHeaderMapCarrier<'a> {
-    fn get(&self, key: &'static str) -> Option<&str> {
-        self.0.get(key).and_then(|value| value.to_str().ok())
-    }
+/// Request headers copied into tracing carriers and span tags.
+///
+/// Besides the trace context, only headers which help to correlate requests
+/// are kept, so that spans aren't cluttered with unrelated tags.
+static CARRIER_HEADERS: &[&str] = &[
+    "traceparent",
+    "tracestate",
+    "user-agent",
+    "x-request-id",
+    "forwarded",
+    "x-forwarded-for",
+];
 
-    fn set(&mut self, _key: &'static str, _value: String) {
-        unimplemented!()
+/// Carrier holding the tracing-relevant headers of a request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderCarrier(BTreeMap<String, String>);
+
+impl HeaderCarrier {
+    /// Iterate over the headers, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
     }
 }
 
-struct ClientHeaderMapCarrier(HashMap<String, String>);
-impl Carrier for ClientHeaderMapCarrier {
+impl Carrier for HeaderCarrier {
     fn get(&self, key: &'static str) -> Option<&str> {
-        self.0.get(key).map(|s| s.as_str())
+        self.0.get(key).map(String::as_str)
     }
 
     fn set(&mut self, key: &'static str, value: String) {
@@ -68,42 +442,536 @@ impl Carrier for ClientHeaderMapCarrier {
     }
 }
 
+/// Build a carrier from the tracing-relevant request headers.
+///
+/// Values which are not valid UTF-8 are converted lossily. Repeated headers
+/// are combined into a single comma-separated value, as per RFC 7230 section
+/// 3.2.2.
+pub fn carrier_from_headers(headers: &http::HeaderMap) -> HeaderCarrier {
+    let mut carrier = HeaderCarrier::default();
+
+    for name in CARRIER_HEADERS {
+        if let Some(value) = header_value(headers, name) {
+            carrier.0.insert(name.to_string(), value);
+        }
+    }
+
+    carrier
+}
+
+/// Return the combined value of a request header, if present.
+fn header_value(headers: &http::HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<_> = headers
+        .get_all(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()))
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
 /// Return the parent context for the request if specific headers found.
 pub fn get_context(req: &ServiceRequest) -> SpanContext {
     let propagator = TraceContextPropagator::new();
-    propagator.extract(&HttpHeaderMapCarrier(&req.headers()))
+    propagator.extract(&carrier_from_headers(req.headers()))
 }
 
 /// Inject context data into headers
 pub fn set_context(context: SpanContext, headers: &mut HeaderMap) -> crate::errors::Fallible<()> {
     use std::str::FromStr;
 
-    let mut carrier = {
-        let headers_converted = headers.iter().try_fold(
-            HashMap::<String, String>::with_capacity(headers.len()),
-            |mut sum, (name, value)| -> crate::errors::Fallible<_> {
-                sum.insert(name.as_str().to_string(), value.to_str()?.to_string());
-                Ok(sum)
-            },
-        )?;
-
-        ClientHeaderMapCarrier(headers_converted)
-    };
-
+    // Only the injected headers are written back, existing ones are untouched.
+    let mut carrier = HeaderCarrier::default();
     let propagator = TraceContextPropagator::new();
     propagator.inject(context, &mut carrier);
 
-    for (name, value) in carrier.0 {
-        headers.insert(HeaderName::from_str(&name)?, HeaderValue::from_str(&value)?);
+    for (name, value) in carrier.iter() {
+        headers.insert(HeaderName::from_str(name)?, HeaderValue::from_str(value)?);
     }
 
     Ok(())
 }
 
-/// Add span attributes from servicerequest
-pub fn set_span_tags(req: &ServiceRequest, span: &dyn Span) {
-    span.set_attribute(Key::new("path").string(req.path()));
-    req.headers().iter().for_each(|(k, v)| {
-        span.set_attribute(Key::new(format!("header.{}", k)).bytes(v.as_bytes().to_vec()))
-    });
+/// Default maximum length of a span tag value, in bytes.
+pub const DEFAULT_TAG_MAX_VALUE_LEN: usize = 256;
+
+/// Default maximum number of tags recorded on a request span.
+pub const DEFAULT_TAG_MAX_COUNT: usize = 16;
+
+/// Default size of the tags above which a span counts as oversized, in bytes.
+///
+/// This is the payload budget of a UDP packet to the jaeger agent, beyond
+/// which the span is silently dropped.
+pub const DEFAULT_OVERSIZE_SPAN_BYTES: usize = 65_000;
+
+/// Marker appended to truncated tag values.
+pub static TRUNCATION_MARKER: &str = "...[truncated]";
+
+/// Policy for the tags recorded on request spans.
+///
+/// Only allowlisted request headers are recorded, in allowlist order after
+/// the request path. Long values are truncated and the number of tags is
+/// capped, so that spans stay within the size the jaeger agent accepts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagPolicy {
+    /// Request headers recorded as tags, by lowercase name.
+    pub headers: Vec<String>,
+    /// Maximum length of a tag value in bytes, excluding the truncation marker.
+    pub max_value_len: usize,
+    /// Maximum number of tags, including the request path.
+    pub max_tags: usize,
+    /// Size of the tags above which a span is counted as oversized, in bytes.
+    pub oversize_bytes: usize,
+}
+
+impl Default for TagPolicy {
+    fn default() -> Self {
+        Self {
+            headers: CARRIER_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            max_value_len: DEFAULT_TAG_MAX_VALUE_LEN,
+            max_tags: DEFAULT_TAG_MAX_COUNT,
+            oversize_bytes: DEFAULT_OVERSIZE_SPAN_BYTES,
+        }
+    }
+}
+
+impl TagPolicy {
+    /// Set the allowlisted request headers, matched case-insensitively.
+    pub fn with_headers<I, S>(self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            headers: headers
+                .into_iter()
+                .map(|name| name.as_ref().to_lowercase())
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Return the tags for a request to `path` with the given headers.
+    ///
+    /// Spans whose tags exceed the oversize threshold are counted in
+    /// `tracing_oversize_spans_total`.
+    pub fn tags(&self, path: &str, headers: &http::HeaderMap) -> Vec<KeyValue> {
+        let tags: Vec<(String, String)> = std::iter::once(("path".to_string(), path.to_string()))
+            .chain(self.headers.iter().filter_map(|name| {
+                header_value(headers, name).map(|value| (format!("header.{}", name), value))
+            }))
+            .take(self.max_tags)
+            .map(|(key, value)| (key, self.truncate(value)))
+            .collect();
+
+        let size: usize = tags
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+        if size > self.oversize_bytes {
+            log::debug!("span tags of request to '{}' take {} bytes", path, size);
+            TRACING_OVERSIZE_SPANS.inc();
+        }
+
+        tags.into_iter()
+            .map(|(key, value)| Key::new(key).string(value))
+            .collect()
+    }
+
+    /// Record the tags for a request on its span.
+    pub fn set_span_tags(&self, req: &ServiceRequest, span: &dyn Span) {
+        self.tags(req.path(), req.headers())
+            .into_iter()
+            .for_each(|tag| span.set_attribute(tag));
+    }
+
+    /// Truncate a tag value to the maximum length, on a character boundary.
+    fn truncate(&self, mut value: String) -> String {
+        if value.len() <= self.max_value_len {
+            return value;
+        }
+
+        let mut end = self.max_value_len;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+        value.push_str(TRUNCATION_MARKER);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static NON_UTF8: &[u8] = b"\xfe\xff invalid";
+
+    #[test]
+    fn get_context_non_utf8_header() {
+        let req = actix_web::test::TestRequest::default()
+            .header(
+                "traceparent",
+                http::HeaderValue::from_bytes(NON_UTF8).unwrap(),
+            )
+            .header("x-custom", http::HeaderValue::from_bytes(NON_UTF8).unwrap())
+            .to_srv_request();
+
+        let context = get_context(&req);
+        assert!(!context.is_valid());
+
+        let span = get_tracer().start("test", Some(context));
+        TagPolicy::default().set_span_tags(&req, &span);
+    }
+
+    #[test]
+    fn carrier_from_headers_lossy_and_repeated() {
+        let req = actix_web::test::TestRequest::default()
+            .header(
+                "user-agent",
+                http::HeaderValue::from_bytes(NON_UTF8).unwrap(),
+            )
+            .header("x-forwarded-for", "192.0.2.1")
+            .header("x-forwarded-for", "198.51.100.2")
+            .header("cookie", "a=1")
+            .header("cookie", "b=2")
+            .to_srv_request();
+
+        let carrier = carrier_from_headers(req.headers());
+
+        assert_eq!(
+            carrier.iter().collect::<Vec<_>>(),
+            vec![
+                ("user-agent", "\u{fffd}\u{fffd} invalid"),
+                ("x-forwarded-for", "192.0.2.1, 198.51.100.2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn span_tags_trace_header() {
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let req = actix_web::test::TestRequest::with_uri("/v1/graph")
+            .header("traceparent", traceparent)
+            .header("x-custom", http::HeaderValue::from_bytes(NON_UTF8).unwrap())
+            .to_srv_request();
+
+        assert!(get_context(&req).is_valid());
+
+        let tags = TagPolicy::default().tags(req.path(), req.headers());
+        assert!(tags.contains(&Key::new("path").string("/v1/graph")));
+        assert!(tags.contains(&Key::new("header.traceparent").string(traceparent)));
+        assert!(tags
+            .iter()
+            .all(|tag| tag.key != Key::new("header.x-custom")));
+    }
+
+    #[test]
+    fn span_tags_allowlist() {
+        let req = actix_web::test::TestRequest::with_uri("/v1/graph")
+            .header("cookie", "session=secret")
+            .header("user-agent", "oc/4.6")
+            .header("X-Custom", "a")
+            .header("x-custom", "b")
+            .to_srv_request();
+
+        let policy = TagPolicy::default().with_headers(&["X-Custom", "user-agent"]);
+        assert_eq!(
+            policy.tags(req.path(), req.headers()),
+            vec![
+                Key::new("path").string("/v1/graph"),
+                Key::new("header.x-custom").string("a, b"),
+                Key::new("header.user-agent").string("oc/4.6"),
+            ]
+        );
+
+        // The tag count is capped, in allowlist order.
+        let policy = TagPolicy {
+            max_tags: 2,
+            ..policy
+        };
+        assert_eq!(
+            policy.tags(req.path(), req.headers()),
+            vec![
+                Key::new("path").string("/v1/graph"),
+                Key::new("header.x-custom").string("a, b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn span_tags_truncation() {
+        let req = actix_web::test::TestRequest::with_uri("/v1/graph")
+            .header("x-forwarded-for", "192.0.2.1, 198.51.100.2")
+            .header("user-agent", "cli\u{e9}nt")
+            .to_srv_request();
+
+        let policy = TagPolicy {
+            max_value_len: 9,
+            ..TagPolicy::default()
+        };
+        let tags = policy.tags(req.path(), req.headers());
+
+        // Values within the limit are kept as they are.
+        assert!(tags.contains(&Key::new("path").string("/v1/graph")));
+        assert!(tags.contains(
+            &Key::new("header.x-forwarded-for").string(format!("192.0.2.1{}", TRUNCATION_MARKER))
+        ));
+
+        // Values are truncated on a character boundary.
+        let policy = TagPolicy {
+            max_value_len: 4,
+            ..TagPolicy::default()
+        };
+        let tags = policy.tags(req.path(), req.headers());
+        assert!(tags
+            .contains(&Key::new("header.user-agent").string(format!("cli{}", TRUNCATION_MARKER))));
+    }
+
+    #[test]
+    fn span_tags_oversize() {
+        let req = actix_web::test::TestRequest::with_uri("/v1/graph")
+            .header("user-agent", "x".repeat(100))
+            .to_srv_request();
+
+        // The path tag takes 4 + 9 bytes.
+        let policy = TagPolicy {
+            headers: vec![],
+            oversize_bytes: 13,
+            ..TagPolicy::default()
+        };
+        let before = TRACING_OVERSIZE_SPANS.get();
+        policy.tags(req.path(), req.headers());
+        assert_eq!(TRACING_OVERSIZE_SPANS.get(), before);
+
+        // The truncated user-agent tag takes another 17 + 64 bytes.
+        let policy = TagPolicy {
+            max_value_len: 50,
+            oversize_bytes: 90,
+            ..TagPolicy::default()
+        };
+        policy.tags(req.path(), req.headers());
+        assert_eq!(TRACING_OVERSIZE_SPANS.get(), before + 1);
+    }
+
+    #[test]
+    fn debug_sampling_cap() {
+        let sampling = DebugSampling::new(Some("X-Debug-Trace".to_string()), 2);
+        let mut headers = http::HeaderMap::new();
+        let start = Instant::now();
+
+        assert_eq!(sampling.check(&headers, start), None);
+
+        headers.insert("x-debug-trace", http::HeaderValue::from_static("custom"));
+        assert_eq!(sampling.check(&headers, start), Some("custom".to_string()));
+
+        // The standard header takes precedence.
+        headers.insert(DEBUG_ID_HEADER, http::HeaderValue::from_static("abc"));
+        assert_eq!(sampling.check(&headers, start), Some("abc".to_string()));
+
+        // The cap is reached, until the next minute.
+        assert_eq!(sampling.check(&headers, start), None);
+        assert_eq!(
+            sampling.check(&headers, start + Duration::from_secs(59)),
+            None
+        );
+        assert_eq!(
+            sampling.check(&headers, start + Duration::from_secs(60)),
+            Some("abc".to_string())
+        );
+
+        // The debug id is still available for forwarding.
+        assert_eq!(sampling.debug_id(&headers), Some("abc".to_string()));
+    }
+
+    #[test]
+    fn debug_sampling_override() {
+        use opentelemetry::api::Sampler;
+
+        let sampler = DebugSampler(Box::new(sdk::Sampler::Never));
+        let should_sample = || {
+            sampler
+                .should_sample(
+                    None,
+                    api::TraceId::from_u128(1),
+                    api::SpanId::from_u64(1),
+                    "request",
+                    &api::SpanKind::Server,
+                    &[],
+                    &[],
+                )
+                .decision
+        };
+
+        assert_eq!(should_sample(), api::SamplingDecision::NotRecord);
+        FORCE_SAMPLING.with(|force| force.set(true));
+        assert_eq!(should_sample(), api::SamplingDecision::RecordAndSampled);
+        FORCE_SAMPLING.with(|force| force.set(false));
+
+        assert_eq!(
+            debug_id_tag("abc".to_string()),
+            Key::new("jaeger-debug-id").string("abc")
+        );
+
+        // The flag is reset after starting a span.
+        let sampling = DebugSampling::new(None, 1);
+        let mut headers = http::HeaderMap::new();
+        headers.insert(DEBUG_ID_HEADER, http::HeaderValue::from_static("abc"));
+        let _span = create_span_from_headers("test", None, &headers, Some(&sampling));
+        assert!(!FORCE_SAMPLING.with(Cell::get));
+    }
+
+    #[test]
+    fn set_context_non_utf8_header() -> Fallible<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-custom", HeaderValue::from_bytes(NON_UTF8)?);
+        headers.insert("x-valid", HeaderValue::from_static("valid"));
+
+        set_context(SpanContext::empty_context(), &mut headers)?;
+
+        assert_eq!(headers.get("x-custom").unwrap().as_bytes(), NON_UTF8);
+        assert_eq!(headers.get("x-valid").unwrap(), "valid");
+
+        Ok(())
+    }
+
+    /// Reporter failing on the items listed in `transient` and `broken`.
+    #[derive(Debug, Default)]
+    struct FlakyReporter {
+        /// Items which fail on the next attempt only, with a transient error.
+        transient: Vec<u32>,
+        /// Items which break the reporter on every attempt.
+        broken: Vec<u32>,
+        /// Items which have been reported.
+        reported: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl Reporter<u32> for FlakyReporter {
+        fn report(&mut self, batch: &[u32]) -> Result<(), ReportError> {
+            if let Some(pos) = self.transient.iter().position(|i| batch.contains(i)) {
+                self.transient.remove(pos);
+                return Err(ReportError::Transient(format_err!("transient failure")));
+            }
+            if self.broken.iter().any(|i| batch.contains(i)) {
+                return Err(ReportError::Broken(format_err!("broken reporter")));
+            }
+
+            self.reported.lock().unwrap().extend_from_slice(batch);
+            Ok(())
+        }
+    }
+
+    const TEST_RETRY_POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(2),
+    };
+
+    #[test]
+    fn reporter_retries_transient_failures() {
+        let reported = Arc::new(Mutex::new(vec![]));
+        let reporter = FlakyReporter {
+            transient: vec![2, 2, 3],
+            broken: vec![],
+            reported: reported.clone(),
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(4);
+        for item in 1..=4 {
+            sender.send(vec![item]).unwrap();
+        }
+        drop(sender);
+
+        run_reporter(
+            receiver,
+            Some(reporter),
+            || -> Fallible<FlakyReporter> { bail!("unexpected reporter re-creation") },
+            TEST_RETRY_POLICY,
+        );
+
+        assert_eq!(*reported.lock().unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn reporter_recreated_when_broken() {
+        let reported = Arc::new(Mutex::new(vec![]));
+        let mut created = 0;
+
+        let (sender, receiver) = mpsc::sync_channel(4);
+        for item in 1..=3 {
+            sender.send(vec![item]).unwrap();
+        }
+        drop(sender);
+
+        run_reporter(
+            receiver,
+            Some(FlakyReporter {
+                transient: vec![],
+                broken: vec![2],
+                reported: reported.clone(),
+            }),
+            || {
+                created += 1;
+                Ok(FlakyReporter {
+                    transient: vec![],
+                    broken: vec![],
+                    reported: reported.clone(),
+                })
+            },
+            TEST_RETRY_POLICY,
+        );
+
+        assert_eq!(*reported.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(created, 1);
+    }
+
+    #[test]
+    fn reporter_drops_after_max_attempts() {
+        let reported = Arc::new(Mutex::new(vec![]));
+        let broken = || FlakyReporter {
+            transient: vec![],
+            broken: vec![2],
+            reported: reported.clone(),
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(4);
+        for item in 1..=3 {
+            sender.send(vec![item]).unwrap();
+        }
+        drop(sender);
+
+        run_reporter(receiver, None, || Ok(broken()), TEST_RETRY_POLICY);
+
+        assert_eq!(*reported.lock().unwrap(), vec![1, 3]);
+    }
+
+    #[test]
+    fn enqueue_drops_on_full_backlog() {
+        let (sender, receiver) = mpsc::sync_channel(1);
+
+        let dropped_before = TRACING_SPANS_DROPPED.get();
+        assert!(matches!(
+            enqueue(&sender, vec![1, 2]),
+            ExportResult::Success
+        ));
+        assert!(matches!(
+            enqueue(&sender, vec![3, 4, 5]),
+            ExportResult::FailedRetryable
+        ));
+        assert!(TRACING_SPANS_DROPPED.get() >= dropped_before + 3);
+
+        assert_eq!(receiver.try_recv().unwrap(), vec![1, 2]);
+        assert!(receiver.try_recv().is_err());
+
+        drop(receiver);
+        assert!(matches!(
+            enqueue(&sender, vec![6]),
+            ExportResult::FailedNotRetryable
+        ));
+    }
 }
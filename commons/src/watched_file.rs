@@ -0,0 +1,104 @@
+//! Files re-read when they change.
+//!
+//! A `WatchedFile` caches the content of a file, such as a mounted secret,
+//! and reads it again whenever its modification time or size changes. The
+//! file metadata is checked on each access, which is cheap enough for files
+//! read once per request.
+
+use crate::prelude_errors::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Identity of a version of the file content.
+type Stamp = (Option<SystemTime>, u64);
+
+/// File whose content is cached until it changes.
+///
+/// The content is left out of the `Debug` output, as it may be a secret.
+pub struct WatchedFile {
+    /// Path to the file.
+    path: PathBuf,
+    /// Last read content, along with the stamp of the file at that time.
+    cached: Mutex<Option<(Stamp, String)>>,
+}
+
+impl std::fmt::Debug for WatchedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchedFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl WatchedFile {
+    /// Watch the file at `path`, reading it right away.
+    pub fn try_new<P: Into<PathBuf>>(path: P) -> Fallible<Self> {
+        let file = Self {
+            path: path.into(),
+            cached: Mutex::new(None),
+        };
+        file.read()?;
+        Ok(file)
+    }
+
+    /// Path to the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the file content, reading it again if the file changed since the last read.
+    pub fn read(&self) -> Fallible<String> {
+        let metadata = std::fs::metadata(&self.path)
+            .context(format!("failed to inspect '{}'", self.path.display()))?;
+        let stamp = (metadata.modified().ok(), metadata.len());
+
+        let mut cached = self
+            .cached
+            .lock()
+            .map_err(|_| format_err!("poisoned lock on '{}'", self.path.display()))?;
+        if let Some((cached_stamp, content)) = &*cached {
+            if *cached_stamp == stamp {
+                return Ok(content.clone());
+            }
+        }
+
+        let content = std::fs::read_to_string(&self.path)
+            .context(format!("failed to read '{}'", self.path.display()))?;
+        if cached.is_some() {
+            log::info!("reloaded '{}'", self.path.display());
+        }
+        *cached = Some((stamp, content.clone()));
+
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reread_on_change() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("token");
+
+        assert!(WatchedFile::try_new(&path).is_err());
+
+        std::fs::write(&path, "first")?;
+        let file = WatchedFile::try_new(&path)?;
+        assert_eq!(file.read()?, "first");
+
+        // A different size is picked up even within the mtime granularity.
+        std::fs::write(&path, "second")?;
+        assert_eq!(file.read()?, "second");
+
+        assert!(!format!("{:?}", file).contains("second"));
+
+        std::fs::remove_file(&path)?;
+        let err = file.read().unwrap_err();
+        assert!(err.to_string().contains("failed to inspect"));
+
+        Ok(())
+    }
+}
@@ -5,8 +5,7 @@ use super::AppSettings;
 use commons::de::de_loglevel;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
-use std::io::Read;
-use std::{fs, io, path};
+use std::path;
 
 /// TOML configuration, top-level.
 #[derive(Debug, Deserialize)]
@@ -33,21 +32,7 @@ impl FileOptions {
     where
         P: AsRef<path::Path>,
     {
-        let cfg_file = fs::File::open(&cfg_path).context(format!(
-            "failed to open config path {:?}",
-            cfg_path.as_ref()
-        ))?;
-        let mut bufrd = io::BufReader::new(cfg_file);
-
-        let mut content = vec![];
-        bufrd.read_to_end(&mut content)?;
-        let cfg = toml::from_slice(&content).context(format!(
-            "failed to parse config file {}:\n{}",
-            cfg_path.as_ref().display(),
-            std::str::from_utf8(&content).unwrap_or("file not decodable")
-        ))?;
-
-        Ok(cfg)
+        commons::signature::read_verified_filepath(cfg_path, None)
     }
 }
 
@@ -131,6 +116,51 @@ mod tests {
         assert_eq!(settings.status_port, 2222);
     }
 
+    #[test]
+    fn toml_max_connections() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.max_connections, None);
+
+        let toml_input = "service.max_connections = 1000";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        assert_eq!(
+            file_opts.service.as_ref().unwrap().max_connections,
+            Some(1000)
+        );
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.max_connections, Some(1000));
+    }
+
+    #[test]
+    fn toml_unknown_channel() {
+        use crate::graph::UnknownChannel;
+
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.unknown_channel, UnknownChannel::Empty);
+
+        let file_opts: FileOptions = toml::from_str("service.unknown_channel = 'reject'").unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.unknown_channel, UnknownChannel::Reject);
+
+        toml::from_str::<FileOptions>("service.unknown_channel = 'ignore'").unwrap_err();
+    }
+
+    #[test]
+    fn toml_on_empty_plugin_chain() {
+        use cincinnati::plugins::catalog::EmptyChain;
+
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.on_empty_plugin_chain, EmptyChain::Warn);
+
+        let file_opts: FileOptions =
+            toml::from_str("service.on_empty_plugin_chain = 'fail'").unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.on_empty_plugin_chain, EmptyChain::Fail);
+
+        toml::from_str::<FileOptions>("service.on_empty_plugin_chain = 'ignore'").unwrap_err();
+    }
+
     #[test]
     fn toml_sample_config() {
         use tempfile;
@@ -172,4 +202,47 @@ mod tests {
         let repo = ups_registry.repository.unwrap();
         assert_eq!(repo, "openshift-release-dev/ocp-release");
     }
+
+    #[test]
+    fn toml_mandatory_client_parameters_file() {
+        use std::io::Write;
+
+        let mut params_file = tempfile::NamedTempFile::new().unwrap();
+        params_file.write_all(b"channel\narch, id\n\n").unwrap();
+
+        let toml_input = format!(
+            "[service]\nmandatory_client_parameters = ['version']\nmandatory_client_parameters_file = '{}'",
+            params_file.path().display()
+        );
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+
+        let mut settings = AppSettings::default();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let expected = vec!["arch", "channel", "id", "version"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(settings.mandatory_client_parameters, expected);
+    }
+
+    #[test]
+    fn toml_mandatory_client_parameters_file_missing() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("missing");
+
+        let toml_input = format!(
+            "[service]\nmandatory_client_parameters_file = '{}'",
+            path.display()
+        );
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+
+        let mut settings = AppSettings::default();
+        let err = settings.try_merge(Some(file_opts)).unwrap_err();
+        assert!(
+            err.to_string().contains(&path.display().to_string()),
+            "unexpected error: {}",
+            err
+        );
+    }
 }
@@ -1,8 +1,14 @@
 //! Options shared by CLI and TOML.
 
 use super::AppSettings;
+use crate::graph::UnknownChannel;
+use cincinnati::plugins::catalog::EmptyChain;
+use commons::http::IpNet;
 use commons::prelude_errors::*;
-use commons::{de_path_prefix, parse_params_set, parse_path_prefix, MergeOptions};
+use commons::{
+    de_path_prefix, parse_params_list, parse_params_set, parse_path_prefix, read_params_set,
+    MergeOptions,
+};
 use std::collections::HashSet;
 use std::net::IpAddr;
 use std::path::PathBuf;
@@ -62,9 +68,80 @@ pub struct ServiceOptions {
     )]
     pub mandatory_client_parameters: Option<HashSet<String>>,
 
+    /// Path to a file with the set of mandatory client parameters, separated by newlines or commas
+    #[structopt(long = "service.mandatory_client_parameters_file")]
+    pub mandatory_client_parameters_file: Option<PathBuf>,
+
     /// Optional tracing endpoint
     #[structopt(name = "tracing_endpoint", long = "service.tracing_endpoint")]
     pub tracing_endpoint: Option<String>,
+
+    /// Log each request of the main service, as JSON at info level on the 'access_log' target
+    #[structopt(long = "service.access_log")]
+    pub access_log: Option<bool>,
+
+    /// Comma-separated set of client parameters whose values are redacted in the access log
+    #[structopt(
+        long = "service.access_log_redacted_params",
+        parse(from_str = parse_params_set)
+    )]
+    pub access_log_redacted_params: Option<HashSet<String>>,
+
+    /// Comma-separated list of CIDRs of proxies trusted to report the client address
+    #[structopt(long = "service.trusted_proxies", use_delimiter = true)]
+    pub trusted_proxies: Option<Vec<IpNet>>,
+
+    /// Maximum number of concurrent connections per worker
+    #[structopt(long = "service.max_connections")]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of requests per minute force-sampled by a debug id header
+    #[structopt(long = "service.tracing_debug_sampling")]
+    pub tracing_debug_sampling: Option<u32>,
+
+    /// Additional header carrying a debug id, besides 'jaeger-debug-id'
+    #[structopt(long = "service.tracing_debug_header")]
+    pub tracing_debug_header: Option<String>,
+
+    /// Comma-separated list of request headers recorded as span tags
+    #[structopt(long = "service.tracing_tag_headers", parse(from_str = parse_params_list))]
+    pub tracing_tag_headers: Option<Vec<String>>,
+
+    /// Maximum length of a span tag value in bytes, beyond which it is truncated
+    #[structopt(long = "service.tracing_tag_max_value_len")]
+    pub tracing_tag_max_value_len: Option<usize>,
+
+    /// Maximum number of tags recorded on a request span
+    #[structopt(long = "service.tracing_tag_max_count")]
+    pub tracing_tag_max_count: Option<usize>,
+
+    /// Size of the span tags in bytes above which a span is counted as oversized
+    #[structopt(long = "service.tracing_oversize_span_bytes")]
+    pub tracing_oversize_span_bytes: Option<usize>,
+
+    /// Handling of requests for channels without any release, either 'empty' or 'reject'
+    #[structopt(long = "service.unknown_channel")]
+    pub unknown_channel: Option<UnknownChannel>,
+
+    /// Handling of an empty plugin chain, either 'warn', 'fail' or 'serve'
+    #[structopt(long = "service.on_empty_plugin_chain")]
+    pub on_empty_plugin_chain: Option<EmptyChain>,
+
+    /// Path to the state file of the first-seen timestamps of releases
+    #[structopt(long = "service.first_seen_path")]
+    pub first_seen_path: Option<PathBuf>,
+
+    /// Retention in seconds of first-seen timestamps of releases absent from the graph
+    #[structopt(
+        long = "service.first_seen_retention_secs",
+        parse(try_from_str = duration_from_secs)
+    )]
+    #[serde(default = "Option::default", deserialize_with = "de_duration_secs")]
+    pub first_seen_retention_secs: Option<Duration>,
+
+    /// Record first-seen timestamps of releases as release metadata
+    #[structopt(long = "service.first_seen_metadata")]
+    pub first_seen_metadata: Option<bool>,
 }
 
 /// Options for the Docker-registry-v2 fetcher.
@@ -103,9 +180,41 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
             assign_if_some!(self.port, service.port);
             assign_if_some!(self.path_prefix, service.path_prefix);
             assign_if_some!(self.tracing_endpoint, service.tracing_endpoint);
+            assign_if_some!(self.tracing_debug_sampling, service.tracing_debug_sampling);
+            assign_if_some!(self.tracing_debug_header, service.tracing_debug_header);
+            if let Some(headers) = service.tracing_tag_headers {
+                self.tracing_tags = self.tracing_tags.clone().with_headers(headers);
+            }
+            assign_if_some!(
+                self.tracing_tags.max_value_len,
+                service.tracing_tag_max_value_len
+            );
+            assign_if_some!(self.tracing_tags.max_tags, service.tracing_tag_max_count);
+            assign_if_some!(
+                self.tracing_tags.oversize_bytes,
+                service.tracing_oversize_span_bytes
+            );
+            assign_if_some!(self.max_connections, service.max_connections);
+            assign_if_some!(self.unknown_channel, service.unknown_channel);
+            assign_if_some!(self.on_empty_plugin_chain, service.on_empty_plugin_chain);
+            assign_if_some!(self.access_log, service.access_log);
+            assign_if_some!(self.trusted_proxies, service.trusted_proxies);
+            assign_if_some!(self.first_seen_path, service.first_seen_path);
+            assign_if_some!(
+                self.first_seen_retention_secs,
+                service.first_seen_retention_secs
+            );
+            assign_if_some!(self.first_seen_metadata, service.first_seen_metadata);
+            if let Some(params) = service.access_log_redacted_params {
+                self.access_log_redacted_params.extend(params);
+            }
             if let Some(params) = service.mandatory_client_parameters {
                 self.mandatory_client_parameters.extend(params);
             }
+            if let Some(path) = service.mandatory_client_parameters_file {
+                let params = read_params_set(path)?;
+                self.mandatory_client_parameters.extend(params);
+            }
         }
         Ok(())
     }
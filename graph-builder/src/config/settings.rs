@@ -1,13 +1,17 @@
 //! Application settings for graph-builder.
 
 use super::{cli, file};
-use cincinnati::plugins::catalog::{build_plugins, PluginSettings};
+use crate::first_seen::{self, FirstSeen};
+use crate::graph::UnknownChannel;
+use cincinnati::plugins::catalog::{build_plugins, check_chain, EmptyChain, PluginSettings};
 use cincinnati::plugins::BoxedPlugin;
+use commons::http::IpNet;
 use commons::prelude_errors::*;
+use commons::tracing::TagPolicy;
 use commons::MergeOptions;
 use std::collections::HashSet;
 use std::net::{IpAddr, Ipv4Addr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time;
 use structopt::StructOpt;
 
@@ -79,6 +83,49 @@ pub struct AppSettings {
 
     /// Jaeger host and port for tracing support
     pub tracing_endpoint: Option<String>,
+
+    /// Maximum number of requests per minute force-sampled by a debug id.
+    ///
+    /// Forced sampling is disabled if unset.
+    pub tracing_debug_sampling: Option<u32>,
+
+    /// Additional header carrying a debug id, besides `jaeger-debug-id`.
+    pub tracing_debug_header: Option<String>,
+
+    /// Policy for the tags recorded on request spans.
+    pub tracing_tags: TagPolicy,
+
+    /// Whether to log each request of the main service.
+    pub access_log: bool,
+
+    /// Client parameters whose values are redacted in the access log.
+    pub access_log_redacted_params: HashSet<String>,
+
+    /// Proxies trusted to report the client address in forwarding headers.
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Maximum number of concurrent connections per worker for the main service.
+    ///
+    /// The actix default is used if unset.
+    pub max_connections: Option<usize>,
+
+    /// Handling of graph requests for channels without any release.
+    pub unknown_channel: UnknownChannel,
+
+    /// Handling of an empty plugin chain.
+    pub on_empty_plugin_chain: EmptyChain,
+
+    /// State file of the first-seen timestamps of releases.
+    ///
+    /// Timestamps are only kept in memory if unset.
+    pub first_seen_path: Option<PathBuf>,
+
+    /// Retention (in seconds) of first-seen timestamps of releases absent from the graph.
+    #[default(time::Duration::from_secs(first_seen::DEFAULT_RETENTION_SECS))]
+    pub first_seen_retention_secs: time::Duration,
+
+    /// Whether to record first-seen timestamps as release metadata.
+    pub first_seen_metadata: bool,
 }
 
 impl AppSettings {
@@ -87,10 +134,7 @@ impl AppSettings {
     pub fn assemble() -> Fallible<Self> {
         // Source options.
         let cli_opts = cli::CliOptions::from_args();
-        let file_opts = match &cli_opts.config_path {
-            Some(ref path) => Some(file::FileOptions::read_filepath(path)?),
-            None => None,
-        };
+        let file_opts = cli_opts.read_config_file()?;
         let defaults = Self::default();
 
         // Combine options into a single config.
@@ -102,6 +146,23 @@ impl AppSettings {
         Self::try_validate(cfg)
     }
 
+    /// Read a configuration file, merge it with defaults, and
+    /// transform into valid runtime settings.
+    ///
+    /// This is meant for embedding the graph-builder in another service,
+    /// which owns the command-line.
+    pub fn from_filepath<P>(cfg_path: P) -> Fallible<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file_opts = file::FileOptions::read_filepath(cfg_path)?;
+
+        let mut cfg = Self::default();
+        cfg.try_merge(Some(file_opts))?;
+
+        Self::try_validate(cfg)
+    }
+
     /// Validate and return configured plugins.
     pub fn validate_and_build_plugins(
         &self,
@@ -115,7 +176,19 @@ impl AppSettings {
             &self.plugin_settings
         };
 
-        build_plugins(plugin_settings, registry)
+        let plugins = build_plugins(plugin_settings, registry)?;
+        check_chain(&plugins, self.on_empty_plugin_chain)?;
+
+        Ok(plugins)
+    }
+
+    /// Load the first-seen timestamps of releases.
+    pub fn load_first_seen(&self) -> Fallible<FirstSeen> {
+        FirstSeen::load(
+            self.first_seen_path.as_deref(),
+            self.first_seen_retention_secs,
+            self.first_seen_metadata,
+        )
     }
 
     /// Validate and build runtime settings.
@@ -124,6 +197,18 @@ impl AppSettings {
             bail!("unexpected 0s pause");
         }
 
+        if self.max_connections == Some(0) {
+            bail!("unexpected zero max_connections");
+        }
+
+        if self.tracing_tags.max_value_len == 0 {
+            bail!("unexpected zero tracing_tag_max_value_len");
+        }
+
+        if self.tracing_tags.max_tags == 0 {
+            bail!("unexpected zero tracing_tag_max_count");
+        }
+
         Ok(self)
     }
 
@@ -188,3 +273,27 @@ impl AppSettings {
         Ok(plugins)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AppSettings;
+
+    #[test]
+    fn validate_max_connections() {
+        let settings = AppSettings {
+            max_connections: Some(0),
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let settings = AppSettings {
+            max_connections: Some(1000),
+            ..Default::default()
+        };
+        let settings = AppSettings::try_validate(settings).unwrap();
+        assert_eq!(settings.max_connections, Some(1000));
+
+        let settings = AppSettings::try_validate(AppSettings::default()).unwrap();
+        assert_eq!(settings.max_connections, None);
+    }
+}
@@ -0,0 +1,50 @@
+//! In-process graph source, for embedding the graph-builder in another service.
+
+use crate::graph::State;
+use cincinnati::plugins::prelude::*;
+use cincinnati::plugins::prelude_plugin_impl::*;
+use commons::GraphError;
+
+/// Plugin serving the graph of an in-process scrape loop.
+///
+/// It replaces fetching the graph from an upstream graph-builder over HTTP,
+/// and is meant to be the first plugin of a policy pipeline.
+#[derive(CustomDebug)]
+pub struct EmbeddedGraphSourcePlugin {
+    #[debug(skip)]
+    state: State,
+}
+
+impl EmbeddedGraphSourcePlugin {
+    /// Plugin name, for diagnostics.
+    pub const PLUGIN_NAME: &'static str = "embedded-graph-source";
+
+    /// Create a plugin reading the graph of the scrape loop sharing `state`.
+    pub fn new(state: State) -> Self {
+        Self { state }
+    }
+
+    /// Create the plugin, boxed for inclusion in a pipeline.
+    pub fn boxed(state: State) -> BoxedPlugin {
+        new_plugin!(InternalPluginWrapper(Self::new(state)))
+    }
+}
+
+#[async_trait]
+impl InternalPlugin for EmbeddedGraphSourcePlugin {
+    const PLUGIN_NAME: &'static str = Self::PLUGIN_NAME;
+    const ABI_VERSION: u32 = 4;
+
+    async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+        let graph = self
+            .state
+            .graph()
+            .ok_or_else(|| GraphError::FailedUpstreamFetch("no graph scraped yet".to_string()))?;
+
+        Ok(InternalIO {
+            graph: graph.as_ref().clone(),
+            parameters: io.parameters,
+            warnings: io.warnings,
+        })
+    }
+}
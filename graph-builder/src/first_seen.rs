@@ -0,0 +1,264 @@
+//! Tracking of the time each release first appeared in the graph.
+//!
+//! The first-seen timestamp of every release version is kept across scrapes
+//! and, if a state file is configured, across restarts. Versions absent from
+//! the graph for longer than the retention are forgotten, so that a release
+//! coming back later is seen anew.
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use commons::prelude_errors::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Metadata key where to record the first-seen timestamp, if enabled.
+pub static FIRST_SEEN_KEY: &str = "io.openshift.upgrades.graph.release.first-seen";
+
+/// Default retention of versions absent from the graph, in seconds.
+pub const DEFAULT_RETENTION_SECS: u64 = 30 * 24 * 3600;
+
+/// Timestamps of a release version, in RFC3339 format.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct Record {
+    first_seen: String,
+    last_seen: String,
+}
+
+/// First-seen timestamps of release versions, optionally backed by a file.
+#[derive(Clone, Debug)]
+pub struct FirstSeen {
+    /// State file, timestamps are only kept in memory if unset.
+    path: Option<PathBuf>,
+    /// Duration after which absent versions are forgotten.
+    retention: Duration,
+    /// Whether to record the timestamps as release metadata.
+    inject_metadata: bool,
+    records: BTreeMap<String, Record>,
+}
+
+impl Default for FirstSeen {
+    fn default() -> Self {
+        Self {
+            path: None,
+            retention: Duration::seconds(DEFAULT_RETENTION_SECS as i64),
+            inject_metadata: false,
+            records: BTreeMap::new(),
+        }
+    }
+}
+
+impl FirstSeen {
+    /// Load the timestamps from the state file, if any.
+    ///
+    /// A missing state file is created on the first update. A corrupt one is
+    /// rebuilt from scratch, with a warning.
+    pub fn load(
+        path: Option<&Path>,
+        retention: std::time::Duration,
+        inject_metadata: bool,
+    ) -> Fallible<Self> {
+        let retention = Duration::from_std(retention)
+            .map_err(|e| format_err!("invalid first-seen retention: {}", e))?;
+        let mut first_seen = Self {
+            path: path.map(Path::to_path_buf),
+            retention,
+            inject_metadata,
+            records: BTreeMap::new(),
+        };
+
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(first_seen),
+        };
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(first_seen),
+            Err(e) => {
+                return Err(e).context(format!(
+                    "failed to read first-seen state file {}",
+                    path.display()
+                ))
+            }
+        };
+
+        match serde_json::from_str::<BTreeMap<String, Record>>(&content) {
+            Ok(records) if records.values().all(Record::is_valid) => {
+                first_seen.records = records;
+            }
+            Ok(_) => warn!(
+                "invalid timestamps in first-seen state file {}, rebuilding it",
+                path.display()
+            ),
+            Err(e) => warn!(
+                "corrupt first-seen state file {}, rebuilding it: {}",
+                path.display(),
+                e
+            ),
+        }
+        Ok(first_seen)
+    }
+
+    /// Whether to record the timestamps as release metadata.
+    pub fn inject_metadata(&self) -> bool {
+        self.inject_metadata
+    }
+
+    /// Return the first-seen timestamp of a version.
+    pub fn get(&self, version: &str) -> Option<&str> {
+        self.records
+            .get(version)
+            .map(|record| record.first_seen.as_str())
+    }
+
+    /// Return the first-seen timestamps of all tracked versions.
+    pub fn timestamps(&self) -> BTreeMap<String, String> {
+        self.records
+            .iter()
+            .map(|(version, record)| (version.clone(), record.first_seen.clone()))
+            .collect()
+    }
+
+    /// Record the versions of a successful scrape at time `now`, and persist them.
+    ///
+    /// New versions are first seen at `now`, and versions absent for longer
+    /// than the retention are pruned.
+    pub fn update<'a, I>(&mut self, versions: I, now: DateTime<Utc>) -> Fallible<()>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let now_rfc3339 = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+        for version in versions {
+            let record = self
+                .records
+                .entry(version.to_string())
+                .or_insert_with(|| Record {
+                    first_seen: now_rfc3339.clone(),
+                    last_seen: now_rfc3339.clone(),
+                });
+            record.last_seen = now_rfc3339.clone();
+        }
+
+        let retention = self.retention;
+        self.records.retain(|version, record| {
+            let keep = match DateTime::parse_from_rfc3339(&record.last_seen) {
+                Ok(last_seen) => now.signed_duration_since(last_seen) <= retention,
+                Err(_) => false,
+            };
+            if !keep {
+                debug!("forgetting first-seen timestamp of '{}'", version);
+            }
+            keep
+        });
+
+        self.save()
+    }
+
+    /// Write the timestamps to the state file, if any.
+    ///
+    /// The file is replaced atomically, so that a crash never leaves it truncated.
+    fn save(&self) -> Fallible<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let json = serde_json::to_string_pretty(&self.records)?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .context(format!(
+                "failed to write first-seen state file {}",
+                path.display()
+            ))?;
+        Ok(())
+    }
+}
+
+impl Record {
+    fn is_valid(&self) -> bool {
+        DateTime::parse_from_rfc3339(&self.first_seen).is_ok()
+            && DateTime::parse_from_rfc3339(&self.last_seen).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.ymd(2021, 3, day).and_hms(12, 0, 0)
+    }
+
+    fn retention_days(days: u64) -> std::time::Duration {
+        std::time::Duration::from_secs(days * 24 * 3600)
+    }
+
+    #[test]
+    fn consecutive_scrapes() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("first-seen.json");
+
+        let mut first_seen = FirstSeen::load(Some(&path), retention_days(7), false)?;
+        first_seen.update(vec!["4.6.0", "4.6.1"], at(1))?;
+
+        // 4.6.2 appears, 4.6.0 disappears.
+        first_seen.update(vec!["4.6.1", "4.6.2"], at(2))?;
+        assert_eq!(first_seen.get("4.6.0"), Some("2021-03-01T12:00:00Z"));
+        assert_eq!(first_seen.get("4.6.1"), Some("2021-03-01T12:00:00Z"));
+        assert_eq!(first_seen.get("4.6.2"), Some("2021-03-02T12:00:00Z"));
+
+        // Timestamps survive a restart.
+        let mut first_seen = FirstSeen::load(Some(&path), retention_days(7), false)?;
+        assert_eq!(first_seen.timestamps().len(), 3);
+
+        // 4.6.0 is still within the retention, and keeps its timestamp.
+        first_seen.update(vec!["4.6.0", "4.6.1", "4.6.2"], at(8))?;
+        assert_eq!(first_seen.get("4.6.0"), Some("2021-03-01T12:00:00Z"));
+
+        // 4.6.1 has been absent for longer than the retention, and is seen anew.
+        first_seen.update(vec!["4.6.0", "4.6.2"], at(9))?;
+        first_seen.update(vec!["4.6.0", "4.6.2"], at(16))?;
+        assert_eq!(first_seen.get("4.6.1"), None);
+        first_seen.update(vec!["4.6.0", "4.6.1", "4.6.2"], at(17))?;
+        assert_eq!(first_seen.get("4.6.1"), Some("2021-03-17T12:00:00Z"));
+
+        let reloaded = FirstSeen::load(Some(&path), retention_days(7), false)?;
+        assert_eq!(reloaded.timestamps(), first_seen.timestamps());
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_state_file_is_rebuilt() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("first-seen.json");
+
+        for content in &[
+            "{ not json",
+            r#"{"4.6.0": {"first_seen": "yesterday", "last_seen": "today"}}"#,
+        ] {
+            std::fs::write(&path, content)?;
+
+            let mut first_seen = FirstSeen::load(Some(&path), retention_days(7), false)?;
+            assert!(first_seen.timestamps().is_empty(), "content: {}", content);
+
+            first_seen.update(vec!["4.6.1"], at(1))?;
+            let reloaded = FirstSeen::load(Some(&path), retention_days(7), false)?;
+            assert_eq!(reloaded.get("4.6.1"), Some("2021-03-01T12:00:00Z"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn in_memory_without_state_file() -> Fallible<()> {
+        let mut first_seen = FirstSeen::default();
+        first_seen.update(vec!["4.6.0"], at(1))?;
+        first_seen.update(vec!["4.6.0", "4.6.1"], at(2))?;
+
+        assert_eq!(first_seen.get("4.6.0"), Some("2021-03-01T12:00:00Z"));
+        assert_eq!(first_seen.get("4.6.1"), Some("2021-03-02T12:00:00Z"));
+
+        Ok(())
+    }
+}
@@ -13,23 +13,49 @@
 // limitations under the License.
 
 use crate::built_info;
-use crate::config;
+use crate::first_seen::{FirstSeen, FIRST_SEEN_KEY};
+use crate::topology::{self, Topology};
+use actix_web::http::header;
+use actix_web::web::Query;
 use actix_web::{HttpRequest, HttpResponse};
+use cincinnati::plugins::catalog::EmptyChain;
 use cincinnati::plugins::prelude::*;
 use cincinnati::CONTENT_TYPE;
+use commons::log_throttle::ThrottledLogger;
 use commons::metrics::HasRegistry;
 use commons::tracing::get_tracer;
 use commons::{Fallible, GraphError};
 use lazy_static;
 use opentelemetry::api::Tracer;
 pub use parking_lot::RwLock;
+use parking_lot::{Condvar, Mutex};
 use prometheus::{self, histogram_opts, labels, opts, Counter, Gauge, Histogram, IntGauge};
 use serde_json;
-use std::collections::HashSet;
-use std::sync::Arc;
-use std::thread;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hasher;
+use std::str::FromStr;
+use std::sync::{Arc, Once};
+use std::time::{Duration, Instant};
+
+/// Name of the query parameter selecting a single channel of the graph.
+pub static CHANNEL_PARAM: &str = "channel";
+
+/// JSON of the empty graph, served for unknown channels.
+pub static EMPTY_GRAPH_JSON: &str = r#"{"nodes":[],"edges":[]}"#;
+
+/// Seconds clients are asked to wait before retrying, until the first scrape succeeds.
+pub const FIRST_SCRAPE_RETRY_AFTER_SECS: u64 = 10;
+
+/// Number of errors of each kind logged per throttling window of failed scrapes.
+const SCRAPE_ERRORS_PER_WINDOW: u32 = 3;
+
+/// Length of the throttling window of failed scrapes, in scrape pauses.
+const SCRAPE_ERROR_WINDOW_PAUSES: u32 = 10;
 
 lazy_static! {
+    /// Throttles warnings about requests received before the first scrape.
+    static ref INDEX_ERROR_LOG: ThrottledLogger = ThrottledLogger::new(1, Duration::from_secs(60));
     static ref GRAPH_FINAL_RELEASES: IntGauge = IntGauge::new(
         "graph_final_releases",
         "Number of releases in the final graph, after processing"
@@ -67,6 +93,9 @@ lazy_static! {
         "Total number of incoming HTTP client request to /v1/graph"
     )
     .unwrap();
+    /// Empty graph, served for unknown channels.
+    static ref EMPTY_GRAPH: Arc<SerializedGraph> =
+        Arc::new(SerializedGraph::new(EMPTY_GRAPH_JSON.to_string()));
     static ref BUILD_INFO: Counter = Counter::with_opts(opts!(
         "build_info",
         "Build information",
@@ -91,6 +120,7 @@ pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
     registry.register(Box::new(UPSTREAM_SCRAPES_DURATION.clone()))?;
     registry.register(Box::new(V1_GRAPH_INCOMING_REQS.clone()))?;
     registry.register(Box::new(BUILD_INFO.clone()))?;
+    topology::register_metrics(registry)?;
     Ok(())
 }
 
@@ -110,43 +140,318 @@ pub async fn index(
     let mandatory_params = &app_data.mandatory_params;
     commons::ensure_query_params(mandatory_params, req.query_string())?;
 
-    let resp = HttpResponse::Ok()
-        .content_type(CONTENT_TYPE)
-        .body(app_data.json.read().clone());
-    Ok(resp)
+    let params = Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map_err(|e| GraphError::InvalidParams(e.to_string()))?;
+
+    // An empty plugin chain only ever scrapes an empty graph, which clients
+    // would take for the absence of upgrades.
+    if app_data.plugins.is_empty() && !app_data.serve_empty_graph {
+        return Err(GraphError::ServiceUnavailable(
+            "no plugins configured".to_string(),
+            None,
+        ));
+    }
+
+    // Until the first scrape succeeds there is no graph to serve, not even an
+    // empty one: clients would wrongly conclude that no update is available.
+    let etags = app_data.etags().ok_or_else(no_graph_error)?;
+
+    // Conditional requests for the current graph are answered from the
+    // entity-tags alone, without waiting for a refresh to publish its graph.
+    let etag = match params.get(CHANNEL_PARAM) {
+        Some(channel) => app_data.channel_etag(&etags, channel)?,
+        None => etags.graph.as_str(),
+    };
+    if let Some(resp) = not_modified(&req, etag) {
+        return Ok(resp);
+    }
+
+    let snapshot = app_data.snapshot().ok_or_else(no_graph_error)?;
+    let graph = match params.get(CHANNEL_PARAM) {
+        Some(channel) => app_data.channel_graph(&snapshot, channel)?,
+        None => snapshot.serialized.clone(),
+    };
+    Ok(graph_response(&req, &graph.json, &graph.etag))
+}
+
+/// Build the error rejecting graph requests before the first scrape.
+fn no_graph_error() -> GraphError {
+    commons::warn_throttled!(
+        INDEX_ERROR_LOG,
+        "no-graph",
+        "rejecting graph request, no graph has been scraped yet"
+    );
+    GraphError::ServiceUnavailable(
+        "no graph has been scraped yet".to_string(),
+        Some(FIRST_SCRAPE_RETRY_AFTER_SECS),
+    )
+}
+
+/// Build the response to a conditional request matching the given entity-tag, if any.
+fn not_modified(req: &HttpRequest, etag: &str) -> Option<HttpResponse> {
+    if !etag.is_empty()
+        && req
+            .headers()
+            .get_all(header::IF_NONE_MATCH)
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| if_none_match(value, etag))
+    {
+        return Some(
+            HttpResponse::NotModified()
+                .header(header::ETAG, etag)
+                .finish(),
+        );
+    }
+    None
+}
+
+/// Build the response serving a JSON graph with the given entity-tag.
+///
+/// Conditional requests are answered without copying the JSON graph.
+fn graph_response(req: &HttpRequest, json: &str, etag: &str) -> HttpResponse {
+    if let Some(resp) = not_modified(req, etag) {
+        return resp;
+    }
+
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(CONTENT_TYPE);
+    if !etag.is_empty() {
+        resp.header(header::ETAG, etag);
+    }
+    resp.body(json.to_string())
+}
+
+/// Compute a weak entity-tag for the given JSON graph.
+///
+/// The tag only needs to be stable for identical content; a weak tag is used
+/// because the response may be served with different content-encodings.
+fn compute_etag(json: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(json.as_bytes());
+    format!("W/\"{:x}-{:016x}\"", json.len(), hasher.finish())
+}
+
+/// Strip the weakness indicator from an entity-tag, returning its opaque part.
+fn opaque_tag(etag: &str) -> &str {
+    etag.trim().trim_start_matches("W/")
+}
+
+/// Evaluate an `If-None-Match` header value against the current entity-tag.
+///
+/// As mandated by RFC 7232 section 3.2, this uses the weak comparison
+/// function: two entity-tags match if their opaque tags match character by
+/// character, regardless of either or both being tagged as weak.
+fn if_none_match(header_value: &str, etag: &str) -> bool {
+    if header_value.trim() == "*" {
+        return true;
+    }
+
+    let current = opaque_tag(etag);
+    let mut rest = header_value;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            return false;
+        }
+
+        // An entity-tag is an optional weakness indicator followed by a
+        // double-quoted opaque tag, which may itself contain commas.
+        let start = if rest.starts_with("W/\"") {
+            2
+        } else if rest.starts_with('"') {
+            0
+        } else {
+            // Malformed entry, skip to the next list element.
+            match rest.find(',') {
+                Some(idx) => {
+                    rest = &rest[idx..];
+                    continue;
+                }
+                None => return false,
+            }
+        };
+
+        let end = match rest[start + 1..].find('"') {
+            Some(idx) => start + 1 + idx + 1,
+            None => return false,
+        };
+
+        if &rest[start..end] == current {
+            return true;
+        }
+        rest = &rest[end..];
+    }
+}
+
+/// Handling of requests for channels without any release.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownChannel {
+    /// Serve an empty graph.
+    #[default]
+    Empty,
+    /// Reject the request as invalid.
+    Reject,
+}
+
+impl FromStr for UnknownChannel {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Fallible<Self> {
+        match input {
+            "empty" => Ok(UnknownChannel::Empty),
+            "reject" => Ok(UnknownChannel::Reject),
+            _ => bail!(
+                "unknown channel handling '{}', expected 'empty' or 'reject'",
+                input
+            ),
+        }
+    }
+}
+
+/// JSON graph along with its entity-tag.
+#[derive(Debug)]
+struct SerializedGraph {
+    json: String,
+    etag: String,
+}
+
+impl SerializedGraph {
+    fn new(json: String) -> Self {
+        let etag = compute_etag(&json);
+        SerializedGraph { json, etag }
+    }
+}
+
+/// Graph published by a scrape, both parsed and serialized.
+///
+/// The parsed graph is meant for in-process consumers, while the HTTP service
+/// only serves the pre-serialized graphs. Both are published together, so
+/// that they always come from the same scrape.
+#[derive(Debug)]
+pub struct GraphSnapshot {
+    graph: Arc<cincinnati::Graph>,
+    serialized: Arc<SerializedGraph>,
+    /// JSON subgraph of every channel of the graph, by channel name.
+    channels: HashMap<String, Arc<SerializedGraph>>,
+}
+
+impl GraphSnapshot {
+    /// Serializes the graph and the subgraph of each of its channels.
+    fn try_new(graph: cincinnati::Graph) -> Fallible<Self> {
+        let json = serde_json::to_string(&graph).context("Failed to serialize graph")?;
+        let channels = serialize_channels(&graph)?;
+        Ok(Self {
+            graph: Arc::new(graph),
+            serialized: Arc::new(SerializedGraph::new(json)),
+            channels,
+        })
+    }
+
+    /// Returns the parsed graph
+    pub fn graph(&self) -> &Arc<cincinnati::Graph> {
+        &self.graph
+    }
+
+    /// Returns the JSON graph
+    pub fn json(&self) -> &str {
+        &self.serialized.json
+    }
+
+    /// Returns the entity-tag of the JSON graph
+    pub fn etag(&self) -> &str {
+        &self.serialized.etag
+    }
+}
+
+/// Entity-tags of the JSON graphs of a snapshot.
+#[derive(Debug)]
+struct GraphEtags {
+    graph: String,
+    /// Entity-tag of the JSON subgraph of every channel, by channel name.
+    channels: HashMap<String, String>,
+}
+
+impl GraphEtags {
+    fn new(snapshot: &GraphSnapshot) -> Self {
+        Self {
+            graph: snapshot.serialized.etag.clone(),
+            channels: snapshot
+                .channels
+                .iter()
+                .map(|(channel, graph)| (channel.clone(), graph.etag.clone()))
+                .collect(),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct State {
-    json: Arc<RwLock<String>>,
+    /// Graph published by the last successful scrape, `None` until the first one.
+    snapshot: Arc<RwLock<Option<Arc<GraphSnapshot>>>>,
+    /// Entity-tags of the current snapshot, readable without its lock.
+    etags: Arc<RwLock<Option<Arc<GraphEtags>>>>,
+    /// Handling of requests for channels without any release.
+    unknown_channel: UnknownChannel,
+    /// Channel topology of the current graph, empty until the first scrape.
+    topology: Arc<RwLock<Topology>>,
+    /// First-seen timestamps of the release versions, updated on each scrape.
+    first_seen: Arc<RwLock<FirstSeen>>,
     /// Query parameters that must be present in all client requests.
     mandatory_params: HashSet<String>,
+    /// Whether the scrape loop has started, never reset.
+    started: Arc<RwLock<bool>>,
     live: Arc<RwLock<bool>>,
     ready: Arc<RwLock<bool>>,
     plugins: &'static [BoxedPlugin],
+    /// Whether to serve empty graphs from an empty plugin chain, instead of failing requests.
+    serve_empty_graph: bool,
     registry: &'static prometheus::Registry,
 }
 
 impl State {
     /// Creates a new State with the given arguments
     pub fn new(
-        json: Arc<RwLock<String>>,
         mandatory_params: HashSet<String>,
         live: Arc<RwLock<bool>>,
         ready: Arc<RwLock<bool>>,
         plugins: &'static [BoxedPlugin],
         registry: &'static prometheus::Registry,
+        unknown_channel: UnknownChannel,
     ) -> State {
         State {
-            json,
+            snapshot: Arc::new(RwLock::new(None)),
+            etags: Arc::new(RwLock::new(None)),
+            unknown_channel,
+            topology: Arc::new(RwLock::new(Topology::new())),
+            first_seen: Arc::new(RwLock::new(FirstSeen::default())),
             mandatory_params,
+            started: Arc::new(RwLock::new(false)),
             live,
             ready,
             plugins,
+            serve_empty_graph: false,
             registry,
         }
     }
 
+    /// Handles an empty plugin chain as configured
+    pub fn with_empty_chain(mut self, on_empty: EmptyChain) -> Self {
+        self.serve_empty_graph = on_empty.serves_empty_graph();
+        self
+    }
+
+    /// Tracks first-seen timestamps with the given tracker, instead of in memory only
+    pub fn with_first_seen(self, first_seen: FirstSeen) -> Self {
+        *self.first_seen.write() = first_seen;
+        self
+    }
+
+    /// Returns the boolean inside self.started
+    pub fn is_started(&self) -> bool {
+        *self.started.read()
+    }
+
     /// Returns the boolean inside self.live
     pub fn is_live(&self) -> bool {
         *self.live.read()
@@ -156,6 +461,118 @@ impl State {
     pub fn is_ready(&self) -> bool {
         *self.ready.read()
     }
+
+    /// Returns whether a graph has been scraped
+    pub fn has_graph(&self) -> bool {
+        self.snapshot.read().is_some()
+    }
+
+    /// Returns the plugins of the scrape loop
+    pub fn plugins(&self) -> &'static [BoxedPlugin] {
+        self.plugins
+    }
+
+    /// Returns a copy of the channel topology of the current graph
+    pub fn topology(&self) -> Topology {
+        self.topology.read().clone()
+    }
+
+    /// Returns the first-seen timestamp of every tracked release version
+    pub fn first_seen(&self) -> BTreeMap<String, String> {
+        self.first_seen.read().timestamps()
+    }
+
+    /// Returns the current graph snapshot, `None` until the first scrape
+    pub fn snapshot(&self) -> Option<Arc<GraphSnapshot>> {
+        self.snapshot.read().clone()
+    }
+
+    /// Returns the entity-tags of the current graph snapshot, `None` until the first scrape
+    fn etags(&self) -> Option<Arc<GraphEtags>> {
+        self.etags.read().clone()
+    }
+
+    /// Returns the current parsed graph, `None` until the first scrape
+    pub fn graph(&self) -> Option<Arc<cincinnati::Graph>> {
+        self.snapshot().map(|snapshot| snapshot.graph.clone())
+    }
+
+    /// Returns the JSON subgraph of the given channel in the given snapshot
+    fn channel_graph(
+        &self,
+        snapshot: &GraphSnapshot,
+        channel: &str,
+    ) -> Result<Arc<SerializedGraph>, GraphError> {
+        match snapshot.channels.get(channel) {
+            Some(graph) => Ok(graph.clone()),
+            None => self.serve_unknown_channel(channel, EMPTY_GRAPH.clone()),
+        }
+    }
+
+    /// Returns the entity-tag of the JSON subgraph of the given channel in the given entity-tags
+    fn channel_etag<'a>(
+        &self,
+        etags: &'a GraphEtags,
+        channel: &str,
+    ) -> Result<&'a str, GraphError> {
+        match etags.channels.get(channel) {
+            Some(etag) => Ok(etag.as_str()),
+            None => self.serve_unknown_channel(channel, EMPTY_GRAPH.etag.as_str()),
+        }
+    }
+
+    /// Handles a request for a channel without any release, served `empty` unless rejected
+    fn serve_unknown_channel<T>(&self, channel: &str, empty: T) -> Result<T, GraphError> {
+        if self.unknown_channel == UnknownChannel::Reject {
+            return Err(GraphError::InvalidParams(format!(
+                "unknown channel '{}'",
+                channel
+            )));
+        }
+        Ok(empty)
+    }
+
+    /// Serializes the graph and the subgraph of each of its channels, and makes them current
+    ///
+    /// The entity-tags are swapped while the snapshot is locked, so that
+    /// requests seeing the new entity-tags are served the new snapshot.
+    fn publish(&self, graph: cincinnati::Graph) -> Fallible<Arc<GraphSnapshot>> {
+        let snapshot = Arc::new(GraphSnapshot::try_new(graph)?);
+        let etags = Arc::new(GraphEtags::new(&snapshot));
+
+        let mut current = self.snapshot.write();
+        *self.etags.write() = Some(etags);
+        *current = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+}
+
+/// Serialize the subgraph of every channel of the graph.
+///
+/// This is done once per scrape, to keep serving channel requests cheap.
+fn serialize_channels(
+    graph: &cincinnati::Graph,
+) -> Fallible<HashMap<String, Arc<SerializedGraph>>> {
+    let channels: BTreeSet<String> = graph
+        .find_by_metadata_key(cincinnati::CHANNELS_KEY)
+        .iter()
+        .flat_map(|(_, _, channels)| cincinnati::parse_channels(channels))
+        .map(str::to_string)
+        .collect();
+
+    channels
+        .into_iter()
+        .map(|channel| {
+            let subset = graph.subset_by_metadata(cincinnati::CHANNELS_KEY, |channels| {
+                cincinnati::parse_channels(channels).any(|c| c == channel)
+            });
+            let json = serde_json::to_string(&subset).context(format!(
+                "Failed to serialize graph of channel '{}'",
+                channel
+            ))?;
+            Ok((channel, Arc::new(SerializedGraph::new(json))))
+        })
+        .collect()
 }
 
 impl HasRegistry for State {
@@ -164,83 +581,828 @@ impl HasRegistry for State {
     }
 }
 
-#[allow(clippy::useless_let_if_seq)]
-pub fn run(settings: &config::AppSettings, state: &State) -> ! {
-    // Indicate if a panic happens
-    let previous_hook = std::panic::take_hook();
-    let panic_live = state.live.clone();
-    std::panic::set_hook(Box::new(move |panic_info| {
-        *panic_live.write() = false;
-        previous_hook(panic_info)
-    }));
+/// Token to stop the scrape loop, shared between threads.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancellationToken {
+    /// Creates a token which is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    // Don't wait on the first iteration
-    let mut first_iteration = true;
-    let mut first_success = true;
+    /// Cancels the token, waking up all waiters
+    pub fn cancel(&self) {
+        let (cancelled, condvar) = &*self.cancelled;
+        *cancelled.lock() = true;
+        condvar.notify_all();
+    }
 
-    BUILD_INFO.inc();
+    /// Returns whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.0.lock()
+    }
 
-    loop {
-        // Store scrape duration value. It would be used for initial scrape gauge or scrape histogram
-        let scrape_value: f64;
+    /// Waits for the token to be cancelled, for at most `timeout`.
+    ///
+    /// Returns whether the token has been cancelled.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (cancelled, condvar) = &*self.cancelled;
+        let deadline = Instant::now() + timeout;
 
-        if first_iteration {
-            *state.live.write() = true;
-            first_iteration = false;
-        } else {
-            thread::sleep(settings.pause_secs);
+        let mut cancelled = cancelled.lock();
+        while !*cancelled {
+            if condvar.wait_until(&mut cancelled, deadline).timed_out() {
+                break;
+            }
         }
+        *cancelled
+    }
+}
 
+/// Outcome of a successful scrape iteration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IterationReport {
+    /// Number of releases in the published graph.
+    pub releases: usize,
+    /// Duration of the scrape in seconds.
+    pub duration_secs: f64,
+    /// Whether this is the first successful scrape.
+    pub first_success: bool,
+}
+
+/// Periodic scraper of the upstream graph, publishing it to the shared state.
+pub struct Scraper {
+    state: State,
+    pause: Duration,
+    scrape_timeout: Option<Duration>,
+    first_success: bool,
+    /// Throttles error logs of failed scrapes, by error kind.
+    error_log: ThrottledLogger,
+}
+
+impl Scraper {
+    /// Creates a scraper running the plugins of `state`
+    pub fn new(state: State, pause: Duration, scrape_timeout: Option<Duration>) -> Self {
+        // The build info is counted once per process, however many scrapers run.
+        static BUILD_INFO_ONCE: Once = Once::new();
+        BUILD_INFO_ONCE.call_once(|| BUILD_INFO.inc());
+
+        Self {
+            state,
+            pause,
+            scrape_timeout,
+            first_success: true,
+            error_log: ThrottledLogger::new(
+                SCRAPE_ERRORS_PER_WINDOW,
+                pause * SCRAPE_ERROR_WINDOW_PAUSES,
+            ),
+        }
+    }
+
+    /// Scrapes the graph once, and publishes it.
+    ///
+    /// All metrics related to scraping are updated here.
+    pub fn run_iteration(&mut self) -> Fallible<IterationReport> {
         debug!("graph update triggered");
         let scrape_timer = UPSTREAM_SCRAPES_DURATION.start_timer();
 
         let scrape = cincinnati::plugins::process_blocking(
-            state.plugins.iter(),
+            self.state.plugins.iter(),
             cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
                 // the first plugin will produce the initial graph
                 graph: Default::default(),
                 // the plugins used in the graph-builder don't expect any parameters yet
                 parameters: Default::default(),
+                warnings: Default::default(),
             }),
-            settings.scrape_timeout_secs,
+            self.scrape_timeout,
         );
         UPSTREAM_SCRAPES.inc();
 
-        let internal_io = match scrape {
-            Ok(internal_io) => internal_io,
-            Err(err) => {
+        let snapshot = scrape
+            .and_then(|mut internal_io| {
+                self.record_first_seen(&mut internal_io.graph);
+                self.state.publish(internal_io.graph)
+            })
+            .map_err(|err| {
+                UPSTREAM_ERRORS.inc();
+                err
+            })?;
+
+        // Record scrape duration
+        let duration_secs = scrape_timer.stop_and_discard();
+
+        let first_success = self.first_success;
+        if first_success {
+            *self.state.ready.write() = true;
+            self.first_success = false;
+            GRAPH_UPSTREAM_INITIAL_SCRAPE.set(duration_secs);
+        } else {
+            UPSTREAM_SCRAPES_DURATION.observe(duration_secs);
+        }
+
+        GRAPH_LAST_SUCCESSFUL_REFRESH.set(chrono::Utc::now().timestamp() as i64);
+
+        let graph = snapshot.graph();
+        let releases = graph.releases_count();
+        GRAPH_FINAL_RELEASES.set(releases as i64);
+
+        let graph_topology = topology::compute(graph);
+        topology::update_metrics(&graph_topology);
+        *self.state.topology.write() = graph_topology;
+        debug!("graph update completed, {} valid releases", releases);
+
+        Ok(IterationReport {
+            releases,
+            duration_secs,
+            first_success,
+        })
+    }
+
+    /// Scrapes the graph once, failing the iteration if it panics.
+    ///
+    /// A panic, e.g. in a plugin, is counted as an upstream error. The panic
+    /// hook installed by `run_loop` still flips the liveness flag.
+    fn run_iteration_unwind_safe(&mut self) -> Fallible<IterationReport> {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run_iteration()))
+            .unwrap_or_else(|payload| {
                 UPSTREAM_ERRORS.inc();
-                err.chain().for_each(|cause| error!("{}", cause));
-                continue;
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                bail!("scrape iteration panicked: {}", message)
+            })
+    }
+
+    /// Updates the first-seen timestamps with the releases of a scraped graph.
+    ///
+    /// The timestamps are recorded as release metadata, if enabled. Failing to
+    /// persist them is logged, but doesn't fail the scrape.
+    fn record_first_seen(&self, graph: &mut cincinnati::Graph) {
+        let mut first_seen = self.state.first_seen.write();
+
+        let versions = graph.find_by_fn_mut(|_| true);
+        if let Err(e) = first_seen.update(
+            versions.iter().map(|(_, version)| version.as_str()),
+            chrono::Utc::now(),
+        ) {
+            error!("{:#}", e);
+        }
+
+        if first_seen.inject_metadata() {
+            graph.find_by_fn_mut(|release| {
+                if let Some(timestamp) = first_seen.get(release.version()) {
+                    let timestamp = timestamp.to_string();
+                    if let Some(metadata) = release.get_metadata_mut() {
+                        metadata.insert(FIRST_SEEN_KEY.to_string(), timestamp);
+                    }
+                }
+                false
+            });
+        }
+    }
+
+    /// Scrapes the graph periodically, until `shutdown` is cancelled.
+    ///
+    /// Cancellation is checked between iterations, so an iteration in
+    /// progress always completes.
+    pub fn run_loop(&mut self, shutdown: CancellationToken) {
+        *self.state.started.write() = true;
+
+        // Indicate if a panic happens
+        let previous_hook = std::panic::take_hook();
+        let panic_live = self.state.live.clone();
+        std::panic::set_hook(Box::new(move |panic_info| {
+            *panic_live.write() = false;
+            previous_hook(panic_info)
+        }));
+
+        *self.state.live.write() = true;
+
+        // Don't wait on the first iteration
+        while !shutdown.is_cancelled() {
+            if let Err(err) = self.run_iteration_unwind_safe() {
+                let kind = scrape_error_kind(&err);
+                commons::error_throttled!(self.error_log, &kind, "{:#}", err);
             }
+            for (kind, suppressed) in self.error_log.take_expired() {
+                error!(
+                    "suppressed {} scrape errors in the last {:?}, caused by: {}",
+                    suppressed,
+                    self.pause * SCRAPE_ERROR_WINDOW_PAUSES,
+                    kind
+                );
+            }
+
+            if shutdown.wait_timeout(self.pause) {
+                break;
+            }
+        }
+
+        info!("graph scraper stopped");
+    }
+}
+
+/// Returns the kind of a scrape error, under which its logs are throttled.
+///
+/// Errors are told apart by their root cause, e.g. the failure of a plugin,
+/// so that a recurring failure doesn't hide others.
+fn scrape_error_kind(err: &Error) -> String {
+    err.root_cause().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use cincinnati::plugins::prelude_plugin_impl::*;
+    use commons::testing;
+    use prometheus::Registry;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// State before the first scrape.
+    fn empty_state() -> State {
+        let plugins = Box::leak(Box::new([]));
+        let registry: &'static Registry = Box::leak(Box::new(
+            commons::metrics::new_registry(Some(config::METRICS_PREFIX.to_string())).unwrap(),
+        ));
+
+        State::new(
+            HashSet::new(),
+            Arc::new(RwLock::new(true)),
+            Arc::new(RwLock::new(false)),
+            plugins,
+            registry,
+            UnknownChannel::default(),
+        )
+        .with_empty_chain(EmptyChain::Serve)
+    }
+
+    fn mock_state(json: &str) -> State {
+        let state = empty_state();
+        state.publish(serde_json::from_str(json).unwrap()).unwrap();
+        state
+    }
+
+    fn current_etag(state: &State) -> String {
+        state.snapshot().unwrap().etag().to_string()
+    }
+
+    fn get_graph(state: &State, if_none_match: Option<&str>) -> Fallible<HttpResponse> {
+        let mut rt = testing::init_runtime()?;
+
+        let mut req = actix_web::test::TestRequest::get().header(header::ACCEPT, CONTENT_TYPE);
+        if let Some(value) = if_none_match {
+            req = req.header(header::IF_NONE_MATCH, value);
+        }
+
+        let resp = rt.block_on(index(
+            req.to_http_request(),
+            actix_web::web::Data::new(state.clone()),
+        ))?;
+        Ok(resp)
+    }
+
+    fn etag_header(resp: &HttpResponse) -> Option<String> {
+        resp.headers()
+            .get(header::ETAG)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn etag_is_emitted() -> Fallible<()> {
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#);
+
+        let resp = get_graph(&state, None)?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(etag_header(&resp), Some(current_etag(&state)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn etag_changes_with_content() {
+        let first = compute_etag(r#"{"nodes":[],"edges":[]}"#);
+        let second = compute_etag(r#"{"nodes":[{}],"edges":[]}"#);
+        assert_ne!(first, second);
+        assert_eq!(first, compute_etag(r#"{"nodes":[],"edges":[]}"#));
+    }
+
+    #[test]
+    fn if_none_match_matching() -> Fallible<()> {
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#);
+        let etag = current_etag(&state);
+
+        let resp = get_graph(&state, Some(&etag))?;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(etag_header(&resp), Some(etag.clone()));
+
+        // Weak comparison ignores the weakness indicator.
+        let strong = etag.trim_start_matches("W/");
+        let resp = get_graph(&state, Some(strong))?;
+        assert_eq!(resp.status(), 304);
+
+        Ok(())
+    }
+
+    #[test]
+    fn if_none_match_mismatching() -> Fallible<()> {
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#);
+
+        let resp = get_graph(&state, Some(r#"W/"0-0000000000000000""#))?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(etag_header(&resp), Some(current_etag(&state)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn if_none_match_multiple() -> Fallible<()> {
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#);
+        let etag = current_etag(&state);
+
+        let header = format!(r#""foo", W/"bar,baz" ,{}"#, etag);
+        let resp = get_graph(&state, Some(&header))?;
+        assert_eq!(resp.status(), 304);
+
+        let resp = get_graph(&state, Some(r#""foo", W/"bar,baz""#))?;
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn if_none_match_any() -> Fallible<()> {
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#);
+
+        let resp = get_graph(&state, Some("*"))?;
+        assert_eq!(resp.status(), 304);
+
+        Ok(())
+    }
+
+    #[test]
+    fn if_none_match_during_refresh() -> Fallible<()> {
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#);
+        let etag = current_etag(&state);
+
+        // A refresh publishing its graph holds the snapshot lock.
+        let publishing = state.snapshot.write();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                let _ = tx.send(get_graph(&state, Some(&etag)).map(|resp| resp.status()));
+            });
+        }
+        let status = rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("conditional request blocked on the snapshot lock")?;
+        assert_eq!(status, 304);
+
+        drop(publishing);
+        Ok(())
+    }
+
+    #[test]
+    fn if_none_match_non_utf8() -> Fallible<()> {
+        let mut rt = testing::init_runtime()?;
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#);
+
+        let req = actix_web::test::TestRequest::get()
+            .header(header::ACCEPT, CONTENT_TYPE)
+            .header(
+                header::IF_NONE_MATCH,
+                header::HeaderValue::from_bytes(b"\"\xfe\xff\"")?,
+            )
+            .to_http_request();
+
+        let resp = rt.block_on(index(req, actix_web::web::Data::new(state)))?;
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    /// Graph with releases in the "stable" and "fast" channels, and one without channel.
+    static MULTI_CHANNEL_GRAPH: &str = r#"{
+        "nodes": [
+            {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable,fast"}},
+            {"version": "1.1.0", "payload": "image/1.1.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "fast"}},
+            {"version": "1.2.0", "payload": "image/1.2.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable, fast"}},
+            {"version": "1.3.0", "payload": "image/1.3.0", "metadata": {}}
+        ],
+        "edges": [[0, 1], [1, 2], [0, 2], [2, 3]]
+    }"#;
+
+    fn get_channel(state: &State, channel: &str) -> Result<HttpResponse, GraphError> {
+        let mut rt = testing::init_runtime().unwrap();
+
+        let req = actix_web::test::TestRequest::get()
+            .uri(&format!("/v1/graph?{}={}", CHANNEL_PARAM, channel))
+            .header(header::ACCEPT, CONTENT_TYPE)
+            .to_http_request();
+
+        rt.block_on(index(req, actix_web::web::Data::new(state.clone())))
+    }
+
+    fn response_graph(resp: &HttpResponse) -> Fallible<cincinnati::Graph> {
+        match resp.body() {
+            actix_web::dev::ResponseBody::Body(actix_web::dev::Body::Bytes(bytes)) => {
+                Ok(serde_json::from_slice(&bytes)?)
+            }
+            _ => bail!("expected byte body"),
+        }
+    }
+
+    #[test]
+    fn channel_subsets_are_precomputed() -> Fallible<()> {
+        let state = empty_state();
+        let graph: cincinnati::Graph = serde_json::from_str(MULTI_CHANNEL_GRAPH)?;
+        let snapshot = state.publish(graph.clone())?;
+
+        let mut channels: Vec<String> = snapshot.channels.keys().cloned().collect();
+        channels.sort();
+        assert_eq!(channels, vec!["fast", "stable"]);
+
+        let resp = get_channel(&state, "stable")?;
+        assert_eq!(resp.status(), 200);
+        let stable = response_graph(&resp)?;
+        assert_eq!(
+            stable,
+            graph.subset_by_metadata(cincinnati::CHANNELS_KEY, |channels| channels
+                .contains("stable"))
+        );
+        assert_eq!(stable.releases_count(), 2);
+
+        // Each channel has its own entity-tag.
+        let stable_etag = snapshot.channels["stable"].etag.clone();
+        assert_eq!(etag_header(&resp), Some(stable_etag.clone()));
+        assert_ne!(stable_etag, snapshot.channels["fast"].etag);
+        assert_ne!(stable_etag, snapshot.etag());
+
+        // The full graph is still served without channel.
+        let resp = get_graph(&state, None)?;
+        assert_eq!(response_graph(&resp)?, graph);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_channel_handling() -> Fallible<()> {
+        let graph: cincinnati::Graph = serde_json::from_str(MULTI_CHANNEL_GRAPH)?;
+
+        let state = empty_state();
+        state.publish(graph.clone())?;
+        let resp = get_channel(&state, "candidate")?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(response_graph(&resp)?, cincinnati::Graph::default());
+
+        let state = State {
+            unknown_channel: UnknownChannel::Reject,
+            ..empty_state()
         };
+        state.publish(graph.clone())?;
+        assert_eq!(
+            get_channel(&state, "candidate").unwrap_err(),
+            GraphError::InvalidParams("unknown channel 'candidate'".to_string())
+        );
+        assert_eq!(get_channel(&state, "fast")?.status(), 200);
 
-        let json_graph = match serde_json::to_string(&internal_io.graph) {
-            Ok(json) => json,
-            Err(err) => {
-                UPSTREAM_ERRORS.inc();
-                error!("Failed to serialize graph: {}", err);
-                continue;
+        Ok(())
+    }
+
+    #[test]
+    fn unavailable_on_empty_chain() -> Fallible<()> {
+        let state = mock_state(r#"{"nodes":[],"edges":[]}"#).with_empty_chain(EmptyChain::Warn);
+        assert_eq!(
+            get_graph(&state, None)
+                .unwrap_err()
+                .downcast::<GraphError>()?,
+            GraphError::ServiceUnavailable("no plugins configured".to_string(), None)
+        );
+
+        let state = state.with_empty_chain(EmptyChain::Serve);
+        assert_eq!(get_graph(&state, None)?.status(), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unavailable_before_first_scrape() -> Fallible<()> {
+        let state = empty_state();
+        assert!(state.snapshot().is_none());
+        assert!(state.graph().is_none());
+
+        let unavailable = GraphError::ServiceUnavailable(
+            "no graph has been scraped yet".to_string(),
+            Some(FIRST_SCRAPE_RETRY_AFTER_SECS),
+        );
+        assert_eq!(
+            get_graph(&state, Some("*"))
+                .unwrap_err()
+                .downcast::<GraphError>()?,
+            unavailable
+        );
+        assert_eq!(get_channel(&state, "stable").unwrap_err(), unavailable);
+
+        let resp = unavailable.as_json_error();
+        assert_eq!(resp.status(), 503);
+        assert_eq!(
+            resp.headers().get(header::RETRY_AFTER).unwrap(),
+            &FIRST_SCRAPE_RETRY_AFTER_SECS.to_string()
+        );
+
+        let graph: cincinnati::Graph = serde_json::from_str(MULTI_CHANNEL_GRAPH)?;
+        state.publish(graph.clone())?;
+        assert!(state.has_graph());
+
+        let resp = get_graph(&state, None)?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(response_graph(&resp)?, graph);
+        assert_eq!(get_channel(&state, "stable")?.status(), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_consistent_across_refresh() -> Fallible<()> {
+        let state = empty_state();
+        let first: cincinnati::Graph = serde_json::from_str(MULTI_CHANNEL_GRAPH)?;
+        let second = first.subset_by_metadata(cincinnati::CHANNELS_KEY, |channels| {
+            channels.contains("fast")
+        });
+        assert_ne!(first, second);
+
+        let assert_consistent = |snapshot: &GraphSnapshot, graph: &cincinnati::Graph| {
+            assert_eq!(**snapshot.graph(), *graph);
+            let parsed: cincinnati::Graph = serde_json::from_str(snapshot.json()).unwrap();
+            assert_eq!(parsed, *graph);
+            assert_eq!(snapshot.etag(), compute_etag(snapshot.json()));
+        };
+
+        state.publish(first.clone())?;
+        let held = state.snapshot().unwrap();
+        assert_consistent(&held, &first);
+
+        // Refreshing leaves previously taken snapshots untouched.
+        state.publish(second.clone())?;
+        assert_consistent(&held, &first);
+        let current = state.snapshot().unwrap();
+        assert_consistent(&current, &second);
+
+        // All consumers observe the same snapshot.
+        assert!(Arc::ptr_eq(current.graph(), &state.graph().unwrap()));
+        let resp = get_graph(&state, None)?;
+        assert_eq!(etag_header(&resp), Some(current.etag().to_string()));
+        assert_eq!(response_graph(&resp)?, second);
+
+        Ok(())
+    }
+
+    /// Plugin producing the given JSON graph, or failing without one.
+    #[derive(Debug)]
+    struct StubPlugin(Option<&'static str>);
+
+    #[async_trait]
+    impl InternalPlugin for StubPlugin {
+        const PLUGIN_NAME: &'static str = "stub";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            match self.0 {
+                Some(json) => Ok(InternalIO {
+                    graph: serde_json::from_str(json)?,
+                    parameters: io.parameters,
+                    warnings: io.warnings,
+                }),
+                None => bail!("stub failure"),
             }
+        }
+    }
+
+    fn stub_scraper(json: Option<&'static str>) -> Scraper {
+        let state = State {
+            plugins: Box::leak(
+                vec![new_plugin!(InternalPluginWrapper(StubPlugin(json)))].into_boxed_slice(),
+            ),
+            ..empty_state()
         };
+        Scraper::new(state, Duration::from_secs(3600), None)
+    }
 
-        *state.json.write() = json_graph;
+    #[test]
+    fn run_iteration_publishes_graph() -> Fallible<()> {
+        let mut scraper = stub_scraper(Some(MULTI_CHANNEL_GRAPH));
+        assert!(!scraper.state.is_ready());
 
-        // Record scrape duration
-        scrape_value = scrape_timer.stop_and_discard();
+        let report = scraper.run_iteration()?;
+        assert_eq!(report.releases, 4);
+        assert!(report.first_success);
+        assert!(scraper.state.is_ready());
 
-        if first_success {
-            *state.ready.write() = true;
-            first_success = false;
-            GRAPH_UPSTREAM_INITIAL_SCRAPE.set(scrape_value);
-        } else {
-            UPSTREAM_SCRAPES_DURATION.observe(scrape_value);
+        let graph: cincinnati::Graph = serde_json::from_str(MULTI_CHANNEL_GRAPH)?;
+        assert_eq!(scraper.state.graph().as_deref(), Some(&graph));
+        assert_eq!(scraper.state.topology().len(), 2);
+
+        let report = scraper.run_iteration()?;
+        assert!(!report.first_success);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_iteration_records_first_seen() -> Fallible<()> {
+        let mut scraper = stub_scraper(Some(MULTI_CHANNEL_GRAPH));
+        scraper.state = scraper.state.clone().with_first_seen(FirstSeen::load(
+            None,
+            Duration::from_secs(3600),
+            true,
+        )?);
+
+        scraper.run_iteration()?;
+        let first_seen = scraper.state.first_seen();
+        assert_eq!(
+            first_seen.keys().collect::<Vec<_>>(),
+            vec!["1.0.0", "1.1.0", "1.2.0", "1.3.0"]
+        );
+
+        let graph = scraper.state.graph().unwrap();
+        let injected = graph.find_by_metadata_key(FIRST_SEEN_KEY);
+        assert_eq!(injected.len(), 4);
+        for (_, version, timestamp) in injected {
+            assert_eq!(Some(&timestamp), first_seen.get(&version));
         }
 
-        GRAPH_LAST_SUCCESSFUL_REFRESH.set(chrono::Utc::now().timestamp() as i64);
+        // Timestamps are kept by later scrapes.
+        scraper.run_iteration()?;
+        assert_eq!(scraper.state.first_seen(), first_seen);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_iteration_failure() {
+        let mut scraper = stub_scraper(None);
+        let errors = UPSTREAM_ERRORS.get();
+
+        let err = scraper.run_iteration().unwrap_err();
+        assert!(err.chain().any(|cause| cause.to_string() == "stub failure"));
+        assert!(UPSTREAM_ERRORS.get() > errors);
+        assert!(!scraper.state.is_ready());
+        assert!(!scraper.state.has_graph());
+    }
+
+    /// Plugin producing the given JSON graph once its gate opens.
+    #[derive(Debug)]
+    struct GatedPlugin(Arc<AtomicBool>, &'static str);
+
+    #[async_trait]
+    impl InternalPlugin for GatedPlugin {
+        const PLUGIN_NAME: &'static str = "gated";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            while !self.0.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Ok(InternalIO {
+                graph: serde_json::from_str(self.1)?,
+                parameters: io.parameters,
+                warnings: io.warnings,
+            })
+        }
+    }
+
+    /// Serializes the tests checking liveness, which the process-wide panic
+    /// hook installed by `run_loop` flips on any panic.
+    static LIVENESS_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+    #[test]
+    fn run_loop_probe_flags() {
+        let _guard = LIVENESS_LOCK.lock();
+        let gate = Arc::new(AtomicBool::new(false));
+        let state = State {
+            live: Arc::new(RwLock::new(false)),
+            plugins: Box::leak(
+                vec![new_plugin!(InternalPluginWrapper(GatedPlugin(
+                    gate.clone(),
+                    MULTI_CHANNEL_GRAPH
+                )))]
+                .into_boxed_slice(),
+            ),
+            ..empty_state()
+        };
+        assert!(!state.is_started());
+        assert!(!state.is_live());
+        assert!(!state.is_ready());
+
+        let mut scraper = Scraper::new(state.clone(), Duration::from_secs(3600), None);
+        let shutdown = CancellationToken::new();
+        let handle = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || scraper.run_loop(shutdown))
+        };
+
+        // Started and live during the first, slow, iteration.
+        while !state.is_live() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(state.is_started());
+        assert!(!state.is_ready());
+
+        // Ready once the first iteration succeeds.
+        gate.store(true, Ordering::SeqCst);
+        while !state.is_ready() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(state.is_started());
+        assert!(state.is_live());
+
+        shutdown.cancel();
+        handle.join().unwrap();
+    }
+
+    /// Plugin panicking on its first run, then producing the given JSON graph.
+    #[derive(Debug)]
+    struct PanickingPlugin(AtomicBool, &'static str);
+
+    #[async_trait]
+    impl InternalPlugin for PanickingPlugin {
+        const PLUGIN_NAME: &'static str = "panicking";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            if !self.0.swap(true, Ordering::SeqCst) {
+                panic!("plugin panic");
+            }
+            Ok(InternalIO {
+                graph: serde_json::from_str(self.1)?,
+                parameters: io.parameters,
+                warnings: io.warnings,
+            })
+        }
+    }
+
+    #[test]
+    fn run_loop_survives_panics() {
+        let _guard = LIVENESS_LOCK.lock();
+        let state = State {
+            live: Arc::new(RwLock::new(false)),
+            plugins: Box::leak(
+                vec![new_plugin!(InternalPluginWrapper(PanickingPlugin(
+                    AtomicBool::new(false),
+                    MULTI_CHANNEL_GRAPH
+                )))]
+                .into_boxed_slice(),
+            ),
+            ..empty_state()
+        };
+        let errors = UPSTREAM_ERRORS.get();
+
+        let mut scraper = Scraper::new(state.clone(), Duration::from_millis(10), None);
+        let shutdown = CancellationToken::new();
+        let handle = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || scraper.run_loop(shutdown))
+        };
+
+        // The iteration after the panic succeeds.
+        while !state.is_ready() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(state.has_graph());
+        assert!(UPSTREAM_ERRORS.get() > errors);
+        assert!(!state.is_live());
+
+        shutdown.cancel();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn run_loop_stops_on_cancellation() {
+        let mut scraper = stub_scraper(Some(MULTI_CHANNEL_GRAPH));
+        let state = scraper.state.clone();
+        let shutdown = CancellationToken::new();
+
+        let handle = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || scraper.run_loop(shutdown))
+        };
+
+        // The first iteration runs without waiting, the loop then pauses for an hour.
+        while !state.is_ready() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        shutdown.cancel();
+        handle.join().unwrap();
 
-        let nodes_count = internal_io.graph.releases_count();
-        GRAPH_FINAL_RELEASES.set(nodes_count as i64);
-        debug!("graph update completed, {} valid releases", nodes_count);
+        assert!(shutdown.is_cancelled());
+        assert!(shutdown.wait_timeout(Duration::from_secs(3600)));
     }
 }
@@ -14,8 +14,11 @@ extern crate structopt;
 extern crate cincinnati;
 
 pub mod config;
+pub mod embedded;
+pub mod first_seen;
 pub mod graph;
 pub mod status;
+pub mod topology;
 
 #[allow(dead_code)]
 /// Build info
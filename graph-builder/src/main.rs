@@ -13,26 +13,35 @@
 // limitations under the License.
 
 use actix_service::Service;
+use actix_web::web::Data;
 use actix_web::{middleware, App, HttpServer};
+use commons::access_log::{AccessLog, ACCESS_LOG_TARGET};
+use commons::build_info;
 use commons::metrics::{self, HasRegistry};
 use commons::prelude_errors::*;
-use commons::tracing::{get_context, get_tracer, init_tracer, set_span_tags};
+use commons::tracing::{create_span_from_headers, get_context, init_tracer, DebugSampling};
+use futures::future;
 use graph_builder::{self, config, graph, status};
-use log::debug;
-use opentelemetry::api::{trace::futures::Instrument, Tracer};
+use log::{debug, error, info};
+use opentelemetry::api::trace::futures::Instrument;
 use parking_lot::RwLock;
 use std::collections::HashSet;
 use std::sync::Arc;
 use std::thread;
+use tokio::signal::unix::{signal, SignalKind};
 
 fn main() -> Result<(), Error> {
     let sys = actix::System::new("graph-builder");
 
     let settings = config::AppSettings::assemble().context("could not assemble AppSettings")?;
-    env_logger::Builder::from_default_env()
+    let mut logger = env_logger::Builder::from_default_env();
+    logger
         .filter(Some(module_path!()), settings.verbosity)
-        .filter(Some("cincinnati"), settings.verbosity)
-        .init();
+        .filter(Some("cincinnati"), settings.verbosity);
+    if settings.access_log {
+        logger.filter(Some(ACCESS_LOG_TARGET), log::LevelFilter::Info);
+    }
+    logger.init();
     debug!("application settings:\n{:#?}", settings);
 
     let registry: prometheus::Registry =
@@ -52,49 +61,74 @@ fn main() -> Result<(), Error> {
     let service_addr = (settings.address, settings.port);
     let status_addr = (settings.status_address, settings.status_port);
     let app_prefix = settings.path_prefix.clone();
+    let max_connections = settings.max_connections;
+    let debug_sampling = settings.tracing_debug_sampling.map(|max_per_minute| {
+        Arc::new(DebugSampling::new(
+            settings.tracing_debug_header.clone(),
+            max_per_minute,
+        ))
+    });
+    let tracing_tags = Arc::new(settings.tracing_tags.clone());
+    let access_log_enabled = settings.access_log;
+    let access_log = AccessLog::new(settings.access_log_redacted_params.clone())
+        .with_trusted_proxies(settings.trusted_proxies.clone());
 
     // Shared state.
     let state = {
-        let json_graph = Arc::new(RwLock::new(String::new()));
         let live = Arc::new(RwLock::new(false));
         let ready = Arc::new(RwLock::new(false));
 
         graph::State::new(
-            json_graph,
             settings.mandatory_client_parameters.clone(),
             live,
             ready,
             Box::leak(Box::new(plugins)),
             Box::leak(Box::new(registry)),
+            settings.unknown_channel,
         )
+        .with_first_seen(settings.load_first_seen()?)
+        .with_empty_chain(settings.on_empty_plugin_chain)
     };
 
     // Graph scraper
-    {
-        let graph_state = state.clone();
-        thread::spawn(move || {
-            graph::run(&settings, &graph_state);
-        });
-    }
+    let shutdown = graph::CancellationToken::new();
+    let scraper = {
+        let mut scraper = graph::Scraper::new(
+            state.clone(),
+            settings.pause_secs,
+            settings.scrape_timeout_secs,
+        );
+        let shutdown = shutdown.clone();
+        thread::spawn(move || scraper.run_loop(shutdown))
+    };
 
     // Status service.
+    //
+    // actix calls the app factories once per worker, so all the shared state
+    // is created here, once, and only cloned in the factories.
     graph::register_metrics(state.registry())?;
 
+    let status_build_info = Data::new(status::build_info(&settings));
+    let status_plugins = Data::new(state.plugins());
+    let state = Data::new(state);
     let status_state = state.clone();
     HttpServer::new(move || {
         App::new()
-            .app_data(actix_web::web::Data::new(status_state.clone()))
+            .app_data(status_state.clone())
+            .app_data(status_build_info.clone())
+            .app_data(status_plugins.clone())
+            .configure(status::configure)
             .service(
-                actix_web::web::resource("/liveness")
-                    .route(actix_web::web::get().to(status::serve_liveness)),
+                actix_web::web::resource("/healthz")
+                    .route(actix_web::web::get().to(cincinnati::plugins::health::serve)),
             )
             .service(
                 actix_web::web::resource("/metrics")
                     .route(actix_web::web::get().to(metrics::serve::<graph::State>)),
             )
             .service(
-                actix_web::web::resource("/readiness")
-                    .route(actix_web::web::get().to(status::serve_readiness)),
+                actix_web::web::resource("/status/build")
+                    .route(actix_web::web::get().to(build_info::serve)),
             )
     })
     .bind(status_addr)?
@@ -102,30 +136,72 @@ fn main() -> Result<(), Error> {
 
     // Main service.
     let main_state = state;
-    HttpServer::new(move || {
+    let main_server = HttpServer::new(move || {
+        let debug_sampling = debug_sampling.clone();
+        let tracing_tags = tracing_tags.clone();
         App::new()
             .wrap(middleware::Compress::default())
-            .wrap_fn(|req, srv| {
+            .wrap_fn(move |req, srv| {
                 let parent_context = get_context(&req);
-                let span = get_tracer().start("request", Some(parent_context));
-                set_span_tags(&req, &span);
+                let span = create_span_from_headers(
+                    "request",
+                    Some(parent_context),
+                    req.headers(),
+                    debug_sampling.as_deref(),
+                );
+                tracing_tags.set_span_tags(&req, &span);
                 srv.call(req).instrument(span)
             })
-            .app_data(actix_web::web::Data::new(main_state.clone()))
+            .wrap(middleware::Condition::new(
+                access_log_enabled,
+                access_log.clone(),
+            ))
+            .app_data(main_state.clone())
             .service(
                 actix_web::web::resource(&format!("{}/v1/graph", app_prefix.clone()))
                     .route(actix_web::web::get().to(graph::index)),
             )
     })
-    .keep_alive(10)
-    .bind(service_addr)?
-    .run();
+    .keep_alive(10);
+    let main_server = match max_connections {
+        Some(max_connections) => main_server.max_connections(max_connections),
+        None => main_server,
+    };
+    main_server.bind(service_addr)?.run();
 
+    actix::Arbiter::spawn(stop_on_signal(shutdown.clone()));
     let _ = sys.run();
 
+    // Let the scraper finish its current iteration.
+    shutdown.cancel();
+    if scraper.join().is_err() {
+        error!("graph scraper panicked");
+    }
+
     Ok(())
 }
 
+/// Stop the scrape loop and the services on SIGINT or SIGTERM.
+async fn stop_on_signal(shutdown: graph::CancellationToken) {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!("failed to listen for SIGTERM: {}", e);
+            return;
+        }
+    };
+
+    future::select(
+        Box::pin(tokio::signal::ctrl_c()),
+        Box::pin(terminate.recv()),
+    )
+    .await;
+    info!("termination signal received, shutting down");
+
+    shutdown.cancel();
+    actix::System::current().stop();
+}
+
 fn ensure_registered_metrics(
     registry: &prometheus::Registry,
     metrics_prefix: &str,
@@ -163,7 +239,6 @@ mod tests {
     use std::sync::Arc;
 
     fn mock_state() -> State {
-        let json_graph = Arc::new(RwLock::new(String::new()));
         let live = Arc::new(RwLock::new(false));
         let ready = Arc::new(RwLock::new(false));
 
@@ -172,7 +247,14 @@ mod tests {
             metrics::new_registry(Some(config::METRICS_PREFIX.to_string())).unwrap(),
         ));
 
-        State::new(json_graph, HashSet::new(), live, ready, plugins, registry)
+        State::new(
+            HashSet::new(),
+            live,
+            ready,
+            plugins,
+            registry,
+            Default::default(),
+        )
     }
 
     #[test]
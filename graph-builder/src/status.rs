@@ -1,7 +1,41 @@
 //! Status service.
+//!
+//! The scrape loop is probed through three flags of its `State`, set in order:
+//!  * started: the scrape loop has been entered, which happens once the
+//!    process is up. It is never reset, and backs the startup probe, so that a
+//!    slow first scrape doesn't get the process restarted.
+//!  * live: the scrape loop is running, reset if it panics.
+//!  * ready: a graph has been successfully scraped.
 
+use crate::built_info;
+use crate::config::AppSettings;
 use crate::graph::State;
 use actix_web::HttpResponse;
+use commons::build_info::BuildInfo;
+
+/// Probe flags of the scrape loop.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProbeStatus {
+    /// Whether the scrape loop has started.
+    pub started: bool,
+    /// Whether the scrape loop is running.
+    pub live: bool,
+    /// Whether a graph has been scraped.
+    pub ready: bool,
+}
+
+/// Expose startup status.
+///
+/// Status:
+///  * Started (200 code): The upstream scrape loop has been entered, regardless of scrape success
+///  * Not Started (503 code): everything else.
+pub async fn serve_startup(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    if app_data.is_started() {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::ServiceUnavailable().finish()
+    }
+}
 
 /// Expose liveness status.
 ///
@@ -28,3 +62,102 @@ pub async fn serve_readiness(app_data: actix_web::web::Data<State>) -> HttpRespo
         HttpResponse::ServiceUnavailable().finish()
     }
 }
+
+/// Expose the startup, liveness and readiness flags (JSON format).
+pub async fn serve_probes(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(ProbeStatus {
+        started: app_data.is_started(),
+        live: app_data.is_live(),
+        ready: app_data.is_ready(),
+    })
+}
+
+/// Expose the channel topology of the current graph (JSON format).
+///
+/// The topology is empty until the first successful scrape.
+pub async fn serve_topology(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(app_data.topology())
+}
+
+/// Expose the first-seen timestamp of every tracked release version (JSON format).
+///
+/// Timestamps are in RFC3339 format, keyed by version.
+pub async fn serve_first_seen(app_data: actix_web::web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(app_data.first_seen())
+}
+
+/// Register the status endpoints of the scrape loop.
+///
+/// The `State` of the scrape loop is expected as application data.
+pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(
+        actix_web::web::resource("/startupz").route(actix_web::web::get().to(serve_startup)),
+    )
+    .service(actix_web::web::resource("/liveness").route(actix_web::web::get().to(serve_liveness)))
+    .service(
+        actix_web::web::resource("/readiness").route(actix_web::web::get().to(serve_readiness)),
+    )
+    .service(
+        actix_web::web::resource("/status/probes").route(actix_web::web::get().to(serve_probes)),
+    )
+    .service(
+        actix_web::web::resource("/status/topology")
+            .route(actix_web::web::get().to(serve_topology)),
+    )
+    .service(
+        actix_web::web::resource("/status/first-seen")
+            .route(actix_web::web::get().to(serve_first_seen)),
+    );
+}
+
+/// Assemble the build information exposed on `/status/build`.
+pub fn build_info(settings: &AppSettings) -> BuildInfo {
+    commons::build_info!(built_info, settings.tracing_endpoint.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::prelude_errors::*;
+    use commons::testing;
+    use parking_lot::RwLock;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    #[test]
+    fn serve_startup_and_probes() -> Fallible<()> {
+        let mut rt = testing::init_runtime()?;
+        let state = actix_web::web::Data::new(State::new(
+            HashSet::new(),
+            Arc::new(RwLock::new(false)),
+            Arc::new(RwLock::new(false)),
+            Box::leak(Box::new([])),
+            Box::leak(Box::new(prometheus::Registry::new())),
+            Default::default(),
+        ));
+
+        // The scrape loop hasn't started.
+        assert_eq!(rt.block_on(serve_startup(state.clone())).status(), 503);
+        let resp = rt.block_on(serve_probes(state));
+        assert_eq!(resp.status(), 200);
+        if let actix_web::body::ResponseBody::Body(body) = resp.body() {
+            if let actix_web::body::Body::Bytes(bytes) = body {
+                let probes: ProbeStatus = serde_json::from_slice(bytes.as_ref())?;
+                assert_eq!(
+                    probes,
+                    ProbeStatus {
+                        started: false,
+                        live: false,
+                        ready: false,
+                    }
+                );
+            } else {
+                bail!("expected Body")
+            }
+        } else {
+            bail!("expected bytes in body")
+        };
+
+        Ok(())
+    }
+}
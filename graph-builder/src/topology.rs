@@ -0,0 +1,239 @@
+//! Channel topology of the processed graph.
+//!
+//! For every channel the newest member (the "channel head") is determined, as
+//! well as the number of other members which are dead ends, i.e. which have no
+//! outgoing edge at all.
+
+use cincinnati::versions::parse_release_version;
+use cincinnati::{parse_channels, Graph, ReleaseId, CHANNELS_KEY};
+use commons::Fallible;
+use prometheus::{IntGaugeVec, Opts};
+use std::collections::{BTreeMap, HashMap};
+
+lazy_static! {
+    static ref GRAPH_CHANNEL_HEAD_INFO: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("graph_channel_head_info", "Newest release of each channel"),
+        &["channel", "version"]
+    )
+    .unwrap();
+    static ref GRAPH_CHANNEL_DEAD_ENDS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "graph_channel_dead_ends",
+            "Number of releases other than the channel head without outgoing edges"
+        ),
+        &["channel"]
+    )
+    .unwrap();
+}
+
+/// Register the topology metrics to a prometheus registry.
+pub fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(GRAPH_CHANNEL_HEAD_INFO.clone()))?;
+    registry.register(Box::new(GRAPH_CHANNEL_DEAD_ENDS.clone()))?;
+    Ok(())
+}
+
+/// Topology summary of a single channel.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ChannelTopology {
+    /// Version of the newest release in the channel.
+    pub head: String,
+    /// Number of releases in the channel, other than the head, without outgoing edges.
+    pub dead_ends: u64,
+}
+
+/// Topology summaries, by channel name.
+pub type Topology = BTreeMap<String, ChannelTopology>;
+
+/// Compute the topology summary of every channel in the graph.
+///
+/// Releases are ordered as defined in `cincinnati::versions`, and those with
+/// an invalid version are ignored.
+pub fn compute(graph: &Graph) -> Topology {
+    let channels: HashMap<ReleaseId, String> = graph
+        .find_by_metadata_key(CHANNELS_KEY)
+        .into_iter()
+        .map(|(release_id, _, channels)| (release_id, channels))
+        .collect();
+
+    // Members of every channel, from the oldest to the newest.
+    let mut members: BTreeMap<String, Vec<(ReleaseId, String)>> = BTreeMap::new();
+    for (release_id, version) in graph.releases_sorted_by_version() {
+        let release_channels = match channels.get(&release_id) {
+            Some(release_channels) => release_channels,
+            None => continue,
+        };
+
+        for channel in parse_channels(release_channels) {
+            members
+                .entry(channel.to_string())
+                .or_default()
+                .push((release_id.clone(), version.clone()));
+        }
+    }
+
+    members
+        .into_iter()
+        .filter_map(|(channel, mut releases)| {
+            let (_, head) = releases.pop()?;
+
+            // Releases of the head version for other architectures aren't dead ends.
+            let head_version = parse_release_version(&head).ok()?;
+            let dead_ends = releases
+                .iter()
+                .filter(|(release_id, version)| {
+                    parse_release_version(version).map_or(false, |version| version != head_version)
+                        && graph.next_releases(release_id).next().is_none()
+                })
+                .count() as u64;
+
+            Some((channel, ChannelTopology { head, dead_ends }))
+        })
+        .collect()
+}
+
+/// Replace the exported topology metrics with the given summary.
+///
+/// Series of channels which are no longer present, and of previous heads, are dropped.
+pub fn update_metrics(topology: &Topology) {
+    GRAPH_CHANNEL_HEAD_INFO.reset();
+    GRAPH_CHANNEL_DEAD_ENDS.reset();
+
+    for (channel, summary) in topology {
+        GRAPH_CHANNEL_HEAD_INFO
+            .with_label_values(&[channel, &summary.head])
+            .set(1);
+        GRAPH_CHANNEL_DEAD_ENDS
+            .with_label_values(&[channel])
+            .set(summary.dead_ends as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::{ConcreteRelease, MapImpl, Release};
+
+    fn build_graph(releases: &[(&str, &str)], edges: &[(&str, &str)]) -> Graph {
+        let mut graph = Graph::default();
+
+        for (version, channels) in releases {
+            let mut metadata = MapImpl::new();
+            metadata.insert(CHANNELS_KEY.to_string(), channels.to_string());
+            graph
+                .add_release(Release::Concrete(ConcreteRelease {
+                    version: version.to_string(),
+                    payload: format!("image:{}", version),
+                    metadata,
+                }))
+                .unwrap();
+        }
+
+        for (from, to) in edges {
+            let from = graph.find_by_version(from).unwrap();
+            let to = graph.find_by_version(to).unwrap();
+            graph.add_edge(&from, &to).unwrap();
+        }
+
+        graph
+    }
+
+    fn fixture() -> Graph {
+        build_graph(
+            &[
+                ("4.5.1", "stable-4.5, fast-4.5"),
+                ("4.5.2", "stable-4.5,fast-4.5"),
+                ("4.5.3", "fast-4.5"),
+                ("4.5.10", "fast-4.5"),
+                ("4.5.9", "stable-4.5,fast-4.5"),
+            ],
+            &[("4.5.1", "4.5.9"), ("4.5.9", "4.5.10")],
+        )
+    }
+
+    #[test]
+    fn heads_and_dead_ends() {
+        let topology = compute(&fixture());
+
+        let expected: Topology = vec![
+            (
+                "fast-4.5".to_string(),
+                ChannelTopology {
+                    head: "4.5.10".to_string(),
+                    dead_ends: 2,
+                },
+            ),
+            (
+                "stable-4.5".to_string(),
+                ChannelTopology {
+                    head: "4.5.9".to_string(),
+                    dead_ends: 1,
+                },
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(topology, expected);
+    }
+
+    #[test]
+    fn heads_by_release_version() {
+        let topology = compute(&build_graph(
+            &[
+                ("4.10.0+amd64", "stable-4.10"),
+                ("4.9.0", "stable-4.10"),
+                ("4.10.0+s390x", "stable-4.10"),
+                ("4.10.0-rc.0", "stable-4.10"),
+            ],
+            &[],
+        ));
+
+        // The other architecture of the head version is not a dead end.
+        assert_eq!(
+            topology.get("stable-4.10"),
+            Some(&ChannelTopology {
+                head: "4.10.0+s390x".to_string(),
+                dead_ends: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn metrics_drop_stale_labels() -> Fallible<()> {
+        let registry = prometheus::Registry::new();
+        register_metrics(&registry)?;
+
+        let series = |name: &str| -> Vec<Vec<String>> {
+            registry
+                .gather()
+                .iter()
+                .filter(|family| family.get_name() == name)
+                .flat_map(|family| family.get_metric().iter())
+                .map(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .map(|label| label.get_value().to_string())
+                        .collect()
+                })
+                .collect()
+        };
+
+        update_metrics(&compute(&fixture()));
+        assert_eq!(
+            series("graph_channel_head_info"),
+            vec![vec!["fast-4.5", "4.5.10"], vec!["stable-4.5", "4.5.9"]]
+        );
+        assert_eq!(series("graph_channel_dead_ends").len(), 2);
+
+        update_metrics(&compute(&build_graph(&[("4.6.0", "stable-4.6")], &[])));
+        assert_eq!(
+            series("graph_channel_head_info"),
+            vec![vec!["stable-4.6", "4.6.0"]]
+        );
+        assert_eq!(series("graph_channel_dead_ends"), vec![vec!["stable-4.6"]]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,388 @@
+//! Soak-test tool: replay recorded query strings against a policy-engine.
+//!
+//! The input file holds one query string per line (e.g.
+//! `channel=stable-4.6&arch=amd64`), optionally followed by whitespace and a
+//! positive integer weight, which is the number of times the query is replayed
+//! per pass. Empty lines and lines starting with `#` are ignored.
+//!
+//! Requests are issued at a fixed rate. Without a duration or request cap the
+//! input is replayed once, otherwise it is replayed in a loop until a cap is
+//! reached. At the end, the number of errors per kind and latency percentiles
+//! are reported, and the tool fails if the error rate exceeds the threshold.
+
+use actix_web::client::Client;
+use actix_web::http::header::ACCEPT;
+use actix_web::rt::time::{delay_until, Instant};
+use commons::prelude_errors::*;
+use futures::stream::{self, StreamExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Maximum size of a response body, in bytes.
+static MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+/// Replay options.
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// URL of the graph endpoint of the target policy-engine
+    #[structopt(long = "url")]
+    url: String,
+
+    /// Path to the file with the query strings to replay
+    #[structopt(long = "input", parse(from_os_str))]
+    input: PathBuf,
+
+    /// Rate of requests, per second
+    #[structopt(long = "rate", default_value = "10")]
+    rate: f64,
+
+    /// Maximum number of in-flight requests
+    #[structopt(long = "concurrency", default_value = "64")]
+    concurrency: usize,
+
+    /// Stop replaying after this number of seconds
+    #[structopt(long = "duration_secs")]
+    duration_secs: Option<u64>,
+
+    /// Stop replaying after this number of requests
+    #[structopt(long = "max_requests")]
+    max_requests: Option<usize>,
+
+    /// Timeout of a single request, in seconds
+    #[structopt(long = "timeout_secs", default_value = "30")]
+    timeout_secs: u64,
+
+    /// Maximum tolerated ratio of failed requests, between 0 and 1
+    #[structopt(long = "max_error_rate", default_value = "0.01")]
+    max_error_rate: f64,
+}
+
+/// A recorded query string.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    query: String,
+    /// Number of times the query is replayed per pass.
+    weight: usize,
+}
+
+/// Parse the content of an input file.
+fn parse_entries(input: &str) -> Fallible<Vec<Entry>> {
+    let mut entries = vec![];
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let query = fields
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches('?')
+            .to_string();
+        let weight = match fields.next() {
+            Some(weight) => weight
+                .parse::<usize>()
+                .context(format!("invalid weight on line {}", index + 1))?,
+            None => 1,
+        };
+        ensure!(weight > 0, "zero weight on line {}", index + 1);
+        ensure!(
+            fields.next().is_none(),
+            "unexpected content on line {}",
+            index + 1
+        );
+
+        entries.push(Entry { query, weight });
+    }
+
+    ensure!(!entries.is_empty(), "no query strings to replay");
+    Ok(entries)
+}
+
+/// Result of a single request.
+#[derive(Debug)]
+struct Outcome {
+    latency: Duration,
+    /// Kind of the error, for failed requests.
+    error: Option<String>,
+}
+
+/// Issue a single request, returning the kind of error on failure.
+async fn request(client: &Client, url: &str, query: &str) -> Result<(), String> {
+    let uri = if query.is_empty() {
+        url.to_string()
+    } else {
+        format!("{}?{}", url, query)
+    };
+
+    let mut response = client
+        .get(uri)
+        .header(ACCEPT, cincinnati::CONTENT_TYPE)
+        .send()
+        .await
+        .map_err(|_| "request_failed".to_string())?;
+    let body = response
+        .body()
+        .limit(MAX_BODY_BYTES)
+        .await
+        .map_err(|_| "response_body".to_string())?;
+
+    if response.status().is_success() {
+        return Ok(());
+    }
+
+    // Error responses carry the kind of a `commons::GraphError`.
+    let kind = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|json| json.get("kind")?.as_str().map(ToString::to_string))
+        .unwrap_or_else(|| format!("http_{}", response.status().as_u16()));
+    Err(kind)
+}
+
+/// Replay the entries at the configured rate, until the input or a cap is exhausted.
+async fn replay(client: &Client, opts: &Options, entries: &[Entry]) -> Vec<Outcome> {
+    let pass = entries
+        .iter()
+        .flat_map(|entry| std::iter::repeat(entry.query.as_str()).take(entry.weight));
+    let queries: Box<dyn Iterator<Item = &str> + '_> =
+        if opts.duration_secs.is_none() && opts.max_requests.is_none() {
+            Box::new(pass)
+        } else {
+            Box::new(pass.cycle())
+        };
+
+    let interval = Duration::from_secs_f64(1.0 / opts.rate);
+    let start = Instant::now();
+    let deadline = opts
+        .duration_secs
+        .map(|secs| start + Duration::from_secs(secs));
+
+    let scheduled = queries
+        .take(opts.max_requests.unwrap_or(usize::MAX))
+        .enumerate()
+        .map(|(index, query)| (start + interval.mul_f64(index as f64), query))
+        .take_while(|(at, _)| deadline.map(|deadline| *at < deadline).unwrap_or(true));
+
+    stream::iter(scheduled)
+        .map(|(at, query)| async move {
+            delay_until(at).await;
+            let sent = std::time::Instant::now();
+            let error = request(client, &opts.url, query).await.err();
+            Outcome {
+                latency: sent.elapsed(),
+                error,
+            }
+        })
+        .buffer_unordered(opts.concurrency)
+        .collect()
+        .await
+}
+
+/// Summary of a replay.
+#[derive(Debug, Default)]
+struct Report {
+    total: usize,
+    /// Number of failed requests, by error kind.
+    errors: BTreeMap<String, usize>,
+    /// Latencies of all requests, sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn new(outcomes: Vec<Outcome>) -> Self {
+        let mut report = Report {
+            total: outcomes.len(),
+            ..Default::default()
+        };
+
+        for outcome in outcomes {
+            if let Some(kind) = outcome.error {
+                *report.errors.entry(kind).or_default() += 1;
+            }
+            report.latencies.push(outcome.latency);
+        }
+        report.latencies.sort();
+
+        report
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.errors.values().sum::<usize>() as f64 / self.total as f64
+    }
+
+    /// Latency percentile, by nearest rank.
+    fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies
+            .get(rank.max(1).min(self.latencies.len()).saturating_sub(1))
+            .cloned()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "requests: {}", self.total)?;
+        writeln!(
+            f,
+            "errors: {} ({:.2}%)",
+            self.errors.values().sum::<usize>(),
+            self.error_rate() * 100.0
+        )?;
+        for (kind, count) in &self.errors {
+            writeln!(f, "  {}: {}", kind, count)?;
+        }
+        for percentile in &[50.0, 90.0, 99.0, 100.0] {
+            if let Some(latency) = self.percentile(*percentile) {
+                writeln!(f, "latency p{}: {:?}", percentile, latency)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn run(opts: &Options, entries: &[Entry]) -> Report {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(opts.timeout_secs))
+        .finish();
+
+    Report::new(replay(&client, opts, entries).await)
+}
+
+fn main() -> Fallible<()> {
+    let opts = Options::from_args();
+    ensure!(opts.rate > 0.0, "rate must be positive");
+    ensure!(opts.concurrency > 0, "concurrency must be positive");
+    ensure!(
+        (0.0..=1.0).contains(&opts.max_error_rate),
+        "maximum error rate must be between 0 and 1"
+    );
+
+    let input = std::fs::read_to_string(&opts.input)
+        .context(format!("failed to read '{}'", opts.input.display()))?;
+    let entries = parse_entries(&input)?;
+
+    let report = actix::System::new("policy-engine-replay").block_on(run(&opts, &entries));
+    print!("{}", report);
+
+    ensure!(
+        report.error_rate() <= opts.max_error_rate,
+        "error rate {:.4} exceeds the maximum of {:.4}",
+        report.error_rate(),
+        opts.max_error_rate
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse};
+    use commons::GraphError;
+
+    /// Graph endpoint performing the request validation of the policy-engine.
+    async fn graph(req: HttpRequest) -> Result<HttpResponse, GraphError> {
+        let mandatory_params = vec!["channel".to_string()].into_iter().collect();
+        commons::ensure_content_type(req.headers(), cincinnati::CONTENT_TYPE)?;
+        commons::ensure_query_params(&mandatory_params, req.query_string())?;
+
+        Ok(HttpResponse::Ok()
+            .content_type(cincinnati::CONTENT_TYPE)
+            .body(r#"{"nodes":[],"edges":[]}"#))
+    }
+
+    fn options(max_requests: Option<usize>) -> Options {
+        Options {
+            url: String::new(),
+            input: PathBuf::new(),
+            rate: 200.0,
+            concurrency: 4,
+            duration_secs: None,
+            max_requests,
+            timeout_secs: 5,
+            max_error_rate: 0.0,
+        }
+    }
+
+    #[test]
+    fn parse_input() -> Fallible<()> {
+        let entries = parse_entries("# recorded\n\nchannel=a 3\n?channel=b&arch=amd64\n")?;
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    query: "channel=a".to_string(),
+                    weight: 3,
+                },
+                Entry {
+                    query: "channel=b&arch=amd64".to_string(),
+                    weight: 1,
+                },
+            ]
+        );
+
+        parse_entries("channel=a 0").unwrap_err();
+        parse_entries("channel=a two").unwrap_err();
+        parse_entries("# nothing\n").unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn report_percentiles() {
+        let outcomes = (1..=10)
+            .map(|ms| Outcome {
+                latency: Duration::from_millis(ms),
+                error: if ms > 8 {
+                    Some("failed_plugin_execution".to_string())
+                } else {
+                    None
+                },
+            })
+            .collect();
+        let report = Report::new(outcomes);
+
+        assert_eq!(report.percentile(50.0), Some(Duration::from_millis(5)));
+        assert_eq!(report.percentile(90.0), Some(Duration::from_millis(9)));
+        assert_eq!(report.percentile(100.0), Some(Duration::from_millis(10)));
+        assert_eq!(report.errors.get("failed_plugin_execution"), Some(&2));
+        assert!((report.error_rate() - 0.2).abs() < f64::EPSILON);
+
+        assert_eq!(Report::new(vec![]).percentile(50.0), None);
+    }
+
+    #[test]
+    fn replay_against_webservice() -> Fallible<()> {
+        let entries = parse_entries("channel=stable-4.6&arch=amd64 3\narch=amd64\n")?;
+
+        let report = actix::System::new("test").block_on(async {
+            let server =
+                actix_web::test::start(|| App::new().route("/graph", web::get().to(graph)));
+            let opts = Options {
+                url: server.url("/graph"),
+                ..options(Some(8))
+            };
+            run(&opts, &entries).await
+        });
+
+        // Two passes over the input, each with one query lacking the channel.
+        assert_eq!(report.total, 8);
+        assert_eq!(report.latencies.len(), 8);
+        assert_eq!(
+            report.errors,
+            vec![("missing_params".to_string(), 2)]
+                .into_iter()
+                .collect()
+        );
+        assert!(report.error_rate() > options(None).max_error_rate);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1011 @@
+//! Cache of graph responses.
+//!
+//! Serialized graphs are cached per set of plugin parameters, for a configured
+//! time-to-live. Stale graphs and requests force-sampled for debugging are
+//! never cached.
+//!
+//! Cache keys are built from the plugin parameters according to configurable
+//! rules: only the included parameters participate, some values are
+//! lowercased, and missing parameters take a default value, so that
+//! equivalent requests share a cache entry.
+//!
+//! The cache can be pre-warmed for a list of client parameter sets: a
+//! background worker runs the plugins for each of them on a schedule slightly
+//! shorter than the time-to-live, so that client requests for those never wait
+//! for the plugins. Pre-warming failures are only logged and counted.
+//!
+//! Independently, graphs can be cached per set of plugin parameters for as
+//! long as the upstream graph keeps the same ETag. Only the graph sources of
+//! the pipeline then run for requests hitting this cache, and all its entries
+//! are invalidated as soon as the upstream ETag changes.
+
+use crate::graph;
+use crate::AppState;
+use actix_web::http::HeaderMap;
+use cincinnati::plugins::parameters::is_reserved;
+use cincinnati::plugins::Parameters;
+use commons::metrics::{record_cache_lookup, CacheOutcome};
+use commons::prelude_errors::*;
+use commons::tracing::DEBUG_ID_PARAM;
+use prometheus::{Counter, Registry};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
+
+/// Default maximum number of cached responses.
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// Name of the response cache in lookup metrics.
+pub static CACHE_NAME: &str = "response";
+
+/// Name of the upstream ETag cache in lookup metrics.
+pub static ETAG_CACHE_NAME: &str = "upstream_etag";
+
+/// Header carrying the effective cache key of requests force-sampled for debugging.
+pub static CACHE_KEY_HEADER: &str = "x-cincinnati-cache-key";
+
+/// Plugin parameters identifying a cached response, in key order.
+pub type CacheKey = Vec<(String, String)>;
+
+lazy_static! {
+    static ref PREWARM_FAILURES: Counter = Counter::new(
+        "cache_prewarm_failures_total",
+        "Total number of failed response cache pre-warms"
+    )
+    .unwrap();
+    static ref PREWARM_SKIPPED: Counter = Counter::new(
+        "cache_prewarm_skipped_total",
+        "Total number of response cache pre-warms skipped as still in progress"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
+    registry.register(Box::new(PREWARM_FAILURES.clone()))?;
+    registry.register(Box::new(PREWARM_SKIPPED.clone()))?;
+    Ok(())
+}
+
+/// Response cache settings.
+#[derive(Clone, Debug, SmartDefault)]
+pub struct CacheSettings {
+    /// Time-to-live of cached responses, caching is disabled if unset.
+    pub ttl: Option<Duration>,
+    /// Maximum number of cached responses.
+    #[default(DEFAULT_MAX_ENTRIES)]
+    pub max_entries: usize,
+    /// Client parameter sets to pre-warm the cache for.
+    pub prewarm: Vec<BTreeMap<String, String>>,
+    /// Rules building cache keys from plugin parameters.
+    pub key: CacheKeyRules,
+    /// Whether to cache graphs for as long as the upstream ETag is unchanged.
+    pub upstream_etag: bool,
+}
+
+/// Rules building cache keys from plugin parameters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheKeyRules {
+    /// Parameters participating in the key, in key order.
+    ///
+    /// All parameters participate, in name order, if empty. Reserved
+    /// parameters, e.g. client capabilities or forwarded headers, always
+    /// participate, as they change the processed graphs.
+    pub include: Vec<String>,
+    /// Parameters whose values are lowercased.
+    pub lowercase: HashSet<String>,
+    /// Values of parameters missing from a request.
+    pub defaults: BTreeMap<String, String>,
+}
+
+impl CacheKeyRules {
+    /// Build the cache key for the given plugin parameters.
+    ///
+    /// The debug id of force-sampled requests never participates, and the
+    /// parameters changing how graphs are rendered and the reserved
+    /// parameters always do.
+    pub fn key(&self, params: &Parameters) -> CacheKey {
+        let mut names: Vec<&str> = if self.include.is_empty() {
+            params
+                .iter()
+                .map(|(name, _)| name)
+                .chain(self.defaults.keys())
+                .map(String::as_str)
+                .filter(|name| *name != DEBUG_ID_PARAM)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect()
+        } else {
+            let mut names: Vec<&str> = self.include.iter().map(String::as_str).collect();
+            let reserved: BTreeSet<&str> = params
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .filter(|name| is_reserved(name) && *name != DEBUG_ID_PARAM)
+                .filter(|name| !names.contains(name))
+                .collect();
+            names.extend(reserved);
+            names
+        };
+        for name in graph::RENDERING_PARAMS {
+            if !names.contains(name) {
+                names.push(*name);
+            }
+        }
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let value = params.get(name).or_else(|| self.defaults.get(name))?;
+                let value = if self.lowercase.contains(name) {
+                    value.to_lowercase()
+                } else {
+                    value.clone()
+                };
+                Some((name.to_string(), value))
+            })
+            .collect()
+    }
+
+    /// Whether the given parameter participates in cache keys, when present.
+    pub fn includes(&self, name: &str) -> bool {
+        if graph::RENDERING_PARAMS.contains(&name) {
+            return true;
+        }
+        if self.include.is_empty() || is_reserved(name) {
+            name != DEBUG_ID_PARAM
+        } else {
+            self.include.iter().any(|included| included == name)
+        }
+    }
+}
+
+/// Format a cache key as a query string, for troubleshooting.
+pub fn format_key(key: &CacheKey) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(key)
+        .finish()
+}
+
+/// Cached serialized graph.
+#[derive(Debug)]
+struct CacheEntry {
+    json: String,
+    inserted: Instant,
+}
+
+/// Cache of serialized graphs, shared by all workers.
+#[derive(Debug)]
+pub struct ResponseCache {
+    name: &'static str,
+    ttl: Duration,
+    max_entries: usize,
+    prewarm: Vec<BTreeMap<String, String>>,
+    key_rules: CacheKeyRules,
+    entries: RwLock<HashMap<CacheKey, CacheEntry>>,
+    prewarming: Mutex<HashSet<CacheKey>>,
+}
+
+impl ResponseCache {
+    /// Create the cache, if enabled by the given settings.
+    pub fn from_settings(settings: &CacheSettings) -> Option<Self> {
+        Some(
+            Self::new(
+                settings.ttl?,
+                settings.max_entries,
+                settings.prewarm.clone(),
+            )
+            .with_key_rules(settings.key.clone()),
+        )
+    }
+
+    /// Create an empty cache.
+    pub fn new(ttl: Duration, max_entries: usize, prewarm: Vec<BTreeMap<String, String>>) -> Self {
+        Self {
+            name: CACHE_NAME,
+            ttl,
+            max_entries,
+            prewarm,
+            key_rules: CacheKeyRules::default(),
+            entries: RwLock::new(HashMap::new()),
+            prewarming: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Build cache keys according to the given rules.
+    pub fn with_key_rules(mut self, key_rules: CacheKeyRules) -> Self {
+        self.key_rules = key_rules;
+        self
+    }
+
+    /// Return the cache key for the given plugin parameters, if the response is cacheable.
+    pub fn key(&self, params: &Parameters) -> Option<CacheKey> {
+        if params.contains_key(DEBUG_ID_PARAM) {
+            return None;
+        }
+
+        Some(self.key_rules.key(params))
+    }
+
+    /// Whether the given parameter participates in cache keys, when present.
+    pub fn key_includes(&self, name: &str) -> bool {
+        self.key_rules.includes(name)
+    }
+
+    /// Return the cache key the given plugin parameters map to, even if not cacheable.
+    pub fn effective_key(&self, params: &Parameters) -> String {
+        format_key(&self.key_rules.key(params))
+    }
+
+    /// Return the cached graph for `key`, unless expired.
+    ///
+    /// Expired entries count as stale lookups.
+    pub fn get(&self, key: &CacheKey) -> Option<String> {
+        let entries = match self.entries.read() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let (outcome, json) = match entries.get(key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => {
+                (CacheOutcome::Hit, Some(entry.json.clone()))
+            }
+            Some(_) => (CacheOutcome::Stale, None),
+            None => (CacheOutcome::Miss, None),
+        };
+        record_cache_lookup(self.name, outcome);
+
+        json
+    }
+
+    /// Cache the graph for `key`.
+    ///
+    /// Expired entries are evicted when the cache is full. If none is, the
+    /// graph is not cached.
+    pub fn insert(&self, key: CacheKey, json: String) {
+        let mut entries = match self.entries.write() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.inserted.elapsed() < ttl);
+            if entries.len() >= self.max_entries {
+                debug!("response cache full, not caching {:?}", key);
+                return;
+            }
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                json,
+                inserted: Instant::now(),
+            },
+        );
+    }
+
+    /// Drop all cached graphs.
+    pub fn clear(&self) {
+        match self.entries.write() {
+            Ok(mut entries) => entries.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+    }
+
+    /// Interval between pre-warms, slightly shorter than the time-to-live.
+    pub fn prewarm_interval(&self) -> Duration {
+        self.ttl - self.ttl / 10
+    }
+
+    /// Mark the pre-warm of `key` as in progress, unless it already is.
+    fn start_prewarm(&self, key: &CacheKey) -> Option<PrewarmGuard<'_>> {
+        let mut prewarming = match self.prewarming.lock() {
+            Ok(prewarming) => prewarming,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if !prewarming.insert(key.clone()) {
+            return None;
+        }
+
+        Some(PrewarmGuard {
+            cache: self,
+            key: key.clone(),
+        })
+    }
+}
+
+/// Graphs cached for the current upstream ETag.
+#[derive(Debug, Default)]
+struct EtagEntries {
+    etag: String,
+    graphs: HashMap<CacheKey, String>,
+}
+
+/// Cache of serialized graphs valid for a single upstream ETag, shared by all workers.
+#[derive(Debug)]
+pub struct EtagCache {
+    name: &'static str,
+    max_entries: usize,
+    key_rules: CacheKeyRules,
+    entries: RwLock<EtagEntries>,
+}
+
+impl EtagCache {
+    /// Create the cache, if enabled by the given settings.
+    pub fn from_settings(settings: &CacheSettings) -> Option<Self> {
+        if !settings.upstream_etag {
+            return None;
+        }
+
+        Some(Self::new(settings.max_entries).with_key_rules(settings.key.clone()))
+    }
+
+    /// Create an empty cache.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            name: ETAG_CACHE_NAME,
+            max_entries,
+            key_rules: CacheKeyRules::default(),
+            entries: RwLock::new(EtagEntries::default()),
+        }
+    }
+
+    /// Build cache keys according to the given rules.
+    pub fn with_key_rules(mut self, key_rules: CacheKeyRules) -> Self {
+        self.key_rules = key_rules;
+        self
+    }
+
+    /// Return the cache key for the given plugin parameters, if the graph is cacheable.
+    pub fn key(&self, params: &Parameters) -> Option<CacheKey> {
+        if params.contains_key(DEBUG_ID_PARAM) {
+            return None;
+        }
+
+        Some(self.key_rules.key(params))
+    }
+
+    /// Return the graph cached for `key`, if the upstream graph still has the given ETag.
+    ///
+    /// Entries cached for another ETag count as stale lookups.
+    pub fn get(&self, etag: &str, key: &CacheKey) -> Option<String> {
+        let entries = match self.entries.read() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let (outcome, json) = match entries.graphs.get(key) {
+            Some(json) if entries.etag == etag => (CacheOutcome::Hit, Some(json.clone())),
+            Some(_) => (CacheOutcome::Stale, None),
+            None => (CacheOutcome::Miss, None),
+        };
+        record_cache_lookup(self.name, outcome);
+
+        json
+    }
+
+    /// Cache the graph processed for `key` from the upstream graph with the given ETag.
+    ///
+    /// A new ETag invalidates all entries. If the cache is full, the graph is not cached.
+    pub fn insert(&self, etag: &str, key: CacheKey, json: String) {
+        let mut entries = match self.entries.write() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if entries.etag != etag {
+            trace!(
+                "upstream ETag changed to {}, invalidating cached graphs",
+                etag
+            );
+            entries.etag = etag.to_string();
+            entries.graphs.clear();
+        }
+        if !entries.graphs.contains_key(&key) && entries.graphs.len() >= self.max_entries {
+            debug!("upstream ETag cache full, not caching {:?}", key);
+            return;
+        }
+
+        entries.graphs.insert(key, json);
+    }
+
+    /// Drop all cached graphs.
+    pub fn clear(&self) {
+        let mut entries = match self.entries.write() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *entries = EtagEntries::default();
+    }
+}
+
+/// Pre-warm in progress, finished on drop.
+struct PrewarmGuard<'a> {
+    cache: &'a ResponseCache,
+    key: CacheKey,
+}
+
+impl Drop for PrewarmGuard<'_> {
+    fn drop(&mut self) {
+        let mut prewarming = match self.cache.prewarming.lock() {
+            Ok(prewarming) => prewarming,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        prewarming.remove(&self.key);
+    }
+}
+
+/// Pre-warm the cache for all configured parameter sets, forever.
+///
+/// Each parameter set is refreshed in a task of its own, so that a slow one
+/// doesn't delay the others.
+pub(crate) async fn run_prewarm(app_data: AppState) {
+    let cache = match &app_data.cache {
+        Some(cache) if !cache.prewarm.is_empty() => cache.clone(),
+        _ => return,
+    };
+
+    let interval = cache.prewarm_interval();
+    loop {
+        for client_params in &cache.prewarm {
+            actix::Arbiter::spawn(prewarm(app_data.clone(), client_params.clone()));
+        }
+        actix::clock::delay_for(interval).await;
+    }
+}
+
+/// Refresh the cache entry for the given client parameters.
+///
+/// The parameters are processed as those of a client request without
+/// headers. The refresh is skipped if a previous one for the same entry is
+/// still in progress.
+pub(crate) async fn prewarm(app_data: AppState, client_params: BTreeMap<String, String>) {
+    let cache = match &app_data.cache {
+        Some(cache) => cache,
+        None => return,
+    };
+
+    let plugin_params = graph::plugin_params(
+        &HeaderMap::new(),
+        &app_data,
+        client_params.clone().into_iter().collect(),
+    );
+    let key = match cache.key(&plugin_params) {
+        Some(key) => key,
+        None => return,
+    };
+
+    let _guard = match cache.start_prewarm(&key) {
+        Some(guard) => guard,
+        None => {
+            debug!(
+                "pre-warm of {:?} still in progress, skipping",
+                client_params
+            );
+            PREWARM_SKIPPED.inc();
+            return;
+        }
+    };
+
+    let plugins = match app_data.live_plugins() {
+        Ok(plugins) => plugins,
+        Err(e) => {
+            warn!(
+                "failed to pre-warm response cache for {:?}: {}",
+                client_params, e
+            );
+            PREWARM_FAILURES.inc();
+            return;
+        }
+    };
+    let rendered = graph::render_graph(
+        plugins.iter(),
+        plugin_params,
+        app_data.max_graph_size,
+        app_data.expose_warnings,
+    )
+    .await;
+    match rendered {
+        Ok(rendered) if rendered.stale_age.is_none() => {
+            trace!("pre-warmed response cache for {:?}", client_params);
+            app_data
+                .plugins
+                .if_current(&plugins, || cache.insert(key, rendered.json));
+        }
+        Ok(_) => {
+            warn!(
+                "not pre-warming response cache for {:?} with a stale graph",
+                client_params
+            );
+            PREWARM_FAILURES.inc();
+        }
+        Err(e) => {
+            warn!(
+                "failed to pre-warm response cache for {:?}: {}",
+                client_params, e
+            );
+            PREWARM_FAILURES.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::{call_index, common_init, RunCounter};
+    use crate::reload::PluginChain;
+    use actix_web::http;
+    use cincinnati::plugins::prelude::*;
+    use cincinnati::plugins::prelude_plugin_impl::{async_trait, InternalIO, InternalPlugin};
+    use cincinnati::plugins::InternalPluginWrapper;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn key(pairs: &[(&str, &str)]) -> CacheKey {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn cache_expiry_and_capacity() {
+        let cache = ResponseCache::new(Duration::from_millis(50), 2, vec![]);
+        let (stable, fast, candidate) = (
+            key(&[("channel", "stable")]),
+            key(&[("channel", "fast")]),
+            key(&[("channel", "candidate")]),
+        );
+
+        cache.insert(stable.clone(), "stable".to_string());
+        cache.insert(fast.clone(), "fast".to_string());
+        assert_eq!(cache.get(&stable), Some("stable".to_string()));
+
+        // Full of fresh entries.
+        cache.insert(candidate.clone(), "candidate".to_string());
+        assert_eq!(cache.get(&candidate), None);
+
+        // Expired entries are evicted.
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(&stable), None);
+        cache.insert(candidate.clone(), "candidate".to_string());
+        assert_eq!(cache.get(&candidate), Some("candidate".to_string()));
+
+        let params = vec![(DEBUG_ID_PARAM.to_string(), "abc".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(cache.key(&params), None);
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Parameters {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn lookup_metrics() {
+        use commons::metrics::cache_lookups;
+
+        // A cache name of its own, as lookup counters are shared by all tests.
+        let mut cache = ResponseCache::new(Duration::from_millis(50), 10, vec![]);
+        cache.name = "response_lookup_metrics_test";
+        let count = |outcome| cache_lookups(cache.name, outcome);
+
+        let stable = key(&[("channel", "stable")]);
+        assert_eq!(cache.get(&stable), None);
+        cache.insert(stable.clone(), "stable".to_string());
+        assert_eq!(cache.get(&stable), Some("stable".to_string()));
+        assert_eq!(cache.get(&stable), Some("stable".to_string()));
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(&stable), None);
+
+        assert_eq!(count(CacheOutcome::Hit), 2);
+        assert_eq!(count(CacheOutcome::Miss), 1);
+        assert_eq!(count(CacheOutcome::Stale), 1);
+        assert_eq!(cache_lookups("response_other_test", CacheOutcome::Hit), 0);
+    }
+
+    #[test]
+    fn key_normalization() {
+        let rules = CacheKeyRules {
+            include: vec!["channel".to_string(), "arch".to_string()],
+            lowercase: vec!["arch".to_string()].into_iter().collect(),
+            defaults: vec![("arch".to_string(), "amd64".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let cache = ResponseCache::new(Duration::from_secs(60), 10, vec![]).with_key_rules(rules);
+
+        let stable = cache
+            .key(&params(&[("channel", "stable-4.6"), ("arch", "amd64")]))
+            .unwrap();
+        assert_eq!(stable, key(&[("channel", "stable-4.6"), ("arch", "amd64")]));
+
+        // Reordered, defaulted, differently-cased and non-included parameters share the entry.
+        for equivalent in &[
+            params(&[("arch", "amd64"), ("channel", "stable-4.6")]),
+            params(&[("channel", "stable-4.6")]),
+            params(&[("channel", "stable-4.6"), ("arch", "AMD64")]),
+            params(&[("channel", "stable-4.6"), ("arch", "amd64"), ("id", "abc")]),
+        ] {
+            assert_eq!(cache.key(equivalent), Some(stable.clone()));
+        }
+
+        // Included parameters discriminate.
+        for different in &[
+            params(&[("channel", "fast-4.6"), ("arch", "amd64")]),
+            params(&[("channel", "stable-4.6"), ("arch", "s390x")]),
+            params(&[("channel", "STABLE-4.6"), ("arch", "amd64")]),
+            params(&[("channel", "stable-4.6"), ("include", "minimal")]),
+        ] {
+            assert_ne!(cache.key(different), Some(stable.clone()));
+        }
+
+        assert_eq!(
+            cache.effective_key(&params(&[
+                ("arch", "AMD64"),
+                ("channel", "stable-4.6"),
+                (DEBUG_ID_PARAM, "abc")
+            ])),
+            "channel=stable-4.6&arch=amd64"
+        );
+    }
+
+    #[test]
+    fn key_reserved_params() {
+        let rules = CacheKeyRules {
+            include: vec!["channel".to_string()],
+            ..Default::default()
+        };
+        let cache = ResponseCache::new(Duration::from_secs(60), 10, vec![]).with_key_rules(rules);
+
+        // Capabilities change the processed graph, even if not included.
+        let conditional = params(&[
+            ("channel", "stable-4.6"),
+            ("__capability.conditional_edges", "true"),
+        ]);
+        let unconditional = params(&[
+            ("channel", "stable-4.6"),
+            ("__capability.conditional_edges", "false"),
+        ]);
+        assert_ne!(cache.key(&conditional), cache.key(&unconditional));
+        assert_eq!(
+            cache.key(&conditional),
+            Some(key(&[
+                ("channel", "stable-4.6"),
+                ("__capability.conditional_edges", "true"),
+            ]))
+        );
+        assert!(cache.key_includes("__header.x-entitlement"));
+        assert!(!cache.key_includes(DEBUG_ID_PARAM));
+    }
+
+    #[test]
+    fn key_rendering_params() {
+        let rules = CacheKeyRules {
+            include: vec!["channel".to_string()],
+            ..Default::default()
+        };
+        let cache = ResponseCache::new(Duration::from_secs(60), 10, vec![]).with_key_rules(rules);
+
+        // Rendering parameters participate even if not included.
+        let minimal = params(&[("channel", "stable-4.6"), (graph::INCLUDE_PARAM, "minimal")]);
+        assert_eq!(
+            cache.key(&minimal),
+            Some(key(&[
+                ("channel", "stable-4.6"),
+                (graph::INCLUDE_PARAM, "minimal")
+            ]))
+        );
+        assert_eq!(
+            cache.key(&params(&[
+                (graph::PRETTY_PARAM, "true"),
+                ("channel", "stable-4.6"),
+            ])),
+            Some(key(&[
+                ("channel", "stable-4.6"),
+                (graph::PRETTY_PARAM, "true")
+            ]))
+        );
+
+        let full = params(&[("channel", "stable-4.6")]);
+        cache.insert(cache.key(&full).unwrap(), "full".to_string());
+        assert_eq!(cache.get(&cache.key(&minimal).unwrap()), None);
+        cache.insert(cache.key(&minimal).unwrap(), "minimal".to_string());
+        assert_eq!(
+            cache.get(&cache.key(&full).unwrap()),
+            Some("full".to_string())
+        );
+    }
+
+    #[test]
+    fn key_without_rules() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 10, vec![]);
+
+        let stable = cache
+            .key(&params(&[("channel", "stable-4.6"), ("arch", "amd64")]))
+            .unwrap();
+        assert_eq!(
+            cache.key(&params(&[("arch", "amd64"), ("channel", "stable-4.6")])),
+            Some(stable.clone())
+        );
+        assert_eq!(stable, key(&[("arch", "amd64"), ("channel", "stable-4.6")]));
+
+        // All parameters participate.
+        assert_ne!(
+            cache.key(&params(&[
+                ("channel", "stable-4.6"),
+                ("arch", "amd64"),
+                ("id", "abc")
+            ])),
+            Some(stable)
+        );
+    }
+
+    #[test]
+    fn prewarm_overlap() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 10, vec![]);
+        let stable = key(&[("channel", "stable")]);
+
+        let guard = cache.start_prewarm(&stable).unwrap();
+        assert!(cache.start_prewarm(&stable).is_none());
+        assert!(cache.start_prewarm(&key(&[("channel", "fast")])).is_some());
+
+        drop(guard);
+        assert!(cache.start_prewarm(&stable).is_some());
+        assert_eq!(cache.prewarm_interval(), Duration::from_secs(54));
+    }
+
+    #[test]
+    fn etag_cache_invalidation() {
+        let cache = EtagCache::new(1);
+        let (stable, fast) = (key(&[("channel", "stable")]), key(&[("channel", "fast")]));
+
+        cache.insert("v1", stable.clone(), "stable".to_string());
+        assert_eq!(cache.get("v1", &stable), Some("stable".to_string()));
+        assert_eq!(cache.get("v2", &stable), None);
+
+        // Full for the current ETag.
+        cache.insert("v1", fast.clone(), "fast".to_string());
+        assert_eq!(cache.get("v1", &fast), None);
+
+        // A new ETag invalidates all entries.
+        cache.insert("v2", fast.clone(), "fast".to_string());
+        assert_eq!(cache.get("v2", &fast), Some("fast".to_string()));
+        assert_eq!(cache.get("v1", &stable), None);
+        assert_eq!(cache.get("v2", &stable), None);
+    }
+
+    #[test]
+    fn etag_cache_reuses_processed_graph() -> Fallible<()> {
+        let mut rt = common_init();
+        let path = "/etag-cache";
+
+        static GRAPH_V1: &str =
+            r#"{"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}}],"edges":[]}"#;
+        static GRAPH_V2: &str =
+            r#"{"nodes":[{"version":"2.0.0","payload":"image/2.0.0","metadata":{}}],"edges":[]}"#;
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}{}", mockito::server_url(), path))
+            )?],
+            None,
+        )?;
+        plugins.push(new_plugin!(InternalPluginWrapper(RunCounter(runs.clone()))));
+        let chain = PluginChain::new(plugins);
+        let plugins = chain.current();
+        let cache = EtagCache::new(DEFAULT_MAX_ENTRIES);
+        let mut render = || {
+            rt.block_on(graph::render_graph_revalidated(
+                &chain,
+                &plugins,
+                params(&[("channel", "stable")]),
+                Some(&cache),
+                None,
+                false,
+            ))
+        };
+
+        let upstream = mockito::mock("GET", path)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", r#""v1""#)
+            .with_body(GRAPH_V1)
+            .expect(1)
+            .create();
+        assert_eq!(render()?.json, GRAPH_V1);
+        upstream.assert();
+        drop(upstream);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Unchanged ETag, the processed graph is reused.
+        let upstream = mockito::mock("GET", path)
+            .match_header("if-none-match", r#""v1""#)
+            .with_status(304)
+            .expect(1)
+            .create();
+        assert_eq!(render()?.json, GRAPH_V1);
+        upstream.assert();
+        drop(upstream);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Changed ETag, the graph is processed again.
+        let upstream = mockito::mock("GET", path)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", r#""v2""#)
+            .with_body(GRAPH_V2)
+            .expect(1)
+            .create();
+        assert_eq!(render()?.json, GRAPH_V2);
+        upstream.assert();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    /// Plugin counting its runs, whose output may change regardless of its input.
+    #[derive(Debug)]
+    struct ImpureRunCounter(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl InternalPlugin for ImpureRunCounter {
+        const PLUGIN_NAME: &'static str = "impure-run-counter";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(io)
+        }
+    }
+
+    #[test]
+    fn etag_cache_skips_impure_plugins() -> Fallible<()> {
+        let mut rt = common_init();
+        let path = "/etag-cache-impure";
+
+        static GRAPH: &str =
+            r#"{"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}}],"edges":[]}"#;
+        let _upstream = mockito::mock("GET", path)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", r#""v1""#)
+            .with_body(GRAPH)
+            .create();
+
+        let (runs, impure_runs) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let mut plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}{}", mockito::server_url(), path))
+            )?],
+            None,
+        )?;
+        plugins.push(new_plugin!(InternalPluginWrapper(RunCounter(runs.clone()))));
+        plugins.push(new_plugin!(InternalPluginWrapper(ImpureRunCounter(
+            impure_runs.clone()
+        ))));
+        let chain = PluginChain::new(plugins);
+        let plugins = chain.current();
+        let cache = EtagCache::new(DEFAULT_MAX_ENTRIES);
+
+        for _ in 0..2 {
+            let rendered = rt.block_on(graph::render_graph_revalidated(
+                &chain,
+                &plugins,
+                params(&[("channel", "stable")]),
+                Some(&cache),
+                None,
+                false,
+            ))?;
+            assert_eq!(rendered.json, GRAPH);
+        }
+
+        // All plugins run for every request, as the graph is never cached.
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(impure_runs.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.get("v1", &key(&[("channel", "stable")])), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn swap_clears_caches() {
+        let cache = Arc::new(ResponseCache::new(Duration::from_secs(60), 10, vec![]));
+        let etag_cache = Arc::new(EtagCache::new(10));
+        let chain =
+            PluginChain::new(vec![]).with_caches(Some(cache.clone()), Some(etag_cache.clone()));
+        let stable = key(&[("channel", "stable")]);
+
+        let old_plugins = chain.current();
+        cache.insert(stable.clone(), "stable".to_string());
+        etag_cache.insert("v1", stable.clone(), "stable".to_string());
+
+        chain.swap(new_plugins!(InternalPluginWrapper(RunCounter(Arc::new(
+            AtomicUsize::new(0)
+        )))));
+        assert_eq!(cache.get(&stable), None);
+        assert_eq!(etag_cache.get("v1", &stable), None);
+
+        // Output of the previous plugins, finished after the swap, isn't cached.
+        chain.if_current(&old_plugins, || {
+            cache.insert(stable.clone(), "stable".to_string())
+        });
+        assert_eq!(cache.get(&stable), None);
+
+        chain.if_current(&chain.current(), || {
+            cache.insert(stable.clone(), "stable".to_string())
+        });
+        assert_eq!(cache.get(&stable), Some("stable".to_string()));
+    }
+
+    #[test]
+    fn prewarmed_request_hits_cache() -> Fallible<()> {
+        let mut rt = common_init();
+
+        static GRAPH: &str =
+            r#"{"nodes":[{"version":"1.0.0","payload":"image/1.0.0","metadata":{}}],"edges":[]}"#;
+        let upstream = mockito::mock("GET", "/prewarm")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(GRAPH)
+            .expect(1)
+            .create();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/prewarm", mockito::server_url()))
+            )?],
+            None,
+        )?;
+        let client_params: BTreeMap<String, String> =
+            key(&[("channel", "stable"), ("arch", "amd64")])
+                .into_iter()
+                .collect();
+        let state = AppState {
+            plugins: crate::reload::PluginChain::new(plugins),
+            cache: Some(Arc::new(ResponseCache::new(
+                Duration::from_secs(60),
+                DEFAULT_MAX_ENTRIES,
+                vec![client_params.clone()],
+            ))),
+            ..Default::default()
+        };
+
+        rt.block_on(prewarm(state.clone(), client_params));
+
+        let http_req =
+            actix_web::test::TestRequest::with_uri("/v1/graph?arch=amd64&channel=stable").header(
+                http::header::ACCEPT,
+                http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+            );
+        let resp = rt.block_on(call_index(http_req, actix_web::web::Data::new(state)))?;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(CACHE_KEY_HEADER).unwrap(),
+            "arch=amd64&channel=stable"
+        );
+
+        let body = match resp.body() {
+            actix_web::dev::ResponseBody::Body(actix_web::dev::Body::Bytes(bytes)) => {
+                std::str::from_utf8(&bytes)?.to_owned()
+            }
+            _ => bail!("expected byte body"),
+        };
+        assert_eq!(body, GRAPH);
+
+        // Only the pre-warm reached the upstream.
+        upstream.assert();
+
+        Ok(())
+    }
+}
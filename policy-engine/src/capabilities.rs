@@ -0,0 +1,227 @@
+//! Client capability detection.
+//!
+//! Clients may announce their version, either via a query parameter or in
+//! their User-Agent. The version is mapped to a set of capability flags, which
+//! are passed to the plugins as reserved parameters (e.g.
+//! `__capability.conditional_edges=true`).
+
+use actix_web::http::{header, HeaderMap};
+use cincinnati::plugins::Parameters;
+use regex::Regex;
+use semver::{Version, VersionReq};
+use std::collections::BTreeSet;
+
+/// Prefix of the plugin parameters carrying client capabilities.
+pub static CAPABILITY_PARAM_PREFIX: &str = "__capability.";
+
+/// Capability flags enabled for a range of client versions.
+#[derive(Clone, Debug)]
+pub struct CapabilityRule {
+    /// Client versions this rule applies to.
+    pub versions: VersionReq,
+    /// Capability flags enabled for matching clients.
+    pub flags: BTreeSet<String>,
+}
+
+/// Mapping from client versions to capability flags.
+#[derive(Clone, Debug, Default)]
+pub struct CapabilitySettings {
+    /// Query parameter carrying the client version.
+    pub version_param: Option<String>,
+    /// Pattern extracting the client version from the User-Agent.
+    ///
+    /// The first capture group is used if present, the whole match otherwise.
+    pub user_agent_pattern: Option<Regex>,
+    /// Rules evaluated against the client version.
+    pub rules: Vec<CapabilityRule>,
+    /// Capability flags for clients with an unknown or unmatched version.
+    pub default_flags: BTreeSet<String>,
+}
+
+impl CapabilitySettings {
+    /// Determine the client version, from the query parameters first and the User-Agent second.
+    pub fn client_version(&self, headers: &HeaderMap, params: &Parameters) -> Option<Version> {
+        let from_param = self
+            .version_param
+            .as_ref()
+            .and_then(|param| params.client(param))
+            .map(String::as_str);
+
+        let from_user_agent = || {
+            let pattern = self.user_agent_pattern.as_ref()?;
+            let user_agent = headers.get(header::USER_AGENT)?.to_str().ok()?;
+            let captures = pattern.captures(user_agent)?;
+            captures
+                .get(1)
+                .or_else(|| captures.get(0))
+                .map(|m| m.as_str())
+        };
+
+        let raw = from_param.or_else(from_user_agent)?;
+        match Version::parse(raw.trim_start_matches('v')) {
+            Ok(version) => Some(version),
+            Err(e) => {
+                debug!("unparseable client version '{}': {}", raw, e);
+                None
+            }
+        }
+    }
+
+    /// Return the capability flags enabled for the given client version.
+    ///
+    /// Flags of all matching rules are combined. Clients with an unknown
+    /// version, or which match no rule, get the default flags.
+    pub fn flags_for(&self, version: Option<&Version>) -> BTreeSet<String> {
+        let version = match version {
+            Some(version) => version,
+            None => return self.default_flags.clone(),
+        };
+
+        let matching: Vec<&CapabilityRule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.versions.matches(version))
+            .collect();
+
+        if matching.is_empty() {
+            return self.default_flags.clone();
+        }
+
+        matching
+            .into_iter()
+            .flat_map(|rule| rule.flags.iter().cloned())
+            .collect()
+    }
+
+    /// All flags known to this mapping.
+    fn known_flags(&self) -> BTreeSet<String> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.flags.iter())
+            .chain(self.default_flags.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Annotate the parameters with the capabilities of the client.
+    ///
+    /// Every known flag is set to either `true` or `false`.
+    pub fn apply(&self, headers: &HeaderMap, params: &mut Parameters) {
+        let enabled = self.flags_for(self.client_version(headers, params).as_ref());
+        for flag in self.known_flags() {
+            let value = enabled.contains(&flag).to_string();
+            params.insert_internal(format!("{}{}", CAPABILITY_PARAM_PREFIX, flag), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use actix_web::HttpRequest;
+    use std::collections::HashMap;
+
+    fn settings() -> CapabilitySettings {
+        CapabilitySettings {
+            version_param: Some("version".to_string()),
+            user_agent_pattern: Some(Regex::new(r"ClusterVersionOperator/(\S+)").unwrap()),
+            rules: vec![
+                CapabilityRule {
+                    versions: VersionReq::parse(">=4.9.0").unwrap(),
+                    flags: vec!["conditional_edges".to_string()].into_iter().collect(),
+                },
+                CapabilityRule {
+                    versions: VersionReq::parse(">=4.6.0").unwrap(),
+                    flags: vec!["channels_metadata".to_string()].into_iter().collect(),
+                },
+            ],
+            default_flags: vec!["legacy".to_string()].into_iter().collect(),
+        }
+    }
+
+    fn apply(req: &HttpRequest, params: &[(&str, &str)]) -> Parameters {
+        let params: HashMap<String, String> = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut params = Parameters::from_client(params);
+        settings().apply(req.headers(), &mut params);
+        params
+    }
+
+    fn flag(params: &Parameters, name: &str) -> Option<String> {
+        params
+            .internal(&format!("{}{}", CAPABILITY_PARAM_PREFIX, name))
+            .cloned()
+    }
+
+    #[test]
+    fn range_matching() {
+        let req = TestRequest::default().to_http_request();
+
+        let params = apply(&req, &[("version", "4.9.1")]);
+        assert_eq!(flag(&params, "conditional_edges"), Some("true".into()));
+        assert_eq!(flag(&params, "channels_metadata"), Some("true".into()));
+        assert_eq!(flag(&params, "legacy"), Some("false".into()));
+
+        let params = apply(&req, &[("version", "4.7.0")]);
+        assert_eq!(flag(&params, "conditional_edges"), Some("false".into()));
+        assert_eq!(flag(&params, "channels_metadata"), Some("true".into()));
+        assert_eq!(flag(&params, "legacy"), Some("false".into()));
+    }
+
+    #[test]
+    fn user_agent() {
+        let req = TestRequest::default()
+            .header(header::USER_AGENT, "ClusterVersionOperator/4.10.3 (linux)")
+            .to_http_request();
+
+        let params = apply(&req, &[]);
+        assert_eq!(flag(&params, "conditional_edges"), Some("true".into()));
+
+        // The query parameter takes precedence.
+        let params = apply(&req, &[("version", "4.6.0")]);
+        assert_eq!(flag(&params, "conditional_edges"), Some("false".into()));
+        assert_eq!(flag(&params, "channels_metadata"), Some("true".into()));
+    }
+
+    #[test]
+    fn default_bucket() {
+        let req = TestRequest::default()
+            .header(header::USER_AGENT, "curl/7.66.0")
+            .to_http_request();
+
+        for params in &[
+            vec![],
+            vec![("version", "not-a-version")],
+            vec![("version", "4.5.0")],
+        ] {
+            let params = apply(&req, params);
+            assert_eq!(flag(&params, "conditional_edges"), Some("false".into()));
+            assert_eq!(flag(&params, "channels_metadata"), Some("false".into()));
+            assert_eq!(flag(&params, "legacy"), Some("true".into()));
+        }
+    }
+
+    #[test]
+    fn reserved_keys_not_spoofable() {
+        let req = TestRequest::default().to_http_request();
+
+        let params = apply(
+            &req,
+            &[
+                ("version", "4.5.0"),
+                ("channel", "stable-4.5"),
+                ("__capability.conditional_edges", "true"),
+                ("__capability.unknown", "true"),
+                ("__other", "value"),
+            ],
+        );
+
+        assert_eq!(flag(&params, "conditional_edges"), Some("false".into()));
+        assert_eq!(flag(&params, "unknown"), None);
+        assert_eq!(params.get("__other"), None);
+        assert_eq!(params.get("channel"), Some(&"stable-4.5".to_string()));
+    }
+}
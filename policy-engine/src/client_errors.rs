@@ -0,0 +1,461 @@
+//! History of the error responses served to each client.
+//!
+//! When enabled, the last few error responses of the main service are kept
+//! per client, so that support cases about a given cluster can be correlated
+//! with what the service actually returned. Clients are identified by the
+//! SHA-256 hash of their `id` parameter, and only this hash is ever stored.
+//!
+//! The history is bounded in the number of clients, evicting the least
+//! recently recorded one, in the number of errors per client, and in the age
+//! of errors. Successful responses and requests without an id are never
+//! recorded.
+//!
+//! The history of a client is served by `/debug/client-errors/{hashed_id}` on
+//! the status service, which requires the debug token.
+
+use crate::debug::{DebugError, DebugState};
+use actix_web::dev::ServiceResponse;
+use actix_web::{web, HttpRequest, HttpResponse};
+use commons::access_log::redact_params;
+use commons::GraphError;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
+
+/// Client parameter identifying a client.
+pub static CLIENT_ID_PARAM: &str = "id";
+
+/// Request header carrying the request id.
+pub static REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Default maximum number of recorded errors per client.
+pub const DEFAULT_MAX_ERRORS: usize = 5;
+
+/// Default retention of recorded errors.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+
+/// Maximum length of recorded values, in characters.
+const MAX_VALUE_LEN: usize = 256;
+
+/// Maximum number of recorded client parameters per error.
+const MAX_PARAMS: usize = 32;
+
+/// Client error history settings.
+#[derive(Clone, Debug, SmartDefault)]
+pub struct ClientErrorsSettings {
+    /// Maximum number of clients with a recorded history, recording is disabled if unset.
+    pub max_clients: Option<usize>,
+    /// Maximum number of recorded errors per client.
+    #[default(DEFAULT_MAX_ERRORS)]
+    pub max_errors: usize,
+    /// Retention of recorded errors.
+    #[default(DEFAULT_RETENTION)]
+    pub retention: Duration,
+}
+
+/// Error response served to a client.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ClientError {
+    /// Error kind, as in the response body.
+    pub kind: String,
+    /// Response status code.
+    pub status: u16,
+    /// Error message, as in the response body.
+    pub value: String,
+    /// Time of the response, in RFC 3339 format.
+    pub timestamp: String,
+    /// Client parameters, without the client id and with sensitive values redacted.
+    pub params: BTreeMap<String, String>,
+    /// Request id, if the request carried one.
+    pub request_id: Option<String>,
+}
+
+/// Recorded error, along with the time it was recorded.
+#[derive(Debug)]
+struct RecordedError {
+    error: ClientError,
+    recorded: Instant,
+}
+
+/// Errors recorded for a client, oldest first.
+#[derive(Debug)]
+struct ClientHistory {
+    errors: VecDeque<RecordedError>,
+    /// Logical time of the last recorded error, for eviction.
+    last_recorded: u64,
+}
+
+/// Recorded histories, by hashed client id.
+#[derive(Debug, Default)]
+struct Histories {
+    clients: HashMap<String, ClientHistory>,
+    /// Logical clock, ticking on each recorded error.
+    clock: u64,
+}
+
+/// Bounded history of the errors served to each client, shared by all workers.
+#[derive(Debug)]
+pub struct ClientErrors {
+    max_clients: usize,
+    max_errors: usize,
+    retention: Duration,
+    redacted_params: HashSet<String>,
+    histories: Mutex<Histories>,
+}
+
+impl ClientErrors {
+    /// Create the history, if enabled by the given settings.
+    pub fn from_settings(settings: &ClientErrorsSettings) -> Option<Self> {
+        Some(Self::new(
+            settings.max_clients?,
+            settings.max_errors,
+            settings.retention,
+        ))
+    }
+
+    /// Create an empty history.
+    pub fn new(max_clients: usize, max_errors: usize, retention: Duration) -> Self {
+        Self {
+            max_clients,
+            max_errors,
+            retention,
+            redacted_params: HashSet::new(),
+            histories: Mutex::new(Histories::default()),
+        }
+    }
+
+    /// Redact the values of the given client parameters.
+    pub fn with_redacted_params(mut self, redacted_params: HashSet<String>) -> Self {
+        self.redacted_params = redacted_params;
+        self
+    }
+
+    /// Return the hashed form of a client id, as used to look up its history.
+    pub fn hash_id(client_id: &str) -> String {
+        format!("{:x}", Sha256::digest(client_id.as_bytes()))
+    }
+
+    /// Record the given response, if it is an error served to a client with an id.
+    pub fn record_response<B>(&self, response: &ServiceResponse<B>) {
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return;
+        }
+
+        // The client id is looked up before redaction, as it may well be redacted.
+        let request = response.request();
+        let client_id = match form_urlencoded::parse(request.query_string().as_bytes())
+            .find(|(key, _)| key == CLIENT_ID_PARAM)
+        {
+            Some((_, client_id)) if !client_id.is_empty() => client_id,
+            _ => return,
+        };
+        let mut params = redact_params(request.query_string(), &self.redacted_params);
+        params.remove(CLIENT_ID_PARAM);
+
+        let error = response.response().error();
+        let (kind, value) = match error.and_then(|e| e.as_error::<GraphError>()) {
+            Some(graph_error) => (graph_error.kind(), graph_error.value()),
+            None => (
+                status
+                    .canonical_reason()
+                    .unwrap_or("error")
+                    .to_lowercase()
+                    .replace(' ', "_"),
+                error.map(ToString::to_string).unwrap_or_default(),
+            ),
+        };
+
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(truncate);
+
+        self.record(
+            &Self::hash_id(&client_id),
+            ClientError {
+                kind,
+                status: status.as_u16(),
+                value: truncate(&value),
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                params: params
+                    .into_iter()
+                    .take(MAX_PARAMS)
+                    .map(|(key, value)| (truncate(&key), truncate(&value)))
+                    .collect(),
+                request_id,
+            },
+        );
+    }
+
+    /// Record an error for the client with the given hashed id.
+    ///
+    /// If the history is full, clients whose errors all expired are evicted
+    /// first, and then the least recently recorded client.
+    pub fn record(&self, hashed_id: &str, error: ClientError) {
+        let mut histories = match self.histories.lock() {
+            Ok(histories) => histories,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        histories.clock += 1;
+        let clock = histories.clock;
+
+        let clients = &mut histories.clients;
+        if !clients.contains_key(hashed_id) && clients.len() >= self.max_clients {
+            let retention = self.retention;
+            clients.retain(|_, history| {
+                history
+                    .errors
+                    .back()
+                    .map_or(false, |last| last.recorded.elapsed() < retention)
+            });
+
+            if clients.len() >= self.max_clients {
+                let evicted = clients
+                    .iter()
+                    .min_by_key(|(_, history)| history.last_recorded)
+                    .map(|(evicted, _)| evicted.clone());
+                if let Some(evicted) = evicted {
+                    trace!("evicting error history of client '{}'", evicted);
+                    clients.remove(&evicted);
+                }
+            }
+        }
+
+        let history = clients
+            .entry(hashed_id.to_string())
+            .or_insert_with(|| ClientHistory {
+                errors: VecDeque::with_capacity(self.max_errors),
+                last_recorded: clock,
+            });
+        history.last_recorded = clock;
+        history.errors.push_back(RecordedError {
+            error,
+            recorded: Instant::now(),
+        });
+        while history.errors.len() > self.max_errors {
+            history.errors.pop_front();
+        }
+    }
+
+    /// Return the unexpired errors recorded for the client with the given hashed id, oldest first.
+    pub fn get(&self, hashed_id: &str) -> Vec<ClientError> {
+        let histories = match self.histories.lock() {
+            Ok(histories) => histories,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        histories
+            .clients
+            .get(&hashed_id.to_lowercase())
+            .map(|history| {
+                history
+                    .errors
+                    .iter()
+                    .filter(|recorded| recorded.recorded.elapsed() < self.retention)
+                    .map(|recorded| recorded.error.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Truncate a recorded value to `MAX_VALUE_LEN` characters.
+fn truncate(value: &str) -> String {
+    value.chars().take(MAX_VALUE_LEN).collect()
+}
+
+/// Serve the errors recorded for a client, by hashed client id.
+pub(crate) async fn serve(
+    req: HttpRequest,
+    hashed_id: web::Path<String>,
+    state: web::Data<DebugState>,
+    client_errors: web::Data<ClientErrors>,
+) -> Result<HttpResponse, DebugError> {
+    state.authorize(&req)?;
+
+    Ok(HttpResponse::Ok().json(client_errors.get(&hashed_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::common_init;
+    use actix_service::Service;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::App;
+    use futures::TryFutureExt;
+
+    fn error(kind: &str) -> ClientError {
+        ClientError {
+            kind: kind.to_string(),
+            status: 500,
+            value: String::new(),
+            timestamp: String::new(),
+            params: BTreeMap::new(),
+            request_id: None,
+        }
+    }
+
+    fn kinds(client_errors: &ClientErrors, hashed_id: &str) -> Vec<String> {
+        client_errors
+            .get(hashed_id)
+            .into_iter()
+            .map(|error| error.kind)
+            .collect()
+    }
+
+    /// Fail requests for the "broken" channel.
+    async fn handler(req: HttpRequest) -> Result<HttpResponse, GraphError> {
+        if req.query_string().contains("channel=broken") {
+            Err(GraphError::InvalidParams("broken channel".to_string()))
+        } else {
+            Ok(HttpResponse::Ok().finish())
+        }
+    }
+
+    #[test]
+    fn hashed_ids() {
+        assert_eq!(
+            ClientErrors::hash_id("cluster-a"),
+            "34ab3e1c8c468878c75341efcf8fd3cd47540aa2b05cfca2ef4da32fd7dd0c36"
+        );
+    }
+
+    #[test]
+    fn records_errors_per_client() {
+        let mut rt = common_init();
+        let client_errors = web::Data::new(
+            ClientErrors::new(10, 5, DEFAULT_RETENTION).with_redacted_params(
+                vec!["id".to_string(), "token".to_string()]
+                    .into_iter()
+                    .collect(),
+            ),
+        );
+
+        let recorder = client_errors.clone();
+        let queries = [
+            ("id=cluster-a&channel=broken&token=s3cr3t", Some("req-1")),
+            ("id=cluster-a&channel=stable-4.6", Some("req-2")),
+            ("id=cluster-b&channel=broken", None),
+            ("id=cluster-a&channel=broken", Some("req-3")),
+            ("id=cluster-c&channel=stable-4.6", None),
+            ("channel=broken", None),
+        ];
+        rt.block_on(async {
+            let app = App::new()
+                .wrap_fn(move |req, srv| {
+                    let recorder = recorder.clone();
+                    srv.call(req).map_ok(move |response| {
+                        recorder.record_response(&response);
+                        response
+                    })
+                })
+                .service(web::resource("/v1/graph").route(web::get().to(handler)));
+            let mut svc = actix_web::test::init_service(app).await;
+
+            for (query, request_id) in &queries {
+                let mut req =
+                    actix_web::test::TestRequest::get().uri(&format!("/v1/graph?{}", query));
+                if let Some(request_id) = request_id {
+                    req = req.header(REQUEST_ID_HEADER, *request_id);
+                }
+                actix_web::test::call_service(&mut svc, req.to_request()).await;
+            }
+        });
+
+        let errors_a = client_errors.get(&ClientErrors::hash_id("cluster-a"));
+        assert_eq!(errors_a.len(), 2);
+        assert_eq!(errors_a[0].kind, "invalid_params");
+        assert_eq!(errors_a[0].status, 400);
+        assert_eq!(errors_a[0].value, "broken channel");
+        assert_eq!(errors_a[0].request_id.as_deref(), Some("req-1"));
+        assert_eq!(
+            errors_a[0].params,
+            vec![("channel", "broken"), ("token", "<redacted>")]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        );
+        assert_eq!(errors_a[1].request_id.as_deref(), Some("req-3"));
+
+        assert_eq!(
+            kinds(&client_errors, &ClientErrors::hash_id("cluster-b")),
+            vec!["invalid_params"]
+        );
+
+        // Successful requests and requests without an id are never recorded.
+        assert!(client_errors
+            .get(&ClientErrors::hash_id("cluster-c"))
+            .is_empty());
+        let histories = client_errors.histories.lock().unwrap();
+        assert_eq!(histories.clients.len(), 2);
+        assert!(histories
+            .clients
+            .keys()
+            .all(|hashed_id| !hashed_id.contains("cluster")));
+    }
+
+    #[test]
+    fn eviction() {
+        let client_errors = ClientErrors::new(2, 2, DEFAULT_RETENTION);
+
+        for kind in &["a1", "a2", "a3"] {
+            client_errors.record("a", error(kind));
+        }
+        assert_eq!(kinds(&client_errors, "a"), vec!["a2", "a3"]);
+
+        // The least recently recorded client is evicted.
+        client_errors.record("b", error("b1"));
+        client_errors.record("a", error("a4"));
+        client_errors.record("c", error("c1"));
+        assert!(client_errors.get("b").is_empty());
+        assert_eq!(kinds(&client_errors, "a"), vec!["a3", "a4"]);
+        assert_eq!(kinds(&client_errors, "c"), vec!["c1"]);
+
+        // Expired errors are not served.
+        let client_errors = ClientErrors::new(2, 2, Duration::from_secs(0));
+        client_errors.record("a", error("a1"));
+        assert!(client_errors.get("a").is_empty());
+    }
+
+    #[test]
+    fn serve_requires_token() {
+        let mut rt = common_init();
+        let client_errors = ClientErrors::new(10, 5, DEFAULT_RETENTION);
+        let hashed_id = ClientErrors::hash_id("cluster-a");
+        client_errors.record(&hashed_id, error("failed_plugin_execution"));
+
+        rt.block_on(async {
+            let app = App::new()
+                .app_data(web::Data::new(DebugState {
+                    token: "secret".to_string(),
+                    plugins: Default::default(),
+                    plugin_allowlist: Default::default(),
+                }))
+                .app_data(web::Data::new(client_errors))
+                .service(
+                    web::resource("/debug/client-errors/{hashed_id}").route(web::get().to(serve)),
+                );
+            let mut svc = actix_web::test::init_service(app).await;
+            let path = format!("/debug/client-errors/{}", hashed_id);
+
+            let req = actix_web::test::TestRequest::get().uri(&path).to_request();
+            let response = actix_web::test::call_service(&mut svc, req).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            let req = actix_web::test::TestRequest::get()
+                .uri(&path)
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .to_request();
+            let response = actix_web::test::call_service(&mut svc, req).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let json: serde_json::Value = actix_web::test::read_body_json(response).await;
+            assert_eq!(json[0]["kind"], "failed_plugin_execution");
+            assert_eq!(json.as_array().map(Vec::len), Some(1));
+        });
+    }
+}
@@ -1,9 +1,10 @@
 //! Command-line options for policy-engine.
 
-use super::options;
 use super::AppSettings;
+use super::{file, options};
 use commons::prelude_errors::*;
 use commons::MergeOptions;
+use std::path::PathBuf;
 
 /// CLI configuration flags, top-level.
 #[derive(Debug, StructOpt)]
@@ -16,6 +17,14 @@ pub struct CliOptions {
     #[structopt(short = "c")]
     pub config_path: Option<String>,
 
+    /// Path to the detached OpenPGP signature of the configuration file
+    #[structopt(long = "config_signature_path", parse(from_os_str))]
+    pub config_signature_path: Option<PathBuf>,
+
+    /// Path to the OpenPGP public key verifying the configuration file signature
+    #[structopt(long = "config_public_key_path", parse(from_os_str))]
+    pub config_public_key_path: Option<PathBuf>,
+
     // Status service options
     #[structopt(flatten)]
     pub service: options::ServiceOptions,
@@ -31,6 +40,24 @@ pub struct CliOptions {
     // Cincinnati upstream options
     #[structopt(flatten)]
     pub upstream_cincinnati: options::UpCincinnatiOptions,
+
+    // Embedded graph-builder options
+    #[structopt(flatten)]
+    pub upstream_embedded: options::UpEmbeddedOptions,
+}
+
+impl CliOptions {
+    /// Read the configuration file, if any.
+    ///
+    /// If a signature and public key are configured, the file is only read
+    /// if its signature is valid.
+    pub fn read_config_file(&self) -> Fallible<Option<file::FileOptions>> {
+        commons::signature::read_config_file(
+            self.config_path.as_deref(),
+            self.config_signature_path.clone(),
+            self.config_public_key_path.clone(),
+        )
+    }
 }
 
 impl MergeOptions<CliOptions> for AppSettings {
@@ -45,6 +72,7 @@ impl MergeOptions<CliOptions> for AppSettings {
         self.try_merge(Some(opts.service))?;
         self.try_merge(Some(opts.status))?;
         self.try_merge(Some(opts.upstream_cincinnati))?;
+        self.try_merge(Some(opts.upstream_embedded))?;
 
         Ok(())
     }
@@ -73,6 +101,24 @@ mod tests {
         assert_eq!(svc_port_cli.service.port, Some(9999));
     }
 
+    #[test]
+    fn cli_trusted_proxies() {
+        let args = vec!["argv0", "--service.trusted_proxies", "10.0.0.0/8,fd00::/8"];
+        let cli = CliOptions::from_iter_safe(args).unwrap();
+
+        let mut settings = AppSettings::default();
+        assert!(settings.trusted_proxies.is_empty());
+
+        settings.try_merge(cli).unwrap();
+        assert_eq!(
+            settings.trusted_proxies,
+            vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+        );
+
+        let invalid_args = vec!["argv0", "--service.trusted_proxies", "10.0.0.1"];
+        CliOptions::from_iter_safe(invalid_args).unwrap_err();
+    }
+
     #[test]
     fn cli_merge_settings() {
         let upstream = "https://example.com";
@@ -2,11 +2,18 @@
 
 use super::options;
 use super::AppSettings;
+use crate::capabilities::CapabilityRule;
+use crate::injection::InjectionRule;
+use crate::maintenance::{MaintenanceRequest, MaintenanceWindow};
+use crate::watchdog::WatchdogAction;
 use commons::de::de_loglevel;
+use commons::http::ResponseHeaders;
 use commons::prelude_errors::*;
 use commons::MergeOptions;
-use std::io::Read;
-use std::{fs, io, path};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path;
+use std::time::Duration;
 
 /// TOML configuration, top-level.
 #[derive(Debug, Deserialize)]
@@ -26,6 +33,30 @@ pub struct FileOptions {
 
     /// Status service options.
     pub status: Option<options::StatusOptions>,
+
+    /// Client capabilities options.
+    pub capabilities: Option<CapabilitiesOptions>,
+
+    /// Plugin parameters injection options.
+    pub parameters: Option<ParametersOptions>,
+
+    /// Maintenance window options.
+    pub maintenance: Option<MaintenanceRequest>,
+
+    /// Response cache options.
+    pub cache: Option<CacheOptions>,
+
+    /// Watchdog options.
+    pub watchdog: Option<WatchdogOptions>,
+
+    /// Client error history options.
+    pub client_errors: Option<ClientErrorsOptions>,
+
+    /// Headers of successful graph responses, by name.
+    pub response_headers: Option<BTreeMap<String, String>>,
+
+    /// Headers of error responses, by name, replacing the defaults.
+    pub error_response_headers: Option<BTreeMap<String, String>>,
 }
 
 impl FileOptions {
@@ -34,21 +65,7 @@ impl FileOptions {
     where
         P: AsRef<path::Path>,
     {
-        let cfg_file = fs::File::open(&cfg_path).context(format!(
-            "failed to open config path {:?}",
-            cfg_path.as_ref()
-        ))?;
-        let mut bufrd = io::BufReader::new(cfg_file);
-
-        let mut content = vec![];
-        bufrd.read_to_end(&mut content)?;
-        let cfg = toml::from_slice(&content).context(format!(
-            "failed to parse config file {}:\n{}",
-            cfg_path.as_ref().display(),
-            std::str::from_utf8(&content).unwrap_or("file not decodable")
-        ))?;
-
-        Ok(cfg)
+        commons::signature::read_verified_filepath(cfg_path, None)
     }
 }
 
@@ -60,6 +77,23 @@ impl MergeOptions<Option<FileOptions>> for AppSettings {
             self.try_merge(file.service)?;
             self.try_merge(file.status)?;
             self.try_merge(file.upstream)?;
+            self.try_merge(file.capabilities)?;
+            self.try_merge(file.parameters)?;
+            self.try_merge(file.cache)?;
+            self.try_merge(file.watchdog)?;
+            self.try_merge(file.client_errors)?;
+            if let Some(headers) = file.response_headers {
+                self.response_headers =
+                    ResponseHeaders::try_from_map(&headers).context("invalid response_headers")?;
+            }
+            if let Some(headers) = file.error_response_headers {
+                self.error_response_headers = ResponseHeaders::try_from_map(&headers)
+                    .context("invalid error_response_headers")?;
+            }
+            if let Some(maintenance) = file.maintenance {
+                self.maintenance = MaintenanceWindow::try_from(maintenance)
+                    .context("invalid maintenance window")?;
+            }
         }
         Ok(())
     }
@@ -85,12 +119,233 @@ pub struct UpstreamOptions {
 
     /// Cincinnati upstream options.
     pub cincinnati: Option<options::UpCincinnatiOptions>,
+
+    /// Embedded graph-builder options.
+    pub embedded: Option<options::UpEmbeddedOptions>,
 }
 
 impl MergeOptions<Option<UpstreamOptions>> for AppSettings {
     fn try_merge(&mut self, opts: Option<UpstreamOptions>) -> Fallible<()> {
         if let Some(upstream) = opts {
             self.try_merge(upstream.cincinnati)?;
+            self.try_merge(upstream.embedded)?;
+        }
+        Ok(())
+    }
+}
+
+/// Options for client capability detection.
+#[derive(Debug, Deserialize)]
+pub struct CapabilitiesOptions {
+    /// Query parameter carrying the client version.
+    pub version_param: Option<String>,
+
+    /// Regular expression extracting the client version from the User-Agent.
+    pub user_agent_pattern: Option<String>,
+
+    /// Capability flags for clients with an unknown or unmatched version.
+    pub default: Option<Vec<String>>,
+
+    /// Capability flags per range of client versions.
+    pub rules: Option<Vec<CapabilityRuleOptions>>,
+}
+
+/// Capability flags for a range of client versions.
+#[derive(Debug, Deserialize)]
+pub struct CapabilityRuleOptions {
+    /// Semver range of client versions.
+    pub versions: String,
+
+    /// Capability flags enabled for matching clients.
+    pub flags: Vec<String>,
+}
+
+impl MergeOptions<Option<CapabilitiesOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<CapabilitiesOptions>) -> Fallible<()> {
+        if let Some(capabilities) = opts {
+            assign_if_some!(self.capabilities.version_param, capabilities.version_param);
+            if let Some(pattern) = capabilities.user_agent_pattern {
+                let regex = regex::Regex::new(&pattern)
+                    .context(format!("invalid user_agent_pattern '{}'", pattern))?;
+                self.capabilities.user_agent_pattern = Some(regex);
+            }
+            if let Some(default) = capabilities.default {
+                self.capabilities.default_flags = default.into_iter().collect();
+            }
+            if let Some(rules) = capabilities.rules {
+                self.capabilities.rules = rules
+                    .into_iter()
+                    .map(|rule| -> Fallible<CapabilityRule> {
+                        let versions = semver::VersionReq::parse(&rule.versions)
+                            .context(format!("invalid capability versions '{}'", rule.versions))?;
+                        Ok(CapabilityRule {
+                            versions,
+                            flags: rule.flags.into_iter().collect(),
+                        })
+                    })
+                    .collect::<Fallible<_>>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options for injecting plugin parameters.
+#[derive(Debug, Deserialize)]
+pub struct ParametersOptions {
+    /// Injection rules, applied in order.
+    pub rules: Option<Vec<InjectionRuleOptions>>,
+}
+
+/// Parameters injected into requests matching given parameter values.
+#[derive(Debug, Deserialize)]
+pub struct InjectionRuleOptions {
+    /// Parameter values which must all be present for the rule to match.
+    #[serde(default, rename = "match")]
+    pub conditions: BTreeMap<String, String>,
+
+    /// Parameters injected into matching requests.
+    pub inject: BTreeMap<String, String>,
+
+    /// Whether injected parameters replace the ones sent by the client.
+    #[serde(default, rename = "override")]
+    pub override_existing: bool,
+}
+
+impl MergeOptions<Option<ParametersOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<ParametersOptions>) -> Fallible<()> {
+        if let Some(rules) = opts.and_then(|parameters| parameters.rules) {
+            self.param_injection.rules = rules
+                .into_iter()
+                .map(|rule| -> Fallible<InjectionRule> {
+                    ensure!(!rule.inject.is_empty(), "injection rule without parameters");
+                    Ok(InjectionRule {
+                        conditions: rule.conditions,
+                        inject: rule.inject,
+                        override_existing: rule.override_existing,
+                    })
+                })
+                .collect::<Fallible<_>>()?;
+        }
+        Ok(())
+    }
+}
+
+/// Options for the response cache.
+#[derive(Debug, Deserialize)]
+pub struct CacheOptions {
+    /// Time-to-live of cached responses, in seconds.
+    pub ttl_secs: Option<u64>,
+
+    /// Maximum number of cached responses.
+    pub max_entries: Option<usize>,
+
+    /// Client parameter sets to pre-warm the cache for.
+    pub prewarm: Option<Vec<BTreeMap<String, String>>>,
+
+    /// Cache key construction options.
+    pub key: Option<CacheKeyOptions>,
+
+    /// Whether to cache graphs for as long as the upstream ETag is unchanged.
+    pub upstream_etag: Option<bool>,
+}
+
+/// Options for the construction of cache keys.
+#[derive(Debug, Deserialize)]
+pub struct CacheKeyOptions {
+    /// Parameters participating in the key, in key order.
+    pub include: Option<Vec<String>>,
+
+    /// Parameters whose values are lowercased.
+    pub lowercase: Option<Vec<String>>,
+
+    /// Values of parameters missing from a request.
+    pub defaults: Option<BTreeMap<String, String>>,
+}
+
+impl MergeOptions<Option<CacheOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<CacheOptions>) -> Fallible<()> {
+        if let Some(cache) = opts {
+            if let Some(ttl_secs) = cache.ttl_secs {
+                self.cache.ttl = Some(Duration::from_secs(ttl_secs));
+            }
+            assign_if_some!(self.cache.max_entries, cache.max_entries);
+            assign_if_some!(self.cache.prewarm, cache.prewarm);
+            assign_if_some!(self.cache.upstream_etag, cache.upstream_etag);
+            if let Some(key) = cache.key {
+                assign_if_some!(self.cache.key.include, key.include);
+                if let Some(lowercase) = key.lowercase {
+                    self.cache.key.lowercase = lowercase.into_iter().collect();
+                }
+                assign_if_some!(self.cache.key.defaults, key.defaults);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options for the watchdog of the main service.
+#[derive(Debug, Deserialize)]
+pub struct WatchdogOptions {
+    /// Interval between probes, in seconds.
+    pub interval_secs: Option<u64>,
+
+    /// Timeout of a probe, in milliseconds.
+    pub timeout_millis: Option<u64>,
+
+    /// Number of consecutive failed probes triggering the action.
+    pub failure_threshold: Option<u32>,
+
+    /// Action taken once the threshold is reached.
+    pub action: Option<WatchdogAction>,
+}
+
+impl MergeOptions<Option<WatchdogOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<WatchdogOptions>) -> Fallible<()> {
+        if let Some(watchdog) = opts {
+            if let Some(interval_secs) = watchdog.interval_secs {
+                self.watchdog.interval = Some(Duration::from_secs(interval_secs));
+            }
+            if let Some(timeout_millis) = watchdog.timeout_millis {
+                self.watchdog.timeout = Duration::from_millis(timeout_millis);
+            }
+            assign_if_some!(self.watchdog.failure_threshold, watchdog.failure_threshold);
+            assign_if_some!(self.watchdog.action, watchdog.action);
+        }
+        Ok(())
+    }
+}
+
+/// Options for the history of the errors served to each client.
+#[derive(Debug, Deserialize)]
+pub struct ClientErrorsOptions {
+    /// Maximum number of clients with a recorded history.
+    pub max_clients: Option<usize>,
+
+    /// Maximum number of recorded errors per client.
+    pub max_errors: Option<usize>,
+
+    /// Retention of recorded errors, in seconds.
+    pub retention_secs: Option<u64>,
+}
+
+impl MergeOptions<Option<ClientErrorsOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<ClientErrorsOptions>) -> Fallible<()> {
+        if let Some(client_errors) = opts {
+            if let Some(max_clients) = client_errors.max_clients {
+                ensure!(
+                    max_clients > 0,
+                    "client_errors.max_clients must be positive"
+                );
+                self.client_errors.max_clients = Some(max_clients);
+            }
+            if let Some(max_errors) = client_errors.max_errors {
+                ensure!(max_errors > 0, "client_errors.max_errors must be positive");
+                self.client_errors.max_errors = max_errors;
+            }
+            if let Some(retention_secs) = client_errors.retention_secs {
+                self.client_errors.retention = Duration::from_secs(retention_secs);
+            }
         }
         Ok(())
     }
@@ -124,6 +379,91 @@ mod tests {
         assert_eq!(settings.status_port, 2222);
     }
 
+    #[test]
+    fn toml_max_connections() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.max_connections, None);
+
+        let toml_input = "service.max_connections = 1000";
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        assert_eq!(
+            file_opts.service.as_ref().unwrap().max_connections,
+            Some(1000)
+        );
+
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.max_connections, Some(1000));
+    }
+
+    #[test]
+    fn toml_on_empty_plugin_chain() {
+        use cincinnati::plugins::catalog::EmptyChain;
+
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.on_empty_plugin_chain, EmptyChain::Warn);
+
+        let file_opts: FileOptions =
+            toml::from_str("service.on_empty_plugin_chain = 'fail'").unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.on_empty_plugin_chain, EmptyChain::Fail);
+
+        // The default plugins are used if no policy is configured.
+        assert!(!settings
+            .validate_and_build_plugins(None)
+            .unwrap()
+            .is_empty());
+
+        let file_opts: FileOptions =
+            toml::from_str("service.on_empty_plugin_chain = 'serve'").unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.on_empty_plugin_chain, EmptyChain::Serve);
+
+        toml::from_str::<FileOptions>("service.on_empty_plugin_chain = 'ignore'").unwrap_err();
+    }
+
+    #[test]
+    fn toml_mandatory_client_parameters_file() {
+        use std::io::Write;
+
+        let mut params_file = tempfile::NamedTempFile::new().unwrap();
+        params_file.write_all(b"channel\narch, id\n\n").unwrap();
+
+        let toml_input = format!(
+            "[service]\nmandatory_client_parameters = ['version']\nmandatory_client_parameters_file = '{}'",
+            params_file.path().display()
+        );
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+
+        let mut settings = AppSettings::default();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        // Parameters are kept in configuration order.
+        assert_eq!(
+            settings.mandatory_client_parameters,
+            vec!["version", "channel", "arch", "id"]
+        );
+    }
+
+    #[test]
+    fn toml_mandatory_client_parameters_file_missing() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("missing");
+
+        let toml_input = format!(
+            "[service]\nmandatory_client_parameters_file = '{}'",
+            path.display()
+        );
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+
+        let mut settings = AppSettings::default();
+        let err = settings.try_merge(Some(file_opts)).unwrap_err();
+        assert!(
+            err.to_string().contains(&path.display().to_string()),
+            "unexpected error: {}",
+            err
+        );
+    }
+
     #[test]
     fn toml_sample_config() {
         use super::FileOptions;
@@ -198,4 +538,228 @@ mod tests {
         let plugins = settings.validate_and_build_plugins(None).unwrap();
         assert_eq!(plugins, expected);
     }
+
+    #[test]
+    fn toml_plugin_allowlist() {
+        let policy = r#"
+            [[policy]]
+            name = "channel-filter"
+        "#;
+
+        let mut settings = AppSettings::default();
+        let toml_input = format!(
+            "[service]\nplugin_allowlist = [\"channel-filter\", \"node-remove\"]\n{}",
+            policy
+        );
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(settings.validate_and_build_plugins(None).unwrap().len(), 1);
+
+        let mut settings = AppSettings::default();
+        let toml_input = format!(
+            "[service]\nplugin_allowlist = [\"node-remove\"]\n{}",
+            policy
+        );
+        let file_opts: FileOptions = toml::from_str(&toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        let err = settings.validate_and_build_plugins(None).unwrap_err();
+        assert!(err.to_string().contains("'channel-filter' is not allowed"));
+    }
+
+    #[test]
+    fn toml_capabilities() {
+        let mut settings = AppSettings::default();
+
+        let toml_input = r#"
+            [capabilities]
+            version_param = "version"
+            user_agent_pattern = 'ClusterVersionOperator/(\S+)'
+            default = ["legacy"]
+
+            [[capabilities.rules]]
+            versions = ">=4.9.0"
+            flags = ["conditional_edges"]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let capabilities = &settings.capabilities;
+        assert_eq!(capabilities.version_param, Some("version".to_string()));
+        assert!(capabilities.user_agent_pattern.is_some());
+        assert_eq!(capabilities.rules.len(), 1);
+        assert!(capabilities.rules[0]
+            .versions
+            .matches(&semver::Version::new(4, 9, 0)));
+        assert!(capabilities.default_flags.contains("legacy"));
+
+        let invalid_range = r#"
+            [[capabilities.rules]]
+            versions = "not a range"
+            flags = []
+        "#;
+        let file_opts: FileOptions = toml::from_str(invalid_range).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap_err();
+    }
+
+    #[test]
+    fn toml_parameters() {
+        let mut settings = AppSettings::default();
+
+        let toml_input = r#"
+            [[parameters.rules]]
+            match = { channel = "candidate" }
+            inject = { experiment = "foo" }
+
+            [[parameters.rules]]
+            inject = { variant = "b" }
+            override = true
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let rules = &settings.param_injection.rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].conditions["channel"], "candidate");
+        assert_eq!(rules[0].inject["experiment"], "foo");
+        assert!(!rules[0].override_existing);
+        assert!(rules[1].conditions.is_empty());
+        assert!(rules[1].override_existing);
+
+        let empty_rule = r#"
+            [[parameters.rules]]
+            match = { channel = "candidate" }
+            inject = {}
+        "#;
+        let file_opts: FileOptions = toml::from_str(empty_rule).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap_err();
+    }
+
+    #[test]
+    fn toml_cache() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.cache.ttl, None);
+
+        let toml_input = r#"
+            [cache]
+            ttl_secs = 60
+            max_entries = 100
+            upstream_etag = true
+
+            [[cache.prewarm]]
+            channel = "stable-4.6"
+            arch = "amd64"
+
+            [[cache.prewarm]]
+            channel = "fast-4.6"
+            arch = "amd64"
+
+            [cache.key]
+            include = ["channel", "arch"]
+            lowercase = ["arch"]
+            defaults = { arch = "amd64" }
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let cache = &settings.cache;
+        assert_eq!(cache.ttl, Some(std::time::Duration::from_secs(60)));
+        assert_eq!(cache.max_entries, 100);
+        assert!(cache.upstream_etag);
+        assert_eq!(cache.prewarm.len(), 2);
+        assert_eq!(cache.prewarm[0]["channel"], "stable-4.6");
+        assert_eq!(cache.prewarm[1]["arch"], "amd64");
+        assert_eq!(cache.key.include, vec!["channel", "arch"]);
+        assert!(cache.key.lowercase.contains("arch"));
+        assert_eq!(cache.key.defaults["arch"], "amd64");
+    }
+
+    #[test]
+    fn toml_client_errors() {
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.client_errors.max_clients, None);
+
+        let toml_input = r#"
+            [client_errors]
+            max_clients = 100
+            retention_secs = 3600
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let client_errors = &settings.client_errors;
+        assert_eq!(client_errors.max_clients, Some(100));
+        assert_eq!(
+            client_errors.max_errors,
+            crate::client_errors::DEFAULT_MAX_ERRORS
+        );
+        assert_eq!(
+            client_errors.retention,
+            std::time::Duration::from_secs(3600)
+        );
+
+        let file_opts: FileOptions = toml::from_str("[client_errors]\nmax_errors = 0").unwrap();
+        settings.try_merge(Some(file_opts)).unwrap_err();
+    }
+
+    #[test]
+    fn toml_watchdog() {
+        use crate::watchdog::WatchdogAction;
+        use std::time::Duration;
+
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.watchdog.interval, None);
+
+        let toml_input = r#"
+            [watchdog]
+            interval_secs = 30
+            timeout_millis = 500
+            action = "exit"
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+
+        let watchdog = &settings.watchdog;
+        assert_eq!(watchdog.interval, Some(Duration::from_secs(30)));
+        assert_eq!(watchdog.timeout, Duration::from_millis(500));
+        assert_eq!(watchdog.failure_threshold, 3);
+        assert_eq!(watchdog.action, WatchdogAction::Exit);
+
+        let toml_input = "[watchdog]\naction = 'restart'";
+        assert!(toml::from_str::<FileOptions>(toml_input).is_err());
+    }
+
+    #[test]
+    fn toml_response_headers() {
+        use commons::http::ResponseHeaders;
+
+        let mut settings = AppSettings::default();
+        assert_eq!(settings.response_headers, ResponseHeaders::default());
+
+        let toml_input = r#"
+            [response_headers]
+            "Cache-Control" = "public, max-age=60"
+            "Surrogate-Control" = "max-age=300"
+
+            [error_response_headers]
+        "#;
+        let file_opts: FileOptions = toml::from_str(toml_input).unwrap();
+        settings.try_merge(Some(file_opts)).unwrap();
+        assert_eq!(
+            settings.response_headers,
+            ResponseHeaders::from_static(&[
+                ("cache-control", "public, max-age=60"),
+                ("surrogate-control", "max-age=300"),
+            ])
+        );
+        assert_eq!(settings.error_response_headers, ResponseHeaders::default());
+
+        for reserved in &[
+            "[response_headers]\n\"Content-Type\" = \"text/plain\"",
+            "[error_response_headers]\n\"Content-Length\" = \"0\"",
+        ] {
+            let file_opts: FileOptions = toml::from_str(reserved).unwrap();
+            let err = settings.try_merge(Some(file_opts)).unwrap_err();
+            assert!(format!("{:#}", err).contains("reserved"), "{:#}", err);
+        }
+    }
 }
@@ -1,10 +1,18 @@
 //! Options shared by CLI and TOML.
 
 use super::AppSettings;
+use cincinnati::plugins::catalog::{EmptyChain, PluginAllowlist};
+use commons::http::IpNet;
+use commons::i18n::ErrorCatalogs;
 use commons::prelude_errors::*;
-use commons::{de_path_prefix, parse_params_set, parse_path_prefix, MergeOptions};
+use commons::{
+    de_path_prefix, extend_params_list, parse_params_list, parse_params_set, parse_path_prefix,
+    read_params_list, MergeOptions,
+};
 use std::collections::HashSet;
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// Status service options.
 #[derive(Debug, Deserialize, Serialize, StructOpt)]
@@ -16,6 +24,10 @@ pub struct StatusOptions {
     /// Port to which the status service will bind
     #[structopt(name = "status_port", long = "status.port")]
     pub port: Option<u16>,
+
+    /// Path to a file with the bearer token for the debugging and admin endpoints, which are disabled if unset
+    #[structopt(long = "status.debug_token_path")]
+    pub debug_token_path: Option<PathBuf>,
 }
 
 impl MergeOptions<Option<StatusOptions>> for AppSettings {
@@ -23,6 +35,19 @@ impl MergeOptions<Option<StatusOptions>> for AppSettings {
         if let Some(status) = opts {
             assign_if_some!(self.status_address, status.address);
             assign_if_some!(self.status_port, status.port);
+            if let Some(path) = status.debug_token_path {
+                let token = std::fs::read_to_string(&path).context(format!(
+                    "failed to read debug token from '{}'",
+                    path.display()
+                ))?;
+                let token = token.trim();
+                ensure!(
+                    !token.is_empty(),
+                    "empty debug token in '{}'",
+                    path.display()
+                );
+                self.debug_token = Some(token.to_string());
+            }
         }
         Ok(())
     }
@@ -39,21 +64,108 @@ pub struct ServiceOptions {
     #[structopt(name = "service_port", long = "service.port")]
     pub port: Option<u16>,
 
+    /// Unix domain socket path on which the server will listen, instead of the address and port
+    #[structopt(long = "service.socket_path")]
+    pub socket_path: Option<PathBuf>,
+
+    /// Port to which the gRPC service will bind, disabled if unset (requires the 'grpc' feature)
+    #[structopt(long = "service.grpc_port")]
+    pub grpc_port: Option<u16>,
+
     /// Namespace prefix for all service endpoints (e.g. '/<prefix>/v1/graph')
     #[structopt(long = "service.path_prefix", parse(from_str = parse_path_prefix))]
     #[serde(default = "Option::default", deserialize_with = "de_path_prefix")]
     pub path_prefix: Option<String>,
 
-    /// Comma-separated set of mandatory client parameters
+    /// Comma-separated list of mandatory client parameters
     #[structopt(
         long = "service.mandatory_client_parameters",
-        parse(from_str = parse_params_set)
+        parse(from_str = parse_params_list)
     )]
-    pub mandatory_client_parameters: Option<HashSet<String>>,
+    pub mandatory_client_parameters: Option<Vec<String>>,
+
+    /// Path to a file with the set of mandatory client parameters, separated by newlines or commas
+    #[structopt(long = "service.mandatory_client_parameters_file")]
+    pub mandatory_client_parameters_file: Option<PathBuf>,
 
     /// Optional tracing endpoint
     #[structopt(name = "tracing_endpoint", long = "service.tracing_endpoint")]
     pub tracing_endpoint: Option<String>,
+
+    /// Log each request of the main service, as JSON at info level on the 'access_log' target
+    #[structopt(long = "service.access_log")]
+    pub access_log: Option<bool>,
+
+    /// Comma-separated set of client parameters whose values are redacted in the access log
+    #[structopt(
+        long = "service.access_log_redacted_params",
+        parse(from_str = parse_params_set)
+    )]
+    pub access_log_redacted_params: Option<HashSet<String>>,
+
+    /// Comma-separated set of request headers forwarded to the plugins
+    #[structopt(long = "service.forwarded_headers", parse(from_str = parse_params_set))]
+    pub forwarded_headers: Option<HashSet<String>>,
+
+    /// Maximum number of concurrent connections per worker
+    #[structopt(long = "service.max_connections")]
+    pub max_connections: Option<usize>,
+
+    /// Maximum size of a serialized graph response, in bytes
+    #[structopt(long = "service.max_graph_size")]
+    pub max_graph_size: Option<usize>,
+
+    /// Expose the warnings recorded by the plugins as a top-level 'warnings' array of graphs
+    #[structopt(long = "service.expose_warnings")]
+    pub expose_warnings: Option<bool>,
+
+    /// Serve the adjacency summary of graphs on '/v1/graph/adjacency', for debugging
+    #[structopt(long = "service.expose_adjacency")]
+    pub expose_adjacency: Option<bool>,
+
+    /// Maximum age of the fetched graph, in seconds, beyond which the service is not ready
+    #[structopt(long = "service.max_graph_age_secs")]
+    pub max_graph_age_secs: Option<u64>,
+
+    /// Maximum number of requests per minute force-sampled by a debug id header
+    #[structopt(long = "service.tracing_debug_sampling")]
+    pub tracing_debug_sampling: Option<u32>,
+
+    /// Additional header carrying a debug id, besides 'jaeger-debug-id'
+    #[structopt(long = "service.tracing_debug_header")]
+    pub tracing_debug_header: Option<String>,
+
+    /// Comma-separated list of request headers recorded as span tags
+    #[structopt(long = "service.tracing_tag_headers", parse(from_str = parse_params_list))]
+    pub tracing_tag_headers: Option<Vec<String>>,
+
+    /// Maximum length of a span tag value in bytes, beyond which it is truncated
+    #[structopt(long = "service.tracing_tag_max_value_len")]
+    pub tracing_tag_max_value_len: Option<usize>,
+
+    /// Maximum number of tags recorded on a request span
+    #[structopt(long = "service.tracing_tag_max_count")]
+    pub tracing_tag_max_count: Option<usize>,
+
+    /// Size of the span tags in bytes above which a span is counted as oversized
+    #[structopt(long = "service.tracing_oversize_span_bytes")]
+    pub tracing_oversize_span_bytes: Option<usize>,
+
+    /// Comma-separated list of CIDRs of proxies trusted to report the client address
+    #[structopt(long = "service.trusted_proxies", use_delimiter = true)]
+    pub trusted_proxies: Option<Vec<IpNet>>,
+
+    /// Handling of an empty plugin chain, either 'warn', 'fail' or 'serve'
+    #[structopt(long = "service.on_empty_plugin_chain")]
+    pub on_empty_plugin_chain: Option<EmptyChain>,
+
+    /// Comma-separated set of plugins allowed in the plugin chain, all by default
+    #[structopt(long = "service.plugin_allowlist", parse(from_str = parse_params_set))]
+    pub plugin_allowlist: Option<HashSet<String>>,
+
+    /// Directory of JSON message catalogs localizing error messages, named by language (e.g. 'fr.json')
+    #[structopt(long = "service.error_catalogs_dir")]
+    pub error_catalogs_dir: Option<PathBuf>,
 }
 
 impl MergeOptions<Option<ServiceOptions>> for AppSettings {
@@ -61,10 +173,53 @@ impl MergeOptions<Option<ServiceOptions>> for AppSettings {
         if let Some(service) = opts {
             assign_if_some!(self.address, service.address);
             assign_if_some!(self.port, service.port);
+            assign_if_some!(self.socket_path, service.socket_path);
+            assign_if_some!(self.grpc_port, service.grpc_port);
             assign_if_some!(self.path_prefix, service.path_prefix);
             assign_if_some!(self.tracing_endpoint, service.tracing_endpoint);
+            assign_if_some!(self.tracing_debug_sampling, service.tracing_debug_sampling);
+            assign_if_some!(self.tracing_debug_header, service.tracing_debug_header);
+            if let Some(headers) = service.tracing_tag_headers {
+                self.tracing_tags = self.tracing_tags.clone().with_headers(headers);
+            }
+            assign_if_some!(
+                self.tracing_tags.max_value_len,
+                service.tracing_tag_max_value_len
+            );
+            assign_if_some!(self.tracing_tags.max_tags, service.tracing_tag_max_count);
+            assign_if_some!(
+                self.tracing_tags.oversize_bytes,
+                service.tracing_oversize_span_bytes
+            );
+            assign_if_some!(self.max_connections, service.max_connections);
+            assign_if_some!(self.max_graph_size, service.max_graph_size);
+            assign_if_some!(self.expose_warnings, service.expose_warnings);
+            assign_if_some!(self.expose_adjacency, service.expose_adjacency);
+            if let Some(secs) = service.max_graph_age_secs {
+                self.max_graph_age = Some(Duration::from_secs(secs));
+            }
+            assign_if_some!(self.trusted_proxies, service.trusted_proxies);
+            assign_if_some!(self.on_empty_plugin_chain, service.on_empty_plugin_chain);
+            if let Some(names) = service.plugin_allowlist {
+                self.plugin_allowlist = PluginAllowlist::from_names(names);
+            }
+            assign_if_some!(self.access_log, service.access_log);
+            if let Some(params) = service.access_log_redacted_params {
+                self.access_log_redacted_params.extend(params);
+            }
+            if let Some(headers) = service.forwarded_headers {
+                self.forwarded_headers
+                    .extend(headers.iter().map(|name| name.to_lowercase()));
+            }
             if let Some(params) = service.mandatory_client_parameters {
-                self.mandatory_client_parameters.extend(params);
+                extend_params_list(&mut self.mandatory_client_parameters, params);
+            }
+            if let Some(path) = service.mandatory_client_parameters_file {
+                let params = read_params_list(path)?;
+                extend_params_list(&mut self.mandatory_client_parameters, params);
+            }
+            if let Some(dir) = service.error_catalogs_dir {
+                self.error_catalogs = ErrorCatalogs::read_dir(dir)?;
             }
         }
         Ok(())
@@ -89,6 +244,23 @@ impl MergeOptions<Option<UpCincinnatiOptions>> for AppSettings {
     }
 }
 
+/// Options for an embedded graph-builder.
+#[derive(Debug, Deserialize, StructOpt)]
+pub struct UpEmbeddedOptions {
+    /// Path to the configuration file of a graph-builder to run in-process, replacing the upstream
+    #[structopt(long = "upstream.embedded.config_path")]
+    pub config_path: Option<PathBuf>,
+}
+
+impl MergeOptions<Option<UpEmbeddedOptions>> for AppSettings {
+    fn try_merge(&mut self, opts: Option<UpEmbeddedOptions>) -> Fallible<()> {
+        if let Some(embedded) = opts {
+            assign_if_some!(self.embedded_config_path, embedded.config_path);
+        }
+        Ok(())
+    }
+}
+
 /// Parse a URI from a string.
 pub fn uri_from_str<S>(input: S) -> Fallible<hyper::Uri>
 where
@@ -1,13 +1,24 @@
 //! Application settings for policy-engine.
 
-use super::{cli, file};
-use cincinnati::plugins::catalog::{self, PluginSettings};
+use super::cli;
+use crate::cache::CacheSettings;
+use crate::capabilities::CapabilitySettings;
+use crate::client_errors::ClientErrorsSettings;
+use crate::injection::ParamInjection;
+use crate::maintenance::MaintenanceWindow;
+use crate::watchdog::WatchdogSettings;
+use cincinnati::plugins::catalog::{self, EmptyChain, PluginAllowlist, PluginSettings};
 use cincinnati::plugins::BoxedPlugin;
+use commons::http::{IpNet, ResponseHeaders, DEFAULT_ERROR_RESPONSE_HEADERS};
+use commons::i18n::ErrorCatalogs;
 use commons::prelude_errors::*;
+use commons::tracing::TagPolicy;
 use custom_debug_derive::Debug as CustomDebug;
 use hyper::Uri;
 use std::collections::HashSet;
 use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 /// Default URL to upstream graph provider.
@@ -24,6 +35,11 @@ pub struct AppSettings {
     #[default(Uri::from_static(DEFAULT_UPSTREAM_URL))]
     pub upstream: Uri,
 
+    /// Configuration file of a graph-builder to run in-process.
+    ///
+    /// If set, the graph scraped in-process replaces fetching from `upstream`.
+    pub embedded_config_path: Option<PathBuf>,
+
     /// Listening address for the main service.
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub address: IpAddr,
@@ -32,6 +48,16 @@ pub struct AppSettings {
     #[default(8081)]
     pub port: u16,
 
+    /// Unix domain socket path for the main service.
+    ///
+    /// If set, the main service listens on this socket instead of `address` and `port`.
+    pub socket_path: Option<PathBuf>,
+
+    /// Listening port for the gRPC service, on the main service address.
+    ///
+    /// The gRPC service is disabled if unset.
+    pub grpc_port: Option<u16>,
+
     /// Listening address for the status service.
     #[default(IpAddr::V4(Ipv4Addr::LOCALHOST))]
     pub status_address: IpAddr,
@@ -40,17 +66,103 @@ pub struct AppSettings {
     #[default(9081)]
     pub status_port: u16,
 
+    /// Bearer token for the debugging and admin endpoints of the status service.
+    ///
+    /// The debugging and admin endpoints are disabled if unset.
+    #[debug(skip)]
+    pub debug_token: Option<String>,
+
     /// Endpoints namespace for the main service.
     pub path_prefix: String,
 
     /// Plugin settings.
     pub plugin_settings: Vec<Box<dyn PluginSettings>>,
 
+    /// Handling of an empty plugin chain.
+    pub on_empty_plugin_chain: EmptyChain,
+
+    /// Plugins allowed in the plugin chain, on top of those allowed by the build.
+    pub plugin_allowlist: PluginAllowlist,
+
     /// Required client parameters for the main service.
-    pub mandatory_client_parameters: HashSet<String>,
+    ///
+    /// Parameters are kept in configuration order.
+    pub mandatory_client_parameters: Vec<String>,
 
     /// Jaeger host and port for tracing support
     pub tracing_endpoint: Option<String>,
+
+    /// Maximum number of requests per minute force-sampled by a debug id.
+    ///
+    /// Forced sampling is disabled if unset.
+    pub tracing_debug_sampling: Option<u32>,
+
+    /// Additional header carrying a debug id, besides `jaeger-debug-id`.
+    pub tracing_debug_header: Option<String>,
+
+    /// Policy for the tags recorded on request spans.
+    pub tracing_tags: TagPolicy,
+
+    /// Whether to log each request of the main service.
+    pub access_log: bool,
+
+    /// Client parameters whose values are redacted in the access log.
+    pub access_log_redacted_params: HashSet<String>,
+
+    /// Request headers forwarded to the plugins, by lowercase name.
+    pub forwarded_headers: HashSet<String>,
+
+    /// Maximum number of concurrent connections per worker for the main service.
+    ///
+    /// The actix default is used if unset.
+    pub max_connections: Option<usize>,
+
+    /// Maximum size of a serialized graph response, in bytes.
+    ///
+    /// Graph responses are unbounded if unset.
+    pub max_graph_size: Option<usize>,
+
+    /// Whether to expose the warnings recorded by the plugins in graph responses.
+    pub expose_warnings: bool,
+
+    /// Whether to serve the adjacency summary of graphs, for debugging.
+    pub expose_adjacency: bool,
+
+    /// Headers set on successful graph responses.
+    pub response_headers: ResponseHeaders,
+
+    /// Headers set on error responses.
+    #[default(ResponseHeaders::from_static(DEFAULT_ERROR_RESPONSE_HEADERS))]
+    pub error_response_headers: ResponseHeaders,
+
+    /// Message catalogs localizing error responses, by language.
+    pub error_catalogs: ErrorCatalogs,
+
+    /// Maximum age of the fetched graph, beyond which the service is not ready.
+    ///
+    /// Readiness doesn't depend on the graph age if unset.
+    pub max_graph_age: Option<Duration>,
+
+    /// Proxies trusted to report the client address in forwarding headers.
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Mapping from client versions to capability flags.
+    pub capabilities: CapabilitySettings,
+
+    /// Rules injecting plugin parameters into matching requests.
+    pub param_injection: ParamInjection,
+
+    /// Maintenance window, during which graph requests are rejected.
+    pub maintenance: MaintenanceWindow,
+
+    /// Response cache settings.
+    pub cache: CacheSettings,
+
+    /// Watchdog settings.
+    pub watchdog: WatchdogSettings,
+
+    /// History of the errors served to each client.
+    pub client_errors: ClientErrorsSettings,
 }
 
 impl AppSettings {
@@ -63,10 +175,7 @@ impl AppSettings {
 
         // Source options.
         let cli_opts = cli::CliOptions::from_args();
-        let file_opts = match &cli_opts.config_path {
-            Some(ref path) => Some(file::FileOptions::read_filepath(path)?),
-            None => None,
-        };
+        let file_opts = cli_opts.read_config_file()?;
 
         // Combine options into a single config.
         let mut cfg = defaults;
@@ -90,15 +199,123 @@ impl AppSettings {
             &self.plugin_settings
         };
 
-        catalog::build_plugins(plugin_settings, registry)
+        let plugins =
+            catalog::build_allowed_plugins(plugin_settings, registry, &self.plugin_allowlist)?;
+        self.check_plugin_chain(&plugins)?;
+
+        Ok(plugins)
+    }
+
+    /// Check that the plugin chain may be served.
+    pub fn check_plugin_chain(&self, plugins: &[BoxedPlugin]) -> Fallible<()> {
+        catalog::check_chain(plugins, self.on_empty_plugin_chain)
     }
 
     /// Validate and build runtime settings.
     fn try_validate(self) -> Fallible<Self> {
-        if self.address == self.status_address && self.port == self.status_port {
+        if self.socket_path.is_none()
+            && self.address == self.status_address
+            && self.port == self.status_port
+        {
             bail!("main and status service configured with the same address and port");
         }
 
+        if let Some(grpc_port) = self.grpc_port {
+            ensure!(
+                cfg!(feature = "grpc"),
+                "gRPC service configured, but not built with the 'grpc' feature"
+            );
+            ensure!(
+                grpc_port != self.port
+                    && (self.address != self.status_address || grpc_port != self.status_port),
+                "gRPC service configured with the same address and port as another service"
+            );
+        }
+
+        if self.max_connections == Some(0) {
+            bail!("unexpected zero max_connections");
+        }
+
+        if self.tracing_tags.max_value_len == 0 {
+            bail!("unexpected zero tracing_tag_max_value_len");
+        }
+
+        if self.tracing_tags.max_tags == 0 {
+            bail!("unexpected zero tracing_tag_max_count");
+        }
+
+        if self.max_graph_size == Some(0) {
+            bail!("unexpected zero max_graph_size");
+        }
+
+        if self.max_graph_age == Some(Duration::from_secs(0)) {
+            bail!("unexpected zero max_graph_age");
+        }
+
+        if self.cache.ttl == Some(Duration::from_secs(0)) {
+            bail!("unexpected zero cache ttl");
+        }
+
+        if self.cache.max_entries == 0 {
+            bail!("unexpected zero cache max_entries");
+        }
+
+        if !self.cache.prewarm.is_empty() && self.cache.ttl.is_none() {
+            bail!("cache pre-warming configured without cache ttl");
+        }
+
+        let key_rules = &self.cache.key;
+        let mut included = HashSet::new();
+        for param in &key_rules.include {
+            ensure!(
+                included.insert(param),
+                "cache key parameter '{}' included twice",
+                param
+            );
+        }
+        if !key_rules.include.is_empty() {
+            for param in key_rules.lowercase.iter().chain(key_rules.defaults.keys()) {
+                ensure!(
+                    included.contains(param),
+                    "cache key rule for parameter '{}' which is not included",
+                    param
+                );
+            }
+        }
+
+        for client_params in &self.cache.prewarm {
+            let missing: Vec<&String> = self
+                .mandatory_client_parameters
+                .iter()
+                .filter(|param| !client_params.contains_key(*param))
+                .collect();
+            ensure!(
+                missing.is_empty(),
+                "cache pre-warm parameters {:?} missing mandatory parameters {:?}",
+                client_params,
+                missing
+            );
+        }
+
+        if let Some(interval) = self.watchdog.interval {
+            ensure!(
+                interval > Duration::from_secs(0),
+                "unexpected zero watchdog interval"
+            );
+            ensure!(
+                self.watchdog.timeout > Duration::from_secs(0) && self.watchdog.timeout <= interval,
+                "watchdog timeout must be positive and at most the watchdog interval"
+            );
+            ensure!(
+                self.watchdog.failure_threshold > 0,
+                "unexpected zero watchdog failure_threshold"
+            );
+            ensure!(
+                self.socket_path.is_none(),
+                "watchdog configured, but the main service listens on a Unix domain socket"
+            );
+        }
+
         // Deprecates options
         if self.upstream.to_string() != hyper::Uri::default().to_string() {
             warn!("the 'upstream' setting is deprecated and will eventually be removed.");
@@ -124,6 +341,17 @@ impl AppSettings {
                 ),
                 ("key_suffix", "release.channels")
             )?,
+            plugin_config!(
+                ("name", ArchNormalizePlugin::PLUGIN_NAME),
+                (
+                    "key_prefix",
+                    cincinnati::plugins::internal::arch_filter::DEFAULT_KEY_FILTER
+                ),
+                (
+                    "key_suffix",
+                    cincinnati::plugins::internal::arch_filter::DEFAULT_ARCH_KEY
+                )
+            )?,
             plugin_config!(
                 ("name", ArchFilterPlugin::PLUGIN_NAME),
                 (
@@ -146,3 +374,149 @@ impl AppSettings {
         ])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AppSettings;
+    use crate::cache::CacheSettings;
+
+    #[test]
+    fn validate_max_connections() {
+        let settings = AppSettings {
+            max_connections: Some(0),
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let settings = AppSettings {
+            max_connections: Some(1000),
+            ..Default::default()
+        };
+        let settings = AppSettings::try_validate(settings).unwrap();
+        assert_eq!(settings.max_connections, Some(1000));
+
+        let settings = AppSettings::try_validate(AppSettings::default()).unwrap();
+        assert_eq!(settings.max_connections, None);
+    }
+
+    #[test]
+    fn validate_max_graph_size() {
+        let settings = AppSettings {
+            max_graph_size: Some(0),
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let settings = AppSettings::try_validate(AppSettings::default()).unwrap();
+        assert_eq!(settings.max_graph_size, None);
+    }
+
+    #[test]
+    fn validate_max_graph_age() {
+        use std::time::Duration;
+
+        let settings = AppSettings {
+            max_graph_age: Some(Duration::from_secs(0)),
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let settings = AppSettings::try_validate(AppSettings::default()).unwrap();
+        assert_eq!(settings.max_graph_age, None);
+    }
+
+    #[test]
+    fn validate_grpc_port() {
+        let settings = AppSettings {
+            grpc_port: Some(8081),
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let settings = AppSettings {
+            grpc_port: Some(50051),
+            ..Default::default()
+        };
+        let validated = AppSettings::try_validate(settings);
+        if cfg!(feature = "grpc") {
+            assert_eq!(validated.unwrap().grpc_port, Some(50051));
+        } else {
+            validated.unwrap_err();
+        }
+    }
+
+    #[test]
+    fn validate_cache() {
+        use std::time::Duration;
+
+        let settings = AppSettings {
+            cache: CacheSettings {
+                ttl: Some(Duration::from_secs(0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let prewarm = vec![vec![("channel".to_string(), "stable".to_string())]
+            .into_iter()
+            .collect()];
+        let settings = AppSettings {
+            cache: CacheSettings {
+                prewarm: prewarm.clone(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let settings = AppSettings {
+            mandatory_client_parameters: vec!["channel".to_string(), "arch".to_string()]
+                .into_iter()
+                .collect(),
+            cache: CacheSettings {
+                ttl: Some(Duration::from_secs(60)),
+                prewarm: prewarm.clone(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap_err();
+
+        let settings = AppSettings {
+            mandatory_client_parameters: vec!["channel".to_string()].into_iter().collect(),
+            cache: CacheSettings {
+                ttl: Some(Duration::from_secs(60)),
+                prewarm,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap();
+
+        let key_rules = |include: &[&str], lowercase: &[&str]| CacheSettings {
+            key: crate::cache::CacheKeyRules {
+                include: include.iter().map(|param| param.to_string()).collect(),
+                lowercase: lowercase.iter().map(|param| param.to_string()).collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // Duplicated parameters and rules for non-included ones are rejected.
+        for cache in vec![
+            key_rules(&["channel", "channel"], &[]),
+            key_rules(&["channel"], &["arch"]),
+        ] {
+            let settings = AppSettings {
+                cache,
+                ..Default::default()
+            };
+            AppSettings::try_validate(settings).unwrap_err();
+        }
+        let settings = AppSettings {
+            cache: key_rules(&["channel", "arch"], &["arch"]),
+            ..Default::default()
+        };
+        AppSettings::try_validate(settings).unwrap();
+    }
+}
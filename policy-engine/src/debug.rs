@@ -0,0 +1,668 @@
+//! Debugging endpoints, served by the status service.
+//!
+//! These endpoints are only served if a debug token is configured, and
+//! require it as bearer token in the `Authorization` header.
+
+use crate::graph::{adjacency_map, process_graph, process_io, split_graph_sources};
+use crate::reload::PluginChain;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use cincinnati::plugins::catalog::{self, PluginAllowlist};
+use cincinnati::plugins::prelude_plugin_impl::{async_trait, InternalPlugin};
+use cincinnati::plugins::{BoxedPlugin, InternalIO, InternalPluginWrapper, Parameters, Warning};
+use commons::prelude_errors::*;
+use custom_debug_derive::Debug as CustomDebug;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::Instant;
+
+/// Shared state of the debugging endpoints.
+#[derive(Clone, CustomDebug)]
+pub struct DebugState {
+    /// Bearer token expected from clients.
+    #[debug(skip)]
+    pub token: String,
+    /// Live policy plugins.
+    pub plugins: PluginChain,
+    /// Plugins which may be built, for candidate pipelines as for the live one.
+    pub plugin_allowlist: PluginAllowlist,
+}
+
+impl DebugState {
+    /// Make sure the request carries the expected bearer token.
+    pub(crate) fn authorize(&self, req: &HttpRequest) -> Result<(), DebugError> {
+        let credentials = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let mut parts = credentials.trim().splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some(scheme), Some(token))
+                if scheme.eq_ignore_ascii_case("bearer")
+                    && constant_time_eq(token.trim().as_bytes(), self.token.as_bytes()) =>
+            {
+                Ok(())
+            }
+            _ => Err(DebugError::Unauthorized),
+        }
+    }
+}
+
+/// Compare two byte strings in time independent of their content.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Error returned by the debugging endpoints.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DebugError {
+    /// Missing or wrong bearer token.
+    Unauthorized,
+    /// Malformed request body.
+    InvalidRequest(String),
+    /// The candidate pipeline could not be built.
+    CandidateBuild(String),
+    /// The candidate pipeline failed.
+    CandidateRun(String),
+    /// The live pipeline failed.
+    LiveRun(String),
+    /// The live pipeline failed with the simulated overrides.
+    SimulationRun(String),
+}
+
+impl DebugError {
+    /// Return the kind for the error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DebugError::Unauthorized => "unauthorized",
+            DebugError::InvalidRequest(_) => "invalid_request",
+            DebugError::CandidateBuild(_) => "candidate_build_failed",
+            DebugError::CandidateRun(_) => "candidate_run_failed",
+            DebugError::LiveRun(_) => "live_run_failed",
+            DebugError::SimulationRun(_) => "simulation_run_failed",
+        }
+    }
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DebugError::Unauthorized => write!(f, "missing or invalid bearer token"),
+            DebugError::InvalidRequest(msg) => write!(f, "invalid request: {}", msg),
+            DebugError::CandidateBuild(msg) => {
+                write!(f, "failed to build candidate pipeline: {}", msg)
+            }
+            DebugError::CandidateRun(msg) => write!(f, "failed to run candidate pipeline: {}", msg),
+            DebugError::LiveRun(msg) => write!(f, "failed to run live pipeline: {}", msg),
+            DebugError::SimulationRun(msg) => write!(f, "failed to run simulation: {}", msg),
+        }
+    }
+}
+
+impl actix_web::ResponseError for DebugError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DebugError::Unauthorized => StatusCode::UNAUTHORIZED,
+            DebugError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            DebugError::CandidateBuild(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DebugError::CandidateRun(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DebugError::LiveRun(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            DebugError::SimulationRun(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "kind": self.kind(),
+            "value": self.to_string(),
+        }))
+    }
+}
+
+/// Body of a pipeline diff request.
+#[derive(Debug, Deserialize)]
+pub struct PipelineDiffRequest {
+    /// Candidate configuration, in TOML with `[[policy]]` entries as in the configuration file.
+    pub config: String,
+    /// Plugin parameters, passed to both pipelines.
+    ///
+    /// Reserved parameters are passed as internal ones, e.g. to simulate client capabilities.
+    #[serde(default)]
+    pub parameters: Parameters,
+}
+
+/// Candidate configuration.
+#[derive(Debug, Deserialize)]
+struct CandidateConfig {
+    #[serde(default)]
+    policy: Vec<toml::Value>,
+}
+
+/// Difference between two graphs, in terms of release versions.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GraphDiff {
+    /// Releases only present in the candidate graph.
+    pub added_releases: BTreeSet<String>,
+    /// Releases only present in the live graph.
+    pub removed_releases: BTreeSet<String>,
+    /// Edges only present in the candidate graph.
+    pub added_edges: BTreeSet<(String, String)>,
+    /// Edges only present in the live graph.
+    pub removed_edges: BTreeSet<(String, String)>,
+    /// Releases present in both graphs, whose payload or metadata differ.
+    #[serde(default)]
+    pub changed_releases: BTreeSet<String>,
+}
+
+impl GraphDiff {
+    /// Compute the difference from the `live` graph to the `candidate` graph.
+    pub fn new(mut live: cincinnati::Graph, mut candidate: cincinnati::Graph) -> Self {
+        let live_releases = releases(&mut live);
+        let candidate_releases = releases(&mut candidate);
+        let changed_releases = live_releases
+            .iter()
+            .filter(|(version, release)| {
+                candidate_releases
+                    .get(*version)
+                    .map_or(false, |candidate| candidate != *release)
+            })
+            .map(|(version, _)| version.clone())
+            .collect();
+
+        let live = adjacency_map(live);
+        let candidate = adjacency_map(candidate);
+
+        let edges = |adjacency: &BTreeMap<String, BTreeSet<String>>| {
+            adjacency
+                .iter()
+                .flat_map(|(from, next)| next.iter().map(move |to| (from.clone(), to.clone())))
+                .collect::<BTreeSet<_>>()
+        };
+        let (live_edges, candidate_edges) = (edges(&live), edges(&candidate));
+
+        Self {
+            added_releases: candidate
+                .keys()
+                .filter(|version| !live.contains_key(*version))
+                .cloned()
+                .collect(),
+            removed_releases: live
+                .keys()
+                .filter(|version| !candidate.contains_key(*version))
+                .cloned()
+                .collect(),
+            added_edges: candidate_edges.difference(&live_edges).cloned().collect(),
+            removed_edges: live_edges.difference(&candidate_edges).cloned().collect(),
+            changed_releases,
+        }
+    }
+}
+
+/// Return the releases of a graph, by version.
+fn releases(graph: &mut cincinnati::Graph) -> BTreeMap<String, cincinnati::Release> {
+    let ids = graph.find_by_fn_mut(|_| true);
+    ids.into_iter()
+        .filter_map(|(release_id, version)| {
+            let release = graph.find_by_releaseid(&release_id).ok()?.clone();
+            Some((version, release))
+        })
+        .collect()
+}
+
+/// Result of a pipeline diff.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PipelineDiffResponse {
+    /// Difference from the live graph to the candidate graph.
+    pub diff: GraphDiff,
+    /// Time spent running the live pipeline, in seconds.
+    pub live_duration_secs: f64,
+    /// Time spent running the candidate pipeline, in seconds.
+    pub candidate_duration_secs: f64,
+}
+
+/// Build the candidate pipeline.
+///
+/// Plugin metrics are registered to the given registry, so that they don't
+/// clash with the ones of the live pipeline. Only the plugins of `allowlist`
+/// may be built.
+fn build_candidate(
+    config: &str,
+    registry: &prometheus::Registry,
+    allowlist: &PluginAllowlist,
+) -> Fallible<Vec<BoxedPlugin>> {
+    let config: CandidateConfig =
+        toml::from_str(config).context("failed to parse candidate configuration")?;
+    ensure!(
+        !config.policy.is_empty(),
+        "no policy plugins in candidate configuration"
+    );
+
+    let settings = config
+        .policy
+        .into_iter()
+        .map(catalog::deserialize_config)
+        .collect::<Fallible<Vec<_>>>()?;
+    catalog::build_allowed_plugins(&settings, Some(registry), allowlist)
+}
+
+/// Compare the output of the live pipeline with the one of a candidate configuration.
+pub(crate) async fn pipeline_diff(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<DebugState>,
+) -> Result<HttpResponse, DebugError> {
+    state.authorize(&req)?;
+
+    let request: PipelineDiffRequest =
+        serde_json::from_slice(&body).map_err(|e| DebugError::InvalidRequest(e.to_string()))?;
+
+    let registry = prometheus::Registry::new();
+    let candidate = build_candidate(&request.config, &registry, &state.plugin_allowlist)
+        .map_err(|e| DebugError::CandidateBuild(format!("{:#}", e)))?;
+
+    let start = Instant::now();
+    let live_graph = process_graph(state.plugins.current().iter(), request.parameters.clone())
+        .await
+        .map_err(|e| DebugError::LiveRun(e.to_string()))?;
+    let live_duration = start.elapsed();
+
+    let start = Instant::now();
+    let candidate_graph = process_graph(candidate.iter(), request.parameters)
+        .await
+        .map_err(|e| DebugError::CandidateRun(e.to_string()))?;
+    let candidate_duration = start.elapsed();
+
+    Ok(HttpResponse::Ok().json(PipelineDiffResponse {
+        diff: GraphDiff::new(live_graph, candidate_graph),
+        live_duration_secs: live_duration.as_secs_f64(),
+        candidate_duration_secs: candidate_duration.as_secs_f64(),
+    }))
+}
+
+/// Metadata overrides, by release version.
+pub type MetadataOverrides = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Body of a simulation request.
+#[derive(Debug, Deserialize)]
+pub struct SimulateRequest {
+    /// Metadata overrides, by release version, e.g. to add a channel to a release.
+    pub overrides: MetadataOverrides,
+    /// Plugin parameters, passed to both runs.
+    ///
+    /// Reserved parameters are passed as internal ones, e.g. to simulate client capabilities.
+    #[serde(default)]
+    pub parameters: Parameters,
+}
+
+/// Result of a simulation.
+#[derive(Debug, Serialize)]
+pub struct SimulateResponse {
+    /// Graph resulting from the live pipeline with the overrides applied.
+    pub graph: cincinnati::Graph,
+    /// Difference from the unmodified graph to the simulated one.
+    pub diff: GraphDiff,
+    /// Warnings recorded during the simulated run, e.g. for unknown versions.
+    pub warnings: Vec<Warning>,
+}
+
+/// Transient plugin applying metadata overrides to the input graph.
+#[derive(Debug)]
+struct MetadataOverridePlugin(MetadataOverrides);
+
+#[async_trait]
+impl InternalPlugin for MetadataOverridePlugin {
+    const PLUGIN_NAME: &'static str = "simulated-metadata-override";
+    const ABI_VERSION: u32 = 4;
+
+    async fn run_internal(self: &Self, mut io: InternalIO) -> Fallible<InternalIO> {
+        let mut unmatched: BTreeSet<&str> = self.0.keys().map(String::as_str).collect();
+
+        io.graph.find_by_fn_mut(|release| {
+            if let Some(overrides) = self.0.get(release.version()) {
+                unmatched.remove(release.version());
+                if let Some(metadata) = release.get_metadata_mut() {
+                    metadata.extend(overrides.clone());
+                }
+            }
+            false
+        });
+
+        io.warnings.extend(unmatched.into_iter().map(|version| {
+            Warning::new(
+                Self::PLUGIN_NAME,
+                format!("no release with version '{}' to override", version),
+            )
+        }));
+        Ok(io)
+    }
+}
+
+/// Return the live pipeline with the overrides applied after its graph sources.
+///
+/// The overrides then take effect before any filtering.
+fn simulated_pipeline<'a>(
+    plugins: &'a [BoxedPlugin],
+    overrides: &'a BoxedPlugin,
+) -> impl Iterator<Item = &'a BoxedPlugin> + Send + Sync {
+    let (sources, filters) = split_graph_sources(plugins);
+
+    sources
+        .iter()
+        .chain(std::iter::once(overrides))
+        .chain(filters.iter())
+}
+
+/// Preview the graph resulting from hypothetical metadata changes, e.g. a release promotion.
+///
+/// The live pipeline is run as is and with the overrides applied, without
+/// involving the response cache or persisting anything.
+pub(crate) async fn simulate(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<DebugState>,
+) -> Result<HttpResponse, DebugError> {
+    state.authorize(&req)?;
+
+    let request: SimulateRequest =
+        serde_json::from_slice(&body).map_err(|e| DebugError::InvalidRequest(e.to_string()))?;
+    if request.overrides.is_empty() {
+        return Err(DebugError::InvalidRequest("no overrides".to_string()));
+    }
+
+    let plugins = state.plugins.current();
+    let live_graph = process_graph(plugins.iter(), request.parameters.clone())
+        .await
+        .map_err(|e| DebugError::LiveRun(e.to_string()))?;
+
+    let overrides: BoxedPlugin = Box::new(InternalPluginWrapper(MetadataOverridePlugin(
+        request.overrides,
+    )));
+    let simulated = process_io(simulated_pipeline(&plugins, &overrides), request.parameters)
+        .await
+        .map_err(|e| DebugError::SimulationRun(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(SimulateResponse {
+        diff: GraphDiff::new(live_graph, simulated.graph.clone()),
+        graph: simulated.graph,
+        warnings: simulated.warnings,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::common_init;
+    use actix_web::App;
+    use cincinnati::plugins::prelude::*;
+
+    static UPSTREAM_PATH: &str = "/debug-pipeline-diff";
+
+    static SIMULATE_UPSTREAM_PATH: &str = "/debug-simulate";
+
+    static SIMULATE_UPSTREAM_GRAPH: &str = r#"{
+        "nodes": [
+            {"version": "4.6.1", "payload": "image:4.6.1", "metadata": {
+                "io.openshift.upgrades.graph.release.channels": "candidate-4.6,fast-4.6"
+            }},
+            {"version": "4.6.2", "payload": "image:4.6.2", "metadata": {
+                "io.openshift.upgrades.graph.release.channels": "candidate-4.6"
+            }}
+        ],
+        "edges": [[0, 1]]
+    }"#;
+
+    static UPSTREAM_GRAPH: &str = r#"{
+        "nodes": [
+            {"version": "4.5.1", "payload": "image:4.5.1", "metadata": {}},
+            {"version": "4.5.2", "payload": "image:4.5.2", "metadata": {
+                "io.openshift.upgrades.graph.release.remove": "true"
+            }},
+            {"version": "4.5.3", "payload": "image:4.5.3", "metadata": {}}
+        ],
+        "edges": [[0, 1], [0, 2], [1, 2]]
+    }"#;
+
+    fn upstream() -> String {
+        format!("{}{}", mockito::server_url(), UPSTREAM_PATH)
+    }
+
+    fn state() -> Fallible<DebugState> {
+        let plugins = catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", upstream().as_str())
+            )?],
+            None,
+        )?;
+
+        Ok(DebugState {
+            token: "secret".to_string(),
+            plugins: crate::reload::PluginChain::new(plugins),
+            plugin_allowlist: PluginAllowlist::all(),
+        })
+    }
+
+    fn call(token: &str, body: String) -> Fallible<(StatusCode, serde_json::Value)> {
+        call_path("/debug/pipeline-diff", state()?, token, body)
+    }
+
+    fn call_path(
+        path: &str,
+        state: DebugState,
+        token: &str,
+        body: String,
+    ) -> Fallible<(StatusCode, serde_json::Value)> {
+        let mut rt = common_init();
+
+        rt.block_on(async {
+            let app = App::new()
+                .app_data(web::Data::new(state))
+                .service(web::resource("/debug/pipeline-diff").route(web::post().to(pipeline_diff)))
+                .service(web::resource("/debug/simulate").route(web::post().to(simulate)));
+            let mut svc = actix_web::test::init_service(app).await;
+            let req = actix_web::test::TestRequest::post()
+                .uri(path)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .set_payload(body)
+                .to_request();
+            let response = actix_web::test::call_service(&mut svc, req).await;
+            let status = response.status();
+            let json = actix_web::test::read_body_json(response).await;
+            Ok((status, json))
+        })
+    }
+
+    fn request_body(config: &str) -> String {
+        serde_json::json!({
+            "config": config,
+            "parameters": {"channel": "stable-4.5"},
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn diff_names_removed_release() -> Fallible<()> {
+        let _m = mockito::mock("GET", UPSTREAM_PATH)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(UPSTREAM_GRAPH)
+            .create();
+
+        let config = format!(
+            r#"
+                [[policy]]
+                name = "cincinnati-graph-fetch"
+                upstream = "{}"
+
+                [[policy]]
+                name = "node-remove"
+            "#,
+            upstream()
+        );
+        let (status, json) = call("secret", request_body(&config))?;
+        assert_eq!(status, StatusCode::OK, "unexpected response: {}", json);
+
+        let response: PipelineDiffResponse = serde_json::from_value(json)?;
+        let edge = |from: &str, to: &str| (from.to_string(), to.to_string());
+        assert_eq!(
+            response.diff,
+            GraphDiff {
+                removed_releases: vec!["4.5.2".to_string()].into_iter().collect(),
+                removed_edges: vec![edge("4.5.1", "4.5.2"), edge("4.5.2", "4.5.3")]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn candidate_build_error() -> Fallible<()> {
+        let config = r#"
+            [[policy]]
+            name = "no-such-plugin"
+        "#;
+        let (status, json) = call("secret", request_body(config))?;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json["kind"], "candidate_build_failed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn candidate_forbidden_plugin() -> Fallible<()> {
+        let config = format!(
+            r#"
+                [[policy]]
+                name = "cincinnati-graph-fetch"
+                upstream = "{}"
+
+                [[policy]]
+                name = "node-remove"
+            "#,
+            upstream()
+        );
+        let state = DebugState {
+            plugin_allowlist: PluginAllowlist::from_names(vec![
+                CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                ChannelFilterPlugin::PLUGIN_NAME,
+            ]),
+            ..state()?
+        };
+        let (status, json) = call_path(
+            "/debug/pipeline-diff",
+            state,
+            "secret",
+            request_body(&config),
+        )?;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json["kind"], "candidate_build_failed");
+        assert!(
+            json["value"]
+                .as_str()
+                .unwrap_or_default()
+                .contains("plugin 'node-remove' is not allowed"),
+            "unexpected response: {}",
+            json
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unauthorized() -> Fallible<()> {
+        let (status, json) = call("wrong", request_body(""))?;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["kind"], "unauthorized");
+
+        Ok(())
+    }
+
+    #[test]
+    fn simulate_promotion() -> Fallible<()> {
+        let _m = mockito::mock("GET", SIMULATE_UPSTREAM_PATH)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(SIMULATE_UPSTREAM_GRAPH)
+            .create();
+
+        let upstream = format!("{}{}", mockito::server_url(), SIMULATE_UPSTREAM_PATH);
+        let plugins = catalog::build_plugins(
+            &[
+                plugin_config!(
+                    ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                    ("upstream", upstream.as_str())
+                )?,
+                plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?,
+            ],
+            None,
+        )?;
+        let state = DebugState {
+            token: "secret".to_string(),
+            plugins: crate::reload::PluginChain::new(plugins),
+            plugin_allowlist: PluginAllowlist::all(),
+        };
+        let parameters: Parameters = vec![("channel".to_string(), "fast-4.6".to_string())]
+            .into_iter()
+            .collect();
+
+        let body = serde_json::json!({
+            "overrides": {
+                "4.6.2": {"io.openshift.upgrades.graph.release.channels": "candidate-4.6,fast-4.6"},
+                "4.6.99": {"io.openshift.upgrades.graph.release.channels": "fast-4.6"},
+            },
+            "parameters": parameters,
+        });
+        let (status, json) =
+            call_path("/debug/simulate", state.clone(), "secret", body.to_string())?;
+        assert_eq!(status, StatusCode::OK, "unexpected response: {}", json);
+
+        // The promoted release is served in the simulated graph.
+        let versions: Vec<&str> = json["graph"]["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| node["version"].as_str().unwrap())
+            .collect();
+        assert_eq!(versions, vec!["4.6.1", "4.6.2"]);
+
+        let diff: GraphDiff = serde_json::from_value(json["diff"].clone())?;
+        assert_eq!(
+            diff,
+            GraphDiff {
+                added_releases: vec!["4.6.2".to_string()].into_iter().collect(),
+                added_edges: vec![("4.6.1".to_string(), "4.6.2".to_string())]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            json["warnings"][0]["message"],
+            "no release with version '4.6.99' to override"
+        );
+
+        // Normal requests are not affected.
+        let live_graph =
+            common_init().block_on(process_graph(state.plugins.current().iter(), parameters))?;
+        assert_eq!(
+            adjacency_map(live_graph).keys().collect::<Vec<_>>(),
+            vec!["4.6.1"]
+        );
+
+        // Overrides are mandatory.
+        let body = serde_json::json!({ "overrides": {} });
+        let (status, json) = call_path("/debug/simulate", state, "secret", body.to_string())?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["kind"], "invalid_request");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! End-to-end tests of the graph-builder and policy-engine services together.
+//!
+//! These tests are built with the `test-e2e` feature. Each test starts, on
+//! ephemeral ports of the test process:
+//! * a release source, serving the graph declared in `test_fixtures/releases.json`;
+//! * a graph-builder, scraping the release source;
+//! * a policy-engine, fetching the graph from the graph-builder.
+
+use crate::graph::{self, tests::common_init, STALE_HEADER};
+use crate::reload::PluginChain;
+use crate::AppState;
+use actix_service::Service;
+use actix_web::http::header::{ACCEPT, ETAG, IF_NONE_MATCH};
+use actix_web::http::{HeaderMap, StatusCode};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use cincinnati::plugins::prelude::*;
+use commons::extractors::ValidatedQueryConfig;
+use commons::prelude_errors::*;
+use commons::testing::{self, TestService};
+use commons::tracing::{DebugSampling, DEBUG_ID_HEADER};
+use graph_builder::graph::{CancellationToken, RwLock, Scraper, State};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Release declarations served by the release source.
+static RELEASES_FIXTURE: &str = "src/e2e/test_fixtures/releases.json";
+
+/// Serve the release declarations, read from disk on each request.
+async fn serve_releases() -> actix_web::Result<HttpResponse> {
+    let json = std::fs::read_to_string(RELEASES_FIXTURE)?;
+    Ok(HttpResponse::Ok()
+        .content_type(cincinnati::CONTENT_TYPE)
+        .body(json))
+}
+
+/// Response of one of the services.
+struct Response {
+    status: StatusCode,
+    headers: reqwest::header::HeaderMap,
+    body: String,
+}
+
+/// Services of a test, stopped when dropped.
+struct Harness {
+    engine: TestService,
+    builder: TestService,
+    _source: TestService,
+    /// Headers of the requests received by the graph-builder, in order.
+    builder_requests: Arc<Mutex<Vec<HeaderMap>>>,
+    scraper_shutdown: CancellationToken,
+    runtime: Runtime,
+}
+
+impl Harness {
+    /// Start all services, once the graph-builder has scraped its first graph.
+    fn start(serve_stale_on_error: bool) -> Fallible<Self> {
+        let mut runtime = common_init();
+
+        let source = TestService::start("e2e-release-source", |listener| {
+            Ok(
+                HttpServer::new(|| App::new().route("/releases", web::get().to(serve_releases)))
+                    .workers(1)
+                    .listen(listener)?
+                    .run(),
+            )
+        })?;
+
+        // Graph-builder, with its scrape loop on its own thread.
+        let builder_plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &source.url("/releases"))
+            )?],
+            None,
+        )?;
+        let registry: &'static prometheus::Registry =
+            Box::leak(Box::new(commons::metrics::new_registry(Some(
+                graph_builder::config::METRICS_PREFIX.to_string(),
+            ))?));
+        let state = State::new(
+            Default::default(),
+            Arc::new(RwLock::new(false)),
+            Arc::new(RwLock::new(false)),
+            Box::leak(Box::new(builder_plugins)),
+            registry,
+            Default::default(),
+        );
+
+        let scraper_shutdown = CancellationToken::new();
+        {
+            let mut scraper = Scraper::new(state.clone(), Duration::from_millis(100), None);
+            let shutdown = scraper_shutdown.clone();
+            thread::spawn(move || scraper.run_loop(shutdown));
+        }
+
+        let builder_requests = Arc::new(Mutex::new(vec![]));
+        let recorded = builder_requests.clone();
+        let builder = TestService::start("e2e-graph-builder", move |listener| {
+            Ok(HttpServer::new(move || {
+                let recorded = recorded.clone();
+                App::new()
+                    .wrap_fn(move |req, srv| {
+                        if let Ok(mut recorded) = recorded.lock() {
+                            recorded.push(req.headers().clone());
+                        }
+                        srv.call(req)
+                    })
+                    .app_data(web::Data::new(state.clone()))
+                    .configure(graph_builder::status::configure)
+                    .route("/v1/graph", web::get().to(builder::index))
+            })
+            .workers(1)
+            .listen(listener)?
+            .run())
+        })?;
+        runtime.block_on(testing::wait_ready(
+            &builder.url("/readiness"),
+            Duration::from_secs(30),
+        ))?;
+
+        // Policy-engine, fetching from the graph-builder.
+        let engine_plugins = cincinnati::plugins::catalog::build_plugins(
+            &[cincinnati::plugins::catalog::deserialize_config(
+                toml::from_str(&format!(
+                    "name = '{}'\nupstream = '{}'\nserve_stale_on_error = {}",
+                    CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                    builder.url("/v1/graph"),
+                    serve_stale_on_error
+                ))?,
+            )?],
+            None,
+        )?;
+        let app_state = AppState {
+            plugins: PluginChain::new(engine_plugins),
+            debug_sampling: Some(Arc::new(DebugSampling::new(None, 1000))),
+            ..Default::default()
+        };
+        let engine = TestService::start("e2e-policy-engine", move |listener| {
+            Ok(HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::new(app_state.clone()))
+                    .app_data(ValidatedQueryConfig::new(Default::default()))
+                    .route("/v1/graph", web::get().to(graph::index))
+            })
+            .workers(1)
+            .listen(listener)?
+            .run())
+        })?;
+
+        Ok(Self {
+            engine,
+            builder,
+            _source: source,
+            builder_requests,
+            scraper_shutdown,
+            runtime,
+        })
+    }
+
+    /// Request the graph from `url`, with the given additional headers.
+    fn get_graph(&mut self, url: &str, headers: &[(&str, &str)]) -> Fallible<Response> {
+        let mut request = reqwest::Client::new()
+            .get(url)
+            .header(ACCEPT.as_str(), cincinnati::CONTENT_TYPE);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        self.runtime.block_on(async {
+            let response = request.send().await?;
+            Ok::<_, Error>(Response {
+                status: response.status(),
+                headers: response.headers().clone(),
+                body: response.text().await?,
+            })
+        })
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.scraper_shutdown.cancel();
+    }
+}
+
+/// Parse a JSON graph, sorted by version.
+fn sorted_graph(json: &str) -> Fallible<serde_json::Value> {
+    let mut graph: serde_json::Value = serde_json::from_str(json)?;
+    testing::sort_json_graph_by_version(&mut graph);
+    Ok(graph)
+}
+
+#[test]
+fn serve_graph_through_both_hops() -> Fallible<()> {
+    let mut harness = Harness::start(false)?;
+
+    let engine_url = harness.engine.url("/v1/graph");
+    let response = harness.get_graph(&engine_url, &[])?;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        sorted_graph(&response.body)?,
+        sorted_graph(&std::fs::read_to_string(RELEASES_FIXTURE)?)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn propagate_debug_id() -> Fallible<()> {
+    let mut harness = Harness::start(false)?;
+    let engine_url = harness.engine.url("/v1/graph");
+
+    let last_debug_id = |harness: &Harness| {
+        harness
+            .builder_requests
+            .lock()
+            .unwrap()
+            .last()
+            .map(|headers| headers.get(DEBUG_ID_HEADER).cloned())
+    };
+
+    let response = harness.get_graph(&engine_url, &[(DEBUG_ID_HEADER, "e2e-debug-id")])?;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        last_debug_id(&harness),
+        Some(Some(actix_web::http::HeaderValue::from_static(
+            "e2e-debug-id"
+        )))
+    );
+
+    let response = harness.get_graph(&engine_url, &[])?;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(last_debug_id(&harness), Some(None));
+
+    Ok(())
+}
+
+#[test]
+fn conditional_requests() -> Fallible<()> {
+    let mut harness = Harness::start(false)?;
+
+    // The graph-builder answers conditional requests.
+    let builder_url = harness.builder.url("/v1/graph");
+    let response = harness.get_graph(&builder_url, &[])?;
+    assert_eq!(response.status, StatusCode::OK);
+    let etag = response
+        .headers
+        .get(ETAG.as_str())
+        .ok_or_else(|| format_err!("missing ETag"))?
+        .to_str()?
+        .to_string();
+
+    let response = harness.get_graph(&builder_url, &[(IF_NONE_MATCH.as_str(), &etag)])?;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert!(response.body.is_empty());
+
+    // The policy-engine does not, so its clients always get the full graph.
+    let engine_url = harness.engine.url("/v1/graph");
+    let response = harness.get_graph(&engine_url, &[(IF_NONE_MATCH.as_str(), &etag)])?;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(!response.body.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn stopped_builder_errors() -> Fallible<()> {
+    let mut harness = Harness::start(false)?;
+    let engine_url = harness.engine.url("/v1/graph");
+
+    assert_eq!(harness.get_graph(&engine_url, &[])?.status, StatusCode::OK);
+
+    harness.builder.stop();
+    let response = harness.get_graph(&engine_url, &[])?;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    let error: serde_json::Value = serde_json::from_str(&response.body)?;
+    assert_eq!(error["kind"], "failed_upstream_fetch");
+
+    Ok(())
+}
+
+#[test]
+fn stopped_builder_serves_stale() -> Fallible<()> {
+    let mut harness = Harness::start(true)?;
+    let engine_url = harness.engine.url("/v1/graph");
+
+    let fresh = harness.get_graph(&engine_url, &[])?;
+    assert_eq!(fresh.status, StatusCode::OK);
+    assert!(fresh.headers.get(STALE_HEADER).is_none());
+
+    harness.builder.stop();
+    let stale = harness.get_graph(&engine_url, &[])?;
+    assert_eq!(stale.status, StatusCode::OK);
+    assert_eq!(
+        stale
+            .headers
+            .get(STALE_HEADER)
+            .map(|value| value.as_bytes()),
+        Some(&b"true"[..])
+    );
+    assert_eq!(sorted_graph(&stale.body)?, sorted_graph(&fresh.body)?);
+
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! Embedded graph-builder, for running both services in a single process.
+//!
+//! The embedded graph-builder is configured by its own configuration file, of
+//! which only the scraping settings are used: the plugins (or the registry
+//! options of the default ones) and the pause and timeout of scrapes. Serving
+//! is configured by the policy-engine settings alone.
+//!
+//! The policy pipeline reads the scraped graph in-process, replacing any
+//! `cincinnati-graph-fetch` plugin. The status service additionally serves
+//! the liveness, readiness and topology of the scrape loop, and its metrics
+//! on `/metrics/graph-builder`.
+
+use actix_web::web::{self, ServiceConfig};
+use cincinnati::plugins::prelude::*;
+use commons::metrics;
+use graph_builder::embedded::EmbeddedGraphSourcePlugin;
+use graph_builder::graph::{self as builder, RwLock, State};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+/// Graph-builder scrape loop running in the policy-engine process.
+#[derive(Clone)]
+pub(crate) struct EmbeddedGraphBuilder {
+    /// State shared with the scrape loop, and with the status service workers.
+    state: web::Data<State>,
+}
+
+impl EmbeddedGraphBuilder {
+    /// Start the scrape loop of a graph-builder configured by the given file.
+    pub(crate) fn start<P>(config_path: P) -> Fallible<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let config_path = config_path.as_ref();
+        let settings =
+            graph_builder::config::AppSettings::from_filepath(config_path).context(format!(
+                "invalid embedded graph-builder configuration '{}'",
+                config_path.display()
+            ))?;
+
+        // The graph-builder metrics live in their own registry, as some of
+        // them share their names with the policy-engine ones.
+        let registry: &'static prometheus::Registry = Box::leak(Box::new(metrics::new_registry(
+            Some(graph_builder::config::METRICS_PREFIX.to_string()),
+        )?));
+        let plugins = settings.validate_and_build_plugins(Some(registry))?;
+        builder::register_metrics(registry)?;
+
+        let state = State::new(
+            Default::default(),
+            Arc::new(RwLock::new(false)),
+            Arc::new(RwLock::new(false)),
+            Box::leak(Box::new(plugins)),
+            registry,
+            settings.unknown_channel,
+        )
+        .with_first_seen(settings.load_first_seen()?);
+
+        let scrape_state = state.clone();
+        thread::Builder::new()
+            .name("graph-builder".to_string())
+            .spawn(move || {
+                builder::run(&settings, &scrape_state);
+            })?;
+
+        Ok(Self {
+            state: web::Data::new(state),
+        })
+    }
+
+    /// Make the embedded graph-builder the graph source of a policy pipeline.
+    pub(crate) fn wire_plugins(&self, plugins: Vec<BoxedPlugin>) -> Vec<BoxedPlugin> {
+        std::iter::once(EmbeddedGraphSourcePlugin::boxed(
+            self.state.get_ref().clone(),
+        ))
+        .chain(
+            plugins
+                .into_iter()
+                .filter(|plugin| plugin.get_name() != CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+        )
+        .collect()
+    }
+
+    /// Register the status endpoints of the scrape loop.
+    pub(crate) fn configure_status(&self, cfg: &mut ServiceConfig) {
+        cfg.app_data(self.state.clone());
+        graph_builder::status::configure(cfg);
+        cfg.service(
+            web::resource("/metrics/graph-builder").route(web::get().to(metrics::serve::<State>)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph, AppState};
+    use actix_web::{http, test, App};
+    use commons::extractors::ValidatedQueryConfig;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    /// Graph served by the mock upstream of the embedded graph-builder.
+    static UPSTREAM_GRAPH: &str = r#"{
+        "nodes": [
+            {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable"}},
+            {"version": "1.1.0", "payload": "image/1.1.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "stable"}},
+            {"version": "1.2.0", "payload": "image/1.2.0", "metadata": {"io.openshift.upgrades.graph.release.channels": "fast"}}
+        ],
+        "edges": [[0, 1], [1, 2]]
+    }"#;
+
+    #[test]
+    fn serve_filtered_graph() -> Fallible<()> {
+        let _m = mockito::mock("GET", "/embedded-upstream")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(UPSTREAM_GRAPH)
+            .create();
+
+        let mut config = tempfile::NamedTempFile::new()?;
+        write!(
+            config,
+            "[[plugin_settings]]\nname = 'cincinnati-graph-fetch'\nupstream = '{}/embedded-upstream'\n",
+            mockito::server_url()
+        )?;
+        let embedded = EmbeddedGraphBuilder::start(config.path())?;
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !embedded.state.is_ready() {
+            ensure!(Instant::now() < deadline, "no graph scraped in time");
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[
+                plugin_config!(
+                    ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                    ("upstream", "http://unused.test/v1/graph")
+                )?,
+                plugin_config!(("name", ChannelFilterPlugin::PLUGIN_NAME))?,
+            ],
+            None,
+        )?;
+        let plugins = embedded.wire_plugins(plugins);
+        assert_eq!(
+            plugins
+                .iter()
+                .map(|plugin| plugin.get_name())
+                .collect::<Vec<_>>(),
+            vec![
+                EmbeddedGraphSourcePlugin::PLUGIN_NAME,
+                ChannelFilterPlugin::PLUGIN_NAME
+            ]
+        );
+
+        let mandatory_params = vec!["channel".to_string()];
+        let query_config = ValidatedQueryConfig::new(mandatory_params.iter().cloned().collect());
+        let state = AppState {
+            mandatory_params,
+            plugins: crate::reload::PluginChain::new(plugins),
+            ..Default::default()
+        };
+
+        let (readiness, graph_json) = actix::System::new("test").block_on(async {
+            let mut app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(state))
+                    .app_data(query_config)
+                    .configure(|cfg| embedded.configure_status(cfg))
+                    .route("/v1/graph", web::get().to(graph::index)),
+            )
+            .await;
+
+            let readiness = test::call_service(
+                &mut app,
+                test::TestRequest::get().uri("/readiness").to_request(),
+            )
+            .await
+            .status();
+
+            let graph_json = test::read_response(
+                &mut app,
+                test::TestRequest::get()
+                    .uri("/v1/graph?channel=stable")
+                    .header(http::header::ACCEPT, cincinnati::CONTENT_TYPE)
+                    .to_request(),
+            )
+            .await;
+
+            (readiness, graph_json)
+        });
+        assert_eq!(readiness, http::StatusCode::OK);
+
+        let mut graph: cincinnati::Graph = serde_json::from_slice(&graph_json)?;
+        let mut versions: Vec<String> = graph
+            .find_by_fn_mut(|_| true)
+            .into_iter()
+            .map(|(_, version)| version)
+            .collect();
+        versions.sort();
+        assert_eq!(versions, vec!["1.0.0", "1.1.0"]);
+
+        Ok(())
+    }
+}
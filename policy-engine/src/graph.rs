@@ -1,18 +1,128 @@
 //! Cincinnati graph service.
 
+use crate::cache::{EtagCache, CACHE_KEY_HEADER};
+use crate::reload::PluginChain;
 use crate::AppState;
-use actix_web::web::Query;
+use actix_web::http::{HeaderMap, HeaderName, HeaderValue};
 use actix_web::{HttpRequest, HttpResponse};
-use cincinnati::plugins::BoxedPlugin;
-use cincinnati::CONTENT_TYPE;
-use commons::tracing::get_tracer;
+use cincinnati::plugins::internal::cincinnati_graph_fetch::{
+    CincinnatiGraphFetchPlugin, STALE_AGE_PARAM, UPSTREAM_ETAG_PARAM,
+};
+use cincinnati::plugins::{BoxedPlugin, GraphWithWarnings, InternalIO, Parameters, Warning};
+use cincinnati::{parse_channels, CHANNELS_KEY, CONTENT_TYPE};
+use commons::extractors::{AcceptsJson, ValidatedQuery};
+use commons::http::{ResponseHeaders, HEADER_PARAM_PREFIX};
+use commons::log_throttle::ThrottledLogger;
+use commons::tracing::{get_tracer, DEBUG_ID_PARAM};
 use commons::{self, Fallible, GraphError};
+use graph_builder::embedded::EmbeddedGraphSourcePlugin;
 use opentelemetry::api::{trace::futures::Instrument, Tracer};
 use prometheus::{histogram_opts, Counter, Histogram, Registry};
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Header set on responses serving a stale graph.
+pub static STALE_HEADER: &str = "x-cincinnati-stale";
+
+/// Header carrying the age of a stale graph, in seconds.
+pub static STALE_AGE_HEADER: &str = "x-cincinnati-stale-age";
+
+/// Media type of graphs served in GraphViz DOT format.
+pub static DOT_CONTENT_TYPE: &str = "text/vnd.graphviz";
+
+/// Client parameter selecting the projection of served graphs.
+pub static INCLUDE_PARAM: &str = "include";
+
+/// Client parameter requesting pretty-printed graphs, for debugging.
+pub static PRETTY_PARAM: &str = "pretty";
+
+/// Client parameters changing how graphs are rendered.
+pub(crate) static RENDERING_PARAMS: &[&str] = &[INCLUDE_PARAM, PRETTY_PARAM];
+
+/// Plugins providing the input graph of a pipeline.
+static GRAPH_SOURCES: &[&str] = &[
+    CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+    EmbeddedGraphSourcePlugin::PLUGIN_NAME,
+];
+
+/// Projection of served graphs, requested through `INCLUDE_PARAM`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Projection {
+    /// Releases with all their metadata.
+    Full,
+    /// Releases with their version and payload only, for bandwidth-constrained clients.
+    Minimal,
+    /// Releases with all their metadata, and their channels as structured objects.
+    Channels,
+}
+
+impl Projection {
+    /// Parse the projection requested by the given plugin parameters.
+    pub(crate) fn from_params(params: &Parameters) -> Result<Self, GraphError> {
+        match params.client(INCLUDE_PARAM).map(String::as_str) {
+            None => Ok(Projection::Full),
+            Some("minimal") => Ok(Projection::Minimal),
+            Some("channels") => Ok(Projection::Channels),
+            Some(other) => Err(GraphError::InvalidParams(format!(
+                "unknown value '{}' for parameter '{}', expected 'minimal' or 'channels'",
+                other, INCLUDE_PARAM
+            ))),
+        }
+    }
+
+    /// Project the graph, right before its serialization.
+    fn apply(self, graph: &mut cincinnati::Graph) {
+        if self == Projection::Minimal {
+            graph.find_by_fn_mut(|release| {
+                if let Some(metadata) = release.get_metadata_mut() {
+                    metadata.clear();
+                }
+                false
+            });
+        }
+    }
+}
+
+/// Channel of a release, in structured form.
+#[derive(Debug, Serialize)]
+struct Channel {
+    /// Channel name.
+    name: String,
+}
+
+/// Channels of a release, served next to its other fields.
+#[derive(Debug, Serialize)]
+struct ReleaseChannels {
+    channels: Vec<Channel>,
+}
+
+/// Parse the channels of a release from its comma-separated channel metadata.
+///
+/// The metadata is left untouched, for clients reading it directly.
+fn release_channels(release: &cincinnati::Release) -> ReleaseChannels {
+    let metadata = match release {
+        cincinnati::Release::Concrete(concrete) => concrete.metadata.get(CHANNELS_KEY),
+        cincinnati::Release::Abstract(_) => None,
+    };
+    let channels = match metadata {
+        Some(channels) => parse_channels(channels)
+            .map(|name| Channel {
+                name: name.to_string(),
+            })
+            .collect(),
+        None => vec![],
+    };
+    ReleaseChannels { channels }
+}
+
+/// Client query parameters, checked for the mandatory ones.
+type ClientParams = ValidatedQuery<HashMap<String, String>>;
 
 lazy_static! {
+    /// Throttles error logs of failed requests, per error kind.
+    static ref INDEX_ERROR_LOG: ThrottledLogger = ThrottledLogger::new(10, Duration::from_secs(60));
     static ref V1_GRAPH_INCOMING_REQS: Counter = Counter::new(
         "v1_graph_incoming_requests_total",
         "Total number of incoming HTTP client request to /v1/graph"
@@ -36,7 +146,11 @@ pub(crate) fn register_metrics(registry: &Registry) -> Fallible<()> {
 }
 
 /// Serve Cincinnati graph requests.
+///
+/// Request validation errors are only reported outside of maintenance windows.
 pub(crate) async fn index(
+    accepts_json: Result<AcceptsJson, GraphError>,
+    query: Result<ClientParams, GraphError>,
     req: HttpRequest,
     app_data: actix_web::web::Data<AppState>,
 ) -> Result<HttpResponse, GraphError> {
@@ -44,65 +158,522 @@ pub(crate) async fn index(
 
     V1_GRAPH_INCOMING_REQS.inc();
 
-    // Check that the client can accept JSON media type.
-    commons::ensure_content_type(req.headers(), CONTENT_TYPE)?;
-
-    // Check for required client parameters.
-    let mandatory_params = &app_data.mandatory_params;
-    commons::ensure_query_params(mandatory_params, req.query_string())?;
+    // Reject requests during maintenance.
+    app_data.maintenance.check()?;
 
-    let plugin_params = Query::<HashMap<String, String>>::from_query(req.query_string())
-        .map(|query| query.into_inner())
-        .map_err(|e| commons::GraphError::InvalidParams(e.to_string()))?;
+    accepts_json?;
+    let plugin_params = plugin_params(req.headers(), &app_data, query?.into_inner());
 
     let timer = V1_GRAPH_SERVE_HIST.start_timer();
 
-    let response = process_plugins(app_data.plugins.iter(), plugin_params)
-        .instrument(span)
-        .await
-        .map_err(|e| {
-            error!(
-                "Error serving request '{}' from '{}': {:?}",
-                format!("{:?}", &req).replace("\n", " ").replace("\t", " "),
-                &req.peer_addr()
-                    .map(|addr| addr.to_string())
-                    .unwrap_or("<not available>".into()),
-                e
-            );
+    // Expose the cache key of the request, for troubleshooting.
+    let cache_key_header = app_data
+        .cache
+        .as_ref()
+        .and_then(|cache| HeaderValue::from_str(&cache.effective_key(&plugin_params)).ok());
+    let with_cache_key = |mut response: HttpResponse| {
+        if let Some(key) = &cache_key_header {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(CACHE_KEY_HEADER), key.clone());
+        }
+        response
+    };
+
+    let cache = app_data
+        .cache
+        .as_ref()
+        .and_then(|cache| Some((cache, cache.key(&plugin_params)?)));
+    if let Some(json) = cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+        timer.observe_duration();
+        return Ok(with_cache_key(graph_response(
+            RenderedGraph {
+                json,
+                stale_age: None,
+            },
+            &app_data.response_headers,
+        )));
+    }
+
+    let plugins = app_data.live_plugins()?;
+    let response = render_graph_revalidated(
+        &app_data.plugins,
+        &plugins,
+        plugin_params,
+        app_data.etag_cache.as_deref(),
+        app_data.max_graph_size,
+        app_data.expose_warnings,
+    )
+    .instrument(span)
+    .await
+    .map(|rendered| {
+        // Stale graphs are not cached, so that the next request retries the upstream.
+        if let (Some((cache, key)), None) = (cache, &rendered.stale_age) {
+            app_data
+                .plugins
+                .if_current(&plugins, || cache.insert(key, rendered.json.clone()));
+        }
+        with_cache_key(graph_response(rendered, &app_data.response_headers))
+    })
+    .map_err(|e| {
+        commons::error_throttled!(
+            INDEX_ERROR_LOG,
+            &e.kind(),
+            "Error serving request '{}' from '{}': {:?}",
+            format!("{:?}", &req).replace("\n", " ").replace("\t", " "),
+            commons::http::client_identity(&req, &app_data.trusted_proxies)
+                .map(|addr| addr.to_string())
+                .unwrap_or("<not available>".into()),
             e
-        });
+        );
+        e
+    });
 
     timer.observe_duration();
     response
 }
 
-async fn process_plugins<P>(
+/// Serve the adjacency summary of the processed graph, for debugging.
+///
+/// The response maps every release version to the sorted versions it has an
+/// edge to.
+pub(crate) async fn adjacency(
+    accepts_json: Result<AcceptsJson, GraphError>,
+    query: Result<ClientParams, GraphError>,
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("adjacency", None);
+
+    // Reject requests during maintenance.
+    app_data.maintenance.check()?;
+
+    accepts_json?;
+    let plugin_params = plugin_params(req.headers(), &app_data, query?.into_inner());
+
+    let graph = process_graph(app_data.live_plugins()?.iter(), plugin_params)
+        .instrument(span)
+        .await?;
+
+    let adjacency_json = serde_json::to_string(&adjacency_map(graph))
+        .map_err(|e| GraphError::FailedJsonOut(e.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .body(adjacency_json))
+}
+
+/// Serve the processed graph in GraphViz DOT format, for documentation and debugging.
+pub(crate) async fn dot(
+    query: Result<ClientParams, GraphError>,
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("dot", None);
+
+    // Reject requests during maintenance.
+    app_data.maintenance.check()?;
+
+    let plugin_params = plugin_params(req.headers(), &app_data, query?.into_inner());
+
+    let graph = process_graph(app_data.live_plugins()?.iter(), plugin_params)
+        .instrument(span)
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(DOT_CONTENT_TYPE)
+        .body(graph.to_dot()))
+}
+
+/// Everything the processed graph knows about a single release.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ReleaseDetails {
+    /// Release version.
+    pub(crate) version: String,
+    /// Release payload, unset for abstract releases.
+    pub(crate) payload: Option<String>,
+    /// Release metadata.
+    pub(crate) metadata: BTreeMap<String, String>,
+    /// Sorted versions with an edge to the release.
+    pub(crate) previous: Vec<String>,
+    /// Sorted versions the release has an edge to.
+    pub(crate) next: Vec<String>,
+}
+
+/// Serve the details of a single release of the processed graph, for debugging.
+pub(crate) async fn release(
+    version: actix_web::web::Path<String>,
+    accepts_json: Result<AcceptsJson, GraphError>,
+    query: Result<ClientParams, GraphError>,
+    req: HttpRequest,
+    app_data: actix_web::web::Data<AppState>,
+) -> Result<HttpResponse, GraphError> {
+    let span = get_tracer().start("release", None);
+
+    // Reject requests during maintenance.
+    app_data.maintenance.check()?;
+
+    accepts_json?;
+    let plugin_params = plugin_params(req.headers(), &app_data, query?.into_inner());
+
+    let graph = process_graph(app_data.live_plugins()?.iter(), plugin_params)
+        .instrument(span)
+        .await?;
+
+    let details = release_details(&graph, &version)?;
+    let details_json =
+        serde_json::to_string(&details).map_err(|e| GraphError::FailedJsonOut(e.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .body(details_json))
+}
+
+/// Collect the details of the release with the given version.
+pub(crate) fn release_details(
+    graph: &cincinnati::Graph,
+    version: &str,
+) -> Result<ReleaseDetails, GraphError> {
+    let release_id = graph
+        .find_by_version(version)
+        .ok_or_else(|| GraphError::ReleaseNotFound(version.to_string()))?;
+    let release = graph
+        .find_by_releaseid(&release_id)
+        .map_err(|e| GraphError::FailedPluginExecution(e.to_string()))?;
+
+    let (payload, metadata) = match release {
+        cincinnati::Release::Concrete(concrete) => {
+            let metadata = concrete
+                .metadata
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            (Some(concrete.payload.clone()), metadata)
+        }
+        cincinnati::Release::Abstract(_) => (None, BTreeMap::new()),
+    };
+
+    Ok(ReleaseDetails {
+        version: version.to_string(),
+        payload,
+        metadata,
+        previous: graph.previous_versions(&release_id),
+        next: graph.next_versions(&release_id),
+    })
+}
+
+/// Build the plugin parameters from the validated client parameters and the request headers.
+///
+/// Client parameters in the reserved namespace are dropped, so that only the
+/// server annotates requests with internal parameters.
+pub(crate) fn plugin_params(
+    headers: &HeaderMap,
+    app_data: &AppState,
+    mut client_params: HashMap<String, String>,
+) -> Parameters {
+    app_data.param_injection.apply(&mut client_params);
+    let mut plugin_params = Parameters::from_client(client_params);
+    app_data.capabilities.apply(headers, &mut plugin_params);
+
+    // Forward the debug id of force-sampled requests upstream.
+    if let Some(debug_id) = app_data
+        .debug_sampling
+        .as_ref()
+        .and_then(|sampling| sampling.debug_id(headers))
+    {
+        plugin_params.insert_internal(DEBUG_ID_PARAM, debug_id);
+    }
+
+    // Forward the configured request headers, as reserved parameters.
+    for name in &app_data.forwarded_headers {
+        if let Some(value) = headers.get(name).and_then(|value| value.to_str().ok()) {
+            plugin_params.insert_internal(format!("{}{}", HEADER_PARAM_PREFIX, name), value);
+        }
+    }
+
+    plugin_params
+}
+
+/// Build a version -> [versions] map from the edges of the graph.
+pub(crate) fn adjacency_map(mut graph: cincinnati::Graph) -> BTreeMap<String, BTreeSet<String>> {
+    graph
+        .find_by_fn_mut(|_| true)
+        .into_iter()
+        .map(|(release_id, version)| {
+            let next = graph
+                .next_releases(&release_id)
+                .map(|(_, _, release)| release.version().to_string())
+                .collect();
+            (version, next)
+        })
+        .collect()
+}
+
+/// Serialized graph, as served to clients.
+#[derive(Debug)]
+pub(crate) struct RenderedGraph {
+    /// Graph in JSON format.
+    pub(crate) json: String,
+    /// Age of the graph in seconds, if a stale one is served.
+    pub(crate) stale_age: Option<String>,
+}
+
+/// Process the plugins and serialize the resulting graph, with their warnings if exposed.
+///
+/// The graph is projected as requested by the plugin parameters.
+pub(crate) async fn render_graph<'a, P>(
     plugins: P,
-    plugin_params: HashMap<String, String>,
-) -> Result<HttpResponse, GraphError>
+    plugin_params: Parameters,
+    max_graph_size: Option<usize>,
+    expose_warnings: bool,
+) -> Result<RenderedGraph, GraphError>
 where
-    P: std::iter::Iterator<Item = &'static BoxedPlugin>,
-    P: 'static + Sync + Send,
+    P: std::iter::Iterator<Item = &'a BoxedPlugin>,
+    P: Sync + Send,
 {
-    let internal_io = cincinnati::plugins::process(
+    let projection = Projection::from_params(&plugin_params)?;
+    let pretty = pretty_param(&plugin_params)?;
+    let io = process_io(plugins, plugin_params).await?;
+    render_io(io, projection, pretty, max_graph_size, expose_warnings)
+}
+
+/// Render a graph like `render_graph`, reusing the result cached for the upstream ETag.
+///
+/// The graph sources of the pipeline always run, so that they revalidate the
+/// upstream graph. The remaining plugins are only run if no result is cached
+/// for the ETag of the upstream graph and the plugin parameters. Results are
+/// only cached if all the remaining plugins are pure, and while `plugins` are
+/// the live plugins of `chain`.
+pub(crate) async fn render_graph_revalidated(
+    chain: &PluginChain,
+    plugins: &Arc<Vec<BoxedPlugin>>,
+    plugin_params: Parameters,
+    etag_cache: Option<&EtagCache>,
+    max_graph_size: Option<usize>,
+    expose_warnings: bool,
+) -> Result<RenderedGraph, GraphError> {
+    let (sources, filters) = split_graph_sources(plugins);
+    let etag_cache = etag_cache.filter(|_| filters.iter().all(|plugin| plugin.is_pure()));
+
+    let (etag_cache, key) =
+        match etag_cache.and_then(|cache| Some((cache, cache.key(&plugin_params)?))) {
+            Some(found) => found,
+            None => {
+                return render_graph(
+                    plugins.iter(),
+                    plugin_params,
+                    max_graph_size,
+                    expose_warnings,
+                )
+                .await
+            }
+        };
+
+    let projection = Projection::from_params(&plugin_params)?;
+    let pretty = pretty_param(&plugin_params)?;
+    let io = process_io(sources.iter(), plugin_params).await?;
+
+    // Stale graphs carry no ETag, and are always processed.
+    let etag = io.parameters.internal(UPSTREAM_ETAG_PARAM).cloned();
+    if let Some(json) = etag.as_ref().and_then(|etag| etag_cache.get(etag, &key)) {
+        return Ok(RenderedGraph {
+            json,
+            stale_age: None,
+        });
+    }
+
+    let io = resume_io(filters.iter(), io).await?;
+    let rendered = render_io(io, projection, pretty, max_graph_size, expose_warnings)?;
+    if let (Some(etag), None) = (etag, &rendered.stale_age) {
+        chain.if_current(plugins, || {
+            etag_cache.insert(&etag, key, rendered.json.clone())
+        });
+    }
+    Ok(rendered)
+}
+
+/// Return the leading graph sources of a pipeline, and the plugins following them.
+pub(crate) fn split_graph_sources(plugins: &[BoxedPlugin]) -> (&[BoxedPlugin], &[BoxedPlugin]) {
+    let position = plugins
+        .iter()
+        .take_while(|plugin| GRAPH_SOURCES.contains(&plugin.get_name()))
+        .count();
+    plugins.split_at(position)
+}
+
+/// Project and serialize the output of the plugins.
+fn render_io(
+    mut io: InternalIO,
+    projection: Projection,
+    pretty: bool,
+    max_graph_size: Option<usize>,
+    expose_warnings: bool,
+) -> Result<RenderedGraph, GraphError> {
+    projection.apply(&mut io.graph);
+    let warnings: &[Warning] = if expose_warnings { &io.warnings } else { &[] };
+
+    let json = match projection {
+        Projection::Channels => serialize_graph(
+            &io.graph.with_release_fields(release_channels),
+            warnings,
+            max_graph_size,
+            pretty,
+        )?,
+        Projection::Full | Projection::Minimal => {
+            serialize_graph(&io.graph, warnings, max_graph_size, pretty)?
+        }
+    };
+
+    Ok(RenderedGraph {
+        json,
+        stale_age: io.parameters.internal(STALE_AGE_PARAM).cloned(),
+    })
+}
+
+/// Parse whether the given plugin parameters request a pretty-printed graph.
+fn pretty_param(params: &Parameters) -> Result<bool, GraphError> {
+    match params.client(PRETTY_PARAM).map(String::as_str) {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(other) => Err(GraphError::InvalidParams(format!(
+            "unknown value '{}' for parameter '{}', expected 'true' or 'false'",
+            other, PRETTY_PARAM
+        ))),
+    }
+}
+
+/// Build the response serving a rendered graph, with the configured headers.
+fn graph_response(rendered: RenderedGraph, headers: &ResponseHeaders) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response.content_type(CONTENT_TYPE);
+    if let Some(age) = rendered.stale_age {
+        response
+            .header(STALE_HEADER, "true")
+            .header(STALE_AGE_HEADER, age);
+    }
+
+    let mut response = response.body(rendered.json);
+    headers.apply(response.headers_mut());
+    response
+}
+
+/// Serialize the graph to JSON, failing if the output exceeds `max_size` bytes.
+///
+/// Non-empty `warnings` are emitted as a top-level `warnings` array, and the
+/// output is compact unless `pretty` is set. The serialization is aborted as
+/// soon as the limit is reached, so that oversized graphs are never fully
+/// allocated.
+pub(crate) fn serialize_graph<G>(
+    graph: &G,
+    warnings: &[Warning],
+    max_size: Option<usize>,
+    pretty: bool,
+) -> Result<String, GraphError>
+where
+    G: serde::Serialize,
+{
+    let graph = GraphWithWarnings { graph, warnings };
+    let limit = match (max_size, pretty) {
+        (Some(limit), _) => limit,
+        (None, false) => {
+            return serde_json::to_string(&graph)
+                .map_err(|e| GraphError::FailedJsonOut(e.to_string()))
+        }
+        (None, true) => {
+            return serde_json::to_string_pretty(&graph)
+                .map_err(|e| GraphError::FailedJsonOut(e.to_string()))
+        }
+    };
+
+    let mut writer = SizeLimitedWriter {
+        buf: Vec::new(),
+        limit,
+    };
+    let written = if pretty {
+        serde_json::to_writer_pretty(&mut writer, &graph)
+    } else {
+        serde_json::to_writer(&mut writer, &graph)
+    };
+    written.map_err(|e| {
+        if e.is_io() {
+            GraphError::GraphTooLarge(limit)
+        } else {
+            GraphError::FailedJsonOut(e.to_string())
+        }
+    })?;
+
+    String::from_utf8(writer.buf).map_err(|e| GraphError::FailedJsonOut(e.to_string()))
+}
+
+/// Writer buffering its output, failing once it would exceed `limit` bytes.
+struct SizeLimitedWriter {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+impl std::io::Write for SizeLimitedWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.buf.len() + data.len() > self.limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "size limit exceeded",
+            ));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) async fn process_graph<'a, P>(
+    plugins: P,
+    plugin_params: Parameters,
+) -> Result<cincinnati::Graph, GraphError>
+where
+    P: std::iter::Iterator<Item = &'a BoxedPlugin>,
+    P: Sync + Send,
+{
+    Ok(process_io(plugins, plugin_params).await?.graph)
+}
+
+/// Process the plugins, returning their final output.
+pub(crate) async fn process_io<'a, P>(
+    plugins: P,
+    plugin_params: Parameters,
+) -> Result<InternalIO, GraphError>
+where
+    P: std::iter::Iterator<Item = &'a BoxedPlugin>,
+    P: Sync + Send,
+{
+    resume_io(
         plugins,
-        cincinnati::plugins::PluginIO::InternalIO(cincinnati::plugins::InternalIO {
+        InternalIO {
             graph: Default::default(),
             parameters: plugin_params,
-        }),
+            warnings: Default::default(),
+        },
     )
     .await
-    .map_err(|e| match e.downcast::<GraphError>() {
-        Ok(graph_error) => graph_error,
-        Err(other_error) => GraphError::FailedPluginExecution(other_error.to_string()),
-    })?;
+}
 
-    let graph_json = serde_json::to_string(&internal_io.graph)
-        .map_err(|e| GraphError::FailedJsonOut(e.to_string()))?;
+/// Process the plugins on the output of previous ones, returning their final output.
+pub(crate) async fn resume_io<'a, P>(plugins: P, io: InternalIO) -> Result<InternalIO, GraphError>
+where
+    P: std::iter::Iterator<Item = &'a BoxedPlugin>,
+    P: Sync + Send,
+{
+    let internal_io =
+        cincinnati::plugins::process(plugins, cincinnati::plugins::PluginIO::InternalIO(io))
+            .await
+            .map_err(|e| match e.downcast::<GraphError>() {
+                Ok(graph_error) => graph_error,
+                Err(other_error) => GraphError::FailedPluginExecution(other_error.to_string()),
+            })?;
 
-    Ok(HttpResponse::Ok()
-        .content_type(CONTENT_TYPE)
-        .body(graph_json))
+    Ok(internal_io)
 }
 
 #[cfg(test)]
@@ -110,24 +681,79 @@ pub(crate) mod tests {
 
     use crate::graph;
     use crate::AppState;
-    use actix_web::http;
+    use actix_web::test::TestRequest;
+    use actix_web::{http, FromRequest, HttpRequest, HttpResponse};
+    use cincinnati::plugins::catalog::EmptyChain;
     use cincinnati::plugins::prelude::*;
+    use cincinnati::plugins::prelude_plugin_impl::{async_trait, InternalPlugin};
+    use cincinnati::plugins::{InternalIO, Warning};
+    use cincinnati::{parse_channels, CHANNELS_KEY};
+    use commons::extractors::{AcceptsJson, ValidatedQueryConfig};
     use mockito;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
     use tokio::runtime::Runtime;
 
+    type HandlerArgs = (
+        Result<AcceptsJson, graph::GraphError>,
+        Result<graph::ClientParams, graph::GraphError>,
+        HttpRequest,
+    );
+
     pub(crate) fn common_init() -> Runtime {
         let _ = env_logger::try_init_from_env(env_logger::Env::default());
         Runtime::new().unwrap()
     }
 
+    /// Extract the handler arguments from a request, as configured for the service.
+    async fn extract_args(req: TestRequest, app_data: &AppState) -> HandlerArgs {
+        let req = req
+            .app_data(ValidatedQueryConfig::new(
+                app_data.mandatory_params.iter().cloned().collect(),
+            ))
+            .to_http_request();
+        let accepts_json = AcceptsJson::extract(&req).await;
+        let query = graph::ClientParams::extract(&req).await;
+        (accepts_json, query, req)
+    }
+
+    pub(crate) async fn call_index(
+        req: TestRequest,
+        app_data: actix_web::web::Data<AppState>,
+    ) -> Result<HttpResponse, graph::GraphError> {
+        let (accepts_json, query, req) = extract_args(req, &app_data).await;
+        graph::index(accepts_json, query, req, app_data).await
+    }
+
+    async fn call_adjacency(
+        req: TestRequest,
+        app_data: actix_web::web::Data<AppState>,
+    ) -> Result<HttpResponse, graph::GraphError> {
+        let (accepts_json, query, req) = extract_args(req, &app_data).await;
+        graph::adjacency(accepts_json, query, req, app_data).await
+    }
+
+    async fn call_dot(
+        req: TestRequest,
+        app_data: actix_web::web::Data<AppState>,
+    ) -> Result<HttpResponse, graph::GraphError> {
+        let (_, query, req) = extract_args(req, &app_data).await;
+        graph::dot(query, req, app_data).await
+    }
+
+    fn query_params(req: &HttpRequest) -> Result<HashMap<String, String>, Error> {
+        Ok(actix_web::web::Query::from_query(req.query_string())?.into_inner())
+    }
+
     #[test]
     fn missing_content_type() {
         let mut rt = common_init();
         let state = AppState::default();
         let app_data = actix_web::web::Data::new(state);
 
-        let http_req = actix_web::test::TestRequest::get().to_http_request();
-        let graph_call = graph::index(http_req, app_data);
+        let http_req = TestRequest::get();
+        let graph_call = call_index(http_req, app_data);
         let resp = rt.block_on(graph_call).unwrap_err();
 
         assert_eq!(resp, graph::GraphError::InvalidContentType);
@@ -143,13 +769,11 @@ pub(crate) mod tests {
         };
         let app_data = actix_web::web::Data::new(state);
 
-        let http_req = actix_web::test::TestRequest::get()
-            .header(
-                http::header::ACCEPT,
-                http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
-            )
-            .to_http_request();
-        let graph_call = graph::index(http_req, app_data);
+        let http_req = actix_web::test::TestRequest::get().header(
+            http::header::ACCEPT,
+            http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+        );
+        let graph_call = call_index(http_req, app_data);
         let resp = rt.block_on(graph_call).unwrap_err();
 
         assert_eq!(
@@ -158,6 +782,52 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn response_headers() {
+        use actix_web::ResponseError;
+        use commons::http::ResponseHeaders;
+
+        let mut rt = common_init();
+        let state = AppState {
+            response_headers: ResponseHeaders::from_static(&[
+                ("cache-control", "public, max-age=60"),
+                ("vary", "Origin"),
+            ])
+            .vary(&["Accept"]),
+            serve_empty_graph: true,
+            ..Default::default()
+        };
+        let app_data = actix_web::web::Data::new(state);
+
+        let http_req = TestRequest::get().header(
+            http::header::ACCEPT,
+            http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+        );
+        let resp = rt.block_on(call_index(http_req, app_data.clone())).unwrap();
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get(http::header::CACHE_CONTROL).unwrap(),
+            "public, max-age=60"
+        );
+        assert_eq!(headers.get(http::header::VARY).unwrap(), "Origin, Accept");
+        assert_eq!(
+            headers.get(http::header::CONTENT_TYPE).unwrap(),
+            cincinnati::CONTENT_TYPE
+        );
+
+        // Error responses carry the error headers instead.
+        let err = rt
+            .block_on(call_index(TestRequest::get(), app_data))
+            .unwrap_err();
+        let resp = err.error_response();
+        assert_eq!(resp.status(), http::StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(
+            resp.headers().get(http::header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+        assert_eq!(resp.headers().get(http::header::VARY), None);
+    }
+
     #[test]
     fn failed_plugin_execution() -> Result<(), Error> {
         let mut rt = common_init();
@@ -175,7 +845,7 @@ pub(crate) mod tests {
 
         let state = AppState {
             mandatory_params,
-            plugins: Box::leak(Box::new(plugins)),
+            plugins: crate::reload::PluginChain::new(plugins),
             ..Default::default()
         };
         let app_data = actix_web::web::Data::new(state);
@@ -185,10 +855,9 @@ pub(crate) mod tests {
             .header(
                 http::header::ACCEPT,
                 http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
-            )
-            .to_http_request();
+            );
 
-        let graph_call = graph::index(http_req, app_data);
+        let graph_call = call_index(http_req, app_data);
 
         let _m = mockito::mock("GET", "/")
             .with_status(200)
@@ -206,6 +875,734 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn adjacency_missing_content_type() {
+        let mut rt = common_init();
+        let app_data = actix_web::web::Data::new(AppState::default());
+
+        let http_req = TestRequest::get();
+        let resp = rt.block_on(call_adjacency(http_req, app_data)).unwrap_err();
+
+        assert_eq!(resp, graph::GraphError::InvalidContentType);
+    }
+
+    #[test]
+    fn adjacency_matches_edges() -> Result<(), Error> {
+        let mut rt = common_init();
+
+        let _m = mockito::mock("GET", "/adjacency")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "nodes": [
+                        {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                        {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {}},
+                        {"version": "3.0.0", "payload": "image/3.0.0", "metadata": {}}
+                    ],
+                    "edges": [[0, 1], [1, 2], [0, 2]]
+                }"#,
+            )
+            .create();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/adjacency", mockito::server_url()))
+            )?],
+            None,
+        )?;
+        let app_data = actix_web::web::Data::new(AppState {
+            plugins: crate::reload::PluginChain::new(plugins),
+            ..Default::default()
+        });
+
+        let http_req = actix_web::test::TestRequest::get().header(
+            http::header::ACCEPT,
+            http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+        );
+        let resp = rt.block_on(call_adjacency(http_req, app_data))?;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body = match resp.body() {
+            actix_web::dev::ResponseBody::Body(actix_web::dev::Body::Bytes(bytes)) => {
+                std::str::from_utf8(&bytes)?.to_owned()
+            }
+            _ => bail!("expected byte body"),
+        };
+
+        let adjacency: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(
+            adjacency,
+            serde_json::json!({
+                "1.0.0": ["2.0.0", "3.0.0"],
+                "2.0.0": ["3.0.0"],
+                "3.0.0": [],
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn release_details() -> Result<(), Error> {
+        let mut rt = common_init();
+
+        let _m = mockito::mock("GET", "/release")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "nodes": [
+                        {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                        {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {"url": "https://example.com/2.0.0"}},
+                        {"version": "3.0.0", "payload": "image/3.0.0", "metadata": {}}
+                    ],
+                    "edges": [[0, 1], [1, 2], [0, 2]]
+                }"#,
+            )
+            .create();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/release", mockito::server_url()))
+            )?],
+            None,
+        )?;
+        let app_data = actix_web::web::Data::new(AppState {
+            plugins: crate::reload::PluginChain::new(plugins),
+            ..Default::default()
+        });
+
+        let responses = rt.block_on(async {
+            let mut svc = actix_web::test::init_service(
+                actix_web::App::new().app_data(app_data).service(
+                    actix_web::web::resource("/v1/release/{version}")
+                        .route(actix_web::web::get().to(graph::release)),
+                ),
+            )
+            .await;
+
+            let mut responses = vec![];
+            for version in &["2.0.0", "4.0.0"] {
+                let req = TestRequest::with_uri(&format!("/v1/release/{}", version))
+                    .header(http::header::ACCEPT, cincinnati::CONTENT_TYPE)
+                    .to_request();
+                let resp = actix_web::test::call_service(&mut svc, req).await;
+                let status = resp.status();
+                let body = actix_web::test::read_body(resp).await;
+                responses.push((status, serde_json::from_slice::<serde_json::Value>(&body)));
+            }
+            responses
+        });
+
+        let mut responses = responses.into_iter();
+        let (status, details) = responses.next().unwrap();
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(
+            details?,
+            serde_json::json!({
+                "version": "2.0.0",
+                "payload": "image/2.0.0",
+                "metadata": {"url": "https://example.com/2.0.0"},
+                "previous": ["1.0.0"],
+                "next": ["3.0.0"],
+            })
+        );
+
+        let (status, error) = responses.next().unwrap();
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+        assert_eq!(
+            error?,
+            serde_json::json!({
+                "kind": "release_not_found",
+                "value": "release '4.0.0' not found",
+                "message": "release '4.0.0' not found",
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_params_injection() -> Result<(), Error> {
+        use crate::injection::{InjectionRule, ParamInjection};
+
+        let state = AppState {
+            param_injection: ParamInjection {
+                rules: vec![InjectionRule {
+                    conditions: vec![("channel".to_string(), "candidate".to_string())]
+                        .into_iter()
+                        .collect(),
+                    inject: vec![("experiment".to_string(), "foo".to_string())]
+                        .into_iter()
+                        .collect(),
+                    override_existing: false,
+                }],
+            },
+            ..Default::default()
+        };
+
+        let req =
+            actix_web::test::TestRequest::with_uri("/v1/graph?channel=candidate").to_http_request();
+        let params = graph::plugin_params(req.headers(), &state, query_params(&req)?);
+        assert_eq!(params.get("experiment").map(String::as_str), Some("foo"));
+
+        let req =
+            actix_web::test::TestRequest::with_uri("/v1/graph?channel=stable").to_http_request();
+        let params = graph::plugin_params(req.headers(), &state, query_params(&req)?);
+        assert_eq!(params.get("experiment"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_params_debug_id() -> Result<(), Error> {
+        use commons::tracing::{DebugSampling, DEBUG_ID_HEADER, DEBUG_ID_PARAM};
+
+        let req = actix_web::test::TestRequest::with_uri("/v1/graph?__tracing.debug_id=spoofed")
+            .header(DEBUG_ID_HEADER, "abc")
+            .to_http_request();
+
+        // Clients can't set the reserved parameter themselves.
+        let params = graph::plugin_params(req.headers(), &AppState::default(), query_params(&req)?);
+        assert_eq!(params.get(DEBUG_ID_PARAM), None);
+
+        let state = AppState {
+            debug_sampling: Some(std::sync::Arc::new(DebugSampling::new(None, 10))),
+            ..Default::default()
+        };
+        let params = graph::plugin_params(req.headers(), &state, query_params(&req)?);
+        assert_eq!(params.get(DEBUG_ID_PARAM).map(String::as_str), Some("abc"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_params_forwarded_headers() -> Result<(), Error> {
+        let req = actix_web::test::TestRequest::with_uri("/v1/graph?__header.x-tier=spoofed")
+            .header("X-Tier", "premium")
+            .header("X-Other", "value")
+            .to_http_request();
+
+        // Clients can't set the reserved parameters themselves.
+        let params = graph::plugin_params(req.headers(), &AppState::default(), query_params(&req)?);
+        assert_eq!(params.get("__header.x-tier"), None);
+
+        let state = AppState {
+            forwarded_headers: vec!["x-tier".to_string()].into_iter().collect(),
+            ..Default::default()
+        };
+        let params = graph::plugin_params(req.headers(), &state, query_params(&req)?);
+        assert_eq!(
+            params.get("__header.x-tier").map(String::as_str),
+            Some("premium")
+        );
+        assert_eq!(params.get("__header.x-other"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_graph_size_limit() -> Result<(), Error> {
+        let graph: cincinnati::Graph = serde_json::from_str(
+            r#"{
+                "nodes": [
+                    {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                    {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {}}
+                ],
+                "edges": [[0, 1]]
+            }"#,
+        )?;
+        let json = serde_json::to_string(&graph)?;
+
+        assert_eq!(graph::serialize_graph(&graph, &[], None, false)?, json);
+        assert_eq!(
+            graph::serialize_graph(&graph, &[], Some(json.len()), false)?,
+            json
+        );
+        assert_eq!(
+            graph::serialize_graph(&graph, &[], Some(json.len() - 1), false).unwrap_err(),
+            graph::GraphError::GraphTooLarge(json.len() - 1)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_graph_pretty() -> Result<(), Error> {
+        let mut rt = common_init();
+        let plugins: Vec<BoxedPlugin> = new_plugins!(InternalPluginWrapper(MetadataGraphPlugin));
+        let render = |rt: &mut Runtime, pretty: Option<&str>| {
+            let params = pretty
+                .map(|value| (graph::PRETTY_PARAM.to_string(), value.to_string()))
+                .into_iter()
+                .collect();
+            rt.block_on(graph::render_graph(plugins.iter(), params, None, false))
+        };
+
+        let compact = render(&mut rt, None)?.json;
+        assert!(!compact.contains('\n'));
+        assert_eq!(render(&mut rt, Some("false"))?.json, compact);
+
+        let pretty = render(&mut rt, Some("true"))?.json;
+        assert!(pretty.contains("\n  \"nodes\": ["), "{}", pretty);
+        let parsed: cincinnati::Graph = serde_json::from_str(&pretty)?;
+        assert_eq!(serde_json::to_string(&parsed)?, compact);
+
+        // The size limit applies to the pretty output.
+        assert_eq!(
+            graph::serialize_graph(&parsed, &[], Some(pretty.len()), true)?,
+            pretty
+        );
+        assert_eq!(
+            graph::serialize_graph(&parsed, &[], Some(compact.len()), true).unwrap_err(),
+            graph::GraphError::GraphTooLarge(compact.len())
+        );
+
+        match render(&mut rt, Some("yes")) {
+            Err(graph::GraphError::InvalidParams(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    /// Plugin recording a warning.
+    #[derive(Debug)]
+    struct WarningPlugin;
+
+    #[async_trait]
+    impl InternalPlugin for WarningPlugin {
+        const PLUGIN_NAME: &'static str = "warning";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, mut io: InternalIO) -> Fallible<InternalIO> {
+            io.warnings
+                .push(Warning::new(Self::PLUGIN_NAME, "recoverable issue"));
+            Ok(io)
+        }
+    }
+
+    #[test]
+    fn expose_warnings() -> Result<(), Error> {
+        let mut rt = common_init();
+        let render = |rt: &mut Runtime, plugins: &[BoxedPlugin], expose_warnings| {
+            let rendered = rt.block_on(graph::render_graph(
+                plugins.iter(),
+                Default::default(),
+                None,
+                expose_warnings,
+            ))?;
+            Ok::<_, Error>(serde_json::from_str::<serde_json::Value>(&rendered.json)?)
+        };
+
+        let plugins: Vec<BoxedPlugin> = new_plugins!(InternalPluginWrapper(WarningPlugin));
+        let json = render(&mut rt, &plugins, true)?;
+        assert_eq!(
+            json["warnings"],
+            serde_json::json!([{ "plugin": "warning", "message": "recoverable issue" }])
+        );
+        assert_eq!(json["nodes"], serde_json::json!([]));
+
+        // Warnings are omitted if not exposed, or if there are none.
+        let json = render(&mut rt, &plugins, false)?;
+        assert_eq!(json.get("warnings"), None);
+        let json = render(&mut rt, &[], true)?;
+        assert_eq!(json.get("warnings"), None);
+
+        Ok(())
+    }
+
+    /// Plugin counting its runs.
+    #[derive(Debug)]
+    pub(crate) struct RunCounter(pub(crate) Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl InternalPlugin for RunCounter {
+        const PLUGIN_NAME: &'static str = "run-counter";
+        const ABI_VERSION: u32 = 4;
+
+        fn is_pure(self: &Self) -> bool {
+            true
+        }
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(io)
+        }
+    }
+
+    /// Plugin producing a graph with release metadata.
+    #[derive(Debug)]
+    pub(crate) struct MetadataGraphPlugin;
+
+    #[async_trait]
+    impl InternalPlugin for MetadataGraphPlugin {
+        const PLUGIN_NAME: &'static str = "metadata-graph";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            let graph = serde_json::from_str(
+                r#"{
+                    "nodes": [
+                        {"version": "4.6.0", "payload": "image/4.6.0", "metadata": {
+                            "io.openshift.upgrades.graph.release.channels": "stable-4.6,fast-4.6",
+                            "io.openshift.upgrades.graph.release.manifestref": "sha256:0000"
+                        }},
+                        {"version": "4.6.1", "payload": "image/4.6.1", "metadata": {
+                            "io.openshift.upgrades.graph.release.channels": "stable-4.6,fast-4.6",
+                            "io.openshift.upgrades.graph.release.manifestref": "sha256:1111"
+                        }}
+                    ],
+                    "edges": [[0, 1]]
+                }"#,
+            )?;
+            Ok(InternalIO { graph, ..io })
+        }
+    }
+
+    #[test]
+    fn minimal_projection() -> Result<(), Error> {
+        let mut rt = common_init();
+        let plugins: Vec<BoxedPlugin> = new_plugins!(InternalPluginWrapper(MetadataGraphPlugin));
+        let render = |rt: &mut Runtime, include: Option<&str>| {
+            let params = include
+                .map(|value| (graph::INCLUDE_PARAM.to_string(), value.to_string()))
+                .into_iter()
+                .collect();
+            rt.block_on(graph::render_graph(plugins.iter(), params, None, false))
+        };
+
+        let full = render(&mut rt, None)?.json;
+        let minimal = render(&mut rt, Some("minimal"))?.json;
+        assert!(
+            minimal.len() < full.len(),
+            "minimal: {}, full: {}",
+            minimal.len(),
+            full.len()
+        );
+
+        // Only the metadata is stripped.
+        let full: serde_json::Value = serde_json::from_str(&full)?;
+        let minimal: serde_json::Value = serde_json::from_str(&minimal)?;
+        assert_eq!(minimal["edges"], full["edges"]);
+        for (minimal, full) in minimal["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(full["nodes"].as_array().unwrap())
+        {
+            assert_eq!(minimal["version"], full["version"]);
+            assert_eq!(minimal["payload"], full["payload"]);
+            assert_eq!(minimal["metadata"], serde_json::json!({}));
+            assert_ne!(full["metadata"], serde_json::json!({}));
+        }
+
+        // Unknown projections are rejected.
+        match render(&mut rt, Some("full")) {
+            Err(graph::GraphError::InvalidParams(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn structured_channels() -> Result<(), Error> {
+        let mut rt = common_init();
+        let plugins: Vec<BoxedPlugin> = new_plugins!(InternalPluginWrapper(MetadataGraphPlugin));
+        let render = |rt: &mut Runtime, include: Option<&str>| {
+            let params = include
+                .map(|value| (graph::INCLUDE_PARAM.to_string(), value.to_string()))
+                .into_iter()
+                .collect();
+            let rendered = rt.block_on(graph::render_graph(plugins.iter(), params, None, false))?;
+            Ok::<_, Error>(serde_json::from_str::<serde_json::Value>(&rendered.json)?)
+        };
+
+        let full = render(&mut rt, None)?;
+        let structured = render(&mut rt, Some("channels"))?;
+        assert_eq!(structured["edges"], full["edges"]);
+        for (structured, full) in structured["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(full["nodes"].as_array().unwrap())
+        {
+            assert_eq!(full.get("channels"), None);
+
+            // The legacy metadata is still served, along with its parsed form.
+            assert_eq!(structured["metadata"], full["metadata"]);
+            let legacy = structured["metadata"][CHANNELS_KEY].as_str().unwrap();
+            let names: Vec<&str> = structured["channels"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|channel| channel["name"].as_str().unwrap())
+                .collect();
+            assert_eq!(names, parse_channels(legacy).collect::<Vec<_>>());
+            assert_eq!(
+                structured["channels"],
+                serde_json::json!([{"name": "stable-4.6"}, {"name": "fast-4.6"}])
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn release_channels_without_metadata() {
+        let release = |metadata: &[(&str, &str)]| {
+            cincinnati::Release::Concrete(cincinnati::ConcreteRelease {
+                version: "4.6.0".to_string(),
+                payload: "image/4.6.0".to_string(),
+                metadata: metadata
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            })
+        };
+        let names = |release: &cincinnati::Release| {
+            graph::release_channels(release)
+                .channels
+                .into_iter()
+                .map(|channel| channel.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(names(&release(&[])).is_empty());
+        assert!(names(&release(&[(CHANNELS_KEY, " , ")])).is_empty());
+        assert_eq!(
+            names(&release(&[(CHANNELS_KEY, "stable-4.6, fast-4.6,")])),
+            vec!["stable-4.6", "fast-4.6"]
+        );
+    }
+
+    #[test]
+    fn oversized_graph_response() -> Result<(), Error> {
+        let mut rt = common_init();
+
+        let _m = mockito::mock("GET", "/oversized")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "nodes": [
+                        {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                        {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {}}
+                    ],
+                    "edges": [[0, 1]]
+                }"#,
+            )
+            .create();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/oversized", mockito::server_url()))
+            )?],
+            None,
+        )?;
+        let app_data = actix_web::web::Data::new(AppState {
+            plugins: crate::reload::PluginChain::new(plugins),
+            max_graph_size: Some(64),
+            ..Default::default()
+        });
+
+        let http_req = actix_web::test::TestRequest::get().header(
+            http::header::ACCEPT,
+            http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+        );
+        let err = rt.block_on(call_index(http_req, app_data)).unwrap_err();
+
+        assert_eq!(err, graph::GraphError::GraphTooLarge(64));
+        assert_eq!(err.status_code(), http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dot_serves_processed_graph() -> Result<(), Error> {
+        let mut rt = common_init();
+
+        let _m = mockito::mock("GET", "/dot")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "nodes": [
+                        {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                        {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {}}
+                    ],
+                    "edges": [[0, 1]]
+                }"#,
+            )
+            .create();
+
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                ("upstream", &format!("{}/dot", mockito::server_url()))
+            )?],
+            None,
+        )?;
+        let app_data = actix_web::web::Data::new(AppState {
+            plugins: crate::reload::PluginChain::new(plugins),
+            ..Default::default()
+        });
+
+        // DOT is served regardless of the accepted media types.
+        let http_req = TestRequest::get();
+        let resp = rt.block_on(call_dot(http_req, app_data))?;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            graph::DOT_CONTENT_TYPE
+        );
+
+        let body = match resp.body() {
+            actix_web::dev::ResponseBody::Body(actix_web::dev::Body::Bytes(bytes)) => {
+                std::str::from_utf8(&bytes)?.to_owned()
+            }
+            _ => bail!("expected byte body"),
+        };
+
+        assert!(body.starts_with("digraph cincinnati {"), "{}", body);
+        assert!(body.contains(r#"0 [label="1.0.0"];"#), "{}", body);
+        assert!(body.contains(r#"1 [label="2.0.0"];"#), "{}", body);
+        assert!(body.contains("0 -> 1;"), "{}", body);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_graph_header() -> Result<(), Error> {
+        let mut rt = common_init();
+        let path = "/stale";
+
+        let config = toml::from_str(&format!(
+            r#"
+                name = "{}"
+                upstream = "{}{}"
+                serve_stale_on_error = true
+            "#,
+            CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+            mockito::server_url(),
+            path
+        ))?;
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[cincinnati::plugins::catalog::deserialize_config(config)?],
+            None,
+        )?;
+        let app_data = actix_web::web::Data::new(AppState {
+            plugins: crate::reload::PluginChain::new(plugins),
+            ..Default::default()
+        });
+
+        let request = || {
+            actix_web::test::TestRequest::get().header(
+                http::header::ACCEPT,
+                http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+            )
+        };
+
+        // Fresh fetch.
+        {
+            let _m = mockito::mock("GET", path)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"nodes":[],"edges":[]}"#)
+                .create();
+            let resp = rt.block_on(call_index(request(), app_data.clone()))?;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            assert!(resp.headers().get(graph::STALE_HEADER).is_none());
+            assert!(resp.headers().get(graph::STALE_AGE_HEADER).is_none());
+        }
+
+        // Failed fetch, falling back to the stale graph.
+        {
+            let _m = mockito::mock("GET", path).with_status(503).create();
+            let resp = rt.block_on(call_index(request(), app_data))?;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(graph::STALE_HEADER),
+                Some(&http::header::HeaderValue::from_static("true"))
+            );
+            assert!(resp.headers().get(graph::STALE_AGE_HEADER).is_some());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn webservice_empty_pipeline() -> Result<(), Error> {
+        let mut rt = common_init();
+
+        for (on_empty, expected) in &[
+            (EmptyChain::Fail, None),
+            (
+                EmptyChain::Warn,
+                Some(http::StatusCode::SERVICE_UNAVAILABLE),
+            ),
+            (EmptyChain::Serve, Some(http::StatusCode::OK)),
+        ] {
+            let settings = crate::config::AppSettings {
+                mandatory_client_parameters: vec!["channel".to_string()],
+                on_empty_plugin_chain: *on_empty,
+                ..Default::default()
+            };
+
+            // The empty chain is rejected at startup when failing.
+            let plugins: Vec<BoxedPlugin> = vec![];
+            let expected = match (settings.check_plugin_chain(&plugins), expected) {
+                (Err(_), None) => continue,
+                (Ok(()), Some(expected)) => expected,
+                (result, _) => bail!("unexpected chain check {:?} with {:?}", result, on_empty),
+            };
+
+            let app = actix_web::App::new()
+                .app_data(ValidatedQueryConfig::new(
+                    settings
+                        .mandatory_client_parameters
+                        .iter()
+                        .cloned()
+                        .collect(),
+                ))
+                .app_data(actix_web::web::Data::new(AppState {
+                    mandatory_params: settings.mandatory_client_parameters.clone(),
+                    plugins: crate::reload::PluginChain::new(plugins),
+                    serve_empty_graph: settings.on_empty_plugin_chain.serves_empty_graph(),
+                    ..Default::default()
+                }))
+                .service(
+                    actix_web::web::resource("/graph")
+                        .route(actix_web::web::get().to(graph::index)),
+                );
+
+            let (status, body) = rt.block_on(async {
+                let mut svc = actix_web::test::init_service(app).await;
+                let req = actix_web::test::TestRequest::with_uri("/graph?channel=stable-4.6")
+                    .header("Accept", "application/json")
+                    .to_request();
+                let resp = actix_web::test::call_service(&mut svc, req).await;
+                let status = resp.status();
+                (status, actix_web::test::read_body(resp).await)
+            });
+
+            assert_eq!(status, *expected);
+            let body: serde_json::Value = serde_json::from_slice(&body)?;
+            if on_empty.serves_empty_graph() {
+                assert_eq!(body, serde_json::json!({"nodes": [], "edges": []}));
+            } else {
+                assert_eq!(body["kind"], "service_unavailable");
+            }
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn webservice_graph_json_response() -> Result<(), Error> {
         let _ = common_init();
@@ -266,10 +1663,15 @@ pub(crate) mod tests {
             // prepare and run the policy-engine test-service
             let plugins = cincinnati::plugins::catalog::build_plugins(plugin_config, None)?;
 
+            let mandatory_params: Vec<String> =
+                mandatory_params.iter().map(|s| s.to_string()).collect();
             let app = actix_web::App::new()
+                .app_data(ValidatedQueryConfig::new(
+                    mandatory_params.iter().cloned().collect(),
+                ))
                 .app_data(actix_web::web::Data::new(AppState {
-                    mandatory_params: mandatory_params.iter().map(|s| s.to_string()).collect(),
-                    plugins: Box::leak(Box::new(plugins)),
+                    mandatory_params,
+                    plugins: crate::reload::PluginChain::new(plugins),
                     ..Default::default()
                 }))
                 .service(
@@ -0,0 +1,448 @@
+//! gRPC service for the graph, built with the `grpc` feature.
+//!
+//! The `cincinnati.Graph/GetGraph` RPC takes a `PluginExchange` message, whose
+//! parameters are the client parameters, and returns the processed graph as a
+//! `Graph` message, as defined in the plugin interface. Requests are validated
+//! and processed like on `/v1/graph`, by the live policy plugins, but bypass
+//! the response cache. The service speaks HTTP/2 without TLS, and does not
+//! support message compression.
+
+use crate::graph::{self, STALE_AGE_HEADER, STALE_HEADER};
+use crate::AppState;
+use cincinnati::plugins::interface;
+use cincinnati::plugins::internal::cincinnati_graph_fetch::STALE_AGE_PARAM;
+use commons::GraphError;
+use grpc_hyper::body::{Bytes, HttpBody};
+use grpc_hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use grpc_hyper::service::{make_service_fn, service_fn};
+use grpc_hyper::{Body, Method, Request, Response, Server};
+use protobuf::Message;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Path of the `GetGraph` RPC.
+pub(crate) static GET_GRAPH_PATH: &str = "/cincinnati.Graph/GetGraph";
+
+static GRPC_CONTENT_TYPE: &str = "application/grpc";
+static GRPC_STATUS_HEADER: &str = "grpc-status";
+static GRPC_MESSAGE_HEADER: &str = "grpc-message";
+
+/// Length of the prefix of each message, a compression flag and a length.
+const MESSAGE_PREFIX_LEN: usize = 5;
+
+/// gRPC status codes.
+mod code {
+    pub(super) const OK: u32 = 0;
+    pub(super) const INVALID_ARGUMENT: u32 = 3;
+    pub(super) const NOT_FOUND: u32 = 5;
+    pub(super) const UNIMPLEMENTED: u32 = 12;
+    pub(super) const INTERNAL: u32 = 13;
+    pub(super) const UNAVAILABLE: u32 = 14;
+}
+
+/// Serve the gRPC service on `listener`.
+pub(crate) async fn serve(listener: std::net::TcpListener, state: AppState) {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(state, req).await) }
+            }))
+        }
+    });
+
+    if let Err(e) = listener.set_nonblocking(true) {
+        error!("failed to start the gRPC service: {}", e);
+        return;
+    }
+    let server = match Server::from_tcp(listener) {
+        Ok(builder) => builder.http2_only(true).serve(make_service),
+        Err(e) => {
+            error!("failed to start the gRPC service: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = server.await {
+        error!("gRPC service failed: {}", e);
+    }
+}
+
+/// Body of a gRPC response: at most one message, followed by the status trailers.
+///
+/// Without trailers, the status is carried by the response headers.
+#[derive(Debug, Default)]
+struct GrpcBody {
+    message: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl HttpBody for GrpcBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Infallible>>> {
+        Poll::Ready(self.message.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.message.is_none() && self.trailers.is_none()
+    }
+}
+
+/// Handle a gRPC call.
+async fn handle(state: AppState, req: Request<Body>) -> Response<GrpcBody> {
+    if req.method() != Method::POST || req.uri().path() != GET_GRAPH_PATH {
+        return status_response(code::UNIMPLEMENTED, "unknown method");
+    }
+
+    // Capabilities and debug ids are read from the request metadata.
+    let mut headers = actix_web::http::HeaderMap::new();
+    for (name, value) in req.headers() {
+        headers.append(name.clone(), value.clone());
+    }
+
+    let body = match grpc_hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return status_response(code::INTERNAL, &e.to_string()),
+    };
+
+    match get_graph(&state, &headers, &body).await {
+        Ok((message, stale_age)) => graph_response(message, stale_age),
+        Err(e) => {
+            debug!("gRPC graph request failed: {}", e);
+            status_response(status_code(&e), &e.to_string())
+        }
+    }
+}
+
+/// Process the graph for a `GetGraph` request.
+///
+/// Return the framed `Graph` message, and the age of the graph in seconds if
+/// a stale one is served.
+async fn get_graph(
+    state: &AppState,
+    headers: &actix_web::http::HeaderMap,
+    body: &[u8],
+) -> Result<(Vec<u8>, Option<String>), GraphError> {
+    state.maintenance.check()?;
+
+    let mut request: interface::PluginExchange = decode_message(body)?;
+    let client_params = request.take_parameters();
+    let missing: Vec<String> = state
+        .mandatory_params
+        .iter()
+        .filter(|param| !client_params.contains_key(*param))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(GraphError::MissingParams(missing));
+    }
+
+    let plugin_params = graph::plugin_params(headers, state, client_params);
+    let io = graph::process_io(state.live_plugins()?.iter(), plugin_params).await?;
+    let stale_age = io.parameters.internal(STALE_AGE_PARAM).cloned();
+
+    let message = encode_message(&interface::Graph::from(io.graph))?;
+    if let Some(limit) = state.max_graph_size {
+        if message.len() - MESSAGE_PREFIX_LEN > limit {
+            return Err(GraphError::GraphTooLarge(limit));
+        }
+    }
+
+    Ok((message, stale_age))
+}
+
+/// Decode a length-prefixed, uncompressed message.
+fn decode_message<M: Message>(body: &[u8]) -> Result<M, GraphError> {
+    if body.len() < MESSAGE_PREFIX_LEN {
+        return Err(GraphError::InvalidParams(
+            "truncated gRPC message".to_string(),
+        ));
+    }
+    if body[0] != 0 {
+        return Err(GraphError::InvalidParams(
+            "compressed gRPC messages are not supported".to_string(),
+        ));
+    }
+
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let message = &body[MESSAGE_PREFIX_LEN..];
+    if message.len() != len {
+        return Err(GraphError::InvalidParams(format!(
+            "expected a single gRPC message of {} bytes, got {} bytes",
+            len,
+            message.len()
+        )));
+    }
+
+    M::parse_from_bytes(message).map_err(|e| GraphError::InvalidParams(e.to_string()))
+}
+
+/// Encode a message, prefixed with its length.
+fn encode_message<M: Message>(message: &M) -> Result<Vec<u8>, GraphError> {
+    let bytes = message
+        .write_to_bytes()
+        .map_err(|e| GraphError::FailedPluginExecution(e.to_string()))?;
+
+    let mut framed = Vec::with_capacity(MESSAGE_PREFIX_LEN + bytes.len());
+    framed.push(0);
+    framed.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    framed.extend_from_slice(&bytes);
+    Ok(framed)
+}
+
+/// Map a graph error to a gRPC status code.
+fn status_code(e: &GraphError) -> u32 {
+    match e {
+        GraphError::MissingParams(_) | GraphError::InvalidParams(_) => code::INVALID_ARGUMENT,
+        GraphError::ReleaseNotFound(_) => code::NOT_FOUND,
+        GraphError::ServiceUnavailable(_, _) => code::UNAVAILABLE,
+        _ => code::INTERNAL,
+    }
+}
+
+/// Build a successful response, with the status in the trailers.
+fn graph_response(message: Vec<u8>, stale_age: Option<String>) -> Response<GrpcBody> {
+    let mut trailers = HeaderMap::new();
+    trailers.insert(GRPC_STATUS_HEADER, HeaderValue::from(code::OK));
+
+    let mut response = Response::new(GrpcBody {
+        message: Some(message.into()),
+        trailers: Some(trailers),
+    });
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_CONTENT_TYPE));
+    if let Some(age) = stale_age.and_then(|age| HeaderValue::from_str(&age).ok()) {
+        headers.insert(STALE_HEADER, HeaderValue::from_static("true"));
+        headers.insert(STALE_AGE_HEADER, age);
+    }
+    response
+}
+
+/// Build a response without messages, carrying the status in its headers.
+fn status_response(code: u32, message: &str) -> Response<GrpcBody> {
+    let mut response = Response::new(GrpcBody::default());
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_CONTENT_TYPE));
+    headers.insert(GRPC_STATUS_HEADER, HeaderValue::from(code));
+    if let Ok(message) = HeaderValue::from_str(&percent_encode(message)) {
+        headers.insert(GRPC_MESSAGE_HEADER, message);
+    }
+    response
+}
+
+/// Percent-encode a status message, as required by the gRPC protocol.
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|b| match b {
+            b' '..=b'~' if b != b'%' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::{call_index, common_init};
+    use crate::reload::PluginChain;
+    use actix_web::http;
+    use cincinnati::plugins::prelude::*;
+    use commons::prelude_errors::*;
+    use grpc_hyper::Client;
+    use std::collections::{BTreeSet, HashMap};
+
+    fn app_state(upstream: &str) -> Fallible<AppState> {
+        let plugins = cincinnati::plugins::catalog::build_plugins(
+            &[plugin_config!(
+                ("name", CincinnatiGraphFetchPlugin::PLUGIN_NAME),
+                (
+                    "upstream",
+                    &format!("{}{}", mockito::server_url(), upstream)
+                )
+            )?],
+            None,
+        )?;
+        Ok(AppState {
+            mandatory_params: vec!["channel".to_string()],
+            plugins: PluginChain::new(plugins),
+            ..Default::default()
+        })
+    }
+
+    /// Call `GetGraph` on the service at `addr`.
+    ///
+    /// Return the status, the decoded graph if any, and the status message.
+    async fn get_graph(
+        addr: std::net::SocketAddr,
+        params: &[(&str, &str)],
+    ) -> Fallible<(u32, Option<interface::Graph>, String)> {
+        let mut request = interface::PluginExchange::new();
+        request.set_parameters(
+            params
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+        let req = Request::post(format!("http://{}{}", addr, GET_GRAPH_PATH))
+            .header(CONTENT_TYPE, GRPC_CONTENT_TYPE)
+            .header("te", "trailers")
+            .body(Body::from(encode_message(&request)?))?;
+
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+        let resp = client.request(req).await?;
+        ensure!(
+            resp.status().is_success(),
+            "unexpected status {}",
+            resp.status()
+        );
+
+        let mut headers = resp.headers().clone();
+        let mut body = resp.into_body();
+        let mut data = vec![];
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk?);
+        }
+        if let Some(trailers) = body.trailers().await? {
+            headers.extend(trailers);
+        }
+
+        let code = headers
+            .get(GRPC_STATUS_HEADER)
+            .ok_or_else(|| format_err!("missing gRPC status"))?
+            .to_str()?
+            .parse()?;
+        let message = headers
+            .get(GRPC_MESSAGE_HEADER)
+            .map(|message| message.to_str().map(ToString::to_string))
+            .transpose()?
+            .unwrap_or_default();
+        let graph = if data.is_empty() {
+            None
+        } else {
+            Some(decode_message(&data)?)
+        };
+
+        Ok((code, graph, message))
+    }
+
+    /// Versions of the nodes, and edges by versions, of a graph message.
+    fn versions(graph: &interface::Graph) -> (BTreeSet<String>, BTreeSet<(String, String)>) {
+        let version = |index: u64| graph.get_nodes()[index as usize].get_version().to_string();
+        (
+            graph
+                .get_nodes()
+                .iter()
+                .map(|node| node.get_version().to_string())
+                .collect(),
+            graph
+                .get_edges()
+                .iter()
+                .map(|edge| (version(edge.get_from()), version(edge.get_to())))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn get_graph_matches_json() -> Fallible<()> {
+        let mut rt = common_init();
+
+        let _m = mockito::mock("GET", "/grpc-graph")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "nodes": [
+                        {"version": "1.0.0", "payload": "image/1.0.0", "metadata": {}},
+                        {"version": "2.0.0", "payload": "image/2.0.0", "metadata": {"foo": "bar"}},
+                        {"version": "3.0.0", "payload": "image/3.0.0", "metadata": {}}
+                    ],
+                    "edges": [[0, 1], [1, 2], [0, 2]]
+                }"#,
+            )
+            .create();
+
+        let state = app_state("/grpc-graph")?;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        rt.spawn(serve(listener, state.clone()));
+
+        let (code, grpc_graph, _) = rt.block_on(get_graph(addr, &[("channel", "stable")]))?;
+        assert_eq!(code, code::OK);
+        let grpc_graph = grpc_graph.ok_or_else(|| format_err!("expected a graph"))?;
+
+        let http_req = actix_web::test::TestRequest::with_uri("/v1/graph?channel=stable").header(
+            http::header::ACCEPT,
+            http::header::HeaderValue::from_static(cincinnati::CONTENT_TYPE),
+        );
+        let resp = rt.block_on(call_index(http_req, actix_web::web::Data::new(state)))?;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let json_graph: cincinnati::Graph = match resp.body() {
+            actix_web::dev::ResponseBody::Body(actix_web::dev::Body::Bytes(bytes)) => {
+                serde_json::from_slice(&bytes)?
+            }
+            _ => bail!("expected byte body"),
+        };
+
+        assert_eq!(versions(&grpc_graph), versions(&json_graph.into()));
+        let metadata: HashMap<String, String> = grpc_graph
+            .get_nodes()
+            .iter()
+            .find(|node| node.get_version() == "2.0.0")
+            .map(|node| node.get_metadata().clone())
+            .unwrap_or_default();
+        assert_eq!(metadata.get("foo").map(String::as_str), Some("bar"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_graph_missing_params() -> Fallible<()> {
+        let mut rt = common_init();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        rt.spawn(serve(listener, app_state("/grpc-missing")?));
+
+        let (code, graph, message) = rt.block_on(get_graph(addr, &[("arch", "amd64")]))?;
+        assert_eq!(code, code::INVALID_ARGUMENT);
+        assert!(graph.is_none());
+        assert_eq!(message, "mandatory client parameters missing");
+
+        Ok(())
+    }
+
+    #[test]
+    fn message_framing() {
+        let mut request = interface::PluginExchange::new();
+        request.set_parameters(
+            vec![("channel".to_string(), "stable".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let framed = encode_message(&request).unwrap();
+        let decoded: interface::PluginExchange = decode_message(&framed).unwrap();
+        assert_eq!(decoded, request);
+
+        assert!(decode_message::<interface::PluginExchange>(&framed[..3]).is_err());
+        assert!(decode_message::<interface::PluginExchange>(&framed[..framed.len() - 1]).is_err());
+        let mut compressed = framed;
+        compressed[0] = 1;
+        assert!(decode_message::<interface::PluginExchange>(&compressed).is_err());
+
+        assert_eq!(percent_encode("missing 100%: é"), "missing 100%25: %C3%A9");
+    }
+}
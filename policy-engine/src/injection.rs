@@ -0,0 +1,150 @@
+//! Rule-based injection of plugin parameters.
+//!
+//! Rules add static parameters to the plugin parameters of requests whose
+//! parameters match given values, e.g. `experiment=foo` for `channel=candidate`.
+//! This allows trying out plugin behavior on a subset of the clients.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Parameters injected into matching requests.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InjectionRule {
+    /// Parameter values which must all be present for the rule to match.
+    ///
+    /// A rule without conditions matches every request.
+    pub conditions: BTreeMap<String, String>,
+    /// Parameters injected into matching requests.
+    pub inject: BTreeMap<String, String>,
+    /// Whether injected parameters replace the ones already present.
+    pub override_existing: bool,
+}
+
+impl InjectionRule {
+    /// Whether the rule matches the given parameters.
+    pub fn matches(&self, params: &HashMap<String, String>) -> bool {
+        self.conditions
+            .iter()
+            .all(|(key, value)| params.get(key) == Some(value))
+    }
+}
+
+/// Set of parameter injection rules.
+#[derive(Clone, Debug, Default)]
+pub struct ParamInjection {
+    /// Rules, applied in order.
+    pub rules: Vec<InjectionRule>,
+}
+
+impl ParamInjection {
+    /// Inject the parameters of all rules matching the given parameters.
+    ///
+    /// Rules are matched against the parameters as they were before any
+    /// injection. Parameters already present, either sent by the client or
+    /// injected by a previous rule, are only replaced by rules configured to
+    /// override them.
+    pub fn apply(&self, params: &mut HashMap<String, String>) {
+        let matching: Vec<&InjectionRule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(params))
+            .collect();
+
+        for rule in matching {
+            for (key, value) in &rule.inject {
+                if !rule.override_existing && params.contains_key(key) {
+                    trace!("not overriding parameter '{}'", key);
+                    continue;
+                }
+                trace!("injecting parameter '{}={}'", key, value);
+                params.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn apply(injection: &ParamInjection, params: &[(&str, &str)]) -> HashMap<String, String> {
+        let mut params = map(params).into_iter().collect();
+        injection.apply(&mut params);
+        params
+    }
+
+    fn experiment() -> ParamInjection {
+        ParamInjection {
+            rules: vec![InjectionRule {
+                conditions: map(&[("channel", "candidate")]),
+                inject: map(&[("experiment", "foo")]),
+                override_existing: false,
+            }],
+        }
+    }
+
+    #[test]
+    fn inject_on_match() {
+        let params = apply(
+            &experiment(),
+            &[("channel", "candidate"), ("arch", "amd64")],
+        );
+
+        assert_eq!(params.get("experiment"), Some(&"foo".to_string()));
+        assert_eq!(params.len(), 3);
+    }
+
+    #[test]
+    fn no_injection_without_match() {
+        for params in &[vec![("channel", "stable")], vec![]] {
+            let params = apply(&experiment(), params);
+            assert_eq!(params.get("experiment"), None);
+        }
+    }
+
+    #[test]
+    fn client_values_precedence() {
+        let params = apply(
+            &experiment(),
+            &[("channel", "candidate"), ("experiment", "bar")],
+        );
+        assert_eq!(params.get("experiment"), Some(&"bar".to_string()));
+
+        let mut injection = experiment();
+        injection.rules[0].override_existing = true;
+        let params = apply(
+            &injection,
+            &[("channel", "candidate"), ("experiment", "bar")],
+        );
+        assert_eq!(params.get("experiment"), Some(&"foo".to_string()));
+    }
+
+    #[test]
+    fn rules_match_client_parameters() {
+        let injection = ParamInjection {
+            rules: vec![
+                InjectionRule {
+                    conditions: BTreeMap::new(),
+                    inject: map(&[("experiment", "foo")]),
+                    override_existing: false,
+                },
+                // Injected parameters don't trigger further rules.
+                InjectionRule {
+                    conditions: map(&[("experiment", "foo")]),
+                    inject: map(&[("variant", "b")]),
+                    override_existing: false,
+                },
+            ],
+        };
+
+        let params = apply(&injection, &[("channel", "stable")]);
+        assert_eq!(params.get("experiment"), Some(&"foo".to_string()));
+        assert_eq!(params.get("variant"), None);
+    }
+}
@@ -19,19 +19,48 @@ extern crate structopt;
 #[macro_use]
 extern crate custom_debug_derive;
 
+mod cache;
+mod capabilities;
+mod client_errors;
 mod config;
+mod debug;
+#[cfg(all(test, feature = "test-e2e"))]
+mod e2e;
+mod embedded;
 mod graph;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod injection;
+mod maintenance;
 mod openapi;
+mod reload;
+mod socket;
+mod watchdog;
 
 use actix_service::Service;
+use actix_web::web::Data;
 use actix_web::{middleware, App, HttpServer};
+use cache::{EtagCache, ResponseCache};
+use capabilities::CapabilitySettings;
 use cincinnati::plugins::BoxedPlugin;
+use client_errors::ClientErrors;
+use commons::access_log::{AccessLog, ACCESS_LOG_TARGET};
+use commons::build_info::BuildInfo;
+use commons::extractors::ValidatedQueryConfig;
+use commons::http::{IpNet, ResponseHeaders};
 use commons::metrics::{self, RegistryWrapper};
 use commons::prelude_errors::*;
-use commons::tracing::{get_tracer, init_tracer, set_span_tags};
-use opentelemetry::api::{trace::futures::Instrument, Tracer};
+use commons::tracing::{create_span_from_headers, init_tracer, DebugSampling, TagPolicy};
+use commons::GraphError;
+use futures::TryFutureExt;
+use injection::ParamInjection;
+use maintenance::Maintenance;
+use opentelemetry::api::trace::futures::Instrument;
 use prometheus::{labels, opts, Counter, Registry};
+use reload::PluginChain;
 use std::collections::HashSet;
+use std::sync::Arc;
+use watchdog::{Watchdog, WatchdogStatus};
 
 #[allow(dead_code)]
 /// Build info
@@ -60,86 +89,559 @@ fn main() -> Result<(), Error> {
     let sys = actix::System::new("policy-engine");
 
     let settings = config::AppSettings::assemble()?;
-    env_logger::Builder::from_default_env()
+    let mut logger = env_logger::Builder::from_default_env();
+    logger
         .filter(Some(module_path!()), settings.verbosity)
-        .filter(Some("cincinnati"), settings.verbosity)
-        .init();
+        .filter(Some("cincinnati"), settings.verbosity);
+    if settings.access_log {
+        logger.filter(Some(ACCESS_LOG_TARGET), log::LevelFilter::Info);
+    }
+    logger.init();
     debug!("application settings:\n{:#?}", &settings);
 
     // Metrics service.
     let registry: &'static Registry = Box::leak(Box::new(metrics::new_registry(Some(
         METRICS_PREFIX.to_string(),
     ))?));
-    graph::register_metrics(registry)?;
-    registry.register(Box::new(BUILD_INFO.clone()))?;
-    HttpServer::new(move || {
-        App::new()
-            .wrap(middleware::Compress::default())
-            .app_data(actix_web::web::Data::new(RegistryWrapper(registry)))
-            .service(
-                actix_web::web::resource("/metrics")
-                    .route(actix_web::web::get().to(metrics::serve::<RegistryWrapper>)),
-            )
-    })
-    .bind((settings.status_address, settings.status_port))?
-    .run();
+    register_metrics(registry)?;
 
     // Enable tracing
     init_tracer("policy-engine", settings.tracing_endpoint.clone())?;
 
+    // Embedded graph-builder, replacing the upstream graph source.
+    let embedded = match &settings.embedded_config_path {
+        Some(path) => Some(embedded::EmbeddedGraphBuilder::start(path)?),
+        None => None,
+    };
+
     // Main service.
-    let plugins = settings.validate_and_build_plugins(Some(registry))?;
+    let mut plugins = settings.validate_and_build_plugins(Some(registry))?;
+    if let Some(embedded) = &embedded {
+        plugins = embedded.wire_plugins(plugins);
+    }
+    let cache = ResponseCache::from_settings(&settings.cache).map(Arc::new);
+    let etag_cache = EtagCache::from_settings(&settings.cache).map(Arc::new);
     let state = AppState {
         mandatory_params: settings.mandatory_client_parameters.clone(),
         path_prefix: settings.path_prefix.clone(),
-        plugins: Box::leak(Box::new(plugins)),
+        plugins: PluginChain::new(plugins).with_caches(cache.clone(), etag_cache.clone()),
+        trusted_proxies: settings.trusted_proxies.clone(),
+        max_graph_size: settings.max_graph_size,
+        expose_warnings: settings.expose_warnings,
+        expose_adjacency: settings.expose_adjacency,
+        debug_sampling: settings.tracing_debug_sampling.map(|max_per_minute| {
+            Arc::new(DebugSampling::new(
+                settings.tracing_debug_header.clone(),
+                max_per_minute,
+            ))
+        }),
+        tracing_tags: Arc::new(settings.tracing_tags.clone()),
+        capabilities: settings.capabilities.clone(),
+        param_injection: settings.param_injection.clone(),
+        maintenance: Maintenance::new(settings.maintenance.clone()),
+        cache,
+        etag_cache,
+        forwarded_headers: settings.forwarded_headers.clone(),
+        // The media type of graphs is negotiated, their encoding isn't.
+        response_headers: settings.response_headers.clone().vary(&["Accept"]),
+        serve_empty_graph: settings.on_empty_plugin_chain.serves_empty_graph(),
     };
+    commons::set_error_response_headers(settings.error_response_headers.clone());
+
+    // Response cache pre-warming.
+    actix::Arbiter::spawn(cache::run_prewarm(state.clone()));
 
+    // Plugin reloading.
+    let reload_embedded = embedded.clone();
+    actix::Arbiter::spawn(reload::reload_on_sighup(state.plugins.clone(), move || {
+        let settings = config::AppSettings::assemble()?;
+        let mut plugins = settings.validate_and_build_plugins(Some(registry))?;
+        if let Some(embedded) = &reload_embedded {
+            plugins = embedded.wire_plugins(plugins);
+        }
+        Ok(plugins)
+    }));
+
+    // Status service.
+    //
+    // actix calls the app factories once per worker, so all the shared state
+    // is created here, once, and only cloned in the factories.
+    let status_registry = Data::new(RegistryWrapper(registry));
+    let status_build_info = Data::new(build_info(&settings));
+    let debug_state = settings.debug_token.clone().map(|token| {
+        Data::new(debug::DebugState {
+            token,
+            plugins: state.plugins.clone(),
+            plugin_allowlist: settings.plugin_allowlist.clone(),
+        })
+    });
+    let status_maintenance = Data::new(state.maintenance.clone());
+    let status_plugins = Data::new(state.plugins.clone());
+    let status_max_graph_age = Data::new(reload::MaxGraphAge(settings.max_graph_age));
+    let watchdog_status = WatchdogStatus::default();
+    let status_watchdog = Data::new(watchdog_status.clone());
+    let client_errors = ClientErrors::from_settings(&settings.client_errors).map(|history| {
+        Data::new(history.with_redacted_params(settings.access_log_redacted_params.clone()))
+    });
+    let status_client_errors = client_errors.clone();
     HttpServer::new(move || {
-        let app_prefix = state.path_prefix.clone();
         App::new()
-            .wrap_fn(|req, srv| {
-                let span = get_tracer().start("request", None);
-                set_span_tags(&req, &span);
-                srv.call(req).instrument(span)
-            })
-            .app_data(actix_web::web::Data::<AppState>::new(state.clone()))
+            .wrap(middleware::Compress::default())
+            .app_data(status_registry.clone())
+            .app_data(status_build_info.clone())
+            .app_data(status_plugins.clone())
+            .app_data(status_max_graph_age.clone())
+            .app_data(status_watchdog.clone())
+            .service(
+                actix_web::web::resource("/healthz")
+                    .route(actix_web::web::get().to(reload::serve_health)),
+            )
+            .service(
+                actix_web::web::resource("/readyz")
+                    .route(actix_web::web::get().to(reload::serve_readiness)),
+            )
             .service(
-                actix_web::web::resource(&format!("{}/v1/graph", app_prefix))
-                    .route(actix_web::web::get().to(graph::index)),
+                actix_web::web::resource("/metrics")
+                    .route(actix_web::web::get().to(metrics::serve::<RegistryWrapper>)),
             )
             .service(
-                actix_web::web::resource(&format!("{}/v1/openapi", app_prefix))
-                    .route(actix_web::web::get().to(openapi::index)),
+                actix_web::web::resource("/status/build")
+                    .route(actix_web::web::get().to(commons::build_info::serve)),
             )
+            .configure(|cfg| {
+                if let Some(embedded) = &embedded {
+                    embedded.configure_status(cfg);
+                }
+            })
+            .configure(|cfg| {
+                // Debugging and admin endpoints are only served if a token is configured.
+                if let Some(debug_state) = &debug_state {
+                    cfg.service(
+                        actix_web::web::resource("/debug/pipeline-diff")
+                            .app_data(debug_state.clone())
+                            .route(actix_web::web::post().to(debug::pipeline_diff)),
+                    )
+                    .service(
+                        actix_web::web::resource("/debug/simulate")
+                            .app_data(debug_state.clone())
+                            .route(actix_web::web::post().to(debug::simulate)),
+                    )
+                    .service(
+                        actix_web::web::resource("/admin/maintenance")
+                            .app_data(debug_state.clone())
+                            .app_data(status_maintenance.clone())
+                            .route(actix_web::web::get().to(maintenance::get_maintenance))
+                            .route(actix_web::web::put().to(maintenance::put_maintenance)),
+                    );
+
+                    if let Some(status_client_errors) = &status_client_errors {
+                        cfg.service(
+                            actix_web::web::resource("/debug/client-errors/{hashed_id}")
+                                .app_data(debug_state.clone())
+                                .app_data(status_client_errors.clone())
+                                .route(actix_web::web::get().to(client_errors::serve)),
+                        );
+                    }
+                }
+            })
     })
-    .keep_alive(10)
-    .bind((settings.address, settings.port))?
+    .bind((settings.status_address, settings.status_port))?
     .run();
 
+    #[cfg(feature = "grpc")]
+    let grpc_state = state.clone();
+    let access_log_enabled = settings.access_log;
+    let access_log = AccessLog::new(settings.access_log_redacted_params.clone())
+        .with_trusted_proxies(settings.trusted_proxies.clone());
+    let error_catalogs = Arc::new(settings.error_catalogs.clone());
+    let query_config = ValidatedQueryConfig::new(state.mandatory_params.iter().cloned().collect());
+    let main_state = Data::new(state);
+    let main_server = HttpServer::new(move || {
+        let app_prefix = main_state.path_prefix.clone();
+        let expose_adjacency = main_state.expose_adjacency;
+        let debug_sampling = main_state.debug_sampling.clone();
+        let tracing_tags = main_state.tracing_tags.clone();
+        let error_catalogs = error_catalogs.clone();
+        let client_errors = client_errors.clone();
+        App::new()
+            // Errors are recorded before their response is localized.
+            .wrap_fn(move |req, srv| {
+                let client_errors = client_errors.clone();
+                srv.call(req).map_ok(move |response| {
+                    if let Some(client_errors) = &client_errors {
+                        client_errors.record_response(&response);
+                    }
+                    response
+                })
+            })
+            .wrap_fn(move |req, srv| {
+                let error_catalogs = error_catalogs.clone();
+                srv.call(req)
+                    .map_ok(move |response| error_catalogs.localize_response(response))
+            })
+            .wrap_fn(move |req, srv| {
+                let span = create_span_from_headers(
+                    "request",
+                    None,
+                    req.headers(),
+                    debug_sampling.as_deref(),
+                );
+                tracing_tags.set_span_tags(&req, &span);
+                srv.call(req).instrument(span)
+            })
+            .wrap(middleware::Condition::new(
+                access_log_enabled,
+                access_log.clone(),
+            ))
+            .app_data(main_state.clone())
+            .app_data(query_config.clone())
+            .configure(|cfg| configure_main_service(cfg, &app_prefix, expose_adjacency))
+    })
+    .keep_alive(10);
+    let main_server = match settings.max_connections {
+        Some(max_connections) => main_server.max_connections(max_connections),
+        None => main_server,
+    };
+    let main_server = match &settings.socket_path {
+        Some(socket_path) => {
+            socket::remove_stale_socket(socket_path)?;
+            main_server.bind_uds(socket_path)?
+        }
+        None => main_server.bind((settings.address, settings.port))?,
+    };
+    main_server.run();
+
+    // Watchdog of the main service.
+    if settings.watchdog.interval.is_some() {
+        let url = watchdog::probe_url(settings.address, settings.port, &settings.path_prefix);
+        let prober = watchdog::HttpProber::new(url, settings.watchdog.timeout)?;
+        actix::Arbiter::spawn(
+            Watchdog::new(settings.watchdog.clone(), Box::new(prober), watchdog_status).run(),
+        );
+    }
+
+    // gRPC service.
+    #[cfg(feature = "grpc")]
+    {
+        if let Some(grpc_port) = settings.grpc_port {
+            let listener = std::net::TcpListener::bind((settings.address, grpc_port))?;
+            actix::Arbiter::spawn(grpc::serve(listener, grpc_state));
+        }
+    }
+
     BUILD_INFO.inc();
 
     let _ = sys.run();
     Ok(())
 }
 
+/// Register the metrics of all modules.
+///
+/// This is called once per registry, outside of the app factories: metrics
+/// can't be registered twice.
+fn register_metrics(registry: &Registry) -> Fallible<()> {
+    graph::register_metrics(registry)?;
+    cache::register_metrics(registry)?;
+    reload::register_metrics(registry)?;
+    watchdog::register_metrics(registry)?;
+    registry.register(Box::new(BUILD_INFO.clone()))?;
+    Ok(())
+}
+
+/// Register the endpoints of the main service, under the given namespace.
+///
+/// The adjacency endpoint is only registered if `expose_adjacency` is set.
+fn configure_main_service(
+    cfg: &mut actix_web::web::ServiceConfig,
+    app_prefix: &str,
+    expose_adjacency: bool,
+) {
+    if expose_adjacency {
+        cfg.service(
+            actix_web::web::resource(&format!("{}/v1/graph/adjacency", app_prefix))
+                .route(actix_web::web::get().to(graph::adjacency)),
+        );
+    }
+    cfg.service(
+        actix_web::web::resource(&format!("{}/v1/graph", app_prefix))
+            .route(actix_web::web::get().to(graph::index)),
+    )
+    .service(
+        actix_web::web::resource(&format!("{}/v1/release/{{version}}", app_prefix))
+            .route(actix_web::web::get().to(graph::release)),
+    )
+    .service(
+        actix_web::web::resource(&format!("{}/v1/graph.dot", app_prefix))
+            .route(actix_web::web::get().to(graph::dot)),
+    )
+    .service(
+        actix_web::web::resource(&format!("{}/v1/openapi", app_prefix))
+            .route(actix_web::web::get().to(openapi::index)),
+    );
+}
+
+/// Assemble the build information exposed on `/status/build`.
+fn build_info(settings: &config::AppSettings) -> BuildInfo {
+    commons::build_info!(built_info, settings.tracing_endpoint.is_some())
+}
+
 /// Shared application configuration (cloned per-thread).
 #[derive(Clone, Debug)]
 struct AppState {
-    /// Query parameters that must be present in all client requests.
-    pub mandatory_params: HashSet<String>,
+    /// Query parameters that must be present in all client requests, in configuration order.
+    pub mandatory_params: Vec<String>,
     /// Upstream cincinnati service.
     pub path_prefix: String,
-    /// Policy plugins.
-    pub plugins: &'static [BoxedPlugin],
+    /// Policy plugins, reloaded on SIGHUP.
+    pub plugins: PluginChain,
+    /// Proxies trusted to report the client address.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Maximum size of a serialized graph response, in bytes.
+    pub max_graph_size: Option<usize>,
+    /// Whether to expose the warnings recorded by the plugins in graph responses.
+    pub expose_warnings: bool,
+    /// Whether to serve the adjacency summary of graphs.
+    pub expose_adjacency: bool,
+    /// Forced sampling of requests carrying a debug id, disabled if unset.
+    pub debug_sampling: Option<Arc<DebugSampling>>,
+    /// Policy for the tags recorded on request spans.
+    pub tracing_tags: Arc<TagPolicy>,
+    /// Mapping from client versions to capability flags.
+    pub capabilities: CapabilitySettings,
+    /// Rules injecting plugin parameters into matching requests.
+    pub param_injection: ParamInjection,
+    /// Maintenance mode, suspending graph serving.
+    pub maintenance: Maintenance,
+    /// Cache of graph responses, disabled if unset.
+    pub cache: Option<Arc<ResponseCache>>,
+    /// Cache of graphs for the current upstream ETag, disabled if unset.
+    pub etag_cache: Option<Arc<EtagCache>>,
+    /// Request headers forwarded to the plugins, by lowercase name.
+    pub forwarded_headers: HashSet<String>,
+    /// Headers set on successful graph responses.
+    pub response_headers: ResponseHeaders,
+    /// Whether to serve empty graphs from an empty plugin chain, instead of failing requests.
+    pub serve_empty_graph: bool,
+}
+
+impl AppState {
+    /// Return the live policy plugins.
+    ///
+    /// An empty chain fails as unavailable unless empty graphs are served, so
+    /// that clients don't take a misconfiguration for the absence of upgrades.
+    fn live_plugins(&self) -> Result<Arc<Vec<BoxedPlugin>>, GraphError> {
+        let plugins = self.plugins.current();
+        if plugins.is_empty() && !self.serve_empty_graph {
+            return Err(GraphError::ServiceUnavailable(
+                "no policy plugins configured".to_string(),
+                None,
+            ));
+        }
+        Ok(plugins)
+    }
 }
 
 impl Default for AppState {
     fn default() -> Self {
         Self {
-            plugins: Box::leak(Box::new([])),
-            mandatory_params: HashSet::new(),
+            plugins: PluginChain::default(),
+            mandatory_params: vec![],
             path_prefix: String::new(),
+            trusted_proxies: vec![],
+            max_graph_size: None,
+            expose_warnings: false,
+            expose_adjacency: false,
+            debug_sampling: None,
+            tracing_tags: Arc::new(TagPolicy::default()),
+            capabilities: CapabilitySettings::default(),
+            param_injection: ParamInjection::default(),
+            maintenance: Maintenance::default(),
+            cache: None,
+            etag_cache: None,
+            forwarded_headers: HashSet::new(),
+            response_headers: ResponseHeaders::default(),
+            serve_empty_graph: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing;
+
+    #[test]
+    fn serve_graph_over_unix_socket() -> Fallible<()> {
+        use cincinnati::plugins::prelude::*;
+        use std::io::{Read, Write};
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let dir = tempfile::tempdir()?;
+        let socket_path = dir.path().join("policy-engine.sock");
+
+        // Leave a stale socket behind, as a crashed instance would.
+        drop(UnixListener::bind(&socket_path)?);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server_path = socket_path.clone();
+        std::thread::spawn(move || -> Fallible<()> {
+            let sys = actix::System::new("policy-engine-uds-test");
+            let state = Data::new(AppState {
+                plugins: PluginChain::new(new_plugins!(InternalPluginWrapper(
+                    graph::tests::MetadataGraphPlugin
+                ))),
+                ..Default::default()
+            });
+
+            socket::remove_stale_socket(&server_path)?;
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(state.clone())
+                    .app_data(ValidatedQueryConfig::new(HashSet::new()))
+                    .configure(|cfg| configure_main_service(cfg, "", false))
+            })
+            .workers(1)
+            .bind_uds(&server_path)?
+            .run();
+            let _ = tx.send(server);
+
+            sys.run()?;
+            Ok(())
+        });
+        let server = rx.recv()?;
+
+        let mut stream = UnixStream::connect(&socket_path)?;
+        write!(
+            stream,
+            "GET /v1/graph HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+            cincinnati::CONTENT_TYPE
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        let mut testing_rt = testing::init_runtime()?;
+        testing_rt.block_on(server.stop(true));
+
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "response: {}",
+            response
+        );
+        let body = match response.find("\r\n\r\n") {
+            Some(pos) => &response[pos + 4..],
+            None => bail!("missing response body: {}", response),
+        };
+        let graph: cincinnati::Graph = serde_json::from_str(body)?;
+        assert_eq!(graph.releases_count(), 2);
+
+        Ok(())
+    }
+
+    /// Send a GET request over HTTP/1.1, returning the raw response.
+    fn http_get(addr: std::net::SocketAddr, path: &str) -> Fallible<String> {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(addr)?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+            path,
+            cincinnati::CONTENT_TYPE
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn servers_share_state() -> Fallible<()> {
+        use crate::graph::tests::{MetadataGraphPlugin, RunCounter};
+        use cincinnati::plugins::prelude::*;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        // Metrics are registered once, whatever the number of servers and workers.
+        let registry: &'static Registry = Box::leak(Box::new(metrics::new_registry(Some(
+            METRICS_PREFIX.to_string(),
+        ))?));
+        register_metrics(registry)?;
+        register_metrics(registry).unwrap_err();
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let state = Data::new(AppState {
+            plugins: PluginChain::new(new_plugins!(
+                InternalPluginWrapper(MetadataGraphPlugin),
+                InternalPluginWrapper(RunCounter(runs.clone()))
+            )),
+            cache: Some(Arc::new(ResponseCache::new(
+                Duration::from_secs(60),
+                10,
+                vec![],
+            ))),
+            ..Default::default()
+        });
+        let registry_data = Data::new(RegistryWrapper(registry));
+        let query_config = ValidatedQueryConfig::new(HashSet::new());
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || -> Fallible<()> {
+            let sys = actix::System::new("policy-engine-shared-state-test");
+
+            let mut servers = vec![];
+            for _ in 0..2 {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+                let addr = listener.local_addr()?;
+                let (state, registry_data, query_config) =
+                    (state.clone(), registry_data.clone(), query_config.clone());
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(state.clone())
+                        .app_data(registry_data.clone())
+                        .app_data(query_config.clone())
+                        .service(
+                            actix_web::web::resource("/metrics")
+                                .route(actix_web::web::get().to(metrics::serve::<RegistryWrapper>)),
+                        )
+                        .configure(|cfg| configure_main_service(cfg, "", false))
+                })
+                .workers(2)
+                .listen(listener)?
+                .run();
+                servers.push((addr, server));
+            }
+            let _ = tx.send(servers);
+
+            sys.run()?;
+            Ok(())
+        });
+        let servers = rx.recv()?;
+
+        // The graph is computed once, and served from the shared cache afterwards.
+        let mut bodies = vec![];
+        for (addr, _) in servers.iter().chain(servers.iter()) {
+            let response = http_get(*addr, "/v1/graph")?;
+            assert!(
+                response.starts_with("HTTP/1.1 200 OK\r\n"),
+                "response: {}",
+                response
+            );
+            bodies.push(response[response.find("\r\n\r\n").unwrap_or(0)..].to_string());
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(bodies.windows(2).all(|pair| pair[0] == pair[1]));
+
+        let response = http_get(servers[1].0, "/metrics")?;
+        assert!(
+            response.contains("cincinnati_pe_build_info"),
+            "response: {}",
+            response
+        );
+
+        let mut testing_rt = testing::init_runtime()?;
+        for (_, server) in servers {
+            testing_rt.block_on(server.stop(true));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+//! Maintenance mode, deliberately suspending graph serving.
+//!
+//! While maintenance is active, graph requests are answered with a
+//! `ServiceUnavailable` error carrying a human-readable message, and with a
+//! `Retry-After` header if the end of the maintenance window is known. The
+//! status service keeps working.
+//!
+//! Maintenance is configured by a window in the settings, and can be replaced
+//! at runtime via an admin endpoint of the status service.
+
+use crate::debug::{DebugError, DebugState};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use commons::prelude_errors::*;
+use commons::GraphError;
+use std::convert::TryFrom;
+use std::sync::{Arc, RwLock};
+
+/// Default message returned to clients during maintenance.
+pub static DEFAULT_MESSAGE: &str = "the service is under maintenance, please retry later";
+
+/// Maintenance window.
+#[derive(Clone, Debug, SmartDefault, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    /// Whether maintenance is enabled at all.
+    pub enabled: bool,
+    /// Start of the window, unbounded if unset.
+    pub start: Option<DateTime<Utc>>,
+    /// End of the window, unbounded if unset.
+    pub end: Option<DateTime<Utc>>,
+    /// Message returned to clients.
+    #[default(DEFAULT_MESSAGE.to_string())]
+    pub message: String,
+}
+
+impl MaintenanceWindow {
+    /// Whether maintenance is active at the given time.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.enabled
+            && self.start.map_or(true, |start| start <= now)
+            && self.end.map_or(true, |end| now < end)
+    }
+
+    /// Return an error if maintenance is active at the given time.
+    ///
+    /// The error carries the number of seconds until the end of the window,
+    /// if it is known.
+    pub fn check(&self, now: DateTime<Utc>) -> Result<(), GraphError> {
+        if !self.is_active(now) {
+            return Ok(());
+        }
+
+        let retry_after = self
+            .end
+            .map(|end| std::cmp::max((end - now).num_seconds(), 1) as u64);
+        Err(GraphError::ServiceUnavailable(
+            self.message.clone(),
+            retry_after,
+        ))
+    }
+}
+
+/// Maintenance window, as exchanged with the admin endpoint.
+///
+/// Timestamps are in RFC 3339 format.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MaintenanceRequest {
+    /// Whether maintenance is enabled at all.
+    pub enabled: bool,
+    /// Start of the window, unbounded if unset.
+    pub start: Option<String>,
+    /// End of the window, unbounded if unset.
+    pub end: Option<String>,
+    /// Message returned to clients, a default one is used if unset.
+    pub message: Option<String>,
+}
+
+impl TryFrom<MaintenanceRequest> for MaintenanceWindow {
+    type Error = Error;
+
+    fn try_from(request: MaintenanceRequest) -> Fallible<Self> {
+        let window = MaintenanceWindow {
+            enabled: request.enabled,
+            start: request.start.as_deref().map(parse_timestamp).transpose()?,
+            end: request.end.as_deref().map(parse_timestamp).transpose()?,
+            message: request
+                .message
+                .unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+        };
+
+        if let (Some(start), Some(end)) = (window.start, window.end) {
+            ensure!(start < end, "maintenance window ends before it starts");
+        }
+
+        Ok(window)
+    }
+}
+
+impl From<MaintenanceWindow> for MaintenanceRequest {
+    fn from(window: MaintenanceWindow) -> Self {
+        MaintenanceRequest {
+            enabled: window.enabled,
+            start: window.start.map(|start| start.to_rfc3339()),
+            end: window.end.map(|end| end.to_rfc3339()),
+            message: Some(window.message),
+        }
+    }
+}
+
+/// Parse an RFC 3339 timestamp.
+pub fn parse_timestamp(input: &str) -> Fallible<DateTime<Utc>> {
+    let timestamp = DateTime::parse_from_rfc3339(input)
+        .context(format!("invalid RFC 3339 timestamp '{}'", input))?;
+    Ok(timestamp.with_timezone(&Utc))
+}
+
+/// Maintenance mode, shared by the main and status services.
+#[derive(Clone, Debug, Default)]
+pub struct Maintenance(Arc<RwLock<MaintenanceWindow>>);
+
+impl Maintenance {
+    /// Create the maintenance mode with the given window.
+    pub fn new(window: MaintenanceWindow) -> Self {
+        Maintenance(Arc::new(RwLock::new(window)))
+    }
+
+    /// Return the current window.
+    pub fn window(&self) -> MaintenanceWindow {
+        match self.0.read() {
+            Ok(window) => window.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replace the current window.
+    pub fn set(&self, window: MaintenanceWindow) {
+        match self.0.write() {
+            Ok(mut current) => *current = window,
+            Err(poisoned) => *poisoned.into_inner() = window,
+        }
+    }
+
+    /// Return an error if maintenance is currently active.
+    pub fn check(&self) -> Result<(), GraphError> {
+        self.window().check(Utc::now())
+    }
+}
+
+/// Serve the current maintenance window.
+pub(crate) async fn get_maintenance(
+    req: HttpRequest,
+    state: web::Data<DebugState>,
+    maintenance: web::Data<Maintenance>,
+) -> Result<HttpResponse, DebugError> {
+    state.authorize(&req)?;
+
+    Ok(HttpResponse::Ok().json(MaintenanceRequest::from(maintenance.window())))
+}
+
+/// Replace the maintenance window.
+pub(crate) async fn put_maintenance(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<DebugState>,
+    maintenance: web::Data<Maintenance>,
+) -> Result<HttpResponse, DebugError> {
+    state.authorize(&req)?;
+
+    let request: MaintenanceRequest =
+        serde_json::from_slice(&body).map_err(|e| DebugError::InvalidRequest(e.to_string()))?;
+    let window = MaintenanceWindow::try_from(request)
+        .map_err(|e| DebugError::InvalidRequest(format!("{:#}", e)))?;
+
+    info!("maintenance window replaced: {:?}", window);
+    maintenance.set(window);
+
+    Ok(HttpResponse::Ok().json(MaintenanceRequest::from(maintenance.window())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{
+        self,
+        tests::{call_index, common_init},
+    };
+    use crate::AppState;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::App;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        parse_timestamp(timestamp).unwrap()
+    }
+
+    #[test]
+    fn window_bounds() {
+        let window = MaintenanceWindow {
+            enabled: true,
+            start: Some(at("2021-03-01T10:00:00Z")),
+            end: Some(at("2021-03-01T12:00:00+01:00")),
+            message: "registry migration".to_string(),
+        };
+
+        assert!(!window.is_active(at("2021-03-01T09:59:59Z")));
+        assert!(window.is_active(at("2021-03-01T10:00:00Z")));
+        assert!(window.is_active(at("2021-03-01T10:59:59Z")));
+        assert!(!window.is_active(at("2021-03-01T11:00:00Z")));
+
+        assert_eq!(
+            window.check(at("2021-03-01T10:30:00Z")),
+            Err(GraphError::ServiceUnavailable(
+                "registry migration".to_string(),
+                Some(30 * 60)
+            ))
+        );
+        assert_eq!(window.check(at("2021-03-01T11:30:00Z")), Ok(()));
+
+        let disabled = MaintenanceWindow {
+            enabled: false,
+            ..window
+        };
+        assert!(!disabled.is_active(at("2021-03-01T10:30:00Z")));
+    }
+
+    #[test]
+    fn open_window() {
+        let window = MaintenanceWindow {
+            enabled: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            window.check(at("2021-03-01T10:30:00Z")),
+            Err(GraphError::ServiceUnavailable(
+                DEFAULT_MESSAGE.to_string(),
+                None
+            ))
+        );
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let response =
+            GraphError::ServiceUnavailable("back soon".to_string(), Some(60)).as_json_error();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "60");
+    }
+
+    #[test]
+    fn invalid_request() {
+        for request in vec![
+            MaintenanceRequest {
+                enabled: true,
+                start: Some("yesterday".to_string()),
+                ..Default::default()
+            },
+            MaintenanceRequest {
+                enabled: true,
+                start: Some("2021-03-01T12:00:00Z".to_string()),
+                end: Some("2021-03-01T10:00:00Z".to_string()),
+                ..Default::default()
+            },
+        ] {
+            MaintenanceWindow::try_from(request).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn runtime_toggle() -> Fallible<()> {
+        let mut rt = common_init();
+        let maintenance = Maintenance::default();
+        let state = DebugState {
+            token: "secret".to_string(),
+            plugins: crate::reload::PluginChain::default(),
+            plugin_allowlist: Default::default(),
+        };
+
+        let statuses = rt.block_on(async {
+            let app = App::new()
+                .app_data(web::Data::new(state))
+                .app_data(web::Data::new(maintenance.clone()))
+                .service(
+                    web::resource("/admin/maintenance")
+                        .route(web::get().to(get_maintenance))
+                        .route(web::put().to(put_maintenance)),
+                );
+            let mut svc = actix_web::test::init_service(app).await;
+
+            let mut statuses = vec![];
+            for (token, body) in &[
+                ("wrong", r#"{"enabled": true}"#),
+                ("secret", r#"{"enabled": true, "message": "back soon"}"#),
+            ] {
+                let req = actix_web::test::TestRequest::put()
+                    .uri("/admin/maintenance")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                    .set_payload(*body)
+                    .to_request();
+                statuses.push(actix_web::test::call_service(&mut svc, req).await.status());
+            }
+            statuses
+        });
+        assert_eq!(statuses, vec![StatusCode::UNAUTHORIZED, StatusCode::OK]);
+
+        // Graph requests are rejected, independently of their content.
+        let app_data = web::Data::new(AppState {
+            maintenance: maintenance.clone(),
+            ..Default::default()
+        });
+        let http_req = actix_web::test::TestRequest::get();
+        assert_eq!(
+            rt.block_on(call_index(http_req, app_data)).unwrap_err(),
+            GraphError::ServiceUnavailable("back soon".to_string(), None)
+        );
+
+        maintenance.set(MaintenanceWindow::default());
+        assert_eq!(maintenance.check(), Ok(()));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,14 @@
+//! OpenAPI document of the policy-engine.
+//!
+//! The document is generated on each request from a static template, with
+//! the parameters of the graph endpoints honored by this deployment: the
+//! configured mandatory parameters, and those declared by the live plugins.
+//! Reloaded plugins are thus documented right away.
+
+use crate::cache::ResponseCache;
 use crate::AppState;
 use actix_web::HttpResponse;
+use cincinnati::plugins::ParameterDeclaration;
 use commons::prelude_errors::*;
 use openapiv3::{OpenAPI, ReferenceOr};
 use std::collections::HashSet;
@@ -19,9 +28,26 @@ pub(crate) fn index(app_data: actix_web::web::Data<AppState>) -> HttpResponse {
             }
         };
 
-    // Add mandatory parameters to the `graph` endpoint.
-    if let Some(path) = spec_object.paths.get_mut("/v1/graph") {
-        add_mandatory_params(path, &app_data.mandatory_params);
+    // Leave out the adjacency endpoint unless it is served.
+    if !app_data.expose_adjacency {
+        spec_object.paths = spec_object
+            .paths
+            .into_iter()
+            .filter(|(path, _)| path != "/v1/graph/adjacency")
+            .collect();
+    }
+
+    // Add the honored parameters to the `graph` endpoints.
+    let params = graph_params(&app_data);
+    for graph_path in &[
+        "/v1/graph",
+        "/v1/graph/adjacency",
+        "/v1/graph.dot",
+        "/v1/release/{version}",
+    ] {
+        if let Some(path) = spec_object.paths.get_mut(*graph_path) {
+            add_params(path, &params);
+        }
     }
 
     // Prefix all paths with `path_prefix`
@@ -47,42 +73,83 @@ fn rewrite_paths(paths: openapiv3::Paths, path_prefix: &str) -> openapiv3::Paths
         .collect()
 }
 
-// Add mandatory parameters to the `graph` endpoint.
-fn add_mandatory_params(path: &mut ReferenceOr<openapiv3::PathItem>, reqs: &HashSet<String>) {
-    // Template for building an `openapiv3::Parameter`, which otherwise has private fields.
-    static PARAM_TEMPLATE: &str = r#"
-{
-    "in": "query",
-    "name": "TEMPLATE",
-    "required": true,
-    "schema": {
-        "type": "string"
+/// Build the parameters honored by the `graph` endpoints.
+///
+/// The mandatory parameters come first, in configuration order, followed by
+/// the optional parameters declared by the live plugins, in plugin order.
+fn graph_params(app_data: &AppState) -> Vec<openapiv3::Parameter> {
+    let declarations: Vec<ParameterDeclaration> = app_data
+        .plugins
+        .current()
+        .iter()
+        .flat_map(|plugin| plugin.declared_parameters())
+        .collect();
+    let declaration = |name: &str| declarations.iter().find(|decl| decl.name == name);
+    let cache = app_data.cache.as_deref();
+
+    let mut params: Vec<openapiv3::Parameter> = app_data
+        .mandatory_params
+        .iter()
+        .map(|name| query_param(name, true, declaration(name), cache))
+        .collect();
+    let mut seen: HashSet<&str> = app_data
+        .mandatory_params
+        .iter()
+        .map(String::as_str)
+        .collect();
+    for decl in &declarations {
+        if seen.insert(&decl.name) {
+            params.push(query_param(&decl.name, false, Some(decl), cache));
+        }
     }
+
+    params
 }
-"#;
 
-    match path {
-        ReferenceOr::Item(item) => {
-            let template: openapiv3::Parameter =
-                serde_json::from_str(PARAM_TEMPLATE).expect("hardcoded deserialization failed");
-            for key in reqs {
-                let mut data = template.clone();
-                match data {
-                    openapiv3::Parameter::Query {
-                        parameter_data: ref mut p,
-                        ..
-                    } => {
-                        p.name = key.clone();
-                    }
-                    _ => {
-                        error!("non-query parameters not allowed");
-                        continue;
-                    }
-                };
-                let param = ReferenceOr::Item(data);
-                item.parameters.push(param);
-            }
+/// Build a query parameter, documenting whether it participates in cache keys.
+fn query_param(
+    name: &str,
+    required: bool,
+    declaration: Option<&ParameterDeclaration>,
+    cache: Option<&ResponseCache>,
+) -> openapiv3::Parameter {
+    let mut description: Vec<String> = declaration
+        .map(|decl| decl.description.clone())
+        .into_iter()
+        .collect();
+    if let Some(cache) = cache {
+        description.push(if cache.key_includes(name) {
+            "Participates in response cache keys".to_string()
+        } else {
+            "Doesn't participate in response cache keys".to_string()
+        });
+    }
+
+    // `openapiv3::Parameter` has private fields, so it is built from JSON.
+    let mut param = serde_json::json!({
+        "in": "query",
+        "name": name,
+        "required": required,
+        "schema": {
+            "type": "string"
         }
+    });
+    if !description.is_empty() {
+        param["description"] = description.join(". ").into();
+    }
+    if let Some(pattern) = declaration.and_then(|decl| decl.pattern.as_ref()) {
+        param["schema"]["pattern"] = pattern.as_str().into();
+    }
+
+    serde_json::from_value(param).expect("hardcoded deserialization failed")
+}
+
+/// Add the given parameters to a `graph` endpoint.
+fn add_params(path: &mut ReferenceOr<openapiv3::PathItem>, params: &[openapiv3::Parameter]) {
+    match path {
+        ReferenceOr::Item(item) => item
+            .parameters
+            .extend(params.iter().cloned().map(ReferenceOr::Item)),
         _ => error!("reference manipulation for paths not allowed"),
     };
 }
@@ -116,17 +183,19 @@ mod tests {
 
     #[test]
     fn graph_params() {
-        use super::{add_mandatory_params, SPEC};
+        use super::{add_params, query_param, SPEC};
         use openapiv3::OpenAPI;
 
-        let params: HashSet<String> = vec!["MARKER1".to_string(), "MARKER2".to_string()]
-            .into_iter()
-            .collect();
+        let params = vec!["MARKER1".to_string(), "MARKER2".to_string()];
         let mut spec: OpenAPI = serde_json::from_str(SPEC).expect("couldn't parse JSON file");
 
         {
             let mut graph_path = spec.paths.get_mut("/v1/graph").unwrap();
-            add_mandatory_params(&mut graph_path, &params);
+            let query_params: Vec<openapiv3::Parameter> = params
+                .iter()
+                .map(|name| query_param(name, true, None, None))
+                .collect();
+            add_params(&mut graph_path, &query_params);
         }
         let output = serde_json::to_string(&spec).unwrap();
 
@@ -141,23 +210,30 @@ mod tests {
     }
 
     #[test]
-    fn graph_params_integration() -> Result<(), Box<dyn std::error::Error>> {
-        let mut runtime = common_init();
+    fn include_param() {
+        let spec: serde_json::Value = serde_json::from_str(SPEC).expect("couldn't parse JSON file");
 
-        // prepare and run the test-service
-        let service_uri = "/openapi";
-        let mandatory_params: HashSet<String> = ["MARKER1", "MARKER2"]
+        let params = &spec["paths"]["/v1/graph"]["get"]["parameters"];
+        let include = params
+            .as_array()
+            .unwrap()
             .iter()
-            .cloned()
-            .map(String::from)
-            .collect();
-        let path_prefix = "test_prefix".to_string();
+            .find(|param| param["name"] == crate::graph::INCLUDE_PARAM)
+            .expect("include parameter not documented");
+        assert_eq!(include["in"], "query");
+        assert_eq!(
+            include["schema"]["enum"],
+            serde_json::json!(["minimal", "channels"])
+        );
+    }
 
-        let data = actix_web::web::Data::new(AppState {
-            mandatory_params: mandatory_params.clone(),
-            path_prefix: path_prefix.clone(),
-            plugins: Box::leak(Box::new([])),
-        });
+    /// Serve the OpenAPI document for the given state.
+    fn serve_spec(
+        runtime: &mut tokio::runtime::Runtime,
+        data: actix_web::web::Data<AppState>,
+    ) -> Result<openapiv3::OpenAPI, Box<dyn std::error::Error>> {
+        // prepare and run the test-service
+        let service_uri = "/openapi";
         let resource =
             actix_web::web::resource(service_uri).route(actix_web::web::get().to(super::index));
         let app = actix_web::App::new().service(resource);
@@ -191,15 +267,36 @@ mod tests {
             }));
 
         let body = runtime.block_on(body_future)?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    #[test]
+    fn graph_params_integration() -> Result<(), Box<dyn std::error::Error>> {
+        let mut runtime = common_init();
+
+        // Not sorted, to check that the configured order is kept.
+        let mandatory_params: Vec<String> = ["MARKER2", "MARKER1"]
+            .iter()
+            .cloned()
+            .map(String::from)
+            .collect();
+        let path_prefix = "test_prefix".to_string();
+
+        let data = actix_web::web::Data::new(AppState {
+            mandatory_params: mandatory_params.clone(),
+            path_prefix: path_prefix.clone(),
+            plugins: crate::reload::PluginChain::default(),
+            ..Default::default()
+        });
 
         // parse the response and extract the required parameters
-        let spec: openapiv3::OpenAPI = serde_json::from_str(&body)?;
+        let spec = serve_spec(&mut runtime, data)?;
         let v1_graph: &openapiv3::ReferenceOr<openapiv3::PathItem> = spec
             .paths
             .get(&format!("{}/v1/graph", path_prefix))
             .ok_or("could not find /v1/graph endpoint in openapi spec")?;
 
-        let v1_graph_mandatory_params_result: HashSet<String> = match v1_graph {
+        let v1_graph_mandatory_params_result: Vec<String> = match v1_graph {
             ReferenceOr::Item(item) => item
                 .parameters
                 .iter()
@@ -223,4 +320,84 @@ mod tests {
 
         Ok(())
     }
+
+    /// Serve the OpenAPI document for the given state, returning the parameters added to `/v1/graph`.
+    fn v1_graph_params(
+        runtime: &mut tokio::runtime::Runtime,
+        state: &AppState,
+    ) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+        let spec = serve_spec(runtime, actix_web::web::Data::new(state.clone()))?;
+        let spec = serde_json::to_value(&spec)?;
+        Ok(spec["paths"]["/v1/graph"]["parameters"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    #[test]
+    fn declared_params_per_pipeline() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::cache::{CacheKeyRules, ResponseCache};
+        use crate::reload::PluginChain;
+        use cincinnati::plugins::prelude::*;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let mut runtime = common_init();
+        let names = |params: &[serde_json::Value]| {
+            params
+                .iter()
+                .map(|param| param["name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let description =
+            |param: &serde_json::Value| param["description"].as_str().unwrap().to_string();
+
+        let cache =
+            ResponseCache::new(Duration::from_secs(60), 10, vec![]).with_key_rules(CacheKeyRules {
+                include: vec!["channel".to_string()],
+                ..Default::default()
+            });
+        let state = AppState {
+            mandatory_params: vec!["channel".to_string()],
+            plugins: PluginChain::new(new_plugins!(
+                InternalPluginWrapper(ChannelFilterPlugin::default()),
+                InternalPluginWrapper(ArchFilterPlugin::default())
+            )),
+            cache: Some(Arc::new(cache)),
+            ..Default::default()
+        };
+        let params = v1_graph_params(&mut runtime, &state)?;
+        assert_eq!(names(&params), vec!["channel", "arch"]);
+
+        // The mandatory parameter is documented as declared.
+        assert_eq!(params[0]["required"], true);
+        assert_eq!(params[0]["schema"]["pattern"], r"^[0-9a-z\-\.]+$");
+        assert!(description(&params[0]).ends_with("Participates in response cache keys"));
+        assert_eq!(params[1]["required"], false);
+        assert_eq!(params[1]["schema"]["pattern"], r"^[0-9a-z]+$");
+        assert!(description(&params[1]).ends_with("Doesn't participate in response cache keys"));
+
+        // Another pipeline honors other parameters.
+        let other = AppState {
+            plugins: PluginChain::new(new_plugins!(InternalPluginWrapper(
+                ClientVersionFilterPlugin::default()
+            ))),
+            ..Default::default()
+        };
+        let other_params = v1_graph_params(&mut runtime, &other)?;
+        assert_eq!(names(&other_params), vec!["client_version"]);
+        assert_eq!(other_params[0]["required"], false);
+        assert_eq!(other_params[0]["schema"].get("pattern"), None);
+
+        // The document follows plugin reloads.
+        state.plugins.swap(new_plugins!(InternalPluginWrapper(
+            ClientVersionFilterPlugin::default()
+        )));
+        assert_eq!(
+            names(&v1_graph_params(&mut runtime, &state)?),
+            vec!["channel", "client_version"]
+        );
+
+        Ok(())
+    }
 }
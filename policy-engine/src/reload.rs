@@ -0,0 +1,343 @@
+//! Reloading of the policy plugins on SIGHUP.
+//!
+//! On SIGHUP the settings are assembled again and the plugins rebuilt. The
+//! new chain replaces the live one only if it builds and validates; otherwise
+//! the live chain keeps serving. Requests in flight during a swap finish with
+//! the chain they started with. Graphs cached from the output of the previous
+//! chain are invalidated by the swap.
+
+use crate::cache::{EtagCache, ResponseCache};
+use crate::watchdog::WatchdogStatus;
+use actix_web::{web, HttpResponse};
+use cincinnati::plugins::{BoxedPlugin, InternalIO, PluginIO};
+use commons::prelude_errors::*;
+use prometheus::IntCounterVec;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+lazy_static! {
+    static ref PLUGIN_RELOADS: IntCounterVec = IntCounterVec::new(
+        prometheus::Opts::new(
+            "plugin_reloads_total",
+            "Total number of plugin reloads, by result"
+        ),
+        &["result"]
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(PLUGIN_RELOADS.clone()))?;
+    Ok(())
+}
+
+/// Policy plugins of the main service, replaceable at runtime.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PluginChain {
+    plugins: Arc<RwLock<Arc<Vec<BoxedPlugin>>>>,
+    cache: Option<Arc<ResponseCache>>,
+    etag_cache: Option<Arc<EtagCache>>,
+}
+
+impl PluginChain {
+    /// Create a chain serving the given plugins.
+    pub(crate) fn new(plugins: Vec<BoxedPlugin>) -> Self {
+        Self {
+            plugins: Arc::new(RwLock::new(Arc::new(plugins))),
+            cache: None,
+            etag_cache: None,
+        }
+    }
+
+    /// Invalidate the given caches of the output of the plugins on swap.
+    pub(crate) fn with_caches(
+        mut self,
+        cache: Option<Arc<ResponseCache>>,
+        etag_cache: Option<Arc<EtagCache>>,
+    ) -> Self {
+        self.cache = cache;
+        self.etag_cache = etag_cache;
+        self
+    }
+
+    /// Return the live plugins.
+    ///
+    /// The returned plugins stay valid after a swap, until dropped.
+    pub(crate) fn current(&self) -> Arc<Vec<BoxedPlugin>> {
+        match self.plugins.read() {
+            Ok(plugins) => plugins.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Run `f` only if `plugins` are still live.
+    ///
+    /// Swaps wait for `f` to return, so that the output of `plugins` can be
+    /// cached by `f` without outliving them.
+    pub(crate) fn if_current<F>(&self, plugins: &Arc<Vec<BoxedPlugin>>, f: F)
+    where
+        F: FnOnce(),
+    {
+        let live = match self.plugins.read() {
+            Ok(live) => live,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if Arc::ptr_eq(&live, plugins) {
+            f();
+        }
+    }
+
+    /// Replace the live plugins, invalidating the cached graphs.
+    pub(crate) fn swap(&self, plugins: Vec<BoxedPlugin>) {
+        let mut live = match self.plugins.write() {
+            Ok(live) => live,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *live = Arc::new(plugins);
+
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+        if let Some(etag_cache) = &self.etag_cache {
+            etag_cache.clear();
+        }
+    }
+}
+
+/// Rebuild the plugins with `build`, swapping them in only if successful.
+pub(crate) fn reload<F>(chain: &PluginChain, build: F) -> Fallible<()>
+where
+    F: FnOnce() -> Fallible<Vec<BoxedPlugin>>,
+{
+    match build() {
+        Ok(plugins) => {
+            info!("reloaded {} policy plugins", plugins.len());
+            chain.swap(plugins);
+            PLUGIN_RELOADS.with_label_values(&["success"]).inc();
+            Ok(())
+        }
+        Err(e) => {
+            PLUGIN_RELOADS.with_label_values(&["failure"]).inc();
+            Err(e.context("plugin reload rejected, keeping the live plugins"))
+        }
+    }
+}
+
+/// Reload the plugins with `build` on each SIGHUP.
+pub(crate) async fn reload_on_sighup<F>(chain: PluginChain, build: F)
+where
+    F: Fn() -> Fallible<Vec<BoxedPlugin>>,
+{
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        info!("SIGHUP received, reloading policy plugins");
+        if let Err(e) = reload(&chain, &build) {
+            error!("{:#}", e);
+        }
+    }
+}
+
+/// Serve the aggregated health of the live plugins.
+///
+/// The service is not live if the watchdog deemed the main service unresponsive.
+pub(crate) async fn serve_health(
+    chain: web::Data<PluginChain>,
+    watchdog: web::Data<WatchdogStatus>,
+) -> HttpResponse {
+    if watchdog.is_unresponsive() {
+        return HttpResponse::ServiceUnavailable().body("main service unresponsive");
+    }
+
+    cincinnati::plugins::health::check(chain.current().iter())
+        .await
+        .into_response()
+}
+
+/// Maximum age of the fetched graph, beyond which the service is not ready.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct MaxGraphAge(pub(crate) Option<Duration>);
+
+/// Serve the readiness of the live plugins, based on the age of their graphs.
+///
+/// Plugins whose graph is older than the maximum age are run once, which
+/// refreshes their graph if their upstream is back. The service is not ready
+/// if a graph is still too old.
+pub(crate) async fn serve_readiness(
+    chain: web::Data<PluginChain>,
+    max_graph_age: web::Data<MaxGraphAge>,
+) -> HttpResponse {
+    let max_age = match max_graph_age.0 {
+        Some(max_age) => max_age,
+        None => return HttpResponse::Ok().finish(),
+    };
+    let is_too_old = |age: &Duration| *age > max_age;
+
+    for plugin in chain.current().iter() {
+        if plugin.graph_age().filter(is_too_old).is_none() {
+            continue;
+        }
+
+        let io = PluginIO::InternalIO(InternalIO {
+            graph: Default::default(),
+            parameters: Default::default(),
+            warnings: Default::default(),
+        });
+        if let Err(e) = plugin.run(io).await {
+            debug!(
+                "failed to refresh the graph of '{}': {}",
+                plugin.get_name(),
+                e
+            );
+        }
+
+        if let Some(age) = plugin.graph_age().filter(is_too_old) {
+            warn!(
+                "graph of plugin '{}' is {}s old, not ready",
+                plugin.get_name(),
+                age.as_secs()
+            );
+            return HttpResponse::ServiceUnavailable().body(format!(
+                "graph of plugin '{}' is {}s old",
+                plugin.get_name(),
+                age.as_secs()
+            ));
+        }
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cincinnati::plugins::prelude::*;
+    use cincinnati::plugins::prelude_plugin_impl::{async_trait, InternalPlugin};
+
+    fn chain_names(plugins: &[BoxedPlugin]) -> Vec<&'static str> {
+        plugins.iter().map(|plugin| plugin.get_name()).collect()
+    }
+
+    fn build(config: &[(&str, &str)]) -> Fallible<Vec<BoxedPlugin>> {
+        let settings = config
+            .iter()
+            .map(|(name, upstream)| plugin_config!(("name", *name), ("upstream", *upstream)))
+            .collect::<Fallible<Vec<_>>>()?;
+        cincinnati::plugins::catalog::build_plugins(&settings, None)
+    }
+
+    #[test]
+    fn valid_reload_swaps_chain() -> Fallible<()> {
+        let chain = PluginChain::new(build(&[(
+            CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+            "http://localhost:8080/v1/graph",
+        )])?);
+
+        // Requests in flight keep the chain they started with.
+        let in_flight = chain.current();
+
+        reload(&chain, || {
+            build(&[
+                (
+                    CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                    "http://localhost:8080/v1/graph",
+                ),
+                (ChannelFilterPlugin::PLUGIN_NAME, ""),
+            ])
+        })?;
+
+        assert_eq!(
+            chain_names(&chain.current()),
+            vec![
+                CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+                ChannelFilterPlugin::PLUGIN_NAME
+            ]
+        );
+        assert_eq!(
+            chain_names(&in_flight),
+            vec![CincinnatiGraphFetchPlugin::PLUGIN_NAME]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_reload_keeps_chain() -> Fallible<()> {
+        let chain = PluginChain::new(build(&[(
+            CincinnatiGraphFetchPlugin::PLUGIN_NAME,
+            "http://localhost:8080/v1/graph",
+        )])?);
+
+        let err = reload(&chain, || build(&[("no-such-plugin", "")])).unwrap_err();
+        assert!(
+            err.to_string().contains("plugin reload rejected"),
+            "unexpected error: {}",
+            err
+        );
+
+        assert_eq!(
+            chain_names(&chain.current()),
+            vec![CincinnatiGraphFetchPlugin::PLUGIN_NAME]
+        );
+
+        Ok(())
+    }
+
+    /// Plugin whose graph has the given age, refreshed on run if its upstream is up.
+    #[derive(Debug)]
+    struct AgedPlugin {
+        age: std::sync::Mutex<Duration>,
+        upstream_up: bool,
+    }
+
+    #[async_trait]
+    impl InternalPlugin for AgedPlugin {
+        const PLUGIN_NAME: &'static str = "aged";
+        const ABI_VERSION: u32 = 4;
+
+        async fn run_internal(self: &Self, io: InternalIO) -> Fallible<InternalIO> {
+            ensure!(self.upstream_up, "upstream unreachable");
+            *self.age.lock().unwrap() = Duration::from_secs(0);
+            Ok(io)
+        }
+
+        fn graph_age(self: &Self) -> Option<Duration> {
+            Some(*self.age.lock().unwrap())
+        }
+    }
+
+    fn readiness(max_graph_age: Option<Duration>, age: Duration, upstream_up: bool) -> u16 {
+        let chain = PluginChain::new(new_plugins!(InternalPluginWrapper(AgedPlugin {
+            age: std::sync::Mutex::new(age),
+            upstream_up,
+        })));
+
+        let response = actix::System::new("readiness").block_on(serve_readiness(
+            web::Data::new(chain),
+            web::Data::new(MaxGraphAge(max_graph_age)),
+        ));
+        response.status().as_u16()
+    }
+
+    #[test]
+    fn readiness_by_graph_age() {
+        let hour = Duration::from_secs(3600);
+
+        // Aged graph, with the upstream still down.
+        assert_eq!(readiness(Some(hour), 2 * hour, false), 503);
+
+        // Aged graph, refreshed by the readiness check.
+        assert_eq!(readiness(Some(hour), 2 * hour, true), 200);
+
+        assert_eq!(readiness(Some(hour), hour / 2, false), 200);
+        assert_eq!(readiness(None, 2 * hour, false), 200);
+    }
+}
@@ -0,0 +1,77 @@
+//! Unix domain socket support for the main service.
+
+use commons::prelude_errors::*;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// Remove a stale socket left at `path`, e.g. by a crashed instance, before binding to it.
+///
+/// Fails if the socket is still served by a live process, or if the path is
+/// not a socket, so that neither another instance nor an unrelated file is
+/// clobbered.
+pub fn remove_stale_socket(path: &Path) -> Fallible<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).context(format!("failed to inspect socket path {}", path.display()))
+        }
+    };
+
+    ensure!(
+        metadata.file_type().is_socket(),
+        "socket path {} exists and is not a socket",
+        path.display()
+    );
+    ensure!(
+        UnixStream::connect(path).is_err(),
+        "socket path {} is in use by another process",
+        path.display()
+    );
+
+    info!("removing stale socket {}", path.display());
+    std::fs::remove_file(path)
+        .context(format!("failed to remove stale socket {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn stale_socket_is_removed() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("pe.sock");
+
+        // Nothing to remove.
+        remove_stale_socket(&path)?;
+
+        // Dropping the listener leaves the socket file behind.
+        drop(UnixListener::bind(&path)?);
+        assert!(path.exists());
+        remove_stale_socket(&path)?;
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn live_socket_and_regular_file_are_kept() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+
+        let live = dir.path().join("live.sock");
+        let _listener = UnixListener::bind(&live)?;
+        assert!(remove_stale_socket(&live).is_err());
+        assert!(live.exists());
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "content")?;
+        assert!(remove_stale_socket(&file).is_err());
+        assert!(file.exists());
+
+        Ok(())
+    }
+}
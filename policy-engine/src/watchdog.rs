@@ -0,0 +1,316 @@
+//! Watchdog of the main service.
+//!
+//! The main service may accept connections but never answer them, e.g. if all
+//! its workers are wedged, in which case no handler runs and no metric
+//! reflects it. The watchdog periodically sends a request to the service
+//! itself, through its full middleware stack, and acts on consecutive
+//! failures: either by failing the liveness check served by the status
+//! service, or by exiting the process.
+//!
+//! At most one probe is in flight, bounded by a short timeout, and the next
+//! one is only scheduled after the previous one completes, so that a slow
+//! service is never loaded any further.
+
+use async_trait::async_trait;
+use commons::prelude_errors::*;
+use prometheus::{Gauge, IntGauge};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default timeout of a probe.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default number of consecutive failed probes triggering the action.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+lazy_static! {
+    static ref WATCHDOG_LAST_SUCCESS: IntGauge = IntGauge::new(
+        "watchdog_last_success_timestamp",
+        "UTC timestamp of the last successful watchdog probe"
+    )
+    .unwrap();
+    static ref WATCHDOG_PROBE_DURATION: Gauge = Gauge::new(
+        "watchdog_probe_duration_seconds",
+        "Duration of the last watchdog probe, in seconds"
+    )
+    .unwrap();
+    static ref WATCHDOG_CONSECUTIVE_FAILURES: IntGauge = IntGauge::new(
+        "watchdog_consecutive_failures",
+        "Number of consecutive failed watchdog probes"
+    )
+    .unwrap();
+}
+
+/// Register relevant metrics to a prometheus registry.
+pub(crate) fn register_metrics(registry: &prometheus::Registry) -> Fallible<()> {
+    registry.register(Box::new(WATCHDOG_LAST_SUCCESS.clone()))?;
+    registry.register(Box::new(WATCHDOG_PROBE_DURATION.clone()))?;
+    registry.register(Box::new(WATCHDOG_CONSECUTIVE_FAILURES.clone()))?;
+    Ok(())
+}
+
+/// Action taken once the main service is deemed unresponsive.
+#[derive(Clone, Copy, Debug, Deserialize, SmartDefault, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchdogAction {
+    /// Fail the liveness check, for the orchestrator to restart the process.
+    #[default]
+    FailLiveness,
+    /// Log the failure and exit the process.
+    Exit,
+}
+
+/// Watchdog settings.
+#[derive(Clone, Debug, SmartDefault)]
+pub struct WatchdogSettings {
+    /// Interval between the end of a probe and the start of the next one.
+    ///
+    /// The watchdog is disabled if unset.
+    pub interval: Option<Duration>,
+    /// Timeout of a probe, after which it is failed.
+    #[default(DEFAULT_TIMEOUT)]
+    pub timeout: Duration,
+    /// Number of consecutive failed probes triggering the action.
+    #[default(DEFAULT_FAILURE_THRESHOLD)]
+    pub failure_threshold: u32,
+    /// Action taken once the threshold is reached.
+    pub action: WatchdogAction,
+}
+
+/// Liveness of the main service, as observed by the watchdog.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WatchdogStatus(Arc<AtomicBool>);
+
+impl WatchdogStatus {
+    /// Whether the main service has been deemed unresponsive.
+    pub(crate) fn is_unresponsive(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn set_unresponsive(&self, unresponsive: bool) {
+        self.0.store(unresponsive, Ordering::SeqCst);
+    }
+}
+
+/// Probe of the main service.
+#[async_trait]
+pub(crate) trait Prober: Send + Sync {
+    /// Send a request to the main service, failing unless it answers successfully.
+    async fn probe(&self) -> Fallible<()>;
+}
+
+/// Build the URL probed on the main service, listening on the given address and port.
+///
+/// The loopback address is probed if the service listens on all addresses.
+pub(crate) fn probe_url(address: IpAddr, port: u16, path_prefix: &str) -> String {
+    let address = match address {
+        IpAddr::V4(address) if address.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(address) if address.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        address => address,
+    };
+    format!(
+        "http://{}{}/v1/openapi",
+        SocketAddr::new(address, port),
+        path_prefix
+    )
+}
+
+/// Prober sending HTTP requests to a URL of the main service.
+pub(crate) struct HttpProber {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpProber {
+    /// Create a prober of the given URL.
+    pub(crate) fn new(url: String, timeout: Duration) -> Fallible<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("failed to build the watchdog client")?;
+        Ok(Self { client, url })
+    }
+}
+
+#[async_trait]
+impl Prober for HttpProber {
+    async fn probe(&self) -> Fallible<()> {
+        let response = self.client.get(&self.url).send().await?;
+        ensure!(
+            response.status().is_success(),
+            "unexpected status {}",
+            response.status()
+        );
+        Ok(())
+    }
+}
+
+/// Watchdog probing the main service.
+pub(crate) struct Watchdog {
+    settings: WatchdogSettings,
+    prober: Box<dyn Prober>,
+    status: WatchdogStatus,
+    failures: u32,
+}
+
+impl Watchdog {
+    /// Create a watchdog, reporting the liveness of the main service to `status`.
+    pub(crate) fn new(
+        settings: WatchdogSettings,
+        prober: Box<dyn Prober>,
+        status: WatchdogStatus,
+    ) -> Self {
+        Self {
+            settings,
+            prober,
+            status,
+            failures: 0,
+        }
+    }
+
+    /// Run a single probe, and return the action triggered by its failure, if any.
+    ///
+    /// The liveness check is failed as part of the `FailLiveness` action, and
+    /// restored on the next successful probe.
+    pub(crate) async fn check(&mut self) -> Option<WatchdogAction> {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(self.settings.timeout, self.prober.probe()).await {
+            Ok(result) => result,
+            Err(_) => Err(format_err!("timed out after {:?}", self.settings.timeout)),
+        };
+        WATCHDOG_PROBE_DURATION.set(start.elapsed().as_secs_f64());
+
+        if let Err(e) = result {
+            self.failures += 1;
+            WATCHDOG_CONSECUTIVE_FAILURES.set(i64::from(self.failures));
+            warn!(
+                "watchdog probe failed ({} consecutive): {}",
+                self.failures, e
+            );
+            if self.failures < self.settings.failure_threshold {
+                return None;
+            }
+
+            if self.settings.action == WatchdogAction::FailLiveness
+                && !self.status.is_unresponsive()
+            {
+                error!(
+                    "main service unresponsive after {} probes, failing liveness",
+                    self.failures
+                );
+                self.status.set_unresponsive(true);
+            }
+            return Some(self.settings.action);
+        }
+
+        if self.status.is_unresponsive() {
+            info!("main service responsive again");
+        }
+        self.failures = 0;
+        self.status.set_unresponsive(false);
+        WATCHDOG_CONSECUTIVE_FAILURES.set(0);
+        WATCHDOG_LAST_SUCCESS.set(chrono::Utc::now().timestamp());
+        None
+    }
+
+    /// Probe the main service until the process exits.
+    pub(crate) async fn run(mut self) {
+        let interval = match self.settings.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        loop {
+            tokio::time::delay_for(interval).await;
+            if self.check().await == Some(WatchdogAction::Exit) {
+                error!(
+                    "main service unresponsive after {} probes, exiting",
+                    self.failures
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::testing::init_runtime;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Prober replaying scripted results, then hanging.
+    struct ScriptedProber(Mutex<VecDeque<bool>>);
+
+    #[async_trait]
+    impl Prober for ScriptedProber {
+        async fn probe(&self) -> Fallible<()> {
+            let next = self.0.lock().unwrap().pop_front();
+            match next {
+                Some(true) => Ok(()),
+                Some(false) => bail!("connection refused"),
+                None => futures::future::pending().await,
+            }
+        }
+    }
+
+    fn watchdog(action: WatchdogAction, script: &[bool]) -> (Watchdog, WatchdogStatus) {
+        let settings = WatchdogSettings {
+            interval: Some(Duration::from_secs(10)),
+            timeout: Duration::from_millis(50),
+            failure_threshold: 3,
+            action,
+        };
+        let prober = ScriptedProber(Mutex::new(script.iter().cloned().collect()));
+        let status = WatchdogStatus::default();
+        let watchdog = Watchdog::new(settings, Box::new(prober), status.clone());
+        (watchdog, status)
+    }
+
+    #[test]
+    fn fail_liveness_after_consecutive_failures() -> Fallible<()> {
+        let mut rt = init_runtime()?;
+        let (mut watchdog, status) = watchdog(
+            WatchdogAction::FailLiveness,
+            &[false, false, true, false, false, false, true],
+        );
+
+        // Failures are only consecutive until a probe succeeds.
+        for _ in 0..5 {
+            assert_eq!(rt.block_on(watchdog.check()), None);
+            assert!(!status.is_unresponsive());
+        }
+
+        assert_eq!(
+            rt.block_on(watchdog.check()),
+            Some(WatchdogAction::FailLiveness)
+        );
+        assert!(status.is_unresponsive());
+
+        // Liveness is restored once the service answers again.
+        assert_eq!(rt.block_on(watchdog.check()), None);
+        assert!(!status.is_unresponsive());
+
+        Ok(())
+    }
+
+    #[test]
+    fn exit_after_timeouts() -> Fallible<()> {
+        let mut rt = init_runtime()?;
+        // Probes hang once the script is exhausted.
+        let (mut watchdog, status) = watchdog(WatchdogAction::Exit, &[true]);
+
+        assert_eq!(rt.block_on(watchdog.check()), None);
+        assert_eq!(rt.block_on(watchdog.check()), None);
+        assert_eq!(rt.block_on(watchdog.check()), None);
+        assert_eq!(rt.block_on(watchdog.check()), Some(WatchdogAction::Exit));
+
+        // Liveness is left alone by the exit action.
+        assert!(!status.is_unresponsive());
+
+        Ok(())
+    }
+}
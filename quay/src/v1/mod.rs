@@ -3,6 +3,12 @@ use reqwest;
 
 mod manifest;
 
+mod repository;
+pub use self::repository::Repository;
+
+mod security;
+pub use self::security::{ScanStatus, SecuritySummary};
+
 mod tag;
 pub use self::tag::Tag;
 
@@ -17,6 +23,8 @@ pub struct Client {
     hclient: reqwest::Client,
     /// Authentication token.
     token: Option<String>,
+    /// User agent of outgoing requests, if not the reqwest default.
+    user_agent: Option<String>,
 }
 
 impl Client {
@@ -25,6 +33,22 @@ impl Client {
         ClientBuilder::default()
     }
 
+    /// Return a copy of this client using the given access token.
+    pub fn with_access_token(&self, token: Option<String>) -> Self {
+        Self {
+            token,
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of this client using the given user agent.
+    pub fn with_user_agent(&self, user_agent: Option<String>) -> Self {
+        Self {
+            user_agent,
+            ..self.clone()
+        }
+    }
+
     /// Return a request builder with base URL and parameters set.
     pub(crate) fn new_request<S: AsRef<str>>(
         &self,
@@ -42,6 +66,10 @@ impl Client {
                 }
             }
         };
+        let builder = match self.user_agent {
+            None => builder,
+            Some(ref user_agent) => builder.header(reqwest::header::USER_AGENT, user_agent),
+        };
         Ok(builder)
     }
 }
@@ -52,6 +80,7 @@ pub struct ClientBuilder {
     api_base: Option<String>,
     hclient: Option<reqwest::Client>,
     token: Option<String>,
+    user_agent: Option<String>,
 }
 
 impl ClientBuilder {
@@ -69,6 +98,13 @@ impl ClientBuilder {
         builder
     }
 
+    /// Set (or reset) the user agent to use.
+    pub fn user_agent(self, user_agent: Option<String>) -> Self {
+        let mut builder = self;
+        builder.user_agent = user_agent;
+        builder
+    }
+
     /// Set (or reset) the base API endpoint URL to use.
     pub fn api_base(self, api_base: Option<String>) -> Self {
         let mut builder = self;
@@ -90,6 +126,7 @@ impl ClientBuilder {
             api_base,
             hclient,
             token: self.token,
+            user_agent: self.user_agent,
         };
         Ok(quay_client)
     }
@@ -101,6 +138,7 @@ impl Default for ClientBuilder {
             api_base: Some(DEFAULT_API_BASE.to_string()),
             hclient: None,
             token: None,
+            user_agent: None,
         }
     }
 }
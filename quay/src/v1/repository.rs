@@ -0,0 +1,69 @@
+//! Repository API.
+
+use super::Client;
+use anyhow::Result as Fallible;
+use reqwest::Method;
+
+/// API result with a page of repositories.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PaginatedRepositories {
+    /// List of repositories in current page.
+    pub(crate) repositories: Vec<Repository>,
+    /// Pagination token of the next page, if any.
+    #[serde(default)]
+    pub(crate) next_page: Option<String>,
+}
+
+/// Repository.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Repository {
+    /// Namespace owning the repository.
+    pub namespace: String,
+    /// Repository name, within its namespace.
+    pub name: String,
+}
+
+impl Repository {
+    /// Return the full repository name, in `namespace/name` format.
+    pub fn full_name(&self) -> String {
+        format!("{}/{}", self.namespace, self.name)
+    }
+}
+
+impl Client {
+    /// List the repositories in a namespace accepted by `filter`.
+    ///
+    /// All pages of the listing are fetched.
+    pub async fn list_repositories<S, F>(
+        &self,
+        namespace: S,
+        filter: F,
+    ) -> Fallible<Vec<Repository>>
+    where
+        S: AsRef<str>,
+        F: Fn(&Repository) -> bool,
+    {
+        let mut repositories = vec![];
+        let mut next_page: Option<String> = None;
+
+        loop {
+            let mut req = self
+                .new_request(Method::GET, "repository")?
+                .query(&[("namespace", namespace.as_ref())]);
+            if let Some(page) = &next_page {
+                req = req.query(&[("next_page", page)]);
+            }
+
+            let resp = req.send().await?.error_for_status()?;
+            let page = resp.json::<PaginatedRepositories>().await?;
+            repositories.extend(page.repositories.into_iter().filter(|repo| filter(repo)));
+
+            match page.next_page {
+                Some(page) => next_page = Some(page),
+                None => break,
+            }
+        }
+
+        Ok(repositories)
+    }
+}
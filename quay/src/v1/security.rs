@@ -0,0 +1,169 @@
+//! Security scanning API.
+
+use super::Client;
+use anyhow::Error;
+use reqwest::Method;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// API result of a manifest security scan.
+///
+/// The quay.io documentation doesn't specify the result type.
+/// It was inspected manually like so:
+/// ```console
+/// $ curl --get https://quay.io/api/v1/repository/openshift-release-dev/ocp-release/manifest/sha256:8a6c7f4d9f8d/security?vulnerabilities=true | jq .
+/// {
+///   "status": "scanned",
+///   "data": {
+///     "Layer": {
+///       "Name": "sha256:8a6c7f4d9f8d",
+///       "Features": [
+///         {
+///           "Name": "openssl-libs",
+///           "Version": "1:1.1.1g-11.el8",
+///           "Vulnerabilities": [
+///             {
+///               "Name": "RHSA-2021:1024",
+///               "Severity": "Critical",
+///               "FixedBy": "1:1.1.1g-15.el8_3"
+///             }
+///           ]
+///         }
+///       ]
+///     }
+///   }
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+pub(crate) struct SecurityScan {
+    pub(crate) status: String,
+    #[serde(default)]
+    pub(crate) data: Option<SecurityData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct SecurityData {
+    #[serde(default)]
+    pub(crate) layer: Option<ScannedLayer>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ScannedLayer {
+    #[serde(default)]
+    pub(crate) features: Vec<ScannedFeature>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ScannedFeature {
+    #[serde(default)]
+    pub(crate) vulnerabilities: Vec<Vulnerability>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct Vulnerability {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) severity: String,
+}
+
+/// Status of a manifest security scan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanStatus {
+    /// The manifest was scanned.
+    Scanned,
+    /// The scan is queued or in progress.
+    Pending,
+    /// The scan failed.
+    Failed,
+    /// The manifest can't be scanned.
+    Unsupported,
+}
+
+impl ScanStatus {
+    fn from_api(status: &str) -> Result<Self, Error> {
+        match status {
+            "scanned" => Ok(ScanStatus::Scanned),
+            "queued" | "scanning" => Ok(ScanStatus::Pending),
+            "failed" => Ok(ScanStatus::Failed),
+            "unsupported" => Ok(ScanStatus::Unsupported),
+            other => Err(anyhow::anyhow!("unknown security scan status '{}'", other)),
+        }
+    }
+}
+
+/// Summary of a manifest security scan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecuritySummary {
+    /// Scan status.
+    pub status: ScanStatus,
+    /// Number of distinct vulnerabilities by lowercase severity, e.g. `critical`.
+    pub vulnerabilities: BTreeMap<String, usize>,
+}
+
+impl SecuritySummary {
+    /// Return the number of distinct vulnerabilities with the given severity.
+    pub fn count(&self, severity: &str) -> usize {
+        self.vulnerabilities
+            .get(&severity.to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl std::convert::TryFrom<SecurityScan> for SecuritySummary {
+    type Error = Error;
+
+    fn try_from(scan: SecurityScan) -> Result<Self, Error> {
+        let status = ScanStatus::from_api(&scan.status)?;
+
+        // The same vulnerability is reported for each affected feature.
+        let distinct: BTreeSet<(String, String)> = scan
+            .data
+            .and_then(|data| data.layer)
+            .map(|layer| layer.features)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|feature| feature.vulnerabilities)
+            .map(|vulnerability| (vulnerability.severity.to_lowercase(), vulnerability.name))
+            .collect();
+
+        let mut vulnerabilities = BTreeMap::new();
+        for (severity, _) in distinct {
+            *vulnerabilities.entry(severity).or_insert(0) += 1;
+        }
+
+        Ok(Self {
+            status,
+            vulnerabilities,
+        })
+    }
+}
+
+impl Client {
+    /// Fetch the security scan summary of a manifestref.
+    pub async fn get_security_status<S: AsRef<str>>(
+        &self,
+        repository: S,
+        manifest_ref: S,
+    ) -> Result<SecuritySummary, Error> {
+        use std::convert::TryInto;
+
+        let endpoint = format!(
+            "repository/{}/manifest/{}/security",
+            repository.as_ref(),
+            manifest_ref.as_ref()
+        );
+
+        let req = self
+            .new_request(Method::GET, &endpoint)?
+            .query(&[("vulnerabilities", "true")]);
+
+        let resp = req.send().await?.error_for_status()?;
+        let scan = resp.json::<SecurityScan>().await?;
+
+        scan.try_into()
+    }
+}